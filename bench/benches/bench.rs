@@ -13,6 +13,7 @@ criterion_group!(benches,
 	lightning::routing::router::benches::generate_routes_with_probabilistic_scorer,
 	lightning::routing::router::benches::generate_mpp_routes_with_probabilistic_scorer,
 	lightning::routing::router::benches::generate_large_mpp_routes_with_probabilistic_scorer,
+	lightning::routing::router::benches::generate_budgeted_large_mpp_routes_with_probabilistic_scorer,
 	lightning::routing::router::benches::generate_routes_with_nonlinear_probabilistic_scorer,
 	lightning::routing::router::benches::generate_mpp_routes_with_nonlinear_probabilistic_scorer,
 	lightning::routing::router::benches::generate_large_mpp_routes_with_nonlinear_probabilistic_scorer,
@@ -43,6 +43,10 @@ def check_feature(feature):
         pass
     elif feature == "electrum":
         pass
+    elif feature == "bitcoind-rpc":
+        pass
+    elif feature == "cbf":
+        pass
     elif feature == "time":
         pass
     elif feature == "_test_utils":
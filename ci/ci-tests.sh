@@ -101,6 +101,10 @@ if [[ "$HOST_PLATFORM" != *windows* ]]; then
 	cargo check --verbose --color always --features esplora-async-https
 	cargo test --verbose --color always --features electrum
 	cargo check --verbose --color always --features electrum
+	cargo test --verbose --color always --features bitcoind-rpc
+	cargo check --verbose --color always --features bitcoind-rpc
+	cargo test --verbose --color always --features cbf
+	cargo check --verbose --color always --features cbf
 	popd
 fi
 
@@ -37,7 +37,7 @@ use lightning::blinded_path::payment::ReceiveTlvs;
 use lightning::blinded_path::BlindedPath;
 use lightning::chain;
 use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
-use lightning::chain::channelmonitor::{ChannelMonitor, MonitorEvent};
+use lightning::chain::channelmonitor::{ChannelMonitor, ClosingTxPackage, MonitorEvent};
 use lightning::chain::transaction::OutPoint;
 use lightning::chain::{
 	chainmonitor, channelmonitor, BestBlock, ChannelMonitorUpdateStatus, Confirm, Watch,
@@ -283,6 +283,12 @@ impl chain::Watch<TestChannelSigner> for TestChainMonitor {
 	) -> Vec<(OutPoint, ChannelId, Vec<MonitorEvent>, Option<PublicKey>)> {
 		return self.chain_monitor.release_pending_monitor_events();
 	}
+
+	fn get_latest_holder_commitment_package(
+		&self, funding_txo: OutPoint,
+	) -> Option<ClosingTxPackage> {
+		self.chain_monitor.get_latest_holder_commitment_package(funding_txo)
+	}
 }
 
 struct KeyProvider {
@@ -560,6 +566,7 @@ fn send_payment(
 					maybe_announced_channel: true,
 				}],
 				blinded_tail: None,
+				trampoline_tail: None,
 			}],
 			route_params: None,
 		},
@@ -640,6 +647,7 @@ fn send_hop_payment(
 					},
 				],
 				blinded_tail: None,
+				trampoline_tail: None,
 			}],
 			route_params: None,
 		},
@@ -9,12 +9,23 @@
 
 //! Convenient utilities for paying Lightning invoices.
 
-use crate::Bolt11Invoice;
+use crate::{Bolt11Invoice, Currency};
+use crate::prelude::*;
 use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::Network;
 
-use lightning::ln::types::PaymentHash;
-use lightning::ln::channelmanager::RecipientOnionFields;
+use lightning::events::Event;
+use lightning::ln::types::{PaymentHash, PaymentPreimage, PaymentSecret};
+use lightning::ln::channelmanager::{AChannelManager, MIN_FINAL_CLTV_EXPIRY_DELTA, PaymentId, ProbeSendFailure, RecipientOnionFields, Retry, RetryableSendFailure};
+use lightning::ln::features::{Bolt11InvoiceFeatures, NodeFeatures};
 use lightning::routing::router::{PaymentParameters, RouteParameters};
+use lightning::sign::EntropySource;
+
+use secp256k1::PublicKey;
+
+use core::ops::Deref;
+use core::time::Duration;
 
 /// Builds the necessary parameters to pay or pre-flight probe the given zero-amount
 /// [`Bolt11Invoice`] using [`ChannelManager::send_payment`] or
@@ -23,15 +34,34 @@ use lightning::routing::router::{PaymentParameters, RouteParameters};
 /// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
 /// same [`PaymentHash`] has never been paid before.
 ///
-/// Will always succeed unless the invoice has an amount specified, in which case
-/// [`payment_parameters_from_invoice`] should be used.
+/// Will fail with [`Bolt11PaymentError::UnsupportedCurrency`] if the invoice wasn't issued for
+/// the given `network`, and with [`Bolt11PaymentError::InvoiceExpired`] if it already expired at
+/// `duration_since_epoch`, the current time as a duration since the Unix epoch. Otherwise, will
+/// always succeed unless the invoice has an amount specified, in which case
+/// [`payment_parameters_from_invoice`] should be used and we fail with
+/// [`Bolt11PaymentError::AmountMismatch`].
 ///
 /// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
 /// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
-pub fn payment_parameters_from_zero_amount_invoice(invoice: &Bolt11Invoice, amount_msat: u64)
--> Result<(PaymentHash, RecipientOnionFields, RouteParameters), ()> {
+pub fn payment_parameters_from_zero_amount_invoice(
+	invoice: &Bolt11Invoice, amount_msat: u64, network: Network, duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	if invoice.amount_milli_satoshis().is_some() {
+		return Err(Bolt11PaymentError::AmountMismatch);
+	}
+	checked_params_from_invoice(invoice, Some(amount_msat), network, duration_since_epoch)
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given zero-amount
+/// [`Bolt11Invoice`] like [`payment_parameters_from_zero_amount_invoice`], but without checking
+/// the invoice's network and expiry, e.g., for testing.
+///
+/// Will always succeed unless the invoice has an amount specified, in which case we fail with
+/// [`Bolt11PaymentError::AmountMismatch`].
+pub fn payment_parameters_from_zero_amount_invoice_unchecked(invoice: &Bolt11Invoice, amount_msat: u64)
+-> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
 	if invoice.amount_milli_satoshis().is_some() {
-		Err(())
+		Err(Bolt11PaymentError::AmountMismatch)
 	} else {
 		Ok(params_from_invoice(invoice, amount_msat))
 	}
@@ -43,18 +73,664 @@ pub fn payment_parameters_from_zero_amount_invoice(invoice: &Bolt11Invoice, amou
 /// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
 /// same [`PaymentHash`] has never been paid before.
 ///
-/// Will always succeed unless the invoice has no amount specified, in which case
-/// [`payment_parameters_from_zero_amount_invoice`] should be used.
+/// Will fail with [`Bolt11PaymentError::UnsupportedCurrency`] if the invoice wasn't issued for
+/// the given `network`, and with [`Bolt11PaymentError::InvoiceExpired`] if it already expired at
+/// `duration_since_epoch`, the current time as a duration since the Unix epoch. Otherwise, will
+/// always succeed unless the invoice has no amount specified, in which case
+/// [`payment_parameters_from_zero_amount_invoice`] should be used and we fail with
+/// [`Bolt11PaymentError::AmountRequired`].
 ///
 /// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
 /// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
-pub fn payment_parameters_from_invoice(invoice: &Bolt11Invoice)
--> Result<(PaymentHash, RecipientOnionFields, RouteParameters), ()> {
+pub fn payment_parameters_from_invoice(
+	invoice: &Bolt11Invoice, network: Network, duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	checked_params_from_invoice(invoice, None, network, duration_since_epoch)
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] like
+/// [`payment_parameters_from_invoice`], but without checking the invoice's network and expiry,
+/// e.g., for testing.
+///
+/// Will always succeed unless the invoice has no amount specified, in which case we fail with
+/// [`Bolt11PaymentError::AmountRequired`].
+pub fn payment_parameters_from_invoice_unchecked(invoice: &Bolt11Invoice)
+-> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
 	if let Some(amount_msat) = invoice.amount_milli_satoshis() {
 		Ok(params_from_invoice(invoice, amount_msat))
 	} else {
-		Err(())
+		Err(Bolt11PaymentError::AmountRequired)
+	}
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] using
+/// [`ChannelManager::send_payment`] or [`ChannelManager::send_preflight_probes`], deliberately
+/// paying `amount_msat` rather than exactly the amount requested by the invoice, e.g., to include
+/// a tip or to round up the amount of a fiat-denominated payment.
+///
+/// Note that recipients are only required to accept payments of up to twice the requested amount
+/// per [BOLT 4], and some may not accept any amount other than the requested one. Thus, the
+/// payment may fail at the recipient when overpaying, after having been routed all the way.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Will fail with [`Bolt11PaymentError::UnsupportedCurrency`] if the invoice wasn't issued for
+/// the given `network`, and with [`Bolt11PaymentError::InvoiceExpired`] if it already expired at
+/// `duration_since_epoch`, the current time as a duration since the Unix epoch. Otherwise, will
+/// always succeed unless `amount_msat` is below the invoice's amount, in which case we fail with
+/// [`Bolt11PaymentError::AmountMismatch`], while any amount may be paid to invoices not
+/// specifying one.
+///
+/// [BOLT 4]: https://github.com/lightning/bolts/blob/master/04-onion-routing.md
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn payment_parameters_from_invoice_with_amount(
+	invoice: &Bolt11Invoice, amount_msat: u64, network: Network, duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	checked_params_from_invoice(invoice, Some(amount_msat), network, duration_since_epoch)
+}
+
+/// Checks the invoice's network and expiry before building the parameters to pay it, either
+/// paying the invoice's amount or, if given, at least `amount_msat`.
+///
+/// This is shared by all utilities building parameters for a [`Bolt11Invoice`] which check the
+/// invoice, so that none of them skip the checks.
+fn checked_params_from_invoice(
+	invoice: &Bolt11Invoice, amount_msat: Option<u64>, network: Network,
+	duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	check_network_and_expiry(invoice, network, duration_since_epoch)?;
+	match (invoice.amount_milli_satoshis(), amount_msat) {
+		(Some(invoice_amount_msat), Some(amount_msat)) if amount_msat < invoice_amount_msat => {
+			Err(Bolt11PaymentError::AmountMismatch)
+		},
+		(_, Some(amount_msat)) | (Some(amount_msat), None) => {
+			Ok(params_from_invoice(invoice, amount_msat))
+		},
+		(None, None) => Err(Bolt11PaymentError::AmountRequired),
+	}
+}
+
+fn check_network_and_expiry(
+	invoice: &Bolt11Invoice, network: Network, duration_since_epoch: Duration
+) -> Result<(), Bolt11PaymentError> {
+	let expected = Currency::from(network);
+	if invoice.currency() != expected {
+		return Err(Bolt11PaymentError::UnsupportedCurrency { expected, found: invoice.currency() });
+	}
+	if invoice.would_expire(duration_since_epoch) {
+		return Err(Bolt11PaymentError::InvoiceExpired);
+	}
+	Ok(())
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] using
+/// [`ChannelManager::send_payment`] or [`ChannelManager::send_preflight_probes`], while limiting
+/// the total CLTV expiry delta of any route used to `max_total_cltv_expiry_delta`.
+///
+/// This bounds the time our funds may be locked up if the payment gets stuck along the way.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Will fail as [`payment_parameters_from_invoice`] does if the invoice wasn't issued for the
+/// given `network`, already expired at `duration_since_epoch` or has no amount specified, and
+/// with [`Bolt11PaymentError::CltvExpiryLimitTooLow`] if `max_total_cltv_expiry_delta` doesn't
+/// exceed the invoice's [`Bolt11Invoice::min_final_cltv_expiry_delta`], as that wouldn't leave any
+/// room for the CLTV expiry deltas of the hops along the route.
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn payment_parameters_from_invoice_with_max_cltv(
+	invoice: &Bolt11Invoice, max_total_cltv_expiry_delta: u32, network: Network,
+	duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	if (max_total_cltv_expiry_delta as u64) <= invoice.min_final_cltv_expiry_delta() {
+		return Err(Bolt11PaymentError::CltvExpiryLimitTooLow);
+	}
+	let (payment_hash, recipient_onion, mut route_params) =
+		payment_parameters_from_invoice(invoice, network, duration_since_epoch)?;
+	route_params.payment_params.max_total_cltv_expiry_delta = max_total_cltv_expiry_delta;
+	Ok((payment_hash, recipient_onion, route_params))
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] using
+/// [`ChannelManager::send_payment`] or [`ChannelManager::send_preflight_probes`], while limiting
+/// any route used to at most `max_path_length` hops.
+///
+/// This may be used to limit the latency and the number of potential points of failure of a
+/// payment. Note that if the payee can't be reached within the given number of hops, this will
+/// still succeed, but no route will be found when paying.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Will fail as [`payment_parameters_from_invoice`] does if the invoice wasn't issued for the
+/// given `network` or already expired at `duration_since_epoch`. Otherwise, will always succeed
+/// unless the invoice has no amount specified, in which case
+/// [`payment_parameters_from_zero_amount_invoice`] should be used and we fail with
+/// [`Bolt11PaymentError::AmountRequired`].
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn payment_parameters_from_invoice_with_max_hops(
+	invoice: &Bolt11Invoice, max_path_length: u8, network: Network, duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	let (payment_hash, recipient_onion, mut route_params) =
+		payment_parameters_from_invoice(invoice, network, duration_since_epoch)?;
+	route_params.payment_params.max_path_length = max_path_length;
+	Ok((payment_hash, recipient_onion, route_params))
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] using
+/// [`ChannelManager::send_payment`] or [`ChannelManager::send_preflight_probes`], while splitting
+/// the payment into at most `max_path_count` parts of at least `min_shard_amount_msat` each.
+///
+/// This may be used to bound the number of HTLCs used for a payment, e.g., for recipients which
+/// only accept few HTLCs at once or when paying over Tor. Note that if the payment can't be split
+/// within these bounds, this will still succeed, but no route will be found when paying. See
+/// [`PaymentParameters::max_path_count_override`] and
+/// [`PaymentParameters::min_shard_amount_msat`] for details.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Will fail as [`payment_parameters_from_invoice`] does if the invoice wasn't issued for the
+/// given `network`, already expired at `duration_since_epoch` or has no amount specified.
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn payment_parameters_from_invoice_with_shard_limits(
+	invoice: &Bolt11Invoice, max_path_count: u8, min_shard_amount_msat: u64, network: Network,
+	duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	let (payment_hash, recipient_onion, mut route_params) =
+		payment_parameters_from_invoice(invoice, network, duration_since_epoch)?;
+	route_params.payment_params.max_path_count_override = Some(max_path_count);
+	route_params.payment_params.min_shard_amount_msat = Some(min_shard_amount_msat);
+	Ok((payment_hash, recipient_onion, route_params))
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] using
+/// [`ChannelManager::send_payment`] or [`ChannelManager::send_preflight_probes`], while limiting
+/// the total routing fees paid to `max_fee`, e.g., to never pay more than 0.5% plus 5 sats.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Will fail as [`payment_parameters_from_invoice`] does if the invoice wasn't issued for the
+/// given `network` or already expired at `duration_since_epoch`. Otherwise, will always succeed
+/// unless the invoice has no amount specified, in which case
+/// [`payment_parameters_from_zero_amount_invoice_with_fee_limit`] should be used and we fail with
+/// [`Bolt11PaymentError::AmountRequired`].
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn payment_parameters_from_invoice_with_fee_limit(
+	invoice: &Bolt11Invoice, max_fee: MaxTotalRoutingFeeLimit, network: Network,
+	duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	let (payment_hash, recipient_onion, mut route_params) =
+		payment_parameters_from_invoice(invoice, network, duration_since_epoch)?;
+	route_params.max_total_routing_fee_msat = Some(max_fee.max_fee_msat(route_params.final_value_msat));
+	Ok((payment_hash, recipient_onion, route_params))
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given zero-amount
+/// [`Bolt11Invoice`] using [`ChannelManager::send_payment`] or
+/// [`ChannelManager::send_preflight_probes`], while limiting the total routing fees paid to
+/// `max_fee`.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Will fail as [`payment_parameters_from_zero_amount_invoice`] does if the invoice wasn't issued
+/// for the given `network` or already expired at `duration_since_epoch`. Otherwise, will always
+/// succeed unless the invoice has an amount specified, in which case
+/// [`payment_parameters_from_invoice_with_fee_limit`] should be used and we fail with
+/// [`Bolt11PaymentError::AmountMismatch`].
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn payment_parameters_from_zero_amount_invoice_with_fee_limit(
+	invoice: &Bolt11Invoice, amount_msat: u64, max_fee: MaxTotalRoutingFeeLimit, network: Network,
+	duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	let (payment_hash, recipient_onion, mut route_params) =
+		payment_parameters_from_zero_amount_invoice(
+			invoice, amount_msat, network, duration_since_epoch
+		)?;
+	route_params.max_total_routing_fee_msat = Some(max_fee.max_fee_msat(amount_msat));
+	Ok((payment_hash, recipient_onion, route_params))
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] using
+/// [`ChannelManager::send_payment`] or [`ChannelManager::send_preflight_probes`], while sending
+/// the given `custom_tlvs` to the recipient, e.g., a payer note or application-specific data.
+///
+/// As the recipient didn't ask for any of them, all `custom_tlvs` must be of odd type, which
+/// recipients may safely ignore if unknown. They are otherwise subject to the same checks as in
+/// [`RecipientOnionFields::with_custom_tlvs`] and sent alongside any payment metadata included in
+/// the invoice.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Will fail with [`Bolt11PaymentError::InvalidCustomTlvs`] if any of the `custom_tlvs` are of
+/// even type or otherwise invalid, and as [`payment_parameters_from_invoice`] does if the invoice
+/// wasn't issued for the given `network`, already expired at `duration_since_epoch` or has no
+/// amount specified.
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn payment_parameters_from_invoice_with_custom_tlvs(
+	invoice: &Bolt11Invoice, custom_tlvs: Vec<(u64, Vec<u8>)>, network: Network,
+	duration_since_epoch: Duration
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	if custom_tlvs.iter().any(|(typ, _)| typ % 2 == 0) {
+		return Err(Bolt11PaymentError::InvalidCustomTlvs);
+	}
+	let (payment_hash, recipient_onion, route_params) =
+		payment_parameters_from_invoice(invoice, network, duration_since_epoch)?;
+	let recipient_onion = recipient_onion.with_custom_tlvs(custom_tlvs)
+		.map_err(|()| Bolt11PaymentError::InvalidCustomTlvs)?;
+	Ok((payment_hash, recipient_onion, route_params))
+}
+
+/// A limit on the total routing fees paid for a payment, as given to
+/// [`payment_parameters_from_invoice_with_fee_limit`] and
+/// [`payment_parameters_from_zero_amount_invoice_with_fee_limit`].
+///
+/// The limit applies to the fees of all paths of a multi-path payment combined, as well as to any
+/// fees accrued while retrying failed paths, see [`RouteParameters::max_total_routing_fee_msat`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaxTotalRoutingFeeLimit {
+	/// An absolute limit, in millisatoshis.
+	Absolute(u64),
+	/// A limit proportional to the amount paid, plus a fixed base.
+	Proportional {
+		/// The fixed part of the limit, in millisatoshis.
+		base_msat: u64,
+		/// The part of the limit proportional to the amount paid, in millionths of the amount.
+		proportional_millionths: u32,
+	},
+}
+
+impl MaxTotalRoutingFeeLimit {
+	/// Returns the maximum total routing fee, in millisatoshis, for paying `amount_msat`.
+	pub fn max_fee_msat(&self, amount_msat: u64) -> u64 {
+		match self {
+			MaxTotalRoutingFeeLimit::Absolute(max_fee_msat) => *max_fee_msat,
+			MaxTotalRoutingFeeLimit::Proportional { base_msat, proportional_millionths } => {
+				let proportional_fee_msat =
+					amount_msat as u128 * *proportional_millionths as u128 / 1_000_000;
+				let proportional_fee_msat = u64::try_from(proportional_fee_msat).unwrap_or(u64::MAX);
+				base_msat.saturating_add(proportional_fee_msat)
+			},
+		}
+	}
+}
+
+/// An error that may occur when paying a [`Bolt11Invoice`] via [`pay_invoice`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaymentError {
+	/// The invoice can't be paid with the given parameters.
+	Invoice(Bolt11PaymentError),
+	/// The payment failed to be sent.
+	Sending(RetryableSendFailure),
+}
+
+/// An error that may occur when probing a [`Bolt11Invoice`] via [`probe_invoice`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProbingError {
+	/// The invoice can't be paid with the given parameters.
+	Invoice(Bolt11PaymentError),
+	/// The probes failed to be sent.
+	Sending(ProbeSendFailure),
+}
+
+/// Pays the given [`Bolt11Invoice`] via [`ChannelManager::send_payment`], retrying failed
+/// payment paths according to `retry_strategy`.
+///
+/// If the invoice doesn't specify an amount, `amount_msat` needs to be given. Otherwise, it may be
+/// given to deliberately overpay the invoice, see [`payment_parameters_from_invoice_with_amount`].
+/// The total routing fees paid are limited to `max_fee`, if given, or to the default of
+/// [`RouteParameters::from_payment_params_and_value`] otherwise.
+///
+/// If no `payment_id` is given, the [`PaymentId`] is derived from the invoice's payment hash,
+/// which will prevent paying the same invoice twice. It is returned on success and will be
+/// included in the resulting payment events.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// Like [`payment_parameters_from_invoice`], will fail without sending anything if the invoice
+/// wasn't issued for the given `network` or if it already expired at `duration_since_epoch`, the
+/// current time as a duration since the Unix epoch.
+///
+/// Note that this isn't a `ChannelManager` method like [`ChannelManager::pay_for_offer`] is for
+/// BOLT 12 offers, as `lightning` can't depend on this crate, which depends on it.
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::pay_for_offer`]: lightning::ln::channelmanager::ChannelManager::pay_for_offer
+pub fn pay_invoice<C: Deref>(
+	invoice: &Bolt11Invoice, amount_msat: Option<u64>, network: Network,
+	duration_since_epoch: Duration, payment_id: Option<PaymentId>, retry_strategy: Retry,
+	max_fee: Option<MaxTotalRoutingFeeLimit>, channel_manager: &C
+) -> Result<PaymentId, PaymentError>
+where
+	C::Target: AChannelManager,
+{
+	let (payment_hash, recipient_onion, route_params) = route_params_for_invoice(
+		invoice, amount_msat, network, duration_since_epoch, max_fee
+	).map_err(PaymentError::Invoice)?;
+	let payment_id = payment_id.unwrap_or(PaymentId(payment_hash.0));
+	channel_manager.get_cm()
+		.send_payment(payment_hash, recipient_onion, payment_id, route_params, retry_strategy)
+		.map_err(PaymentError::Sending)?;
+	Ok(payment_id)
+}
+
+/// Sends pre-flight probes for paying the given [`Bolt11Invoice`] via
+/// [`ChannelManager::send_preflight_probes`], e.g., while waiting for the user to confirm the
+/// payment.
+///
+/// The `amount_msat`, `network`, `duration_since_epoch`, and `max_fee` are interpreted as by
+/// [`pay_invoice`], while `liquidity_limit_multiplier` is passed on to
+/// [`ChannelManager::send_preflight_probes`], see its documentation for details.
+///
+/// Returns the payment hashes and [`PaymentId`]s of the probes sent on success.
+///
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn probe_invoice<C: Deref>(
+	invoice: &Bolt11Invoice, amount_msat: Option<u64>, network: Network,
+	duration_since_epoch: Duration, liquidity_limit_multiplier: Option<u64>,
+	max_fee: Option<MaxTotalRoutingFeeLimit>, channel_manager: &C
+) -> Result<Vec<(PaymentHash, PaymentId)>, ProbingError>
+where
+	C::Target: AChannelManager,
+{
+	let (_, _, route_params) = route_params_for_invoice(
+		invoice, amount_msat, network, duration_since_epoch, max_fee
+	).map_err(ProbingError::Invoice)?;
+	channel_manager.get_cm()
+		.send_preflight_probes(route_params, liquidity_limit_multiplier)
+		.map_err(ProbingError::Sending)
+}
+
+/// A summary of the outcome of a set of pre-flight probes, as returned by
+/// [`ProbeTracker::report`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProbeReport {
+	/// The number of paths probes were sent over.
+	pub paths_tried: usize,
+	/// The number of paths the probes succeeded over, i.e., which had sufficient liquidity to
+	/// forward the probed amount all the way to the recipient.
+	pub paths_succeeded: usize,
+	/// The total amount probed successfully over all paths, i.e., an estimate of how much we may
+	/// currently send to the recipient over the paths tried.
+	pub estimated_sendable_msat: u64,
+}
+
+/// Tracks the outcome of pre-flight probes, e.g., as sent via [`probe_invoice`] or
+/// [`ChannelManager::send_preflight_probes`], to estimate whether a payment is likely to succeed.
+///
+/// Any [`Event`]s handled should be passed to [`ProbeTracker::handle_event`], e.g., from within
+/// the event handler given to the background processor. As probes may take a while to resolve or
+/// may not resolve at all, it is up to the user to decide when to stop waiting and act on the
+/// current [`ProbeTracker::report`], in which any probes still pending are considered to have
+/// failed.
+///
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+#[derive(Clone, Debug)]
+pub struct ProbeTracker {
+	pending_probes: Vec<PaymentId>,
+	report: ProbeReport,
+}
+
+impl ProbeTracker {
+	/// Creates a new [`ProbeTracker`] for the given probes, as returned when sending them.
+	pub fn new(probes: &[(PaymentHash, PaymentId)]) -> Self {
+		Self {
+			pending_probes: probes.iter().map(|(_, payment_id)| *payment_id).collect(),
+			report: ProbeReport { paths_tried: probes.len(), ..Default::default() },
+		}
+	}
+
+	/// Updates the tracked probes given an [`Event`] generated by the `ChannelManager`.
+	///
+	/// Returns whether the event resolved one of the tracked probes, i.e., was an
+	/// [`Event::ProbeSuccessful`] or [`Event::ProbeFailed`] for a probe still pending.
+	pub fn handle_event(&mut self, event: &Event) -> bool {
+		let (payment_id, successful_path) = match event {
+			Event::ProbeSuccessful { payment_id, path, .. } => (payment_id, Some(path)),
+			Event::ProbeFailed { payment_id, .. } => (payment_id, None),
+			_ => return false,
+		};
+		match self.pending_probes.iter().position(|id| id == payment_id) {
+			Some(idx) => { self.pending_probes.swap_remove(idx); },
+			None => return false,
+		}
+		if let Some(path) = successful_path {
+			self.report.paths_succeeded += 1;
+			self.report.estimated_sendable_msat =
+				self.report.estimated_sendable_msat.saturating_add(path.final_value_msat());
+		}
+		true
+	}
+
+	/// Returns whether all tracked probes resolved.
+	pub fn is_complete(&self) -> bool {
+		self.pending_probes.is_empty()
+	}
+
+	/// Returns a [`ProbeReport`] summarizing the outcome of the probes resolved so far.
+	pub fn report(&self) -> ProbeReport {
+		self.report
+	}
+}
+
+fn route_params_for_invoice(
+	invoice: &Bolt11Invoice, amount_msat: Option<u64>, network: Network,
+	duration_since_epoch: Duration, max_fee: Option<MaxTotalRoutingFeeLimit>
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Bolt11PaymentError> {
+	let (payment_hash, recipient_onion, mut route_params) =
+		checked_params_from_invoice(invoice, amount_msat, network, duration_since_epoch)?;
+	if let Some(max_fee) = max_fee {
+		route_params.max_total_routing_fee_msat = Some(max_fee.max_fee_msat(route_params.final_value_msat));
+	}
+	Ok((payment_hash, recipient_onion, route_params))
+}
+
+/// An issue preventing the payment of a [`Bolt11Invoice`], as reported by
+/// [`validate_invoice_for_payment`] and the `payment_parameters_from_*` utilities.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bolt11PaymentError {
+	/// The invoice was issued for a different currency, i.e., network, than the one we're paying
+	/// on.
+	UnsupportedCurrency {
+		/// The currency we're paying on.
+		expected: Currency,
+		/// The currency the invoice was issued for.
+		found: Currency,
+	},
+	/// The invoice expired.
+	InvoiceExpired,
+	/// The invoice requires features we don't support.
+	UnsupportedFeatures,
+	/// The amount we intend to pay is below the amount requested by the invoice, or the invoice
+	/// requests an amount at all when paying it via
+	/// [`payment_parameters_from_zero_amount_invoice`].
+	AmountMismatch,
+	/// Neither the invoice nor we specified an amount to pay.
+	AmountRequired,
+	/// The limit on the total CLTV expiry delta of the route doesn't exceed the final CLTV expiry
+	/// delta required by the invoice, leaving no room for the hops along the route.
+	CltvExpiryLimitTooLow,
+	/// The custom TLVs we intend to send are invalid, e.g., because they aren't in the range
+	/// reserved for custom types or, as the recipient didn't ask for them, are of even type.
+	InvalidCustomTlvs,
+}
+
+/// The context a [`Bolt11Invoice`] is checked against by [`validate_invoice_for_payment`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationContext {
+	/// The currency, i.e., the network, we're paying on.
+	pub currency: Currency,
+	/// The current time as a duration since the Unix epoch, used to check the invoice's expiry.
+	pub duration_since_epoch: Duration,
+	/// The amount we intend to pay. Required for zero-amount invoices, while it may not be below
+	/// the invoice's amount otherwise, if given. Paying more than the invoice's amount is allowed,
+	/// see [`payment_parameters_from_invoice_with_amount`] for the caveats of doing so.
+	pub amount_msat: Option<u64>,
+	/// The features we support when paying. If given, the invoice may not require any others.
+	/// Otherwise, it may only not require any features unknown to LDK.
+	pub supported_features: Option<Bolt11InvoiceFeatures>,
+}
+
+/// Builds the necessary parameters to pay or pre-flight probe the given [`Bolt11Invoice`] using
+/// [`ChannelManager::send_payment`] or [`ChannelManager::send_preflight_probes`], after checking
+/// it may be paid in the given [`ValidationContext`].
+///
+/// Rather than failing on the first problem, all issues with the invoice are reported at once,
+/// e.g., so that they can all be shown to the user when entering an invoice. They are reported in
+/// the order of the [`Bolt11PaymentError`] variants.
+///
+/// Prior to paying, you must ensure that the [`Bolt11Invoice::payment_hash`] is unique and the
+/// same [`PaymentHash`] has never been paid before.
+///
+/// [`ChannelManager::send_payment`]: lightning::ln::channelmanager::ChannelManager::send_payment
+/// [`ChannelManager::send_preflight_probes`]: lightning::ln::channelmanager::ChannelManager::send_preflight_probes
+pub fn validate_invoice_for_payment(invoice: &Bolt11Invoice, ctx: &ValidationContext)
+-> Result<(PaymentHash, RecipientOnionFields, RouteParameters), Vec<Bolt11PaymentError>> {
+	let mut errors = Vec::new();
+
+	if invoice.currency() != ctx.currency {
+		errors.push(Bolt11PaymentError::UnsupportedCurrency {
+			expected: ctx.currency.clone(), found: invoice.currency(),
+		});
+	}
+
+	if invoice.would_expire(ctx.duration_since_epoch) {
+		errors.push(Bolt11PaymentError::InvoiceExpired);
+	}
+
+	if let Some(features) = invoice.features() {
+		let requires_unsupported_bits = match &ctx.supported_features {
+			Some(supported_features) => features.requires_unknown_bits_from(supported_features),
+			None => features.requires_unknown_bits(),
+		};
+		if requires_unsupported_bits {
+			errors.push(Bolt11PaymentError::UnsupportedFeatures);
+		}
+	}
+
+	let amount_msat = match (invoice.amount_milli_satoshis(), ctx.amount_msat) {
+		(Some(invoice_amount_msat), Some(amount_msat)) if amount_msat < invoice_amount_msat => {
+			errors.push(Bolt11PaymentError::AmountMismatch);
+			None
+		},
+		(_, Some(amount_msat)) | (Some(amount_msat), None) => Some(amount_msat),
+		(None, None) => {
+			errors.push(Bolt11PaymentError::AmountRequired);
+			None
+		},
+	};
+
+	match amount_msat {
+		Some(amount_msat) if errors.is_empty() => Ok(params_from_invoice(invoice, amount_msat)),
+		_ => Err(errors),
+	}
+}
+
+/// Returns whether a payment to the given [`Bolt11Invoice`] should be given up on, i.e., whether
+/// the invoice expired more than `grace_period` before `duration_since_epoch`.
+///
+/// Retry loops should stop retrying and abandon the payment via
+/// [`ChannelManager::abandon_payment`] once this returns `true`, as the recipient may not accept
+/// any further payment attempts. The grace period allows to tolerate clock skew between us and
+/// the recipient.
+///
+/// [`ChannelManager::abandon_payment`]: lightning::ln::channelmanager::ChannelManager::abandon_payment
+pub fn should_abandon(
+	invoice: &Bolt11Invoice, duration_since_epoch: Duration, grace_period: Duration
+) -> bool {
+	invoice.would_expire(duration_since_epoch.saturating_sub(grace_period))
+}
+
+/// An issue preventing a spontaneous (keysend) payment, as reported by
+/// [`spontaneous_payment_parameters`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpontaneousPaymentError {
+	/// The maximum number of paths to send the payment over was zero.
+	InvalidPathCount,
+	/// The final CLTV expiry delta is less than [`MIN_FINAL_CLTV_EXPIRY_DELTA`], so the payee
+	/// would reject the payment.
+	FinalCltvExpiryDeltaTooLow,
+	/// The custom TLVs we intend to send are invalid, see
+	/// [`RecipientOnionFields::with_custom_tlvs`].
+	InvalidCustomTlvs,
+}
+
+/// Builds the necessary parameters to send a spontaneous (keysend) payment of `amount_msat` to
+/// `payee_pubkey` over at most `max_path_count` paths using
+/// [`ChannelManager::send_spontaneous_payment_with_retry`].
+///
+/// If no `payment_preimage` is given, a fresh one is generated using the given `entropy_source`.
+/// Either way, it is returned alongside its [`PaymentHash`] and needs to be passed along when
+/// sending.
+///
+/// If `max_path_count` is greater than one and `payee_features` signal support for `basic_mpp`, a
+/// random [`PaymentSecret`] is included in the returned [`RecipientOnionFields`] as it is required
+/// for multi-path payments. Otherwise, as MPP keysend is not widely supported yet, this falls back
+/// to a single-path payment without a payment secret. The `payee_features` should be the payee's
+/// announced [`NodeFeatures`], e.g., as retrieved from the network graph.
+///
+/// The `custom_tlvs` are included in the returned [`RecipientOnionFields`] and are subject to
+/// the same checks as in [`RecipientOnionFields::with_custom_tlvs`]. Note that, following the
+/// "it's okay to be odd" rule, the payee will fail the payment back if it doesn't understand any
+/// of the even TLV types, while unknown odd types are ignored.
+///
+/// Will fail if `max_path_count` is zero, if any of the `custom_tlvs` are invalid, or if
+/// `final_cltv_expiry_delta` is less than [`MIN_FINAL_CLTV_EXPIRY_DELTA`], as the payee would
+/// reject the payment otherwise.
+///
+/// [`ChannelManager::send_spontaneous_payment_with_retry`]: lightning::ln::channelmanager::ChannelManager::send_spontaneous_payment_with_retry
+pub fn spontaneous_payment_parameters<ES: Deref>(
+	payee_pubkey: PublicKey, payee_features: &NodeFeatures, amount_msat: u64, max_path_count: u8,
+	final_cltv_expiry_delta: u32, payment_preimage: Option<PaymentPreimage>,
+	custom_tlvs: Vec<(u64, Vec<u8>)>, entropy_source: &ES
+) -> Result<(PaymentHash, RecipientOnionFields, RouteParameters, PaymentPreimage), SpontaneousPaymentError>
+where
+	ES::Target: EntropySource,
+{
+	if max_path_count == 0 {
+		return Err(SpontaneousPaymentError::InvalidPathCount);
+	}
+	if final_cltv_expiry_delta < MIN_FINAL_CLTV_EXPIRY_DELTA as u32 {
+		return Err(SpontaneousPaymentError::FinalCltvExpiryDeltaTooLow);
 	}
+
+	let payment_preimage = payment_preimage
+		.unwrap_or_else(|| PaymentPreimage(entropy_source.get_secure_random_bytes()));
+	let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).to_byte_array());
+
+	let allow_mpp = max_path_count > 1 && payee_features.supports_basic_mpp();
+	let (recipient_onion, max_path_count) = if allow_mpp {
+		let payment_secret = PaymentSecret(entropy_source.get_secure_random_bytes());
+		(RecipientOnionFields::secret_only(payment_secret), max_path_count)
+	} else {
+		(RecipientOnionFields::spontaneous_empty(), 1)
+	};
+	let recipient_onion = recipient_onion.with_custom_tlvs(custom_tlvs)
+		.map_err(|()| SpontaneousPaymentError::InvalidCustomTlvs)?;
+
+	let payment_params =
+		PaymentParameters::for_keysend(payee_pubkey, final_cltv_expiry_delta, allow_mpp)
+		.with_max_path_count(max_path_count);
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params, amount_msat);
+	Ok((payment_hash, recipient_onion, route_params, payment_preimage))
 }
 
 fn params_from_invoice(invoice: &Bolt11Invoice, amount_msat: u64)
@@ -84,11 +760,9 @@ fn params_from_invoice(invoice: &Bolt11Invoice, amount_msat: u64)
 mod tests {
 	use super::*;
 	use crate::{InvoiceBuilder, Currency};
-	use bitcoin::hashes::sha256::Hash as Sha256;
 	use lightning::ln::types::PaymentSecret;
-	use lightning::routing::router::Payee;
+	use lightning::routing::router::{MAX_PATH_LENGTH_ESTIMATE, Payee};
 	use secp256k1::{SecretKey, PublicKey, Secp256k1};
-	use core::time::Duration;
 	#[cfg(feature = "std")]
 	use std::time::SystemTime;
 
@@ -120,9 +794,13 @@ mod tests {
 			})
 			.unwrap();
 
-		assert!(payment_parameters_from_zero_amount_invoice(&invoice, 42).is_err());
+		assert_eq!(payment_parameters_from_zero_amount_invoice(&invoice, 42, Network::Bitcoin, duration_since_epoch()),
+			Err(Bolt11PaymentError::AmountMismatch));
+		assert_eq!(payment_parameters_from_zero_amount_invoice(&invoice, 128, Network::Bitcoin, duration_since_epoch()),
+			Err(Bolt11PaymentError::AmountMismatch));
 
-		let (hash, onion, params) = payment_parameters_from_invoice(&invoice).unwrap();
+		let (hash, onion, params) =
+			payment_parameters_from_invoice(&invoice, Network::Bitcoin, duration_since_epoch()).unwrap();
 		assert_eq!(&hash.0[..], &payment_hash[..]);
 		assert_eq!(onion.payment_secret, Some(PaymentSecret([0; 32])));
 		assert_eq!(params.final_value_msat, 128);
@@ -152,9 +830,12 @@ mod tests {
 			})
 		.unwrap();
 
-		assert!(payment_parameters_from_invoice(&invoice).is_err());
+		assert_eq!(payment_parameters_from_invoice(&invoice, Network::Bitcoin, duration_since_epoch()),
+			Err(Bolt11PaymentError::AmountRequired));
 
-		let (hash, onion, params) = payment_parameters_from_zero_amount_invoice(&invoice, 42).unwrap();
+		let (hash, onion, params) = payment_parameters_from_zero_amount_invoice(
+			&invoice, 42, Network::Bitcoin, duration_since_epoch()
+		).unwrap();
 		assert_eq!(&hash.0[..], &payment_hash[..]);
 		assert_eq!(onion.payment_secret, Some(PaymentSecret([0; 32])));
 		assert_eq!(params.final_value_msat, 42);
@@ -167,55 +848,1038 @@ mod tests {
 	}
 
 	#[test]
-	#[cfg(feature = "std")]
-	fn payment_metadata_end_to_end() {
-		use lightning::events::Event;
-		use lightning::ln::channelmanager::{Retry, PaymentId};
-		use lightning::ln::msgs::ChannelMessageHandler;
-		use lightning::ln::functional_test_utils::*;
-		// Test that a payment metadata read from an invoice passed to `pay_invoice` makes it all
-		// the way out through the `PaymentClaimable` event.
-		let chanmon_cfgs = create_chanmon_cfgs(2);
-		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
-		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
-		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
-		create_announced_chan_between_nodes(&nodes, 0, 1);
+	fn network_and_expiry_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+		let created_at = duration_since_epoch();
 
-		let payment_metadata = vec![42, 43, 44, 45, 46, 47, 48, 49, 42];
+		let build_invoice = |currency: Currency, amount_msat: Option<u64>| {
+			let builder = InvoiceBuilder::new(currency)
+				.description("test".into())
+				.payment_hash(payment_hash)
+				.payment_secret(PaymentSecret([0; 32]))
+				.duration_since_epoch(created_at)
+				.expiry_time(Duration::from_secs(3600))
+				.min_final_cltv_expiry_delta(144);
+			match amount_msat {
+				Some(amount_msat) => builder.amount_milli_satoshis(amount_msat)
+					.build_signed(|hash| secp_ctx.sign_ecdsa_recoverable(hash, &private_key)),
+				None => builder.build_signed(|hash| secp_ctx.sign_ecdsa_recoverable(hash, &private_key)),
+			}.unwrap()
+		};
+		let invoice = build_invoice(Currency::BitcoinTestnet, Some(128));
+		let zero_amount_invoice = build_invoice(Currency::BitcoinTestnet, None);
+		let expires_at = invoice.expires_at().unwrap();
 
-		let (payment_hash, payment_secret) =
-			nodes[1].node.create_inbound_payment(None, 7200, None).unwrap();
+		// Invoices for another network are rejected...
+		let wrong_network_error = Err(Bolt11PaymentError::UnsupportedCurrency {
+			expected: Currency::Bitcoin, found: Currency::BitcoinTestnet,
+		});
+		assert_eq!(payment_parameters_from_invoice(&invoice, Network::Bitcoin, created_at), wrong_network_error);
+		assert_eq!(payment_parameters_from_zero_amount_invoice(
+			&zero_amount_invoice, 42, Network::Bitcoin, created_at
+		), wrong_network_error);
+
+		// ...as are invoices that already expired.
+		assert!(payment_parameters_from_invoice(&invoice, Network::Testnet, expires_at).is_ok());
+		assert_eq!(payment_parameters_from_invoice(
+			&invoice, Network::Testnet, expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+		assert!(payment_parameters_from_zero_amount_invoice(
+			&zero_amount_invoice, 42, Network::Testnet, expires_at
+		).is_ok());
+		assert_eq!(payment_parameters_from_zero_amount_invoice(
+			&zero_amount_invoice, 42, Network::Testnet, expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+
+		// Overpaying doesn't skip the checks either.
+		assert_eq!(payment_parameters_from_invoice_with_amount(
+			&invoice, 256, Network::Bitcoin, created_at
+		), wrong_network_error);
+		assert_eq!(payment_parameters_from_invoice_with_amount(
+			&invoice, 256, Network::Testnet, expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+
+		// Nor does limiting the routing fees.
+		let fee_limit = MaxTotalRoutingFeeLimit::Absolute(1234);
+		assert_eq!(payment_parameters_from_invoice_with_fee_limit(
+			&invoice, fee_limit, Network::Bitcoin, created_at
+		), wrong_network_error);
+		assert_eq!(payment_parameters_from_zero_amount_invoice_with_fee_limit(
+			&zero_amount_invoice, 42, fee_limit, Network::Testnet,
+			expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+
+		// Nor does capping the total CLTV expiry delta.
+		assert_eq!(payment_parameters_from_invoice_with_max_cltv(
+			&invoice, 500, Network::Bitcoin, created_at
+		), wrong_network_error);
+		assert_eq!(payment_parameters_from_invoice_with_max_cltv(
+			&invoice, 500, Network::Testnet, expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+
+		// Nor does limiting the number of hops.
+		assert_eq!(payment_parameters_from_invoice_with_max_hops(
+			&invoice, 3, Network::Bitcoin, created_at
+		), wrong_network_error);
+		assert_eq!(payment_parameters_from_invoice_with_max_hops(
+			&invoice, 3, Network::Testnet, expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+
+		// Nor does limiting the number of parts of the payment.
+		assert_eq!(payment_parameters_from_invoice_with_shard_limits(
+			&invoice, 3, 42, Network::Bitcoin, created_at
+		), wrong_network_error);
+		assert_eq!(payment_parameters_from_invoice_with_shard_limits(
+			&invoice, 3, 42, Network::Testnet, expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+
+		// Nor does sending custom TLVs.
+		let custom_tlvs = vec![((1 << 16) + 1, vec![])];
+		assert_eq!(payment_parameters_from_invoice_with_custom_tlvs(
+			&invoice, custom_tlvs.clone(), Network::Bitcoin, created_at
+		), wrong_network_error);
+		assert_eq!(payment_parameters_from_invoice_with_custom_tlvs(
+			&invoice, custom_tlvs, Network::Testnet, expires_at + Duration::from_secs(1)
+		), Err(Bolt11PaymentError::InvoiceExpired));
+
+		// The unchecked variants only check the amount.
+		let (_, _, params) = payment_parameters_from_invoice_unchecked(&invoice).unwrap();
+		assert_eq!(params.final_value_msat, 128);
+		assert_eq!(payment_parameters_from_invoice_unchecked(&zero_amount_invoice),
+			Err(Bolt11PaymentError::AmountRequired));
+		let (_, _, params) =
+			payment_parameters_from_zero_amount_invoice_unchecked(&zero_amount_invoice, 42).unwrap();
+		assert_eq!(params.final_value_msat, 42);
+		assert_eq!(payment_parameters_from_zero_amount_invoice_unchecked(&invoice, 42),
+			Err(Bolt11PaymentError::AmountMismatch));
+	}
+
+	#[test]
+	fn overpaying_invoice_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
 
 		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
 			.description("test".into())
-			.payment_hash(Sha256::from_slice(&payment_hash.0).unwrap())
-			.payment_secret(payment_secret)
-			.current_timestamp()
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
 			.min_final_cltv_expiry_delta(144)
-			.amount_milli_satoshis(50_000)
-			.payment_metadata(payment_metadata.clone())
+			.amount_milli_satoshis(128)
 			.build_signed(|hash| {
-				Secp256k1::new().sign_ecdsa_recoverable(hash,
-					&nodes[1].keys_manager.backing.get_node_secret_key())
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
 			})
 			.unwrap();
 
-		let (hash, onion, params) = payment_parameters_from_invoice(&invoice).unwrap();
-		nodes[0].node.send_payment(hash, onion, PaymentId(hash.0), params, Retry::Attempts(0)).unwrap();
-		check_added_monitors(&nodes[0], 1);
-		let send_event = SendEvent::from_node(&nodes[0]);
-		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &send_event.msgs[0]);
-		commitment_signed_dance!(nodes[1], nodes[0], &send_event.commitment_msg, false);
-
-		expect_pending_htlcs_forwardable!(nodes[1]);
+		// We may pay exactly the invoice's amount or more, but not less.
+		let with_amount = |invoice, amount_msat| payment_parameters_from_invoice_with_amount(
+			invoice, amount_msat, Network::Bitcoin, duration_since_epoch()
+		);
+		assert_eq!(with_amount(&invoice, 127), Err(Bolt11PaymentError::AmountMismatch));
+		assert_eq!(with_amount(&invoice, 128), payment_parameters_from_invoice_unchecked(&invoice));
+		let (hash, onion, params) = with_amount(&invoice, 256).unwrap();
+		assert_eq!(&hash.0[..], &payment_hash[..]);
+		assert_eq!(onion.payment_secret, Some(PaymentSecret([0; 32])));
+		assert_eq!(params.final_value_msat, 256);
 
-		let mut events = nodes[1].node.get_and_clear_pending_events();
-		assert_eq!(events.len(), 1);
-		match events.pop().unwrap() {
-			Event::PaymentClaimable { onion_fields, .. } => {
-				assert_eq!(Some(payment_metadata), onion_fields.unwrap().payment_metadata);
-			},
-			_ => panic!("Unexpected event")
-		}
+		// Any amount may be paid to a zero-amount invoice.
+		let zero_amount_invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+		assert_eq!(with_amount(&zero_amount_invoice, 42),
+			payment_parameters_from_zero_amount_invoice_unchecked(&zero_amount_invoice, 42));
+	}
+
+	#[test]
+	fn fee_limit_invoice_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(2_000_000)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+
+		let absolute_limit = MaxTotalRoutingFeeLimit::Absolute(1234);
+		let (hash, _, params) = payment_parameters_from_invoice_with_fee_limit(
+			&invoice, absolute_limit, Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		assert_eq!(&hash.0[..], &payment_hash[..]);
+		assert_eq!(params.final_value_msat, 2_000_000);
+		assert_eq!(params.max_total_routing_fee_msat, Some(1234));
+
+		// 0.5% of the amount plus 5 sats.
+		let proportional_limit =
+			MaxTotalRoutingFeeLimit::Proportional { base_msat: 5_000, proportional_millionths: 5_000 };
+		let (_, _, params) = payment_parameters_from_invoice_with_fee_limit(
+			&invoice, proportional_limit, Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		assert_eq!(params.max_total_routing_fee_msat, Some(10_000 + 5_000));
+		assert_eq!(payment_parameters_from_zero_amount_invoice_with_fee_limit(
+			&invoice, 42, proportional_limit, Network::Bitcoin, duration_since_epoch()
+		), Err(Bolt11PaymentError::AmountMismatch));
+
+		let zero_amount_invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+		let (_, _, params) = payment_parameters_from_zero_amount_invoice_with_fee_limit(
+			&zero_amount_invoice, 4_000_000, proportional_limit, Network::Bitcoin,
+			duration_since_epoch()
+		).unwrap();
+		assert_eq!(params.final_value_msat, 4_000_000);
+		assert_eq!(params.max_total_routing_fee_msat, Some(20_000 + 5_000));
+		assert_eq!(payment_parameters_from_invoice_with_fee_limit(
+			&zero_amount_invoice, proportional_limit, Network::Bitcoin, duration_since_epoch()
+		), Err(Bolt11PaymentError::AmountRequired));
+
+		// The proportional limit doesn't overflow for large amounts.
+		let max_limit =
+			MaxTotalRoutingFeeLimit::Proportional { base_msat: 1, proportional_millionths: u32::MAX };
+		assert_eq!(max_limit.max_fee_msat(u64::MAX), u64::MAX);
+		assert_eq!(max_limit.max_fee_msat(1_000_000), u32::MAX as u64 + 1);
+	}
+
+	#[test]
+	fn custom_tlvs_invoice_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(128)
+			.payment_metadata(vec![42; 4])
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+
+		let custom_tlvs = vec![((1 << 16) + 3, vec![1]), ((1 << 16) + 1, vec![2, 3])];
+		let (hash, onion, params) = payment_parameters_from_invoice_with_custom_tlvs(
+			&invoice, custom_tlvs, Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		assert_eq!(&hash.0[..], &payment_hash[..]);
+		assert_eq!(onion.payment_secret, Some(PaymentSecret([0; 32])));
+		assert_eq!(onion.payment_metadata, Some(vec![42; 4]));
+		assert_eq!(onion.custom_tlvs(), &vec![((1 << 16) + 1, vec![2, 3]), ((1 << 16) + 3, vec![1])]);
+		assert_eq!(params.final_value_msat, 128);
+
+		// Even types may not be understood by the recipient, which didn't ask for them.
+		for bad_tlvs in [
+			vec![(1 << 16, vec![])],
+			vec![((1 << 16) + 1, vec![]), (5482373484, vec![])],
+			vec![(43, vec![])],
+			vec![((1 << 16) + 1, vec![]), ((1 << 16) + 1, vec![1])],
+		] {
+			assert_eq!(payment_parameters_from_invoice_with_custom_tlvs(
+				&invoice, bad_tlvs, Network::Bitcoin, duration_since_epoch()
+			), Err(Bolt11PaymentError::InvalidCustomTlvs));
+		}
+
+		let zero_amount_invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+		assert_eq!(payment_parameters_from_invoice_with_custom_tlvs(
+			&zero_amount_invoice, vec![((1 << 16) + 1, vec![])], Network::Bitcoin,
+			duration_since_epoch()
+		), Err(Bolt11PaymentError::AmountRequired));
+	}
+
+	#[test]
+	fn max_cltv_invoice_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(128)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+
+		let with_max_cltv = |max_cltv| payment_parameters_from_invoice_with_max_cltv(
+			&invoice, max_cltv, Network::Bitcoin, duration_since_epoch()
+		);
+
+		// We can't cap the total CLTV expiry delta at or below what the recipient requires.
+		assert_eq!(with_max_cltv(143), Err(Bolt11PaymentError::CltvExpiryLimitTooLow));
+		assert_eq!(with_max_cltv(144), Err(Bolt11PaymentError::CltvExpiryLimitTooLow));
+
+		let (hash, _, params) = with_max_cltv(145).unwrap();
+		assert_eq!(&hash.0[..], &payment_hash[..]);
+		assert_eq!(params.payment_params.max_total_cltv_expiry_delta, 145);
+		assert_eq!(params.final_value_msat, 128);
+
+		let (_, _, params) = with_max_cltv(500).unwrap();
+		assert_eq!(params.payment_params.max_total_cltv_expiry_delta, 500);
+	}
+
+	#[test]
+	fn max_hops_invoice_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(128)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+
+		let (_, _, params) = payment_parameters_from_invoice_unchecked(&invoice).unwrap();
+		assert_eq!(params.payment_params.max_path_length, MAX_PATH_LENGTH_ESTIMATE);
+
+		let (hash, _, params) = payment_parameters_from_invoice_with_max_hops(
+			&invoice, 3, Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		assert_eq!(&hash.0[..], &payment_hash[..]);
+		assert_eq!(params.payment_params.max_path_length, 3);
+		assert_eq!(params.final_value_msat, 128);
+
+		let zero_amount_invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+		assert_eq!(payment_parameters_from_invoice_with_max_hops(
+			&zero_amount_invoice, 3, Network::Bitcoin, duration_since_epoch()
+		), Err(Bolt11PaymentError::AmountRequired));
+	}
+
+	#[test]
+	fn shard_limits_invoice_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(150_000_000)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+
+		let (_, _, params) = payment_parameters_from_invoice_unchecked(&invoice).unwrap();
+		assert_eq!(params.payment_params.max_path_count_override, None);
+		assert_eq!(params.payment_params.min_shard_amount_msat, None);
+
+		let (hash, _, params) = payment_parameters_from_invoice_with_shard_limits(
+			&invoice, 3, 50_000_000, Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		assert_eq!(&hash.0[..], &payment_hash[..]);
+		assert_eq!(params.payment_params.max_path_count_override, Some(3));
+		assert_eq!(params.payment_params.min_shard_amount_msat, Some(50_000_000));
+		assert_eq!(params.final_value_msat, 150_000_000);
+
+		let zero_amount_invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(duration_since_epoch())
+			.min_final_cltv_expiry_delta(144)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+		assert_eq!(payment_parameters_from_invoice_with_shard_limits(
+			&zero_amount_invoice, 3, 50_000_000, Network::Bitcoin, duration_since_epoch()
+		), Err(Bolt11PaymentError::AmountRequired));
+	}
+
+	#[test]
+	fn should_abandon_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+		let created_at = duration_since_epoch();
+
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(created_at)
+			.expiry_time(Duration::from_secs(3600))
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(128)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+
+		let expires_at = invoice.expires_at().unwrap();
+		let grace_period = Duration::from_secs(60);
+
+		// A fresh invoice shouldn't be abandoned.
+		assert!(!should_abandon(&invoice, created_at, grace_period));
+		assert!(!should_abandon(&invoice, created_at, Duration::ZERO));
+
+		// Neither should an expired invoice as long as we're within the grace period.
+		assert!(!should_abandon(&invoice, expires_at + Duration::from_secs(30), grace_period));
+		assert!(!should_abandon(&invoice, expires_at + grace_period, grace_period));
+		assert!(should_abandon(&invoice, expires_at + Duration::from_secs(30), Duration::ZERO));
+
+		// Once the grace period passed, we give up.
+		assert!(should_abandon(&invoice, expires_at + grace_period + Duration::from_secs(1), grace_period));
+	}
+
+	#[test]
+	fn validate_invoice_for_payment_test() {
+		let payment_hash = Sha256::hash(&[0; 32]);
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+		let created_at = duration_since_epoch();
+
+		let invoice = InvoiceBuilder::new(Currency::Regtest)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(created_at)
+			.expiry_time(Duration::from_secs(3600))
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(128)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+
+		let ctx = ValidationContext {
+			currency: Currency::Regtest,
+			duration_since_epoch: created_at,
+			amount_msat: None,
+			supported_features: None,
+		};
+		let (hash, onion, params) = validate_invoice_for_payment(&invoice, &ctx).unwrap();
+		assert_eq!(&hash.0[..], &payment_hash[..]);
+		assert_eq!(onion.payment_secret, Some(PaymentSecret([0; 32])));
+		assert_eq!(params.final_value_msat, 128);
+		assert_eq!(Ok((hash, onion, params)), payment_parameters_from_invoice(&invoice, Network::Regtest, created_at));
+
+		let matching_amount_ctx = ValidationContext { amount_msat: Some(128), ..ctx.clone() };
+		assert!(validate_invoice_for_payment(&invoice, &matching_amount_ctx).is_ok());
+
+		// We may deliberately overpay, paying the amount given rather than the invoice's.
+		let overpaying_ctx = ValidationContext { amount_msat: Some(256), ..ctx.clone() };
+		let (_, _, params) = validate_invoice_for_payment(&invoice, &overpaying_ctx).unwrap();
+		assert_eq!(params.final_value_msat, 256);
+
+		// Check all issues are reported at once.
+		let mut payment_secret_features = Bolt11InvoiceFeatures::empty();
+		payment_secret_features.set_payment_secret_optional();
+		let failing_ctx = ValidationContext {
+			currency: Currency::Bitcoin,
+			duration_since_epoch: invoice.expires_at().unwrap() + Duration::from_secs(1),
+			amount_msat: Some(127),
+			supported_features: Some(payment_secret_features),
+		};
+		assert_eq!(validate_invoice_for_payment(&invoice, &failing_ctx), Err(vec![
+			Bolt11PaymentError::UnsupportedCurrency { expected: Currency::Bitcoin, found: Currency::Regtest },
+			Bolt11PaymentError::InvoiceExpired, Bolt11PaymentError::UnsupportedFeatures,
+			Bolt11PaymentError::AmountMismatch,
+		]));
+
+		// Zero-amount invoices require us to specify the amount.
+		let zero_amount_invoice = InvoiceBuilder::new(Currency::Regtest)
+			.description("test".into())
+			.payment_hash(payment_hash)
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(created_at)
+			.min_final_cltv_expiry_delta(144)
+			.build_signed(|hash| {
+				secp_ctx.sign_ecdsa_recoverable(hash, &private_key)
+			})
+			.unwrap();
+		let wrong_network_ctx = ValidationContext { currency: Currency::Bitcoin, ..ctx.clone() };
+		assert_eq!(validate_invoice_for_payment(&zero_amount_invoice, &wrong_network_ctx), Err(vec![
+			Bolt11PaymentError::UnsupportedCurrency { expected: Currency::Bitcoin, found: Currency::Regtest },
+			Bolt11PaymentError::AmountRequired,
+		]));
+		let (_, _, params) =
+			validate_invoice_for_payment(&zero_amount_invoice, &matching_amount_ctx).unwrap();
+		assert_eq!(params.final_value_msat, 128);
+	}
+
+	#[test]
+	fn spontaneous_mpp_test() {
+		struct CountingEntropySource(core::cell::Cell<u8>);
+		impl EntropySource for CountingEntropySource {
+			fn get_secure_random_bytes(&self) -> [u8; 32] {
+				self.0.set(self.0.get() + 1);
+				[self.0.get(); 32]
+			}
+		}
+
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+		let public_key = PublicKey::from_secret_key(&secp_ctx, &private_key);
+		let entropy_source = CountingEntropySource(core::cell::Cell::new(0));
+
+		let mut mpp_features = NodeFeatures::empty();
+		mpp_features.set_basic_mpp_optional();
+
+		assert_eq!(spontaneous_payment_parameters(
+			public_key, &mpp_features, 100_000, 0, 40, None, Vec::new(), &&entropy_source
+		), Err(SpontaneousPaymentError::InvalidPathCount));
+
+		// The payee would reject HTLCs expiring too soon, no matter how many paths we use.
+		let min_final_cltv_expiry_delta = MIN_FINAL_CLTV_EXPIRY_DELTA as u32;
+		assert_eq!(spontaneous_payment_parameters(
+			public_key, &mpp_features, 100_000, 4, min_final_cltv_expiry_delta - 1, None, Vec::new(),
+			&&entropy_source
+		), Err(SpontaneousPaymentError::FinalCltvExpiryDeltaTooLow));
+
+		let (hash, onion, params, preimage) = spontaneous_payment_parameters(
+			public_key, &mpp_features, 100_000, 4, 40, None, Vec::new(), &&entropy_source
+		).unwrap();
+		assert_eq!(hash, PaymentHash(Sha256::hash(&preimage.0).to_byte_array()));
+		assert_ne!(preimage, PaymentPreimage([0; 32]));
+		assert!(onion.payment_secret.is_some());
+		assert_ne!(onion.payment_secret, Some(PaymentSecret(preimage.0)));
+		assert_eq!(params.final_value_msat, 100_000);
+		assert_eq!(params.payment_params.max_path_count, 4);
+		match params.payment_params.payee {
+			Payee::Clear { node_id, features, final_cltv_expiry_delta, .. } => {
+				assert_eq!(node_id, public_key);
+				assert!(features.unwrap().supports_basic_mpp());
+				assert_eq!(final_cltv_expiry_delta, 40);
+			},
+			_ => panic!(),
+		}
+
+		// If the payee doesn't support MPP, we fall back to a single path without a secret.
+		let (_, onion, params, other_preimage) = spontaneous_payment_parameters(
+			public_key, &NodeFeatures::empty(), 100_000, 4, 40, None, Vec::new(), &&entropy_source
+		).unwrap();
+		assert_ne!(other_preimage, preimage);
+		assert_eq!(onion.payment_secret, None);
+		assert_eq!(params.payment_params.max_path_count, 1);
+		match params.payment_params.payee {
+			Payee::Clear { features, .. } => assert!(!features.unwrap().supports_basic_mpp()),
+			_ => panic!(),
+		}
+	}
+
+	#[test]
+	fn spontaneous_payment_test() {
+		struct FixedEntropySource;
+		impl EntropySource for FixedEntropySource {
+			fn get_secure_random_bytes(&self) -> [u8; 32] { [42; 32] }
+		}
+
+		let private_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let secp_ctx = Secp256k1::new();
+		let public_key = PublicKey::from_secret_key(&secp_ctx, &private_key);
+		let custom_tlvs = vec![((1 << 16) + 1, vec![42; 4]), (1 << 16, vec![0; 2])];
+
+		let (hash, onion, params, preimage) = spontaneous_payment_parameters(
+			public_key, &NodeFeatures::empty(), 100_000, 1, 40, None, custom_tlvs.clone(),
+			&&FixedEntropySource
+		).unwrap();
+		assert_eq!(preimage, PaymentPreimage([42; 32]));
+		assert_eq!(hash, PaymentHash(Sha256::hash(&[42; 32]).to_byte_array()));
+		assert_eq!(onion.payment_secret, None);
+		assert_eq!(onion.custom_tlvs(), &vec![(1 << 16, vec![0; 2]), ((1 << 16) + 1, vec![42; 4])]);
+		assert_eq!(params.final_value_msat, 100_000);
+		assert_eq!(params.payment_params.max_path_count, 1);
+		match params.payment_params.payee {
+			Payee::Clear { node_id, features, final_cltv_expiry_delta, .. } => {
+				assert_eq!(node_id, public_key);
+				assert!(!features.unwrap().supports_basic_mpp());
+				assert_eq!(final_cltv_expiry_delta, 40);
+			},
+			_ => panic!(),
+		}
+
+		let (hash, _, _, preimage) = spontaneous_payment_parameters(
+			public_key, &NodeFeatures::empty(), 100_000, 1, 40, Some(PaymentPreimage([1; 32])),
+			Vec::new(), &&FixedEntropySource
+		).unwrap();
+		assert_eq!(preimage, PaymentPreimage([1; 32]));
+		assert_eq!(hash, PaymentHash(Sha256::hash(&[1; 32]).to_byte_array()));
+
+		// Custom TLVs have to be unique, in the custom range, and may not collide with keysend.
+		for bad_tlvs in [
+			vec![(42, vec![])],
+			vec![(5482373484, vec![])],
+			vec![(1 << 16, vec![]), (1 << 16, vec![1])],
+		] {
+			assert_eq!(spontaneous_payment_parameters(
+				public_key, &NodeFeatures::empty(), 100_000, 1, 40, None, bad_tlvs, &&FixedEntropySource
+			), Err(SpontaneousPaymentError::InvalidCustomTlvs));
+		}
+
+		// The payee would reject HTLCs expiring too soon.
+		let min_final_cltv_expiry_delta = MIN_FINAL_CLTV_EXPIRY_DELTA as u32;
+		assert_eq!(spontaneous_payment_parameters(
+			public_key, &NodeFeatures::empty(), 100_000, 1, min_final_cltv_expiry_delta - 1, None,
+			Vec::new(), &&FixedEntropySource
+		), Err(SpontaneousPaymentError::FinalCltvExpiryDeltaTooLow));
+		assert!(spontaneous_payment_parameters(
+			public_key, &NodeFeatures::empty(), 100_000, 1, min_final_cltv_expiry_delta, None,
+			Vec::new(), &&FixedEntropySource
+		).is_ok());
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn spontaneous_payment_end_to_end() {
+		use lightning::events::MessageSendEventsProvider;
+		use lightning::ln::functional_test_utils::*;
+		// Test that a keysend payment built via spontaneous_payment_parameters delivers its custom
+		// TLVs to the payee.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		create_announced_chan_between_nodes(&nodes, 0, 1);
+
+		let amt_msat = 10_000;
+		let custom_tlvs = vec![(5482373483, vec![1, 2, 3, 4]), (5482373487, vec![0x42; 16])];
+		let (payment_hash, recipient_onion, route_params, payment_preimage) =
+			spontaneous_payment_parameters(
+				nodes[1].node.get_our_node_id(), &NodeFeatures::empty(), amt_msat, 1, 40, None,
+				custom_tlvs.clone(), &nodes[0].keys_manager
+			).unwrap();
+
+		nodes[0].node.send_spontaneous_payment_with_retry(
+			Some(payment_preimage), recipient_onion, PaymentId(payment_hash.0), route_params,
+			Retry::Attempts(0)
+		).unwrap();
+		check_added_monitors(&nodes[0], 1);
+
+		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		let path = &[&nodes[1]];
+		let args = PassAlongPathArgs::new(&nodes[0], path, amt_msat, payment_hash, events.pop().unwrap())
+			.with_payment_preimage(payment_preimage)
+			.with_custom_tlvs(custom_tlvs.clone());
+		do_pass_along_path(args);
+		claim_payment_along_route(
+			ClaimAlongRouteArgs::new(&nodes[0], &[path], payment_preimage)
+				.with_custom_tlvs(custom_tlvs)
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn overpaying_invoice_end_to_end() {
+		use lightning::ln::functional_test_utils::*;
+		// Test that a deliberate overpayment of an invoice, split over multiple paths, is accepted
+		// by a recipient which doesn't accept underpaying HTLCs.
+		let chanmon_cfgs = create_chanmon_cfgs(4);
+		let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+		let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+		// As we may only have 10% of a channel's value in flight by default, neither path may carry
+		// more than 10_000 sats.
+		create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100_000, 0);
+		create_announced_chan_between_nodes_with_value(&nodes, 0, 2, 100_000, 0);
+		create_announced_chan_between_nodes_with_value(&nodes, 1, 3, 100_000, 0);
+		create_announced_chan_between_nodes_with_value(&nodes, 2, 3, 100_000, 0);
+		assert!(!nodes[3].node.get_current_default_configuration().channel_config.accept_underpaying_htlcs);
+
+		let invoice_amount_msat = 12_000_000;
+		let (preimage, payment_hash, payment_secret) =
+			get_payment_preimage_hash(&nodes[3], Some(invoice_amount_msat), None);
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(Sha256::from_slice(&payment_hash.0).unwrap())
+			.payment_secret(payment_secret)
+			.current_timestamp()
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(invoice_amount_msat)
+			.basic_mpp()
+			.build_signed(|hash| {
+				Secp256k1::new().sign_ecdsa_recoverable(hash,
+					&nodes[3].keys_manager.backing.get_node_secret_key())
+			})
+			.unwrap();
+
+		// Overpay by half the invoice's amount, well within the 2x recipients are required to accept.
+		let amount_msat = invoice_amount_msat * 3 / 2;
+		let (hash, onion, params) = payment_parameters_from_invoice_with_amount(
+			&invoice, amount_msat, Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		assert_eq!(params.final_value_msat, amount_msat);
+		nodes[0].node.send_payment(hash, onion, PaymentId(hash.0), params, Retry::Attempts(0)).unwrap();
+		check_added_monitors(&nodes[0], 2);
+
+		let expected_route: &[&[&Node]] = &[&[&nodes[1], &nodes[3]], &[&nodes[2], &nodes[3]]];
+		pass_along_route(&nodes[0], expected_route, amount_msat, payment_hash, payment_secret);
+		claim_payment_along_route(ClaimAlongRouteArgs::new(&nodes[0], expected_route, preimage));
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn fee_limit_route_test() {
+		use lightning::ln::functional_test_utils::*;
+		// Test that the router rejects routes whose fees exceed a proportional fee limit, failing
+		// the payment before sending anything, while a route right at the limit is used.
+		let chanmon_cfgs = create_chanmon_cfgs(3);
+		let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+		let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+		create_announced_chan_between_nodes(&nodes, 0, 1);
+		create_announced_chan_between_nodes(&nodes, 1, 2);
+
+		// Forwarding via nodes[1] costs its default base fee of 1 sat, i.e., 0.1% of the amount.
+		let forwarding_config = nodes[1].node.get_current_default_configuration().channel_config;
+		assert_eq!(forwarding_config.forwarding_fee_base_msat, 1_000);
+		assert_eq!(forwarding_config.forwarding_fee_proportional_millionths, 0);
+
+		let (preimage, payment_hash, payment_secret) = get_payment_preimage_hash(&nodes[2], None, None);
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(Sha256::from_slice(&payment_hash.0).unwrap())
+			.payment_secret(payment_secret)
+			.current_timestamp()
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(1_000_000)
+			.build_signed(|hash| {
+				Secp256k1::new().sign_ecdsa_recoverable(hash,
+					&nodes[2].keys_manager.backing.get_node_secret_key())
+			})
+			.unwrap();
+
+		let limit = |proportional_millionths| {
+			MaxTotalRoutingFeeLimit::Proportional { base_msat: 0, proportional_millionths }
+		};
+		let (hash, onion, params) = payment_parameters_from_invoice_with_fee_limit(
+			&invoice, limit(999), Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		assert_eq!(params.max_total_routing_fee_msat, Some(999));
+		assert!(get_route(&nodes[0], &params).is_err());
+		assert_eq!(nodes[0].node.send_payment(hash, onion, PaymentId(hash.0), params, Retry::Attempts(0)),
+			Err(RetryableSendFailure::RouteNotFound));
+		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+		let (hash, onion, params) = payment_parameters_from_invoice_with_fee_limit(
+			&invoice, limit(1_000), Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		let route = get_route(&nodes[0], &params).unwrap();
+		assert_eq!(route.get_total_fees(), 1_000);
+		nodes[0].node.send_payment(hash, onion, PaymentId(hash.0), params, Retry::Attempts(0)).unwrap();
+		check_added_monitors(&nodes[0], 1);
+		pass_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]], 1_000_000, payment_hash, payment_secret);
+		claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], preimage);
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn pay_and_probe_invoice_test() {
+		use lightning::events::MessageSendEventsProvider;
+		use lightning::ln::functional_test_utils::*;
+		let chanmon_cfgs = create_chanmon_cfgs(3);
+		let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+		let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+		create_announced_chan_between_nodes(&nodes, 0, 1);
+		create_announced_chan_between_nodes(&nodes, 1, 2);
+
+		let build_invoice = |payment_hash: PaymentHash, payment_secret, amount_msat: Option<u64>| {
+			let builder = InvoiceBuilder::new(Currency::Bitcoin)
+				.description("test".into())
+				.payment_hash(Sha256::from_slice(&payment_hash.0).unwrap())
+				.payment_secret(payment_secret)
+				.current_timestamp()
+				.min_final_cltv_expiry_delta(144);
+			let sign = |hash: &secp256k1::Message| Secp256k1::new().sign_ecdsa_recoverable(hash,
+				&nodes[2].keys_manager.backing.get_node_secret_key());
+			match amount_msat {
+				Some(amount_msat) => builder.amount_milli_satoshis(amount_msat).build_signed(sign),
+				None => builder.build_signed(sign),
+			}.unwrap()
+		};
+		let (preimage, payment_hash, payment_secret) = get_payment_preimage_hash(&nodes[2], None, None);
+		let invoice = build_invoice(payment_hash, payment_secret, Some(100_000));
+
+		let pay = |invoice, network, duration_since_epoch, max_fee| pay_invoice(
+			invoice, None, network, duration_since_epoch, None, Retry::Attempts(0), max_fee,
+			&nodes[0].node
+		);
+		let probe = |invoice, network, duration_since_epoch| probe_invoice(
+			invoice, None, network, duration_since_epoch, None, None, &nodes[0].node
+		);
+		let now = duration_since_epoch();
+
+		// Issues with the invoice are reported before sending anything.
+		let zero_amount_invoice = build_invoice(payment_hash, payment_secret, None);
+		assert_eq!(pay(&zero_amount_invoice, Network::Bitcoin, now, None),
+			Err(PaymentError::Invoice(Bolt11PaymentError::AmountRequired)));
+		assert_eq!(probe(&zero_amount_invoice, Network::Bitcoin, now),
+			Err(ProbingError::Invoice(Bolt11PaymentError::AmountRequired)));
+		let wrong_network_error = Bolt11PaymentError::UnsupportedCurrency {
+			expected: Currency::BitcoinTestnet, found: Currency::Bitcoin,
+		};
+		assert_eq!(pay(&invoice, Network::Testnet, now, None),
+			Err(PaymentError::Invoice(wrong_network_error.clone())));
+		assert_eq!(probe(&invoice, Network::Testnet, now),
+			Err(ProbingError::Invoice(wrong_network_error)));
+		let expired_at = invoice.expires_at().unwrap() + Duration::from_secs(1);
+		assert_eq!(pay(&invoice, Network::Bitcoin, expired_at, None),
+			Err(PaymentError::Invoice(Bolt11PaymentError::InvoiceExpired)));
+		assert_eq!(probe(&invoice, Network::Bitcoin, expired_at),
+			Err(ProbingError::Invoice(Bolt11PaymentError::InvoiceExpired)));
+
+		// Forwarding via nodes[1] costs its default base fee of 1 sat, exceeding the given limit.
+		let fee_limit = Some(MaxTotalRoutingFeeLimit::Absolute(999));
+		assert_eq!(pay(&invoice, Network::Bitcoin, now, fee_limit),
+			Err(PaymentError::Sending(RetryableSendFailure::RouteNotFound)));
+
+		// By default, the payment id is derived from the payment hash, preventing duplicate payments.
+		let payment_id = pay(&invoice, Network::Bitcoin, now, None).unwrap();
+		assert_eq!(payment_id, PaymentId(payment_hash.0));
+		check_added_monitors(&nodes[0], 1);
+		assert_eq!(pay(&invoice, Network::Bitcoin, now, None),
+			Err(PaymentError::Sending(RetryableSendFailure::DuplicatePayment)));
+		pass_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]], 100_000, payment_hash, payment_secret);
+		claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], preimage);
+
+		let probes = probe(&invoice, Network::Bitcoin, now).unwrap();
+		assert_eq!(probes.len(), 1);
+		check_added_monitors(&nodes[0], 1);
+		assert_eq!(nodes[0].node.get_and_clear_pending_msg_events().len(), 1);
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn probe_tracker_end_to_end() {
+		use lightning::events::MessageSendEventsProvider;
+		use lightning::ln::msgs::ChannelMessageHandler;
+		use lightning::ln::functional_test_utils::*;
+		// Test that a `ProbeTracker` reports how much we may send along a three-node line, where the
+		// middle hop lacks the liquidity to forward the full amount.
+		let chanmon_cfgs = create_chanmon_cfgs(3);
+		let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+		let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+		create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1_000_000, 0);
+		// nodes[1] pushes most of its balance to nodes[2], leaving it with less than 10k sats.
+		create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 100_000, 90_000_000);
+
+		let route_params = |amount_msat| RouteParameters::from_payment_params_and_value(
+			PaymentParameters::from_node_id(nodes[2].node.get_our_node_id(), 42), amount_msat
+		);
+
+		let probes = nodes[0].node.send_preflight_probes(route_params(9_998_000), Some(1)).unwrap();
+		assert_eq!(probes.len(), 1);
+		let mut tracker = ProbeTracker::new(&probes);
+		assert!(!tracker.is_complete());
+		let failed_report = ProbeReport { paths_tried: 1, paths_succeeded: 0, estimated_sendable_msat: 0 };
+		assert_eq!(tracker.report(), failed_report);
+
+		// The probe fails back from nodes[1].
+		check_added_monitors!(nodes[0], 1);
+		let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+		let probe_event = SendEvent::from_commitment_update(nodes[1].node.get_our_node_id(), updates);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &probe_event.msgs[0]);
+		check_added_monitors!(nodes[1], 0);
+		commitment_signed_dance!(nodes[1], nodes[0], probe_event.commitment_msg, false);
+		expect_pending_htlcs_forwardable!(nodes[1]);
+
+		check_added_monitors!(nodes[1], 1);
+		let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+		let _ = nodes[1].node.get_and_clear_pending_events();
+		nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+		commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		assert!(tracker.handle_event(&events[0]));
+		// Events for probes which already resolved are ignored.
+		assert!(!tracker.handle_event(&events[0]));
+		assert!(tracker.is_complete());
+		assert_eq!(tracker.report(), failed_report);
+
+		// A smaller amount may still be sent.
+		let probes = nodes[0].node.send_preflight_probes(route_params(1_000_000), Some(1)).unwrap();
+		assert_eq!(probes.len(), 1);
+		let mut tracker = ProbeTracker::new(&probes);
+		send_probe_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]]);
+
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		assert!(tracker.handle_event(&events[0]));
+		assert!(tracker.is_complete());
+		assert_eq!(tracker.report(),
+			ProbeReport { paths_tried: 1, paths_succeeded: 1, estimated_sendable_msat: 1_000_000 });
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn payment_metadata_end_to_end() {
+		use lightning::events::Event;
+		use lightning::ln::msgs::ChannelMessageHandler;
+		use lightning::ln::functional_test_utils::*;
+		// Test that a payment metadata read from an invoice passed to `pay_invoice` makes it all
+		// the way out through the `PaymentClaimable` event.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		create_announced_chan_between_nodes(&nodes, 0, 1);
+
+		let payment_metadata = vec![42, 43, 44, 45, 46, 47, 48, 49, 42];
+
+		let (payment_hash, payment_secret) =
+			nodes[1].node.create_inbound_payment(None, 7200, None).unwrap();
+
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(Sha256::from_slice(&payment_hash.0).unwrap())
+			.payment_secret(payment_secret)
+			.current_timestamp()
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(50_000)
+			.payment_metadata(payment_metadata.clone())
+			.build_signed(|hash| {
+				Secp256k1::new().sign_ecdsa_recoverable(hash,
+					&nodes[1].keys_manager.backing.get_node_secret_key())
+			})
+			.unwrap();
+
+		let payment_id = pay_invoice(
+			&invoice, None, Network::Bitcoin, duration_since_epoch(), None, Retry::Attempts(0), None,
+			&nodes[0].node
+		).unwrap();
+		assert_eq!(payment_id, PaymentId(payment_hash.0));
+		check_added_monitors(&nodes[0], 1);
+		let send_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &send_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], &send_event.commitment_msg, false);
+
+		expect_pending_htlcs_forwardable!(nodes[1]);
+
+		let mut events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events.pop().unwrap() {
+			Event::PaymentClaimable { onion_fields, .. } => {
+				assert_eq!(Some(payment_metadata), onion_fields.unwrap().payment_metadata);
+			},
+			_ => panic!("Unexpected event")
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn custom_tlvs_and_metadata_end_to_end() {
+		use lightning::events::{Event, MessageSendEventsProvider};
+		use lightning::ln::functional_test_utils::*;
+		// Test that custom TLVs sent when paying an invoice make it to the `PaymentClaimable` event
+		// alongside the invoice's payment metadata.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		create_announced_chan_between_nodes(&nodes, 0, 1);
+
+		let payment_metadata = vec![42, 43, 44, 45, 46, 47, 48, 49, 42];
+		let custom_tlvs = vec![(5482373483, vec![1, 2, 3, 4]), (5482373487, vec![0x42; 16])];
+
+		let amt_msat = 50_000;
+		let (payment_preimage, payment_hash, payment_secret) =
+			get_payment_preimage_hash(&nodes[1], Some(amt_msat), None);
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("test".into())
+			.payment_hash(Sha256::from_slice(&payment_hash.0).unwrap())
+			.payment_secret(payment_secret)
+			.current_timestamp()
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(amt_msat)
+			.payment_metadata(payment_metadata.clone())
+			.build_signed(|hash| {
+				Secp256k1::new().sign_ecdsa_recoverable(hash,
+					&nodes[1].keys_manager.backing.get_node_secret_key())
+			})
+			.unwrap();
+
+		let (hash, onion, params) = payment_parameters_from_invoice_with_custom_tlvs(
+			&invoice, custom_tlvs.clone(), Network::Bitcoin, duration_since_epoch()
+		).unwrap();
+		nodes[0].node.send_payment(hash, onion, PaymentId(hash.0), params, Retry::Attempts(0)).unwrap();
+		check_added_monitors(&nodes[0], 1);
+
+		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		let path = &[&nodes[1]];
+		let args = PassAlongPathArgs::new(&nodes[0], path, amt_msat, payment_hash, events.pop().unwrap())
+			.with_payment_secret(payment_secret)
+			.with_payment_metadata(payment_metadata.clone())
+			.with_custom_tlvs(custom_tlvs.clone())
+			.without_clearing_recipient_events();
+		do_pass_along_path(args);
+
+		let mut events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events.pop().unwrap() {
+			Event::PaymentClaimable { onion_fields, .. } => {
+				let onion_fields = onion_fields.unwrap();
+				assert_eq!(onion_fields.payment_metadata, Some(payment_metadata));
+				assert_eq!(onion_fields.custom_tlvs(), &custom_tlvs);
+			},
+			_ => panic!("Unexpected event")
+		}
+
+		claim_payment_along_route(
+			ClaimAlongRouteArgs::new(&nodes[0], &[path], payment_preimage)
+				.with_custom_tlvs(custom_tlvs)
+		);
 	}
 }
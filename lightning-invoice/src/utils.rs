@@ -8,7 +8,7 @@ use bitcoin::hashes::Hash;
 use lightning::chain;
 use lightning::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
 use lightning::sign::{Recipient, NodeSigner, SignerProvider, EntropySource};
-use lightning::ln::types::{PaymentHash, PaymentSecret};
+use lightning::ln::types::{ChannelId, PaymentHash, PaymentSecret};
 use lightning::ln::channel_state::ChannelDetails;
 use lightning::ln::channelmanager::{ChannelManager, MIN_FINAL_CLTV_EXPIRY_DELTA};
 use lightning::ln::channelmanager::{PhantomRouteHints, MIN_CLTV_EXPIRY_DELTA};
@@ -36,6 +36,9 @@ use core::iter::Iterator;
 ///   may be too long for QR code scanning. To fix this, `PhantomRouteHints::channels` may be pared
 ///   down
 ///
+/// `route_hint_selection` controls which of the participating nodes' channels are included as
+/// route hints. If `None` is provided, [`PhantomRouteHintSelection::default`] is used.
+///
 /// `payment_hash` can be specified if you have a specific need for a custom payment hash (see the difference
 /// between [`ChannelManager::create_inbound_payment`] and [`ChannelManager::create_inbound_payment_for_hash`]).
 /// If `None` is provided for `payment_hash`, then one will be created.
@@ -65,7 +68,8 @@ use core::iter::Iterator;
 /// available and the current time is supplied by the caller.
 pub fn create_phantom_invoice<ES: Deref, NS: Deref, L: Deref>(
 	amt_msat: Option<u64>, payment_hash: Option<PaymentHash>, description: String,
-	invoice_expiry_delta_secs: u32, phantom_route_hints: Vec<PhantomRouteHints>, entropy_source: ES,
+	invoice_expiry_delta_secs: u32, phantom_route_hints: Vec<PhantomRouteHints>,
+	route_hint_selection: Option<PhantomRouteHintSelection>, entropy_source: ES,
 	node_signer: NS, logger: L, network: Currency, min_final_cltv_expiry_delta: Option<u16>, duration_since_epoch: Duration,
 ) -> Result<Bolt11Invoice, SignOrCreationError<()>>
 where
@@ -77,7 +81,8 @@ where
 	let description = Bolt11InvoiceDescription::Direct(&description,);
 	_create_phantom_invoice::<ES, NS, L>(
 		amt_msat, payment_hash, description, invoice_expiry_delta_secs, phantom_route_hints,
-		entropy_source, node_signer, logger, network, min_final_cltv_expiry_delta, duration_since_epoch,
+		route_hint_selection, entropy_source, node_signer, logger, network,
+		min_final_cltv_expiry_delta, duration_since_epoch,
 	)
 }
 
@@ -96,6 +101,9 @@ where
 ///   until the maximum is hit. Callers may provide as many `PhantomRouteHints::channels` as
 ///   desired, but note that some nodes will be trimmed if more than 3 nodes are provided.
 ///
+/// `route_hint_selection` controls which of the participating nodes' channels are included as
+/// route hints. If `None` is provided, [`PhantomRouteHintSelection::default`] is used.
+///
 /// `description_hash` is a SHA-256 hash of the description text
 ///
 /// `payment_hash` can be specified if you have a specific need for a custom payment hash (see the difference
@@ -121,7 +129,8 @@ where
 /// available and the current time is supplied by the caller.
 pub fn create_phantom_invoice_with_description_hash<ES: Deref, NS: Deref, L: Deref>(
 	amt_msat: Option<u64>, payment_hash: Option<PaymentHash>, invoice_expiry_delta_secs: u32,
-	description_hash: Sha256, phantom_route_hints: Vec<PhantomRouteHints>, entropy_source: ES,
+	description_hash: Sha256, phantom_route_hints: Vec<PhantomRouteHints>,
+	route_hint_selection: Option<PhantomRouteHintSelection>, entropy_source: ES,
 	node_signer: NS, logger: L, network: Currency, min_final_cltv_expiry_delta: Option<u16>, duration_since_epoch: Duration,
 ) -> Result<Bolt11Invoice, SignOrCreationError<()>>
 where
@@ -131,16 +140,69 @@ where
 {
 	_create_phantom_invoice::<ES, NS, L>(
 		amt_msat, payment_hash, Bolt11InvoiceDescription::Hash(&description_hash),
-		invoice_expiry_delta_secs, phantom_route_hints, entropy_source, node_signer, logger, network,
-		min_final_cltv_expiry_delta, duration_since_epoch,
+		invoice_expiry_delta_secs, phantom_route_hints, route_hint_selection, entropy_source,
+		node_signer, logger, network, min_final_cltv_expiry_delta, duration_since_epoch,
 	)
 }
 
 const MAX_CHANNEL_HINTS: usize = 3;
 
+/// A policy for selecting which channels of the nodes participating in a phantom invoice are
+/// included as route hints, used by [`create_phantom_invoice`] and
+/// [`create_phantom_invoice_with_description_hash`].
+///
+/// Regardless of the policy, at most 3 hints are included in total, drawn from each node in turn
+/// so that payers can split a payment across multiple real nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhantomRouteHintSelection {
+	/// The maximum number of hints to include for any single participating node.
+	///
+	/// Default value: `3`
+	pub max_hints_per_node: usize,
+	/// If set, each node's channels are ranked by [`ChannelDetails::inbound_capacity_msat`], highest
+	/// first, and nodes are ordered by the inbound capacity of their best channel. Otherwise, for
+	/// invoices with an amount, the channels with the least inbound capacity which is still
+	/// sufficient for the amount are preferred, and nodes are ordered as provided.
+	///
+	/// Default value: `false`
+	pub prefer_highest_inbound: bool,
+	/// Non-public channels with a [`ChannelDetails::channel_value_satoshis`] below this value are
+	/// never included as route hints.
+	///
+	/// Default value: `0`
+	pub min_private_channel_value_satoshis: u64,
+	/// If set, only channels with one of the given ids are considered for route hints.
+	///
+	/// Default value: `None`
+	pub channel_allowlist: Option<Vec<ChannelId>>,
+}
+
+impl Default for PhantomRouteHintSelection {
+	fn default() -> Self {
+		PhantomRouteHintSelection {
+			max_hints_per_node: MAX_CHANNEL_HINTS,
+			prefer_highest_inbound: false,
+			min_private_channel_value_satoshis: 0,
+			channel_allowlist: None,
+		}
+	}
+}
+
+impl PhantomRouteHintSelection {
+	fn allows_channel(&self, channel: &ChannelDetails) -> bool {
+		if let Some(allowlist) = &self.channel_allowlist {
+			if !allowlist.contains(&channel.channel_id) {
+				return false;
+			}
+		}
+		channel.is_public || channel.channel_value_satoshis >= self.min_private_channel_value_satoshis
+	}
+}
+
 fn _create_phantom_invoice<ES: Deref, NS: Deref, L: Deref>(
 	amt_msat: Option<u64>, payment_hash: Option<PaymentHash>, description: Bolt11InvoiceDescription,
-	invoice_expiry_delta_secs: u32, phantom_route_hints: Vec<PhantomRouteHints>, entropy_source: ES,
+	invoice_expiry_delta_secs: u32, phantom_route_hints: Vec<PhantomRouteHints>,
+	route_hint_selection: Option<PhantomRouteHintSelection>, entropy_source: ES,
 	node_signer: NS, logger: L, network: Currency, min_final_cltv_expiry_delta: Option<u16>, duration_since_epoch: Duration,
 ) -> Result<Bolt11Invoice, SignOrCreationError<()>>
 where
@@ -209,7 +271,10 @@ where
 	}
 
 
-	for route_hint in select_phantom_hints(amt_msat, phantom_route_hints, logger).take(MAX_CHANNEL_HINTS) {
+	let route_hint_selection = route_hint_selection.unwrap_or_default();
+	for route_hint in select_phantom_hints(amt_msat, phantom_route_hints, &route_hint_selection, logger)
+		.take(MAX_CHANNEL_HINTS)
+	{
 		invoice = invoice.private_route(route_hint);
 	}
 
@@ -231,21 +296,29 @@ where
 /// See [`PhantomKeysManager`] for more information on phantom node payments.
 ///
 /// To ensure that the phantom invoice is still readable by QR code, we limit to 3 hints per invoice:
-/// * Select up to three channels per node.
+/// * Select up to `max_hints_per_node` channels per node, as allowed by the `selection` policy.
 /// * Select one hint from each node, up to three hints or until we run out of hints.
 ///
 /// [`PhantomKeysManager`]: lightning::sign::PhantomKeysManager
 fn select_phantom_hints<L: Deref>(amt_msat: Option<u64>, phantom_route_hints: Vec<PhantomRouteHints>,
-	logger: L) -> impl Iterator<Item = RouteHint>
+	selection: &PhantomRouteHintSelection, logger: L) -> impl Iterator<Item = RouteHint>
 where
 	L::Target: Logger,
 {
-	let mut phantom_hints: Vec<_> = Vec::new();
+	let mut phantom_hints: Vec<(u64, _)> = Vec::new();
 
 	for PhantomRouteHints { channels, phantom_scid, real_node_pubkey } in phantom_route_hints {
 		log_trace!(logger, "Generating phantom route hints for node {}",
 			log_pubkey!(real_node_pubkey));
-		let route_hints = sort_and_filter_channels(channels, amt_msat, &logger);
+		let channels = channels.into_iter()
+			.filter(|channel| selection.allows_channel(channel))
+			.collect::<Vec<_>>();
+		let max_inbound_capacity_msat = channels.iter()
+			.filter(|channel| channel.is_channel_ready)
+			.map(|channel| channel.inbound_capacity_msat)
+			.max().unwrap_or(0);
+		let route_hints = sort_and_filter_channels(
+			channels, amt_msat, selection.prefer_highest_inbound, &logger);
 
 		// If we have any public channel, the route hints from `sort_and_filter_channels` will be
 		// empty. In that case we create a RouteHint on which we will push a single hop with the
@@ -254,6 +327,7 @@ where
 		let empty_route_hints = route_hints.len() == 0;
 		let mut have_pushed_empty = false;
 		let route_hints = route_hints
+			.take(selection.max_hints_per_node)
 			.chain(core::iter::from_fn(move || {
 				if empty_route_hints && !have_pushed_empty {
 					// set flag of having handled the empty route_hints and ensure empty vector
@@ -279,14 +353,20 @@ where
 				hint
 			});
 
-		phantom_hints.push(route_hints);
+		phantom_hints.push((max_inbound_capacity_msat, route_hints));
+	}
+
+	if selection.prefer_highest_inbound {
+		// Start with the node with the most inbound capacity, keeping the provided order for nodes
+		// with the same capacity.
+		phantom_hints.sort_by(|(a, _), (b, _)| b.cmp(a));
 	}
 
 	// We have one vector per real node involved in creating the phantom invoice. To distribute
 	// the hints across our real nodes we add one hint from each in turn until no node has any hints
 	// left (if one node has more hints than any other, these will accumulate at the end of the
 	// vector).
-	rotate_through_iterators(phantom_hints)
+	rotate_through_iterators(phantom_hints.into_iter().map(|(_, hints)| hints).collect())
 }
 
 /// Draw items iteratively from multiple iterators.  The items are retrieved by index and
@@ -560,7 +640,7 @@ fn _create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_has
 		invoice = invoice.amount_milli_satoshis(amt);
 	}
 
-	let route_hints = sort_and_filter_channels(channels, amt_msat, &logger);
+	let route_hints = sort_and_filter_channels(channels, amt_msat, false, &logger);
 	for hint in route_hints {
 		invoice = invoice.private_route(hint);
 	}
@@ -598,9 +678,13 @@ fn _create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_has
 /// * Limited to a total of 3 channels.
 /// * Sorted by lowest inbound capacity if an online channel with the minimum amount requested exists,
 ///   otherwise sort by highest inbound capacity to give the payment the best chance of succeeding.
+///
+/// If `prefer_highest_inbound` is set, the channel with the highest inbound capacity is selected
+/// per counterparty and the result is always sorted by highest inbound capacity.
 fn sort_and_filter_channels<L: Deref>(
 	channels: Vec<ChannelDetails>,
 	min_inbound_capacity_msat: Option<u64>,
+	prefer_highest_inbound: bool,
 	logger: &L,
 ) -> impl ExactSizeIterator<Item = RouteHint>
 where
@@ -674,8 +758,9 @@ where
 				let new_now_public = channel.is_public && !entry.get().is_public;
 				// Decide whether we prefer the currently selected channel with the node to the new one,
 				// based on their inbound capacity.
-				let prefer_current = prefer_current_channel(min_inbound_capacity_msat, current_max_capacity,
-					channel.inbound_capacity_msat);
+				let prefer_current = prefer_current_channel(
+					if prefer_highest_inbound { None } else { min_inbound_capacity_msat },
+					current_max_capacity, channel.inbound_capacity_msat);
 				// If the public-ness of the channel has not changed (in which case simply defer to
 				// `new_now_public), and this channel has more desirable inbound than the incumbent,
 				// prefer to include this channel.
@@ -751,7 +836,7 @@ where
 		.collect::<Vec<ChannelDetails>>();
 
 		eligible_channels.sort_unstable_by(|a, b| {
-			if online_min_capacity_channel_exists {
+			if online_min_capacity_channel_exists && !prefer_highest_inbound {
 				a.inbound_capacity_msat.cmp(&b.inbound_capacity_msat)
 			} else {
 				b.inbound_capacity_msat.cmp(&a.inbound_capacity_msat)
@@ -834,7 +919,7 @@ mod test {
 	use lightning::routing::router::{PaymentParameters, RouteParameters};
 	use lightning::util::test_utils;
 	use lightning::util::config::UserConfig;
-	use crate::utils::{create_invoice_from_channelmanager_and_duration_since_epoch, rotate_through_iterators};
+	use crate::utils::{create_invoice_from_channelmanager_and_duration_since_epoch, rotate_through_iterators, PhantomRouteHintSelection};
 	use std::collections::HashSet;
 	use lightning::util::string::UntrustedString;
 
@@ -1335,7 +1420,7 @@ mod test {
 		let invoice =
 			crate::utils::create_phantom_invoice::<&test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestLogger>(
 				Some(payment_amt), payment_hash, "test".to_string(), non_default_invoice_expiry_secs,
-				route_hints, nodes[1].keys_manager, nodes[1].keys_manager, nodes[1].logger,
+				route_hints, None, nodes[1].keys_manager, nodes[1].keys_manager, nodes[1].logger,
 				Currency::BitcoinTestnet, None, Duration::from_secs(genesis_timestamp)
 			).unwrap();
 		let (payment_hash, payment_secret) = (PaymentHash(invoice.payment_hash().to_byte_array()), *invoice.payment_secret());
@@ -1429,7 +1514,7 @@ mod test {
 
 		let invoice = crate::utils::create_phantom_invoice::<&test_utils::TestKeysInterface,
 			&test_utils::TestKeysInterface, &test_utils::TestLogger>(Some(payment_amt), Some(payment_hash),
-				"test".to_string(), 3600, route_hints, nodes[1].keys_manager, nodes[1].keys_manager,
+				"test".to_string(), 3600, route_hints, None, nodes[1].keys_manager, nodes[1].keys_manager,
 				nodes[1].logger, Currency::BitcoinTestnet, None, Duration::from_secs(1234567)).unwrap();
 
 		let chan_0_1 = &nodes[1].node.list_usable_channels()[0];
@@ -1461,7 +1546,7 @@ mod test {
 			&test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestLogger,
 		>(
 			Some(payment_amt), None, non_default_invoice_expiry_secs, description_hash,
-			route_hints, nodes[1].keys_manager, nodes[1].keys_manager, nodes[1].logger,
+			route_hints, None, nodes[1].keys_manager, nodes[1].keys_manager, nodes[1].logger,
 			Currency::BitcoinTestnet, None, Duration::from_secs(1234567),
 		)
 		.unwrap();
@@ -1491,7 +1576,7 @@ mod test {
 		let duration_since_epoch = Duration::from_secs(1234567);
 		let invoice = crate::utils::create_phantom_invoice::<&test_utils::TestKeysInterface,
 			&test_utils::TestKeysInterface, &test_utils::TestLogger>(Some(payment_amt), payment_hash,
-				"".to_string(), non_default_invoice_expiry_secs, route_hints, nodes[1].keys_manager, nodes[1].keys_manager,
+				"".to_string(), non_default_invoice_expiry_secs, route_hints, None, nodes[1].keys_manager, nodes[1].keys_manager,
 				nodes[1].logger, Currency::BitcoinTestnet, min_final_cltv_expiry_delta, duration_since_epoch).unwrap();
 		assert_eq!(invoice.amount_pico_btc(), Some(200_000));
 		assert_eq!(invoice.min_final_cltv_expiry_delta(), (min_final_cltv_expiry_delta.unwrap() + 3) as u64);
@@ -1880,6 +1965,84 @@ mod test {
 		);
 	}
 
+	#[test]
+	fn test_multi_node_hints_with_route_hint_selection() {
+		let mut chanmon_cfgs = create_chanmon_cfgs(4);
+		let seed_1 = [42u8; 32];
+		let seed_2 = [43u8; 32];
+		let cross_node_seed = [44u8; 32];
+		chanmon_cfgs[2].keys_manager.backing = PhantomKeysManager::new(&seed_1, 43, 44, &cross_node_seed);
+		chanmon_cfgs[3].keys_manager.backing = PhantomKeysManager::new(&seed_2, 43, 44, &cross_node_seed);
+		let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+		let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+		// `nodes[2]` has little inbound capacity from `nodes[0]` but more from `nodes[1]`, while
+		// `nodes[3]` has the most inbound capacity overall, from `nodes[0]`.
+		let chan_0_2 = create_unannounced_chan_between_nodes_with_value(&nodes, 0, 2, 100_000, 0).0;
+		let chan_1_2 = create_unannounced_chan_between_nodes_with_value(&nodes, 1, 2, 500_000, 0).0;
+		let chan_0_3 = create_unannounced_chan_between_nodes_with_value(&nodes, 0, 3, 1_000_000, 0).0;
+		let chan_1_3 = create_unannounced_chan_between_nodes_with_value(&nodes, 1, 3, 200_000, 0).0;
+		let scid_0_2 = chan_0_2.short_channel_id_alias.unwrap();
+		let scid_1_2 = chan_1_2.short_channel_id_alias.unwrap();
+		let scid_0_3 = chan_0_3.short_channel_id_alias.unwrap();
+		let scid_1_3 = chan_1_3.short_channel_id_alias.unwrap();
+		let phantom_nodes = [&nodes[2], &nodes[3]];
+
+		// By default, the lowest inbound channels are listed first, with nodes in the order given.
+		assert_eq!(phantom_invoice_hint_scids(&nodes[2], &phantom_nodes, None),
+			vec![scid_0_2, scid_1_3, scid_1_2]);
+
+		// Preferring the highest inbound capacity starts with `nodes[3]`'s largest channel and
+		// still interleaves hints across both nodes.
+		let highest_inbound = PhantomRouteHintSelection {
+			prefer_highest_inbound: true, ..Default::default()
+		};
+		assert_eq!(phantom_invoice_hint_scids(&nodes[2], &phantom_nodes, Some(highest_inbound.clone())),
+			vec![scid_0_3, scid_1_2, scid_1_3]);
+
+		let one_per_node = PhantomRouteHintSelection {
+			max_hints_per_node: 1, ..highest_inbound.clone()
+		};
+		assert_eq!(phantom_invoice_hint_scids(&nodes[2], &phantom_nodes, Some(one_per_node)),
+			vec![scid_0_3, scid_1_2]);
+
+		let no_small_channels = PhantomRouteHintSelection {
+			min_private_channel_value_satoshis: 300_000, ..highest_inbound.clone()
+		};
+		assert_eq!(phantom_invoice_hint_scids(&nodes[2], &phantom_nodes, Some(no_small_channels)),
+			vec![scid_0_3, scid_1_2]);
+
+		// With an allowlist, nodes are ranked by the inbound capacity of their allowed channels.
+		let allowlisted = PhantomRouteHintSelection {
+			channel_allowlist: Some(vec![chan_0_2.channel_id, chan_1_3.channel_id]), ..highest_inbound
+		};
+		assert_eq!(phantom_invoice_hint_scids(&nodes[2], &phantom_nodes, Some(allowlisted)),
+			vec![scid_1_3, scid_0_2]);
+	}
+
+	fn phantom_invoice_hint_scids<'a, 'b: 'a, 'c: 'b>(
+		invoice_node: &Node<'a, 'b, 'c>,
+		network_multi_nodes: &[&Node<'a, 'b, 'c>],
+		route_hint_selection: Option<PhantomRouteHintSelection>,
+	) -> Vec<u64> {
+		let phantom_route_hints = network_multi_nodes.iter()
+			.map(|node| node.node.get_phantom_route_hints())
+			.collect::<Vec<PhantomRouteHints>>();
+
+		let invoice = crate::utils::create_phantom_invoice::<&test_utils::TestKeysInterface,
+			&test_utils::TestKeysInterface, &test_utils::TestLogger>(None, None, "test".to_string(),
+				3600, phantom_route_hints, route_hint_selection, invoice_node.keys_manager,
+				invoice_node.keys_manager, invoice_node.logger, Currency::BitcoinTestnet, None,
+				Duration::from_secs(1234567)).unwrap();
+
+		invoice.private_routes().iter().map(|hint| {
+			let hops = &(hint.0).0;
+			assert_eq!(hops.len(), 2);
+			hops[0].short_channel_id
+		}).collect()
+	}
+
 	fn match_multi_node_invoice_routes<'a, 'b: 'a, 'c: 'b>(
 		invoice_amt: Option<u64>,
 		invoice_node: &Node<'a, 'b, 'c>,
@@ -1896,7 +2059,7 @@ mod test {
 
 		let invoice = crate::utils::create_phantom_invoice::<&test_utils::TestKeysInterface,
 			&test_utils::TestKeysInterface, &test_utils::TestLogger>(invoice_amt, None, "test".to_string(),
-				3600, phantom_route_hints, invoice_node.keys_manager, invoice_node.keys_manager,
+				3600, phantom_route_hints, None, invoice_node.keys_manager, invoice_node.keys_manager,
 				invoice_node.logger, Currency::BitcoinTestnet, None, Duration::from_secs(1234567)).unwrap();
 
 		let invoice_hints = invoice.private_routes();
@@ -0,0 +1,514 @@
+use crate::common::{
+	restore_with_chain_source, sort_in_delivery_order, sync_with_chain_source, ChainSource,
+	ConfirmedTx, FilterQueue, LastSync, SpentOutput, SyncState,
+};
+use crate::config::SyncConfig;
+use crate::error::{ChainSourceError, TxSyncError};
+use crate::metrics::{MetricsCounters, SyncMetrics, SyncStats};
+
+use lightning::chain::WatchedOutput;
+use lightning::chain::{Confirm, Filter};
+use lightning::io;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::{log_debug, log_error, log_trace};
+
+use bitcoin::bip158::BlockFilter;
+use bitcoin::block::Header;
+use bitcoin::{Block, BlockHash, OutPoint, Script, Txid};
+
+use std::collections::{HashSet, VecDeque};
+use std::ops::Deref;
+use std::sync::Mutex;
+#[cfg(feature = "time")]
+use std::time::SystemTime;
+
+/// The number of most recently scanned blocks we keep track of. This allows us to resume scanning
+/// from the fork point after reorgs of up to this depth, while we rescan from the start height
+/// after any deeper reorg.
+const MAX_TRACKED_REORG_DEPTH: usize = 144;
+
+/// A source of [BIP 158] compact block filters and the blocks they commit to, e.g., a P2P client
+/// retrieving them from peers via [BIP 157] or an HTTP endpoint serving them.
+///
+/// Note that the [`CbfSyncClient`] trusts the source to serve the filters of the best chain. As a
+/// filter omitting a watched item would have us miss the respective confirmation, sources should
+/// check the filters they serve against the filter headers reported by multiple peers.
+///
+/// Errors returned by any of the methods abort the current sync, which then fails with the given
+/// error, e.g., [`TxSyncError::Failed`] or [`TxSyncError::Connection`].
+///
+/// [BIP 157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+/// [BIP 158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+pub trait CompactFilterSource {
+	/// Returns the hash and height of the current best block.
+	fn get_best_block(&self) -> Result<(BlockHash, u32), TxSyncError>;
+
+	/// Returns the hash of the block at the given height in the best chain, or `None` if the best
+	/// chain doesn't reach the given height.
+	fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, TxSyncError>;
+
+	/// Returns the header and height of the block with the given hash, or `None` if the block
+	/// isn't part of the best chain.
+	fn get_header(&self, block_hash: &BlockHash) -> Result<Option<(Header, u32)>, TxSyncError>;
+
+	/// Returns the basic compact block filter of the block with the given hash.
+	fn get_filter(&self, block_hash: &BlockHash) -> Result<BlockFilter, TxSyncError>;
+
+	/// Returns the full block with the given hash.
+	fn get_block(&self, block_hash: &BlockHash) -> Result<Block, TxSyncError>;
+}
+
+/// Synchronizes LDK based on [BIP 158] compact block filters retrieved from a given
+/// [`CompactFilterSource`].
+///
+/// Needs to be registered with a [`ChainMonitor`] via the [`Filter`] interface to be informed of
+/// transactions and outputs to monitor for on-chain confirmation, unconfirmation, and
+/// reconfirmation.
+///
+/// Note that registration via [`Filter`] needs to happen before any calls to
+/// [`Watch::watch_channel`] to ensure we get notified of the items to monitor.
+///
+/// On each sync, we match the scripts of the watched items against the filters of the blocks
+/// connected since the last sync and only download the blocks matching any of them. Registered
+/// transactions are matched via the script given to [`Filter::register_tx`], while registered
+/// outputs are matched via their script pubkey, which the filters cover for both the creation and
+/// the spend of an output. Items registered with an empty script can't be matched.
+///
+/// Items are scanned for starting at the height given on construction, and in blocks following
+/// the tip of the last sync if registered later on. As LDK registers transactions before
+/// broadcasting them, this covers all confirmations relevant to it. Outputs registered with the
+/// hash of the block they were confirmed in are additionally scanned for starting at that block.
+///
+/// [BIP 158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+/// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
+/// [`Watch::watch_channel`]: lightning::chain::Watch::watch_channel
+/// [`Filter`]: lightning::chain::Filter
+/// [`Filter::register_tx`]: lightning::chain::Filter::register_tx
+pub struct CbfSyncClient<S: Deref, L: Deref>
+where
+	S::Target: CompactFilterSource,
+	L::Target: Logger,
+{
+	sync_state: Mutex<SyncState>,
+	queue: Mutex<FilterQueue>,
+	source: S,
+	start_height: u32,
+	scan_state: Mutex<ScanState>,
+	metrics: MetricsCounters,
+	last_sync: Mutex<Option<LastSync>>,
+	logger: L,
+}
+
+// Our progress scanning the filters of the best chain for the watched items.
+#[derive(Clone)]
+struct ScanProgress {
+	// The most recently scanned blocks in ascending order, the last one being the tip we scanned up
+	// to.
+	scanned_blocks: VecDeque<(u32, BlockHash)>,
+	// The watched outputs we scanned for in all blocks up to the tip we scanned up to.
+	covered_outputs: HashSet<OutPoint>,
+}
+
+struct ScanState {
+	// The progress as of the last successful sync.
+	committed: ScanProgress,
+	// The progress of the latest scan of the current sync. It is only committed once the sync
+	// succeeded, as the scan's results are discarded if the sync is restarted or aborted.
+	pending: Option<ScanProgress>,
+}
+
+impl<S: Deref, L: Deref> CbfSyncClient<S, L>
+where
+	S::Target: CompactFilterSource,
+	L::Target: Logger,
+{
+	/// Returns a new [`CbfSyncClient`] object retrieving filters and blocks from the given source.
+	///
+	/// Any items watched when syncing for the first time are scanned for in all blocks starting at
+	/// `start_height`. This should therefore be set to the height of the best block the
+	/// confirmables to be synced were last synced to, or to the current chain tip if they were
+	/// never synced before.
+	pub fn new(source: S, start_height: u32, logger: L) -> Self {
+		let sync_state = Mutex::new(SyncState::new());
+		let queue = Mutex::new(FilterQueue::new());
+		let committed =
+			ScanProgress { scanned_blocks: VecDeque::new(), covered_outputs: HashSet::new() };
+		let scan_state = Mutex::new(ScanState { committed, pending: None });
+		let metrics = MetricsCounters::new();
+		let last_sync = Mutex::new(None);
+
+		Self { sync_state, queue, source, start_height, scan_state, metrics, last_sync, logger }
+	}
+
+	/// Synchronizes the given `confirmables` via their [`Confirm`] interface implementations. This
+	/// method should be called regularly to keep LDK up-to-date with current chain data.
+	///
+	/// For example, instances of [`ChannelManager`] and [`ChainMonitor`] can be informed about the
+	/// newest on-chain activity related to the items previously registered via the [`Filter`]
+	/// interface.
+	///
+	/// [`Confirm`]: lightning::chain::Confirm
+	/// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
+	/// [`ChannelManager`]: lightning::ln::channelmanager::ChannelManager
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn sync<C: Deref>(&self, confirmables: Vec<C>) -> Result<(), TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		self.sync_with_stats(confirmables).map(|_| ())
+	}
+
+	/// Synchronizes the given `confirmables` just like [`CbfSyncClient::sync`], but returns
+	/// [`SyncStats`] describing the sync on success.
+	pub fn sync_with_stats<C: Deref>(&self, confirmables: Vec<C>) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		// This lock makes sure we're syncing once at a time.
+		let mut sync_state = self.sync_state.lock().unwrap();
+
+		let res = sync_with_chain_source(
+			self,
+			&confirmables,
+			&mut sync_state,
+			&self.queue,
+			&self.metrics,
+			&self.logger,
+			SyncConfig::default().max_sync_restarts,
+			SyncConfig::default().min_confirmations,
+		);
+
+		// Only if the sync succeeded, the results of its latest scan were handed to the
+		// confirmables, so that we can skip the scanned blocks going forward.
+		let mut scan_state = self.scan_state.lock().unwrap();
+		let pending = scan_state.pending.take();
+		let stats = res?;
+		if let Some(progress) = pending {
+			scan_state.committed = progress;
+		}
+
+		*self.last_sync.lock().unwrap() = Some(LastSync::now(stats.tip_hash, stats.tip_height));
+		Ok(stats)
+	}
+
+	// Drops any scanned blocks that are no longer part of the best chain, e.g., due to a reorg, so
+	// that we resume scanning from the fork point.
+	fn rewind_to_best_chain(&self, progress: &mut ScanProgress) -> Result<(), ChainSourceError> {
+		while let Some((height, block_hash)) = progress.scanned_blocks.back().copied() {
+			if self.get_block_hash(height)? == Some(block_hash) {
+				return Ok(());
+			}
+			log_trace!(self.logger, "Scanned block {} was reorged out.", block_hash);
+			progress.scanned_blocks.pop_back();
+		}
+
+		if !progress.covered_outputs.is_empty() {
+			log_debug!(
+				self.logger,
+				"Reorg exceeded the tracked depth, rescanning from height {}.",
+				self.start_height
+			);
+			progress.covered_outputs.clear();
+		}
+		Ok(())
+	}
+
+	// Returns whether the filter of the given block matches any of the given scripts.
+	fn filter_matches_any<'a, I: Iterator<Item = &'a Script>>(
+		&self, block_hash: &BlockHash, scripts: I,
+	) -> Result<bool, ChainSourceError> {
+		self.metrics.record_request();
+		let filter = self.source.get_filter(block_hash)?;
+		filter.match_any(block_hash, scripts.map(|script| script.as_bytes())).map_err(|e| {
+			log_error!(self.logger, "Failed to decode filter of block {}: {}", block_hash, e);
+			ChainSourceError::ServerMisbehaving(format!(
+				"Failed to decode filter of block {}",
+				block_hash
+			))
+		})
+	}
+
+	fn get_block(&self, block_hash: &BlockHash) -> Result<Block, ChainSourceError> {
+		self.metrics.record_request();
+		let block = self.source.get_block(block_hash)?;
+		if block.block_hash() != *block_hash || !block.check_merkle_root() {
+			log_error!(self.logger, "Retrieved block {} doesn't match expectations. This should not happen. Please verify server integrity.", block_hash);
+			return Err(ChainSourceError::ServerMisbehaving(format!(
+				"Block {} doesn't match expectations",
+				block_hash
+			)));
+		}
+		Ok(block)
+	}
+
+	fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, ChainSourceError> {
+		self.metrics.record_request();
+		Ok(self.source.get_block_hash(height)?)
+	}
+
+	/// Stops monitoring the transaction with the given `txid`, e.g., once the channel it belongs
+	/// to has been fully resolved.
+	///
+	/// The transaction will be dropped from the set of watched items on the next call to
+	/// [`sync`], i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_tx(&self, txid: &Txid) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_tx(txid);
+	}
+
+	/// Registers the given output to be watched for spends just like [`Filter::register_output`],
+	/// associating it with the given opaque `tag`.
+	///
+	/// The tag is purely local and returned alongside the output once a spend of it is reported via
+	/// [`spent_outputs`], which allows to correlate it with application state.
+	///
+	/// [`Filter::register_output`]: lightning::chain::Filter::register_output
+	/// [`spent_outputs`]: Self::spent_outputs
+	pub fn register_output_with_tag(&self, output: WatchedOutput, tag: u64) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output_with_tag(output, tag);
+	}
+
+	/// Stops monitoring the given output for spends, e.g., once the channel it belongs to has
+	/// been fully resolved.
+	///
+	/// The output will be dropped from the set of watched items on the next call to [`sync`],
+	/// i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_output(&self, outpoint: &OutPoint) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_output(outpoint);
+	}
+
+	/// Returns the spends of watched outputs detected since the last call to this method, along
+	/// with the tag the respective output was registered with via [`register_output_with_tag`],
+	/// if any.
+	///
+	/// Note that a spend may be reported again if the spending transaction is reorged out and
+	/// confirmed again.
+	///
+	/// [`register_output_with_tag`]: Self::register_output_with_tag
+	pub fn spent_outputs(&self) -> Vec<SpentOutput> {
+		let mut sync_state = self.sync_state.lock().unwrap();
+		core::mem::take(&mut sync_state.spent_outputs)
+	}
+
+	/// Persists the current sync state, i.e., the watched transactions and outputs along with the
+	/// chain tip we last synced to, to the given [`KVStore`].
+	///
+	/// Restoring it via [`restore_sync_state`] after a restart allows us to avoid re-querying the
+	/// status of items we already watched when they are registered again via [`Filter`]. This
+	/// should therefore be called after each successful [`sync`].
+	///
+	/// Note that our progress scanning filters isn't persisted, i.e., after a restart, all watched
+	/// items are scanned for starting at the height given on construction.
+	///
+	/// [`restore_sync_state`]: Self::restore_sync_state
+	/// [`sync`]: Self::sync
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn persist_sync_state<K: Deref>(&self, kv_store: K) -> Result<(), io::Error>
+	where
+		K::Target: KVStore,
+	{
+		let sync_state = self.sync_state.lock().unwrap();
+		sync_state.write_to(&*kv_store)
+	}
+
+	/// Restores the sync state previously persisted via [`persist_sync_state`] from the given
+	/// [`KVStore`], returning whether it was restored.
+	///
+	/// The persisted state is only restored if the chain tip it was synced to is still part of the
+	/// best chain. Otherwise, e.g., if a reorg happened while we were offline, it is discarded and
+	/// the next [`sync`] will do a full resync.
+	///
+	/// Needs to be called before the first call to [`sync`] as it replaces the current state.
+	///
+	/// [`persist_sync_state`]: Self::persist_sync_state
+	/// [`sync`]: Self::sync
+	pub fn restore_sync_state<K: Deref>(&self, kv_store: K) -> Result<bool, TxSyncError>
+	where
+		K::Target: KVStore,
+	{
+		let mut sync_state = self.sync_state.lock().unwrap();
+		restore_with_chain_source(self, &*kv_store, &mut sync_state, &self.logger)
+	}
+
+	/// Returns the metrics accumulated across all syncs so far.
+	pub fn metrics(&self) -> SyncMetrics {
+		self.metrics.snapshot()
+	}
+
+	/// Returns the hash and height of the chain tip the last successful [`sync`] synced to.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	pub fn last_synced_tip(&self) -> Option<(BlockHash, u32)> {
+		self.last_sync.lock().unwrap().map(|last_sync| (last_sync.tip_hash, last_sync.tip_height))
+	}
+
+	/// Returns the time at which the last successful [`sync`] completed.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	#[cfg(feature = "time")]
+	pub fn last_sync_completed_at(&self) -> Option<SystemTime> {
+		self.last_sync.lock().unwrap().map(|last_sync| last_sync.completed_at)
+	}
+
+	/// Returns a reference to the underlying filter source.
+	pub fn source(&self) -> &S {
+		&self.source
+	}
+}
+
+impl<S: Deref, L: Deref> ChainSource for CbfSyncClient<S, L>
+where
+	S::Target: CompactFilterSource,
+	L::Target: Logger,
+{
+	fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+		self.metrics.record_request();
+		Ok(self.source.get_best_block()?.0)
+	}
+
+	fn get_header(&self, block_hash: &BlockHash) -> Result<(Header, u32), ChainSourceError> {
+		self.metrics.record_request();
+		// If the block isn't known, it got reorged out in the meantime.
+		self.source.get_header(block_hash)?.ok_or(ChainSourceError::Inconsistency)
+	}
+
+	fn is_block_in_best_chain(
+		&self, block_hash: &BlockHash, block_height: u32,
+	) -> Result<bool, ChainSourceError> {
+		Ok(self.get_block_hash(block_height)? == Some(*block_hash))
+	}
+
+	fn get_confirmed_transactions(
+		&self, sync_state: &SyncState,
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+		let mut scan_state = self.scan_state.lock().unwrap();
+		let mut progress = scan_state.committed.clone();
+		self.rewind_to_best_chain(&mut progress)?;
+
+		self.metrics.record_request();
+		let (tip_hash, tip_height) = self.source.get_best_block()?;
+
+		// Determine the height from which on we need to scan for each watched item. Items we
+		// already scanned for, as well as any items registered since, are scanned for in the
+		// blocks following the tip we scanned up to. However, outputs we learn the confirmation
+		// block of are also scanned for in any earlier blocks following their confirmation.
+		let scanned_height = progress.scanned_blocks.back().map(|(height, _)| *height);
+		let default_start_height = scanned_height.map_or(self.start_height, |height| height + 1);
+		let mut scripts = Vec::new();
+		for txid in &sync_state.watched_transactions {
+			match sync_state.watched_scripts.get(txid) {
+				Some(script) if !script.is_empty() => {
+					scripts.push((default_start_height, script.as_script()))
+				},
+				_ => log_error!(
+					self.logger,
+					"Can't match transaction {} against block filters as its script is unknown.",
+					txid
+				),
+			}
+		}
+		for (outpoint, output) in &sync_state.watched_outputs {
+			let mut start_height = default_start_height;
+			if !progress.covered_outputs.contains(outpoint) {
+				if let Some(block_hash) = output.block_hash {
+					self.metrics.record_request();
+					if let Some((_, conf_height)) = self.source.get_header(&block_hash)? {
+						start_height = start_height.min(conf_height);
+					}
+				}
+			}
+			scripts.push((start_height, output.script_pubkey.as_script()));
+		}
+
+		let scan_start_height = scripts.iter().map(|(start_height, _)| *start_height).min();
+		let mut scanned_blocks = Vec::new();
+		let mut confirmed_txs: Vec<ConfirmedTx> = Vec::new();
+		for height in scan_start_height.unwrap_or(tip_height + 1)..=tip_height {
+			let block_hash = match self.get_block_hash(height)? {
+				Some(block_hash) => block_hash,
+				None => {
+					// The chain got shorter in the meantime.
+					log_trace!(self.logger, "Inconsistency: Block at height {} vanished.", height);
+					return Err(ChainSourceError::Inconsistency);
+				},
+			};
+			scanned_blocks.push((height, block_hash));
+
+			let relevant_scripts = scripts
+				.iter()
+				.filter(|(start_height, _)| *start_height <= height)
+				.map(|(_, script)| *script);
+			if !self.filter_matches_any(&block_hash, relevant_scripts)? {
+				continue;
+			}
+
+			// Filters may match spuriously, so we check for the watched items in the block.
+			let block = self.get_block(&block_hash)?;
+			for (pos, tx) in block.txdata.iter().enumerate() {
+				let txid = tx.txid();
+				let is_watched_tx = sync_state.watched_transactions.contains(&txid);
+				let spends_watched_output = tx
+					.input
+					.iter()
+					.any(|txin| sync_state.watched_outputs.contains_key(&txin.previous_output));
+				if is_watched_tx || spends_watched_output {
+					confirmed_txs.push(ConfirmedTx {
+						tx: tx.clone(),
+						txid,
+						block_header: block.header,
+						block_height: height,
+						pos,
+					});
+				}
+			}
+		}
+
+		// Record our progress, which is committed once the sync succeeded.
+		if scanned_blocks.is_empty() {
+			// Nothing is watched, so there was nothing to scan for.
+			scanned_blocks.push((tip_height, tip_hash));
+		}
+		let first_scanned_height = scanned_blocks[0].0;
+		progress.scanned_blocks.retain(|(height, _)| *height < first_scanned_height);
+		progress.scanned_blocks.extend(scanned_blocks);
+		while progress.scanned_blocks.len() > MAX_TRACKED_REORG_DEPTH {
+			progress.scanned_blocks.pop_front();
+		}
+		progress.covered_outputs = sync_state.watched_outputs.keys().copied().collect();
+		scan_state.pending = Some(progress);
+
+		sort_in_delivery_order(&mut confirmed_txs, SyncConfig::default().delivery_order, |ctx| {
+			(ctx.block_height, ctx.pos)
+		});
+
+		Ok(confirmed_txs)
+	}
+}
+
+impl<S: Deref, L: Deref> Filter for CbfSyncClient<S, L>
+where
+	S::Target: CompactFilterSource,
+	L::Target: Logger,
+{
+	fn register_tx(&self, txid: &Txid, script_pubkey: &Script) {
+		// We always need to keep the script around, as we can only match it against filters.
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_tx(*txid, Some(script_pubkey.to_owned()));
+	}
+
+	fn register_output(&self, output: WatchedOutput) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output(output);
+	}
+}
@@ -0,0 +1,351 @@
+use crate::common::{
+	restore_with_chain_source, sync_with_chain_source, ChainSource, FilterQueue, LastSync,
+	SpentOutput, SyncState,
+};
+use crate::config::SyncConfig;
+use crate::error::TxSyncError;
+use crate::metrics::{MetricsCounters, SyncMetrics, SyncStats};
+
+use lightning::chain::WatchedOutput;
+use lightning::chain::{Confirm, Filter};
+use lightning::io;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+
+use bitcoin::{BlockHash, OutPoint, Script, Txid};
+
+use std::ops::Deref;
+use std::sync::Mutex;
+#[cfg(feature = "time")]
+use std::time::SystemTime;
+
+/// Synchronizes LDK with a custom chain backend implementing [`ChainSource`], e.g., a local
+/// indexer.
+///
+/// Needs to be registered with a [`ChainMonitor`] via the [`Filter`] interface to be informed of
+/// transactions and outputs to monitor for on-chain confirmation, unconfirmation, and
+/// reconfirmation.
+///
+/// Note that registration via [`Filter`] needs to happen before any calls to
+/// [`Watch::watch_channel`] to ensure we get notified of the items to monitor.
+///
+/// The source is only queried for the chain data described by [`ChainSource`], while any reorg
+/// handling is done by the same sync loop the other sync clients use.
+///
+/// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
+/// [`Watch::watch_channel`]: lightning::chain::Watch::watch_channel
+/// [`Filter`]: lightning::chain::Filter
+pub struct ChainSourceSyncClient<S: Deref, L: Deref>
+where
+	S::Target: ChainSource,
+	L::Target: Logger,
+{
+	sync_state: Mutex<SyncState>,
+	queue: Mutex<FilterQueue>,
+	source: S,
+	config: SyncConfig,
+	metrics: MetricsCounters,
+	last_sync: Mutex<Option<LastSync>>,
+	logger: L,
+}
+
+impl<S: Deref, L: Deref> ChainSourceSyncClient<S, L>
+where
+	S::Target: ChainSource,
+	L::Target: Logger,
+{
+	/// Returns a new [`ChainSourceSyncClient`] object syncing from the given source, using the
+	/// default [`SyncConfig`].
+	pub fn new(source: S, logger: L) -> Self {
+		Self::new_with_config(source, SyncConfig::default(), logger)
+	}
+
+	/// Returns a new [`ChainSourceSyncClient`] object syncing from the given source, using the
+	/// given [`SyncConfig`].
+	///
+	/// Only [`SyncConfig::max_sync_restarts`] and [`SyncConfig::min_confirmations`] apply, as the
+	/// remaining options are up to the source.
+	pub fn new_with_config(source: S, config: SyncConfig, logger: L) -> Self {
+		let sync_state = Mutex::new(SyncState::new());
+		let queue = Mutex::new(FilterQueue::new());
+		let metrics = MetricsCounters::new();
+		let last_sync = Mutex::new(None);
+
+		Self { sync_state, queue, source, config, metrics, last_sync, logger }
+	}
+
+	/// Synchronizes the given `confirmables` via their [`Confirm`] interface implementations. This
+	/// method should be called regularly to keep LDK up-to-date with current chain data.
+	///
+	/// For example, instances of [`ChannelManager`] and [`ChainMonitor`] can be informed about the
+	/// newest on-chain activity related to the items previously registered via the [`Filter`]
+	/// interface.
+	///
+	/// [`Confirm`]: lightning::chain::Confirm
+	/// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
+	/// [`ChannelManager`]: lightning::ln::channelmanager::ChannelManager
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn sync<C: Deref>(&self, confirmables: Vec<C>) -> Result<(), TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		self.sync_with_stats(confirmables).map(|_| ())
+	}
+
+	/// Synchronizes the given `confirmables` just like [`ChainSourceSyncClient::sync`], but
+	/// returns [`SyncStats`] describing the sync on success.
+	pub fn sync_with_stats<C: Deref>(&self, confirmables: Vec<C>) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		// This lock makes sure we're syncing once at a time.
+		let mut sync_state = self.sync_state.lock().unwrap();
+
+		let stats = sync_with_chain_source(
+			&*self.source,
+			&confirmables,
+			&mut sync_state,
+			&self.queue,
+			&self.metrics,
+			&self.logger,
+			self.config.max_sync_restarts,
+			self.config.min_confirmations,
+		)?;
+
+		*self.last_sync.lock().unwrap() = Some(LastSync::now(stats.tip_hash, stats.tip_height));
+		Ok(stats)
+	}
+
+	/// Stops monitoring the transaction with the given `txid`, e.g., once the channel it belongs
+	/// to has been fully resolved.
+	///
+	/// The transaction will be dropped from the set of watched items on the next call to
+	/// [`sync`], i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_tx(&self, txid: &Txid) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_tx(txid);
+	}
+
+	/// Registers the given output to be watched for spends just like [`Filter::register_output`],
+	/// associating it with the given opaque `tag`.
+	///
+	/// The tag is purely local and returned alongside the output once a spend of it is reported via
+	/// [`spent_outputs`], which allows to correlate it with application state.
+	///
+	/// [`Filter::register_output`]: lightning::chain::Filter::register_output
+	/// [`spent_outputs`]: Self::spent_outputs
+	pub fn register_output_with_tag(&self, output: WatchedOutput, tag: u64) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output_with_tag(output, tag);
+	}
+
+	/// Stops monitoring the given output for spends, e.g., once the channel it belongs to has
+	/// been fully resolved.
+	///
+	/// The output will be dropped from the set of watched items on the next call to [`sync`],
+	/// i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_output(&self, outpoint: &OutPoint) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_output(outpoint);
+	}
+
+	/// Returns the spends of watched outputs detected since the last call to this method, along
+	/// with the tag the respective output was registered with via [`register_output_with_tag`],
+	/// if any.
+	///
+	/// Note that a spend may be reported again if the spending transaction is reorged out and
+	/// confirmed again.
+	///
+	/// [`register_output_with_tag`]: Self::register_output_with_tag
+	pub fn spent_outputs(&self) -> Vec<SpentOutput> {
+		let mut sync_state = self.sync_state.lock().unwrap();
+		core::mem::take(&mut sync_state.spent_outputs)
+	}
+
+	/// Persists the current sync state, i.e., the watched transactions and outputs along with the
+	/// chain tip we last synced to, to the given [`KVStore`].
+	///
+	/// Restoring it via [`restore_sync_state`] after a restart allows us to avoid re-querying the
+	/// status of items we already watched when they are registered again via [`Filter`]. This
+	/// should therefore be called after each successful [`sync`].
+	///
+	/// [`restore_sync_state`]: Self::restore_sync_state
+	/// [`sync`]: Self::sync
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn persist_sync_state<K: Deref>(&self, kv_store: K) -> Result<(), io::Error>
+	where
+		K::Target: KVStore,
+	{
+		let sync_state = self.sync_state.lock().unwrap();
+		sync_state.write_to(&*kv_store)
+	}
+
+	/// Restores the sync state previously persisted via [`persist_sync_state`] from the given
+	/// [`KVStore`], returning whether it was restored.
+	///
+	/// The persisted state is only restored if the chain tip it was synced to is still part of the
+	/// best chain. Otherwise, e.g., if a reorg happened while we were offline, it is discarded and
+	/// the next [`sync`] will do a full resync.
+	///
+	/// Needs to be called before the first call to [`sync`] as it replaces the current state.
+	///
+	/// [`persist_sync_state`]: Self::persist_sync_state
+	/// [`sync`]: Self::sync
+	pub fn restore_sync_state<K: Deref>(&self, kv_store: K) -> Result<bool, TxSyncError>
+	where
+		K::Target: KVStore,
+	{
+		let mut sync_state = self.sync_state.lock().unwrap();
+		restore_with_chain_source(&*self.source, &*kv_store, &mut sync_state, &self.logger)
+	}
+
+	/// Returns the metrics accumulated across all syncs so far.
+	///
+	/// As we don't see the requests the source issues, no requests are counted.
+	pub fn metrics(&self) -> SyncMetrics {
+		self.metrics.snapshot()
+	}
+
+	/// Returns the hash and height of the chain tip the last successful [`sync`] synced to.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	pub fn last_synced_tip(&self) -> Option<(BlockHash, u32)> {
+		self.last_sync.lock().unwrap().map(|last_sync| (last_sync.tip_hash, last_sync.tip_height))
+	}
+
+	/// Returns the time at which the last successful [`sync`] completed.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	#[cfg(feature = "time")]
+	pub fn last_sync_completed_at(&self) -> Option<SystemTime> {
+		self.last_sync.lock().unwrap().map(|last_sync| last_sync.completed_at)
+	}
+
+	/// Returns a reference to the underlying chain source.
+	pub fn source(&self) -> &S {
+		&self.source
+	}
+}
+
+impl<S: Deref, L: Deref> Filter for ChainSourceSyncClient<S, L>
+where
+	S::Target: ChainSource,
+	L::Target: Logger,
+{
+	fn register_tx(&self, txid: &Txid, script_pubkey: &Script) {
+		// We keep the script around, so that the source may look up the transaction by it.
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_tx(*txid, Some(script_pubkey.to_owned()));
+	}
+
+	fn register_output(&self, output: WatchedOutput) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output(output);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::common::ConfirmedTx;
+	use crate::error::ChainSourceError;
+
+	use bitcoin::block::Header;
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::Network;
+	use lightning::chain::transaction::TransactionData;
+	use lightning::util::test_utils::TestLogger;
+
+	const TIP_HEIGHT: u32 = 100;
+
+	// A custom chain source reporting the genesis coinbase as confirmed at the tip, if watched.
+	struct TestChainSource;
+
+	impl ChainSource for TestChainSource {
+		fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+			Ok(genesis_block(Network::Regtest).block_hash())
+		}
+
+		fn get_header(&self, _block_hash: &BlockHash) -> Result<(Header, u32), ChainSourceError> {
+			Ok((genesis_block(Network::Regtest).header, TIP_HEIGHT))
+		}
+
+		fn is_block_in_best_chain(
+			&self, _block_hash: &BlockHash, _block_height: u32,
+		) -> Result<bool, ChainSourceError> {
+			Ok(true)
+		}
+
+		fn get_confirmed_transactions(
+			&self, sync_state: &SyncState,
+		) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+			let block = genesis_block(Network::Regtest);
+			let tx = block.txdata[0].clone();
+			let txid = tx.txid();
+			if !sync_state.watched_transactions().contains(&txid) {
+				return Ok(Vec::new());
+			}
+			assert_eq!(
+				sync_state.watched_script(&txid),
+				Some(tx.output[0].script_pubkey.as_script())
+			);
+			let block_header = block.header;
+			Ok(vec![ConfirmedTx { tx, txid, block_header, block_height: TIP_HEIGHT, pos: 0 }])
+		}
+	}
+
+	#[derive(Default)]
+	struct TestConfirmable {
+		confirmed_txs: Mutex<Vec<(Txid, u32)>>,
+		best_height: Mutex<u32>,
+	}
+
+	impl Confirm for TestConfirmable {
+		fn transactions_confirmed(&self, _header: &Header, txdata: &TransactionData, height: u32) {
+			let mut confirmed_txs = self.confirmed_txs.lock().unwrap();
+			confirmed_txs.extend(txdata.iter().map(|(_, tx)| (tx.txid(), height)));
+		}
+
+		fn transaction_unconfirmed(&self, _txid: &Txid) {}
+
+		fn best_block_updated(&self, _header: &Header, height: u32) {
+			*self.best_height.lock().unwrap() = height;
+		}
+
+		fn get_relevant_txids(&self) -> Vec<(Txid, u32, Option<BlockHash>)> {
+			Vec::new()
+		}
+	}
+
+	#[test]
+	fn syncs_from_custom_chain_source() {
+		let logger = TestLogger::new();
+		let tx_sync = ChainSourceSyncClient::new(&TestChainSource, &logger);
+		let confirmable = TestConfirmable::default();
+		assert!(tx_sync.last_synced_tip().is_none());
+
+		let tx = genesis_block(Network::Regtest).txdata[0].clone();
+		tx_sync.register_tx(&tx.txid(), &tx.output[0].script_pubkey);
+		let stats = tx_sync.sync_with_stats(vec![&confirmable]).unwrap();
+		assert_eq!(stats.num_confirmed, 1);
+		assert_eq!(*confirmable.confirmed_txs.lock().unwrap(), vec![(tx.txid(), TIP_HEIGHT)]);
+		assert_eq!(*confirmable.best_height.lock().unwrap(), TIP_HEIGHT);
+
+		let tip_hash = genesis_block(Network::Regtest).block_hash();
+		assert_eq!(tx_sync.last_synced_tip(), Some((tip_hash, TIP_HEIGHT)));
+
+		// Once confirmed, the transaction isn't looked up and delivered again.
+		let stats = tx_sync.sync_with_stats(vec![&confirmable]).unwrap();
+		assert_eq!(stats.num_confirmed, 0);
+		assert_eq!(confirmable.confirmed_txs.lock().unwrap().len(), 1);
+	}
+}
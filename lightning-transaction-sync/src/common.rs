@@ -1,39 +1,562 @@
+use crate::config::DeliveryOrder;
+#[cfg(all(feature = "time", any(feature = "esplora-blocking", feature = "esplora-async")))]
+use crate::config::RateLimit;
+use crate::error::{ChainSourceError, TxSyncError};
+use crate::metrics::{MetricsCounters, SyncStats};
+
 use bitcoin::block::Header;
-use bitcoin::{BlockHash, OutPoint, Transaction, Txid};
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use bitcoin::blockdata::constants::genesis_block;
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use bitcoin::hash_types::TxMerkleNode;
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use bitcoin::hashes::sha256d::Hash as Sha256d;
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use bitcoin::hashes::Hash;
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use bitcoin::merkle_tree::PartialMerkleTree;
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use bitcoin::Network;
+use bitcoin::{BlockHash, OutPoint, Script, ScriptBuf, Transaction, Txid};
 use lightning::chain::channelmonitor::ANTI_REORG_DELAY;
 use lightning::chain::{Confirm, WatchedOutput};
+use lightning::impl_writeable_tlv_based;
+use lightning::io;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
 
 use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
+use std::sync::Mutex;
+
+/// The primary namespace under which the transaction sync state will be persisted.
+pub const SYNC_STATE_PERSISTENCE_PRIMARY_NAMESPACE: &str = "transaction_sync";
+/// The secondary namespace under which the transaction sync state will be persisted.
+pub const SYNC_STATE_PERSISTENCE_SECONDARY_NAMESPACE: &str = "";
+/// The key under which the transaction sync state will be persisted.
+pub const SYNC_STATE_PERSISTENCE_KEY: &str = "sync_state";
+
+/// A blocking chain backend the [`Confirm`] interface can be synced from.
+///
+/// The sync loop, including all reorg handling, is shared between all sync clients and runs over
+/// this trait, so custom backends, e.g., a local indexer, can be synced from by implementing it
+/// and handing it to a [`ChainSourceSyncClient`].
+///
+/// Any method may return [`ChainSourceError::Inconsistency`] if the backend noticed the chain to
+/// change in a way that requires the current sync to be restarted. Any other error aborts the
+/// current sync.
+///
+/// [`Confirm`]: lightning::chain::Confirm
+/// [`ChainSourceSyncClient`]: crate::ChainSourceSyncClient
+pub trait ChainSource {
+	/// Returns the hash of the current best block.
+	fn get_tip(&self) -> Result<BlockHash, ChainSourceError>;
+
+	/// Returns the header and height of the block with the given hash.
+	///
+	/// Should fail with [`ChainSourceError::Inconsistency`] if the block isn't part of the best
+	/// chain.
+	fn get_header(&self, block_hash: &BlockHash) -> Result<(Header, u32), ChainSourceError>;
+
+	/// Returns whether the block with the given hash and height is still part of the best chain.
+	fn is_block_in_best_chain(
+		&self, block_hash: &BlockHash, block_height: u32,
+	) -> Result<bool, ChainSourceError>;
+
+	/// Returns the watched transactions and any transactions spending watched outputs that are
+	/// confirmed in the best chain, as given by the [`SyncState`].
+	///
+	/// The returned transactions are handed to the [`Confirm`] interface in the returned order,
+	/// which should thus be by block height and in-block position, see
+	/// [`expected_delivery_order`].
+	///
+	/// [`Confirm`]: lightning::chain::Confirm
+	fn get_confirmed_transactions(
+		&self, sync_state: &SyncState,
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError>;
+
+	/// Returns any previously-seen unconfirmed transactions spending watched outputs that were
+	/// evicted or replaced in the mempool since the last sync.
+	///
+	/// Backends not tracking the mempool may rely on the default implementation, which never
+	/// reports any replacements.
+	fn get_replaced_transactions(
+		&self, _sync_state: &mut SyncState,
+	) -> Result<Vec<Txid>, ChainSourceError> {
+		Ok(Vec::new())
+	}
+
+	/// Called whenever the current sync is restarted, e.g., to discard any chain data cached
+	/// during the current sync pass, as it may be stale by now.
+	fn on_sync_restart(&self) {}
+}
+
+// Aborts the current sync after a (semi-)permanent failure, making sure we retry later.
+macro_rules! abort_sync {
+	($logger: expr, $sync_state: expr, $num_confirmed: expr, $num_unconfirmed: expr, $err: expr) => {{
+		lightning::log_error!(
+			$logger,
+			"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
+			$num_confirmed,
+			$num_unconfirmed
+		);
+		$sync_state.pending_sync = true;
+		return Err($crate::error::TxSyncError::from($err));
+	}};
+}
+
+// Immediately restarts the current sync after encountering an inconsistency, unless we already
+// restarted `$max_restarts` times, in which case we give up. As we leave `pending_sync` set, the
+// next sync will then resume from scratch.
+macro_rules! restart_sync {
+	($source: expr, $logger: expr, $metrics: expr, $sync_state: expr, $num_restarts: expr, $max_restarts: expr) => {{
+		$source.on_sync_restart();
+		$metrics.record_reorg();
+		$sync_state.pending_sync = true;
+		if $num_restarts >= $max_restarts as usize {
+			lightning::log_error!(
+				$logger,
+				"Transaction sync was restarted {} times as the chain kept changing, giving up.",
+				$num_restarts
+			);
+			return Err($crate::error::TxSyncError::MaxRestartsExceeded);
+		}
+		$num_restarts += 1;
+		lightning::log_debug!(
+			$logger,
+			"Encountered inconsistency during transaction sync, restarting."
+		);
+		continue;
+	}};
+}
+
+// Synchronizes the given `confirmables` with the given chain source, evaluating to the
+// [`SyncStats`] of the sync. Fails with `TxSyncError::MaxRestartsExceeded` if the sync had to be
+// restarted more than `$max_restarts` times. Confirmations are only reported once they reached a
+// depth of `$min_confirmations`.
+//
+// This needs to be a macro as it's shared between blocking and async chain sources. Async sources
+// need to pass `.await` after the semicolon, which is then appended to any call to the chain
+// source. On failure, it returns early from the surrounding function, which therefore needs to
+// return a `Result<_, TxSyncError>`.
+macro_rules! sync_confirmables {
+	($source: expr, $confirmables: expr, $sync_state: expr, $queue: expr, $metrics: expr,
+		$logger: expr, $max_restarts: expr, $min_confirmations: expr; $($await: tt)*) => {{
+		use $crate::error::ChainSourceError;
+
+		lightning::log_trace!($logger, "Starting transaction sync.");
+		#[cfg(feature = "time")]
+		let start_time = std::time::Instant::now();
+		let mut num_confirmed = 0;
+		let mut num_unconfirmed = 0;
+		let mut num_restarts = 0;
+
+		let mut tip_hash = $source.get_tip()$($await)*?;
+
+		loop {
+			let pending_registrations = $queue.lock().unwrap().process_queues(&mut $sync_state);
+			let tip_is_new = Some(tip_hash) != $sync_state.last_sync_hash;
+
+			// We loop until any registered transactions have been processed at least once, or the
+			// tip hasn't been updated during the last iteration.
+			if !$sync_state.pending_sync && !pending_registrations && !tip_is_new {
+				// Nothing to do.
+				break;
+			} else {
+				// Update the known tip to the newest one.
+				if tip_is_new {
+					// Retrieve the new tip's header and height, so that we can check the depth of
+					// previously-confirmed transactions against it.
+					let (tip_header, tip_height) = match $source.get_header(&tip_hash)$($await)* {
+						Ok(tip) => tip,
+						Err(ChainSourceError::Inconsistency) => {
+							// The tip we're syncing to isn't part of the best chain anymore, so
+							// we need to retrieve the new one before restarting.
+							match $source.get_tip()$($await)* {
+								Ok(new_tip_hash) => tip_hash = new_tip_hash,
+								Err(ChainSourceError::Inconsistency) => {},
+								Err(err) => {
+									abort_sync!($logger, $sync_state, num_confirmed, num_unconfirmed, err);
+								},
+							}
+							restart_sync!($source, $logger, $metrics, $sync_state, num_restarts, $max_restarts);
+						},
+						Err(err) => {
+							abort_sync!($logger, $sync_state, num_confirmed, num_unconfirmed, err);
+						},
+					};
+
+					// First check for any unconfirmed transactions by querying the interface for
+					// relevant txids and checking whether the relevant blocks are still in the
+					// best chain.
+					let relevant_txids = $confirmables
+						.iter()
+						.flat_map(|c| c.get_relevant_txids())
+						.collect::<std::collections::HashSet<(bitcoin::Txid, u32, Option<bitcoin::BlockHash>)>>();
+
+					// If a full resync was requested, have any transactions we previously reported
+					// as confirmed be looked up and delivered again.
+					if $sync_state.pending_full_resync {
+						$sync_state
+							.watched_transactions
+							.extend(relevant_txids.iter().map(|(txid, _, _)| *txid));
+						$sync_state.pending_full_resync = false;
+					}
+
+					let mut unconfirmed_txs = Vec::new();
+					for (txid, conf_height, block_hash_opt) in relevant_txids {
+						if let Some(block_hash) = block_hash_opt {
+							// Transactions that dropped back below the required depth, e.g., as a
+							// reorg shortened the chain, are considered unconfirmed again.
+							if $min_confirmations > 1
+								&& !$crate::common::has_min_confirmations(
+									conf_height,
+									tip_height,
+									$min_confirmations,
+								) {
+								unconfirmed_txs.push(txid);
+								continue;
+							}
+							match $source.is_block_in_best_chain(&block_hash, conf_height)$($await)* {
+								// Skip if the block in question is still confirmed.
+								Ok(true) => continue,
+								Ok(false) => unconfirmed_txs.push(txid),
+								Err(err) => abort_sync!(
+									$logger,
+									$sync_state,
+									num_confirmed,
+									num_unconfirmed,
+									err
+								),
+							}
+						} else {
+							lightning::log_error!($logger, "Untracked confirmation of funding transaction. Please ensure none of your channels had been created with LDK prior to version 0.0.113!");
+							panic!("Untracked confirmation of funding transaction. Please ensure none of your channels had been created with LDK prior to version 0.0.113!");
+						}
+					}
+
+					// Double-check the tip hash. If it changed, a reorg happened since we started
+					// syncing and we need to restart last-minute. Otherwise, act on the
+					// unconfirmed transactions immediately.
+					match $source.get_tip()$($await)* {
+						Ok(check_tip_hash) => {
+							if check_tip_hash != tip_hash {
+								tip_hash = check_tip_hash;
+								restart_sync!($source, $logger, $metrics, $sync_state, num_restarts, $max_restarts);
+							}
+							num_unconfirmed += unconfirmed_txs.len();
+							$metrics.record_unconfirmed(unconfirmed_txs.len());
+							$sync_state.sync_unconfirmed_transactions(&$confirmables, unconfirmed_txs);
+						},
+						Err(ChainSourceError::Inconsistency) => {
+							restart_sync!($source, $logger, $metrics, $sync_state, num_restarts, $max_restarts);
+						},
+						Err(err) => {
+							abort_sync!($logger, $sync_state, num_confirmed, num_unconfirmed, err);
+						},
+					}
+
+					// Inform the interface of the new block.
+					for c in &$confirmables {
+						c.best_block_updated(&tip_header, tip_height);
+					}
+
+					// Prune any sufficiently confirmed output spends
+					$sync_state.prune_output_spends(tip_height);
+					$metrics.record_tip_height(tip_height);
+					$sync_state.last_sync_height = Some(tip_height);
+				}
+
+				match $source.get_confirmed_transactions(&$sync_state)$($await)* {
+					Ok(confirmed_txs) => {
+						// Double-check the tip hash. If it changed, a reorg happened since
+						// we started syncing and we need to restart last-minute.
+						match $source.get_tip()$($await)* {
+							Ok(check_tip_hash) => {
+								if check_tip_hash != tip_hash {
+									tip_hash = check_tip_hash;
+									restart_sync!($source, $logger, $metrics, $sync_state, num_restarts, $max_restarts);
+								}
+
+								// Withhold any confirmations that didn't reach the required depth
+								// yet. As we keep watching the respective items, we'll report them
+								// on a later sync.
+								// unwrap() safety: we set the tip height above if the tip is new, and
+								// otherwise did so when we synced to it previously.
+								let tip_height = $sync_state.last_sync_height.unwrap();
+								let (confirmed_txs, withheld_txs): (Vec<_>, Vec<_>) =
+									confirmed_txs.into_iter().partition(|ctx| {
+										$crate::common::has_min_confirmations(
+											ctx.block_height,
+											tip_height,
+											$min_confirmations,
+										)
+									});
+								if !withheld_txs.is_empty() {
+									lightning::log_trace!(
+										$logger,
+										"Withholding {} confirmed transactions below {} confirmations.",
+										withheld_txs.len(),
+										$min_confirmations
+									);
+								}
+								num_confirmed += confirmed_txs.len();
+								$metrics.record_confirmed(confirmed_txs.len());
+								$sync_state.sync_confirmed_transactions(&$confirmables, confirmed_txs);
+							},
+							Err(ChainSourceError::Inconsistency) => {
+								restart_sync!($source, $logger, $metrics, $sync_state, num_restarts, $max_restarts);
+							},
+							Err(err) => {
+								abort_sync!($logger, $sync_state, num_confirmed, num_unconfirmed, err);
+							},
+						}
+					},
+					Err(ChainSourceError::Inconsistency) => {
+						// Immediately restart syncing when we encounter any inconsistencies.
+						restart_sync!($source, $logger, $metrics, $sync_state, num_restarts, $max_restarts);
+					},
+					Err(err) => {
+						abort_sync!($logger, $sync_state, num_confirmed, num_unconfirmed, err);
+					},
+				}
+				$sync_state.last_sync_hash = Some(tip_hash);
+				$sync_state.pending_sync = false;
+			}
+		}
+
+		let replaced_transactions =
+			match $source.get_replaced_transactions(&mut $sync_state)$($await)* {
+				Ok(replaced_txs) => replaced_txs,
+				Err(err) => {
+					abort_sync!($logger, $sync_state, num_confirmed, num_unconfirmed, err);
+				},
+			};
+
+		#[cfg(feature = "time")]
+		let duration = Some(start_time.elapsed());
+		#[cfg(not(feature = "time"))]
+		let duration = None;
+		#[cfg(feature = "time")]
+		$metrics.record_sync_duration(start_time.elapsed());
+		#[cfg(feature = "time")]
+		lightning::log_debug!(
+			$logger,
+			"Finished transaction sync at tip {} in {}ms: {} confirmed, {} unconfirmed.",
+			tip_hash,
+			start_time.elapsed().as_millis(),
+			num_confirmed,
+			num_unconfirmed
+		);
+		#[cfg(not(feature = "time"))]
+		lightning::log_debug!(
+			$logger,
+			"Finished transaction sync at tip {}: {} confirmed, {} unconfirmed.",
+			tip_hash,
+			num_confirmed,
+			num_unconfirmed
+		);
+
+		// unwrap() safety: we always set the tip height whenever we set `last_sync_hash`, which
+		// is guaranteed to happen at least once before we get here.
+		let tip_height = $sync_state.last_sync_height.unwrap();
+		$crate::metrics::SyncStats {
+			num_confirmed,
+			num_unconfirmed,
+			tip_hash,
+			tip_height,
+			duration,
+			num_restarts,
+			replaced_transactions,
+		}
+	}};
+}
+
+// Restores a previously-persisted [`SyncState`] from the given `KVStore` into the given sync
+// state, evaluating to whether it was restored.
+//
+// The restored state is only adopted if the tip it was synced to is still part of the best chain.
+// Otherwise, e.g., if a reorg happened while we were offline, it is discarded and we fall back to
+// a full resync. Just like [`sync_confirmables`], this is shared between blocking and async chain
+// sources and expects the latter to pass `.await` after the semicolon.
+macro_rules! restore_sync_state {
+	($source: expr, $kv_store: expr, $sync_state: expr, $logger: expr; $($await: tt)*) => {{
+		match $crate::common::SyncState::read_from($kv_store) {
+			Ok(Some(restored_state)) => {
+				match (restored_state.last_sync_hash, restored_state.last_sync_height) {
+					(Some(tip_hash), Some(tip_height)) => {
+						match $source.is_block_in_best_chain(&tip_hash, tip_height)$($await)* {
+							Ok(true) => {
+								lightning::log_debug!(
+									$logger,
+									"Restored persisted transaction sync state at tip {}.",
+									tip_hash
+								);
+								$sync_state = restored_state;
+								Ok(true)
+							},
+							Ok(false) => {
+								lightning::log_info!(
+									$logger,
+									"Persisted transaction sync state at tip {} was reorged out, falling back to a full resync.",
+									tip_hash
+								);
+								Ok(false)
+							},
+							Err(e) => Err($crate::error::TxSyncError::from(e)),
+						}
+					},
+					// We never completed a sync, so there's nothing worth restoring.
+					_ => Ok(false),
+				}
+			},
+			Ok(None) => Ok(false),
+			Err(e) => {
+				lightning::log_error!($logger, "Failed to read persisted transaction sync state: {}", e);
+				Err($crate::error::TxSyncError::Failed)
+			},
+		}
+	}};
+}
+
+// Restores a previously-persisted [`SyncState`] using the given blocking chain source.
+pub(crate) fn restore_with_chain_source<S: ChainSource + ?Sized, K: KVStore + ?Sized, L: Deref>(
+	source: &S, kv_store: &K, sync_state: &mut SyncState, logger: &L,
+) -> Result<bool, TxSyncError>
+where
+	L::Target: Logger,
+{
+	restore_sync_state!(source, kv_store, *sync_state, logger;)
+}
+
+// Synchronizes the given `confirmables` with the given blocking chain source.
+pub(crate) fn sync_with_chain_source<S: ChainSource + ?Sized, C: Deref, L: Deref>(
+	source: &S, confirmables: &Vec<C>, sync_state: &mut SyncState, queue: &Mutex<FilterQueue>,
+	metrics: &MetricsCounters, logger: &L, max_restarts: u16, min_confirmations: u32,
+) -> Result<SyncStats, TxSyncError>
+where
+	C::Target: Confirm,
+	L::Target: Logger,
+{
+	Ok(sync_confirmables!(
+		source,
+		*confirmables,
+		*sync_state,
+		queue,
+		metrics,
+		logger,
+		max_restarts,
+		min_confirmations;
+	))
+}
+
+// Returns whether a transaction confirmed at `conf_height` reached a depth of at least
+// `min_confirmations` at the given tip height.
+pub(crate) fn has_min_confirmations(
+	conf_height: u32, tip_height: u32, min_confirmations: u32,
+) -> bool {
+	tip_height.saturating_add(1) >= conf_height.saturating_add(min_confirmations)
+}
 
-// Represents the current state.
-pub(crate) struct SyncState {
+/// The state of a sync client, i.e., the items it watches along with the chain tip it last synced
+/// to.
+///
+/// Handed to a [`ChainSource`] to retrieve the confirmations of the watched items.
+pub struct SyncState {
 	// Transactions that were previously processed, but must not be forgotten
 	// yet since they still need to be monitored for confirmation on-chain.
-	pub watched_transactions: HashSet<Txid>,
+	pub(crate) watched_transactions: HashSet<Txid>,
+	// The scripts registered transactions are expected to pay to. Populated unless the chain
+	// source ignores them.
+	pub(crate) watched_scripts: HashMap<Txid, ScriptBuf>,
+	// Transactions we reported as confirmed as they pay to the script of a watched transaction,
+	// mapped to the txid of the latter, so that we don't deliver them again on the next sync.
+	pub(crate) script_matches: HashMap<Txid, Txid>,
 	// Outputs that were previously processed, but must not be forgotten yet as
 	// as we still need to monitor any spends on-chain.
-	pub watched_outputs: HashMap<OutPoint, WatchedOutput>,
+	pub(crate) watched_outputs: HashMap<OutPoint, WatchedOutput>,
 	// Outputs for which we previously saw a spend on-chain but kept around until the spends reach
 	// sufficient depth.
-	pub outputs_spends_pending_threshold_conf: Vec<(Txid, u32, OutPoint, WatchedOutput)>,
+	pub(crate) outputs_spends_pending_threshold_conf: Vec<(Txid, u32, OutPoint, WatchedOutput)>,
+	// Unconfirmed transactions we saw spending watched outputs during our last sync. Only
+	// populated if mempool replacement detection is enabled.
+	pub(crate) unconfirmed_output_spends: HashMap<OutPoint, Txid>,
+	// The opaque tags watched outputs were registered with, if any.
+	pub(crate) output_tags: HashMap<OutPoint, u64>,
+	// Spends of watched outputs we detected but didn't hand out via `spent_outputs` yet.
+	pub(crate) spent_outputs: Vec<SpentOutput>,
 	// The tip hash observed during our last sync.
-	pub last_sync_hash: Option<BlockHash>,
+	pub(crate) last_sync_hash: Option<BlockHash>,
+	// The tip height observed during our last sync.
+	pub(crate) last_sync_height: Option<u32>,
 	// Indicates whether we need to resync, e.g., after encountering an error.
-	pub pending_sync: bool,
+	pub(crate) pending_sync: bool,
+	// Indicates whether the next sync needs to re-check all transactions the confirmables consider
+	// relevant, re-delivering any confirmations we already reported.
+	pub(crate) pending_full_resync: bool,
+	// Changes to the watched items that weren't published yet. Only populated if watch events were
+	// opted into.
+	pub(crate) watch_events: Option<Vec<WatchEvent>>,
 }
 
 impl SyncState {
-	pub fn new() -> Self {
+	/// Returns the transactions to check for confirmation, i.e., the ones that were registered
+	/// and didn't confirm yet.
+	pub fn watched_transactions(&self) -> &HashSet<Txid> {
+		&self.watched_transactions
+	}
+
+	/// Returns the outputs to check for spends, i.e., the ones that were registered and weren't
+	/// spent by a confirmed transaction yet.
+	pub fn watched_outputs(&self) -> &HashMap<OutPoint, WatchedOutput> {
+		&self.watched_outputs
+	}
+
+	/// Returns the script the given watched transaction was registered with, if it is known.
+	pub fn watched_script(&self, txid: &Txid) -> Option<&Script> {
+		self.watched_scripts.get(txid).map(|script| script.as_script())
+	}
+
+	pub(crate) fn new() -> Self {
 		Self {
 			watched_transactions: HashSet::new(),
+			watched_scripts: HashMap::new(),
+			script_matches: HashMap::new(),
 			watched_outputs: HashMap::new(),
 			outputs_spends_pending_threshold_conf: Vec::new(),
+			unconfirmed_output_spends: HashMap::new(),
+			output_tags: HashMap::new(),
+			spent_outputs: Vec::new(),
 			last_sync_hash: None,
+			last_sync_height: None,
 			pending_sync: false,
+			pending_full_resync: false,
+			watch_events: None,
+		}
+	}
+
+	// Records the given change to the watched items, if we collect watch events.
+	pub(crate) fn push_watch_event(&mut self, event: WatchEvent) {
+		if let Some(watch_events) = &mut self.watch_events {
+			watch_events.push(event);
 		}
 	}
-	pub fn sync_unconfirmed_transactions<C: Deref>(
+
+	// Resets our bookkeeping of already-reported confirmations, so that the next sync re-checks
+	// all watched items and re-delivers any confirmations.
+	pub(crate) fn request_full_resync(&mut self) {
+		for (_, _, outpoint, output) in self.outputs_spends_pending_threshold_conf.drain(..) {
+			self.watched_outputs.insert(outpoint, output);
+		}
+		self.script_matches.clear();
+		// Forgetting the last synced tip has us query the confirmables for relevant transactions.
+		self.last_sync_hash = None;
+		self.pending_sync = true;
+		self.pending_full_resync = true;
+	}
+
+	pub(crate) fn sync_unconfirmed_transactions<C: Deref>(
 		&mut self, confirmables: &Vec<C>, unconfirmed_txs: Vec<Txid>,
 	) where
 		C::Target: Confirm,
@@ -44,13 +567,21 @@ impl SyncState {
 			}
 
 			self.watched_transactions.insert(txid);
+			self.script_matches.remove(&txid);
+			self.push_watch_event(WatchEvent::Unconfirmed { txid });
 
 			// If a previously-confirmed output spend is unconfirmed, re-add the watched output to
 			// the tracking map.
+			let watched_outputs = &mut self.watched_outputs;
+			let watch_events = &mut self.watch_events;
 			self.outputs_spends_pending_threshold_conf.retain(
 				|(conf_txid, _, prev_outpoint, output)| {
 					if txid == *conf_txid {
-						self.watched_outputs.insert(*prev_outpoint, output.clone());
+						watched_outputs.insert(*prev_outpoint, output.clone());
+						if let Some(watch_events) = watch_events {
+							let item = WatchedItem::Output(*prev_outpoint);
+							watch_events.push(WatchEvent::Added(item));
+						}
 						false
 					} else {
 						true
@@ -60,7 +591,7 @@ impl SyncState {
 		}
 	}
 
-	pub fn sync_confirmed_transactions<C: Deref>(
+	pub(crate) fn sync_confirmed_transactions<C: Deref>(
 		&mut self, confirmables: &Vec<C>, confirmed_txs: Vec<ConfirmedTx>,
 	) where
 		C::Target: Confirm,
@@ -74,10 +605,31 @@ impl SyncState {
 				);
 			}
 
-			self.watched_transactions.remove(&ctx.tx.txid());
+			if self.watched_transactions.remove(&ctx.tx.txid()) {
+				let confirmation_height = ctx.block_height;
+				self.push_watch_event(WatchEvent::Confirmed {
+					txid: ctx.txid,
+					confirmation_height,
+				});
+				self.script_matches.retain(|_, watched_txid| *watched_txid != ctx.txid);
+			}
+			if let Some(watched_txid) = self.watched_txid_paid_by(&ctx.tx) {
+				self.script_matches.insert(ctx.txid, watched_txid);
+			}
 
 			for input in &ctx.tx.input {
 				if let Some(output) = self.watched_outputs.remove(&input.previous_output) {
+					self.spent_outputs.push(SpentOutput {
+						outpoint: input.previous_output,
+						spending_txid: ctx.tx.txid(),
+						confirmation_height: ctx.block_height,
+						tag: self.output_tags.get(&input.previous_output).copied(),
+					});
+					self.push_watch_event(WatchEvent::Spent {
+						outpoint: input.previous_output,
+						spending_txid: ctx.txid,
+						confirmation_height: ctx.block_height,
+					});
 					let spent = (ctx.tx.txid(), ctx.block_height, input.previous_output, output);
 					self.outputs_spends_pending_threshold_conf.push(spent);
 				}
@@ -85,9 +637,255 @@ impl SyncState {
 		}
 	}
 
-	pub fn prune_output_spends(&mut self, cur_height: u32) {
-		self.outputs_spends_pending_threshold_conf
-			.retain(|(_, conf_height, _, _)| cur_height < conf_height + ANTI_REORG_DELAY - 1);
+	// Returns the txid of a watched transaction whose registered script the given transaction
+	// pays to, if any.
+	fn watched_txid_paid_by(&self, tx: &Transaction) -> Option<Txid> {
+		self.watched_scripts
+			.iter()
+			.filter(|(txid, _)| self.watched_transactions.contains(*txid))
+			.find(|(_, script)| tx.output.iter().any(|txout| txout.script_pubkey == **script))
+			.map(|(txid, _)| *txid)
+	}
+
+	// Returns the distinct scripts registered along with any watched transactions that aren't
+	// among the given confirmed transactions, so that we can look for other transactions paying
+	// to them.
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	pub(crate) fn unconfirmed_watched_scripts(
+		&self, confirmed_txs: &[ConfirmedTx],
+	) -> Vec<&Script> {
+		let mut scripts = Vec::new();
+		for txid in &self.watched_transactions {
+			if confirmed_txs.iter().any(|ctx| ctx.txid == *txid) {
+				continue;
+			}
+			if let Some(script) = self.watched_scripts.get(txid) {
+				if !scripts.contains(&script.as_script()) {
+					scripts.push(script.as_script());
+				}
+			}
+		}
+		scripts
+	}
+
+	// Returns whether the given transaction found in the history of a watched script still needs
+	// to be reported, i.e., whether we neither look it up by its txid nor already reported it.
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	pub(crate) fn is_unreported_script_match(
+		&self, txid: &Txid, confirmed_txs: &[ConfirmedTx],
+	) -> bool {
+		!self.watched_transactions.contains(txid)
+			&& !self.script_matches.contains_key(txid)
+			&& !confirmed_txs.iter().any(|ctx| ctx.txid == *txid)
+	}
+
+	pub(crate) fn prune_output_spends(&mut self, cur_height: u32) {
+		let output_tags = &mut self.output_tags;
+		let watch_events = &mut self.watch_events;
+		self.outputs_spends_pending_threshold_conf.retain(|(_, conf_height, outpoint, _)| {
+			let keep = cur_height < conf_height + ANTI_REORG_DELAY - 1;
+			if !keep {
+				output_tags.remove(outpoint);
+				if let Some(watch_events) = watch_events {
+					watch_events.push(WatchEvent::Pruned { outpoint: *outpoint });
+				}
+			}
+			keep
+		});
+	}
+}
+
+impl SyncState {
+	// Persists the state to the given `KVStore`.
+	pub(crate) fn write_to<K: KVStore + ?Sized>(&self, kv_store: &K) -> Result<(), io::Error> {
+		kv_store.write(
+			SYNC_STATE_PERSISTENCE_PRIMARY_NAMESPACE,
+			SYNC_STATE_PERSISTENCE_SECONDARY_NAMESPACE,
+			SYNC_STATE_PERSISTENCE_KEY,
+			&self.encode(),
+		)
+	}
+
+	// Reads a previously-persisted state from the given `KVStore`, returning `None` if no state
+	// was persisted yet.
+	pub(crate) fn read_from<K: KVStore + ?Sized>(kv_store: &K) -> Result<Option<Self>, io::Error> {
+		let buf = match kv_store.read(
+			SYNC_STATE_PERSISTENCE_PRIMARY_NAMESPACE,
+			SYNC_STATE_PERSISTENCE_SECONDARY_NAMESPACE,
+			SYNC_STATE_PERSISTENCE_KEY,
+		) {
+			Ok(buf) => buf,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(e),
+		};
+		SyncState::read(&mut io::Cursor::new(buf))
+			.map(Some)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+	}
+}
+
+// Whether we need to resync isn't persisted, as we'll resync after restarting anyways if the tip
+// changed in the meantime.
+impl_writeable_tlv_based!(SyncState, {
+	(0, watched_transactions, required),
+	(2, watched_scripts, required),
+	(4, watched_outputs, required),
+	(6, outputs_spends_pending_threshold_conf, required_vec),
+	(8, unconfirmed_output_spends, required),
+	(10, last_sync_hash, option),
+	(12, last_sync_height, option),
+	(15, output_tags, (default_value, HashMap::new())),
+	(17, script_matches, (default_value, HashMap::new())),
+	(not_written, pending_sync, (static_value, false)),
+	(not_written, pending_full_resync, (static_value, false)),
+	(not_written, spent_outputs, (static_value, Vec::new())),
+	(not_written, watch_events, (static_value, None)),
+});
+
+// Checks that the genesis block hash reported by the chain backend matches the given network.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+pub(crate) fn check_genesis_block_hash<L: Deref>(
+	network: Network, genesis_block_hash: BlockHash, logger: &L,
+) -> Result<(), TxSyncError>
+where
+	L::Target: Logger,
+{
+	let expected_block_hash = genesis_block(network).block_hash();
+	if genesis_block_hash != expected_block_hash {
+		lightning::log_error!(
+			logger,
+			"Chain backend is not on the expected network {}: its genesis block hash is {}, but expected {}.",
+			network,
+			genesis_block_hash,
+			expected_block_hash
+		);
+		return Err(TxSyncError::WrongNetwork);
+	}
+	Ok(())
+}
+
+// A proof of a transaction's inclusion in a block, as served by the chain backend.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+#[derive(Clone, Copy)]
+pub(crate) enum MerkleProof<'a> {
+	// A partial Merkle tree matching only the transaction, as served by Esplora.
+	PartialTree(&'a PartialMerkleTree),
+	// The Merkle branch of the transaction along with its position in the block, as served by
+	// Electrum. The hashes are given in the byte order they're displayed in.
+	Branch { pos: usize, merkle: &'a [[u8; 32]] },
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+impl<'a> MerkleProof<'a> {
+	// Returns the Merkle root the proof commits to along with the position of the transaction
+	// with the given txid in the block, or `None` if the proof isn't one for only that transaction.
+	fn merkle_root_and_pos(&self, txid: Txid) -> Option<(TxMerkleNode, usize)> {
+		match self {
+			Self::PartialTree(tree) => {
+				let mut matches = Vec::new();
+				let mut indexes = Vec::new();
+				let merkle_root = tree.extract_matches(&mut matches, &mut indexes).ok()?;
+				if indexes.len() != 1 || matches.len() != 1 || matches[0] != txid {
+					return None;
+				}
+				Some((merkle_root, indexes[0] as usize))
+			},
+			Self::Branch { pos, merkle } => {
+				let mut index = *pos;
+				let mut cur = txid.to_raw_hash();
+				for bytes in merkle.iter() {
+					let mut bytes = *bytes;
+					bytes.reverse();
+					let next_hash = Sha256d::from_byte_array(bytes);
+					let (left, right) =
+						if index % 2 == 0 { (cur, next_hash) } else { (next_hash, cur) };
+
+					let data = [&left[..], &right[..]].concat();
+					cur = Sha256d::hash(&data);
+					index /= 2;
+				}
+				Some((TxMerkleNode::from_raw_hash(cur), *pos))
+			},
+		}
+	}
+}
+
+// Checks that the given transaction is the one we looked up by `txid` and that the given proof
+// shows its inclusion in the block with the given header, returning its position in the block.
+//
+// The header needs to be one we retrieved or verified ourselves, e.g., by looking it up by
+// height, as otherwise a proof for the wrong block would go unnoticed. As the block may have been
+// reorged out in the meantime, a proof not committing to the header is treated as an
+// inconsistency.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+pub(crate) fn validate_confirmed_tx<L: Deref>(
+	tx: &Transaction, txid: Txid, block_header: &Header, proof: MerkleProof, logger: &L,
+) -> Result<usize, ChainSourceError>
+where
+	L::Target: Logger,
+{
+	if tx.txid() != txid {
+		lightning::log_error!(logger, "Retrieved transaction for txid {} doesn't match expectations. This should not happen. Please verify server integrity.", txid);
+		return Err(ChainSourceError::ServerMisbehaving(format!(
+			"Transaction for txid {} doesn't match expectations",
+			txid
+		)));
+	}
+
+	// Bitcoin's Merkle tree doesn't discern between inner nodes and leaves, which allows to
+	// present a 64-byte transaction matching the concatenation of two inner nodes' hashes as
+	// being included in a block (see
+	// https://web.archive.org/web/20240329003521/https://bitslog.com/2018/06/09/leaf-node-weakness-in-bitcoin-merkle-tree-design/).
+	// As no standard transaction is that small, we simply refuse to consider any as confirmed.
+	if tx.base_size() == 64 {
+		lightning::log_error!(logger, "Refusing to consider 64-byte transaction {} confirmed as its Merkle proof can't be trusted. Please verify server integrity.", txid);
+		return Err(ChainSourceError::ServerMisbehaving(format!(
+			"Transaction {} is exactly 64 bytes in size",
+			txid
+		)));
+	}
+
+	let (merkle_root, pos) = match proof.merkle_root_and_pos(txid) {
+		Some(merkle_root_and_pos) => merkle_root_and_pos,
+		None => {
+			lightning::log_error!(logger, "Retrieved Merkle proof for txid {} doesn't match expectations. This should not happen. Please verify server integrity.", txid);
+			return Err(ChainSourceError::ServerMisbehaving(format!(
+				"Merkle proof for txid {} doesn't match expectations",
+				txid
+			)));
+		},
+	};
+
+	if merkle_root != block_header.merkle_root {
+		lightning::log_trace!(
+			logger,
+			"Inconsistency: Merkle proof for tx {} doesn't match block {}.",
+			txid,
+			block_header.block_hash()
+		);
+		return Err(ChainSourceError::Inconsistency);
+	}
+
+	Ok(pos)
+}
+
+// The chain tip we last successfully synced to.
+#[derive(Clone, Copy)]
+pub(crate) struct LastSync {
+	pub tip_hash: BlockHash,
+	pub tip_height: u32,
+	#[cfg(feature = "time")]
+	pub completed_at: std::time::SystemTime,
+}
+
+impl LastSync {
+	pub fn now(tip_hash: BlockHash, tip_height: u32) -> Self {
+		Self {
+			tip_hash,
+			tip_height,
+			#[cfg(feature = "time")]
+			completed_at: std::time::SystemTime::now(),
+		}
 	}
 }
 
@@ -95,41 +893,1215 @@ impl SyncState {
 pub(crate) struct FilterQueue {
 	// Transactions that were registered via the `Filter` interface and have to be processed.
 	pub transactions: HashSet<Txid>,
+	// The scripts registered transactions are expected to pay to, unless the chain source ignores
+	// them.
+	pub scripts: HashMap<Txid, ScriptBuf>,
 	// Outputs that were registered via the `Filter` interface and have to be processed.
 	pub outputs: HashMap<OutPoint, WatchedOutput>,
+	// The opaque tags outputs were registered with, if any.
+	pub output_tags: HashMap<OutPoint, u64>,
+	// Transactions that were unregistered and have to be dropped from the watched set.
+	pub removed_transactions: HashSet<Txid>,
+	// Outputs that were unregistered and have to be dropped from the watched set.
+	pub removed_outputs: HashSet<OutPoint>,
 }
 
 impl FilterQueue {
 	pub fn new() -> Self {
-		Self { transactions: HashSet::new(), outputs: HashMap::new() }
+		Self {
+			transactions: HashSet::new(),
+			scripts: HashMap::new(),
+			outputs: HashMap::new(),
+			output_tags: HashMap::new(),
+			removed_transactions: HashSet::new(),
+			removed_outputs: HashSet::new(),
+		}
+	}
+
+	// Queues the given transaction for registration, overriding any pending removal. If an
+	// expected script is given, it's kept around alongside the watched transaction, e.g., to
+	// validate the transaction pays to it once confirmed.
+	pub fn register_tx(&mut self, txid: Txid, expected_script: Option<ScriptBuf>) {
+		self.removed_transactions.remove(&txid);
+		self.transactions.insert(txid);
+		if let Some(script) = expected_script {
+			self.scripts.insert(txid, script);
+		}
+	}
+
+	// Queues the given output for registration, overriding any pending removal.
+	pub fn register_output(&mut self, output: WatchedOutput) {
+		let outpoint = output.outpoint.into_bitcoin_outpoint();
+		self.removed_outputs.remove(&outpoint);
+		self.outputs.insert(outpoint, output);
+	}
+
+	// Queues the given output for registration just like `register_output`, associating it with
+	// the given tag.
+	pub fn register_output_with_tag(&mut self, output: WatchedOutput, tag: u64) {
+		self.output_tags.insert(output.outpoint.into_bitcoin_outpoint(), tag);
+		self.register_output(output);
+	}
+
+	// Queues the given transaction for removal, dropping any pending registration.
+	pub fn unregister_tx(&mut self, txid: &Txid) {
+		self.transactions.remove(txid);
+		self.scripts.remove(txid);
+		self.removed_transactions.insert(*txid);
+	}
+
+	// Queues the given output for removal, dropping any pending registration.
+	pub fn unregister_output(&mut self, outpoint: &OutPoint) {
+		self.outputs.remove(outpoint);
+		self.output_tags.remove(outpoint);
+		self.removed_outputs.insert(*outpoint);
+	}
+
+	// Returns whether any transactions or outputs are queued for registration.
+	#[cfg(feature = "electrum")]
+	pub fn has_pending_registrations(&self) -> bool {
+		!self.transactions.is_empty() || !self.outputs.is_empty()
 	}
 
 	// Processes the transaction and output queues and adds them to the given [`SyncState`].
+	// Any items that were unregistered in the meantime are removed from the [`SyncState`].
 	//
 	// Returns `true` if new items had been registered.
 	pub fn process_queues(&mut self, sync_state: &mut SyncState) -> bool {
 		let mut pending_registrations = false;
 
-		if !self.transactions.is_empty() {
-			pending_registrations = true;
+		for txid in self.removed_transactions.drain() {
+			if sync_state.watched_transactions.remove(&txid) {
+				sync_state.push_watch_event(WatchEvent::Removed(WatchedItem::Transaction(txid)));
+			}
+			sync_state.watched_scripts.remove(&txid);
+			sync_state.script_matches.retain(|_, watched_txid| *watched_txid != txid);
+		}
 
-			sync_state.watched_transactions.extend(self.transactions.drain());
+		for outpoint in self.removed_outputs.drain() {
+			let mut was_watched = sync_state.watched_outputs.remove(&outpoint).is_some();
+			sync_state.unconfirmed_output_spends.remove(&outpoint);
+			sync_state.output_tags.remove(&outpoint);
+			sync_state.outputs_spends_pending_threshold_conf.retain(|(_, _, prev_outpoint, _)| {
+				let keep = *prev_outpoint != outpoint;
+				was_watched |= !keep;
+				keep
+			});
+			if was_watched {
+				sync_state.push_watch_event(WatchEvent::Removed(WatchedItem::Output(outpoint)));
+			}
 		}
 
-		if !self.outputs.is_empty() {
-			pending_registrations = true;
+		// Items we're already watching, e.g., as they were restored from a persisted state, don't
+		// require us to sync again.
+		for txid in self.transactions.drain() {
+			if sync_state.watched_transactions.insert(txid) {
+				pending_registrations = true;
+				sync_state.push_watch_event(WatchEvent::Added(WatchedItem::Transaction(txid)));
+			}
+		}
+		sync_state.watched_scripts.extend(self.scripts.drain());
 
-			sync_state.watched_outputs.extend(self.outputs.drain());
+		for (outpoint, output) in self.outputs.drain() {
+			if sync_state.watched_outputs.insert(outpoint, output).is_none() {
+				pending_registrations = true;
+				sync_state.push_watch_event(WatchEvent::Added(WatchedItem::Output(outpoint)));
+			}
 		}
+		sync_state.output_tags.extend(self.output_tags.drain());
 		pending_registrations
 	}
+
+	// Returns a snapshot of the transactions queued here and the ones watched by the given state.
+	pub fn watched_transactions(&self, sync_state: &SyncState) -> WatchedTransactions {
+		let sorted = |txids: &HashSet<Txid>| {
+			let mut txids = txids.iter().copied().collect::<Vec<_>>();
+			txids.sort_unstable();
+			txids
+		};
+		WatchedTransactions {
+			pending: sorted(&self.transactions),
+			pending_removal: sorted(&self.removed_transactions),
+			active: sorted(&sync_state.watched_transactions),
+		}
+	}
+
+	// Returns a snapshot of the outputs queued here and the ones watched by the given state.
+	pub fn watched_outputs(&self, sync_state: &SyncState) -> WatchedOutputs {
+		let sorted = |outputs: &HashMap<OutPoint, WatchedOutput>| {
+			let mut outputs = outputs.iter().collect::<Vec<_>>();
+			outputs.sort_unstable_by_key(|(outpoint, _)| **outpoint);
+			outputs.into_iter().map(|(_, output)| output.clone()).collect::<Vec<_>>()
+		};
+		let mut pending_removal = self.removed_outputs.iter().copied().collect::<Vec<_>>();
+		pending_removal.sort_unstable();
+		let awaiting_prune = sync_state
+			.outputs_spends_pending_threshold_conf
+			.iter()
+			.map(|(spending_txid, confirmation_height, outpoint, _)| SpentOutput {
+				outpoint: *outpoint,
+				spending_txid: *spending_txid,
+				confirmation_height: *confirmation_height,
+				tag: sync_state.output_tags.get(outpoint).copied(),
+			})
+			.collect();
+		WatchedOutputs {
+			pending: sorted(&self.outputs),
+			pending_removal,
+			active: sorted(&sync_state.watched_outputs),
+			awaiting_prune,
+		}
+	}
+}
+
+/// A spend of a watched output that was detected while syncing.
+///
+/// Returned by `spent_outputs` on the sync clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpentOutput {
+	/// The spent output.
+	pub outpoint: OutPoint,
+	/// The txid of the transaction spending the output.
+	pub spending_txid: Txid,
+	/// The height of the block the spending transaction was confirmed in.
+	pub confirmation_height: u32,
+	/// The opaque tag the output was registered with via `register_output_with_tag`, if any.
+	pub tag: Option<u64>,
 }
 
+/// A snapshot of the transactions watched by a sync client.
+///
+/// Returned by `watched_transactions` on the sync clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchedTransactions {
+	/// Transactions registered since the last sync. They are only checked for confirmation once
+	/// the next sync started.
+	pub pending: Vec<Txid>,
+	/// Transactions unregistered since the last sync. They are only dropped from [`Self::active`]
+	/// once the next sync started.
+	pub pending_removal: Vec<Txid>,
+	/// Transactions we check for confirmation on each sync, i.e., the ones that were registered
+	/// and didn't confirm yet.
+	pub active: Vec<Txid>,
+}
+
+/// A snapshot of the outputs watched by a sync client.
+///
+/// Returned by `watched_outputs` on the sync clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchedOutputs {
+	/// Outputs registered since the last sync. They are only checked for spends once the next
+	/// sync started.
+	pub pending: Vec<WatchedOutput>,
+	/// Outputs unregistered since the last sync. They are only dropped from [`Self::active`] and
+	/// [`Self::awaiting_prune`] once the next sync started.
+	pub pending_removal: Vec<OutPoint>,
+	/// Outputs we check for spends on each sync, i.e., the ones that were registered and weren't
+	/// spent by a confirmed transaction yet.
+	pub active: Vec<WatchedOutput>,
+	/// Outputs whose spend we detected, but which we keep track of until the spend reached
+	/// [`ANTI_REORG_DELAY`] confirmations, so that we start watching them again if the spending
+	/// transaction is unconfirmed.
+	///
+	/// [`ANTI_REORG_DELAY`]: lightning::chain::channelmonitor::ANTI_REORG_DELAY
+	pub awaiting_prune: Vec<SpentOutput>,
+}
+
+/// An item watched by the sync clients, i.e., a transaction registered via
+/// [`Filter::register_tx`] or an output registered via [`Filter::register_output`].
+///
+/// [`Filter::register_tx`]: lightning::chain::Filter::register_tx
+/// [`Filter::register_output`]: lightning::chain::Filter::register_output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WatchedItem {
+	/// A transaction watched for confirmation.
+	Transaction(Txid),
+	/// An output watched for spends.
+	Output(OutPoint),
+}
+
+/// A change to the items watched by a sync client.
+///
+/// If opted into, these are published by the sync clients at the end of each sync, after the
+/// respective changes were committed and any confirmations were handed to the [`Confirm`]
+/// interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchEvent {
+	/// We started watching the given item.
+	///
+	/// Note that this is emitted once the registration is processed during the next sync, and
+	/// again when an output is watched anew after the transaction spending it was unconfirmed.
+	Added(WatchedItem),
+	/// We stopped watching the given item as it was unregistered.
+	Removed(WatchedItem),
+	/// A watched transaction was confirmed, so we stopped watching it.
+	Confirmed {
+		/// The txid of the confirmed transaction.
+		txid: Txid,
+		/// The height of the block the transaction was confirmed in.
+		confirmation_height: u32,
+	},
+	/// A previously confirmed watched transaction was unconfirmed, e.g., due to a reorg, so we're
+	/// watching it again.
+	Unconfirmed {
+		/// The txid of the unconfirmed transaction.
+		txid: Txid,
+	},
+	/// A watched output was spent by a confirmed transaction. We keep track of the spend until it
+	/// is buried deep enough to be considered final, see [`WatchEvent::Pruned`].
+	Spent {
+		/// The spent output.
+		outpoint: OutPoint,
+		/// The txid of the transaction spending the output.
+		spending_txid: Txid,
+		/// The height of the block the spending transaction was confirmed in.
+		confirmation_height: u32,
+	},
+	/// The spend of a watched output reached [`ANTI_REORG_DELAY`] confirmations, so we forgot
+	/// about the output.
+	///
+	/// [`ANTI_REORG_DELAY`]: lightning::chain::channelmonitor::ANTI_REORG_DELAY
+	Pruned {
+		/// The spent output.
+		outpoint: OutPoint,
+	},
+}
+
+// Publishes the watch events collected during a sync to the receiver the user opted in with.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+pub(crate) struct WatchEventPublisher {
+	sender: std::sync::Mutex<std::sync::mpsc::Sender<WatchEvent>>,
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+impl WatchEventPublisher {
+	pub fn new(sender: std::sync::mpsc::Sender<WatchEvent>) -> Self {
+		Self { sender: std::sync::Mutex::new(sender) }
+	}
+
+	// Has the given state collect watch events during the upcoming sync. This needs to be called
+	// before each sync, as the state may have been replaced by a restored one in the meantime.
+	pub fn prepare(&self, sync_state: &mut SyncState) {
+		sync_state.watch_events.get_or_insert_with(Vec::new);
+	}
+
+	// Publishes the watch events collected by the given state. Events are dropped if the receiver
+	// went away.
+	pub fn publish(&self, sync_state: &mut SyncState) {
+		if let Some(watch_events) = &mut sync_state.watch_events {
+			let sender = self.sender.lock().unwrap();
+			for event in watch_events.drain(..) {
+				let _ = sender.send(event);
+			}
+		}
+	}
+}
+
+// Limits the rate of requests issued to the chain backend via a token bucket.
+//
+// Rather than counting tokens, we track the time at which the bucket would be full again if no
+// further requests were issued, which avoids any rounding issues.
+#[cfg(all(feature = "time", any(feature = "esplora-blocking", feature = "esplora-async")))]
+pub(crate) struct RateLimiter {
+	full_at: std::sync::Mutex<Option<std::time::Instant>>,
+	// The time it takes to refill a single token.
+	refill_interval: std::time::Duration,
+	// The time it takes to refill all but one token, i.e., how far `full_at` may lie in the future
+	// before we need to delay requests.
+	burst_tolerance: std::time::Duration,
+}
+
+#[cfg(all(feature = "time", any(feature = "esplora-blocking", feature = "esplora-async")))]
+impl RateLimiter {
+	pub fn new(rate_limit: RateLimit) -> Self {
+		let refill_interval =
+			std::time::Duration::from_secs(1) / rate_limit.requests_per_second.max(1);
+		let burst_tolerance = refill_interval * (rate_limit.burst.max(1) - 1);
+		Self { full_at: std::sync::Mutex::new(None), refill_interval, burst_tolerance }
+	}
+
+	// Takes a token for a request issued now, returning how long it needs to be delayed to stay
+	// within the rate limit.
+	pub fn reserve(&self) -> std::time::Duration {
+		self.reserve_at(std::time::Instant::now())
+	}
+
+	fn reserve_at(&self, now: std::time::Instant) -> std::time::Duration {
+		let mut full_at = self.full_at.lock().unwrap();
+		let prev_full_at = full_at.map_or(now, |full_at| full_at.max(now));
+		// Requests that have to wait take their token right away, so that concurrent requests are
+		// spaced out rather than all being issued once a token becomes available.
+		*full_at = Some(prev_full_at + self.refill_interval);
+		prev_full_at.saturating_duration_since(now).saturating_sub(self.burst_tolerance)
+	}
+}
+
+/// A transaction confirmed in the best chain, as returned by a [`ChainSource`].
 #[derive(Debug)]
-pub(crate) struct ConfirmedTx {
+pub struct ConfirmedTx {
+	/// The confirmed transaction.
 	pub tx: Transaction,
+	/// The txid of [`Self::tx`].
 	pub txid: Txid,
+	/// The header of the block the transaction was confirmed in.
 	pub block_header: Header,
+	/// The height of the block the transaction was confirmed in.
 	pub block_height: u32,
+	/// The position of the transaction within its block.
 	pub pos: usize,
 }
+
+impl ConfirmedTx {
+	/// Returns whether the transaction spends the given output.
+	pub fn spends(&self, outpoint: &OutPoint) -> bool {
+		self.tx.input.iter().any(|txin| txin.previous_output == *outpoint)
+	}
+
+	/// Returns the timestamp of the block the transaction was confirmed in.
+	pub fn block_time(&self) -> u32 {
+		self.block_header.time
+	}
+}
+
+/// Returns the given transactions in the order in which the sync clients hand them to
+/// [`Confirm::transactions_confirmed`] by default, i.e., first ordered by the height of the block
+/// they were confirmed in and then by their position within that block.
+///
+/// Each entry consists of the transaction's txid, its confirmation height, and its position in the
+/// block. This allows to predict the order in which confirmations are delivered, e.g., in tests.
+///
+/// Note that the order is reversed if [`SyncConfig::delivery_order`] is set to
+/// [`DeliveryOrder::Descending`].
+///
+/// [`SyncConfig::delivery_order`]: crate::SyncConfig::delivery_order
+pub fn expected_delivery_order(txs: &[(Txid, u32, usize)]) -> Vec<Txid> {
+	let mut txs = txs.to_vec();
+	sort_in_delivery_order(&mut txs, DeliveryOrder::Ascending, |(_, height, pos)| (*height, *pos));
+	txs.into_iter().map(|(txid, _, _)| txid).collect()
+}
+
+// Sorts the given items by the confirmation height and in-block position returned by
+// `height_and_pos` in the given order, which is the order in which we feed confirmed transactions
+// to the `Confirm` interface.
+pub(crate) fn sort_in_delivery_order<T, F: Fn(&T) -> (u32, usize)>(
+	items: &mut [T], order: DeliveryOrder, height_and_pos: F,
+) {
+	match order {
+		DeliveryOrder::Ascending => items.sort_unstable_by_key(height_and_pos),
+		DeliveryOrder::Descending => {
+			items.sort_unstable_by_key(|item| core::cmp::Reverse(height_and_pos(item)))
+		},
+	}
+}
+
+// Orders the given transactions such that any parent comes before its children, retaining the
+// given order otherwise. This is required when broadcasting packages to servers that don't accept
+// them atomically, as they'd otherwise reject children spending outputs they don't know yet.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+pub(crate) fn order_by_dependencies(txs: &[&Transaction]) -> Vec<Transaction> {
+	let mut remaining_txs = txs.iter().map(|tx| (*tx).clone()).collect::<Vec<_>>();
+	let mut ordered_txs = Vec::with_capacity(remaining_txs.len());
+	while !remaining_txs.is_empty() {
+		let remaining_txids = remaining_txs.iter().map(|tx| tx.txid()).collect::<HashSet<_>>();
+		let idx = remaining_txs
+			.iter()
+			.position(|tx| {
+				tx.input.iter().all(|txin| !remaining_txids.contains(&txin.previous_output.txid))
+			})
+			.unwrap_or(0);
+		ordered_txs.push(remaining_txs.remove(idx));
+	}
+	ordered_txs
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::Network;
+
+	#[test]
+	fn confirmed_tx_block_time_matches_header() {
+		let block = genesis_block(Network::Regtest);
+		let tx = block.txdata[0].clone();
+		let confirmed_tx = ConfirmedTx {
+			txid: tx.txid(),
+			tx,
+			block_header: block.header,
+			block_height: 0,
+			pos: 0,
+		};
+		assert_eq!(confirmed_tx.block_time(), block.header.time);
+		assert_eq!(confirmed_tx.block_time(), 1296688602);
+	}
+
+	#[test]
+	#[cfg(all(feature = "time", any(feature = "esplora-blocking", feature = "esplora-async")))]
+	fn rate_limiter_allows_bursts_and_spaces_out_requests() {
+		use std::time::{Duration, Instant};
+
+		let rate_limiter = RateLimiter::new(RateLimit { requests_per_second: 10, burst: 3 });
+		let start = Instant::now();
+
+		// We may issue a full burst right away, after which each request has to wait its turn.
+		for _ in 0..3 {
+			assert_eq!(rate_limiter.reserve_at(start), Duration::ZERO);
+		}
+		assert_eq!(rate_limiter.reserve_at(start), Duration::from_millis(100));
+		assert_eq!(rate_limiter.reserve_at(start), Duration::from_millis(200));
+
+		// Once the scheduled requests were issued, tokens are refilled at the configured rate.
+		let later = start + Duration::from_millis(300);
+		assert_eq!(rate_limiter.reserve_at(later), Duration::ZERO);
+		assert_eq!(rate_limiter.reserve_at(later), Duration::from_millis(100));
+
+		// But never beyond the burst size, however long we were idle.
+		let much_later = later + Duration::from_secs(60);
+		for _ in 0..3 {
+			assert_eq!(rate_limiter.reserve_at(much_later), Duration::ZERO);
+		}
+		assert!(rate_limiter.reserve_at(much_later) > Duration::ZERO);
+	}
+
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	fn test_tx(value: u64, script_pubkey: ScriptBuf) -> Transaction {
+		use bitcoin::blockdata::locktime::absolute::LockTime;
+		use bitcoin::blockdata::transaction::Version;
+		use bitcoin::{Amount, TxIn, TxOut};
+
+		Transaction {
+			version: Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: vec![TxIn::default()],
+			output: vec![TxOut { value: Amount::from_sat(value), script_pubkey }],
+		}
+	}
+
+	// Returns the given hash in the byte order Electrum servers serve Merkle branches in.
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	fn display_bytes<H: Hash<Bytes = [u8; 32]>>(hash: H) -> [u8; 32] {
+		let mut bytes = hash.to_byte_array();
+		bytes.reverse();
+		bytes
+	}
+
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	#[test]
+	fn confirmed_txs_are_validated_against_their_proof() {
+		use bitcoin::Block;
+		use lightning::util::test_utils::TestLogger;
+
+		let logger = TestLogger::new();
+		let txs = (0..4).map(|i| test_tx(1000 + i, ScriptBuf::new())).collect::<Vec<_>>();
+		let txids = txs.iter().map(|tx| tx.txid()).collect::<Vec<_>>();
+		let mut block = Block { header: genesis_block(Network::Regtest).header, txdata: txs };
+		block.header.merkle_root = block.compute_merkle_root().unwrap();
+		let other_header = genesis_block(Network::Regtest).header;
+
+		let tree = PartialMerkleTree::from_txids(&txids, &[false, false, true, false]);
+		let left_node = bitcoin::merkle_tree::calculate_root(txids[..2].iter().cloned()).unwrap();
+		let branch = [display_bytes(txids[3]), display_bytes(left_node)];
+		let proofs =
+			[MerkleProof::PartialTree(&tree), MerkleProof::Branch { pos: 2, merkle: &branch }];
+
+		for proof in proofs {
+			let tx = &block.txdata[2];
+			assert_eq!(
+				validate_confirmed_tx(tx, txids[2], &block.header, proof, &&logger).unwrap(),
+				2
+			);
+		}
+
+		// Check we detect the server handing us a different transaction.
+		for proof in proofs {
+			let tx = &block.txdata[1];
+			assert!(matches!(
+				validate_confirmed_tx(tx, txids[2], &block.header, proof, &&logger),
+				Err(ChainSourceError::ServerMisbehaving(_))
+			));
+		}
+
+		// Check we detect a proof for a different transaction.
+		let other_tree = PartialMerkleTree::from_txids(&txids, &[false, true, false, false]);
+		let proof = MerkleProof::PartialTree(&other_tree);
+		assert!(matches!(
+			validate_confirmed_tx(&block.txdata[2], txids[2], &block.header, proof, &&logger),
+			Err(ChainSourceError::ServerMisbehaving(_))
+		));
+
+		// Check we detect a proof for the wrong block, e.g., as it was reorged out.
+		for proof in proofs {
+			let tx = &block.txdata[2];
+			assert!(matches!(
+				validate_confirmed_tx(tx, txids[2], &other_header, proof, &&logger),
+				Err(ChainSourceError::Inconsistency)
+			));
+		}
+	}
+
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	#[test]
+	fn sixty_four_byte_txs_are_never_considered_confirmed() {
+		use lightning::util::test_utils::TestLogger;
+
+		let logger = TestLogger::new();
+		let tx = test_tx(1000, ScriptBuf::from_bytes(vec![0x51; 4]));
+		let txid = tx.txid();
+		assert_eq!(tx.base_size(), 64);
+
+		// Craft a block whose first two leaves are the two halves of the transaction's
+		// serialization, so that its txid matches the block's first inner node.
+		let serialized_tx = bitcoin::consensus::encode::serialize(&tx);
+		let left_leaf = Txid::from_slice(&serialized_tx[..32]).unwrap();
+		let right_leaf = Txid::from_slice(&serialized_tx[32..]).unwrap();
+		let other_txids =
+			[test_tx(2000, ScriptBuf::new()).txid(), test_tx(3000, ScriptBuf::new()).txid()];
+		let leaves = [left_leaf, right_leaf, other_txids[0], other_txids[1]];
+		let mut header = genesis_block(Network::Regtest).header;
+		let merkle_root = bitcoin::merkle_tree::calculate_root(leaves.iter().cloned()).unwrap();
+		header.merkle_root = TxMerkleNode::from_raw_hash(merkle_root.to_raw_hash());
+
+		let right_node = bitcoin::merkle_tree::calculate_root(other_txids.iter().cloned()).unwrap();
+		let tree = PartialMerkleTree::from_txids(&[txid, right_node], &[true, false]);
+		let branch = [display_bytes(right_node)];
+		let proofs =
+			[MerkleProof::PartialTree(&tree), MerkleProof::Branch { pos: 0, merkle: &branch }];
+
+		for proof in proofs {
+			// The proofs commit to the crafted block, even though the transaction isn't in it.
+			assert_eq!(proof.merkle_root_and_pos(txid), Some((header.merkle_root, 0)));
+			assert!(matches!(
+				validate_confirmed_tx(&tx, txid, &header, proof, &&logger),
+				Err(ChainSourceError::ServerMisbehaving(_))
+			));
+		}
+	}
+
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	#[test]
+	fn packages_are_ordered_by_dependencies() {
+		use bitcoin::blockdata::locktime::absolute::LockTime;
+		use bitcoin::blockdata::transaction::Version;
+		use bitcoin::{Amount, TxIn, TxOut};
+
+		let spending_tx = |previous_output: OutPoint, value: u64| Transaction {
+			version: Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: vec![TxIn { previous_output, ..Default::default() }],
+			output: vec![TxOut { value: Amount::from_sat(value), script_pubkey: ScriptBuf::new() }],
+		};
+		let parent = spending_tx(OutPoint::null(), 1000);
+		let child = spending_tx(OutPoint::new(parent.txid(), 0), 900);
+		let grandchild = spending_tx(OutPoint::new(child.txid(), 0), 800);
+		let unrelated = spending_tx(OutPoint::null(), 2000);
+
+		let ordered_txs = order_by_dependencies(&[&grandchild, &unrelated, &child, &parent]);
+		assert_eq!(ordered_txs, vec![unrelated.clone(), parent.clone(), child.clone(), grandchild]);
+
+		// Independent transactions keep their order.
+		let ordered_txs = order_by_dependencies(&[&unrelated, &parent]);
+		assert_eq!(ordered_txs, vec![unrelated, parent]);
+	}
+
+	#[test]
+	fn output_tags_are_returned_with_detected_spends() {
+		let block = genesis_block(Network::Regtest);
+		let funding_tx = block.txdata[0].clone();
+		let tagged_outpoint = OutPoint::new(funding_tx.txid(), 0);
+		let untagged_outpoint = OutPoint::new(funding_tx.txid(), 1);
+		let watched_output = |outpoint: OutPoint| WatchedOutput {
+			block_hash: Some(block.block_hash()),
+			outpoint: lightning::chain::transaction::OutPoint {
+				txid: outpoint.txid,
+				index: outpoint.vout as u16,
+			},
+			script_pubkey: ScriptBuf::new(),
+		};
+
+		let mut sync_state = SyncState::new();
+		let mut queue = FilterQueue::new();
+		queue.register_output_with_tag(watched_output(tagged_outpoint), 42);
+		queue.register_output(watched_output(untagged_outpoint));
+		assert!(queue.process_queues(&mut sync_state));
+
+		let mut spending_tx = funding_tx.clone();
+		spending_tx.input[0].previous_output = tagged_outpoint;
+		spending_tx.input.push(spending_tx.input[0].clone());
+		spending_tx.input[1].previous_output = untagged_outpoint;
+		let confirmed_tx = ConfirmedTx {
+			txid: spending_tx.txid(),
+			tx: spending_tx.clone(),
+			block_header: block.header,
+			block_height: 100,
+			pos: 1,
+		};
+		let confirmables: Vec<&dyn Confirm> = Vec::new();
+		sync_state.sync_confirmed_transactions(&confirmables, vec![confirmed_tx]);
+
+		let spent_output = |outpoint, tag| SpentOutput {
+			outpoint,
+			spending_txid: spending_tx.txid(),
+			confirmation_height: 100,
+			tag,
+		};
+		assert_eq!(
+			core::mem::take(&mut sync_state.spent_outputs),
+			vec![spent_output(tagged_outpoint, Some(42)), spent_output(untagged_outpoint, None)]
+		);
+
+		// Tags are dropped once the spend is sufficiently confirmed.
+		sync_state.prune_output_spends(100 + ANTI_REORG_DELAY - 2);
+		assert_eq!(sync_state.output_tags.get(&tagged_outpoint), Some(&42));
+		sync_state.prune_output_spends(100 + ANTI_REORG_DELAY - 1);
+		assert!(sync_state.output_tags.is_empty());
+
+		// Or when the output is unregistered.
+		queue.register_output_with_tag(watched_output(tagged_outpoint), 43);
+		queue.process_queues(&mut sync_state);
+		assert_eq!(sync_state.output_tags.get(&tagged_outpoint), Some(&43));
+		queue.unregister_output(&tagged_outpoint);
+		queue.process_queues(&mut sync_state);
+		assert!(sync_state.output_tags.is_empty());
+	}
+
+	#[test]
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	fn watch_events_follow_confirm_then_spend_lifecycle() {
+		let block = genesis_block(Network::Regtest);
+		let funding_tx = block.txdata[0].clone();
+		let funding_txid = funding_tx.txid();
+		let outpoint = OutPoint::new(funding_txid, 0);
+		let watched_output = WatchedOutput {
+			block_hash: None,
+			outpoint: lightning::chain::transaction::OutPoint { txid: funding_txid, index: 0 },
+			script_pubkey: ScriptBuf::new(),
+		};
+		let mut spending_tx = funding_tx.clone();
+		spending_tx.input[0].previous_output = outpoint;
+		let spending_txid = spending_tx.txid();
+		let confirmed_tx = |tx: &Transaction, block_height| ConfirmedTx {
+			txid: tx.txid(),
+			tx: tx.clone(),
+			block_header: block.header,
+			block_height,
+			pos: 1,
+		};
+
+		let (sender, receiver) = std::sync::mpsc::channel();
+		let publisher = WatchEventPublisher::new(sender);
+		let mut sync_state = SyncState::new();
+		let mut queue = FilterQueue::new();
+		let confirmables: Vec<&dyn Confirm> = Vec::new();
+
+		// Nothing is collected unless watch events were opted into.
+		queue.register_tx(funding_txid, None);
+		queue.process_queues(&mut sync_state);
+		assert!(sync_state.watch_events.is_none());
+		queue.unregister_tx(&funding_txid);
+		queue.process_queues(&mut sync_state);
+
+		publisher.prepare(&mut sync_state);
+		queue.register_tx(funding_txid, None);
+		queue.register_output(watched_output);
+		queue.process_queues(&mut sync_state);
+		sync_state.sync_confirmed_transactions(&confirmables, vec![confirmed_tx(&funding_tx, 100)]);
+		publisher.publish(&mut sync_state);
+		assert_eq!(
+			receiver.try_iter().collect::<Vec<_>>(),
+			vec![
+				WatchEvent::Added(WatchedItem::Transaction(funding_txid)),
+				WatchEvent::Added(WatchedItem::Output(outpoint)),
+				WatchEvent::Confirmed { txid: funding_txid, confirmation_height: 100 },
+			]
+		);
+
+		// Confirming an unwatched transaction spending the output only reports the spend.
+		publisher.prepare(&mut sync_state);
+		sync_state
+			.sync_confirmed_transactions(&confirmables, vec![confirmed_tx(&spending_tx, 101)]);
+		sync_state.prune_output_spends(101);
+		publisher.publish(&mut sync_state);
+		assert_eq!(
+			receiver.try_iter().collect::<Vec<_>>(),
+			vec![WatchEvent::Spent { outpoint, spending_txid, confirmation_height: 101 }]
+		);
+
+		publisher.prepare(&mut sync_state);
+		sync_state.prune_output_spends(101 + ANTI_REORG_DELAY - 1);
+		publisher.publish(&mut sync_state);
+		assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![WatchEvent::Pruned { outpoint }]);
+		assert!(sync_state.watch_events.as_ref().unwrap().is_empty());
+	}
+
+	#[test]
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	fn script_matches_are_reported_once() {
+		let block = genesis_block(Network::Regtest);
+		let watched_tx = block.txdata[0].clone();
+		let watched_txid = watched_tx.txid();
+		let script = watched_tx.output[0].script_pubkey.clone();
+		let mut alternate_tx = watched_tx.clone();
+		alternate_tx.lock_time = bitcoin::absolute::LockTime::from_consensus(1);
+		let alternate_txid = alternate_tx.txid();
+		let confirmed_tx = |tx: &Transaction| ConfirmedTx {
+			txid: tx.txid(),
+			tx: tx.clone(),
+			block_header: block.header,
+			block_height: 100,
+			pos: 1,
+		};
+
+		let mut sync_state = SyncState::new();
+		let mut queue = FilterQueue::new();
+		let confirmables: Vec<&dyn Confirm> = Vec::new();
+		queue.register_tx(watched_txid, Some(script.clone()));
+		queue.process_queues(&mut sync_state);
+		assert_eq!(sync_state.unconfirmed_watched_scripts(&[]), vec![script.as_script()]);
+		assert!(sync_state.is_unreported_script_match(&alternate_txid, &[]));
+		assert!(!sync_state.is_unreported_script_match(&watched_txid, &[]));
+
+		// Once we reported a transaction paying to the script, we don't do so again.
+		sync_state.sync_confirmed_transactions(&confirmables, vec![confirmed_tx(&alternate_tx)]);
+		assert!(sync_state.watched_transactions.contains(&watched_txid));
+		assert!(!sync_state.is_unreported_script_match(&alternate_txid, &[]));
+
+		// Unless it's unconfirmed again, in which case we watch it by its txid until it reconfirms.
+		sync_state.sync_unconfirmed_transactions(&confirmables, vec![alternate_txid]);
+		assert!(!sync_state.script_matches.contains_key(&alternate_txid));
+		assert!(sync_state.watched_transactions.contains(&alternate_txid));
+		sync_state.sync_confirmed_transactions(&confirmables, vec![confirmed_tx(&alternate_tx)]);
+		assert!(!sync_state.watched_transactions.contains(&alternate_txid));
+		assert!(!sync_state.is_unreported_script_match(&alternate_txid, &[]));
+
+		// We forget about the match once the watched transaction itself confirmed.
+		let confirmed_txs = vec![confirmed_tx(&watched_tx)];
+		assert!(sync_state.unconfirmed_watched_scripts(&confirmed_txs).is_empty());
+		sync_state.sync_confirmed_transactions(&confirmables, confirmed_txs);
+		assert!(sync_state.script_matches.is_empty());
+	}
+
+	#[test]
+	fn watched_items_distinguish_pending_and_active() {
+		let block = genesis_block(Network::Regtest);
+		let funding_tx = block.txdata[0].clone();
+		let funding_txid = funding_tx.txid();
+		let watched_output = |vout: u32| WatchedOutput {
+			block_hash: None,
+			outpoint: lightning::chain::transaction::OutPoint {
+				txid: funding_txid,
+				index: vout as u16,
+			},
+			script_pubkey: ScriptBuf::new(),
+		};
+		let spent_outpoint = OutPoint::new(funding_txid, 0);
+		let unspent_outpoint = OutPoint::new(funding_txid, 1);
+
+		let mut sync_state = SyncState::new();
+		let mut queue = FilterQueue::new();
+		queue.register_tx(funding_txid, None);
+		queue.register_output_with_tag(watched_output(0), 42);
+		queue.register_output(watched_output(1));
+		assert_eq!(
+			queue.watched_transactions(&sync_state),
+			WatchedTransactions {
+				pending: vec![funding_txid],
+				pending_removal: Vec::new(),
+				active: Vec::new()
+			}
+		);
+		assert_eq!(
+			queue.watched_outputs(&sync_state),
+			WatchedOutputs {
+				pending: vec![watched_output(0), watched_output(1)],
+				pending_removal: Vec::new(),
+				active: Vec::new(),
+				awaiting_prune: Vec::new(),
+			}
+		);
+
+		queue.process_queues(&mut sync_state);
+		queue.unregister_tx(&funding_txid);
+		assert_eq!(
+			queue.watched_transactions(&sync_state),
+			WatchedTransactions {
+				pending: Vec::new(),
+				pending_removal: vec![funding_txid],
+				active: vec![funding_txid]
+			}
+		);
+
+		// Spent outputs are reported as awaiting prune until the spend is buried deep enough.
+		let mut spending_tx = funding_tx.clone();
+		spending_tx.input[0].previous_output = spent_outpoint;
+		let confirmed_tx = ConfirmedTx {
+			txid: spending_tx.txid(),
+			tx: spending_tx.clone(),
+			block_header: block.header,
+			block_height: 100,
+			pos: 1,
+		};
+		let confirmables: Vec<&dyn Confirm> = Vec::new();
+		sync_state.sync_confirmed_transactions(&confirmables, vec![confirmed_tx]);
+		queue.unregister_output(&unspent_outpoint);
+		let awaiting_prune = SpentOutput {
+			outpoint: spent_outpoint,
+			spending_txid: spending_tx.txid(),
+			confirmation_height: 100,
+			tag: Some(42),
+		};
+		assert_eq!(
+			queue.watched_outputs(&sync_state),
+			WatchedOutputs {
+				pending: Vec::new(),
+				pending_removal: vec![unspent_outpoint],
+				active: vec![watched_output(1)],
+				awaiting_prune: vec![awaiting_prune],
+			}
+		);
+
+		sync_state.prune_output_spends(100 + ANTI_REORG_DELAY - 1);
+		queue.process_queues(&mut sync_state);
+		assert_eq!(
+			queue.watched_outputs(&sync_state),
+			WatchedOutputs {
+				pending: Vec::new(),
+				pending_removal: Vec::new(),
+				active: Vec::new(),
+				awaiting_prune: Vec::new(),
+			}
+		);
+	}
+
+	#[test]
+	fn sync_state_round_trips_through_kv_store() {
+		use bitcoin::hashes::Hash;
+		use lightning::util::test_utils::TestStore;
+
+		let kv_store = TestStore::new(false);
+		assert!(SyncState::read_from(&kv_store).unwrap().is_none());
+
+		let txid = Txid::from_byte_array([1; 32]);
+		let spending_txid = Txid::from_byte_array([2; 32]);
+		let block_hash = BlockHash::from_byte_array([3; 32]);
+		let watched_output = |vout: u16| WatchedOutput {
+			block_hash: Some(block_hash),
+			outpoint: lightning::chain::transaction::OutPoint { txid, index: vout },
+			script_pubkey: ScriptBuf::new(),
+		};
+
+		let mut sync_state = SyncState::new();
+		sync_state.watched_transactions.insert(txid);
+		sync_state.watched_scripts.insert(txid, ScriptBuf::new());
+		sync_state.watched_outputs.insert(OutPoint::new(txid, 0), watched_output(0));
+		sync_state.outputs_spends_pending_threshold_conf.push((
+			spending_txid,
+			100,
+			OutPoint::new(txid, 1),
+			watched_output(1),
+		));
+		sync_state.unconfirmed_output_spends.insert(OutPoint::new(txid, 0), spending_txid);
+		sync_state.output_tags.insert(OutPoint::new(txid, 0), 42);
+		sync_state.script_matches.insert(spending_txid, txid);
+		sync_state.last_sync_hash = Some(block_hash);
+		sync_state.last_sync_height = Some(102);
+		sync_state.pending_sync = true;
+		sync_state.write_to(&kv_store).unwrap();
+
+		let mut restored_state = SyncState::read_from(&kv_store).unwrap().unwrap();
+		assert_eq!(restored_state.watched_transactions, sync_state.watched_transactions);
+		assert_eq!(restored_state.watched_scripts, sync_state.watched_scripts);
+		assert!(restored_state.watched_outputs == sync_state.watched_outputs);
+		assert!(
+			restored_state.outputs_spends_pending_threshold_conf
+				== sync_state.outputs_spends_pending_threshold_conf
+		);
+		assert_eq!(restored_state.unconfirmed_output_spends, sync_state.unconfirmed_output_spends);
+		assert_eq!(restored_state.output_tags, sync_state.output_tags);
+		assert_eq!(restored_state.script_matches, sync_state.script_matches);
+		assert_eq!(restored_state.last_sync_hash, Some(block_hash));
+		assert_eq!(restored_state.last_sync_height, Some(102));
+		assert!(!restored_state.pending_sync);
+
+		// Registering items we already watch doesn't require us to sync again.
+		let mut queue = FilterQueue::new();
+		queue.register_tx(txid, None);
+		queue.register_output(watched_output(0));
+		assert!(!queue.process_queues(&mut restored_state));
+
+		queue.register_tx(spending_txid, None);
+		assert!(queue.process_queues(&mut restored_state));
+		assert!(restored_state.watched_transactions.contains(&spending_txid));
+	}
+
+	#[test]
+	fn sync_gives_up_once_max_restarts_are_exceeded() {
+		use bitcoin::hashes::Hash;
+		use lightning::util::test_utils::TestLogger;
+		use std::sync::atomic::{AtomicUsize, Ordering};
+
+		// A chain source whose tip changes on each of the first `num_flaps` requests.
+		struct FlappingChainSource {
+			num_flaps: usize,
+			num_tip_requests: AtomicUsize,
+		}
+
+		impl ChainSource for FlappingChainSource {
+			fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+				let num_requests = self.num_tip_requests.fetch_add(1, Ordering::SeqCst);
+				let mut hash_bytes = [0u8; 32];
+				hash_bytes[..8]
+					.copy_from_slice(&(num_requests.min(self.num_flaps) as u64).to_be_bytes());
+				Ok(BlockHash::from_byte_array(hash_bytes))
+			}
+
+			fn get_header(
+				&self, _block_hash: &BlockHash,
+			) -> Result<(Header, u32), ChainSourceError> {
+				Ok((genesis_block(Network::Regtest).header, 0))
+			}
+
+			fn is_block_in_best_chain(
+				&self, _block_hash: &BlockHash, _block_height: u32,
+			) -> Result<bool, ChainSourceError> {
+				Ok(true)
+			}
+
+			fn get_confirmed_transactions(
+				&self, _sync_state: &SyncState,
+			) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+				Ok(Vec::new())
+			}
+		}
+
+		let confirmables: Vec<&dyn Confirm> = Vec::new();
+		let queue = Mutex::new(FilterQueue::new());
+		let logger = TestLogger::new();
+
+		// We tolerate the tip changing up to the configured number of times.
+		let source = FlappingChainSource { num_flaps: 3, num_tip_requests: AtomicUsize::new(0) };
+		let mut sync_state = SyncState::new();
+		let metrics = MetricsCounters::new();
+		let stats = sync_with_chain_source(
+			&source,
+			&confirmables,
+			&mut sync_state,
+			&queue,
+			&metrics,
+			&&logger,
+			3,
+			1,
+		)
+		.unwrap();
+		assert_eq!(stats.num_restarts, 3);
+		assert_eq!(metrics.snapshot().total_reorgs, 3);
+		assert!(!sync_state.pending_sync);
+
+		// But bail out rather than spinning forever if it keeps changing.
+		let source =
+			FlappingChainSource { num_flaps: usize::MAX, num_tip_requests: AtomicUsize::new(0) };
+		let mut sync_state = SyncState::new();
+		let metrics = MetricsCounters::new();
+		match sync_with_chain_source(
+			&source,
+			&confirmables,
+			&mut sync_state,
+			&queue,
+			&metrics,
+			&&logger,
+			3,
+			1,
+		) {
+			Err(TxSyncError::MaxRestartsExceeded) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		assert_eq!(metrics.snapshot().total_reorgs, 4);
+		assert_eq!(source.num_tip_requests.load(Ordering::SeqCst), 5);
+
+		// We leave the sync pending, so that the next sync resumes cleanly.
+		assert!(sync_state.pending_sync);
+		assert!(sync_state.last_sync_hash.is_none());
+	}
+
+	#[test]
+	fn confirmations_are_withheld_until_min_confirmations() {
+		use bitcoin::hashes::Hash;
+		use lightning::chain::transaction::TransactionData;
+		use lightning::util::test_utils::TestLogger;
+
+		const CONF_HEIGHT: u32 = 100;
+
+		// A chain source reporting the genesis coinbase as confirmed at `CONF_HEIGHT`.
+		struct TestChainSource {
+			tip: Mutex<(BlockHash, u32)>,
+		}
+
+		impl ChainSource for TestChainSource {
+			fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+				Ok(self.tip.lock().unwrap().0)
+			}
+
+			fn get_header(
+				&self, _block_hash: &BlockHash,
+			) -> Result<(Header, u32), ChainSourceError> {
+				Ok((genesis_block(Network::Regtest).header, self.tip.lock().unwrap().1))
+			}
+
+			fn is_block_in_best_chain(
+				&self, _block_hash: &BlockHash, _block_height: u32,
+			) -> Result<bool, ChainSourceError> {
+				Ok(true)
+			}
+
+			fn get_confirmed_transactions(
+				&self, sync_state: &SyncState,
+			) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+				let block = genesis_block(Network::Regtest);
+				let tx = block.txdata[0].clone();
+				if !sync_state.watched_transactions.contains(&tx.txid()) {
+					return Ok(Vec::new());
+				}
+				let txid = tx.txid();
+				let block_header = block.header;
+				Ok(vec![ConfirmedTx { tx, txid, block_header, block_height: CONF_HEIGHT, pos: 0 }])
+			}
+		}
+
+		#[derive(Default)]
+		struct TestConfirmable {
+			confirmed_txs: Mutex<HashSet<Txid>>,
+			best_height: Mutex<u32>,
+		}
+
+		impl Confirm for TestConfirmable {
+			fn transactions_confirmed(
+				&self, _header: &Header, txdata: &TransactionData, _height: u32,
+			) {
+				self.confirmed_txs.lock().unwrap().extend(txdata.iter().map(|(_, tx)| tx.txid()));
+			}
+
+			fn transaction_unconfirmed(&self, txid: &Txid) {
+				self.confirmed_txs.lock().unwrap().remove(txid);
+			}
+
+			fn best_block_updated(&self, _header: &Header, height: u32) {
+				*self.best_height.lock().unwrap() = height;
+			}
+
+			fn get_relevant_txids(&self) -> Vec<(Txid, u32, Option<BlockHash>)> {
+				let block_hash = genesis_block(Network::Regtest).block_hash();
+				let confirmed_txs = self.confirmed_txs.lock().unwrap();
+				confirmed_txs.iter().map(|txid| (*txid, CONF_HEIGHT, Some(block_hash))).collect()
+			}
+		}
+
+		assert!(!has_min_confirmations(CONF_HEIGHT, CONF_HEIGHT + 1, 3));
+		assert!(has_min_confirmations(CONF_HEIGHT, CONF_HEIGHT + 2, 3));
+		assert!(has_min_confirmations(CONF_HEIGHT, CONF_HEIGHT, 0));
+
+		let txid = genesis_block(Network::Regtest).txdata[0].txid();
+		let tip = |n: u8, height: u32| (BlockHash::from_byte_array([n; 32]), height);
+		let source = TestChainSource { tip: Mutex::new(tip(1, CONF_HEIGHT + 1)) };
+		let confirmable = TestConfirmable::default();
+		let confirmables: Vec<&dyn Confirm> = vec![&confirmable];
+		let queue = Mutex::new(FilterQueue::new());
+		queue.lock().unwrap().register_tx(txid, None);
+		let mut sync_state = SyncState::new();
+		let metrics = MetricsCounters::new();
+		let logger = TestLogger::new();
+		let mut sync = || {
+			sync_with_chain_source(
+				&source,
+				&confirmables,
+				&mut sync_state,
+				&queue,
+				&metrics,
+				&&logger,
+				10,
+				3,
+			)
+			.unwrap()
+		};
+
+		// The confirmation is withheld at a depth of two, while the tip is still delivered.
+		let stats = sync();
+		assert_eq!(stats.num_confirmed, 0);
+		assert_eq!(*confirmable.best_height.lock().unwrap(), CONF_HEIGHT + 1);
+		assert!(confirmable.confirmed_txs.lock().unwrap().is_empty());
+
+		// And reported once it reached the required depth.
+		*source.tip.lock().unwrap() = tip(2, CONF_HEIGHT + 2);
+		let stats = sync();
+		assert_eq!(stats.num_confirmed, 1);
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains(&txid));
+
+		// A reorg shortening the chain has it drop back below the required depth.
+		*source.tip.lock().unwrap() = tip(3, CONF_HEIGHT + 1);
+		let stats = sync();
+		assert_eq!((stats.num_confirmed, stats.num_unconfirmed), (0, 1));
+		assert_eq!(*confirmable.best_height.lock().unwrap(), CONF_HEIGHT + 1);
+		assert!(confirmable.confirmed_txs.lock().unwrap().is_empty());
+
+		// Until the chain grows again.
+		*source.tip.lock().unwrap() = tip(4, CONF_HEIGHT + 2);
+		let stats = sync();
+		assert_eq!((stats.num_confirmed, stats.num_unconfirmed), (1, 0));
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains(&txid));
+	}
+
+	#[test]
+	fn confirmations_are_counted_by_convention() {
+		use crate::config::ConfirmationCounting;
+
+		// Both conventions counted off the same tip height.
+		let tip_height = 102;
+		assert_eq!(ConfirmationCounting::Inclusive.confirmations(102, tip_height), 1);
+		assert_eq!(ConfirmationCounting::FromNextBlock.confirmations(102, tip_height), 0);
+		assert_eq!(ConfirmationCounting::Inclusive.confirmations(100, tip_height), 3);
+		assert_eq!(ConfirmationCounting::FromNextBlock.confirmations(100, tip_height), 2);
+		assert_eq!(ConfirmationCounting::Inclusive.confirmations(0, tip_height), 103);
+		assert_eq!(ConfirmationCounting::FromNextBlock.confirmations(0, tip_height), 102);
+
+		// Transactions confirmed above the tip have no confirmations in either convention.
+		assert_eq!(ConfirmationCounting::Inclusive.confirmations(103, tip_height), 0);
+		assert_eq!(ConfirmationCounting::FromNextBlock.confirmations(103, tip_height), 0);
+
+		// The inclusive convention matches the depth we require via `min_confirmations`.
+		for confirmation_height in 95..=102 {
+			let confirmations =
+				ConfirmationCounting::Inclusive.confirmations(confirmation_height, tip_height);
+			assert!(has_min_confirmations(confirmation_height, tip_height, confirmations));
+			assert!(!has_min_confirmations(confirmation_height, tip_height, confirmations + 1));
+		}
+	}
+
+	#[test]
+	fn delivery_order_matches_sort_by_height_and_pos() {
+		use bitcoin::hashes::Hash;
+
+		let txid = |n: u8| Txid::from_byte_array([n; 32]);
+		let txs = [
+			(txid(1), 102, 3),
+			(txid(2), 101, 7),
+			(txid(3), 102, 1),
+			(txid(4), 100, 2),
+			(txid(5), 101, 0),
+			(txid(6), 102, 2),
+		];
+		assert_eq!(
+			expected_delivery_order(&txs),
+			vec![txid(4), txid(5), txid(2), txid(3), txid(6), txid(1)]
+		);
+		assert!(expected_delivery_order(&[]).is_empty());
+
+		// If requested, we deliver the newest confirmations first.
+		let mut descending_txs = txs;
+		sort_in_delivery_order(
+			&mut descending_txs,
+			DeliveryOrder::Descending,
+			|(_, height, pos)| (*height, *pos),
+		);
+		let descending_txids = descending_txs.iter().map(|(txid, _, _)| *txid).collect::<Vec<_>>();
+		assert_eq!(descending_txids, vec![txid(1), txid(6), txid(3), txid(2), txid(5), txid(4)]);
+	}
+}
@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+/// Configuration options for the transaction sync clients.
+///
+/// Use [`SyncConfig::default`] to get a configuration with sensible defaults.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncConfig {
+	/// The timeout applied to each individual request to the chain backend, in seconds.
+	///
+	/// This is given in whole seconds as the underlying Esplora clients don't support finer
+	/// grained timeouts. A value of `0` is treated like `1`.
+	///
+	/// Default value: 30
+	pub request_timeout_secs: u64,
+	/// The maximum number of times a request is retried after it failed with a transient error,
+	/// e.g., because the server is rate-limiting us or is temporarily unavailable.
+	///
+	/// Note that inconsistencies encountered during syncing, e.g., due to a reorg happening while
+	/// we sync, are not subject to this limit as they always lead to the sync being restarted.
+	///
+	/// The Esplora clients also apply this limit when the server announces a new chain tip it
+	/// can't serve the header for yet. Once exhausted, we sync against the previous tip instead
+	/// and catch up with the new one on the next sync.
+	///
+	/// Default value: 3
+	pub max_retries: u8,
+	/// The delay before retrying a failed request for the first time. The delay is doubled on each
+	/// subsequent retry of the same request.
+	///
+	/// Default value: 500 milliseconds.
+	pub backoff_base: Duration,
+	/// The maximum number of requests we issue to the chain backend concurrently.
+	///
+	/// Note that this currently only applies to the async Esplora client, which looks up the status
+	/// of registered transactions and the spending status of registered outputs in parallel.
+	///
+	/// Default value: 8
+	pub max_concurrent_requests: usize,
+	/// The maximum number of Merkle block requests we issue to the chain backend concurrently.
+	///
+	/// Each Merkle block request is followed by a request for the full transaction data, which
+	/// makes them considerably heavier for the backend than the status requests bounded by
+	/// [`Self::max_concurrent_requests`]. This allows to bound them separately, e.g., for backends
+	/// that handle lightweight queries well but struggle with proof requests.
+	///
+	/// Note that this currently only applies to the async Esplora client.
+	///
+	/// Default value: 8
+	pub max_concurrent_merkle_block_requests: usize,
+	/// Whether we check that confirmed transactions pay to the script they were registered with.
+	///
+	/// If enabled, we fail syncing if a registered transaction is reported as confirmed but
+	/// doesn't contain an output paying to the script given via [`Filter::register_tx`]. This
+	/// guards against misbehaving servers and integration bugs.
+	///
+	/// Note that this currently only applies to the Esplora clients.
+	///
+	/// Default value: false
+	///
+	/// [`Filter::register_tx`]: lightning::chain::Filter::register_tx
+	pub validate_scripts: bool,
+	/// Whether we check if unconfirmed spends of registered outputs were evicted from or replaced
+	/// in the mempool since the last sync.
+	///
+	/// If enabled, we keep track of any unconfirmed transactions spending registered outputs and
+	/// report those that were evicted or replaced by a conflicting spend via
+	/// [`SyncStats::replaced_transactions`]. This allows users to act on it, e.g., by re-bumping
+	/// the fee of an evicted CPFP transaction. Note that this requires an additional request per
+	/// registered output and sync.
+	///
+	/// Note that this currently only applies to the Esplora clients and has no effect if the server
+	/// doesn't expose mempool data.
+	///
+	/// Default value: false
+	///
+	/// [`SyncStats::replaced_transactions`]: crate::SyncStats::replaced_transactions
+	pub detect_mempool_replacements: bool,
+	/// The time after which we switch back to the primary server after failing over to a fallback
+	/// server.
+	///
+	/// Note that this currently only applies to Esplora clients configured with fallback servers,
+	/// and that we never switch back to the primary server on our own if the `time` feature is
+	/// disabled.
+	///
+	/// Default value: 10 minutes.
+	pub failover_cool_down: Duration,
+	/// The maximum number of times a single sync is restarted as the chain tip changed or an
+	/// inconsistency was encountered while syncing, e.g., due to a reorg.
+	///
+	/// Once exceeded, the sync fails with [`TxSyncError::MaxRestartsExceeded`], so that we don't
+	/// keep syncing forever against a chain backend whose view of the chain keeps changing.
+	///
+	/// Note that this currently only applies to the Esplora clients, while the other clients
+	/// always use the default value.
+	///
+	/// Default value: 10
+	///
+	/// [`TxSyncError::MaxRestartsExceeded`]: crate::TxSyncError::MaxRestartsExceeded
+	pub max_sync_restarts: u16,
+	/// The number of confirmations a transaction needs to reach before we report it as confirmed
+	/// via [`Confirm::transactions_confirmed`].
+	///
+	/// Until then, we keep watching the transaction and report it on a later sync once it reached
+	/// the required depth, while still delivering any new chain tip via
+	/// [`Confirm::best_block_updated`] on every sync. Transactions we already reported that drop
+	/// back below the required depth, e.g., as a reorg shortened the chain, are reported as
+	/// unconfirmed via [`Confirm::transaction_unconfirmed`]. This allows to have LDK only act on
+	/// transactions buried as deep as required elsewhere, e.g., by a wallet's own channel
+	/// acceptance policy. A value of `0` is treated like `1`.
+	///
+	/// The block including a transaction always counts as its first confirmation here,
+	/// independently of [`Self::confirmation_counting`].
+	///
+	/// Note that this currently only applies to the Esplora clients, while the other clients
+	/// always use the default value.
+	///
+	/// Default value: 1
+	///
+	/// [`Confirm::transactions_confirmed`]: lightning::chain::Confirm::transactions_confirmed
+	/// [`Confirm::best_block_updated`]: lightning::chain::Confirm::best_block_updated
+	/// [`Confirm::transaction_unconfirmed`]: lightning::chain::Confirm::transaction_unconfirmed
+	pub min_confirmations: u32,
+	/// The convention used when reporting the number of confirmations of a transaction, e.g., via
+	/// `EsploraSyncClient::confirmations_for`.
+	///
+	/// This allows to have displayed confirmation counts match what users expect from other
+	/// wallets and tools.
+	///
+	/// Note that this currently only applies to the Esplora clients.
+	///
+	/// Default value: [`ConfirmationCounting::Inclusive`]
+	pub confirmation_counting: ConfirmationCounting,
+	/// The order in which confirmed transactions are handed to [`Confirm::transactions_confirmed`]
+	/// during a sync.
+	///
+	/// **Caution:** This is an advanced, experimental option meant for custom reorg-recovery or
+	/// re-scan tooling that wants the newest confirmations to be applied first. The [`Confirm`]
+	/// interface generally expects transactions to be confirmed in the order they appear in the
+	/// chain, and LDK's own implementations, e.g., [`ChannelManager`] and [`ChainMonitor`], may
+	/// act on confirmations delivered in [`DeliveryOrder::Descending`] in unexpected ways, e.g.,
+	/// by failing to detect a spend of an output whose creating transaction they learn about
+	/// only afterwards. Do not change this unless all given confirmables are known to cope with it.
+	///
+	/// Note that this currently only applies to the Esplora clients.
+	///
+	/// Default value: [`DeliveryOrder::Ascending`]
+	///
+	/// [`Confirm`]: lightning::chain::Confirm
+	/// [`Confirm::transactions_confirmed`]: lightning::chain::Confirm::transactions_confirmed
+	/// [`ChannelManager`]: lightning::ln::channelmanager::ChannelManager
+	/// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
+	pub delivery_order: DeliveryOrder,
+	/// The rate at which we issue requests to the chain backend, or `None` if requests shouldn't
+	/// be rate-limited.
+	///
+	/// Public servers, e.g., blockstream.info or mempool.space, aggressively rate-limit clients
+	/// and respond with HTTP status 429 once a limit is exceeded, which can have the initial sync
+	/// of a node with many channels fail repeatedly. Setting a rate limit below the server's
+	/// allows to avoid this. Any requests exceeding the limit are delayed rather than failed,
+	/// including retries, broadcasts, and requests issued concurrently by the async client.
+	///
+	/// Note that this currently only applies to the Esplora clients, and that requests are never
+	/// rate-limited if the `time` feature is disabled.
+	///
+	/// Default value: None
+	pub rate_limit: Option<RateLimit>,
+}
+
+/// A limit on the rate at which requests are issued to the chain backend.
+///
+/// Requests are limited via a token bucket holding up to [`Self::burst`] tokens and refilling at
+/// [`Self::requests_per_second`], i.e., we may issue a burst of requests after being idle for a
+/// while, but stay within the given rate on average.
+///
+/// See [`SyncConfig::rate_limit`] for details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+	/// The number of requests we may issue per second on average. A value of `0` is treated like
+	/// `1`.
+	pub requests_per_second: u32,
+	/// The number of requests we may issue at once before the rate limit kicks in. A value of `0`
+	/// is treated like `1`.
+	pub burst: u32,
+}
+
+/// The order in which confirmed transactions are delivered to the [`Confirm`] interface.
+///
+/// See [`SyncConfig::delivery_order`] for details.
+///
+/// [`Confirm`]: lightning::chain::Confirm
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryOrder {
+	/// Transactions are delivered oldest-first, i.e., ordered by ascending confirmation height and
+	/// in-block position. This is what the [`Confirm`] interface expects.
+	///
+	/// [`Confirm`]: lightning::chain::Confirm
+	Ascending,
+	/// Transactions are delivered newest-first, i.e., ordered by descending confirmation height and
+	/// in-block position.
+	Descending,
+}
+
+/// The convention used to count the confirmations of a transaction.
+///
+/// See [`SyncConfig::confirmation_counting`] for details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfirmationCounting {
+	/// The block including the transaction counts as its first confirmation, i.e., a transaction
+	/// confirmed at height `h` has `tip_height - h + 1` confirmations. This is the convention used
+	/// by Bitcoin Core and LDK itself.
+	Inclusive,
+	/// Only the blocks built on top of the block including the transaction count as
+	/// confirmations, i.e., a transaction confirmed at height `h` has `tip_height - h`
+	/// confirmations.
+	FromNextBlock,
+}
+
+impl ConfirmationCounting {
+	/// Returns the number of confirmations of a transaction confirmed at `confirmation_height`
+	/// with the best chain's tip at `tip_height`, counted according to this convention.
+	///
+	/// Returns `0` if the transaction was confirmed above the given tip.
+	pub fn confirmations(&self, confirmation_height: u32, tip_height: u32) -> u32 {
+		if confirmation_height > tip_height {
+			return 0;
+		}
+		match self {
+			Self::Inclusive => tip_height - confirmation_height + 1,
+			Self::FromNextBlock => tip_height - confirmation_height,
+		}
+	}
+}
+
+impl Default for SyncConfig {
+	fn default() -> Self {
+		Self {
+			request_timeout_secs: 30,
+			max_retries: 3,
+			backoff_base: Duration::from_millis(500),
+			max_concurrent_requests: 8,
+			max_concurrent_merkle_block_requests: 8,
+			validate_scripts: false,
+			detect_mempool_replacements: false,
+			failover_cool_down: Duration::from_secs(600),
+			max_sync_restarts: 10,
+			min_confirmations: 1,
+			confirmation_counting: ConfirmationCounting::Inclusive,
+			delivery_order: DeliveryOrder::Ascending,
+			rate_limit: None,
+		}
+	}
+}
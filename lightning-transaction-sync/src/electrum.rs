@@ -1,25 +1,33 @@
-use crate::common::{ConfirmedTx, FilterQueue, SyncState};
-use crate::error::{InternalError, TxSyncError};
+use crate::common::{
+	check_genesis_block_hash, order_by_dependencies, restore_with_chain_source,
+	sort_in_delivery_order, sync_with_chain_source, validate_confirmed_tx, ChainSource,
+	ConfirmedTx, FilterQueue, LastSync, MerkleProof, SpentOutput, SyncState, WatchEvent,
+	WatchEventPublisher,
+};
+use crate::config::SyncConfig;
+use crate::error::{ChainSourceError, TxSyncError};
+use crate::metrics::{MetricsCounters, RequestKind, SyncMetrics, SyncMetricsHook, SyncStats};
 
 use electrum_client::Client as ElectrumClient;
 use electrum_client::ElectrumApi;
-use electrum_client::GetMerkleRes;
+use electrum_client::{Config as ElectrumConfig, ConfigBuilder as ElectrumConfigBuilder};
 
+use lightning::chain::chaininterface::BroadcasterInterface;
 use lightning::chain::WatchedOutput;
 use lightning::chain::{Confirm, Filter};
+use lightning::io;
 use lightning::util::logger::Logger;
-use lightning::{log_debug, log_error, log_trace};
+use lightning::util::persist::KVStore;
+use lightning::{log_error, log_trace};
 
 use bitcoin::block::Header;
-use bitcoin::hash_types::TxMerkleNode;
-use bitcoin::hashes::sha256d::Hash as Sha256d;
-use bitcoin::hashes::Hash;
-use bitcoin::{BlockHash, Script, Transaction, Txid};
+use bitcoin::{BlockHash, Network, OutPoint, Script, Transaction, Txid};
 
-use std::collections::HashSet;
 use std::ops::Deref;
-use std::sync::Mutex;
-use std::time::Instant;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "time")]
+use std::time::SystemTime;
 
 /// Synchronizes LDK with a given Electrum server.
 ///
@@ -30,6 +38,14 @@ use std::time::Instant;
 /// Note that registration via [`Filter`] needs to happen before any calls to
 /// [`Watch::watch_channel`] to ensure we get notified of the items to monitor.
 ///
+/// It may also be used as a [`BroadcasterInterface`], broadcasting transactions via the same
+/// server.
+///
+/// If created via [`ElectrumSyncClient::new_with_header_subscription`], the client keeps a
+/// subscription to new block headers and [`sync`] is a cheap no-op unless a new header or
+/// registration arrived since the last sync.
+///
+/// [`sync`]: Self::sync
 /// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
 /// [`Watch::watch_channel`]: lightning::chain::Watch::watch_channel
 /// [`Filter`]: lightning::chain::Filter
@@ -40,9 +56,23 @@ where
 	sync_state: Mutex<SyncState>,
 	queue: Mutex<FilterQueue>,
 	client: ElectrumClient,
+	metrics: MetricsCounters,
+	last_sync: Mutex<Option<LastSync>>,
+	pending_network_check: Mutex<Option<Network>>,
+	failed_broadcasts: Mutex<Vec<Transaction>>,
+	header_subscription: Option<Mutex<HeaderSubscription>>,
+	watch_events: Option<WatchEventPublisher>,
 	logger: L,
 }
 
+// The state of our subscription to new block headers, if we sync based on it.
+struct HeaderSubscription {
+	// Whether we're currently subscribed, i.e., whether we'd have been notified about any new block
+	// since the last sync. Reset whenever a request fails as the connection might have dropped,
+	// which also drops the subscription.
+	is_subscribed: bool,
+}
+
 impl<L: Deref> ElectrumSyncClient<L>
 where
 	L::Target: Logger,
@@ -57,14 +87,94 @@ where
 		Self::from_client(client, logger)
 	}
 
+	/// Returns a new [`ElectrumSyncClient`] object which syncs based on a subscription to new
+	/// block headers rather than by polling the chain tip.
+	///
+	/// Each [`sync`] only checks for any header notifications the server pushed since the last
+	/// sync, returning early if there are none and no new items were registered via [`Filter`].
+	/// If the connection to the server drops, we resubscribe on the next [`sync`], which is then
+	/// treated as a full pass over all watched items as we might have missed notifications.
+	///
+	/// [`sync`]: Self::sync
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn new_with_header_subscription(
+		server_url: String, logger: L,
+	) -> Result<Self, TxSyncError> {
+		let client = ElectrumClient::from_config(&server_url, header_subscription_config())
+			.map_err(|e| {
+				log_error!(logger, "Failed to connect to electrum server '{}': {}", server_url, e);
+				e
+			})?;
+
+		Self::from_client_with_header_subscription(client, logger)
+	}
+
 	/// Returns a new [`ElectrumSyncClient`] object using the given Electrum client.
 	///
 	/// This is not exported to bindings users as the underlying client from BDK is not exported.
 	pub fn from_client(client: ElectrumClient, logger: L) -> Result<Self, TxSyncError> {
+		Self::from_client_inner(client, None, logger)
+	}
+
+	/// Returns a new [`ElectrumSyncClient`] object using the given Electrum client which syncs based
+	/// on a subscription to new block headers. See
+	/// [`ElectrumSyncClient::new_with_header_subscription`] for details.
+	///
+	/// Note that the given client needs to be configured not to retry failed requests, e.g., via
+	/// [`ConfigBuilder::retry`]. Otherwise, it may transparently reconnect to the server, silently
+	/// dropping our subscription.
+	///
+	/// This is not exported to bindings users as the underlying client from BDK is not exported.
+	///
+	/// [`ConfigBuilder::retry`]: electrum_client::ConfigBuilder::retry
+	pub fn from_client_with_header_subscription(
+		client: ElectrumClient, logger: L,
+	) -> Result<Self, TxSyncError> {
+		let header_subscription = HeaderSubscription { is_subscribed: false };
+		Self::from_client_inner(client, Some(Mutex::new(header_subscription)), logger)
+	}
+
+	fn from_client_inner(
+		client: ElectrumClient, header_subscription: Option<Mutex<HeaderSubscription>>, logger: L,
+	) -> Result<Self, TxSyncError> {
 		let sync_state = Mutex::new(SyncState::new());
 		let queue = Mutex::new(FilterQueue::new());
+		let metrics = MetricsCounters::new();
+		let last_sync = Mutex::new(None);
+		let pending_network_check = Mutex::new(None);
+		let failed_broadcasts = Mutex::new(Vec::new());
+
+		Ok(Self {
+			sync_state,
+			queue,
+			client,
+			metrics,
+			last_sync,
+			pending_network_check,
+			failed_broadcasts,
+			header_subscription,
+			watch_events: None,
+			logger,
+		})
+	}
+
+	/// Sets a [`SyncMetricsHook`] that is informed about each request issued to the Electrum
+	/// server and about each completed sync.
+	pub fn with_metrics_hook(mut self, metrics_hook: Arc<dyn SyncMetricsHook>) -> Self {
+		self.metrics.set_hook(metrics_hook);
+		self
+	}
 
-		Ok(Self { sync_state, queue, client, logger })
+	/// Sets a [`Sender`] we publish a [`WatchEvent`] to for each change to the set of watched
+	/// transactions and outputs.
+	///
+	/// The events collected during a sync are published once it completed, whether successfully
+	/// or not, i.e., after the respective changes were committed to the sync state and any
+	/// confirmations were handed to the given confirmables. Events are dropped if the
+	/// corresponding receiver was dropped.
+	pub fn with_watch_event_sender(mut self, watch_event_sender: Sender<WatchEvent>) -> Self {
+		self.watch_events = Some(WatchEventPublisher::new(watch_event_sender));
+		self
 	}
 
 	/// Synchronizes the given `confirmables` via their [`Confirm`] interface implementations. This
@@ -79,189 +189,394 @@ where
 	/// [`ChannelManager`]: lightning::ln::channelmanager::ChannelManager
 	/// [`Filter`]: lightning::chain::Filter
 	pub fn sync<C: Deref>(&self, confirmables: Vec<C>) -> Result<(), TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		self.sync_with_stats(confirmables).map(|_| ())
+	}
+
+	/// Synchronizes the given `confirmables` just like [`ElectrumSyncClient::sync`], but returns
+	/// [`SyncStats`] describing the sync on success.
+	pub fn sync_with_stats<C: Deref>(&self, confirmables: Vec<C>) -> Result<SyncStats, TxSyncError>
 	where
 		C::Target: Confirm,
 	{
 		// This lock makes sure we're syncing once at a time.
 		let mut sync_state = self.sync_state.lock().unwrap();
 
-		log_trace!(self.logger, "Starting transaction sync.");
-		#[cfg(feature = "time")]
-		let start_time = Instant::now();
-		let mut num_confirmed = 0;
-		let mut num_unconfirmed = 0;
+		if let Some(watch_events) = &self.watch_events {
+			watch_events.prepare(&mut sync_state);
+		}
 
-		// Clear any header notifications we might have gotten to keep the queue count low.
-		while let Some(_) = self.client.block_headers_pop()? {}
+		let res = match self.header_subscription {
+			Some(ref header_subscription) => {
+				let mut header_subscription = header_subscription.lock().unwrap();
+				let res = self.sync_with_header_subscription(
+					&confirmables,
+					&mut sync_state,
+					&mut header_subscription,
+				);
+				if res.is_err() {
+					// The connection might have dropped, so make sure we resubscribe next time.
+					header_subscription.is_subscribed = false;
+				}
+				res
+			},
+			None => {
+				// Clear any header notifications we might have gotten to keep the queue count low.
+				while let Some(_) = self.client.block_headers_pop()? {}
+				self.sync_with_state(&confirmables, &mut sync_state)
+			},
+		};
 
-		let tip_notification = self.client.block_headers_subscribe()?;
-		let mut tip_header = tip_notification.header;
-		let mut tip_height = tip_notification.height as u32;
+		if let Some(watch_events) = &self.watch_events {
+			watch_events.publish(&mut sync_state);
+		}
 
-		loop {
-			let pending_registrations = self.queue.lock().unwrap().process_queues(&mut sync_state);
-			let tip_is_new = Some(tip_header.block_hash()) != sync_state.last_sync_hash;
+		if let Ok(stats) = &res {
+			self.metrics.record_sync_complete(stats);
+		}
+		res
+	}
 
-			// We loop until any registered transactions have been processed at least once, or the
-			// tip hasn't been updated during the last iteration.
-			if !sync_state.pending_sync && !pending_registrations && !tip_is_new {
-				// Nothing to do.
-				break;
-			} else {
-				// Update the known tip to the newest one.
-				if tip_is_new {
-					// First check for any unconfirmed transactions and act on it immediately.
-					match self.get_unconfirmed_transactions(&confirmables) {
-						Ok(unconfirmed_txs) => {
-							// Double-check the tip hash. If it changed, a reorg happened since
-							// we started syncing and we need to restart last-minute.
-							match self.check_update_tip(&mut tip_header, &mut tip_height) {
-								Ok(false) => {
-									num_unconfirmed += unconfirmed_txs.len();
-									sync_state.sync_unconfirmed_transactions(
-										&confirmables,
-										unconfirmed_txs,
-									);
-								},
-								Ok(true) => {
-									log_debug!(self.logger,
-										"Encountered inconsistency during transaction sync, restarting.");
-									sync_state.pending_sync = true;
-									continue;
-								},
-								Err(err) => {
-									// (Semi-)permanent failure, retry later.
-									log_error!(self.logger,
-										"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-										num_confirmed,
-										num_unconfirmed
-									);
-									sync_state.pending_sync = true;
-									return Err(TxSyncError::from(err));
-								},
-							}
-						},
-						Err(err) => {
-							// (Semi-)permanent failure, retry later.
-							log_error!(self.logger,
-								"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-								num_confirmed,
-								num_unconfirmed
-							);
-							sync_state.pending_sync = true;
-							return Err(TxSyncError::from(err));
-						},
-					}
+	fn sync_with_header_subscription<C: Deref>(
+		&self, confirmables: &Vec<C>, sync_state: &mut SyncState,
+		header_subscription: &mut HeaderSubscription,
+	) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		#[cfg(feature = "time")]
+		let start_time = std::time::Instant::now();
+
+		let has_new_header = if header_subscription.is_subscribed {
+			// Pinging the server has the client process any notifications the server pushed in the
+			// meantime.
+			self.metrics.track_request(RequestKind::Tip, || self.client.ping())?;
+			let mut has_new_header = false;
+			while let Some(_) = self.client.block_headers_pop()? {
+				has_new_header = true;
+			}
+			has_new_header
+		} else {
+			self.metrics
+				.track_request(RequestKind::Tip, || self.client.block_headers_subscribe())?;
+			while let Some(_) = self.client.block_headers_pop()? {}
+			header_subscription.is_subscribed = true;
+
+			// We might have missed notifications while we weren't subscribed, so check on all
+			// watched items.
+			log_trace!(self.logger, "Subscribed to block headers, conducting a full sync.");
+			sync_state.pending_sync = true;
+			true
+		};
+
+		let has_pending_registrations = self.queue.lock().unwrap().has_pending_registrations();
+		let has_pending_network_check = self.pending_network_check.lock().unwrap().is_some();
+		if !has_new_header && !has_pending_registrations && !has_pending_network_check {
+			if let (Some(tip_hash), Some(tip_height)) =
+				(sync_state.last_sync_hash, sync_state.last_sync_height)
+			{
+				log_trace!(self.logger, "No new block headers or registrations, skipping sync.");
+				*self.last_sync.lock().unwrap() = Some(LastSync::now(tip_hash, tip_height));
+				#[cfg(feature = "time")]
+				let duration = Some(start_time.elapsed());
+				#[cfg(not(feature = "time"))]
+				let duration = None;
+				return Ok(SyncStats {
+					num_confirmed: 0,
+					num_unconfirmed: 0,
+					tip_hash,
+					tip_height,
+					duration,
+					num_restarts: 0,
+					replaced_transactions: Vec::new(),
+				});
+			}
+		}
 
-					// Update the best block.
-					for c in &confirmables {
-						c.best_block_updated(&tip_header, tip_height);
-					}
+		self.sync_with_state(confirmables, sync_state)
+	}
 
-					// Prune any sufficiently confirmed output spends
-					sync_state.prune_output_spends(tip_height);
-				}
+	fn sync_with_state<C: Deref>(
+		&self, confirmables: &Vec<C>, sync_state: &mut SyncState,
+	) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		let pending_network_check = *self.pending_network_check.lock().unwrap();
+		if let Some(network) = pending_network_check {
+			let genesis_block_hash = self
+				.metrics
+				.track_request(RequestKind::BlockHeader, || self.client.block_header(0))?
+				.block_hash();
+			check_genesis_block_hash(network, genesis_block_hash, &self.logger)?;
+			*self.pending_network_check.lock().unwrap() = None;
+		}
 
-				match self.get_confirmed_transactions(&sync_state) {
-					Ok(confirmed_txs) => {
-						// Double-check the tip hash. If it changed, a reorg happened since
-						// we started syncing and we need to restart last-minute.
-						match self.check_update_tip(&mut tip_header, &mut tip_height) {
-							Ok(false) => {
-								num_confirmed += confirmed_txs.len();
-								sync_state
-									.sync_confirmed_transactions(&confirmables, confirmed_txs);
-							},
-							Ok(true) => {
-								log_debug!(self.logger,
-									"Encountered inconsistency during transaction sync, restarting.");
-								sync_state.pending_sync = true;
-								continue;
-							},
-							Err(err) => {
-								// (Semi-)permanent failure, retry later.
-								log_error!(self.logger,
-									"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-									num_confirmed,
-									num_unconfirmed
-								);
-								sync_state.pending_sync = true;
-								return Err(TxSyncError::from(err));
-							},
-						}
+		let stats = sync_with_chain_source(
+			self,
+			confirmables,
+			sync_state,
+			&self.queue,
+			&self.metrics,
+			&self.logger,
+			SyncConfig::default().max_sync_restarts,
+			SyncConfig::default().min_confirmations,
+		)?;
+		*self.last_sync.lock().unwrap() = Some(LastSync::now(stats.tip_hash, stats.tip_height));
+		Ok(stats)
+	}
+
+	fn get_confirmed_tx(
+		&self, txid: Txid, tx: &Transaction, prob_conf_height: u32,
+	) -> Result<ConfirmedTx, ChainSourceError> {
+		match self.metrics.track_request(RequestKind::MerkleProof, || {
+			self.client.transaction_get_merkle(&txid, prob_conf_height as usize)
+		}) {
+			Ok(merkle_res) => {
+				debug_assert_eq!(prob_conf_height, merkle_res.block_height as u32);
+				match self.metrics.track_request(RequestKind::BlockHeader, || {
+					self.client.block_header(prob_conf_height as usize)
+				}) {
+					Ok(block_header) => {
+						let proof =
+							MerkleProof::Branch { pos: merkle_res.pos, merkle: &merkle_res.merkle };
+						let pos =
+							validate_confirmed_tx(tx, txid, &block_header, proof, &self.logger)?;
+						let confirmed_tx = ConfirmedTx {
+							tx: tx.clone(),
+							txid,
+							block_header,
+							block_height: prob_conf_height,
+							pos,
+						};
+						Ok(confirmed_tx)
 					},
-					Err(InternalError::Inconsistency) => {
-						// Immediately restart syncing when we encounter any inconsistencies.
-						log_debug!(
+					Err(e) => {
+						log_error!(
 							self.logger,
-							"Encountered inconsistency during transaction sync, restarting."
-						);
-						sync_state.pending_sync = true;
-						continue;
-					},
-					Err(err) => {
-						// (Semi-)permanent failure, retry later.
-						log_error!(self.logger,
-							"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-							num_confirmed,
-							num_unconfirmed
+							"Failed to retrieve block header for height {}: {}.",
+							prob_conf_height,
+							e
 						);
-						sync_state.pending_sync = true;
-						return Err(TxSyncError::from(err));
+						Err(ChainSourceError::Failed)
 					},
 				}
-				sync_state.last_sync_hash = Some(tip_header.block_hash());
-				sync_state.pending_sync = false;
-			}
+			},
+			Err(e) => {
+				log_trace!(
+					self.logger,
+					"Inconsistency: Tx {} was unconfirmed during syncing: {}",
+					txid,
+					e
+				);
+				Err(ChainSourceError::Inconsistency)
+			},
 		}
-		#[cfg(feature = "time")]
-		log_debug!(
-			self.logger,
-			"Finished transaction sync at tip {} in {}ms: {} confirmed, {} unconfirmed.",
-			tip_header.block_hash(),
-			start_time.elapsed().as_millis(),
-			num_confirmed,
-			num_unconfirmed
-		);
-		#[cfg(not(feature = "time"))]
-		log_debug!(
-			self.logger,
-			"Finished transaction sync at tip {}: {} confirmed, {} unconfirmed.",
-			tip_header.block_hash(),
-			num_confirmed,
-			num_unconfirmed
-		);
-		Ok(())
 	}
 
-	fn check_update_tip(
-		&self, cur_tip_header: &mut Header, cur_tip_height: &mut u32,
-	) -> Result<bool, InternalError> {
-		let check_notification = self.client.block_headers_subscribe()?;
-		let check_tip_hash = check_notification.header.block_hash();
+	/// Returns the transactions we failed to broadcast via
+	/// [`BroadcasterInterface::broadcast_transactions`] since the last call to this method.
+	///
+	/// This allows to rebroadcast them later on, e.g., once the server is reachable again.
+	pub fn take_failed_broadcasts(&self) -> Vec<Transaction> {
+		core::mem::take(&mut *self.failed_broadcasts.lock().unwrap())
+	}
+
+	/// Stops monitoring the transaction with the given `txid`, e.g., once the channel it belongs
+	/// to has been fully resolved.
+	///
+	/// The transaction will be dropped from the set of watched items on the next call to
+	/// [`sync`], i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_tx(&self, txid: &Txid) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_tx(txid);
+	}
+
+	/// Registers the given output to be watched for spends just like [`Filter::register_output`],
+	/// associating it with the given opaque `tag`.
+	///
+	/// The tag is purely local and returned alongside the output once a spend of it is reported via
+	/// [`spent_outputs`], which allows to correlate it with application state.
+	///
+	/// [`Filter::register_output`]: lightning::chain::Filter::register_output
+	/// [`spent_outputs`]: Self::spent_outputs
+	pub fn register_output_with_tag(&self, output: WatchedOutput, tag: u64) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output_with_tag(output, tag);
+	}
+
+	/// Stops monitoring the given output for spends, e.g., once the channel it belongs to has
+	/// been fully resolved.
+	///
+	/// The output will be dropped from the set of watched items on the next call to [`sync`],
+	/// i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_output(&self, outpoint: &OutPoint) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_output(outpoint);
+	}
+
+	/// Returns the spends of watched outputs detected since the last call to this method, along
+	/// with the tag the respective output was registered with via [`register_output_with_tag`],
+	/// if any.
+	///
+	/// Note that a spend may be reported again if the spending transaction is reorged out and
+	/// confirmed again.
+	///
+	/// [`register_output_with_tag`]: Self::register_output_with_tag
+	pub fn spent_outputs(&self) -> Vec<SpentOutput> {
+		let mut sync_state = self.sync_state.lock().unwrap();
+		core::mem::take(&mut sync_state.spent_outputs)
+	}
+
+	/// Checks that the Electrum server is on the given network before syncing.
+	///
+	/// The check is conducted once, during the next call to [`sync`], which fails with
+	/// [`TxSyncError::WrongNetwork`] if the genesis block hash reported by the server doesn't match
+	/// the one of the given network. Once the check succeeded, it isn't repeated on subsequent
+	/// syncs.
+	///
+	/// [`sync`]: Self::sync
+	pub fn check_network(&self, network: Network) {
+		*self.pending_network_check.lock().unwrap() = Some(network);
+	}
+
+	/// Persists the current sync state, i.e., the watched transactions and outputs along with the
+	/// chain tip we last synced to, to the given [`KVStore`].
+	///
+	/// Restoring it via [`restore_sync_state`] after a restart allows us to avoid re-querying the
+	/// status of items we already watched when they are registered again via [`Filter`]. This
+	/// should therefore be called after each successful [`sync`].
+	///
+	/// [`restore_sync_state`]: Self::restore_sync_state
+	/// [`sync`]: Self::sync
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn persist_sync_state<K: Deref>(&self, kv_store: K) -> Result<(), io::Error>
+	where
+		K::Target: KVStore,
+	{
+		let sync_state = self.sync_state.lock().unwrap();
+		sync_state.write_to(&*kv_store)
+	}
+
+	/// Restores the sync state previously persisted via [`persist_sync_state`] from the given
+	/// [`KVStore`], returning whether it was restored.
+	///
+	/// The persisted state is only restored if the chain tip it was synced to is still part of the
+	/// best chain. Otherwise, e.g., if a reorg happened while we were offline, it is discarded and
+	/// the next [`sync`] will do a full resync.
+	///
+	/// Needs to be called before the first call to [`sync`] as it replaces the current state.
+	///
+	/// [`persist_sync_state`]: Self::persist_sync_state
+	/// [`sync`]: Self::sync
+	pub fn restore_sync_state<K: Deref>(&self, kv_store: K) -> Result<bool, TxSyncError>
+	where
+		K::Target: KVStore,
+	{
+		let mut sync_state = self.sync_state.lock().unwrap();
+		restore_with_chain_source(self, &*kv_store, &mut sync_state, &self.logger)
+	}
 
-		// Restart if either the tip changed or we got some divergent tip
-		// change notification since we started. In the latter case we
-		// make sure we clear the queue before continuing.
-		let mut restart_sync = check_tip_hash != cur_tip_header.block_hash();
+	/// Returns the metrics accumulated across all syncs so far.
+	pub fn metrics(&self) -> SyncMetrics {
+		self.metrics.snapshot()
+	}
+
+	/// Returns the hash and height of the chain tip the last successful [`sync`] synced to.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	pub fn last_synced_tip(&self) -> Option<(BlockHash, u32)> {
+		self.last_sync.lock().unwrap().map(|last_sync| (last_sync.tip_hash, last_sync.tip_height))
+	}
+
+	/// Returns the time at which the last successful [`sync`] completed.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	#[cfg(feature = "time")]
+	pub fn last_sync_completed_at(&self) -> Option<SystemTime> {
+		self.last_sync.lock().unwrap().map(|last_sync| last_sync.completed_at)
+	}
+
+	/// Returns a reference to the underlying Electrum client.
+	///
+	/// This is not exported to bindings users as the underlying client from BDK is not exported.
+	pub fn client(&self) -> &ElectrumClient {
+		&self.client
+	}
+}
+
+// The client configuration used when syncing based on a header subscription. We don't have the
+// client retry failed requests, as it would then transparently reconnect, silently dropping our
+// subscription.
+fn header_subscription_config() -> ElectrumConfig {
+	ElectrumConfigBuilder::new().retry(0).build()
+}
+
+impl<L: Deref> ChainSource for ElectrumSyncClient<L>
+where
+	L::Target: Logger,
+{
+	fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+		let tip_notification = self
+			.metrics
+			.track_request(RequestKind::Tip, || self.client.block_headers_subscribe())?;
+		let tip_hash = tip_notification.header.block_hash();
+
+		// Restart if we got some divergent tip change notification since we last checked. In this
+		// case we make sure we clear the queue before continuing.
+		let mut restart_sync = false;
 		while let Some(queued_notif) = self.client.block_headers_pop()? {
-			if queued_notif.header.block_hash() != check_tip_hash {
+			if queued_notif.header.block_hash() != tip_hash {
 				restart_sync = true
 			}
 		}
 
 		if restart_sync {
-			*cur_tip_header = check_notification.header;
-			*cur_tip_height = check_notification.height as u32;
-			Ok(true)
+			Err(ChainSourceError::Inconsistency)
 		} else {
-			Ok(false)
+			Ok(tip_hash)
 		}
 	}
 
+	fn get_header(&self, block_hash: &BlockHash) -> Result<(Header, u32), ChainSourceError> {
+		// As we can't look up headers by their hash, we retrieve the current tip, which is
+		// the only block the sync loop requests headers for.
+		let tip_notification = self
+			.metrics
+			.track_request(RequestKind::Tip, || self.client.block_headers_subscribe())?;
+		if tip_notification.header.block_hash() != *block_hash {
+			log_trace!(
+				self.logger,
+				"Inconsistency: Block {} isn't the tip of the best chain anymore.",
+				block_hash
+			);
+			return Err(ChainSourceError::Inconsistency);
+		}
+		Ok((tip_notification.header, tip_notification.height as u32))
+	}
+
+	fn is_block_in_best_chain(
+		&self, block_hash: &BlockHash, block_height: u32,
+	) -> Result<bool, ChainSourceError> {
+		let block_header = self.metrics.track_request(RequestKind::BlockHeader, || {
+			self.client.block_header(block_height as usize)
+		})?;
+		Ok(block_header.block_hash() == *block_hash)
+	}
+
 	fn get_confirmed_transactions(
 		&self, sync_state: &SyncState,
-	) -> Result<Vec<ConfirmedTx>, InternalError> {
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
 		// First, check the confirmation status of registered transactions as well as the
 		// status of dependent transactions of registered outputs.
 		let mut confirmed_txs: Vec<ConfirmedTx> = Vec::new();
@@ -271,7 +586,10 @@ where
 		let mut watched_txs = Vec::with_capacity(sync_state.watched_transactions.len());
 
 		for txid in &sync_state.watched_transactions {
-			match self.client.transaction_get(&txid) {
+			match self
+				.metrics
+				.track_request(RequestKind::Transaction, || self.client.transaction_get(&txid))
+			{
 				Ok(tx) => {
 					watched_txs.push((txid, tx.clone()));
 					if let Some(tx_out) = tx.output.first() {
@@ -282,7 +600,10 @@ where
 					} else {
 						debug_assert!(false, "Failed due to retrieving invalid tx data.");
 						log_error!(self.logger, "Failed due to retrieving invalid tx data.");
-						return Err(InternalError::Failed);
+						return Err(ChainSourceError::ServerMisbehaving(format!(
+							"Transaction {} doesn't have any outputs",
+							txid
+						)));
 					}
 				},
 				Err(electrum_client::Error::Protocol(_)) => {
@@ -290,7 +611,7 @@ where
 				},
 				Err(e) => {
 					log_error!(self.logger, "Failed to look up transaction {}: {}.", txid, e);
-					return Err(InternalError::Failed);
+					return Err(ChainSourceError::Failed);
 				},
 			}
 		}
@@ -305,8 +626,9 @@ where
 		let num_output_spend_lookups = watched_script_pubkeys.len() - num_tx_lookups;
 		debug_assert_eq!(num_output_spend_lookups, sync_state.watched_outputs.len());
 
-		match self.client.batch_script_get_history(watched_script_pubkeys.iter().map(|s| s.deref()))
-		{
+		match self.metrics.track_request(RequestKind::ScriptHistory, || {
+			self.client.batch_script_get_history(watched_script_pubkeys.iter().map(|s| s.deref()))
+		}) {
 			Ok(results) => {
 				let (tx_results, output_results) = results.split_at(num_tx_lookups);
 				debug_assert_eq!(num_output_spend_lookups, output_results.len());
@@ -320,7 +642,7 @@ where
 						script_history.iter().filter(|h| h.tx_hash == **txid);
 					if let Some(history) = filtered_history.next() {
 						let prob_conf_height = history.height as u32;
-						let confirmed_tx = self.get_confirmed_tx(tx, prob_conf_height)?;
+						let confirmed_tx = self.get_confirmed_tx(**txid, tx, prob_conf_height)?;
 						confirmed_txs.push(confirmed_tx);
 					}
 					debug_assert!(filtered_history.next().is_none());
@@ -329,6 +651,21 @@ where
 				for (watched_output, script_history) in
 					sync_state.watched_outputs.values().zip(output_results)
 				{
+					let watched_outpoint = watched_output.outpoint.into_bitcoin_outpoint();
+					if let Some(spending_tx) =
+						confirmed_txs.iter().find(|ctx| ctx.spends(&watched_outpoint))
+					{
+						// We already found a registered transaction spending this output
+						// confirmed, no need to look through the script history again.
+						log_trace!(
+							self.logger,
+							"Skipping spend lookup for output {} as it's spent by confirmed transaction {}",
+							watched_outpoint,
+							spending_tx.txid
+						);
+						continue;
+					}
+
 					for possible_output_spend in script_history {
 						if possible_output_spend.height <= 0 {
 							continue;
@@ -339,12 +676,12 @@ where
 							continue;
 						}
 
-						match self.client.transaction_get(&txid) {
+						match self.metrics.track_request(RequestKind::Transaction, || {
+							self.client.transaction_get(&txid)
+						}) {
 							Ok(tx) => {
 								let mut is_spend = false;
 								for txin in &tx.input {
-									let watched_outpoint =
-										watched_output.outpoint.into_bitcoin_outpoint();
 									if txin.previous_output == watched_outpoint {
 										is_spend = true;
 										break;
@@ -356,7 +693,8 @@ where
 								}
 
 								let prob_conf_height = possible_output_spend.height as u32;
-								let confirmed_tx = self.get_confirmed_tx(&tx, prob_conf_height)?;
+								let confirmed_tx =
+									self.get_confirmed_tx(txid, &tx, prob_conf_height)?;
 								confirmed_txs.push(confirmed_tx);
 							},
 							Err(e) => {
@@ -366,7 +704,7 @@ where
 									txid,
 									e
 								);
-								return Err(InternalError::Inconsistency);
+								return Err(ChainSourceError::Inconsistency);
 							},
 						}
 					}
@@ -374,130 +712,95 @@ where
 			},
 			Err(e) => {
 				log_error!(self.logger, "Failed to look up script histories: {}.", e);
-				return Err(InternalError::Failed);
+				return Err(ChainSourceError::Failed);
 			},
 		}
 
-		// Sort all confirmed transactions first by block height, then by in-block
-		// position, and finally feed them to the interface in order.
-		confirmed_txs.sort_unstable_by(|tx1, tx2| {
-			tx1.block_height.cmp(&tx2.block_height).then_with(|| tx1.pos.cmp(&tx2.pos))
-		});
-
-		Ok(confirmed_txs)
-	}
+		// Look for any other confirmed transactions paying to the scripts of watched transactions
+		// we didn't find confirmed, e.g., as a transaction with a different txid spending the same
+		// inputs confirmed instead.
+		let unconfirmed_watched_scripts = sync_state.unconfirmed_watched_scripts(&confirmed_txs);
+		if !unconfirmed_watched_scripts.is_empty() {
+			let results = match self.metrics.track_request(RequestKind::ScriptHistory, || {
+				self.client.batch_script_get_history(unconfirmed_watched_scripts.iter().copied())
+			}) {
+				Ok(results) => results,
+				Err(e) => {
+					log_error!(self.logger, "Failed to look up script histories: {}.", e);
+					return Err(ChainSourceError::Failed);
+				},
+			};
 
-	fn get_unconfirmed_transactions<C: Deref>(
-		&self, confirmables: &Vec<C>,
-	) -> Result<Vec<Txid>, InternalError>
-	where
-		C::Target: Confirm,
-	{
-		// Query the interface for relevant txids and check whether the relevant blocks are still
-		// in the best chain, mark them unconfirmed otherwise
-		let relevant_txids = confirmables
-			.iter()
-			.flat_map(|c| c.get_relevant_txids())
-			.collect::<HashSet<(Txid, u32, Option<BlockHash>)>>();
-
-		let mut unconfirmed_txs = Vec::new();
-
-		for (txid, conf_height, block_hash_opt) in relevant_txids {
-			if let Some(block_hash) = block_hash_opt {
-				let block_header = self.client.block_header(conf_height as usize)?;
-				if block_header.block_hash() == block_hash {
-					// Skip if the tx is still confirmed in the block in question.
-					continue;
-				}
+			for (script, script_history) in unconfirmed_watched_scripts.iter().zip(results) {
+				for history in script_history {
+					if history.height <= 0 {
+						continue;
+					}
 
-				unconfirmed_txs.push(txid);
-			} else {
-				log_error!(self.logger,
-					"Untracked confirmation of funding transaction. Please ensure none of your channels had been created with LDK prior to version 0.0.113!");
-				panic!("Untracked confirmation of funding transaction. Please ensure none of your channels had been created with LDK prior to version 0.0.113!");
-			}
-		}
-		Ok(unconfirmed_txs)
-	}
+					let txid = history.tx_hash;
+					if !sync_state.is_unreported_script_match(&txid, &confirmed_txs) {
+						continue;
+					}
 
-	fn get_confirmed_tx(
-		&self, tx: &Transaction, prob_conf_height: u32,
-	) -> Result<ConfirmedTx, InternalError> {
-		let txid = tx.txid();
-		match self.client.transaction_get_merkle(&txid, prob_conf_height as usize) {
-			Ok(merkle_res) => {
-				debug_assert_eq!(prob_conf_height, merkle_res.block_height as u32);
-				match self.client.block_header(prob_conf_height as usize) {
-					Ok(block_header) => {
-						let pos = merkle_res.pos;
-						if !self.validate_merkle_proof(
-							&txid,
-							&block_header.merkle_root,
-							merkle_res,
-						)? {
+					let tx = match self.metrics.track_request(RequestKind::Transaction, || {
+						self.client.transaction_get(&txid)
+					}) {
+						Ok(tx) => tx,
+						Err(e) => {
 							log_trace!(
 								self.logger,
-								"Inconsistency: Block {} was unconfirmed during syncing.",
-								block_header.block_hash()
+								"Inconsistency: Tx {} was unconfirmed during syncing: {}",
+								txid,
+								e
 							);
-							return Err(InternalError::Inconsistency);
-						}
-						let confirmed_tx = ConfirmedTx {
-							tx: tx.clone(),
-							txid,
-							block_header,
-							block_height: prob_conf_height,
-							pos,
-						};
-						Ok(confirmed_tx)
-					},
-					Err(e) => {
-						log_error!(
-							self.logger,
-							"Failed to retrieve block header for height {}: {}.",
-							prob_conf_height,
-							e
-						);
-						Err(InternalError::Failed)
-					},
+							return Err(ChainSourceError::Inconsistency);
+						},
+					};
+					if !tx.output.iter().any(|txout| txout.script_pubkey.as_script() == *script) {
+						// The transaction spends from the script rather than paying to it.
+						continue;
+					}
+
+					let confirmed_tx = self.get_confirmed_tx(txid, &tx, history.height as u32)?;
+					confirmed_txs.push(confirmed_tx);
 				}
-			},
-			Err(e) => {
-				log_trace!(
-					self.logger,
-					"Inconsistency: Tx {} was unconfirmed during syncing: {}",
-					txid,
-					e
-				);
-				Err(InternalError::Inconsistency)
-			},
+			}
 		}
-	}
 
-	/// Returns a reference to the underlying Electrum client.
-	///
-	/// This is not exported to bindings users as the underlying client from BDK is not exported.
-	pub fn client(&self) -> &ElectrumClient {
-		&self.client
+		// Sort all confirmed transactions first by block height, then by in-block
+		// position, and finally feed them to the interface in order.
+		sort_in_delivery_order(&mut confirmed_txs, SyncConfig::default().delivery_order, |ctx| {
+			(ctx.block_height, ctx.pos)
+		});
+
+		Ok(confirmed_txs)
 	}
+}
 
-	fn validate_merkle_proof(
-		&self, txid: &Txid, merkle_root: &TxMerkleNode, merkle_res: GetMerkleRes,
-	) -> Result<bool, InternalError> {
-		let mut index = merkle_res.pos;
-		let mut cur = txid.to_raw_hash();
-		for mut bytes in merkle_res.merkle {
-			bytes.reverse();
-			// unwrap() safety: `bytes` has len 32 so `from_slice` can never fail.
-			let next_hash = Sha256d::from_slice(&bytes).unwrap();
-			let (left, right) = if index % 2 == 0 { (cur, next_hash) } else { (next_hash, cur) };
-
-			let data = [&left[..], &right[..]].concat();
-			cur = Sha256d::hash(&data);
-			index /= 2;
+impl<L: Deref> BroadcasterInterface for ElectrumSyncClient<L>
+where
+	L::Target: Logger,
+{
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		// We broadcast packages one by one, parents first, as they'd otherwise get rejected.
+		let package = order_by_dependencies(txs);
+		let mut failed_idx = None;
+		for (idx, tx) in package.iter().enumerate() {
+			log_trace!(self.logger, "Broadcasting transaction {}", tx.txid());
+			let res = self
+				.metrics
+				.track_request(RequestKind::Broadcast, || self.client.transaction_broadcast(tx));
+			if let Err(e) = res {
+				log_error!(self.logger, "Failed to broadcast transaction {}: {}", tx.txid(), e);
+				failed_idx = Some(idx);
+				break;
+			}
 		}
 
-		Ok(cur == merkle_root.to_raw_hash())
+		// The remaining transactions might depend on the failed one, so we didn't try them.
+		if let Some(idx) = failed_idx {
+			self.failed_broadcasts.lock().unwrap().extend(package.into_iter().skip(idx));
+		}
 	}
 }
 
@@ -505,13 +808,13 @@ impl<L: Deref> Filter for ElectrumSyncClient<L>
 where
 	L::Target: Logger,
 {
-	fn register_tx(&self, txid: &Txid, _script_pubkey: &Script) {
+	fn register_tx(&self, txid: &Txid, script_pubkey: &Script) {
 		let mut locked_queue = self.queue.lock().unwrap();
-		locked_queue.transactions.insert(*txid);
+		locked_queue.register_tx(*txid, Some(script_pubkey.to_owned()));
 	}
 
 	fn register_output(&self, output: WatchedOutput) {
 		let mut locked_queue = self.queue.lock().unwrap();
-		locked_queue.outputs.insert(output.outpoint.into_bitcoin_outpoint(), output);
+		locked_queue.register_output(output);
 	}
 }
@@ -2,72 +2,248 @@ use std::fmt;
 
 #[derive(Debug)]
 /// An error that possibly needs to be handled by the user.
+///
+/// Note that [`TxSyncError::Failed`], [`TxSyncError::Connection`], [`TxSyncError::HttpStatus`],
+/// and [`TxSyncError::Timeout`] are usually transient, i.e., syncing may simply be retried later
+/// on, while the remaining variants indicate a misconfiguration or a misbehaving chain backend and
+/// are unlikely to resolve themselves.
 pub enum TxSyncError {
 	/// A transaction sync failed and needs to be retried eventually.
 	Failed,
+	/// A transaction sync failed as we couldn't connect to the chain backend, e.g., because it or
+	/// a configured proxy is unreachable. Syncing needs to be retried eventually.
+	Connection {
+		/// The error we encountered trying to connect.
+		source: Box<dyn std::error::Error + Send + Sync + 'static>,
+	},
+	/// A transaction sync failed as the chain backend responded with the given unexpected HTTP
+	/// status code, e.g., because it is rate-limiting us or is temporarily unavailable.
+	HttpStatus(u16),
+	/// A transaction sync failed as the chain backend returned data that doesn't match what we
+	/// requested, e.g., a transaction with a different txid. This should not happen and indicates
+	/// the backend is faulty or malicious.
+	ServerMisbehaving(String),
+	/// The chain backend is not on the network we expected it to be on.
+	WrongNetwork,
+	/// The chain backend doesn't expose any mempool data, e.g., as its mempool endpoints were
+	/// disabled, so the requested operation can't be conducted. Syncing confirmed data is still
+	/// supported.
+	MempoolUnsupported,
+	/// A transaction sync was restarted more than [`SyncConfig::max_sync_restarts`] times as the
+	/// chain kept changing while we synced, e.g., due to a reorg storm or a flapping or malicious
+	/// chain backend. Syncing may be retried later on and will resume from scratch.
+	///
+	/// [`SyncConfig::max_sync_restarts`]: crate::SyncConfig::max_sync_restarts
+	MaxRestartsExceeded,
+	/// A transaction sync was aborted as it didn't complete before the deadline given to
+	/// `EsploraSyncClient::sync_with_deadline`. Any progress made so far was retained and syncing
+	/// needs to be retried to complete it.
+	Timeout,
 }
 
-impl std::error::Error for TxSyncError {}
+impl std::error::Error for TxSyncError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Connection { source } => Some(&**source),
+			_ => None,
+		}
+	}
+}
 
 impl fmt::Display for TxSyncError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		match *self {
+		match self {
 			Self::Failed => write!(f, "Failed to conduct transaction sync."),
+			Self::Connection { source } => {
+				write!(
+					f,
+					"Failed to conduct transaction sync as we couldn't connect to the backend: {}",
+					source
+				)
+			},
+			Self::HttpStatus(status) => {
+				write!(
+					f,
+					"Failed to conduct transaction sync as the backend responded with HTTP status {}.",
+					status
+				)
+			},
+			Self::ServerMisbehaving(details) => {
+				write!(
+					f,
+					"Failed to conduct transaction sync as the backend misbehaved: {}",
+					details
+				)
+			},
+			Self::WrongNetwork => {
+				write!(
+					f,
+					"Failed to conduct transaction sync as the backend is on the wrong network."
+				)
+			},
+			Self::MempoolUnsupported => {
+				write!(f, "The backend doesn't support retrieving mempool data.")
+			},
+			Self::MaxRestartsExceeded => {
+				write!(
+					f,
+					"Failed to conduct transaction sync as the chain kept changing while we synced."
+				)
+			},
+			Self::Timeout => {
+				write!(f, "Failed to complete transaction sync before the deadline.")
+			},
 		}
 	}
 }
 
+/// An error returned by a [`ChainSource`].
+///
+/// Apart from [`ChainSourceError::Inconsistency`], which has the current sync be restarted, any
+/// error aborts the current sync, which then fails with the corresponding [`TxSyncError`].
+///
+/// [`ChainSource`]: crate::ChainSource
 #[derive(Debug)]
-pub(crate) enum InternalError {
+pub enum ChainSourceError {
 	/// A transaction sync failed and needs to be retried eventually.
 	Failed,
-	/// An inconsistency was encountered during transaction sync.
+	/// An inconsistency was encountered during transaction sync, e.g., as the chain changed while
+	/// we were syncing.
 	Inconsistency,
+	/// We couldn't connect to the chain backend.
+	Connection(Box<dyn std::error::Error + Send + Sync + 'static>),
+	/// The chain backend responded with an unexpected HTTP status code.
+	HttpStatus(u16),
+	/// The chain backend returned data that doesn't match what we requested.
+	ServerMisbehaving(String),
+	/// The deadline of the current sync passed.
+	Timeout,
 }
 
-impl fmt::Display for InternalError {
+impl fmt::Display for ChainSourceError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		match *self {
+		match self {
 			Self::Failed => write!(f, "Failed to conduct transaction sync."),
 			Self::Inconsistency => {
 				write!(f, "Encountered an inconsistency during transaction sync.")
 			},
+			Self::Connection(e) => {
+				write!(f, "Failed to connect to the backend during transaction sync: {}", e)
+			},
+			Self::HttpStatus(status) => {
+				write!(
+					f,
+					"The backend responded with HTTP status {} during transaction sync.",
+					status
+				)
+			},
+			Self::ServerMisbehaving(details) => {
+				write!(f, "The backend misbehaved during transaction sync: {}", details)
+			},
+			Self::Timeout => write!(f, "Reached the deadline during transaction sync."),
 		}
 	}
 }
 
-impl std::error::Error for InternalError {}
+impl std::error::Error for ChainSourceError {}
 
-impl From<InternalError> for TxSyncError {
-	fn from(_e: InternalError) -> Self {
-		Self::Failed
+impl From<ChainSourceError> for TxSyncError {
+	fn from(e: ChainSourceError) -> Self {
+		match e {
+			ChainSourceError::Failed | ChainSourceError::Inconsistency => Self::Failed,
+			ChainSourceError::Connection(source) => Self::Connection { source },
+			ChainSourceError::HttpStatus(status) => Self::HttpStatus(status),
+			ChainSourceError::ServerMisbehaving(details) => Self::ServerMisbehaving(details),
+			ChainSourceError::Timeout => Self::Timeout,
+		}
 	}
 }
 
 #[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
 impl From<esplora_client::Error> for TxSyncError {
-	fn from(_e: esplora_client::Error) -> Self {
-		Self::Failed
+	fn from(e: esplora_client::Error) -> Self {
+		ChainSourceError::from(e).into()
 	}
 }
 
 #[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
-impl From<esplora_client::Error> for InternalError {
-	fn from(_e: esplora_client::Error) -> Self {
-		Self::Failed
+impl From<esplora_client::Error> for ChainSourceError {
+	fn from(e: esplora_client::Error) -> Self {
+		match e {
+			esplora_client::Error::HttpResponse { status, .. } => Self::HttpStatus(status),
+			e if is_esplora_connection_error(&e) => Self::Connection(Box::new(e)),
+			_ => Self::Failed,
+		}
+	}
+}
+
+// Returns whether the given error indicates that we couldn't reach the Esplora server, either
+// directly or via the configured proxy.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+fn is_esplora_connection_error(e: &esplora_client::Error) -> bool {
+	match e {
+		#[cfg(not(feature = "async-interface"))]
+		esplora_client::Error::Minreq(e) => {
+			matches!(e, minreq::Error::IoError(_) | minreq::Error::ProxyConnect)
+		},
+		#[cfg(feature = "async-interface")]
+		esplora_client::Error::Reqwest(e) => e.is_connect() || e.is_timeout(),
+		_ => false,
 	}
 }
 
 #[cfg(feature = "electrum")]
-impl From<electrum_client::Error> for InternalError {
-	fn from(_e: electrum_client::Error) -> Self {
-		Self::Failed
+impl From<electrum_client::Error> for ChainSourceError {
+	fn from(e: electrum_client::Error) -> Self {
+		if is_electrum_connection_error(&e) {
+			Self::Connection(Box::new(e))
+		} else {
+			Self::Failed
+		}
 	}
 }
 
 #[cfg(feature = "electrum")]
 impl From<electrum_client::Error> for TxSyncError {
-	fn from(_e: electrum_client::Error) -> Self {
+	fn from(e: electrum_client::Error) -> Self {
+		ChainSourceError::from(e).into()
+	}
+}
+
+// Returns whether the given error indicates that we couldn't reach the Electrum server.
+#[cfg(feature = "electrum")]
+fn is_electrum_connection_error(e: &electrum_client::Error) -> bool {
+	match e {
+		electrum_client::Error::IOError(_) | electrum_client::Error::SharedIOError(_) => true,
+		electrum_client::Error::AllAttemptsErrored(errors) => {
+			!errors.is_empty() && errors.iter().all(is_electrum_connection_error)
+		},
+		_ => false,
+	}
+}
+
+#[cfg(feature = "bitcoind-rpc")]
+impl From<bitcoincore_rpc::Error> for ChainSourceError {
+	fn from(_e: bitcoincore_rpc::Error) -> Self {
+		Self::Failed
+	}
+}
+
+#[cfg(feature = "bitcoind-rpc")]
+impl From<bitcoincore_rpc::Error> for TxSyncError {
+	fn from(_e: bitcoincore_rpc::Error) -> Self {
 		Self::Failed
 	}
 }
+
+impl From<TxSyncError> for ChainSourceError {
+	fn from(e: TxSyncError) -> Self {
+		match e {
+			TxSyncError::Connection { source } => Self::Connection(source),
+			TxSyncError::HttpStatus(status) => Self::HttpStatus(status),
+			TxSyncError::ServerMisbehaving(details) => Self::ServerMisbehaving(details),
+			TxSyncError::Timeout => Self::Timeout,
+			_ => Self::Failed,
+		}
+	}
+}
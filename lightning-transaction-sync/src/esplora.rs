@@ -1,21 +1,299 @@
-use crate::common::{ConfirmedTx, FilterQueue, SyncState};
-use crate::error::{InternalError, TxSyncError};
+#[cfg(not(feature = "async-interface"))]
+use crate::common::ChainSource;
+#[cfg(feature = "time")]
+use crate::common::RateLimiter;
+use crate::common::{
+	check_genesis_block_hash, order_by_dependencies, sort_in_delivery_order, validate_confirmed_tx,
+	ConfirmedTx, FilterQueue, LastSync, MerkleProof, SpentOutput, SyncState, WatchEvent,
+	WatchEventPublisher, WatchedOutputs, WatchedTransactions,
+};
+use crate::config::SyncConfig;
+use crate::error::{ChainSourceError, TxSyncError};
+use crate::metrics::{MetricsCounters, RequestKind, SyncMetrics, SyncMetricsHook, SyncStats};
 
+use lightning::chain::chaininterface::BroadcasterInterface;
 use lightning::chain::WatchedOutput;
 use lightning::chain::{Confirm, Filter};
+use lightning::io;
 use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
 use lightning::{log_debug, log_error, log_trace};
 
-use bitcoin::{BlockHash, Script, Txid};
+use bitcoin::block::Header;
+use bitcoin::{BlockHash, Network, OutPoint, Script, Transaction, Txid};
 
 #[cfg(not(feature = "async-interface"))]
 use esplora_client::blocking::BlockingClient;
 #[cfg(feature = "async-interface")]
 use esplora_client::r#async::AsyncClient;
-use esplora_client::Builder;
+use esplora_client::{Builder, OutputStatus, TxStatus};
 
+use core::fmt;
 use core::ops::Deref;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "time")]
+use std::time::{Instant, SystemTime};
+
+// The number of confirmed transactions the Esplora server returns per page of a script's history.
+const ESPLORA_SCRIPT_HISTORY_PAGE_SIZE: usize = 25;
+
+// Retries the given client call with exponential backoff as long as its error is classified as
+// retriable and we haven't exhausted the configured number of retries, evaluating to a
+// `Result<_, ChainSourceError>`. Each attempt is subject to the configured rate limit, if any, and
+// is reported to the metrics hook as a request of the given kind.
+macro_rules! retry_transient {
+	($self: expr, $kind: expr, $call: expr) => {{
+		let mut num_retries = 0;
+		loop {
+			#[cfg(feature = "time")]
+			if let Err(e) = $self.check_deadline(Duration::ZERO) {
+				break Err(e);
+			}
+			if let Err(e) = maybe_await!($self.wait_for_rate_limit()) {
+				break Err(e);
+			}
+			let timer = $self.metrics.start_request();
+			let res = maybe_await!($call);
+			$self.metrics.finish_request($kind, timer, res.is_ok());
+			let e = match res {
+				Ok(res) => break Ok(res),
+				Err(e) => e,
+			};
+			match ($self.error_classifier)(&e) {
+				ErrorDisposition::Retriable if num_retries < $self.config.max_retries => {
+					let backoff = $self
+						.config
+						.backoff_base
+						.saturating_mul(2u32.saturating_pow(num_retries as u32));
+					log_debug!(
+						$self.logger,
+						"Encountered transient error during transaction sync, retrying in {}ms: {}",
+						backoff.as_millis(),
+						e
+					);
+					#[cfg(feature = "time")]
+					if let Err(e) = $self.check_deadline(backoff) {
+						break Err(e);
+					}
+					#[cfg(not(feature = "async-interface"))]
+					std::thread::sleep(backoff);
+					#[cfg(feature = "async-interface")]
+					tokio::time::sleep(backoff).await;
+					num_retries += 1;
+				},
+				ErrorDisposition::Inconsistency => {
+					log_trace!($self.logger, "Inconsistency: Encountered error {}", e);
+					break Err(ChainSourceError::Inconsistency);
+				},
+				ErrorDisposition::Retriable => {
+					// We exhausted our retries, so we switch to the next server, if any.
+					$self.fail_over();
+					break Err(ChainSourceError::from(e));
+				},
+				ErrorDisposition::Fatal => break Err(ChainSourceError::from(e)),
+			}
+		}
+	}};
+}
+
+/// Describes how an error returned by the underlying Esplora client is handled during syncing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorDisposition {
+	/// The error is likely to resolve itself, e.g., as we're being rate-limited. The request is
+	/// retried up to [`SyncConfig::max_retries`] times before we give up, failing over to the next
+	/// server if any fallback servers are configured.
+	Retriable,
+	/// The error is not going to resolve itself by retrying the request. The sync is aborted.
+	Fatal,
+	/// The error indicates that the chain data changed while we were syncing, e.g., due to a
+	/// reorg. The sync is restarted.
+	Inconsistency,
+}
+
+/// A function deciding how errors returned by the underlying Esplora client are handled during
+/// syncing.
+///
+/// See [`default_error_classifier`] for the classification used by default.
+///
+/// This is not exported to bindings users as the underlying client from BDK is not exported.
+pub type ErrorClassifier =
+	Arc<dyn Fn(&esplora_client::Error) -> ErrorDisposition + Send + Sync + 'static>;
+
+/// The [`ErrorClassifier`] used by default.
+///
+/// Treats connection errors, rate limiting (i.e., HTTP status 429), and server errors (i.e., HTTP
+/// status 5xx) as [`ErrorDisposition::Retriable`] and any other errors as
+/// [`ErrorDisposition::Fatal`]. Custom classifiers may fall back to this one for any errors they
+/// don't want to treat differently.
+///
+/// This is not exported to bindings users as the underlying client from BDK is not exported.
+pub fn default_error_classifier(e: &esplora_client::Error) -> ErrorDisposition {
+	match e {
+		#[cfg(not(feature = "async-interface"))]
+		esplora_client::Error::Minreq(_) => ErrorDisposition::Retriable,
+		#[cfg(feature = "async-interface")]
+		esplora_client::Error::Reqwest(_) => ErrorDisposition::Retriable,
+		esplora_client::Error::HttpResponse { status, .. } if *status == 429 || *status >= 500 => {
+			ErrorDisposition::Retriable
+		},
+		_ => ErrorDisposition::Fatal,
+	}
+}
+
+/// A builder for an [`EsploraSyncClient`], allowing to route requests through a proxy, to set a
+/// custom [`SyncConfig`], to configure fallback servers, to customize the handling of errors, to
+/// set a [`SyncMetricsHook`], and to subscribe to [`WatchEvent`]s.
+#[derive(Clone)]
+pub struct EsploraSyncClientBuilder {
+	server_url: String,
+	fallback_server_urls: Vec<String>,
+	proxy_url: Option<String>,
+	config: SyncConfig,
+	error_classifier: Option<ErrorClassifier>,
+	metrics_hook: Option<Arc<dyn SyncMetricsHook>>,
+	watch_event_sender: Option<Sender<WatchEvent>>,
+}
+
+impl fmt::Debug for EsploraSyncClientBuilder {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("EsploraSyncClientBuilder")
+			.field("server_url", &self.server_url)
+			.field("fallback_server_urls", &self.fallback_server_urls)
+			.field("proxy_url", &self.proxy_url)
+			.field("config", &self.config)
+			.field("custom_error_classifier", &self.error_classifier.is_some())
+			.field("metrics_hook", &self.metrics_hook.is_some())
+			.field("watch_event_sender", &self.watch_event_sender.is_some())
+			.finish()
+	}
+}
+
+impl EsploraSyncClientBuilder {
+	/// Returns a new builder for an [`EsploraSyncClient`] connecting to the given server, using
+	/// the default [`SyncConfig`] and no proxy.
+	pub fn new(server_url: String) -> Self {
+		Self {
+			server_url,
+			fallback_server_urls: Vec::new(),
+			proxy_url: None,
+			config: SyncConfig::default(),
+			error_classifier: None,
+			metrics_hook: None,
+			watch_event_sender: None,
+		}
+	}
+
+	/// Sets the servers to fail over to, in order, if the primary server keeps failing with
+	/// transient errors, e.g., as it is down or rate-limiting us.
+	///
+	/// We switch to the next server once a request failed [`SyncConfig::max_retries`] times with
+	/// an error classified as [`ErrorDisposition::Retriable`]. If this happens during a sync, the
+	/// sync is restarted from scratch against the new server, so that we never mix data retrieved
+	/// from different servers, and each server is tried at most once per sync. After
+	/// [`SyncConfig::failover_cool_down`] passed, we switch back to the primary server at the start
+	/// of the next sync.
+	///
+	/// Before syncing against any server for the first time, we check it reports the same genesis
+	/// block hash as the servers we already used, failing with [`TxSyncError::WrongNetwork`]
+	/// otherwise.
+	pub fn fallback_servers(mut self, fallback_server_urls: Vec<String>) -> Self {
+		self.fallback_server_urls = fallback_server_urls;
+		self
+	}
+
+	/// Routes all requests through the proxy at the given URL.
+	///
+	/// Note that the supported proxy URL formats and protocols differ slightly between the
+	/// blocking and async clients, see the documentation of `esplora_client::Builder::proxy`.
+	pub fn proxy(mut self, proxy_url: String) -> Self {
+		self.proxy_url = Some(proxy_url);
+		self
+	}
+
+	/// Sets the [`SyncConfig`] to use.
+	pub fn config(mut self, config: SyncConfig) -> Self {
+		self.config = config;
+		self
+	}
+
+	/// Sets the timeout applied to each individual request, in seconds, overriding the
+	/// [`SyncConfig::request_timeout_secs`] previously set.
+	pub fn request_timeout_secs(mut self, request_timeout_secs: u64) -> Self {
+		self.config.request_timeout_secs = request_timeout_secs;
+		self
+	}
+
+	/// Sets a custom [`ErrorClassifier`] deciding how errors returned by the Esplora server are
+	/// handled, e.g., to retry requests failing with a particular gateway error.
+	///
+	/// If not set, the [`default_error_classifier`] is used.
+	///
+	/// This is not exported to bindings users as the underlying client from BDK is not exported.
+	pub fn error_classifier<F>(mut self, error_classifier: F) -> Self
+	where
+		F: Fn(&esplora_client::Error) -> ErrorDisposition + Send + Sync + 'static,
+	{
+		self.error_classifier = Some(Arc::new(error_classifier));
+		self
+	}
+
+	/// Sets a [`SyncMetricsHook`] that is informed about each request issued to the Esplora
+	/// servers and about each completed sync.
+	pub fn metrics_hook(mut self, metrics_hook: Arc<dyn SyncMetricsHook>) -> Self {
+		self.metrics_hook = Some(metrics_hook);
+		self
+	}
+
+	/// Sets a [`Sender`] the [`EsploraSyncClient`] publishes a [`WatchEvent`] to for each change to
+	/// the set of watched transactions and outputs.
+	///
+	/// The events collected during a sync are published once it completed, whether successfully
+	/// or not, i.e., after the respective changes were committed to the sync state and any
+	/// confirmations were handed to the given confirmables. Events are dropped if the
+	/// corresponding receiver was dropped.
+	pub fn watch_event_sender(mut self, watch_event_sender: Sender<WatchEvent>) -> Self {
+		self.watch_event_sender = Some(watch_event_sender);
+		self
+	}
+
+	/// Builds the [`EsploraSyncClient`].
+	///
+	/// Fails if any of the underlying clients can't be built, e.g., due to an invalid proxy URL
+	/// when using the async client variant.
+	pub fn build<L: Deref>(self, logger: L) -> Result<EsploraSyncClient<L>, TxSyncError>
+	where
+		L::Target: Logger,
+	{
+		let clients = core::iter::once(&self.server_url)
+			.chain(self.fallback_server_urls.iter())
+			.map(|server_url| {
+				build_client(
+					server_url,
+					self.proxy_url.as_deref(),
+					self.config.request_timeout_secs,
+				)
+				.map_err(|e| {
+					log_error!(logger, "Failed to build Esplora client for {}: {}", server_url, e);
+					TxSyncError::from(ChainSourceError::from(e))
+				})
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let mut sync_client = EsploraSyncClient::from_clients(clients, self.config, logger);
+		sync_client.proxy_url = self.proxy_url;
+		if let Some(error_classifier) = self.error_classifier {
+			sync_client.error_classifier = error_classifier;
+		}
+		if let Some(metrics_hook) = self.metrics_hook {
+			sync_client.metrics.set_hook(metrics_hook);
+		}
+		sync_client.watch_events = self.watch_event_sender.map(WatchEventPublisher::new);
+		Ok(sync_client)
+	}
+}
 
 /// Synchronizes LDK with a given [`Esplora`] server.
 ///
@@ -29,6 +307,10 @@ use std::collections::HashSet;
 /// This uses and exposes either a blocking or async client variant dependent on whether the
 /// `esplora-blocking` or the `esplora-async` feature is enabled.
 ///
+/// It may also be used as a [`BroadcasterInterface`], broadcasting transactions via the same
+/// server. Note that with the `esplora-async` feature, transactions are only queued and
+/// `process_broadcast_queue` needs to be called regularly to actually broadcast them.
+///
 /// [`Esplora`]: https://github.com/Blockstream/electrs
 /// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
 /// [`Watch::watch_channel`]: lightning::chain::Watch::watch_channel
@@ -39,7 +321,32 @@ where
 {
 	sync_state: MutexType<SyncState>,
 	queue: std::sync::Mutex<FilterQueue>,
-	client: EsploraClientType,
+	// The primary client, followed by the clients for any fallback servers. The primary client may
+	// be replaced via `set_server_url` while we hold the `sync_state` lock.
+	clients: std::sync::RwLock<Vec<Arc<EsploraClientType>>>,
+	// The proxy any clients we build ourselves route their requests through, if any.
+	proxy_url: Option<String>,
+	servers: std::sync::Mutex<ServerState>,
+	config: SyncConfig,
+	error_classifier: ErrorClassifier,
+	metrics: MetricsCounters,
+	last_sync: std::sync::Mutex<Option<LastSync>>,
+	pending_network_check: std::sync::Mutex<Option<Network>>,
+	// The most recent tip whose header the server was able to serve us.
+	last_available_tip: std::sync::Mutex<Option<BlockHash>>,
+	// The deadline of the sync currently in progress, if any. Requests issued after it passed fail
+	// with `ChainSourceError::Timeout`.
+	sync_deadline: std::sync::Mutex<Option<std::time::Instant>>,
+	// Whether the blocks we looked up during the current sync pass are in the best chain and at
+	// which height, so that we fetch each block's status at most once per pass. Only set while a
+	// sync pass is in progress and discarded whenever it's restarted.
+	block_statuses: std::sync::Mutex<Option<HashMap<BlockHash, (bool, Option<u32>)>>>,
+	#[cfg(feature = "time")]
+	rate_limiter: Option<RateLimiter>,
+	watch_events: Option<WatchEventPublisher>,
+	#[cfg(feature = "async-interface")]
+	pending_broadcasts: std::sync::Mutex<Vec<Vec<Transaction>>>,
+	failed_broadcasts: std::sync::Mutex<Vec<Transaction>>,
 	logger: L,
 }
 
@@ -47,24 +354,78 @@ impl<L: Deref> EsploraSyncClient<L>
 where
 	L::Target: Logger,
 {
-	/// Returns a new [`EsploraSyncClient`] object.
+	/// Returns a new [`EsploraSyncClient`] object using the default [`SyncConfig`].
+	///
+	/// Panics if the async client variant is used and the underlying client can't be built.
 	pub fn new(server_url: String, logger: L) -> Self {
-		let builder = Builder::new(&server_url);
-		#[cfg(not(feature = "async-interface"))]
-		let client = builder.build_blocking();
-		#[cfg(feature = "async-interface")]
-		let client = builder.build_async().unwrap();
+		Self::new_with_config(server_url, SyncConfig::default(), logger)
+	}
 
-		EsploraSyncClient::from_client(client, logger)
+	/// Returns a new [`EsploraSyncClient`] object using the given [`SyncConfig`].
+	///
+	/// Panics if the async client variant is used and the underlying client can't be built.
+	pub fn new_with_config(server_url: String, config: SyncConfig, logger: L) -> Self {
+		EsploraSyncClientBuilder::new(server_url).config(config).build(logger).unwrap()
 	}
 
 	/// Returns a new [`EsploraSyncClient`] object using the given Esplora client.
 	///
 	/// This is not exported to bindings users as the underlying client from BDK is not exported.
 	pub fn from_client(client: EsploraClientType, logger: L) -> Self {
+		Self::from_client_with_config(client, SyncConfig::default(), logger)
+	}
+
+	/// Returns a new [`EsploraSyncClient`] object using the given Esplora client and
+	/// [`SyncConfig`].
+	///
+	/// Note that the [`SyncConfig::request_timeout_secs`] is not applied to the given client.
+	///
+	/// This is not exported to bindings users as the underlying client from BDK is not exported.
+	pub fn from_client_with_config(
+		client: EsploraClientType, config: SyncConfig, logger: L,
+	) -> Self {
+		Self::from_clients(vec![client], config, logger)
+	}
+
+	fn from_clients(clients: Vec<EsploraClientType>, config: SyncConfig, logger: L) -> Self {
 		let sync_state = MutexType::new(SyncState::new());
 		let queue = std::sync::Mutex::new(FilterQueue::new());
-		Self { sync_state, queue, client, logger }
+		let servers = std::sync::Mutex::new(ServerState::new(clients.len()));
+		let clients = std::sync::RwLock::new(clients.into_iter().map(Arc::new).collect());
+		let error_classifier = Arc::new(default_error_classifier);
+		let metrics = MetricsCounters::new();
+		let last_sync = std::sync::Mutex::new(None);
+		let pending_network_check = std::sync::Mutex::new(None);
+		let last_available_tip = std::sync::Mutex::new(None);
+		let sync_deadline = std::sync::Mutex::new(None);
+		let block_statuses = std::sync::Mutex::new(None);
+		#[cfg(feature = "time")]
+		let rate_limiter = config.rate_limit.map(RateLimiter::new);
+		#[cfg(feature = "async-interface")]
+		let pending_broadcasts = std::sync::Mutex::new(Vec::new());
+		let failed_broadcasts = std::sync::Mutex::new(Vec::new());
+		Self {
+			sync_state,
+			queue,
+			clients,
+			proxy_url: None,
+			servers,
+			config,
+			error_classifier,
+			metrics,
+			last_sync,
+			pending_network_check,
+			last_available_tip,
+			sync_deadline,
+			block_statuses,
+			#[cfg(feature = "time")]
+			rate_limiter,
+			watch_events: None,
+			#[cfg(feature = "async-interface")]
+			pending_broadcasts,
+			failed_broadcasts,
+			logger,
+		}
 	}
 
 	/// Synchronizes the given `confirmables` via their [`Confirm`] interface implementations. This
@@ -80,6 +441,50 @@ where
 	/// [`Filter`]: lightning::chain::Filter
 	#[maybe_async]
 	pub fn sync<C: Deref>(&self, confirmables: Vec<C>) -> Result<(), TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		maybe_await!(self.sync_with_stats(confirmables)).map(|_| ())
+	}
+
+	/// Synchronizes the given `confirmables` just like [`EsploraSyncClient::sync`], but returns
+	/// [`SyncStats`] describing the sync on success.
+	#[maybe_async]
+	pub fn sync_with_stats<C: Deref>(&self, confirmables: Vec<C>) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		maybe_await!(self.sync_until(confirmables, None))
+	}
+
+	/// Synchronizes the given `confirmables` just like [`EsploraSyncClient::sync_with_stats`], but
+	/// gives up once the given deadline passed.
+	///
+	/// The deadline is checked before each request to the Esplora server and before backing off
+	/// to retry a failed request, so a single request may still exceed it by up to
+	/// [`SyncConfig::request_timeout_secs`]. Once it passed, the sync is aborted with
+	/// [`TxSyncError::Timeout`]. Any transactions already handed to the `confirmables` remain
+	/// (un)confirmed, and the next sync picks up any remaining work.
+	///
+	/// This allows to bound the time a sync may take, e.g., if the server is slow, without having
+	/// to drop a sync in progress. Note that the deadline also applies to any requests issued via
+	/// this client from other tasks while the sync is in progress.
+	#[cfg(feature = "time")]
+	#[maybe_async]
+	pub fn sync_with_deadline<C: Deref>(
+		&self, confirmables: Vec<C>, deadline: Instant,
+	) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		maybe_await!(self.sync_until(confirmables, Some(deadline)))
+	}
+
+	// Synchronizes the given `confirmables`, giving up once the given deadline passed, if any.
+	#[maybe_async]
+	fn sync_until<C: Deref>(
+		&self, confirmables: Vec<C>, deadline: Option<std::time::Instant>,
+	) -> Result<SyncStats, TxSyncError>
 	where
 		C::Target: Confirm,
 	{
@@ -89,258 +494,751 @@ where
 		#[cfg(feature = "async-interface")]
 		let mut sync_state = self.sync_state.lock().await;
 
-		log_trace!(self.logger, "Starting transaction sync.");
-		#[cfg(feature = "time")]
-		let start_time = std::time::Instant::now();
-		let mut num_confirmed = 0;
-		let mut num_unconfirmed = 0;
+		if let Some(watch_events) = &self.watch_events {
+			watch_events.prepare(&mut sync_state);
+		}
 
-		let mut tip_hash = maybe_await!(self.client.get_tip_hash())?;
+		*self.sync_deadline.lock().unwrap() = deadline;
+		let res = maybe_await!(self.sync_with_failover(&confirmables, &mut sync_state));
+		*self.sync_deadline.lock().unwrap() = None;
 
-		loop {
-			let pending_registrations = self.queue.lock().unwrap().process_queues(&mut sync_state);
-			let tip_is_new = Some(tip_hash) != sync_state.last_sync_hash;
+		if let Some(watch_events) = &self.watch_events {
+			watch_events.publish(&mut sync_state);
+		}
 
-			// We loop until any registered transactions have been processed at least once, or the
-			// tip hasn't been updated during the last iteration.
-			if !sync_state.pending_sync && !pending_registrations && !tip_is_new {
-				// Nothing to do.
-				break;
-			} else {
-				// Update the known tip to the newest one.
-				if tip_is_new {
-					// First check for any unconfirmed transactions and act on it immediately.
-					match maybe_await!(self.get_unconfirmed_transactions(&confirmables)) {
-						Ok(unconfirmed_txs) => {
-							// Double-check the tip hash. If it changed, a reorg happened since
-							// we started syncing and we need to restart last-minute.
-							match maybe_await!(self.client.get_tip_hash()) {
-								Ok(check_tip_hash) => {
-									if check_tip_hash != tip_hash {
-										tip_hash = check_tip_hash;
-
-										log_debug!(self.logger, "Encountered inconsistency during transaction sync, restarting.");
-										sync_state.pending_sync = true;
-										continue;
-									}
-									num_unconfirmed += unconfirmed_txs.len();
-									sync_state.sync_unconfirmed_transactions(
-										&confirmables,
-										unconfirmed_txs,
-									);
-								},
-								Err(err) => {
-									// (Semi-)permanent failure, retry later.
-									log_error!(self.logger,
-										"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-										num_confirmed,
-										num_unconfirmed
-										);
-									sync_state.pending_sync = true;
-									return Err(TxSyncError::from(err));
-								},
-							}
-						},
-						Err(err) => {
-							// (Semi-)permanent failure, retry later.
-							log_error!(self.logger,
-								"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-								num_confirmed,
-								num_unconfirmed
-							);
-							sync_state.pending_sync = true;
-							return Err(TxSyncError::from(err));
-						},
-					}
+		if let Err(TxSyncError::Timeout) = res {
+			// Make sure the next sync picks up where we left off.
+			sync_state.pending_sync = true;
+		}
+		res
+	}
 
-					match maybe_await!(self.sync_best_block_updated(
-						&confirmables,
-						&mut sync_state,
-						&tip_hash
-					)) {
-						Ok(()) => {},
-						Err(InternalError::Inconsistency) => {
-							// Immediately restart syncing when we encounter any inconsistencies.
-							log_debug!(
-								self.logger,
-								"Encountered inconsistency during transaction sync, restarting."
-							);
-							sync_state.pending_sync = true;
-							continue;
-						},
-						Err(err) => {
-							// (Semi-)permanent failure, retry later.
-							log_error!(self.logger,
-								"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-								num_confirmed,
-								num_unconfirmed
-							);
-							sync_state.pending_sync = true;
-							return Err(TxSyncError::from(err));
-						},
-					}
-				}
+	// Syncs against the active server, failing over to the next server if necessary.
+	#[maybe_async]
+	fn sync_with_failover<C: Deref>(
+		&self, confirmables: &Vec<C>, sync_state: &mut SyncState,
+	) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		self.maybe_switch_back_to_primary();
 
-				match maybe_await!(self.get_confirmed_transactions(&sync_state)) {
-					Ok(confirmed_txs) => {
-						// Double-check the tip hash. If it changed, a reorg happened since
-						// we started syncing and we need to restart last-minute.
-						match maybe_await!(self.client.get_tip_hash()) {
-							Ok(check_tip_hash) => {
-								if check_tip_hash != tip_hash {
-									tip_hash = check_tip_hash;
-
-									log_debug!(self.logger,
-										"Encountered inconsistency during transaction sync, restarting.");
-									sync_state.pending_sync = true;
-									continue;
-								}
-								num_confirmed += confirmed_txs.len();
-								sync_state
-									.sync_confirmed_transactions(&confirmables, confirmed_txs);
-							},
-							Err(err) => {
-								// (Semi-)permanent failure, retry later.
-								log_error!(self.logger,
-									"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-									num_confirmed,
-									num_unconfirmed
-								);
-								sync_state.pending_sync = true;
-								return Err(TxSyncError::from(err));
-							},
-						}
-					},
-					Err(InternalError::Inconsistency) => {
-						// Immediately restart syncing when we encounter any inconsistencies.
-						log_debug!(
-							self.logger,
-							"Encountered inconsistency during transaction sync, restarting."
-						);
-						sync_state.pending_sync = true;
-						continue;
-					},
-					Err(err) => {
-						// (Semi-)permanent failure, retry later.
-						log_error!(self.logger,
-							"Failed during transaction sync, aborting. Synced so far: {} confirmed, {} unconfirmed.",
-							num_confirmed,
-							num_unconfirmed
-						);
-						sync_state.pending_sync = true;
-						return Err(TxSyncError::from(err));
-					},
-				}
-				sync_state.last_sync_hash = Some(tip_hash);
-				sync_state.pending_sync = false;
+		let mut num_failovers = 0;
+		loop {
+			self.servers.lock().unwrap().failed_over = false;
+			let res = match maybe_await!(self.check_active_server()) {
+				Ok(()) => {
+					*self.block_statuses.lock().unwrap() = Some(HashMap::new());
+					let res = maybe_await!(self.sync_with_active_server(confirmables, sync_state));
+					*self.block_statuses.lock().unwrap() = None;
+					res
+				},
+				Err(e) => Err(e),
+			};
+			match res {
+				Ok(stats) => {
+					*self.last_sync.lock().unwrap() =
+						Some(LastSync::now(stats.tip_hash, stats.tip_height));
+					self.metrics.record_sync_complete(&stats);
+					return Ok(stats);
+				},
+				Err(e) => {
+					// If we failed over to another server, we restart the sync from scratch against
+					// it, unless we already tried all of them.
+					let failed_over = self.servers.lock().unwrap().failed_over;
+					if !failed_over || num_failovers + 1 >= self.num_servers() {
+						return Err(e);
+					}
+					num_failovers += 1;
+					sync_state.pending_sync = true;
+					log_debug!(self.logger, "Restarting transaction sync after failing over.");
+				},
 			}
 		}
-		#[cfg(feature = "time")]
-		log_debug!(
+	}
+
+	// Conducts a single pass of syncing against the currently active server.
+	#[maybe_async]
+	fn sync_with_active_server<C: Deref>(
+		&self, confirmables: &Vec<C>, sync_state: &mut SyncState,
+	) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		#[cfg(not(feature = "async-interface"))]
+		let stats = sync_confirmables!(
+			self,
+			*confirmables,
+			*sync_state,
+			self.queue,
+			self.metrics,
 			self.logger,
-			"Finished transaction sync at tip {} in {}ms: {} confirmed, {} unconfirmed.",
-			tip_hash,
-			start_time.elapsed().as_millis(),
-			num_confirmed,
-			num_unconfirmed
+			self.config.max_sync_restarts,
+			self.config.min_confirmations;
 		);
-		#[cfg(not(feature = "time"))]
-		log_debug!(
+		#[cfg(feature = "async-interface")]
+		let stats = sync_confirmables!(
+			self,
+			*confirmables,
+			*sync_state,
+			self.queue,
+			self.metrics,
 			self.logger,
-			"Finished transaction sync at tip {}: {} confirmed, {} unconfirmed.",
-			tip_hash,
-			num_confirmed,
-			num_unconfirmed
+			self.config.max_sync_restarts,
+			self.config.min_confirmations;
+			.await
 		);
+		Ok(stats)
+	}
+
+	// Checks the currently active server is on the expected network before we sync against it.
+	#[maybe_async]
+	fn check_active_server(&self) -> Result<(), TxSyncError> {
+		let pending_network_check = *self.pending_network_check.lock().unwrap();
+		if let Some(network) = pending_network_check {
+			let genesis_block_hash =
+				retry_transient!(self, RequestKind::BlockHeader, self.client().get_block_hash(0))?;
+			check_genesis_block_hash(network, genesis_block_hash, &self.logger)?;
+			*self.pending_network_check.lock().unwrap() = None;
+			let mut locked_servers = self.servers.lock().unwrap();
+			let active_idx = locked_servers.active_idx;
+			locked_servers.genesis_block_hashes[active_idx] = Some(genesis_block_hash);
+		}
+
+		if self.num_servers() == 1 {
+			return Ok(());
+		}
+
+		let active_idx = self.servers.lock().unwrap().active_idx;
+		if self.servers.lock().unwrap().genesis_block_hashes[active_idx].is_some() {
+			return Ok(());
+		}
+
+		// Make sure all servers agree on the genesis block hash, i.e., are on the same network.
+		let genesis_block_hash =
+			retry_transient!(self, RequestKind::BlockHeader, self.client().get_block_hash(0))?;
+		let mut locked_servers = self.servers.lock().unwrap();
+		let expected_genesis_block_hash =
+			locked_servers.genesis_block_hashes.iter().flatten().next().copied();
+		match expected_genesis_block_hash {
+			Some(expected) if expected != genesis_block_hash => {
+				log_error!(
+					self.logger,
+					"Esplora server {} reported genesis block hash {}, expected {}.",
+					active_idx,
+					genesis_block_hash,
+					expected
+				);
+				drop(locked_servers);
+				self.fail_over();
+				Err(TxSyncError::WrongNetwork)
+			},
+			_ => {
+				locked_servers.genesis_block_hashes[active_idx] = Some(genesis_block_hash);
+				Ok(())
+			},
+		}
+	}
+
+	// Switches to the next server, if any, unless we already did so during the current sync pass.
+	fn fail_over(&self) {
+		let num_servers = self.num_servers();
+		if num_servers == 1 {
+			return;
+		}
+		let mut locked_servers = self.servers.lock().unwrap();
+		if locked_servers.failed_over {
+			return;
+		}
+		let prev_idx = locked_servers.active_idx;
+		locked_servers.active_idx = (prev_idx + 1) % num_servers;
+		locked_servers.failed_over = true;
+		#[cfg(feature = "time")]
+		{
+			locked_servers.failed_over_at = Some(Instant::now());
+		}
+		log_error!(
+			self.logger,
+			"Failing over from Esplora server {} to server {}.",
+			prev_idx,
+			locked_servers.active_idx
+		);
+	}
+
+	fn num_servers(&self) -> usize {
+		self.clients.read().unwrap().len()
+	}
+
+	// Fails with `ChainSourceError::Timeout` if the deadline of the current sync, if any, passes
+	// within the given delay, e.g., as we'd back off for it before retrying a request.
+	#[cfg(feature = "time")]
+	fn check_deadline(&self, delay: Duration) -> Result<(), ChainSourceError> {
+		let deadline = *self.sync_deadline.lock().unwrap();
+		if let Some(deadline) = deadline {
+			if Instant::now() + delay >= deadline {
+				log_debug!(self.logger, "Reached the deadline of the current transaction sync.");
+				return Err(ChainSourceError::Timeout);
+			}
+		}
 		Ok(())
 	}
 
+	// Waits until the configured rate limit, if any, allows us to issue another request. Fails with
+	// `ChainSourceError::Timeout` if we'd need to wait beyond the deadline of the current sync.
 	#[maybe_async]
-	fn sync_best_block_updated<C: Deref>(
-		&self, confirmables: &Vec<C>, sync_state: &mut SyncState, tip_hash: &BlockHash,
-	) -> Result<(), InternalError>
-	where
-		C::Target: Confirm,
-	{
-		// Inform the interface of the new block.
-		let tip_header = maybe_await!(self.client.get_header_by_hash(tip_hash))?;
-		let tip_status = maybe_await!(self.client.get_block_status(&tip_hash))?;
-		if tip_status.in_best_chain {
-			if let Some(tip_height) = tip_status.height {
-				for c in confirmables {
-					c.best_block_updated(&tip_header, tip_height);
+	fn wait_for_rate_limit(&self) -> Result<(), ChainSourceError> {
+		#[cfg(feature = "time")]
+		if let Some(rate_limiter) = &self.rate_limiter {
+			let delay = rate_limiter.reserve();
+			if !delay.is_zero() {
+				log_trace!(
+					self.logger,
+					"Delaying request to Esplora server by {}ms to stay within the rate limit.",
+					delay.as_millis()
+				);
+				self.check_deadline(delay)?;
+				#[cfg(not(feature = "async-interface"))]
+				std::thread::sleep(delay);
+				#[cfg(feature = "async-interface")]
+				tokio::time::sleep(delay).await;
+			}
+		}
+		Ok(())
+	}
+
+	// Switches back to the primary server if the cool-down after failing over has passed.
+	fn maybe_switch_back_to_primary(&self) {
+		#[cfg(feature = "time")]
+		{
+			let mut locked_servers = self.servers.lock().unwrap();
+			if locked_servers.active_idx == 0 {
+				return;
+			}
+			let cool_down_passed = locked_servers
+				.failed_over_at
+				.map_or(true, |t| t.elapsed() >= self.config.failover_cool_down);
+			if cool_down_passed {
+				log_debug!(
+					self.logger,
+					"Switching back from Esplora server {} to the primary server.",
+					locked_servers.active_idx
+				);
+				locked_servers.active_idx = 0;
+				locked_servers.failed_over_at = None;
+			}
+		}
+	}
+
+	#[maybe_async]
+	fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+		let tip_hash = retry_transient!(self, RequestKind::Tip, self.client().get_tip_hash())?;
+		maybe_await!(self.get_available_tip(tip_hash))
+	}
+
+	// Returns the given tip if the server is able to serve its header.
+	//
+	// Servers behind caches or load balancers may announce a new tip before they're able to serve
+	// the corresponding block data. In this case, we retry a bounded number of times and, if the
+	// header still isn't available, fall back to the previous tip we know to be available, as
+	// long as it is still part of the best chain. We therefore never paper over a reorg, but
+	// simply catch up with the new tip on the next sync.
+	#[maybe_async]
+	fn get_available_tip(&self, tip_hash: BlockHash) -> Result<BlockHash, ChainSourceError> {
+		let prev_tip_hash = *self.last_available_tip.lock().unwrap();
+		if prev_tip_hash == Some(tip_hash) {
+			return Ok(tip_hash);
+		}
+
+		let mut num_retries = 0;
+		loop {
+			match retry_transient!(
+				self,
+				RequestKind::BlockHeader,
+				self.client().get_header_by_hash(&tip_hash)
+			) {
+				Ok(_) => {
+					*self.last_available_tip.lock().unwrap() = Some(tip_hash);
+					return Ok(tip_hash);
+				},
+				Err(ChainSourceError::HttpStatus(404)) if num_retries < self.config.max_retries => {
+					let backoff = self
+						.config
+						.backoff_base
+						.saturating_mul(2u32.saturating_pow(num_retries as u32));
+					log_debug!(
+						self.logger,
+						"Header of new tip {} is not available yet, retrying in {}ms.",
+						tip_hash,
+						backoff.as_millis()
+					);
+					#[cfg(feature = "time")]
+					self.check_deadline(backoff)?;
+					#[cfg(not(feature = "async-interface"))]
+					std::thread::sleep(backoff);
+					#[cfg(feature = "async-interface")]
+					tokio::time::sleep(backoff).await;
+					num_retries += 1;
+				},
+				Err(ChainSourceError::HttpStatus(404)) => break,
+				Err(e) => return Err(e),
+			}
+		}
+
+		match prev_tip_hash {
+			Some(prev_tip_hash) => {
+				if !maybe_await!(self.is_block_in_best_chain(&prev_tip_hash, 0))? {
+					log_trace!(
+						self.logger,
+						"Inconsistency: Previous tip {} is not part of the best chain anymore",
+						prev_tip_hash
+					);
+					return Err(ChainSourceError::Inconsistency);
 				}
+				log_debug!(
+					self.logger,
+					"Header of new tip {} is still not available, syncing to previous tip {} instead.",
+					tip_hash,
+					prev_tip_hash
+				);
+				Ok(prev_tip_hash)
+			},
+			None => {
+				log_error!(self.logger, "Failed to retrieve header of new tip {}.", tip_hash);
+				Err(ChainSourceError::HttpStatus(404))
+			},
+		}
+	}
+
+	#[maybe_async]
+	fn get_header(&self, block_hash: &BlockHash) -> Result<(Header, u32), ChainSourceError> {
+		let header = retry_transient!(
+			self,
+			RequestKind::BlockHeader,
+			self.client().get_header_by_hash(block_hash)
+		)?;
+		if let (true, Some(height)) = maybe_await!(self.get_block_status(block_hash))? {
+			return Ok((header, height));
+		}
+		Err(ChainSourceError::Inconsistency)
+	}
+
+	#[maybe_async]
+	fn is_block_in_best_chain(
+		&self, block_hash: &BlockHash, _block_height: u32,
+	) -> Result<bool, ChainSourceError> {
+		let (in_best_chain, _) = maybe_await!(self.get_block_status(block_hash))?;
+		Ok(in_best_chain)
+	}
 
-				// Prune any sufficiently confirmed output spends
-				sync_state.prune_output_spends(tip_height);
+	fn on_sync_restart(&self) {
+		if let Some(block_statuses) = self.block_statuses.lock().unwrap().as_mut() {
+			block_statuses.clear();
+		}
+	}
+
+	// Returns whether the block with the given hash is in the best chain and its height, reusing
+	// the status we fetched earlier during the current sync pass, if any.
+	#[maybe_async]
+	fn get_block_status(
+		&self, block_hash: &BlockHash,
+	) -> Result<(bool, Option<u32>), ChainSourceError> {
+		if let Some(block_statuses) = self.block_statuses.lock().unwrap().as_ref() {
+			if let Some(status) = block_statuses.get(block_hash) {
+				return Ok(*status);
 			}
-		} else {
-			return Err(InternalError::Inconsistency);
 		}
-		Ok(())
+
+		let block_status = retry_transient!(
+			self,
+			RequestKind::BlockStatus,
+			self.client().get_block_status(block_hash)
+		)?;
+		let status = (block_status.in_best_chain, block_status.height);
+		if let Some(block_statuses) = self.block_statuses.lock().unwrap().as_mut() {
+			block_statuses.insert(*block_hash, status);
+		}
+		Ok(status)
 	}
 
 	#[maybe_async]
 	fn get_confirmed_transactions(
 		&self, sync_state: &SyncState,
-	) -> Result<Vec<ConfirmedTx>, InternalError> {
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
 		// First, check the confirmation status of registered transactions as well as the
 		// status of dependent transactions of registered outputs.
 
 		let mut confirmed_txs: Vec<ConfirmedTx> = Vec::new();
 
-		for txid in &sync_state.watched_transactions {
-			if confirmed_txs.iter().any(|ctx| ctx.txid == *txid) {
+		// We first retrieve the lightweight status of all registered transactions, so that we only
+		// need to fetch the Merkle block and full transaction data of those that are confirmed.
+		let watched_txids = sync_state.watched_transactions.iter().collect::<Vec<_>>();
+		let tx_statuses = maybe_await!(self.get_tx_statuses(&watched_txids))?;
+
+		let confirmed_watched_txids = watched_txids
+			.into_iter()
+			.zip(tx_statuses)
+			.filter(|(_, tx_status)| tx_status.confirmed)
+			.collect::<Vec<_>>();
+		confirmed_txs.extend(maybe_await!(
+			self.get_confirmed_watched_txs(sync_state, &confirmed_watched_txids)
+		)?);
+		let script_matches = maybe_await!(self.get_script_matches(sync_state, &confirmed_txs))?;
+		confirmed_txs.extend(script_matches);
+
+		let mut outputs_to_check = Vec::with_capacity(sync_state.watched_outputs.len());
+		for (outpoint, output) in &sync_state.watched_outputs {
+			if let Some(spending_tx) = confirmed_txs.iter().find(|ctx| ctx.spends(outpoint)) {
+				// We already found a registered transaction spending this output confirmed, no need
+				// to look it up again.
+				log_trace!(
+					self.logger,
+					"Skipping spend lookup for output {} as it's spent by confirmed transaction {}",
+					outpoint,
+					spending_tx.txid
+				);
 				continue;
 			}
-			if let Some(confirmed_tx) = maybe_await!(self.get_confirmed_tx(*txid, None, None))? {
-				confirmed_txs.push(confirmed_tx);
-			}
+			outputs_to_check.push(output);
 		}
 
-		for (_, output) in &sync_state.watched_outputs {
-			if let Some(output_status) = maybe_await!(self
-				.client
-				.get_output_status(&output.outpoint.txid, output.outpoint.index as u64))?
-			{
-				if let Some(spending_txid) = output_status.txid {
-					if let Some(spending_tx_status) = output_status.status {
-						if confirmed_txs.iter().any(|ctx| ctx.txid == spending_txid) {
-							if spending_tx_status.confirmed {
-								// Skip inserting duplicate ConfirmedTx entry
-								continue;
-							} else {
-								log_trace!(self.logger, "Inconsistency: Detected previously-confirmed Tx {} as unconfirmed", spending_txid);
-								return Err(InternalError::Inconsistency);
-							}
+		let output_statuses = maybe_await!(self.get_output_statuses(&outputs_to_check))?;
+		for output_status in output_statuses.into_iter().flatten() {
+			if let Some(spending_txid) = output_status.txid {
+				if let Some(spending_tx_status) = output_status.status {
+					if confirmed_txs.iter().any(|ctx| ctx.txid == spending_txid) {
+						if spending_tx_status.confirmed {
+							// Skip inserting duplicate ConfirmedTx entry
+							continue;
+						} else {
+							log_trace!(
+								self.logger,
+								"Inconsistency: Detected previously-confirmed Tx {} as unconfirmed",
+								spending_txid
+							);
+							return Err(ChainSourceError::Inconsistency);
 						}
+					}
 
-						if let Some(confirmed_tx) = maybe_await!(self.get_confirmed_tx(
-							spending_txid,
-							spending_tx_status.block_hash,
-							spending_tx_status.block_height,
-						))? {
-							confirmed_txs.push(confirmed_tx);
-						}
+					if let Some(confirmed_tx) = maybe_await!(self.get_confirmed_tx(
+						spending_txid,
+						spending_tx_status.block_hash,
+						spending_tx_status.block_height,
+						None,
+					))? {
+						confirmed_txs.push(confirmed_tx);
 					}
 				}
 			}
 		}
 
 		// Sort all confirmed transactions first by block height, then by in-block
-		// position, and finally feed them to the interface in order.
-		confirmed_txs.sort_unstable_by(|tx1, tx2| {
-			tx1.block_height.cmp(&tx2.block_height).then_with(|| tx1.pos.cmp(&tx2.pos))
+		// position, and finally feed them to the interface in the configured order.
+		sort_in_delivery_order(&mut confirmed_txs, self.config.delivery_order, |ctx| {
+			(ctx.block_height, ctx.pos)
 		});
 
 		Ok(confirmed_txs)
 	}
 
+	#[maybe_async]
+	fn get_replaced_transactions(
+		&self, sync_state: &mut SyncState,
+	) -> Result<Vec<Txid>, ChainSourceError> {
+		let mut replaced_txs = Vec::new();
+		if !self.config.detect_mempool_replacements {
+			return Ok(replaced_txs);
+		}
+		if !maybe_await!(self.check_mempool_support())? {
+			// We still sync confirmed data, but can't tell anything about the mempool.
+			return Ok(replaced_txs);
+		}
+
+		// Check whether any previously-seen unconfirmed spends of outputs we're not watching
+		// anymore got replaced by the spend that confirmed in the meantime.
+		let watched_outputs = &sync_state.watched_outputs;
+		let confirmed_spends = &sync_state.outputs_spends_pending_threshold_conf;
+		sync_state.unconfirmed_output_spends.retain(|outpoint, spending_txid| {
+			if watched_outputs.contains_key(outpoint) {
+				return true;
+			}
+			let replaced = confirmed_spends.iter().any(|(conf_txid, _, prev_outpoint, _)| {
+				prev_outpoint == outpoint && conf_txid != spending_txid
+			});
+			if replaced {
+				replaced_txs.push(*spending_txid);
+			}
+			false
+		});
+
+		let watched_outpoints = sync_state.watched_outputs.keys().cloned().collect::<Vec<_>>();
+		for outpoint in watched_outpoints {
+			let output_status = retry_transient!(
+				self,
+				RequestKind::OutputStatus,
+				self.client().get_output_status(&outpoint.txid, outpoint.vout as u64)
+			)?;
+			let unconfirmed_spend = output_status.and_then(|output_status| {
+				let is_confirmed = output_status.status.map_or(false, |status| status.confirmed);
+				if output_status.spent && !is_confirmed {
+					output_status.txid
+				} else {
+					None
+				}
+			});
+
+			if let Some(prev_spending_txid) = sync_state.unconfirmed_output_spends.get(&outpoint) {
+				if unconfirmed_spend != Some(*prev_spending_txid) {
+					log_debug!(
+						self.logger,
+						"Unconfirmed transaction {} spending watched output {} was evicted or replaced in the mempool.",
+						prev_spending_txid,
+						outpoint
+					);
+					replaced_txs.push(*prev_spending_txid);
+				}
+			}
+
+			if let Some(spending_txid) = unconfirmed_spend {
+				sync_state.unconfirmed_output_spends.insert(outpoint, spending_txid);
+			} else {
+				sync_state.unconfirmed_output_spends.remove(&outpoint);
+			}
+		}
+
+		Ok(replaced_txs)
+	}
+
+	// Evaluates to whether the server exposes mempool data, probing it on first use and caching the
+	// result for the lifetime of the client. Errors are not cached, i.e., we probe again next time.
+	#[maybe_async]
+	fn check_mempool_support(&self) -> Result<bool, ChainSourceError> {
+		let active_idx = self.servers.lock().unwrap().active_idx;
+		if let Some(mempool_supported) = self.servers.lock().unwrap().mempool_supported[active_idx]
+		{
+			return Ok(mempool_supported);
+		}
+
+		let status = retry_transient!(self, RequestKind::Mempool, self.get_mempool_status())?;
+		let mempool_supported = match status {
+			200..=299 => true,
+			// Deployments disabling the mempool endpoints commonly respond with any of these.
+			403 | 404 | 405 | 501 => {
+				log_error!(
+					self.logger,
+					"Esplora server doesn't expose mempool data (HTTP status {}). Only confirmed data will be synced.",
+					status
+				);
+				false
+			},
+			_ => return Err(ChainSourceError::HttpStatus(status)),
+		};
+		self.servers.lock().unwrap().mempool_supported[active_idx] = Some(mempool_supported);
+		Ok(mempool_supported)
+	}
+
+	// Requests the server's mempool statistics, evaluating to the HTTP status of the response.
+	#[maybe_async]
+	fn get_mempool_status(&self) -> Result<u16, esplora_client::Error> {
+		#[cfg(not(feature = "async-interface"))]
+		let status = self
+			.client()
+			.get_request("/mempool")?
+			.send()
+			.map(|response| response.status_code as u16)
+			.map_err(esplora_client::Error::Minreq);
+		#[cfg(feature = "async-interface")]
+		let client = self.client();
+		#[cfg(feature = "async-interface")]
+		let status = client
+			.client()
+			.get(format!("{}/mempool", client.url()))
+			.send()
+			.await
+			.map(|response| response.status().as_u16())
+			.map_err(esplora_client::Error::Reqwest);
+		status
+	}
+
+	#[maybe_async]
+	fn get_confirmed_watched_txs(
+		&self, sync_state: &SyncState, txids: &[(&Txid, TxStatus)],
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+		#[cfg(not(feature = "async-interface"))]
+		let confirmed_txs = txids
+			.iter()
+			.map(|(txid, tx_status)| {
+				self.get_confirmed_tx(
+					**txid,
+					tx_status.block_hash,
+					tx_status.block_height,
+					sync_state
+						.watched_scripts
+						.get(*txid)
+						.filter(|_| self.config.validate_scripts)
+						.map(|s| s.as_script()),
+				)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		// Merkle block requests are considerably heavier than status requests, which is why we
+		// bound their concurrency separately.
+		#[cfg(feature = "async-interface")]
+		let confirmed_txs = {
+			let mut confirmed_txs = Vec::with_capacity(txids.len());
+			for chunk in txids.chunks(self.config.max_concurrent_merkle_block_requests.max(1)) {
+				let results = futures::future::join_all(chunk.iter().map(|(txid, tx_status)| {
+					self.get_confirmed_tx(
+						**txid,
+						tx_status.block_hash,
+						tx_status.block_height,
+						sync_state
+							.watched_scripts
+							.get(*txid)
+							.filter(|_| self.config.validate_scripts)
+							.map(|s| s.as_script()),
+					)
+				}))
+				.await;
+				for res in results {
+					confirmed_txs.push(res?);
+				}
+			}
+			confirmed_txs
+		};
+
+		Ok(confirmed_txs.into_iter().flatten().collect())
+	}
+
+	// Looks up any confirmed transactions paying to the scripts of watched transactions that aren't
+	// among the given confirmed transactions, e.g., as a transaction with a different txid
+	// spending the same inputs confirmed instead.
+	#[maybe_async]
+	fn get_script_matches(
+		&self, sync_state: &SyncState, confirmed_txs: &[ConfirmedTx],
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+		let mut script_matches: Vec<ConfirmedTx> = Vec::new();
+		for script in sync_state.unconfirmed_watched_scripts(confirmed_txs) {
+			let mut last_seen = None;
+			loop {
+				let txs = retry_transient!(
+					self,
+					RequestKind::ScriptHistory,
+					self.client().scripthash_txs(script, last_seen)
+				)?;
+				let confirmed_history =
+					txs.into_iter().filter(|tx| tx.status.confirmed).collect::<Vec<_>>();
+				for tx in &confirmed_history {
+					if !sync_state.is_unreported_script_match(&tx.txid, confirmed_txs)
+						|| script_matches.iter().any(|ctx| ctx.txid == tx.txid)
+					{
+						continue;
+					}
+					if !tx.vout.iter().any(|txout| txout.scriptpubkey.as_script() == script) {
+						// The transaction spends from the script rather than paying to it.
+						continue;
+					}
+					if let Some(confirmed_tx) = maybe_await!(self.get_confirmed_tx(
+						tx.txid,
+						tx.status.block_hash,
+						tx.status.block_height,
+						None,
+					))? {
+						script_matches.push(confirmed_tx);
+					}
+				}
+
+				// The server serves the confirmed history in pages, so we continue after the last
+				// transaction of a full page.
+				if confirmed_history.len() < ESPLORA_SCRIPT_HISTORY_PAGE_SIZE {
+					break;
+				}
+				last_seen = confirmed_history.last().map(|tx| tx.txid);
+			}
+		}
+		Ok(script_matches)
+	}
+
+	// Looks up the spending status of the given outputs, returning them in the same order.
+	#[maybe_async]
+	fn get_output_statuses(
+		&self, outputs: &[&WatchedOutput],
+	) -> Result<Vec<Option<OutputStatus>>, ChainSourceError> {
+		#[cfg(not(feature = "async-interface"))]
+		let output_statuses = outputs
+			.iter()
+			.map(|output| {
+				retry_transient!(
+					self,
+					RequestKind::OutputStatus,
+					self.client()
+						.get_output_status(&output.outpoint.txid, output.outpoint.index as u64)
+				)
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		#[cfg(feature = "async-interface")]
+		let output_statuses = {
+			use futures::stream::{self, StreamExt};
+
+			let mut results =
+				stream::iter(outputs.iter().enumerate().map(|(idx, output)| async move {
+					let res = retry_transient!(
+						self,
+						RequestKind::OutputStatus,
+						self.client()
+							.get_output_status(&output.outpoint.txid, output.outpoint.index as u64)
+					);
+					(idx, res)
+				}))
+				.buffer_unordered(self.config.max_concurrent_requests.max(1))
+				.collect::<Vec<_>>()
+				.await;
+
+			// Restore the order of the outputs, so that the first error we return and any
+			// inconsistencies we detect don't depend on the order in which the requests completed.
+			results.sort_unstable_by_key(|(idx, _)| *idx);
+			results.into_iter().map(|(_, res)| res).collect::<Result<Vec<_>, _>>()?
+		};
+
+		Ok(output_statuses)
+	}
+
+	#[maybe_async]
+	fn get_tx_statuses(&self, txids: &[&Txid]) -> Result<Vec<TxStatus>, ChainSourceError> {
+		#[cfg(not(feature = "async-interface"))]
+		let tx_statuses = txids
+			.iter()
+			.map(|txid| {
+				retry_transient!(self, RequestKind::TxStatus, self.client().get_tx_status(txid))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		#[cfg(feature = "async-interface")]
+		let tx_statuses = {
+			let mut tx_statuses = Vec::with_capacity(txids.len());
+			for chunk in txids.chunks(self.config.max_concurrent_requests.max(1)) {
+				let results = futures::future::join_all(chunk.iter().map(|txid| async move {
+					retry_transient!(self, RequestKind::TxStatus, self.client().get_tx_status(txid))
+				}))
+				.await;
+				for res in results {
+					tx_statuses.push(res?);
+				}
+			}
+			tx_statuses
+		};
+
+		Ok(tx_statuses)
+	}
+
 	#[maybe_async]
 	fn get_confirmed_tx(
 		&self, txid: Txid, expected_block_hash: Option<BlockHash>, known_block_height: Option<u32>,
-	) -> Result<Option<ConfirmedTx>, InternalError> {
-		if let Some(merkle_block) = maybe_await!(self.client.get_merkle_block(&txid))? {
+		expected_script: Option<&Script>,
+	) -> Result<Option<ConfirmedTx>, ChainSourceError> {
+		if let Some(merkle_block) =
+			retry_transient!(self, RequestKind::MerkleProof, self.client().get_merkle_block(&txid))?
+		{
 			let block_header = merkle_block.header;
 			let block_hash = block_header.block_hash();
 			if let Some(expected_block_hash) = expected_block_hash {
@@ -352,24 +1250,36 @@ where
 						expected_block_hash,
 						block_hash
 					);
-					return Err(InternalError::Inconsistency);
+					return Err(ChainSourceError::Inconsistency);
 				}
 			}
 
-			let mut matches = Vec::new();
-			let mut indexes = Vec::new();
-			let _ = merkle_block.txn.extract_matches(&mut matches, &mut indexes);
-			if indexes.len() != 1 || matches.len() != 1 || matches[0] != txid {
-				log_error!(self.logger, "Retrieved Merkle block for txid {} doesn't match expectations. This should not happen. Please verify server integrity.", txid);
-				return Err(InternalError::Failed);
-			}
+			if let Some(tx) =
+				retry_transient!(self, RequestKind::Transaction, self.client().get_tx(&txid))?
+			{
+				// While the header is served along with the proof, its hash commits to the Merkle
+				// root and was either checked against the block we expected above or is checked to
+				// be in the best chain when looking up its height below.
+				let pos = validate_confirmed_tx(
+					&tx,
+					txid,
+					&block_header,
+					MerkleProof::PartialTree(&merkle_block.txn),
+					&self.logger,
+				)?;
 
-			// unwrap() safety: len() > 0 is checked above
-			let pos = *indexes.first().unwrap() as usize;
-			if let Some(tx) = maybe_await!(self.client.get_tx(&txid))? {
-				if tx.txid() != txid {
-					log_error!(self.logger, "Retrieved transaction for txid {} doesn't match expectations. This should not happen. Please verify server integrity.", txid);
-					return Err(InternalError::Failed);
+				if let Some(expected_script) = expected_script {
+					if !tx
+						.output
+						.iter()
+						.any(|txout| txout.script_pubkey.as_script() == expected_script)
+					{
+						log_error!(self.logger, "Confirmed transaction {} doesn't pay to the script it was registered with. This should not happen. Please verify server integrity.", txid);
+						return Err(ChainSourceError::ServerMisbehaving(format!(
+							"Confirmed transaction {} doesn't pay to the registered script",
+							txid
+						)));
+					}
 				}
 
 				if let Some(block_height) = known_block_height {
@@ -377,8 +1287,8 @@ where
 					return Ok(Some(ConfirmedTx { tx, txid, block_header, pos, block_height }));
 				}
 
-				let block_status = maybe_await!(self.client.get_block_status(&block_hash))?;
-				if let Some(block_height) = block_status.height {
+				let (_, block_height) = maybe_await!(self.get_block_status(&block_hash))?;
+				if let Some(block_height) = block_height {
 					return Ok(Some(ConfirmedTx { tx, txid, block_header, pos, block_height }));
 				} else {
 					// If any previously-confirmed block suddenly is no longer confirmed, we found
@@ -388,51 +1298,503 @@ where
 						"Inconsistency: Tx {} was unconfirmed during syncing.",
 						txid
 					);
-					return Err(InternalError::Inconsistency);
+					return Err(ChainSourceError::Inconsistency);
 				}
 			}
 		}
 		Ok(None)
 	}
 
+	/// Rebroadcasts any of the given transactions that are still unconfirmed.
+	///
+	/// Only transactions that were registered via [`Filter::register_tx`] and are still awaiting
+	/// confirmation as of the last call to [`sync`] will be rebroadcast via the Esplora server's
+	/// broadcast endpoint. As we don't keep track of full transaction data, the caller needs to
+	/// supply the transactions to consider.
+	///
+	/// Returns the number of transactions that were rebroadcast, or
+	/// [`TxSyncError::MempoolUnsupported`] if the server doesn't expose mempool data (see
+	/// [`supports_mempool`]).
+	///
+	/// [`supports_mempool`]: Self::supports_mempool
+	/// [`Filter::register_tx`]: lightning::chain::Filter::register_tx
+	/// [`sync`]: Self::sync
+	#[maybe_async]
+	pub fn rebroadcast_unconfirmed_transactions(
+		&self, txs: &[Transaction],
+	) -> Result<usize, TxSyncError> {
+		let unconfirmed_txs = {
+			#[cfg(not(feature = "async-interface"))]
+			let sync_state = self.sync_state.lock().unwrap();
+			#[cfg(feature = "async-interface")]
+			let sync_state = self.sync_state.lock().await;
+
+			txs.iter()
+				.filter(|tx| sync_state.watched_transactions.contains(&tx.txid()))
+				.collect::<Vec<_>>()
+		};
+
+		if !unconfirmed_txs.is_empty() && !maybe_await!(self.check_mempool_support())? {
+			return Err(TxSyncError::MempoolUnsupported);
+		}
+
+		for tx in &unconfirmed_txs {
+			log_trace!(self.logger, "Rebroadcasting unconfirmed transaction {}", tx.txid());
+			maybe_await!(self.wait_for_rate_limit())?;
+			let timer = self.metrics.start_request();
+			let res = maybe_await!(self.client().broadcast(tx));
+			self.metrics.finish_request(RequestKind::Broadcast, timer, res.is_ok());
+			res.map_err(|e| {
+				log_error!(self.logger, "Failed to rebroadcast transaction {}: {}", tx.txid(), e);
+				e
+			})?;
+		}
+
+		Ok(unconfirmed_txs.len())
+	}
+
+	/// Broadcasts any transactions previously queued via
+	/// [`BroadcasterInterface::broadcast_transactions`].
+	///
+	/// As the async client can't broadcast from within the synchronous [`BroadcasterInterface`],
+	/// this needs to be called regularly, e.g., from a background task.
+	#[cfg(feature = "async-interface")]
+	pub async fn process_broadcast_queue(&self) {
+		let packages = core::mem::take(&mut *self.pending_broadcasts.lock().unwrap());
+		for package in packages {
+			self.broadcast_package(package).await;
+		}
+	}
+
+	/// Returns the transactions we failed to broadcast via
+	/// [`BroadcasterInterface::broadcast_transactions`] since the last call to this method.
+	///
+	/// This allows to rebroadcast them later on, e.g., once the server is reachable again.
+	pub fn take_failed_broadcasts(&self) -> Vec<Transaction> {
+		core::mem::take(&mut *self.failed_broadcasts.lock().unwrap())
+	}
+
+	// Broadcasts the given transactions in order. If one fails, we give up on the remaining ones
+	// as they might depend on it.
+	#[maybe_async]
+	fn broadcast_package(&self, package: Vec<Transaction>) {
+		let mut failed_idx = None;
+		for (idx, tx) in package.iter().enumerate() {
+			log_trace!(self.logger, "Broadcasting transaction {}", tx.txid());
+			if let Err(e) = maybe_await!(self.wait_for_rate_limit()) {
+				log_error!(self.logger, "Failed to broadcast transaction {}: {}", tx.txid(), e);
+				failed_idx = Some(idx);
+				break;
+			}
+			let timer = self.metrics.start_request();
+			let res = maybe_await!(self.client().broadcast(tx));
+			self.metrics.finish_request(RequestKind::Broadcast, timer, res.is_ok());
+			if let Err(e) = res {
+				log_error!(self.logger, "Failed to broadcast transaction {}: {}", tx.txid(), e);
+				failed_idx = Some(idx);
+				break;
+			}
+		}
+
+		if let Some(idx) = failed_idx {
+			self.failed_broadcasts.lock().unwrap().extend(package.into_iter().skip(idx));
+		}
+	}
+
+	/// Stops monitoring the transaction with the given `txid`, e.g., once the channel it belongs
+	/// to has been fully resolved.
+	///
+	/// The transaction will be dropped from the set of watched items on the next call to
+	/// [`sync`], i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_tx(&self, txid: &Txid) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_tx(txid);
+	}
+
+	/// Registers the given output to be watched for spends just like [`Filter::register_output`],
+	/// associating it with the given opaque `tag`.
+	///
+	/// The tag is purely local and returned alongside the output once a spend of it is reported via
+	/// [`spent_outputs`], which allows to correlate it with application state.
+	///
+	/// [`Filter::register_output`]: lightning::chain::Filter::register_output
+	/// [`spent_outputs`]: Self::spent_outputs
+	pub fn register_output_with_tag(&self, output: WatchedOutput, tag: u64) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output_with_tag(output, tag);
+	}
+
+	/// Stops monitoring the given output for spends, e.g., once the channel it belongs to has
+	/// been fully resolved.
+	///
+	/// The output will be dropped from the set of watched items on the next call to [`sync`],
+	/// i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_output(&self, outpoint: &OutPoint) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_output(outpoint);
+	}
+
+	/// Returns the spends of watched outputs detected since the last call to this method, along
+	/// with the tag the respective output was registered with via [`register_output_with_tag`],
+	/// if any.
+	///
+	/// Note that a spend may be reported again if the spending transaction is reorged out and
+	/// confirmed again.
+	///
+	/// [`register_output_with_tag`]: Self::register_output_with_tag
+	#[maybe_async]
+	pub fn spent_outputs(&self) -> Vec<SpentOutput> {
+		#[cfg(not(feature = "async-interface"))]
+		let mut sync_state = self.sync_state.lock().unwrap();
+		#[cfg(feature = "async-interface")]
+		let mut sync_state = self.sync_state.lock().await;
+		core::mem::take(&mut sync_state.spent_outputs)
+	}
+
+	/// Returns a snapshot of the transactions we watch for confirmation, distinguishing those
+	/// registered or unregistered since the last [`sync`] from those we actively check.
+	///
+	/// This is meant for debugging, e.g., if confirmations seem to go missing. Note that this
+	/// waits for any [`sync`] in flight to complete, so it must not be called from within the
+	/// [`Confirm`] implementations given to [`sync`].
+	///
+	/// [`sync`]: Self::sync
+	/// [`Confirm`]: lightning::chain::Confirm
+	#[maybe_async]
+	pub fn watched_transactions(&self) -> WatchedTransactions {
+		// We need to lock the sync state before the queue, just like while syncing.
+		#[cfg(not(feature = "async-interface"))]
+		let sync_state = self.sync_state.lock().unwrap();
+		#[cfg(feature = "async-interface")]
+		let sync_state = self.sync_state.lock().await;
+		self.queue.lock().unwrap().watched_transactions(&sync_state)
+	}
+
+	/// Returns a snapshot of the outputs we watch for spends, distinguishing those registered or
+	/// unregistered since the last [`sync`] from those we actively check, along with any spent
+	/// outputs we keep track of until the spend is buried deep enough.
+	///
+	/// This is meant for debugging, e.g., if spends seem to go missing. Note that this waits for
+	/// any [`sync`] in flight to complete, so it must not be called from within the [`Confirm`]
+	/// implementations given to [`sync`].
+	///
+	/// [`sync`]: Self::sync
+	/// [`Confirm`]: lightning::chain::Confirm
+	#[maybe_async]
+	pub fn watched_outputs(&self) -> WatchedOutputs {
+		// We need to lock the sync state before the queue, just like while syncing.
+		#[cfg(not(feature = "async-interface"))]
+		let sync_state = self.sync_state.lock().unwrap();
+		#[cfg(feature = "async-interface")]
+		let sync_state = self.sync_state.lock().await;
+		self.queue.lock().unwrap().watched_outputs(&sync_state)
+	}
+
+	/// Checks that the Esplora server is on the given network before syncing.
+	///
+	/// The check is conducted once, during the next call to [`sync`], which fails with
+	/// [`TxSyncError::WrongNetwork`] if the genesis block hash reported by the server doesn't match
+	/// the one of the given network. Once the check succeeded, it isn't repeated on subsequent
+	/// syncs.
+	///
+	/// [`sync`]: Self::sync
+	pub fn check_network(&self, network: Network) {
+		*self.pending_network_check.lock().unwrap() = Some(network);
+	}
+
+	/// Returns whether the Esplora server exposes mempool data.
+	///
+	/// Some minimal deployments disable the mempool endpoints, in which case we can still sync
+	/// confirmed data, but any features relying on the mempool are unavailable, i.e.,
+	/// [`SyncConfig::detect_mempool_replacements`] has no effect and rebroadcasting fails with
+	/// [`TxSyncError::MempoolUnsupported`].
+	///
+	/// The server is probed on first use and the result is cached for the lifetime of the client.
 	#[maybe_async]
-	fn get_unconfirmed_transactions<C: Deref>(
-		&self, confirmables: &Vec<C>,
-	) -> Result<Vec<Txid>, InternalError>
+	pub fn supports_mempool(&self) -> Result<bool, TxSyncError> {
+		Ok(maybe_await!(self.check_mempool_support())?)
+	}
+
+	/// Persists the current sync state, i.e., the watched transactions and outputs along with the
+	/// chain tip we last synced to, to the given [`KVStore`].
+	///
+	/// Restoring it via [`restore_sync_state`] after a restart allows us to avoid re-querying the
+	/// status of items we already watched when they are registered again via [`Filter`]. This
+	/// should therefore be called after each successful [`sync`].
+	///
+	/// [`restore_sync_state`]: Self::restore_sync_state
+	/// [`sync`]: Self::sync
+	/// [`Filter`]: lightning::chain::Filter
+	#[maybe_async]
+	pub fn persist_sync_state<K: Deref>(&self, kv_store: K) -> Result<(), io::Error>
 	where
-		C::Target: Confirm,
+		K::Target: KVStore,
 	{
-		// Query the interface for relevant txids and check whether the relevant blocks are still
-		// in the best chain, mark them unconfirmed otherwise
-		let relevant_txids = confirmables
-			.iter()
-			.flat_map(|c| c.get_relevant_txids())
-			.collect::<HashSet<(Txid, u32, Option<BlockHash>)>>();
+		#[cfg(not(feature = "async-interface"))]
+		let sync_state = self.sync_state.lock().unwrap();
+		#[cfg(feature = "async-interface")]
+		let sync_state = self.sync_state.lock().await;
+		sync_state.write_to(&*kv_store)
+	}
 
-		let mut unconfirmed_txs = Vec::new();
+	/// Restores the sync state previously persisted via [`persist_sync_state`] from the given
+	/// [`KVStore`], returning whether it was restored.
+	///
+	/// The persisted state is only restored if the chain tip it was synced to is still part of the
+	/// best chain. Otherwise, e.g., if a reorg happened while we were offline, it is discarded and
+	/// the next [`sync`] will do a full resync.
+	///
+	/// Needs to be called before the first call to [`sync`] as it replaces the current state.
+	///
+	/// [`persist_sync_state`]: Self::persist_sync_state
+	/// [`sync`]: Self::sync
+	#[maybe_async]
+	pub fn restore_sync_state<K: Deref>(&self, kv_store: K) -> Result<bool, TxSyncError>
+	where
+		K::Target: KVStore,
+	{
+		#[cfg(not(feature = "async-interface"))]
+		let mut sync_state = self.sync_state.lock().unwrap();
+		#[cfg(feature = "async-interface")]
+		let mut sync_state = self.sync_state.lock().await;
 
-		for (txid, _conf_height, block_hash_opt) in relevant_txids {
-			if let Some(block_hash) = block_hash_opt {
-				let block_status = maybe_await!(self.client.get_block_status(&block_hash))?;
-				if block_status.in_best_chain {
-					// Skip if the block in question is still confirmed.
-					continue;
-				}
+		#[cfg(not(feature = "async-interface"))]
+		let restored = restore_sync_state!(self, &*kv_store, *sync_state, self.logger;);
+		#[cfg(feature = "async-interface")]
+		let restored = restore_sync_state!(self, &*kv_store, *sync_state, self.logger; .await);
+		restored
+	}
 
-				unconfirmed_txs.push(txid);
-			} else {
-				log_error!(self.logger, "Untracked confirmation of funding transaction. Please ensure none of your channels had been created with LDK prior to version 0.0.113!");
-				panic!("Untracked confirmation of funding transaction. Please ensure none of your channels had been created with LDK prior to version 0.0.113!");
+	/// Forces the next [`sync`] to re-check all watched transactions and outputs, even if the chain
+	/// tip didn't change since the last sync.
+	///
+	/// Any confirmations that were already reported are looked up and delivered again via
+	/// [`Confirm::transactions_confirmed`], which LDK handles idempotently. This may be useful,
+	/// e.g., after restoring from a backup or recovering channel monitors. Any items queued for
+	/// registration via [`Filter`] are unaffected.
+	///
+	/// [`sync`]: Self::sync
+	/// [`Confirm::transactions_confirmed`]: lightning::chain::Confirm::transactions_confirmed
+	/// [`Filter`]: lightning::chain::Filter
+	#[maybe_async]
+	pub fn force_resync(&self) {
+		#[cfg(not(feature = "async-interface"))]
+		let mut sync_state = self.sync_state.lock().unwrap();
+		#[cfg(feature = "async-interface")]
+		let mut sync_state = self.sync_state.lock().await;
+		sync_state.request_full_resync();
+	}
+
+	/// Returns the metrics accumulated across all syncs so far.
+	pub fn metrics(&self) -> SyncMetrics {
+		self.metrics.snapshot()
+	}
+
+	/// Returns the hash and height of the chain tip the last successful [`sync`] synced to.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	pub fn last_synced_tip(&self) -> Option<(BlockHash, u32)> {
+		self.last_sync.lock().unwrap().map(|last_sync| (last_sync.tip_hash, last_sync.tip_height))
+	}
+
+	/// Returns the number of confirmations a transaction confirmed at `confirmation_height` has
+	/// at the chain tip the last successful [`sync`] synced to, counted according to
+	/// [`SyncConfig::confirmation_counting`].
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	pub fn confirmations_for(&self, confirmation_height: u32) -> Option<u32> {
+		self.last_synced_tip().map(|(_, tip_height)| {
+			self.config.confirmation_counting.confirmations(confirmation_height, tip_height)
+		})
+	}
+
+	/// Returns the time at which the last successful [`sync`] completed.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	#[cfg(feature = "time")]
+	pub fn last_sync_completed_at(&self) -> Option<SystemTime> {
+		self.last_sync.lock().unwrap().map(|last_sync| last_sync.completed_at)
+	}
+
+	/// Returns the underlying esplora client of the currently active server.
+	///
+	/// Note that the returned client keeps using the same server, even if the primary server is
+	/// replaced via [`set_server_url`] in the meantime.
+	///
+	/// This is not exported to bindings users as the underlying client from BDK is not exported.
+	///
+	/// [`set_server_url`]: Self::set_server_url
+	pub fn client(&self) -> Arc<EsploraClientType> {
+		let active_idx = self.active_server();
+		Arc::clone(&self.clients.read().unwrap()[active_idx])
+	}
+
+	/// Switches to syncing against the Esplora server at the given URL, replacing the primary
+	/// server, e.g., as the user configured a different server.
+	///
+	/// Unlike creating a new [`EsploraSyncClient`], this retains any transactions and outputs
+	/// registered via the [`Filter`] interface, as well as the sync state. Any sync in progress
+	/// is completed against the previous server first, while the next sync re-checks all
+	/// registered items against the new server.
+	///
+	/// Before switching, we check the new server is on the same network as the previous one, or
+	/// on the network given to [`check_network`], if any. If the check fails, we keep using the
+	/// previous server and return [`TxSyncError::WrongNetwork`] or, if we couldn't reach either
+	/// server, the respective error.
+	///
+	/// The new client uses the configured [`SyncConfig::request_timeout_secs`] and routes requests
+	/// through the proxy given to the [`EsploraSyncClientBuilder`], if any. Any fallback servers
+	/// are retained.
+	///
+	/// [`Filter`]: lightning::chain::Filter
+	/// [`check_network`]: Self::check_network
+	#[maybe_async]
+	pub fn set_server_url(&self, server_url: String) -> Result<(), TxSyncError> {
+		// Holding the sync state lock throughout makes sure we never switch in the middle of a sync.
+		#[cfg(not(feature = "async-interface"))]
+		let mut sync_state = self.sync_state.lock().unwrap();
+		#[cfg(feature = "async-interface")]
+		let mut sync_state = self.sync_state.lock().await;
+
+		let client =
+			build_client(&server_url, self.proxy_url.as_deref(), self.config.request_timeout_secs)
+				.map_err(|e| {
+					log_error!(self.logger, "Failed to build Esplora client: {}", e);
+					TxSyncError::from(ChainSourceError::from(e))
+				})?;
+
+		// We don't retry or fail over here, as any failure shouldn't affect the servers in use.
+		let timer = self.metrics.start_request();
+		let res = maybe_await!(client.get_block_hash(0));
+		self.metrics.finish_request(RequestKind::BlockHeader, timer, res.is_ok());
+		let genesis_block_hash = res.map_err(|e| {
+			log_error!(self.logger, "Failed to reach Esplora server {}: {}", server_url, e);
+			TxSyncError::from(ChainSourceError::from(e))
+		})?;
+
+		let pending_network_check = *self.pending_network_check.lock().unwrap();
+		if let Some(network) = pending_network_check {
+			check_genesis_block_hash(network, genesis_block_hash, &self.logger)?;
+			*self.pending_network_check.lock().unwrap() = None;
+		} else {
+			let known_genesis_block_hash =
+				self.servers.lock().unwrap().genesis_block_hashes.iter().flatten().next().copied();
+			let expected_genesis_block_hash = match known_genesis_block_hash {
+				Some(genesis_block_hash) => genesis_block_hash,
+				None => retry_transient!(
+					self,
+					RequestKind::BlockHeader,
+					self.client().get_block_hash(0)
+				)?,
+			};
+			if genesis_block_hash != expected_genesis_block_hash {
+				log_error!(
+					self.logger,
+					"Esplora server {} reported genesis block hash {}, expected {}.",
+					server_url,
+					genesis_block_hash,
+					expected_genesis_block_hash
+				);
+				return Err(TxSyncError::WrongNetwork);
+			}
+		}
+
+		self.clients.write().unwrap()[0] = Arc::new(client);
+		{
+			let mut locked_servers = self.servers.lock().unwrap();
+			locked_servers.active_idx = 0;
+			locked_servers.failed_over = false;
+			#[cfg(feature = "time")]
+			{
+				locked_servers.failed_over_at = None;
 			}
+			locked_servers.genesis_block_hashes[0] = Some(genesis_block_hash);
+			locked_servers.mempool_supported[0] = None;
 		}
-		Ok(unconfirmed_txs)
+		*self.last_available_tip.lock().unwrap() = None;
+		sync_state.pending_sync = true;
+		log_debug!(self.logger, "Switched to Esplora server {}.", server_url);
+		Ok(())
 	}
 
-	/// Returns a reference to the underlying esplora client.
+	/// Returns the index of the server we're currently using, i.e., `0` for the primary server and
+	/// `i` for the `i`-th fallback server given to [`EsploraSyncClientBuilder::fallback_servers`].
 	///
-	/// This is not exported to bindings users as the underlying client from BDK is not exported.
-	pub fn client(&self) -> &EsploraClientType {
-		&self.client
+	/// This may be used to monitor whether we failed over to any of the fallback servers.
+	pub fn active_server(&self) -> usize {
+		self.servers.lock().unwrap().active_idx
+	}
+
+	/// Returns the hash of the current chain tip as reported by the Esplora server.
+	///
+	/// This may be used to conduct custom checks before calling [`sync`].
+	///
+	/// [`sync`]: Self::sync
+	#[maybe_async]
+	pub fn get_tip_hash(&self) -> Result<BlockHash, TxSyncError> {
+		Ok(retry_transient!(self, RequestKind::Tip, self.client().get_tip_hash())?)
+	}
+
+	/// Returns the height of the current chain tip as reported by the Esplora server.
+	///
+	/// This may be used to conduct custom checks before calling [`sync`].
+	///
+	/// [`sync`]: Self::sync
+	#[maybe_async]
+	pub fn get_tip_height(&self) -> Result<u32, TxSyncError> {
+		Ok(retry_transient!(self, RequestKind::Tip, self.client().get_height())?)
+	}
+}
+
+// Builds a client for the given server, routing its requests through the given proxy, if any.
+fn build_client(
+	server_url: &str, proxy_url: Option<&str>, request_timeout_secs: u64,
+) -> Result<EsploraClientType, esplora_client::Error> {
+	let mut builder = Builder::new(server_url).timeout(request_timeout_secs.max(1));
+	if let Some(proxy_url) = proxy_url {
+		builder = builder.proxy(proxy_url);
+	}
+	#[cfg(not(feature = "async-interface"))]
+	let client = builder.build_blocking();
+	#[cfg(feature = "async-interface")]
+	let client = builder.build_async()?;
+	Ok(client)
+}
+
+// What we know about the servers we may sync against.
+struct ServerState {
+	// The index of the server currently in use, the primary server being at index 0.
+	active_idx: usize,
+	// Whether we failed over to another server since the start of the current sync pass.
+	failed_over: bool,
+	#[cfg(feature = "time")]
+	failed_over_at: Option<Instant>,
+	// The genesis block hash reported by each server, if we checked it already.
+	genesis_block_hashes: Vec<Option<BlockHash>>,
+	// Whether each server exposes mempool data, if we probed it already.
+	mempool_supported: Vec<Option<bool>>,
+}
+
+impl ServerState {
+	fn new(num_servers: usize) -> Self {
+		Self {
+			active_idx: 0,
+			failed_over: false,
+			#[cfg(feature = "time")]
+			failed_over_at: None,
+			genesis_block_hashes: vec![None; num_servers],
+			mempool_supported: vec![None; num_servers],
+		}
 	}
 }
 
@@ -443,21 +1805,73 @@ type MutexType<I> = std::sync::Mutex<I>;
 
 // The underlying client type.
 #[cfg(feature = "async-interface")]
-type EsploraClientType = AsyncClient;
+pub(crate) type EsploraClientType = AsyncClient;
 #[cfg(not(feature = "async-interface"))]
-type EsploraClientType = BlockingClient;
+pub(crate) type EsploraClientType = BlockingClient;
+
+impl<L: Deref> BroadcasterInterface for EsploraSyncClient<L>
+where
+	L::Target: Logger,
+{
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		// Esplora doesn't accept packages, so we broadcast them one by one, parents first.
+		let package = order_by_dependencies(txs);
+		#[cfg(not(feature = "async-interface"))]
+		self.broadcast_package(package);
+		#[cfg(feature = "async-interface")]
+		self.pending_broadcasts.lock().unwrap().push(package);
+	}
+}
+
+// Allows the blocking client to be used wherever a `ChainSource` is expected. Its own syncs use
+// the inherent methods, which are `async` for the async client.
+#[cfg(not(feature = "async-interface"))]
+impl<L: Deref> ChainSource for EsploraSyncClient<L>
+where
+	L::Target: Logger,
+{
+	fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+		EsploraSyncClient::get_tip(self)
+	}
+
+	fn get_header(&self, block_hash: &BlockHash) -> Result<(Header, u32), ChainSourceError> {
+		EsploraSyncClient::get_header(self, block_hash)
+	}
+
+	fn is_block_in_best_chain(
+		&self, block_hash: &BlockHash, block_height: u32,
+	) -> Result<bool, ChainSourceError> {
+		EsploraSyncClient::is_block_in_best_chain(self, block_hash, block_height)
+	}
+
+	fn get_confirmed_transactions(
+		&self, sync_state: &SyncState,
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+		EsploraSyncClient::get_confirmed_transactions(self, sync_state)
+	}
+
+	fn get_replaced_transactions(
+		&self, sync_state: &mut SyncState,
+	) -> Result<Vec<Txid>, ChainSourceError> {
+		EsploraSyncClient::get_replaced_transactions(self, sync_state)
+	}
+
+	fn on_sync_restart(&self) {
+		EsploraSyncClient::on_sync_restart(self)
+	}
+}
 
 impl<L: Deref> Filter for EsploraSyncClient<L>
 where
 	L::Target: Logger,
 {
-	fn register_tx(&self, txid: &Txid, _script_pubkey: &Script) {
+	fn register_tx(&self, txid: &Txid, script_pubkey: &Script) {
 		let mut locked_queue = self.queue.lock().unwrap();
-		locked_queue.transactions.insert(*txid);
+		locked_queue.register_tx(*txid, Some(script_pubkey.to_owned()));
 	}
 
 	fn register_output(&self, output: WatchedOutput) {
 		let mut locked_queue = self.queue.lock().unwrap();
-		locked_queue.outputs.insert(output.outpoint.into_bitcoin_outpoint(), output);
+		locked_queue.register_output(output);
 	}
 }
@@ -0,0 +1,284 @@
+use crate::config::SyncConfig;
+use crate::error::TxSyncError;
+use crate::esplora::EsploraClientType;
+
+use lightning::chain::chaininterface::{
+	ConfirmationTarget, FeeEstimator, FEERATE_FLOOR_SATS_PER_KW,
+};
+use lightning::util::logger::Logger;
+use lightning::{log_error, log_trace};
+
+use esplora_client::Builder;
+
+use core::ops::Deref;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(feature = "time")]
+use std::time::Instant;
+
+const ALL_CONFIRMATION_TARGETS: [ConfirmationTarget; 7] = [
+	ConfirmationTarget::OnChainSweep,
+	ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
+	ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+	ConfirmationTarget::AnchorChannelFee,
+	ConfirmationTarget::NonAnchorChannelFee,
+	ConfirmationTarget::ChannelCloseMinimum,
+	ConfirmationTarget::OutputSpendingFee,
+];
+
+/// Provides fee rate estimates based on the `/fee-estimates` endpoint of a given [`Esplora`]
+/// server.
+///
+/// Retrieved estimates are cached for the configured duration. As
+/// [`FeeEstimator::get_est_sat_per_1000_weight`] can't fail, we fall back to the last estimates
+/// we successfully retrieved, or to static defaults if we never did, if the server can't be
+/// reached.
+///
+/// With the `esplora-blocking` feature, expired estimates are refreshed on the next call to
+/// [`FeeEstimator::get_est_sat_per_1000_weight`], which will then block on the request. With the
+/// `esplora-async` feature, estimates are never retrieved on that hot path and
+/// [`EsploraFeeEstimator::update_fees`] needs to be called regularly instead, e.g., from a
+/// background task.
+///
+/// If the `time` feature is disabled, cached estimates never expire and are only refreshed via
+/// [`EsploraFeeEstimator::update_fees`].
+///
+/// [`Esplora`]: https://github.com/Blockstream/electrs
+pub struct EsploraFeeEstimator<L: Deref>
+where
+	L::Target: Logger,
+{
+	client: EsploraClientType,
+	cache: Mutex<FeeRateCache>,
+	cache_duration: Duration,
+	logger: L,
+}
+
+struct FeeRateCache {
+	fee_rates: HashMap<ConfirmationTarget, u32>,
+	#[cfg(feature = "time")]
+	last_updated: Option<Instant>,
+}
+
+impl<L: Deref> EsploraFeeEstimator<L>
+where
+	L::Target: Logger,
+{
+	/// Returns a new [`EsploraFeeEstimator`] object caching retrieved estimates for the given
+	/// duration. A zero duration disables caching, i.e., estimates are then only reused if they
+	/// can't be refreshed.
+	///
+	/// Panics if the async client variant is used and the underlying client can't be built.
+	pub fn new(server_url: String, cache_duration: Duration, logger: L) -> Self {
+		let builder = Builder::new(&server_url).timeout(SyncConfig::default().request_timeout_secs);
+		#[cfg(not(feature = "async-interface"))]
+		let client = builder.build_blocking();
+		#[cfg(feature = "async-interface")]
+		let client = builder.build_async().unwrap();
+
+		Self::from_client(client, cache_duration, logger)
+	}
+
+	/// Returns a new [`EsploraFeeEstimator`] object using the given Esplora client, e.g., the one
+	/// already used by an [`EsploraSyncClient`].
+	///
+	/// This is not exported to bindings users as the underlying client from BDK is not exported.
+	///
+	/// [`EsploraSyncClient`]: crate::EsploraSyncClient
+	pub fn from_client(client: EsploraClientType, cache_duration: Duration, logger: L) -> Self {
+		let cache = Mutex::new(FeeRateCache {
+			fee_rates: HashMap::new(),
+			#[cfg(feature = "time")]
+			last_updated: None,
+		});
+		Self { client, cache, cache_duration, logger }
+	}
+
+	/// Retrieves the current fee estimates from the Esplora server and updates the cache.
+	///
+	/// On failure, the previously cached estimates are left untouched.
+	#[maybe_async]
+	pub fn update_fees(&self) -> Result<(), TxSyncError> {
+		let estimates = maybe_await!(self.client.get_fee_estimates()).map_err(|e| {
+			log_error!(self.logger, "Failed to retrieve fee estimates: {}", e);
+			TxSyncError::from(e)
+		})?;
+
+		let mut fee_rates = HashMap::with_capacity(ALL_CONFIRMATION_TARGETS.len());
+		for target in ALL_CONFIRMATION_TARGETS {
+			let fee_rate = match fee_rate_from_estimates(&estimates, target) {
+				Some(fee_rate) => fee_rate,
+				None => {
+					log_error!(self.logger, "Esplora server didn't provide any fee estimates.");
+					return Err(TxSyncError::Failed);
+				},
+			};
+			log_trace!(
+				self.logger,
+				"Updated fee rate estimate for {:?}: {} sat/kW",
+				target,
+				fee_rate
+			);
+			fee_rates.insert(target, fee_rate);
+		}
+
+		let mut locked_cache = self.cache.lock().unwrap();
+		locked_cache.fee_rates = fee_rates;
+		#[cfg(feature = "time")]
+		{
+			locked_cache.last_updated = Some(Instant::now());
+		}
+		Ok(())
+	}
+
+	/// Returns a reference to the underlying esplora client.
+	///
+	/// This is not exported to bindings users as the underlying client from BDK is not exported.
+	pub fn client(&self) -> &EsploraClientType {
+		&self.client
+	}
+
+	/// Returns whether the cached estimates are missing or expired and should be refreshed via
+	/// [`EsploraFeeEstimator::update_fees`].
+	///
+	/// This may be used to only refresh estimates when needed, e.g., when regularly polling from a
+	/// background task.
+	pub fn is_cache_expired(&self) -> bool {
+		let locked_cache = self.cache.lock().unwrap();
+		if locked_cache.fee_rates.is_empty() || self.cache_duration.is_zero() {
+			return true;
+		}
+		#[cfg(feature = "time")]
+		let is_expired = locked_cache.last_updated.map_or(true, |t| t.elapsed() >= self.cache_duration);
+		#[cfg(not(feature = "time"))]
+		let is_expired = false;
+		is_expired
+	}
+}
+
+impl<L: Deref> FeeEstimator for EsploraFeeEstimator<L>
+where
+	L::Target: Logger,
+{
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		#[cfg(not(feature = "async-interface"))]
+		if self.is_cache_expired() {
+			// Any failure is logged, we just continue with what we have.
+			let _ = self.update_fees();
+		}
+
+		let locked_cache = self.cache.lock().unwrap();
+		locked_cache
+			.fee_rates
+			.get(&confirmation_target)
+			.copied()
+			.unwrap_or_else(|| fallback_fee_rate(confirmation_target))
+	}
+}
+
+// The number of blocks within which we'd like a transaction for the given target to confirm.
+fn num_blocks_for_target(confirmation_target: ConfirmationTarget) -> u16 {
+	match confirmation_target {
+		ConfirmationTarget::OnChainSweep => 6,
+		ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => 1008,
+		ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee => 144,
+		ConfirmationTarget::AnchorChannelFee => 1008,
+		ConfirmationTarget::NonAnchorChannelFee => 12,
+		ConfirmationTarget::ChannelCloseMinimum => 144,
+		ConfirmationTarget::OutputSpendingFee => 12,
+	}
+}
+
+// The fee rate in sat/kW we use for the given target if we never retrieved any estimates.
+fn fallback_fee_rate(confirmation_target: ConfirmationTarget) -> u32 {
+	match confirmation_target {
+		ConfirmationTarget::OnChainSweep => 5000,
+		ConfirmationTarget::MinAllowedAnchorChannelRemoteFee => FEERATE_FLOOR_SATS_PER_KW,
+		ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee => FEERATE_FLOOR_SATS_PER_KW,
+		ConfirmationTarget::AnchorChannelFee => 500,
+		ConfirmationTarget::NonAnchorChannelFee => 1000,
+		ConfirmationTarget::ChannelCloseMinimum => 500,
+		ConfirmationTarget::OutputSpendingFee => 1000,
+	}
+}
+
+// Converts the given Esplora estimates, which map confirmation targets in blocks to fee rates in
+// sat/vB, to a fee rate in sat/kW for the given target, clamped to the fee rate floor.
+//
+// We use the estimate for the largest number of blocks not exceeding the desired one, falling
+// back to the smallest number of blocks provided if there is none. Returns `None` if no estimates
+// were provided at all.
+fn fee_rate_from_estimates(
+	estimates: &HashMap<u16, f64>, confirmation_target: ConfirmationTarget,
+) -> Option<u32> {
+	let num_blocks = num_blocks_for_target(confirmation_target);
+	let (_, sat_per_vb) = estimates
+		.iter()
+		.filter(|(blocks, _)| **blocks <= num_blocks)
+		.max_by_key(|(blocks, _)| **blocks)
+		.or_else(|| estimates.iter().min_by_key(|(blocks, _)| **blocks))?;
+
+	let mut fee_rate = (sat_per_vb * 250.0).round() as u32;
+	if confirmation_target == ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee {
+		// Allow for counterparties rounding their fee rates differently, see the documentation
+		// of `ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee`.
+		fee_rate = fee_rate.saturating_sub(250);
+	}
+	Some(fee_rate.max(FEERATE_FLOOR_SATS_PER_KW))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fee_rate_conversion_uses_closest_estimate() {
+		let estimates: HashMap<u16, f64> =
+			[(1, 20.0), (6, 10.0), (12, 5.0), (144, 2.0), (1008, 1.0)].into_iter().collect();
+
+		assert_eq!(
+			fee_rate_from_estimates(&estimates, ConfirmationTarget::OnChainSweep),
+			Some(2500)
+		);
+		assert_eq!(
+			fee_rate_from_estimates(&estimates, ConfirmationTarget::NonAnchorChannelFee),
+			Some(1250)
+		);
+		assert_eq!(
+			fee_rate_from_estimates(&estimates, ConfirmationTarget::ChannelCloseMinimum),
+			Some(500)
+		);
+		// We apply the rounding allowance for non-anchor counterparties.
+		assert_eq!(
+			fee_rate_from_estimates(
+				&estimates,
+				ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee
+			),
+			Some(253)
+		);
+
+		// If there's no estimate for fewer blocks, we use the closest one above.
+		let estimates: HashMap<u16, f64> = [(25, 3.0)].into_iter().collect();
+		assert_eq!(
+			fee_rate_from_estimates(&estimates, ConfirmationTarget::OnChainSweep),
+			Some(750)
+		);
+
+		assert_eq!(
+			fee_rate_from_estimates(&HashMap::new(), ConfirmationTarget::OnChainSweep),
+			None
+		);
+	}
+
+	#[test]
+	fn fee_rate_conversion_clamps_to_floor() {
+		let estimates: HashMap<u16, f64> = [(1, 0.5), (1008, 0.1)].into_iter().collect();
+		for target in ALL_CONFIRMATION_TARGETS {
+			assert_eq!(
+				fee_rate_from_estimates(&estimates, target),
+				Some(FEERATE_FLOOR_SATS_PER_KW)
+			);
+		}
+	}
+}
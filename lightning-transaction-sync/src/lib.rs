@@ -10,6 +10,13 @@
 //!- `esplora-blocking` enables syncing against an Esplora backend based on a blocking client.
 //!- `esplora-async` enables syncing against an Esplora backend based on an async client.
 //!- `esplora-async-https` enables the async Esplora client with support for HTTPS.
+//!- `bitcoind-rpc` enables syncing against a Bitcoin Core node via its RPC interface.
+//!- `cbf` enables syncing based on BIP 157/158 compact block filters retrieved from a
+//!  user-provided source, e.g., a P2P light client.
+//!
+//! With any of the above features enabled, custom blocking chain backends may be synced from by
+//! implementing `ChainSource` and handing them to a `ChainSourceSyncClient`. Note that there is
+//! no async flavor of `ChainSource` yet.
 //!
 //! ## Version Compatibility
 //!
@@ -68,20 +75,119 @@
 #[macro_use]
 extern crate bdk_macros;
 
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+#[macro_use]
+mod common;
+
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+mod chain_source;
+
 #[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
 mod esplora;
 
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+mod fee_estimator;
+
 #[cfg(any(feature = "electrum"))]
 mod electrum;
 
-#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
-mod common;
-#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+#[cfg(feature = "bitcoind-rpc")]
+mod rpc;
+
+#[cfg(feature = "cbf")]
+mod cbf;
+
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+mod config;
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
 mod error;
-#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
-pub use error::TxSyncError;
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+mod metrics;
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+pub use chain_source::ChainSourceSyncClient;
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+pub use common::{
+	expected_delivery_order, ChainSource, ConfirmedTx, SpentOutput, SyncState, WatchEvent,
+	WatchedItem, WatchedOutputs, WatchedTransactions, SYNC_STATE_PERSISTENCE_KEY,
+	SYNC_STATE_PERSISTENCE_PRIMARY_NAMESPACE, SYNC_STATE_PERSISTENCE_SECONDARY_NAMESPACE,
+};
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+pub use config::{ConfirmationCounting, DeliveryOrder, RateLimit, SyncConfig};
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+pub use error::{ChainSourceError, TxSyncError};
+#[cfg(any(
+	feature = "esplora-blocking",
+	feature = "esplora-async",
+	feature = "electrum",
+	feature = "bitcoind-rpc",
+	feature = "cbf"
+))]
+pub use metrics::{RequestKind, SyncMetrics, SyncMetricsHook, SyncStats};
 
+#[cfg(feature = "cbf")]
+pub use cbf::{CbfSyncClient, CompactFilterSource};
 #[cfg(feature = "electrum")]
 pub use electrum::ElectrumSyncClient;
 #[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
-pub use esplora::EsploraSyncClient;
+pub use esplora::{
+	default_error_classifier, ErrorClassifier, ErrorDisposition, EsploraSyncClient,
+	EsploraSyncClientBuilder,
+};
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+pub use fee_estimator::EsploraFeeEstimator;
+#[cfg(feature = "bitcoind-rpc")]
+pub use rpc::{BitcoindRpcAuth, BitcoindRpcSyncClient};
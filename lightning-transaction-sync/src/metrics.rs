@@ -0,0 +1,288 @@
+use bitcoin::{BlockHash, Txid};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use std::time::Duration;
+#[cfg(all(
+	feature = "time",
+	any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum")
+))]
+use std::time::Instant;
+
+/// Statistics about a single successful sync.
+///
+/// Returned by `sync_with_stats` on the sync clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncStats {
+	/// The number of transactions that were reported as confirmed during the sync.
+	pub num_confirmed: usize,
+	/// The number of transactions that were reported as unconfirmed during the sync.
+	pub num_unconfirmed: usize,
+	/// The hash of the chain tip we synced to.
+	pub tip_hash: BlockHash,
+	/// The height of the chain tip we synced to.
+	pub tip_height: u32,
+	/// The time it took to sync.
+	///
+	/// Will be `None` if the `time` feature is disabled.
+	pub duration: Option<Duration>,
+	/// The number of times the sync had to be restarted as the chain tip changed or an
+	/// inconsistency was encountered, e.g., due to a reorg.
+	///
+	/// Regularly seeing a high number of restarts may indicate the chain backend is flapping.
+	pub num_restarts: usize,
+	/// Previously-seen unconfirmed transactions spending registered outputs that were since
+	/// evicted from or replaced in the mempool.
+	///
+	/// Will always be empty unless [`SyncConfig::detect_mempool_replacements`] is enabled.
+	///
+	/// [`SyncConfig::detect_mempool_replacements`]: crate::SyncConfig::detect_mempool_replacements
+	pub replaced_transactions: Vec<Txid>,
+}
+
+/// A snapshot of the metrics a transaction sync client accumulated across all syncs so far.
+///
+/// Use [`SyncMetrics::to_prometheus`] to export them in the Prometheus text exposition format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncMetrics {
+	/// The total number of transactions that were reported as confirmed.
+	pub total_confirmed: u64,
+	/// The total number of transactions that were reported as unconfirmed.
+	pub total_unconfirmed: u64,
+	/// The total number of times syncing had to be restarted as the chain tip changed or an
+	/// inconsistency was encountered, e.g., due to a reorg.
+	pub total_reorgs: u64,
+	/// The total number of requests issued to the chain backend.
+	pub total_requests: u64,
+	/// The duration of the last successful sync.
+	///
+	/// Will be `None` if no sync succeeded yet or if the `time` feature is disabled.
+	pub last_sync_duration: Option<Duration>,
+	/// The height of the chain tip we last synced to.
+	///
+	/// Will be `None` if we didn't sync to any chain tip yet.
+	pub last_tip_height: Option<u32>,
+}
+
+impl SyncMetrics {
+	/// Returns the metrics formatted in the Prometheus text exposition format.
+	pub fn to_prometheus(&self) -> String {
+		let mut res = String::new();
+		let mut write_metric = |name: &str, kind: &str, help: &str, value: String| {
+			// unwrap() safety: writing to a `String` never fails.
+			writeln!(res, "# HELP {} {}", name, help).unwrap();
+			writeln!(res, "# TYPE {} {}", name, kind).unwrap();
+			writeln!(res, "{} {}", name, value).unwrap();
+		};
+
+		write_metric(
+			"ldk_tx_sync_confirmed_total",
+			"counter",
+			"Total number of transactions reported as confirmed.",
+			self.total_confirmed.to_string(),
+		);
+		write_metric(
+			"ldk_tx_sync_unconfirmed_total",
+			"counter",
+			"Total number of transactions reported as unconfirmed.",
+			self.total_unconfirmed.to_string(),
+		);
+		write_metric(
+			"ldk_tx_sync_reorgs_total",
+			"counter",
+			"Total number of times syncing was restarted due to a chain tip change or inconsistency.",
+			self.total_reorgs.to_string(),
+		);
+		write_metric(
+			"ldk_tx_sync_requests_total",
+			"counter",
+			"Total number of requests issued to the chain backend.",
+			self.total_requests.to_string(),
+		);
+		if let Some(duration) = self.last_sync_duration {
+			write_metric(
+				"ldk_tx_sync_last_sync_duration_seconds",
+				"gauge",
+				"Duration of the last successful sync.",
+				duration.as_secs_f64().to_string(),
+			);
+		}
+		if let Some(height) = self.last_tip_height {
+			write_metric(
+				"ldk_tx_sync_last_tip_height",
+				"gauge",
+				"Height of the chain tip we last synced to.",
+				height.to_string(),
+			);
+		}
+		res
+	}
+}
+
+/// The kind of a request issued to the chain backend, as reported to a [`SyncMetricsHook`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+	/// Retrieving the current chain tip, or checking for new tips via a header subscription.
+	Tip,
+	/// Retrieving a block header or block hash.
+	BlockHeader,
+	/// Retrieving whether a block is part of the best chain and at which height.
+	BlockStatus,
+	/// Retrieving the confirmation status of a transaction.
+	TxStatus,
+	/// Retrieving the spending status of an output.
+	OutputStatus,
+	/// Retrieving the history of a script.
+	ScriptHistory,
+	/// Retrieving the full data of a transaction.
+	Transaction,
+	/// Retrieving a Merkle proof of a transaction's inclusion in a block.
+	MerkleProof,
+	/// Retrieving mempool data.
+	Mempool,
+	/// Broadcasting a transaction.
+	Broadcast,
+}
+
+/// A hook that is informed about each request a sync client issues to the chain backend and
+/// about each completed sync, e.g., to export per-request latencies and failure rates.
+///
+/// Both methods do nothing by default. Note that they are called inline, i.e., while syncing,
+/// so they should return quickly.
+///
+/// Can be set for the Esplora and Electrum clients via `EsploraSyncClientBuilder::metrics_hook`
+/// and `ElectrumSyncClient::with_metrics_hook`, respectively. If none is set, requests aren't
+/// timed at all.
+pub trait SyncMetricsHook: Send + Sync {
+	/// Called once a request to the chain backend completed, with the time it took and whether it
+	/// succeeded.
+	///
+	/// Requests that are retried are reported once per attempt. The `duration` will always be
+	/// zero if the `time` feature is disabled.
+	fn on_request(&self, _kind: RequestKind, _duration: Duration, _success: bool) {}
+
+	/// Called once a sync completed successfully.
+	fn on_sync_complete(&self, _stats: &SyncStats) {}
+}
+
+// Thread-safe counters backing the [`SyncMetrics`] of a sync client.
+pub(crate) struct MetricsCounters {
+	total_confirmed: AtomicU64,
+	total_unconfirmed: AtomicU64,
+	total_reorgs: AtomicU64,
+	total_requests: AtomicU64,
+	last_sync: Mutex<(Option<Duration>, Option<u32>)>,
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	hook: Option<Arc<dyn SyncMetricsHook>>,
+}
+
+// Tracks an in-flight request, recording the time it was issued at if a hook needs to be
+// informed about it.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+pub(crate) struct RequestTimer {
+	#[cfg(feature = "time")]
+	start: Option<Instant>,
+}
+
+impl MetricsCounters {
+	pub fn new() -> Self {
+		Self {
+			total_confirmed: AtomicU64::new(0),
+			total_unconfirmed: AtomicU64::new(0),
+			total_reorgs: AtomicU64::new(0),
+			total_requests: AtomicU64::new(0),
+			last_sync: Mutex::new((None, None)),
+			#[cfg(any(
+				feature = "esplora-blocking",
+				feature = "esplora-async",
+				feature = "electrum"
+			))]
+			hook: None,
+		}
+	}
+
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	pub fn set_hook(&mut self, hook: Arc<dyn SyncMetricsHook>) {
+		self.hook = Some(hook);
+	}
+
+	// Records a request we're about to issue, which needs to be followed by a call to
+	// `finish_request` once it completed.
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	pub fn start_request(&self) -> RequestTimer {
+		self.record_request();
+		RequestTimer {
+			#[cfg(feature = "time")]
+			start: self.hook.as_ref().map(|_| Instant::now()),
+		}
+	}
+
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	#[cfg_attr(not(feature = "time"), allow(unused_variables))]
+	pub fn finish_request(&self, kind: RequestKind, timer: RequestTimer, success: bool) {
+		if let Some(hook) = &self.hook {
+			#[cfg(feature = "time")]
+			let duration = timer.start.map_or(Duration::ZERO, |start| start.elapsed());
+			#[cfg(not(feature = "time"))]
+			let duration = Duration::ZERO;
+			hook.on_request(kind, duration, success);
+		}
+	}
+
+	// Issues the given blocking request, recording it and informing the hook, if any.
+	#[cfg(feature = "electrum")]
+	pub fn track_request<T, E, F: FnOnce() -> Result<T, E>>(
+		&self, kind: RequestKind, request: F,
+	) -> Result<T, E> {
+		let timer = self.start_request();
+		let res = request();
+		self.finish_request(kind, timer, res.is_ok());
+		res
+	}
+
+	#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+	pub fn record_sync_complete(&self, stats: &SyncStats) {
+		if let Some(hook) = &self.hook {
+			hook.on_sync_complete(stats);
+		}
+	}
+
+	pub fn record_confirmed(&self, num_confirmed: usize) {
+		self.total_confirmed.fetch_add(num_confirmed as u64, Ordering::Relaxed);
+	}
+
+	pub fn record_unconfirmed(&self, num_unconfirmed: usize) {
+		self.total_unconfirmed.fetch_add(num_unconfirmed as u64, Ordering::Relaxed);
+	}
+
+	pub fn record_reorg(&self) {
+		self.total_reorgs.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_request(&self) {
+		self.total_requests.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn record_tip_height(&self, tip_height: u32) {
+		self.last_sync.lock().unwrap().1 = Some(tip_height);
+	}
+
+	pub fn record_sync_duration(&self, duration: Duration) {
+		self.last_sync.lock().unwrap().0 = Some(duration);
+	}
+
+	pub fn snapshot(&self) -> SyncMetrics {
+		let (last_sync_duration, last_tip_height) = *self.last_sync.lock().unwrap();
+		SyncMetrics {
+			total_confirmed: self.total_confirmed.load(Ordering::Relaxed),
+			total_unconfirmed: self.total_unconfirmed.load(Ordering::Relaxed),
+			total_reorgs: self.total_reorgs.load(Ordering::Relaxed),
+			total_requests: self.total_requests.load(Ordering::Relaxed),
+			last_sync_duration,
+			last_tip_height,
+		}
+	}
+}
@@ -0,0 +1,495 @@
+use crate::common::{
+	restore_with_chain_source, sort_in_delivery_order, sync_with_chain_source, ChainSource,
+	ConfirmedTx, FilterQueue, LastSync, SpentOutput, SyncState,
+};
+use crate::config::SyncConfig;
+use crate::error::{ChainSourceError, TxSyncError};
+use crate::metrics::{MetricsCounters, SyncMetrics, SyncStats};
+
+use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+use bitcoincore_rpc::{Auth, Client as RpcClient, RpcApi};
+
+use lightning::chain::WatchedOutput;
+use lightning::chain::{Confirm, Filter};
+use lightning::io;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::{log_error, log_trace};
+
+use bitcoin::block::Header;
+use bitcoin::{BlockHash, OutPoint, Script, Transaction, Txid};
+
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Mutex;
+#[cfg(feature = "time")]
+use std::time::SystemTime;
+
+/// The error code Bitcoin Core returns if it couldn't find the requested item, i.e.,
+/// `RPC_INVALID_ADDRESS_OR_KEY`.
+const RPC_NOT_FOUND_ERROR_CODE: i32 = -5;
+
+/// The means of authenticating with the Bitcoin Core RPC interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BitcoindRpcAuth {
+	/// Authenticate via the cookie file Bitcoin Core creates on startup.
+	CookieFile(PathBuf),
+	/// Authenticate via the given RPC user name and password.
+	UserPass(String, String),
+}
+
+/// Synchronizes LDK with a given Bitcoin Core node via its RPC interface.
+///
+/// Needs to be registered with a [`ChainMonitor`] via the [`Filter`] interface to be informed of
+/// transactions and outputs to monitor for on-chain confirmation, unconfirmation, and
+/// reconfirmation.
+///
+/// Note that registration via [`Filter`] needs to happen before any calls to
+/// [`Watch::watch_channel`] to ensure we get notified of the items to monitor.
+///
+/// As transactions are looked up via `getrawtransaction`, the connected node needs to run with
+/// `-txindex` enabled. Moreover, as Bitcoin Core doesn't keep track of which transaction spent a
+/// given output, spends of registered outputs are detected via `gettxout` and the spending
+/// transaction is then found by scanning the blocks following the output's confirmation.
+///
+/// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
+/// [`Watch::watch_channel`]: lightning::chain::Watch::watch_channel
+/// [`Filter`]: lightning::chain::Filter
+pub struct BitcoindRpcSyncClient<L: Deref>
+where
+	L::Target: Logger,
+{
+	sync_state: Mutex<SyncState>,
+	queue: Mutex<FilterQueue>,
+	client: RpcClient,
+	metrics: MetricsCounters,
+	last_sync: Mutex<Option<LastSync>>,
+	logger: L,
+}
+
+impl<L: Deref> BitcoindRpcSyncClient<L>
+where
+	L::Target: Logger,
+{
+	/// Returns a new [`BitcoindRpcSyncClient`] object connecting to the given RPC URL.
+	pub fn new(rpc_url: String, auth: BitcoindRpcAuth, logger: L) -> Result<Self, TxSyncError> {
+		let auth = match auth {
+			BitcoindRpcAuth::CookieFile(path) => Auth::CookieFile(path),
+			BitcoindRpcAuth::UserPass(user, pass) => Auth::UserPass(user, pass),
+		};
+		let client = RpcClient::new(&rpc_url, auth).map_err(|e| {
+			log_error!(logger, "Failed to set up bitcoind RPC client for '{}': {}", rpc_url, e);
+			e
+		})?;
+
+		Ok(Self::from_client(client, logger))
+	}
+
+	/// Returns a new [`BitcoindRpcSyncClient`] object using the given RPC client.
+	///
+	/// This is not exported to bindings users as the underlying RPC client is not exported.
+	pub fn from_client(client: RpcClient, logger: L) -> Self {
+		let sync_state = Mutex::new(SyncState::new());
+		let queue = Mutex::new(FilterQueue::new());
+		let metrics = MetricsCounters::new();
+		let last_sync = Mutex::new(None);
+
+		Self { sync_state, queue, client, metrics, last_sync, logger }
+	}
+
+	/// Synchronizes the given `confirmables` via their [`Confirm`] interface implementations. This
+	/// method should be called regularly to keep LDK up-to-date with current chain data.
+	///
+	/// For example, instances of [`ChannelManager`] and [`ChainMonitor`] can be informed about the
+	/// newest on-chain activity related to the items previously registered via the [`Filter`]
+	/// interface.
+	///
+	/// [`Confirm`]: lightning::chain::Confirm
+	/// [`ChainMonitor`]: lightning::chain::chainmonitor::ChainMonitor
+	/// [`ChannelManager`]: lightning::ln::channelmanager::ChannelManager
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn sync<C: Deref>(&self, confirmables: Vec<C>) -> Result<(), TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		self.sync_with_stats(confirmables).map(|_| ())
+	}
+
+	/// Synchronizes the given `confirmables` just like [`BitcoindRpcSyncClient::sync`], but
+	/// returns [`SyncStats`] describing the sync on success.
+	pub fn sync_with_stats<C: Deref>(&self, confirmables: Vec<C>) -> Result<SyncStats, TxSyncError>
+	where
+		C::Target: Confirm,
+	{
+		// This lock makes sure we're syncing once at a time.
+		let mut sync_state = self.sync_state.lock().unwrap();
+
+		let stats = sync_with_chain_source(
+			self,
+			&confirmables,
+			&mut sync_state,
+			&self.queue,
+			&self.metrics,
+			&self.logger,
+			SyncConfig::default().max_sync_restarts,
+			SyncConfig::default().min_confirmations,
+		)?;
+		*self.last_sync.lock().unwrap() = Some(LastSync::now(stats.tip_hash, stats.tip_height));
+		Ok(stats)
+	}
+
+	// Looks up the given transaction, returning it along with the hash of the block it was
+	// confirmed in, if any.
+	fn get_tx_with_block_hash(
+		&self, txid: &Txid,
+	) -> Result<Option<(Transaction, BlockHash)>, ChainSourceError> {
+		self.metrics.record_request();
+		let tx_info = match self.client.get_raw_transaction_info(txid, None) {
+			Ok(tx_info) => tx_info,
+			Err(e) if is_not_found_error(&e) => {
+				// We couldn't find the tx, do nothing.
+				return Ok(None);
+			},
+			Err(e) => {
+				log_error!(self.logger, "Failed to look up transaction {}: {}.", txid, e);
+				return Err(ChainSourceError::Failed);
+			},
+		};
+
+		if let Some(block_hash) = tx_info.blockhash {
+			let tx = tx_info.transaction().map_err(|e| {
+				log_error!(self.logger, "Failed to decode transaction {}: {}.", txid, e);
+				ChainSourceError::Failed
+			})?;
+			if tx.txid() != *txid {
+				log_error!(self.logger, "Retrieved transaction for txid {} doesn't match expectations. This should not happen. Please verify server integrity.", txid);
+				return Err(ChainSourceError::ServerMisbehaving(format!(
+					"Transaction for txid {} doesn't match expectations",
+					txid
+				)));
+			}
+			Ok(Some((tx, block_hash)))
+		} else {
+			Ok(None)
+		}
+	}
+
+	fn get_confirmed_tx(
+		&self, txid: Txid, tx: Transaction, block_hash: &BlockHash,
+	) -> Result<ConfirmedTx, ChainSourceError> {
+		self.metrics.record_request();
+		let block_info = self.client.get_block_info(block_hash)?;
+		if block_info.confirmations < 1 {
+			// If any previously-confirmed block suddenly is no longer confirmed, we found
+			// an inconsistency and should start over.
+			log_trace!(self.logger, "Inconsistency: Tx {} was unconfirmed during syncing.", txid);
+			return Err(ChainSourceError::Inconsistency);
+		}
+
+		let pos = match block_info.tx.iter().position(|block_txid| *block_txid == txid) {
+			Some(pos) => pos,
+			None => {
+				log_error!(self.logger, "Retrieved block {} doesn't contain txid {}. This should not happen. Please verify server integrity.", block_hash, txid);
+				return Err(ChainSourceError::ServerMisbehaving(format!(
+					"Block {} doesn't contain txid {}",
+					block_hash, txid
+				)));
+			},
+		};
+
+		self.metrics.record_request();
+		let block_header = self.client.get_block_header(block_hash)?;
+		let block_height = block_info.height as u32;
+		Ok(ConfirmedTx { tx, txid, block_header, block_height, pos })
+	}
+
+	/// Stops monitoring the transaction with the given `txid`, e.g., once the channel it belongs
+	/// to has been fully resolved.
+	///
+	/// The transaction will be dropped from the set of watched items on the next call to
+	/// [`sync`], i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_tx(&self, txid: &Txid) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_tx(txid);
+	}
+
+	/// Registers the given output to be watched for spends just like [`Filter::register_output`],
+	/// associating it with the given opaque `tag`.
+	///
+	/// The tag is purely local and returned alongside the output once a spend of it is reported via
+	/// [`spent_outputs`], which allows to correlate it with application state.
+	///
+	/// [`Filter::register_output`]: lightning::chain::Filter::register_output
+	/// [`spent_outputs`]: Self::spent_outputs
+	pub fn register_output_with_tag(&self, output: WatchedOutput, tag: u64) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output_with_tag(output, tag);
+	}
+
+	/// Stops monitoring the given output for spends, e.g., once the channel it belongs to has
+	/// been fully resolved.
+	///
+	/// The output will be dropped from the set of watched items on the next call to [`sync`],
+	/// i.e., any sync already in flight is unaffected.
+	///
+	/// [`sync`]: Self::sync
+	pub fn unregister_output(&self, outpoint: &OutPoint) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.unregister_output(outpoint);
+	}
+
+	/// Returns the spends of watched outputs detected since the last call to this method, along
+	/// with the tag the respective output was registered with via [`register_output_with_tag`],
+	/// if any.
+	///
+	/// Note that a spend may be reported again if the spending transaction is reorged out and
+	/// confirmed again.
+	///
+	/// [`register_output_with_tag`]: Self::register_output_with_tag
+	pub fn spent_outputs(&self) -> Vec<SpentOutput> {
+		let mut sync_state = self.sync_state.lock().unwrap();
+		core::mem::take(&mut sync_state.spent_outputs)
+	}
+
+	/// Persists the current sync state, i.e., the watched transactions and outputs along with the
+	/// chain tip we last synced to, to the given [`KVStore`].
+	///
+	/// Restoring it via [`restore_sync_state`] after a restart allows us to avoid re-querying the
+	/// status of items we already watched when they are registered again via [`Filter`]. This
+	/// should therefore be called after each successful [`sync`].
+	///
+	/// [`restore_sync_state`]: Self::restore_sync_state
+	/// [`sync`]: Self::sync
+	/// [`Filter`]: lightning::chain::Filter
+	pub fn persist_sync_state<K: Deref>(&self, kv_store: K) -> Result<(), io::Error>
+	where
+		K::Target: KVStore,
+	{
+		let sync_state = self.sync_state.lock().unwrap();
+		sync_state.write_to(&*kv_store)
+	}
+
+	/// Restores the sync state previously persisted via [`persist_sync_state`] from the given
+	/// [`KVStore`], returning whether it was restored.
+	///
+	/// The persisted state is only restored if the chain tip it was synced to is still part of the
+	/// best chain. Otherwise, e.g., if a reorg happened while we were offline, it is discarded and
+	/// the next [`sync`] will do a full resync.
+	///
+	/// Needs to be called before the first call to [`sync`] as it replaces the current state.
+	///
+	/// [`persist_sync_state`]: Self::persist_sync_state
+	/// [`sync`]: Self::sync
+	pub fn restore_sync_state<K: Deref>(&self, kv_store: K) -> Result<bool, TxSyncError>
+	where
+		K::Target: KVStore,
+	{
+		let mut sync_state = self.sync_state.lock().unwrap();
+		restore_with_chain_source(self, &*kv_store, &mut sync_state, &self.logger)
+	}
+
+	/// Returns the metrics accumulated across all syncs so far.
+	pub fn metrics(&self) -> SyncMetrics {
+		self.metrics.snapshot()
+	}
+
+	/// Returns the hash and height of the chain tip the last successful [`sync`] synced to.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	pub fn last_synced_tip(&self) -> Option<(BlockHash, u32)> {
+		self.last_sync.lock().unwrap().map(|last_sync| (last_sync.tip_hash, last_sync.tip_height))
+	}
+
+	/// Returns the time at which the last successful [`sync`] completed.
+	///
+	/// Returns `None` if we didn't successfully sync yet.
+	///
+	/// [`sync`]: Self::sync
+	#[cfg(feature = "time")]
+	pub fn last_sync_completed_at(&self) -> Option<SystemTime> {
+		self.last_sync.lock().unwrap().map(|last_sync| last_sync.completed_at)
+	}
+
+	/// Returns a reference to the underlying RPC client.
+	///
+	/// This is not exported to bindings users as the underlying RPC client is not exported.
+	pub fn client(&self) -> &RpcClient {
+		&self.client
+	}
+}
+
+impl<L: Deref> ChainSource for BitcoindRpcSyncClient<L>
+where
+	L::Target: Logger,
+{
+	fn get_tip(&self) -> Result<BlockHash, ChainSourceError> {
+		self.metrics.record_request();
+		Ok(self.client.get_best_block_hash()?)
+	}
+
+	fn get_header(&self, block_hash: &BlockHash) -> Result<(Header, u32), ChainSourceError> {
+		self.metrics.record_request();
+		let header = self.client.get_block_header(block_hash)?;
+		self.metrics.record_request();
+		let header_info = self.client.get_block_header_info(block_hash)?;
+		if header_info.confirmations < 1 {
+			// The block got reorged out in the meantime.
+			return Err(ChainSourceError::Inconsistency);
+		}
+		Ok((header, header_info.height as u32))
+	}
+
+	fn is_block_in_best_chain(
+		&self, block_hash: &BlockHash, _block_height: u32,
+	) -> Result<bool, ChainSourceError> {
+		self.metrics.record_request();
+		match self.client.get_block_header_info(block_hash) {
+			Ok(block_info) => Ok(block_info.confirmations > 0),
+			Err(e) if is_not_found_error(&e) => Ok(false),
+			Err(e) => {
+				log_error!(self.logger, "Failed to look up block {}: {}.", block_hash, e);
+				Err(ChainSourceError::Failed)
+			},
+		}
+	}
+
+	fn get_confirmed_transactions(
+		&self, sync_state: &SyncState,
+	) -> Result<Vec<ConfirmedTx>, ChainSourceError> {
+		// First, check the confirmation status of registered transactions as well as the
+		// status of dependent transactions of registered outputs.
+		let mut confirmed_txs: Vec<ConfirmedTx> = Vec::new();
+
+		for txid in &sync_state.watched_transactions {
+			if let Some((tx, block_hash)) = self.get_tx_with_block_hash(txid)? {
+				confirmed_txs.push(self.get_confirmed_tx(*txid, tx, &block_hash)?);
+			}
+		}
+
+		// Bitcoin Core can't tell us which transaction spent a given output. We therefore first
+		// check which of the watched outputs have been spent and then scan the chain for the
+		// spending transactions, starting at the earliest block confirming any of the outputs.
+		let mut spent_outpoints = HashSet::new();
+		let mut scan_start: Option<(u32, BlockHash)> = None;
+		for outpoint in sync_state.watched_outputs.keys() {
+			if let Some(spending_tx) = confirmed_txs.iter().find(|ctx| ctx.spends(outpoint)) {
+				// We already found a registered transaction spending this output confirmed, no need
+				// to look it up again.
+				log_trace!(
+					self.logger,
+					"Skipping spend lookup for output {} as it's spent by confirmed transaction {}",
+					outpoint,
+					spending_tx.txid
+				);
+				continue;
+			}
+
+			self.metrics.record_request();
+			if self.client.get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?.is_some() {
+				// The output is still unspent.
+				continue;
+			}
+
+			// The output is either spent or not confirmed (yet), so we check whether and where it
+			// got confirmed to know where to start looking for a spend.
+			let funding_block_hash = match self.get_tx_with_block_hash(&outpoint.txid)? {
+				Some((_, block_hash)) => block_hash,
+				None => continue,
+			};
+			self.metrics.record_request();
+			let funding_info = self.client.get_block_header_info(&funding_block_hash)?;
+			if funding_info.confirmations < 1 {
+				log_trace!(
+					self.logger,
+					"Inconsistency: Block {} was unconfirmed during syncing.",
+					funding_block_hash
+				);
+				return Err(ChainSourceError::Inconsistency);
+			}
+
+			let funding_height = funding_info.height as u32;
+			if scan_start.map_or(true, |(start_height, _)| funding_height < start_height) {
+				scan_start = Some((funding_height, funding_block_hash));
+			}
+			spent_outpoints.insert(*outpoint);
+		}
+
+		let mut next_block_hash = scan_start.map(|(_, block_hash)| block_hash);
+		while let Some(block_hash) = next_block_hash {
+			if spent_outpoints.is_empty() {
+				break;
+			}
+
+			self.metrics.record_request();
+			let block_info = self.client.get_block_header_info(&block_hash)?;
+			if block_info.confirmations < 1 {
+				log_trace!(
+					self.logger,
+					"Inconsistency: Block {} was unconfirmed during syncing.",
+					block_hash
+				);
+				return Err(ChainSourceError::Inconsistency);
+			}
+
+			self.metrics.record_request();
+			let block = self.client.get_block(&block_hash)?;
+			for (pos, tx) in block.txdata.iter().enumerate() {
+				let mut spends_watched_output = false;
+				for txin in &tx.input {
+					if spent_outpoints.remove(&txin.previous_output) {
+						spends_watched_output = true;
+					}
+				}
+
+				let txid = tx.txid();
+				if spends_watched_output && !confirmed_txs.iter().any(|ctx| ctx.txid == txid) {
+					confirmed_txs.push(ConfirmedTx {
+						tx: tx.clone(),
+						txid,
+						block_header: block.header,
+						block_height: block_info.height as u32,
+						pos,
+					});
+				}
+			}
+			next_block_hash = block_info.next_block_hash;
+		}
+
+		// Sort all confirmed transactions first by block height, then by in-block
+		// position, and finally feed them to the interface in order.
+		sort_in_delivery_order(&mut confirmed_txs, SyncConfig::default().delivery_order, |ctx| {
+			(ctx.block_height, ctx.pos)
+		});
+
+		Ok(confirmed_txs)
+	}
+}
+
+impl<L: Deref> Filter for BitcoindRpcSyncClient<L>
+where
+	L::Target: Logger,
+{
+	fn register_tx(&self, txid: &Txid, _script_pubkey: &Script) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_tx(*txid, None);
+	}
+
+	fn register_output(&self, output: WatchedOutput) {
+		let mut locked_queue = self.queue.lock().unwrap();
+		locked_queue.register_output(output);
+	}
+}
+
+fn is_not_found_error(e: &bitcoincore_rpc::Error) -> bool {
+	match e {
+		bitcoincore_rpc::Error::JsonRpc(JsonRpcError::Rpc(rpc_err)) => {
+			rpc_err.code == RPC_NOT_FOUND_ERROR_CODE
+		},
+		_ => false,
+	}
+}
@@ -1,20 +1,54 @@
 #![cfg(all(
 	not(target_os = "windows"),
-	any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum")
+	any(
+		feature = "esplora-blocking",
+		feature = "esplora-async",
+		feature = "electrum",
+		feature = "bitcoind-rpc",
+		feature = "cbf"
+	)
 ))]
 
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use lightning::chain::chaininterface::BroadcasterInterface;
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+use lightning::chain::chaininterface::{
+	ConfirmationTarget, FeeEstimator, FEERATE_FLOOR_SATS_PER_KW,
+};
 use lightning::chain::transaction::{OutPoint, TransactionData};
 use lightning::chain::{Confirm, Filter, WatchedOutput};
 use lightning::util::test_utils::TestLogger;
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use lightning::util::test_utils::TestStore;
 #[cfg(feature = "electrum")]
 use lightning_transaction_sync::ElectrumSyncClient;
+#[cfg(all(
+	feature = "cbf",
+	not(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))
+))]
+use lightning_transaction_sync::TxSyncError;
 #[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
-use lightning_transaction_sync::EsploraSyncClient;
+use lightning_transaction_sync::{
+	default_error_classifier, ConfirmationCounting, DeliveryOrder, ErrorDisposition,
+	EsploraFeeEstimator, EsploraSyncClient, EsploraSyncClientBuilder, RateLimit, SyncConfig,
+};
+#[cfg(feature = "bitcoind-rpc")]
+use lightning_transaction_sync::{BitcoindRpcAuth, BitcoindRpcSyncClient};
+#[cfg(feature = "cbf")]
+use lightning_transaction_sync::{CbfSyncClient, CompactFilterSource};
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+use lightning_transaction_sync::{
+	RequestKind, SpentOutput, SyncMetricsHook, SyncStats, TxSyncError, WatchEvent, WatchedItem,
+};
 
 use bdk_macros::maybe_await;
+#[cfg(feature = "cbf")]
+use bitcoin::bip158::BlockFilter;
 use bitcoin::blockdata::block::Header;
 use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::network::Network;
+#[cfg(feature = "cbf")]
+use bitcoin::Block;
 use bitcoin::{Amount, BlockHash, Txid};
 use bitcoind::bitcoincore_rpc::RpcApi;
 use electrsd::bitcoind::bitcoincore_rpc::bitcoincore_rpc_json::AddressType;
@@ -32,6 +66,8 @@ pub fn setup_bitcoind_and_electrsd() -> (BitcoinD, ElectrsD) {
 		);
 	let mut bitcoind_conf = bitcoind::Conf::default();
 	bitcoind_conf.network = "regtest";
+	bitcoind_conf.args.push("-txindex");
+	bitcoind_conf.args.push("-blockfilterindex=1");
 	let bitcoind = BitcoinD::with_conf(bitcoind_exe, &bitcoind_conf).unwrap();
 
 	let electrs_exe = env::var("ELECTRS_EXE")
@@ -84,6 +120,14 @@ pub fn wait_for_block(electrsd: &ElectrsD, min_height: usize) {
 	}
 }
 
+pub fn wait_for_tx(electrsd: &ElectrsD, txid: Txid) {
+	use electrsd::electrum_client::ElectrumApi;
+	exponential_backoff_poll(|| {
+		electrsd.trigger().expect("failed to trigger electrsd");
+		electrsd.client.transaction_get(&txid).ok()
+	});
+}
+
 fn exponential_backoff_poll<T, F>(mut poll: F) -> T
 where
 	F: FnMut() -> Option<T>,
@@ -105,6 +149,346 @@ where
 	}
 }
 
+// Spawns a proxy in front of the given HTTP server that responds to the first `num_failures`
+// connections with a `503 Service Unavailable` error and forwards any subsequent ones.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+pub fn setup_flaky_proxy(server_addr: String, num_failures: usize) -> String {
+	use std::io::{Read, Write};
+	use std::net::{TcpListener, TcpStream};
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let proxy_addr = listener.local_addr().unwrap();
+	std::thread::spawn(move || {
+		let mut remaining_failures = num_failures;
+		for stream in listener.incoming() {
+			let mut stream = match stream {
+				Ok(stream) => stream,
+				Err(_) => continue,
+			};
+			if remaining_failures > 0 {
+				remaining_failures -= 1;
+				let mut buf = [0u8; 4096];
+				let _ = stream.read(&mut buf);
+				let _ = stream.write_all(
+					b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+				);
+				continue;
+			}
+
+			let upstream = TcpStream::connect(&server_addr).unwrap();
+			let mut upstream_write = upstream.try_clone().unwrap();
+			let mut downstream_read = stream.try_clone().unwrap();
+			std::thread::spawn(move || std::io::copy(&mut downstream_read, &mut upstream_write));
+			let (mut upstream_read, mut downstream_write) = (upstream, stream);
+			std::thread::spawn(move || std::io::copy(&mut upstream_read, &mut downstream_write));
+		}
+	});
+	format!("http://{}", proxy_addr)
+}
+
+// Reads an HTTP message head, returning it along with the value of its `Content-Length`.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+fn read_head<R: std::io::BufRead>(reader: &mut R) -> Option<(Vec<u8>, usize)> {
+	let mut head = Vec::new();
+	let mut content_length = 0;
+	loop {
+		let mut line = String::new();
+		if reader.read_line(&mut line).ok()? == 0 {
+			return None;
+		}
+		let lowercase_line = line.to_ascii_lowercase();
+		if let Some(value) = lowercase_line.strip_prefix("content-length:") {
+			content_length = value.trim().parse().ok()?;
+		}
+		head.extend_from_slice(line.as_bytes());
+		if line == "\r\n" {
+			return Some((head, content_length));
+		}
+	}
+}
+
+// Spawns a proxy in front of the given HTTP server that forwards all requests, delaying those
+// whose path contains `path_pattern` to track the maximum number of them in flight at once.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+pub fn setup_in_flight_tracking_proxy(
+	server_addr: String, path_pattern: &'static str,
+) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+	use std::io::{BufReader, Read, Write};
+	use std::net::{TcpListener, TcpStream};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let proxy_addr = listener.local_addr().unwrap();
+	let in_flight = Arc::new(AtomicUsize::new(0));
+	let max_in_flight = Arc::new(AtomicUsize::new(0));
+	let max_in_flight_ref = Arc::clone(&max_in_flight);
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let stream = match stream {
+				Ok(stream) => stream,
+				Err(_) => continue,
+			};
+			let server_addr = server_addr.clone();
+			let in_flight = Arc::clone(&in_flight);
+			let max_in_flight = Arc::clone(&max_in_flight_ref);
+			std::thread::spawn(move || {
+				let mut upstream = TcpStream::connect(&server_addr).unwrap();
+				let mut upstream_reader = BufReader::new(upstream.try_clone().unwrap());
+				let mut downstream_reader = BufReader::new(stream.try_clone().unwrap());
+				let mut downstream = stream;
+				while let Some((mut request, body_len)) = read_head(&mut downstream_reader) {
+					let mut body = vec![0u8; body_len];
+					if downstream_reader.read_exact(&mut body).is_err() {
+						return;
+					}
+					request.extend_from_slice(&body);
+
+					let tracked = String::from_utf8_lossy(&request)
+						.lines()
+						.next()
+						.map_or(false, |request_line| request_line.contains(path_pattern));
+					if tracked {
+						let num_in_flight = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+						max_in_flight.fetch_max(num_in_flight, Ordering::SeqCst);
+						std::thread::sleep(Duration::from_millis(100));
+					}
+
+					let response = upstream.write_all(&request).ok().and_then(|_| {
+						let (mut response, body_len) = read_head(&mut upstream_reader)?;
+						let mut body = vec![0u8; body_len];
+						upstream_reader.read_exact(&mut body).ok()?;
+						response.extend_from_slice(&body);
+						Some(response)
+					});
+
+					if tracked {
+						in_flight.fetch_sub(1, Ordering::SeqCst);
+					}
+					match response {
+						Some(response) if downstream.write_all(&response).is_ok() => {},
+						_ => return,
+					}
+				}
+			});
+		}
+	});
+	(format!("http://{}", proxy_addr), max_in_flight)
+}
+
+// Spawns a proxy in front of the given HTTP server that mimics a deployment with disabled mempool
+// endpoints, i.e., it responds to any requests for them with a `404 Not Found` error and forwards
+// all other requests.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+pub fn setup_mempool_less_proxy(server_addr: String) -> String {
+	setup_not_found_proxy(server_addr, |request_line| request_line.contains(" /mempool"))
+}
+
+// Spawns a proxy in front of the given HTTP server that mimics a server lagging behind on block
+// data, i.e., it responds to requests for block headers with a `404 Not Found` error as long as the
+// returned counter is non-zero, decrementing it on each such response, and forwards all other
+// requests.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+pub fn setup_lagging_header_proxy(
+	server_addr: String,
+) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+
+	let num_misses = Arc::new(AtomicUsize::new(0));
+	let proxy_misses = Arc::clone(&num_misses);
+	let proxy_url = setup_not_found_proxy(server_addr, move |request_line| {
+		let is_header_request =
+			request_line.contains(" /block/") && request_line.contains("/header ");
+		is_header_request
+			&& proxy_misses
+				.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+				.is_ok()
+	});
+	(proxy_url, num_misses)
+}
+
+// Spawns a proxy in front of the given HTTP server that responds to any requests whose request
+// line matches the given predicate with a `404 Not Found` error and forwards all other requests.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+fn setup_not_found_proxy<F>(server_addr: String, is_not_found: F) -> String
+where
+	F: Fn(&str) -> bool + Send + Sync + 'static,
+{
+	use std::io::{BufReader, Read, Write};
+	use std::net::{TcpListener, TcpStream};
+	use std::sync::Arc;
+
+	let is_not_found = Arc::new(is_not_found);
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let proxy_addr = listener.local_addr().unwrap();
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let stream = match stream {
+				Ok(stream) => stream,
+				Err(_) => continue,
+			};
+			let server_addr = server_addr.clone();
+			let is_not_found = Arc::clone(&is_not_found);
+			std::thread::spawn(move || {
+				let mut upstream = TcpStream::connect(&server_addr).unwrap();
+				let mut upstream_reader = BufReader::new(upstream.try_clone().unwrap());
+				let mut downstream_reader = BufReader::new(stream.try_clone().unwrap());
+				let mut downstream = stream;
+				while let Some((mut request, body_len)) = read_head(&mut downstream_reader) {
+					let mut body = vec![0u8; body_len];
+					if downstream_reader.read_exact(&mut body).is_err() {
+						return;
+					}
+					request.extend_from_slice(&body);
+
+					let respond_not_found = String::from_utf8_lossy(&request)
+						.lines()
+						.next()
+						.map_or(false, |request_line| is_not_found(request_line));
+					let response = if respond_not_found {
+						Some(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec())
+					} else {
+						upstream.write_all(&request).ok().and_then(|_| {
+							let (mut response, body_len) = read_head(&mut upstream_reader)?;
+							let mut body = vec![0u8; body_len];
+							upstream_reader.read_exact(&mut body).ok()?;
+							response.extend_from_slice(&body);
+							Some(response)
+						})
+					};
+
+					match response {
+						Some(response) if downstream.write_all(&response).is_ok() => {},
+						_ => return,
+					}
+				}
+			});
+		}
+	});
+	format!("http://{}", proxy_addr)
+}
+
+// Spawns a fake Esplora server that reports the given genesis block hash and responds to any other
+// request with a `503 Service Unavailable` error.
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+pub fn setup_fake_esplora_server(genesis_block_hash: BlockHash) -> String {
+	use std::io::{BufReader, Write};
+	use std::net::TcpListener;
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let server_addr = listener.local_addr().unwrap();
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let mut stream = match stream {
+				Ok(stream) => stream,
+				Err(_) => continue,
+			};
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let request = match read_head(&mut reader) {
+				Some((request, _)) => request,
+				None => continue,
+			};
+			let is_genesis_request = String::from_utf8_lossy(&request)
+				.lines()
+				.next()
+				.map_or(false, |request_line| request_line.contains(" /block-height/0 "));
+			let response = if is_genesis_request {
+				let body = genesis_block_hash.to_string();
+				format!(
+					"HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+					body.len(),
+					body
+				)
+			} else {
+				"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+					.to_string()
+			};
+			let _ = stream.write_all(response.as_bytes());
+		}
+	});
+	format!("http://{}", server_addr)
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+pub fn setup_socks5_proxy() -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+	use std::io::{Read, Write};
+	use std::net::{Ipv4Addr, TcpListener, TcpStream};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::Arc;
+
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let proxy_addr = listener.local_addr().unwrap();
+	let num_proxied = Arc::new(AtomicUsize::new(0));
+	let num_proxied_ref = Arc::clone(&num_proxied);
+	std::thread::spawn(move || {
+		for stream in listener.incoming() {
+			let mut stream = match stream {
+				Ok(stream) => stream,
+				Err(_) => continue,
+			};
+
+			// Read the greeting and always choose "no authentication".
+			let mut greeting = [0u8; 2];
+			if stream.read_exact(&mut greeting).is_err() || greeting[0] != 0x05 {
+				continue;
+			}
+			let mut methods = vec![0u8; greeting[1] as usize];
+			if stream.read_exact(&mut methods).is_err() || stream.write_all(&[0x05, 0x00]).is_err()
+			{
+				continue;
+			}
+
+			// Read the connection request, supporting IPv4 addresses and domain names.
+			let mut request = [0u8; 4];
+			if stream.read_exact(&mut request).is_err() || request[1] != 0x01 {
+				continue;
+			}
+			let host = match request[3] {
+				0x01 => {
+					let mut addr = [0u8; 4];
+					if stream.read_exact(&mut addr).is_err() {
+						continue;
+					}
+					Ipv4Addr::from(addr).to_string()
+				},
+				0x03 => {
+					let mut len = [0u8; 1];
+					if stream.read_exact(&mut len).is_err() {
+						continue;
+					}
+					let mut name = vec![0u8; len[0] as usize];
+					if stream.read_exact(&mut name).is_err() {
+						continue;
+					}
+					String::from_utf8(name).unwrap()
+				},
+				_ => continue,
+			};
+			let mut port = [0u8; 2];
+			if stream.read_exact(&mut port).is_err() {
+				continue;
+			}
+
+			let upstream = match TcpStream::connect((host.as_str(), u16::from_be_bytes(port))) {
+				Ok(upstream) => upstream,
+				Err(_) => continue,
+			};
+			if stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).is_err() {
+				continue;
+			}
+			num_proxied_ref.fetch_add(1, Ordering::SeqCst);
+
+			let mut upstream_write = upstream.try_clone().unwrap();
+			let mut downstream_read = stream.try_clone().unwrap();
+			std::thread::spawn(move || std::io::copy(&mut downstream_read, &mut upstream_write));
+			let (mut upstream_read, mut downstream_write) = (upstream, stream);
+			std::thread::spawn(move || std::io::copy(&mut upstream_read, &mut downstream_write));
+		}
+	});
+	(format!("socks5://{}", proxy_addr), num_proxied)
+}
+
 #[derive(Debug)]
 enum TestConfirmableEvent {
 	Confirmed(Txid, BlockHash, u32),
@@ -162,13 +546,35 @@ impl Confirm for TestConfirmable {
 	}
 }
 
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+#[derive(Default)]
+struct TestMetricsHook {
+	pub requests: Mutex<Vec<(RequestKind, bool)>>,
+	pub completed_syncs: Mutex<Vec<SyncStats>>,
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+impl SyncMetricsHook for TestMetricsHook {
+	fn on_request(&self, kind: RequestKind, _duration: Duration, success: bool) {
+		self.requests.lock().unwrap().push((kind, success));
+	}
+
+	fn on_sync_complete(&self, stats: &SyncStats) {
+		self.completed_syncs.lock().unwrap().push(stats.clone());
+	}
+}
+
 macro_rules! test_syncing {
 	($tx_sync: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr) => {{
 		// Check we pick up on new best blocks
 		assert_eq!($confirmable.best_block.lock().unwrap().1, 0);
+		assert_eq!($tx_sync.last_synced_tip(), None);
 
 		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
 		assert_eq!($confirmable.best_block.lock().unwrap().1, 102);
+		assert_eq!($tx_sync.last_synced_tip(), Some(*$confirmable.best_block.lock().unwrap()));
+		#[cfg(feature = "time")]
+		assert!($tx_sync.last_sync_completed_at().is_some());
 
 		let events = std::mem::take(&mut *$confirmable.events.lock().unwrap());
 		assert_eq!(events.len(), 1);
@@ -215,7 +621,11 @@ macro_rules! test_syncing {
 		assert!($confirmable.unconfirmed_txs.lock().unwrap().is_empty());
 
 		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
-		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		let stats = maybe_await!($tx_sync.sync_with_stats(vec![&$confirmable])).unwrap();
+		assert_eq!(stats.num_confirmed, 1);
+		assert_eq!(stats.num_unconfirmed, 0);
+		assert_eq!(stats.tip_hash, $bitcoind.client.get_best_block_hash().unwrap());
+		assert_eq!(stats.tip_height as u64, $bitcoind.client.get_block_count().unwrap());
 
 		let events = std::mem::take(&mut *$confirmable.events.lock().unwrap());
 		assert_eq!(events.len(), 2);
@@ -313,43 +723,2705 @@ macro_rules! test_syncing {
 		}
 
 		assert_eq!(seen_txids.len(), 0);
+
+		// Check the accumulated metrics reflect what we've seen.
+		let metrics = $tx_sync.metrics();
+		assert_eq!(metrics.total_confirmed, 4);
+		assert_eq!(metrics.total_unconfirmed, 2);
+		assert!(metrics.total_requests > 0);
+		assert_eq!(metrics.last_tip_height, Some(104));
+
+		let exported_metrics = metrics.to_prometheus();
+		assert!(exported_metrics.contains("# TYPE ldk_tx_sync_confirmed_total counter\n"));
+		assert!(exported_metrics.contains("\nldk_tx_sync_confirmed_total 4\n"));
+		assert!(exported_metrics.contains("\nldk_tx_sync_unconfirmed_total 2\n"));
+		assert!(exported_metrics.contains("\nldk_tx_sync_last_tip_height 104\n"));
+		assert!(exported_metrics.contains("\nldk_tx_sync_last_sync_duration_seconds "));
 	}};
 }
 
-#[test]
-#[cfg(feature = "esplora-blocking")]
-fn test_esplora_syncs() {
-	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
-	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
-	let mut logger = TestLogger::new();
-	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
-	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
-	let confirmable = TestConfirmable::new();
+macro_rules! test_unregistering {
+	($tx_sync: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr) => {{
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
 
-	test_syncing!(tx_sync, confirmable, bitcoind, electrsd);
+		let new_address = $bitcoind
+			.client
+			.get_new_address(Some("test"), Some(AddressType::Legacy))
+			.unwrap()
+			.assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		let unregistered_txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+
+		// Check transactions unregistered before being processed are never picked up.
+		$tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		$tx_sync.register_tx(&unregistered_txid, &new_address.payload().script_pubkey());
+		$tx_sync.unregister_tx(&unregistered_txid);
+
+		// Check transactions unregistered after being processed are dropped on the next sync.
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		$tx_sync.register_tx(&unregistered_txid, &new_address.payload().script_pubkey());
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		$tx_sync.unregister_tx(&unregistered_txid);
+
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+
+		assert!($confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+		assert!(!$confirmable.confirmed_txs.lock().unwrap().contains_key(&unregistered_txid));
+		assert_eq!($confirmable.confirmed_txs.lock().unwrap().len(), 1);
+
+		// Check unregistered outputs are no longer monitored for spends.
+		let tx_res = $bitcoind.client.get_transaction(&unregistered_txid, None).unwrap();
+		let block_hash = tx_res.info.blockhash.unwrap();
+		let tx = tx_res.transaction().unwrap();
+		let prev_outpoint = tx.input.first().unwrap().previous_output;
+		let prev_tx = $bitcoind
+			.client
+			.get_transaction(&prev_outpoint.txid, None)
+			.unwrap()
+			.transaction()
+			.unwrap();
+		let prev_script_pubkey = prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone();
+		let output = WatchedOutput {
+			block_hash: Some(block_hash),
+			outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+			script_pubkey: prev_script_pubkey,
+		};
+
+		$tx_sync.register_output(output);
+		$tx_sync.unregister_output(&prev_outpoint);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+
+		assert!(!$confirmable.confirmed_txs.lock().unwrap().contains_key(&unregistered_txid));
+		assert_eq!($confirmable.confirmed_txs.lock().unwrap().len(), 1);
+	}};
 }
 
-#[tokio::test]
-#[cfg(feature = "esplora-async")]
-async fn test_esplora_syncs() {
-	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
-	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
-	let mut logger = TestLogger::new();
-	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
-	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
-	let confirmable = TestConfirmable::new();
+macro_rules! test_overlapping_registrations {
+	($tx_sync: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr, $logger: expr, $module: expr) => {{
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
 
-	test_syncing!(tx_sync, confirmable, bitcoind, electrsd);
+		let new_address = $bitcoind
+			.client
+			.get_new_address(Some("test"), Some(AddressType::Legacy))
+			.unwrap()
+			.assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+
+		// Register both the transaction and an output it spends.
+		let tx_res = $bitcoind.client.get_transaction(&txid, None).unwrap();
+		let block_hash = tx_res.info.blockhash.unwrap();
+		let tx = tx_res.transaction().unwrap();
+		let prev_outpoint = tx.input.first().unwrap().previous_output;
+		let prev_tx = $bitcoind
+			.client
+			.get_transaction(&prev_outpoint.txid, None)
+			.unwrap()
+			.transaction()
+			.unwrap();
+		let prev_script_pubkey = prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone();
+		let output = WatchedOutput {
+			block_hash: Some(block_hash),
+			outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+			script_pubkey: prev_script_pubkey,
+		};
+		$tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		$tx_sync.register_output(output);
+
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+
+		// Check the transaction was confirmed exactly once and we didn't look up the spend again.
+		let events = std::mem::take(&mut *$confirmable.events.lock().unwrap());
+		let num_confirmed = events
+			.iter()
+			.filter(|e| matches!(e, TestConfirmableEvent::Confirmed(t, _, _) if *t == txid))
+			.count();
+		assert_eq!(num_confirmed, 1);
+		assert!($confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+		$logger.assert_log_contains($module, "Skipping spend lookup for output", 1);
+	}};
 }
 
-#[test]
-#[cfg(feature = "electrum")]
-fn test_electrum_syncs() {
-	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
-	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
-	let mut logger = TestLogger::new();
-	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
-	let tx_sync = ElectrumSyncClient::new(electrum_url, &mut logger).unwrap();
-	let confirmable = TestConfirmable::new();
-	test_syncing!(tx_sync, confirmable, bitcoind, electrsd);
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_rebroadcasting {
+	($tx_sync: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr) => {{
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+
+		let new_address = $bitcoind
+			.client
+			.get_new_address(Some("test"), Some(AddressType::Legacy))
+			.unwrap()
+			.assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		let tx = $bitcoind.client.get_raw_transaction(&txid, None).unwrap();
+		$tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+
+		// Transactions we're not watching are never rebroadcast.
+		let unrelated_txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		let unrelated_tx = $bitcoind.client.get_raw_transaction(&unrelated_txid, None).unwrap();
+
+		// The watched transaction is still unconfirmed after syncing, so we rebroadcast it.
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert!($confirmable.confirmed_txs.lock().unwrap().is_empty());
+		let txs = vec![tx.clone(), unrelated_tx.clone()];
+		assert_eq!(maybe_await!($tx_sync.rebroadcast_unconfirmed_transactions(&txs)).unwrap(), 1);
+
+		// Once it confirmed, there's nothing left to rebroadcast.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert!($confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+		assert_eq!(maybe_await!($tx_sync.rebroadcast_unconfirmed_transactions(&txs)).unwrap(), 0);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+macro_rules! test_broadcasting {
+	($tx_sync: expr, $bitcoind: expr) => {{
+		use electrsd::bitcoind::bitcoincore_rpc::bitcoincore_rpc_json::{
+			CreateRawTransactionInput, SignRawTransactionInput,
+		};
+
+		// Create a parent transaction and a child spending it, e.g., a commitment transaction and
+		// an anchor spend, neither of which are known to the network yet.
+		let parent_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let parent_script_pubkey = parent_address.payload().script_pubkey();
+		let parent_outputs =
+			HashMap::from([(parent_address.to_string(), Amount::from_sat(100_000))]);
+		let unfunded_parent =
+			$bitcoind.client.create_raw_transaction(&[], &parent_outputs, None, None).unwrap();
+		let funded_parent = $bitcoind
+			.client
+			.fund_raw_transaction(&unfunded_parent, None, None)
+			.unwrap()
+			.transaction()
+			.unwrap();
+		let parent = $bitcoind
+			.client
+			.sign_raw_transaction_with_wallet(&funded_parent, None, None)
+			.unwrap()
+			.transaction()
+			.unwrap();
+		let vout =
+			parent.output.iter().position(|o| o.script_pubkey == parent_script_pubkey).unwrap();
+
+		let child_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let child_inputs =
+			[CreateRawTransactionInput { txid: parent.txid(), vout: vout as u32, sequence: None }];
+		let child_outputs = HashMap::from([(child_address.to_string(), Amount::from_sat(99_000))]);
+		let unsigned_child = $bitcoind
+			.client
+			.create_raw_transaction(&child_inputs, &child_outputs, None, None)
+			.unwrap();
+		let prev_outputs = [SignRawTransactionInput {
+			txid: parent.txid(),
+			vout: vout as u32,
+			script_pub_key: parent_script_pubkey,
+			redeem_script: None,
+			amount: Some(parent.output[vout].value),
+		}];
+		let child = $bitcoind
+			.client
+			.sign_raw_transaction_with_wallet(&unsigned_child, Some(&prev_outputs), None)
+			.unwrap()
+			.transaction()
+			.unwrap();
+
+		// Check both land in the mempool, even if handed to us in the wrong order.
+		$tx_sync.broadcast_transactions(&[&child, &parent]);
+		#[cfg(feature = "esplora-async")]
+		$tx_sync.process_broadcast_queue().await;
+		let mempool = $bitcoind.client.get_raw_mempool().unwrap();
+		assert!(mempool.contains(&parent.txid()));
+		assert!(mempool.contains(&child.txid()));
+		assert!($tx_sync.take_failed_broadcasts().is_empty());
+
+		// Check we keep track of failed broadcasts, so that they may be retried.
+		let mut invalid_tx = child.clone();
+		invalid_tx.input[0].previous_output.vout = 42;
+		$tx_sync.broadcast_transactions(&[&invalid_tx]);
+		#[cfg(feature = "esplora-async")]
+		$tx_sync.process_broadcast_queue().await;
+		assert_eq!($tx_sync.take_failed_broadcasts(), vec![invalid_tx]);
+		assert!($tx_sync.take_failed_broadcasts().is_empty());
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_tip_fetching {
+	($tx_sync: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr) => {{
+		let stats = maybe_await!($tx_sync.sync_with_stats(vec![&$confirmable])).unwrap();
+		assert_eq!(maybe_await!($tx_sync.get_tip_hash()).unwrap(), stats.tip_hash);
+		assert_eq!(maybe_await!($tx_sync.get_tip_height()).unwrap(), stats.tip_height);
+		assert_eq!(*$confirmable.best_block.lock().unwrap(), (stats.tip_hash, stats.tip_height));
+
+		// Check we see the new tip before syncing to it.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let tip_hash = maybe_await!($tx_sync.get_tip_hash()).unwrap();
+		let tip_height = maybe_await!($tx_sync.get_tip_height()).unwrap();
+		assert_eq!(tip_hash, $bitcoind.client.get_best_block_hash().unwrap());
+		assert_eq!(tip_height, stats.tip_height + 1);
+
+		let stats = maybe_await!($tx_sync.sync_with_stats(vec![&$confirmable])).unwrap();
+		assert_eq!(stats.tip_hash, tip_hash);
+		assert_eq!(stats.tip_height, tip_height);
+		assert_eq!(*$confirmable.best_block.lock().unwrap(), (tip_hash, tip_height));
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_validating_scripts {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let config = SyncConfig { validate_scripts: true, ..SyncConfig::default() };
+		let tx_sync = EsploraSyncClient::new_with_config(esplora_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Register a transaction with the script it actually pays to and one with a mismatching
+		// script.
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let other_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		let second_txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		tx_sync.register_tx(&second_txid, &other_address.payload().script_pubkey());
+
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		assert!(maybe_await!(tx_sync.sync(vec![&confirmable])).is_err());
+		assert!(confirmable.confirmed_txs.lock().unwrap().is_empty());
+
+		// Once the offending transaction is dropped, we're able to sync again.
+		tx_sync.unregister_tx(&second_txid);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+		assert!(!confirmable.confirmed_txs.lock().unwrap().contains_key(&second_txid));
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_delivering_in_descending_order {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let config =
+			SyncConfig { delivery_order: DeliveryOrder::Descending, ..SyncConfig::default() };
+		let tx_sync = EsploraSyncClient::new_with_config(esplora_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Have two registered transactions confirm in subsequent blocks.
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let first_txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&first_txid, &new_address.payload().script_pubkey());
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+
+		let second_txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&second_txid, &new_address.payload().script_pubkey());
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+
+		// Check the newer confirmation is delivered first.
+		confirmable.events.lock().unwrap().clear();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		let confirmed_txids = confirmable
+			.events
+			.lock()
+			.unwrap()
+			.iter()
+			.filter_map(|event| match event {
+				TestConfirmableEvent::Confirmed(txid, _, _) => Some(*txid),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(confirmed_txids, vec![second_txid, first_txid]);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+macro_rules! test_reporting_request_metrics {
+	($tx_sync: expr, $hook: expr, $num_failed_requests: expr) => {{
+		let confirmable = TestConfirmable::new();
+		let stats = maybe_await!($tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+
+		// Check every request we counted was reported to the hook, as was the completed sync.
+		let requests = $hook.requests.lock().unwrap().clone();
+		assert_eq!(requests.len() as u64, $tx_sync.metrics().total_requests);
+		assert!(requests.iter().any(|(kind, success)| *kind == RequestKind::Tip && *success));
+		assert_eq!(requests.iter().filter(|(_, success)| !success).count(), $num_failed_requests);
+		assert_eq!(*$hook.completed_syncs.lock().unwrap(), vec![stats]);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_caching_block_statuses {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let hook = std::sync::Arc::new(TestMetricsHook::default());
+		let logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let tx_sync = EsploraSyncClientBuilder::new(esplora_url)
+			.metrics_hook(hook.clone())
+			.build(&logger)
+			.unwrap();
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Have several registered transactions confirm in the same block.
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let mut txids = Vec::new();
+		for _ in 0..3 {
+			let txid = $bitcoind
+				.client
+				.send_to_address(
+					&new_address,
+					Amount::from_sat(5000),
+					None,
+					None,
+					None,
+					None,
+					None,
+					None,
+				)
+				.unwrap();
+			tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+			txids.push(txid);
+		}
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_confirmed, 3);
+		let conf_block_hash = confirmable.confirmed_txs.lock().unwrap().get(&txids[0]).unwrap().0;
+		for txid in &txids {
+			assert_eq!(
+				confirmable.confirmed_txs.lock().unwrap().get(txid).unwrap().0,
+				conf_block_hash
+			);
+		}
+
+		// Check that on the next tip we only look up the status of the new tip and, once, of the
+		// block the transactions confirmed in.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		hook.requests.lock().unwrap().clear();
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_unconfirmed, 0);
+		assert_eq!(stats.num_restarts, 0);
+		let num_block_status_requests = hook
+			.requests
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(kind, _)| *kind == RequestKind::BlockStatus)
+			.count();
+		assert_eq!(num_block_status_requests, 2);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_rate_limiting_requests {
+	($electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let rate_limit = RateLimit { requests_per_second: 20, burst: 1 };
+		let config = SyncConfig { rate_limit: Some(rate_limit), ..SyncConfig::default() };
+		let tx_sync = EsploraSyncClient::new_with_config(esplora_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+
+		// With a burst of a single request, each further request has to wait for a new token.
+		let start = std::time::Instant::now();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		let num_requests = tx_sync.metrics().total_requests as u32;
+		assert!(num_requests > 1);
+		assert!(start.elapsed() >= Duration::from_millis(50) * (num_requests - 1));
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_switching_server_url {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let tx_sync = EsploraSyncClient::new(esplora_url.clone(), &mut logger);
+		let confirmable = TestConfirmable::new();
+
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+
+		// Check we stick with the previous server if we can't reach the new one.
+		assert!(maybe_await!(tx_sync.set_server_url("http://127.0.0.1:1".to_string())).is_err());
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Check we refuse to switch to a server on another network.
+		tx_sync.check_network(Network::Bitcoin);
+		match maybe_await!(tx_sync.set_server_url(esplora_url.clone())) {
+			Err(TxSyncError::WrongNetwork) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		tx_sync.check_network(Network::Regtest);
+
+		// Check the registered items are retained and re-checked against the new server, without
+		// reporting them again.
+		maybe_await!(tx_sync.set_server_url(esplora_url)).unwrap();
+		let num_requests = tx_sync.metrics().total_requests;
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert!(tx_sync.metrics().total_requests > num_requests + 1);
+		assert_eq!(stats.num_confirmed, 0);
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert_eq!(
+			confirmable.best_block.lock().unwrap().1 as u64,
+			$bitcoind.client.get_block_count().unwrap()
+		);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_requiring_min_confirmations {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let config = SyncConfig { min_confirmations: 3, ..SyncConfig::default() };
+		let tx_sync = EsploraSyncClient::new_with_config(esplora_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+
+		// Check the confirmation is withheld while the new tips are still delivered.
+		for _ in 0..2 {
+			generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+			let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+			assert_eq!(stats.num_confirmed, 0);
+			assert_eq!(
+				confirmable.best_block.lock().unwrap().1 as u64,
+				$bitcoind.client.get_block_count().unwrap()
+			);
+			assert!(confirmable.confirmed_txs.lock().unwrap().is_empty());
+		}
+
+		// Check it's reported once it reached the required depth.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_confirmed, 1);
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_counting_confirmations {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let inclusive_sync = EsploraSyncClient::new(esplora_url.clone(), &mut logger);
+		let config = SyncConfig {
+			confirmation_counting: ConfirmationCounting::FromNextBlock,
+			..SyncConfig::default()
+		};
+		let from_next_block_sync =
+			EsploraSyncClient::new_with_config(esplora_url, config, &mut logger);
+		assert_eq!(inclusive_sync.confirmations_for(0), None);
+		assert_eq!(from_next_block_sync.confirmations_for(0), None);
+
+		let confirmable = TestConfirmable::new();
+		maybe_await!(inclusive_sync.sync(vec![&confirmable])).unwrap();
+		maybe_await!(from_next_block_sync.sync(vec![&confirmable])).unwrap();
+
+		// Check both conventions are counted off the same synced tip.
+		let tip_height = $bitcoind.client.get_block_count().unwrap() as u32;
+		assert_eq!(inclusive_sync.last_synced_tip().unwrap().1, tip_height);
+		assert_eq!(from_next_block_sync.last_synced_tip().unwrap().1, tip_height);
+		assert_eq!(inclusive_sync.confirmations_for(tip_height), Some(1));
+		assert_eq!(from_next_block_sync.confirmations_for(tip_height), Some(0));
+		assert_eq!(inclusive_sync.confirmations_for(tip_height - 5), Some(6));
+		assert_eq!(from_next_block_sync.confirmations_for(tip_height - 5), Some(5));
+		assert_eq!(inclusive_sync.confirmations_for(tip_height + 1), Some(0));
+		assert_eq!(from_next_block_sync.confirmations_for(tip_height + 1), Some(0));
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_forcing_resync {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		let (conf_block_hash, conf_height) =
+			*confirmable.confirmed_txs.lock().unwrap().get(&txid).unwrap();
+
+		// Without a new block, a sync doesn't deliver the confirmation again.
+		confirmable.events.lock().unwrap().clear();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert!(confirmable.events.lock().unwrap().is_empty());
+
+		// But it does once we forced a resync.
+		maybe_await!(tx_sync.force_resync());
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_confirmed, 1);
+		let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+		assert!(events.iter().any(|event| match event {
+			TestConfirmableEvent::Confirmed(confirmed_txid, block_hash, height) => {
+				*confirmed_txid == txid && *block_hash == conf_block_hash && *height == conf_height
+			},
+			_ => false,
+		}));
+
+		// The resync is only conducted once.
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert!(confirmable.events.lock().unwrap().is_empty());
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_detecting_replacements {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let config = SyncConfig { detect_mempool_replacements: true, ..SyncConfig::default() };
+		let tx_sync = EsploraSyncClient::new_with_config(esplora_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Watch an output that is spent by an unconfirmed transaction.
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		wait_for_tx(&$electrsd, txid);
+
+		let tx = $bitcoind.client.get_transaction(&txid, None).unwrap().transaction().unwrap();
+		let prev_outpoint = tx.input.first().unwrap().previous_output;
+		let prev_tx_res = $bitcoind.client.get_transaction(&prev_outpoint.txid, None).unwrap();
+		let prev_tx = prev_tx_res.transaction().unwrap();
+		let output = WatchedOutput {
+			block_hash: prev_tx_res.info.blockhash,
+			outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+			script_pubkey: prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone(),
+		};
+		tx_sync.register_output(output);
+
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert!(stats.replaced_transactions.is_empty());
+
+		// Replace the spend and check we detect it.
+		let bumped_txid = $bitcoind.client.bump_fee(&txid, None).unwrap().txid.unwrap();
+		assert_ne!(bumped_txid, txid);
+		wait_for_tx(&$electrsd, bumped_txid);
+
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.replaced_transactions, vec![txid]);
+
+		// We only report the replacement once.
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert!(stats.replaced_transactions.is_empty());
+
+		// Once the replacement confirms, nothing is reported as replaced.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert!(stats.replaced_transactions.is_empty());
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&bumped_txid));
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_syncing_without_mempool {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_addr = $electrsd.esplora_url.as_ref().unwrap().to_string();
+		let esplora_url = format!("http://{}", esplora_addr);
+		let config = SyncConfig { detect_mempool_replacements: true, ..SyncConfig::default() };
+
+		// A fully-featured server is detected as such.
+		let tx_sync = EsploraSyncClient::new_with_config(esplora_url, config, &mut logger);
+		assert!(maybe_await!(tx_sync.supports_mempool()).unwrap());
+
+		// A server lacking the mempool endpoints is detected once, and the result is cached.
+		let proxy_url = setup_mempool_less_proxy(esplora_addr);
+		let tx_sync = EsploraSyncClient::new_with_config(proxy_url, config, &mut logger);
+		assert!(!maybe_await!(tx_sync.supports_mempool()).unwrap());
+		let num_requests = tx_sync.metrics().total_requests;
+		assert!(!maybe_await!(tx_sync.supports_mempool()).unwrap());
+		assert_eq!(tx_sync.metrics().total_requests, num_requests);
+
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		let tx = $bitcoind.client.get_raw_transaction(&txid, None).unwrap();
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+
+		// Mempool features fail with a dedicated error...
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert!(stats.replaced_transactions.is_empty());
+		match maybe_await!(tx_sync.rebroadcast_unconfirmed_transactions(&[tx])) {
+			Err(TxSyncError::MempoolUnsupported) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+
+		// ... while we still sync confirmed data.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_proxying {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let (proxy_url, num_proxied) = setup_socks5_proxy();
+		let tx_sync = EsploraSyncClientBuilder::new(esplora_url.clone())
+			.proxy(proxy_url)
+			.build(&mut logger)
+			.unwrap();
+		let confirmable = TestConfirmable::new();
+
+		// Check we sync successfully with all requests being routed through the proxy.
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		let tip_height = $bitcoind.client.get_block_count().unwrap() as u32;
+		assert_eq!(confirmable.best_block.lock().unwrap().1, tip_height);
+		assert!(num_proxied.load(std::sync::atomic::Ordering::SeqCst) > 0);
+
+		// Check we report a dedicated error if the proxy is unreachable.
+		let unused_addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let config = SyncConfig { max_retries: 0, ..SyncConfig::default() };
+		let tx_sync = EsploraSyncClientBuilder::new(esplora_url)
+			.proxy(format!("socks5://{}", unused_addr))
+			.config(config)
+			.build(&mut logger)
+			.unwrap();
+		match maybe_await!(tx_sync.sync(vec![&confirmable])) {
+			Err(e @ TxSyncError::Connection { .. }) => {
+				assert!(std::error::Error::source(&e).is_some());
+			},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_bounding_merkle_block_requests {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_addr = $electrsd.esplora_url.as_ref().unwrap().to_string();
+		let (proxy_url, max_in_flight) =
+			setup_in_flight_tracking_proxy(esplora_addr, "merkleblock-proof");
+		let config = SyncConfig {
+			max_concurrent_requests: 8,
+			max_concurrent_merkle_block_requests: 2,
+			..SyncConfig::default()
+		};
+		let tx_sync = EsploraSyncClient::new_with_config(proxy_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Register more transactions than we're allowed to fetch Merkle blocks for at once.
+		let mut txids = Vec::new();
+		for _ in 0..5 {
+			let new_address =
+				$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+			let txid = $bitcoind
+				.client
+				.send_to_address(
+					&new_address,
+					Amount::from_sat(5000),
+					None,
+					None,
+					None,
+					None,
+					None,
+					None,
+				)
+				.unwrap();
+			tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+			txids.push(txid);
+		}
+
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		for txid in &txids {
+			assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(txid));
+		}
+
+		// Check the Merkle block requests were bounded independently of the status requests.
+		let max_in_flight = max_in_flight.load(std::sync::atomic::Ordering::SeqCst);
+		assert!(max_in_flight > 0);
+		assert!(max_in_flight <= 2);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_bounding_output_status_requests {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let mut logger = TestLogger::new();
+		let esplora_addr = $electrsd.esplora_url.as_ref().unwrap().to_string();
+		let (proxy_url, max_in_flight) = setup_in_flight_tracking_proxy(esplora_addr, "outspend");
+		let config = SyncConfig { max_concurrent_requests: 2, ..SyncConfig::default() };
+		let tx_sync = EsploraSyncClient::new_with_config(proxy_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+
+		// Register more outputs than we're allowed to look up at once, all of which get spent.
+		let mut spending_txids = Vec::new();
+		for _ in 0..5 {
+			let new_address =
+				$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+			let txid = $bitcoind
+				.client
+				.send_to_address(
+					&new_address,
+					Amount::from_sat(5000),
+					None,
+					None,
+					None,
+					None,
+					None,
+					None,
+				)
+				.unwrap();
+			let tx = $bitcoind.client.get_transaction(&txid, None).unwrap().transaction().unwrap();
+			let prev_outpoint = tx.input.first().unwrap().previous_output;
+			let prev_tx_res = $bitcoind.client.get_transaction(&prev_outpoint.txid, None).unwrap();
+			let prev_tx = prev_tx_res.transaction().unwrap();
+			let output = WatchedOutput {
+				block_hash: prev_tx_res.info.blockhash,
+				outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+				script_pubkey: prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone(),
+			};
+			tx_sync.register_output(output);
+			spending_txids.push(txid);
+		}
+
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		for spending_txid in &spending_txids {
+			assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(spending_txid));
+		}
+
+		// Check the spending transactions were handed to the confirmable in order.
+		let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+		let confirmations = events
+			.iter()
+			.filter_map(|e| match e {
+				TestConfirmableEvent::Confirmed(txid, _, height) => Some((*txid, *height)),
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(confirmations.len(), spending_txids.len());
+		assert!(confirmations.windows(2).all(|w| w[0].1 <= w[1].1));
+
+		let max_in_flight = max_in_flight.load(std::sync::atomic::Ordering::SeqCst);
+		assert!(max_in_flight > 0);
+		assert!(max_in_flight <= 2);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_estimating_fees {
+	($electrsd: expr) => {{
+		let targets = [
+			ConfirmationTarget::OnChainSweep,
+			ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
+			ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+			ConfirmationTarget::AnchorChannelFee,
+			ConfirmationTarget::NonAnchorChannelFee,
+			ConfirmationTarget::ChannelCloseMinimum,
+			ConfirmationTarget::OutputSpendingFee,
+		];
+
+		// Check we always return sane estimates, no matter whether the server has any.
+		let mut logger = TestLogger::new();
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let fee_estimator =
+			EsploraFeeEstimator::new(esplora_url, Duration::from_secs(60), &mut logger);
+		assert!(fee_estimator.is_cache_expired());
+		let _ = maybe_await!(fee_estimator.update_fees());
+		let fee_rates = targets
+			.iter()
+			.map(|t| fee_estimator.get_est_sat_per_1000_weight(*t))
+			.collect::<Vec<_>>();
+		assert!(fee_rates.iter().all(|fee_rate| *fee_rate >= FEERATE_FLOOR_SATS_PER_KW));
+
+		// Check we fall back to static defaults if the server is unreachable.
+		let mut logger = TestLogger::new();
+		let unused_addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let fee_estimator = EsploraFeeEstimator::new(
+			format!("http://{}", unused_addr),
+			Duration::from_secs(60),
+			&mut logger,
+		);
+		assert!(maybe_await!(fee_estimator.update_fees()).is_err());
+		assert!(fee_estimator.is_cache_expired());
+		for target in targets {
+			assert!(fee_estimator.get_est_sat_per_1000_weight(target) >= FEERATE_FLOOR_SATS_PER_KW);
+		}
+		assert!(
+			fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::OnChainSweep)
+				> fee_estimator.get_est_sat_per_1000_weight(
+					ConfirmationTarget::MinAllowedAnchorChannelRemoteFee
+				)
+		);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+macro_rules! test_checking_network {
+	($tx_sync: expr, $confirmable: expr) => {{
+		// Check we refuse to sync from a server on another network.
+		$tx_sync.check_network(Network::Bitcoin);
+		match maybe_await!($tx_sync.sync(vec![&$confirmable])) {
+			Err(TxSyncError::WrongNetwork) => {},
+			res => panic!("Unexpected sync result: {:?}", res),
+		}
+		assert_eq!($confirmable.best_block.lock().unwrap().1, 0);
+
+		$tx_sync.check_network(Network::Regtest);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert_eq!($confirmable.best_block.lock().unwrap().1, 102);
+
+		// Check we don't repeat the check once it succeeded, i.e., only look up the tip.
+		let num_requests = $tx_sync.metrics().total_requests;
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert_eq!($tx_sync.metrics().total_requests, num_requests + 1);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+macro_rules! test_tagging_outputs {
+	($tx_sync: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr) => {{
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+
+		// Watch an output spent by the transaction, tagged with some application context.
+		let tx_res = $bitcoind.client.get_transaction(&txid, None).unwrap();
+		let tx = tx_res.transaction().unwrap();
+		let prev_outpoint = tx.input.first().unwrap().previous_output;
+		let prev_tx_res = $bitcoind.client.get_transaction(&prev_outpoint.txid, None).unwrap();
+		let prev_tx = prev_tx_res.transaction().unwrap();
+		let output = WatchedOutput {
+			block_hash: prev_tx_res.info.blockhash,
+			outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+			script_pubkey: prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone(),
+		};
+		$tx_sync.register_output_with_tag(output, 42);
+		assert!(maybe_await!($tx_sync.spent_outputs()).is_empty());
+
+		// Check the tag is returned alongside the detected spend.
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		let conf_height = $confirmable.best_block.lock().unwrap().1;
+		let expected_spend = SpentOutput {
+			outpoint: prev_outpoint,
+			spending_txid: txid,
+			confirmation_height: conf_height,
+			tag: Some(42),
+		};
+		assert_eq!(maybe_await!($tx_sync.spent_outputs()), vec![expected_spend]);
+
+		// Spends are only reported once.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert!(maybe_await!($tx_sync.spent_outputs()).is_empty());
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+macro_rules! test_detecting_script_matches {
+	($tx_sync: expr, $bitcoind: expr, $electrsd: expr) => {{
+		use bitcoin::hashes::Hash;
+
+		let confirmable = TestConfirmable::new();
+		maybe_await!($tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Watch a transaction that never confirms, e.g., as another one spending the same inputs
+		// confirmed instead.
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let script_pubkey = new_address.payload().script_pubkey();
+		let watched_txid = Txid::from_byte_array([42; 32]);
+		$tx_sync.register_tx(&watched_txid, &script_pubkey);
+		let stats = maybe_await!($tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_confirmed, 0);
+
+		// Check a confirmed transaction paying to the registered script is reported.
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		wait_for_tx(&$electrsd, txid);
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let stats = maybe_await!($tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_confirmed, 1);
+		let tip_hash = $bitcoind.client.get_best_block_hash().unwrap();
+		let tip_height = $bitcoind.client.get_block_count().unwrap() as u32;
+		assert_eq!(
+			confirmable.confirmed_txs.lock().unwrap().get(&txid),
+			Some(&(tip_hash, tip_height))
+		);
+		assert!(!confirmable.confirmed_txs.lock().unwrap().contains_key(&watched_txid));
+
+		// Check it's not delivered again on the next sync.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let stats = maybe_await!($tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_confirmed, 0);
+		assert_eq!(stats.num_unconfirmed, 0);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+macro_rules! test_streaming_watch_events {
+	($tx_sync: expr, $receiver: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr) => {{
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert!($receiver.try_recv().is_err());
+
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+
+		// Watch the transaction as well as an output it spends.
+		let tx_res = $bitcoind.client.get_transaction(&txid, None).unwrap();
+		let tx = tx_res.transaction().unwrap();
+		let prev_outpoint = tx.input.first().unwrap().previous_output;
+		let prev_tx_res = $bitcoind.client.get_transaction(&prev_outpoint.txid, None).unwrap();
+		let prev_tx = prev_tx_res.transaction().unwrap();
+		let output = WatchedOutput {
+			block_hash: prev_tx_res.info.blockhash,
+			outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+			script_pubkey: prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone(),
+		};
+		$tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		$tx_sync.register_output(output);
+
+		// Registrations are only reported once they were processed by a sync.
+		assert!($receiver.try_recv().is_err());
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert_eq!(
+			$receiver.try_iter().collect::<Vec<_>>(),
+			vec![
+				WatchEvent::Added(WatchedItem::Transaction(txid)),
+				WatchEvent::Added(WatchedItem::Output(prev_outpoint)),
+			]
+		);
+
+		// Check the confirmation and the spend are reported once the transaction confirmed.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		let conf_height = $confirmable.best_block.lock().unwrap().1;
+		assert_eq!(
+			$receiver.try_iter().collect::<Vec<_>>(),
+			vec![
+				WatchEvent::Confirmed { txid, confirmation_height: conf_height },
+				WatchEvent::Spent {
+					outpoint: prev_outpoint,
+					spending_txid: txid,
+					confirmation_height: conf_height,
+				},
+			]
+		);
+
+		// Check we report forgetting about the output once the spend is buried deep enough.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 4);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert!($receiver.try_recv().is_err());
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert_eq!(
+			$receiver.try_iter().collect::<Vec<_>>(),
+			vec![WatchEvent::Pruned { outpoint: prev_outpoint }]
+		);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_inspecting_watched_items {
+	($tx_sync: expr, $confirmable: expr, $bitcoind: expr, $electrsd: expr) => {{
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		let tx_res = $bitcoind.client.get_transaction(&txid, None).unwrap();
+		let tx = tx_res.transaction().unwrap();
+		let prev_outpoint = tx.input.first().unwrap().previous_output;
+		let prev_tx_res = $bitcoind.client.get_transaction(&prev_outpoint.txid, None).unwrap();
+		let prev_tx = prev_tx_res.transaction().unwrap();
+		let output = WatchedOutput {
+			block_hash: prev_tx_res.info.blockhash,
+			outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+			script_pubkey: prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone(),
+		};
+
+		// Registrations are reported as pending until the next sync.
+		$tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		$tx_sync.register_output_with_tag(output.clone(), 42);
+		let watched_txs = maybe_await!($tx_sync.watched_transactions());
+		assert_eq!(watched_txs.pending, vec![txid]);
+		assert!(watched_txs.active.is_empty());
+		let watched_outputs = maybe_await!($tx_sync.watched_outputs());
+		assert_eq!(watched_outputs.pending, vec![output.clone()]);
+		assert!(watched_outputs.active.is_empty());
+
+		// Unconfirmed items are actively watched once synced.
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		let watched_txs = maybe_await!($tx_sync.watched_transactions());
+		assert!(watched_txs.pending.is_empty());
+		assert_eq!(watched_txs.active, vec![txid]);
+		let watched_outputs = maybe_await!($tx_sync.watched_outputs());
+		assert!(watched_outputs.pending.is_empty());
+		assert_eq!(watched_outputs.active, vec![output]);
+
+		// Once confirmed, the transaction is dropped, while the spent output is kept around until
+		// the spend is buried deep enough.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		let conf_height = $confirmable.best_block.lock().unwrap().1;
+		assert!(maybe_await!($tx_sync.watched_transactions()).active.is_empty());
+		let watched_outputs = maybe_await!($tx_sync.watched_outputs());
+		assert!(watched_outputs.active.is_empty());
+		let expected_spend = SpentOutput {
+			outpoint: prev_outpoint,
+			spending_txid: txid,
+			confirmation_height: conf_height,
+			tag: Some(42),
+		};
+		assert_eq!(watched_outputs.awaiting_prune, vec![expected_spend]);
+
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 5);
+		maybe_await!($tx_sync.sync(vec![&$confirmable])).unwrap();
+		assert!(maybe_await!($tx_sync.watched_outputs()).awaiting_prune.is_empty());
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async", feature = "electrum"))]
+macro_rules! test_persisting_sync_state {
+	($new_tx_sync: expr, $bitcoind: expr, $electrsd: expr) => {{
+		let kv_store = TestStore::new(false);
+		let confirmable = TestConfirmable::new();
+
+		// Check there's nothing to restore before we persisted anything.
+		let tx_sync = $new_tx_sync;
+		assert!(!maybe_await!(tx_sync.restore_sync_state(&kv_store)).unwrap());
+
+		let new_address = $bitcoind
+			.client
+			.get_new_address(Some("test"), Some(AddressType::Legacy))
+			.unwrap()
+			.assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		maybe_await!(tx_sync.persist_sync_state(&kv_store)).unwrap();
+
+		// Check that after restarting we don't need to sync again if the tip didn't change and
+		// only already-watched items were registered.
+		let tx_sync = $new_tx_sync;
+		assert!(maybe_await!(tx_sync.restore_sync_state(&kv_store)).unwrap());
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		let num_requests = tx_sync.metrics().total_requests;
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert_eq!(tx_sync.metrics().total_requests, num_requests + 1);
+
+		// Check the restored transaction is still watched.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		let (conf_block_hash, _) = *confirmable.confirmed_txs.lock().unwrap().get(&txid).unwrap();
+		maybe_await!(tx_sync.persist_sync_state(&kv_store)).unwrap();
+
+		// Reorg out the block confirming the transaction while we're offline and check we discard
+		// the persisted state after restarting.
+		$bitcoind.client.invalidate_block(&conf_block_hash).unwrap();
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 2);
+		let tx_sync = $new_tx_sync;
+		assert!(!maybe_await!(tx_sync.restore_sync_state(&kv_store)).unwrap());
+
+		// Check the full resync picks up on the reorg and the transaction's reconfirmation.
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.num_unconfirmed, 1);
+		assert_eq!(stats.num_confirmed, 1);
+		assert_eq!(stats.tip_hash, $bitcoind.client.get_best_block_hash().unwrap());
+		let (reconf_block_hash, _) = *confirmable.confirmed_txs.lock().unwrap().get(&txid).unwrap();
+		assert_ne!(reconf_block_hash, conf_block_hash);
+	}};
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_syncs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_syncing!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_syncs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_syncing!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_fetches_tip() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_tip_fetching!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_fetches_tip() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_tip_fetching!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_validates_scripts() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_validating_scripts!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_validates_scripts() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_validating_scripts!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_detects_replacements() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_detecting_replacements!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_detects_replacements() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_detecting_replacements!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_delivers_in_descending_order() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_delivering_in_descending_order!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_delivers_in_descending_order() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_delivering_in_descending_order!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_reports_request_metrics() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	// Have the first request fail, so that we can check failed attempts are reported, too.
+	let esplora_addr = electrsd.esplora_url.as_ref().unwrap().to_string();
+	let proxy_url = setup_flaky_proxy(esplora_addr, 1);
+	let hook = std::sync::Arc::new(TestMetricsHook::default());
+	let logger = TestLogger::new();
+	let tx_sync =
+		EsploraSyncClientBuilder::new(proxy_url).metrics_hook(hook.clone()).build(&logger).unwrap();
+
+	test_reporting_request_metrics!(tx_sync, hook, 1);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_reports_request_metrics() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	// Have the first request fail, so that we can check failed attempts are reported, too.
+	let esplora_addr = electrsd.esplora_url.as_ref().unwrap().to_string();
+	let proxy_url = setup_flaky_proxy(esplora_addr, 1);
+	let hook = std::sync::Arc::new(TestMetricsHook::default());
+	let logger = TestLogger::new();
+	let tx_sync =
+		EsploraSyncClientBuilder::new(proxy_url).metrics_hook(hook.clone()).build(&logger).unwrap();
+
+	test_reporting_request_metrics!(tx_sync, hook, 1);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_caches_block_statuses() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_caching_block_statuses!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_caches_block_statuses() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_caching_block_statuses!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_rate_limits_requests() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_rate_limiting_requests!(electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_rate_limits_requests() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_rate_limiting_requests!(electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_switches_server_url() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_switching_server_url!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_switches_server_url() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_switching_server_url!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_requires_min_confirmations() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_requiring_min_confirmations!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_requires_min_confirmations() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_requiring_min_confirmations!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_counts_confirmations() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_counting_confirmations!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_counts_confirmations() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_counting_confirmations!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_forces_resync() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_forcing_resync!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_forces_resync() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_forcing_resync!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_syncs_without_mempool() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_syncing_without_mempool!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_syncs_without_mempool() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_syncing_without_mempool!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_uses_proxy() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_proxying!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_uses_proxy() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_proxying!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_bounds_merkle_block_requests() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_bounding_merkle_block_requests!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_bounds_merkle_block_requests() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_bounding_merkle_block_requests!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_bounds_output_status_requests() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_bounding_output_status_requests!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_bounds_output_status_requests() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_bounding_output_status_requests!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_estimates_fees() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_estimating_fees!(electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_estimates_fees() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_estimating_fees!(electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_rebroadcasts_unconfirmed() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_rebroadcasting!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_rebroadcasts_unconfirmed() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_rebroadcasting!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_broadcasts() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+
+	test_broadcasting!(tx_sync, bitcoind);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_broadcasts() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+
+	test_broadcasting!(tx_sync, bitcoind);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_unregisters() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_unregistering!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_unregisters() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_unregistering!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_overlapping_registrations() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &logger);
+	let confirmable = TestConfirmable::new();
+
+	test_overlapping_registrations!(
+		tx_sync,
+		confirmable,
+		bitcoind,
+		electrsd,
+		logger,
+		"lightning_transaction_sync::esplora"
+	);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_overlapping_registrations() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &logger);
+	let confirmable = TestConfirmable::new();
+
+	test_overlapping_registrations!(
+		tx_sync,
+		confirmable,
+		bitcoind,
+		electrsd,
+		logger,
+		"lightning_transaction_sync::esplora"
+	);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_checks_network() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_checking_network!(tx_sync, confirmable);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_tags_outputs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_tagging_outputs!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_tags_outputs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_tagging_outputs!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_checks_network() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_checking_network!(tx_sync, confirmable);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_detects_script_matches() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+
+	test_detecting_script_matches!(tx_sync, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_detects_script_matches() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+
+	test_detecting_script_matches!(tx_sync, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_streams_watch_events() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let (sender, receiver) = std::sync::mpsc::channel();
+	let tx_sync = EsploraSyncClientBuilder::new(esplora_url)
+		.watch_event_sender(sender)
+		.build(&mut logger)
+		.unwrap();
+	let confirmable = TestConfirmable::new();
+
+	test_streaming_watch_events!(tx_sync, receiver, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_streams_watch_events() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let (sender, receiver) = std::sync::mpsc::channel();
+	let tx_sync = EsploraSyncClientBuilder::new(esplora_url)
+		.watch_event_sender(sender)
+		.build(&mut logger)
+		.unwrap();
+	let confirmable = TestConfirmable::new();
+
+	test_streaming_watch_events!(tx_sync, receiver, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_exposes_watched_items() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_inspecting_watched_items!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_exposes_watched_items() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+	let tx_sync = EsploraSyncClient::new(esplora_url, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	test_inspecting_watched_items!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_persists_sync_state() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+
+	test_persisting_sync_state!(
+		EsploraSyncClient::new(esplora_url.clone(), &logger),
+		bitcoind,
+		electrsd
+	);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_persists_sync_state() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let logger = TestLogger::new();
+	let esplora_url = format!("http://{}", electrsd.esplora_url.as_ref().unwrap());
+
+	test_persisting_sync_state!(
+		EsploraSyncClient::new(esplora_url.clone(), &logger),
+		bitcoind,
+		electrsd
+	);
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_retrying {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let esplora_addr = $electrsd.esplora_url.as_ref().unwrap().to_string();
+		let config = SyncConfig {
+			request_timeout_secs: 10,
+			max_retries: 3,
+			backoff_base: Duration::from_millis(10),
+			..SyncConfig::default()
+		};
+
+		// Check we recover from a server failing fewer times than we're willing to retry.
+		let mut logger = TestLogger::new();
+		let proxy_url = setup_flaky_proxy(esplora_addr.clone(), 3);
+		let tx_sync = EsploraSyncClient::new_with_config(proxy_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert_eq!(confirmable.best_block.lock().unwrap().1, 102);
+
+		// Check we give up once we exhausted our retries, reporting the last error.
+		let mut logger = TestLogger::new();
+		let proxy_url = setup_flaky_proxy(esplora_addr, 4);
+		let tx_sync = EsploraSyncClient::new_with_config(proxy_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		match maybe_await!(tx_sync.sync(vec![&confirmable])) {
+			Err(TxSyncError::HttpStatus(503)) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		assert_eq!(confirmable.best_block.lock().unwrap().1, 0);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_syncing_with_deadline {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let esplora_addr = $electrsd.esplora_url.as_ref().unwrap().to_string();
+		let config = SyncConfig {
+			request_timeout_secs: 10,
+			max_retries: 3,
+			backoff_base: Duration::from_secs(60),
+			..SyncConfig::default()
+		};
+
+		// Check we give up rather than backing off past the deadline.
+		let mut logger = TestLogger::new();
+		let proxy_url = setup_flaky_proxy(esplora_addr, 1);
+		let tx_sync = EsploraSyncClient::new_with_config(proxy_url, config, &mut logger);
+		let confirmable = TestConfirmable::new();
+		let start = std::time::Instant::now();
+		match maybe_await!(
+			tx_sync.sync_with_deadline(vec![&confirmable], start + Duration::from_secs(30))
+		) {
+			Err(TxSyncError::Timeout) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		assert!(start.elapsed() < Duration::from_secs(30));
+		assert_eq!(confirmable.best_block.lock().unwrap().1, 0);
+
+		// Check we don't issue any requests once the deadline passed.
+		let new_address =
+			$bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+		let txid = $bitcoind
+			.client
+			.send_to_address(
+				&new_address,
+				Amount::from_sat(5000),
+				None,
+				None,
+				None,
+				None,
+				None,
+				None,
+			)
+			.unwrap();
+		tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let num_requests = tx_sync.metrics().total_requests;
+		let deadline = std::time::Instant::now();
+		match maybe_await!(tx_sync.sync_with_deadline(vec![&confirmable], deadline)) {
+			Err(TxSyncError::Timeout) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		assert_eq!(tx_sync.metrics().total_requests, num_requests);
+
+		// Check the next sync picks up the remaining work.
+		let deadline = std::time::Instant::now() + Duration::from_secs(30);
+		let stats = maybe_await!(tx_sync.sync_with_deadline(vec![&confirmable], deadline)).unwrap();
+		assert_eq!(stats.num_confirmed, 1);
+		assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+		assert_eq!(
+			confirmable.best_block.lock().unwrap().1 as u64,
+			$bitcoind.client.get_block_count().unwrap()
+		);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_classifying_errors {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let esplora_addr = $electrsd.esplora_url.as_ref().unwrap().to_string();
+		let config = SyncConfig {
+			request_timeout_secs: 10,
+			max_retries: 3,
+			backoff_base: Duration::from_millis(10),
+			..SyncConfig::default()
+		};
+
+		// Check we give up immediately if a custom classifier deems an otherwise transient error
+		// fatal, while still falling back to the default classification for other errors.
+		let mut logger = TestLogger::new();
+		let proxy_url = setup_flaky_proxy(esplora_addr, 1);
+		let tx_sync = EsploraSyncClientBuilder::new(proxy_url)
+			.config(config)
+			.error_classifier(|e| match e {
+				esplora_client::Error::HttpResponse { status: 503, .. } => ErrorDisposition::Fatal,
+				_ => default_error_classifier(e),
+			})
+			.build(&mut logger)
+			.unwrap();
+		let confirmable = TestConfirmable::new();
+		assert!(maybe_await!(tx_sync.sync(vec![&confirmable])).is_err());
+		assert_eq!(tx_sync.metrics().total_requests, 1);
+		assert_eq!(confirmable.best_block.lock().unwrap().1, 0);
+
+		// The proxy forwards any subsequent requests, so we succeed on the next try.
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert_eq!(confirmable.best_block.lock().unwrap().1, 102);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_failing_over {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let esplora_url = format!("http://{}", $electrsd.esplora_url.as_ref().unwrap());
+		let unused_addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let unreachable_url = format!("http://{}", unused_addr);
+		let config = SyncConfig {
+			request_timeout_secs: 10,
+			max_retries: 0,
+			failover_cool_down: Duration::ZERO,
+			..SyncConfig::default()
+		};
+		let tip_height = $bitcoind.client.get_block_count().unwrap() as u32;
+
+		// Check we fail over to the fallback server if the primary server is unreachable.
+		let logger = TestLogger::new();
+		let tx_sync = EsploraSyncClientBuilder::new(unreachable_url.clone())
+			.fallback_servers(vec![esplora_url.clone()])
+			.config(config)
+			.build(&logger)
+			.unwrap();
+		assert_eq!(tx_sync.active_server(), 0);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert_eq!(tx_sync.active_server(), 1);
+		assert_eq!(confirmable.best_block.lock().unwrap().1, tip_height);
+
+		// Once the cool-down passed, we switch back to the primary server, and fail over again.
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+		assert_eq!(tx_sync.active_server(), 1);
+		logger.assert_log_contains(
+			"lightning_transaction_sync::esplora",
+			"Switching back from Esplora server 1 to the primary server.",
+			1,
+		);
+		logger.assert_log_contains(
+			"lightning_transaction_sync::esplora",
+			"Failing over from Esplora server 0 to server 1.",
+			2,
+		);
+
+		// We give up once we tried all servers.
+		let mut logger = TestLogger::new();
+		let tx_sync = EsploraSyncClientBuilder::new(unreachable_url.clone())
+			.fallback_servers(vec![unreachable_url])
+			.config(config)
+			.build(&mut logger)
+			.unwrap();
+		let confirmable = TestConfirmable::new();
+		match maybe_await!(tx_sync.sync(vec![&confirmable])) {
+			Err(TxSyncError::Connection { .. }) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		assert_eq!(confirmable.best_block.lock().unwrap().1, 0);
+
+		// Check we refuse to fail over to a server on another network.
+		let mut logger = TestLogger::new();
+		let mainnet_genesis_hash = genesis_block(Network::Bitcoin).block_hash();
+		let fake_url = setup_fake_esplora_server(mainnet_genesis_hash);
+		let tx_sync = EsploraSyncClientBuilder::new(fake_url)
+			.fallback_servers(vec![esplora_url])
+			.config(config)
+			.build(&mut logger)
+			.unwrap();
+		let confirmable = TestConfirmable::new();
+		match maybe_await!(tx_sync.sync(vec![&confirmable])) {
+			Err(TxSyncError::WrongNetwork) => {},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+		assert_eq!(confirmable.best_block.lock().unwrap().1, 0);
+	}};
+}
+
+#[cfg(any(feature = "esplora-blocking", feature = "esplora-async"))]
+macro_rules! test_tolerating_missing_block_data {
+	($bitcoind: expr, $electrsd: expr) => {{
+		let esplora_addr = $electrsd.esplora_url.as_ref().unwrap().to_string();
+		let (proxy_url, num_misses) = setup_lagging_header_proxy(esplora_addr);
+		let config = SyncConfig {
+			request_timeout_secs: 10,
+			max_retries: 3,
+			backoff_base: Duration::from_millis(10),
+			..SyncConfig::default()
+		};
+		let logger = TestLogger::new();
+		let tx_sync = EsploraSyncClient::new_with_config(proxy_url, config, &logger);
+		let confirmable = TestConfirmable::new();
+		maybe_await!(tx_sync.sync(vec![&confirmable])).unwrap();
+
+		// Check we retry if the header of a new tip only becomes available on the third request.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		let tip_hash = $bitcoind.client.get_best_block_hash().unwrap();
+		let tip_height = $bitcoind.client.get_block_count().unwrap() as u32;
+		num_misses.store(2, std::sync::atomic::Ordering::SeqCst);
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(num_misses.load(std::sync::atomic::Ordering::SeqCst), 0);
+		assert_eq!(stats.tip_hash, tip_hash);
+		assert_eq!(*confirmable.best_block.lock().unwrap(), (tip_hash, tip_height));
+
+		// Check we sync against the previous tip if the header remains unavailable.
+		generate_blocks_and_wait(&$bitcoind, &$electrsd, 1);
+		num_misses.store(100, std::sync::atomic::Ordering::SeqCst);
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.tip_hash, tip_hash);
+		assert_eq!(*confirmable.best_block.lock().unwrap(), (tip_hash, tip_height));
+
+		// Check we catch up once the server is able to serve the new tip.
+		num_misses.store(0, std::sync::atomic::Ordering::SeqCst);
+		let new_tip_hash = $bitcoind.client.get_best_block_hash().unwrap();
+		let stats = maybe_await!(tx_sync.sync_with_stats(vec![&confirmable])).unwrap();
+		assert_eq!(stats.tip_hash, new_tip_hash);
+		assert_eq!(*confirmable.best_block.lock().unwrap(), (new_tip_hash, tip_height + 1));
+	}};
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_fails_over() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_failing_over!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_fails_over() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_failing_over!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_retries_transient_errors() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_retrying!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_retries_transient_errors() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_retrying!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_syncs_with_deadline() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_syncing_with_deadline!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_syncs_with_deadline() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_syncing_with_deadline!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_tolerates_missing_block_data() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_tolerating_missing_block_data!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_tolerates_missing_block_data() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_tolerating_missing_block_data!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "esplora-blocking")]
+fn test_esplora_classifies_errors() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_classifying_errors!(bitcoind, electrsd);
+}
+
+#[tokio::test]
+#[cfg(feature = "esplora-async")]
+async fn test_esplora_classifies_errors() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	test_classifying_errors!(bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_syncs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync = ElectrumSyncClient::new(electrum_url, &mut logger).unwrap();
+	let confirmable = TestConfirmable::new();
+	test_syncing!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_syncs_with_header_subscription() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync =
+		ElectrumSyncClient::new_with_header_subscription(electrum_url, &mut logger).unwrap();
+	let confirmable = TestConfirmable::new();
+	test_syncing!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_skips_idle_syncs_with_header_subscription() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync =
+		ElectrumSyncClient::new_with_header_subscription(electrum_url, &mut logger).unwrap();
+	let confirmable = TestConfirmable::new();
+	let stats = tx_sync.sync_with_stats(vec![&confirmable]).unwrap();
+	let tip_height = stats.tip_height;
+	assert_eq!(confirmable.best_block.lock().unwrap().1, tip_height);
+
+	// If nothing happened since the last sync, we only poll for header notifications.
+	let num_requests = tx_sync.metrics().total_requests;
+	let events_len = confirmable.events.lock().unwrap().len();
+	let idle_stats = tx_sync.sync_with_stats(vec![&confirmable]).unwrap();
+	assert_eq!(tx_sync.metrics().total_requests, num_requests + 1);
+	assert_eq!((idle_stats.tip_hash, idle_stats.tip_height), (stats.tip_hash, tip_height));
+	assert_eq!(confirmable.events.lock().unwrap().len(), events_len);
+
+	// New blocks are picked up via their notifications.
+	generate_blocks_and_wait(&bitcoind, &electrsd, 1);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+	assert_eq!(confirmable.best_block.lock().unwrap().1, tip_height + 1);
+
+	// As are new registrations, even if no new block arrived.
+	let new_address = bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+	let txid = bitcoind
+		.client
+		.send_to_address(&new_address, Amount::from_sat(5000), None, None, None, None, None, None)
+		.unwrap();
+	tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+	let num_requests = tx_sync.metrics().total_requests;
+	tx_sync.sync(vec![&confirmable]).unwrap();
+	assert!(tx_sync.metrics().total_requests > num_requests + 1);
+
+	generate_blocks_and_wait(&bitcoind, &electrsd, 1);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+	assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_broadcasts() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync = ElectrumSyncClient::new(electrum_url, &mut logger).unwrap();
+
+	test_broadcasting!(tx_sync, bitcoind);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_unregisters() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync = ElectrumSyncClient::new(electrum_url, &mut logger).unwrap();
+	let confirmable = TestConfirmable::new();
+	test_unregistering!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_overlapping_registrations() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync = ElectrumSyncClient::new(electrum_url, &logger).unwrap();
+	let confirmable = TestConfirmable::new();
+	test_overlapping_registrations!(
+		tx_sync,
+		confirmable,
+		bitcoind,
+		electrsd,
+		logger,
+		"lightning_transaction_sync::electrum"
+	);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_reports_request_metrics() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let hook = std::sync::Arc::new(TestMetricsHook::default());
+	let logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync =
+		ElectrumSyncClient::new(electrum_url, &logger).unwrap().with_metrics_hook(hook.clone());
+
+	test_reporting_request_metrics!(tx_sync, hook, 0);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_tags_outputs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync = ElectrumSyncClient::new(electrum_url, &mut logger).unwrap();
+	let confirmable = TestConfirmable::new();
+
+	test_tagging_outputs!(tx_sync, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_detects_script_matches() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync = ElectrumSyncClient::new(electrum_url, &mut logger).unwrap();
+
+	test_detecting_script_matches!(tx_sync, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_streams_watch_events() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let (sender, receiver) = std::sync::mpsc::channel();
+	let tx_sync =
+		ElectrumSyncClient::new(electrum_url, &mut logger).unwrap().with_watch_event_sender(sender);
+	let confirmable = TestConfirmable::new();
+
+	test_streaming_watch_events!(tx_sync, receiver, confirmable, bitcoind, electrsd);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_checks_network() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+	let tx_sync = ElectrumSyncClient::new(electrum_url, &mut logger).unwrap();
+	let confirmable = TestConfirmable::new();
+
+	test_checking_network!(tx_sync, confirmable);
+}
+
+#[test]
+#[cfg(feature = "electrum")]
+fn test_electrum_persists_sync_state() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let logger = TestLogger::new();
+	let electrum_url = format!("tcp://{}", electrsd.electrum_url);
+
+	test_persisting_sync_state!(
+		ElectrumSyncClient::new(electrum_url.clone(), &logger).unwrap(),
+		bitcoind,
+		electrsd
+	);
+}
+
+#[test]
+#[cfg(feature = "bitcoind-rpc")]
+fn test_bitcoind_rpc_syncs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let auth = BitcoindRpcAuth::CookieFile(bitcoind.params.cookie_file.clone());
+	let tx_sync = BitcoindRpcSyncClient::new(bitcoind.rpc_url(), auth, &mut logger).unwrap();
+	let confirmable = TestConfirmable::new();
+
+	// Check we pick up on new best blocks
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 0);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 102);
+
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 1);
+
+	// Check registered transactions and spends of registered outputs are picked up.
+	let new_address = bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+	let txid = bitcoind
+		.client
+		.send_to_address(&new_address, Amount::from_sat(5000), None, None, None, None, None, None)
+		.unwrap();
+	tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+
+	let second_txid = bitcoind
+		.client
+		.send_to_address(&new_address, Amount::from_sat(5000), None, None, None, None, None, None)
+		.unwrap();
+	let second_tx =
+		bitcoind.client.get_transaction(&second_txid, None).unwrap().transaction().unwrap();
+	let prev_outpoint = second_tx.input.first().unwrap().previous_output;
+	let prev_tx =
+		bitcoind.client.get_transaction(&prev_outpoint.txid, None).unwrap().transaction().unwrap();
+	let output = WatchedOutput {
+		block_hash: None,
+		outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+		script_pubkey: prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone(),
+	};
+	tx_sync.register_output(output);
+
+	tx_sync.sync(vec![&confirmable]).unwrap();
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 0);
+
+	generate_blocks_and_wait(&bitcoind, &electrsd, 1);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 3);
+	assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+	assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&second_txid));
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().is_empty());
+
+	// Check previously confirmed transactions are marked unconfirmed when they are reorged.
+	let best_block_hash = bitcoind.client.get_best_block_hash().unwrap();
+	bitcoind.client.invalidate_block(&best_block_hash).unwrap();
+	tx_sync.sync(vec![&confirmable]).unwrap();
+
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 3);
+	assert!(confirmable.confirmed_txs.lock().unwrap().is_empty());
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().contains(&txid));
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().contains(&second_txid));
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 102);
+
+	// Check they are reconfirmed once they make it into the new best chain.
+	generate_blocks_and_wait(&bitcoind, &electrsd, 2);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+
+	let new_block_hash = bitcoind.client.get_block_hash(103).unwrap();
+	assert_ne!(new_block_hash, best_block_hash);
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 104);
+	assert_eq!(confirmable.confirmed_txs.lock().unwrap().get(&txid), Some(&(new_block_hash, 103)));
+	assert_eq!(
+		confirmable.confirmed_txs.lock().unwrap().get(&second_txid),
+		Some(&(new_block_hash, 103))
+	);
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().is_empty());
+
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 3);
+	match events[0] {
+		TestConfirmableEvent::BestBlockUpdated(..) => {},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[cfg(feature = "cbf")]
+struct BitcoindFilterSource<'a> {
+	bitcoind: &'a BitcoinD,
+}
+
+#[cfg(feature = "cbf")]
+impl<'a> CompactFilterSource for BitcoindFilterSource<'a> {
+	fn get_best_block(&self) -> Result<(BlockHash, u32), TxSyncError> {
+		let hash = self.bitcoind.client.get_best_block_hash().map_err(|_| TxSyncError::Failed)?;
+		let info =
+			self.bitcoind.client.get_block_header_info(&hash).map_err(|_| TxSyncError::Failed)?;
+		Ok((hash, info.height as u32))
+	}
+
+	fn get_block_hash(&self, height: u32) -> Result<Option<BlockHash>, TxSyncError> {
+		let tip_height = self.bitcoind.client.get_block_count().map_err(|_| TxSyncError::Failed)?;
+		if height as u64 > tip_height {
+			return Ok(None);
+		}
+		self.bitcoind
+			.client
+			.get_block_hash(height as u64)
+			.map(Some)
+			.map_err(|_| TxSyncError::Failed)
+	}
+
+	fn get_header(&self, block_hash: &BlockHash) -> Result<Option<(Header, u32)>, TxSyncError> {
+		let info = self
+			.bitcoind
+			.client
+			.get_block_header_info(block_hash)
+			.map_err(|_| TxSyncError::Failed)?;
+		if info.confirmations < 1 {
+			return Ok(None);
+		}
+		let header =
+			self.bitcoind.client.get_block_header(block_hash).map_err(|_| TxSyncError::Failed)?;
+		Ok(Some((header, info.height as u32)))
+	}
+
+	fn get_filter(&self, block_hash: &BlockHash) -> Result<BlockFilter, TxSyncError> {
+		let res =
+			self.bitcoind.client.get_block_filter(block_hash).map_err(|_| TxSyncError::Failed)?;
+		Ok(BlockFilter::new(&res.filter))
+	}
+
+	fn get_block(&self, block_hash: &BlockHash) -> Result<Block, TxSyncError> {
+		self.bitcoind.client.get_block(block_hash).map_err(|_| TxSyncError::Failed)
+	}
+}
+
+#[test]
+#[cfg(feature = "cbf")]
+fn test_cbf_syncs() {
+	let (bitcoind, electrsd) = setup_bitcoind_and_electrsd();
+	generate_blocks_and_wait(&bitcoind, &electrsd, 101);
+	let mut logger = TestLogger::new();
+	let source = BitcoindFilterSource { bitcoind: &bitcoind };
+	let tx_sync = CbfSyncClient::new(&source, 0, &mut logger);
+	let confirmable = TestConfirmable::new();
+
+	// Check we pick up on new best blocks
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 0);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 102);
+
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 1);
+
+	// Check registered transactions and spends of registered outputs are picked up.
+	let new_address = bitcoind.client.get_new_address(Some("test"), None).unwrap().assume_checked();
+	let txid = bitcoind
+		.client
+		.send_to_address(&new_address, Amount::from_sat(5000), None, None, None, None, None, None)
+		.unwrap();
+	tx_sync.register_tx(&txid, &new_address.payload().script_pubkey());
+
+	let second_txid = bitcoind
+		.client
+		.send_to_address(&new_address, Amount::from_sat(5000), None, None, None, None, None, None)
+		.unwrap();
+	let second_tx =
+		bitcoind.client.get_transaction(&second_txid, None).unwrap().transaction().unwrap();
+	let prev_outpoint = second_tx.input.first().unwrap().previous_output;
+	let prev_tx =
+		bitcoind.client.get_transaction(&prev_outpoint.txid, None).unwrap().transaction().unwrap();
+	let output = WatchedOutput {
+		block_hash: None,
+		outpoint: OutPoint { txid: prev_outpoint.txid, index: prev_outpoint.vout as u16 },
+		script_pubkey: prev_tx.output[prev_outpoint.vout as usize].script_pubkey.clone(),
+	};
+	tx_sync.register_output(output);
+
+	tx_sync.sync(vec![&confirmable]).unwrap();
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 0);
+
+	generate_blocks_and_wait(&bitcoind, &electrsd, 1);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 3);
+	assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&txid));
+	assert!(confirmable.confirmed_txs.lock().unwrap().contains_key(&second_txid));
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().is_empty());
+
+	// Check previously confirmed transactions are marked unconfirmed when they are reorged.
+	let best_block_hash = bitcoind.client.get_best_block_hash().unwrap();
+	bitcoind.client.invalidate_block(&best_block_hash).unwrap();
+	tx_sync.sync(vec![&confirmable]).unwrap();
+
+	let events = std::mem::take(&mut *confirmable.events.lock().unwrap());
+	assert_eq!(events.len(), 3);
+	assert!(confirmable.confirmed_txs.lock().unwrap().is_empty());
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().contains(&txid));
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().contains(&second_txid));
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 102);
+
+	// Check they are reconfirmed once they make it into the new best chain, which requires
+	// rescanning the filters of the replacement blocks.
+	generate_blocks_and_wait(&bitcoind, &electrsd, 2);
+	tx_sync.sync(vec![&confirmable]).unwrap();
+
+	let new_block_hash = bitcoind.client.get_block_hash(103).unwrap();
+	assert_ne!(new_block_hash, best_block_hash);
+	assert_eq!(confirmable.best_block.lock().unwrap().1, 104);
+	assert_eq!(confirmable.confirmed_txs.lock().unwrap().get(&txid), Some(&(new_block_hash, 103)));
+	assert_eq!(
+		confirmable.confirmed_txs.lock().unwrap().get(&second_txid),
+		Some(&(new_block_hash, 103))
+	);
+	assert!(confirmable.unconfirmed_txs.lock().unwrap().is_empty());
 }
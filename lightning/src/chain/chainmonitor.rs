@@ -29,7 +29,7 @@ use bitcoin::hash_types::{Txid, BlockHash};
 use crate::chain;
 use crate::chain::{ChannelMonitorUpdateStatus, Filter, WatchedOutput};
 use crate::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
-use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, Balance, MonitorEvent, TransactionOutputs, WithChannelMonitor};
+use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, Balance, ClosingTxPackage, MonitorEvent, TransactionOutputs, WithChannelMonitor};
 use crate::chain::transaction::{OutPoint, TransactionData};
 use crate::ln::types::ChannelId;
 use crate::sign::ecdsa::EcdsaChannelSigner;
@@ -197,6 +197,56 @@ impl<ChannelSigner: EcdsaChannelSigner> Deref for LockedChannelMonitor<'_, Chann
 	}
 }
 
+/// An aggregate summary of our balances across all channels, as returned by
+/// [`ChainMonitor::get_node_balances`].
+///
+/// Open channels are accounted for solely via the [`ChannelDetails`] passed in, while channels
+/// which have been closed (or whose funding is not in the given list) are accounted for solely via
+/// their [`ChannelMonitor`]'s [`Balance`]s. Thus, no amount is counted twice.
+///
+/// All on-chain amounts exclude the on-chain fees which will be required to claim them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NodeBalances {
+	/// The sum of [`ChannelDetails::outbound_capacity_msat`] across all open, funded channels.
+	pub outbound_capacity_msat: u64,
+	/// The sum of [`ChannelDetails::inbound_capacity_msat`] across all open, funded channels.
+	pub inbound_capacity_msat: u64,
+	/// The sum of all [`Balance::ClaimableOnChannelClose`] amounts for channels which are no longer
+	/// open but whose commitment or closing transaction has not yet confirmed.
+	pub claimable_on_channel_close_satoshis: u64,
+	/// The sum of all [`Balance::ClaimableAwaitingConfirmations`] amounts.
+	pub claimable_awaiting_confirmations_satoshis: u64,
+	/// The sum of all [`Balance::ContentiousClaimable`] amounts.
+	pub contentious_claimable_satoshis: u64,
+	/// The sum of all [`Balance::MaybeTimeoutClaimableHTLC`] amounts.
+	pub maybe_timeout_claimable_htlc_satoshis: u64,
+	/// The sum of all [`Balance::MaybePreimageClaimableHTLC`] amounts.
+	pub maybe_preimage_claimable_htlc_satoshis: u64,
+	/// The sum of all [`Balance::CounterpartyRevokedOutputClaimable`] amounts.
+	pub counterparty_revoked_output_claimable_satoshis: u64,
+	/// The portion of the above on-chain balances which is still waiting on a timelock to expire
+	/// before it can be spent or claimed by us.
+	///
+	/// This is the sum of all [`Balance::ClaimableAwaitingConfirmations`] whose
+	/// `confirmation_height` and all [`Balance::MaybeTimeoutClaimableHTLC`] whose
+	/// `claimable_height` have not yet been reached by the relevant [`ChannelMonitor`].
+	pub timelocked_satoshis: u64,
+}
+
+impl NodeBalances {
+	/// The total amount pending on-chain which we expect to be able to claim, in satoshis.
+	///
+	/// Matches [`Balance::claimable_amount_satoshis`] in that it excludes
+	/// [`Self::maybe_timeout_claimable_htlc_satoshis`] and
+	/// [`Self::maybe_preimage_claimable_htlc_satoshis`] as we cannot be sure we will claim them.
+	pub fn pending_on_chain_claimable_satoshis(&self) -> u64 {
+		self.claimable_on_channel_close_satoshis
+			+ self.claimable_awaiting_confirmations_satoshis
+			+ self.contentious_claimable_satoshis
+			+ self.counterparty_revoked_output_claimable_satoshis
+	}
+}
+
 /// An implementation of [`chain::Watch`] for monitoring channels.
 ///
 /// Connected and disconnected blocks must be provided to `ChainMonitor` as documented by
@@ -403,6 +453,54 @@ where C::Target: chain::Filter,
 		ret
 	}
 
+	/// Gets an aggregate summary of our balances, combining the open `channels` (as returned by
+	/// [`ChannelManager::list_channels`]) with the claimable balances of all other
+	/// [`ChannelMonitor`]s.
+	///
+	/// See [`NodeBalances`] for the exact definition of each field.
+	///
+	/// [`ChannelManager::list_channels`]: crate::ln::channelmanager::ChannelManager::list_channels
+	pub fn get_node_balances(&self, channels: &[ChannelDetails]) -> NodeBalances {
+		let mut balances = NodeBalances::default();
+		for chan in channels.iter().filter(|chan| chan.funding_txo.is_some()) {
+			balances.outbound_capacity_msat += chan.outbound_capacity_msat;
+			balances.inbound_capacity_msat += chan.inbound_capacity_msat;
+		}
+
+		let monitor_states = self.monitors.read().unwrap();
+		for (funding_outpoint, monitor_state) in monitor_states.iter() {
+			if channels.iter().any(|chan| chan.funding_txo.as_ref() == Some(funding_outpoint)) {
+				continue;
+			}
+			let height = monitor_state.monitor.current_best_block().height;
+			for balance in monitor_state.monitor.get_claimable_balances() {
+				match balance {
+					Balance::ClaimableOnChannelClose { amount_satoshis } =>
+						balances.claimable_on_channel_close_satoshis += amount_satoshis,
+					Balance::ClaimableAwaitingConfirmations { amount_satoshis, confirmation_height } => {
+						balances.claimable_awaiting_confirmations_satoshis += amount_satoshis;
+						if confirmation_height > height {
+							balances.timelocked_satoshis += amount_satoshis;
+						}
+					},
+					Balance::ContentiousClaimable { amount_satoshis, .. } =>
+						balances.contentious_claimable_satoshis += amount_satoshis,
+					Balance::MaybeTimeoutClaimableHTLC { amount_satoshis, claimable_height, .. } => {
+						balances.maybe_timeout_claimable_htlc_satoshis += amount_satoshis;
+						if claimable_height > height {
+							balances.timelocked_satoshis += amount_satoshis;
+						}
+					},
+					Balance::MaybePreimageClaimableHTLC { amount_satoshis, .. } =>
+						balances.maybe_preimage_claimable_htlc_satoshis += amount_satoshis,
+					Balance::CounterpartyRevokedOutputClaimable { amount_satoshis } =>
+						balances.counterparty_revoked_output_claimable_satoshis += amount_satoshis,
+				}
+			}
+		}
+		balances
+	}
+
 	/// Gets the [`LockedChannelMonitor`] for a given funding outpoint, returning an `Err` if no
 	/// such [`ChannelMonitor`] is currently being monitored for.
 	///
@@ -856,6 +954,11 @@ where C::Target: chain::Filter,
 		}
 		pending_monitor_events
 	}
+
+	fn get_latest_holder_commitment_package(&self, funding_txo: OutPoint) -> Option<ClosingTxPackage> {
+		self.monitors.read().unwrap().get(&funding_txo)
+			.and_then(|monitor_state| monitor_state.monitor.get_latest_holder_commitment_package())
+	}
 }
 
 impl<ChannelSigner: EcdsaChannelSigner, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref> events::EventsProvider for ChainMonitor<ChannelSigner, C, T, F, L, P>
@@ -558,6 +558,10 @@ pub(crate) enum ChannelMonitorUpdateStep {
 		/// If set to false, we shouldn't broadcast the latest holder commitment transaction as we
 		/// think we've fallen behind!
 		should_broadcast: bool,
+		/// If set (along with `should_broadcast`), the user broadcasts the latest holder
+		/// commitment transaction themselves, so we should only track its claims rather than
+		/// broadcasting it immediately.
+		broadcast_externally: bool,
 	},
 	ShutdownScript {
 		scriptpubkey: ScriptBuf,
@@ -602,12 +606,37 @@ impl_writeable_tlv_based_enum_upgradable!(ChannelMonitorUpdateStep,
 	},
 	(4, ChannelForceClosed) => {
 		(0, should_broadcast, required),
+		(1, broadcast_externally, (default_value, false)),
 	},
 	(5, ShutdownScript) => {
 		(0, scriptpubkey, required),
 	},
 );
 
+/// The latest holder commitment transaction of a force-closed channel, handed to the user to
+/// broadcast rather than being broadcast by LDK.
+///
+/// See [`ChannelManager::force_close_without_broadcast`] for more details.
+///
+/// [`ChannelManager::force_close_without_broadcast`]: crate::ln::channelmanager::ChannelManager::force_close_without_broadcast
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClosingTxPackage {
+	/// The fully-signed latest holder commitment transaction.
+	pub commitment_tx: Transaction,
+	/// The fee, in satoshis, paid by [`Self::commitment_tx`].
+	pub commitment_tx_fee_satoshis: u64,
+	/// For channels with anchor outputs, the descriptor for our anchor output on
+	/// [`Self::commitment_tx`], which may be spent immediately to bump its fee via CPFP.
+	pub anchor_descriptor: Option<AnchorDescriptor>,
+}
+
+impl ClosingTxPackage {
+	/// Returns the consensus-serialized [`Self::commitment_tx`], ready for submission.
+	pub fn serialized_commitment_tx(&self) -> Vec<u8> {
+		bitcoin::consensus::encode::serialize(&self.commitment_tx)
+	}
+}
+
 /// Details about the balance(s) available for spending once the channel appears on chain.
 ///
 /// See [`ChannelMonitor::get_claimable_balances`] for more details on when these will or will not
@@ -1628,6 +1657,16 @@ impl<Signer: EcdsaChannelSigner> ChannelMonitor<Signer> {
 		inner.queue_latest_holder_commitment_txn_for_broadcast(broadcaster, &fee_estimator, &logger);
 	}
 
+	/// Gets the latest holder commitment transaction, fully signed, along with the information
+	/// needed to bump its fee, if any.
+	///
+	/// Returns `None` unless the channel has been force-closed and the commitment transaction's
+	/// claims are tracked by this monitor (i.e. we have broadcast it or queued it for external
+	/// broadcast), or if the signer is currently unable to sign it.
+	pub fn get_latest_holder_commitment_package(&self) -> Option<ClosingTxPackage> {
+		self.inner.lock().unwrap().get_latest_holder_commitment_package()
+	}
+
 	/// Unsafe test-only version of `broadcast_latest_holder_commitment_txn` used by our test framework
 	/// to bypass HolderCommitmentTransaction state update lockdown after signature and generate
 	/// revoked commitment transaction.
@@ -2527,6 +2566,14 @@ pub fn deliberately_bogus_accepted_htlc_witness() -> Vec<Vec<u8>> {
 	vec![Vec::new(), Vec::new(), Vec::new(), Vec::new(), deliberately_bogus_accepted_htlc_witness_program().into()].into()
 }
 
+/// A [`BroadcasterInterface`] which drops all transactions, used when queueing claims whose
+/// initial broadcast is left to the user.
+struct DroppingBroadcaster;
+
+impl BroadcasterInterface for DroppingBroadcaster {
+	fn broadcast_transactions(&self, _txs: &[&Transaction]) {}
+}
+
 impl<Signer: EcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 	/// Inserts a revocation secret into this channel monitor. Prunes old preimages if neither
 	/// needed by holder commitment transactions HTCLs nor by counterparty ones. Unless we haven't already seen
@@ -2886,6 +2933,54 @@ impl<Signer: EcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		);
 	}
 
+	/// Queues the claims for the latest holder commitment transaction as in
+	/// [`Self::queue_latest_holder_commitment_txn_for_broadcast`], but leaves the initial broadcast
+	/// to the user. The claims are still tracked, so they'll be broadcast on the next call to
+	/// [`ChannelMonitor::rebroadcast_pending_claims`] if the user's broadcast fails.
+	fn queue_latest_holder_commitment_txn_for_external_broadcast<F: Deref, L: Deref>(
+		&mut self, fee_estimator: &LowerBoundedFeeEstimator<F>, logger: &WithChannelMonitor<L>
+	)
+	where
+		F::Target: FeeEstimator,
+		L::Target: Logger,
+	{
+		let (claimable_outpoints, _) = self.generate_claimable_outpoints_and_watch_outputs(ClosureReason::HolderForceClosed { broadcasted_latest_txn: Some(false) });
+		self.onchain_tx_handler.update_claims_view_from_requests(
+			claimable_outpoints, self.best_block.height, self.best_block.height, &&DroppingBroadcaster,
+			fee_estimator, logger
+		);
+		// Similarly, anchor channels shouldn't yield the commitment transaction to be bumped and
+		// broadcast until the next rebroadcast.
+		self.onchain_tx_handler.clear_pending_commitment_claim_events();
+	}
+
+	fn get_latest_holder_commitment_package(&mut self) -> Option<ClosingTxPackage> {
+		if !self.holder_tx_signed {
+			return None;
+		}
+		let commitment_tx = self.onchain_tx_handler.get_maybe_signed_holder_tx(&self.funding_redeemscript);
+		if !commitment_tx.is_fully_signed() {
+			return None;
+		}
+		let commitment_tx = commitment_tx.0;
+		let commitment_tx_fee_satoshis = self.channel_value_satoshis -
+			commitment_tx.output.iter().fold(0u64, |sum, output| sum + output.value.to_sat());
+		let funding_pubkey = &self.onchain_tx_handler.channel_transaction_parameters.holder_pubkeys.funding_pubkey;
+		let anchor_descriptor = chan_utils::get_anchor_output(&commitment_tx, funding_pubkey)
+			.map(|(anchor_output_idx, _)| AnchorDescriptor {
+				channel_derivation_parameters: ChannelDerivationParameters {
+					keys_id: self.channel_keys_id,
+					value_satoshis: self.channel_value_satoshis,
+					transaction_parameters: self.onchain_tx_handler.channel_transaction_parameters.clone(),
+				},
+				outpoint: BitcoinOutPoint {
+					txid: commitment_tx.txid(),
+					vout: anchor_output_idx,
+				},
+			});
+		Some(ClosingTxPackage { commitment_tx, commitment_tx_fee_satoshis, anchor_descriptor })
+	}
+
 	fn update_monitor<B: Deref, F: Deref, L: Deref>(
 		&mut self, updates: &ChannelMonitorUpdate, broadcaster: &B, fee_estimator: &F, logger: &WithChannelMonitor<L>
 	) -> Result<(), ()>
@@ -2966,8 +3061,8 @@ impl<Signer: EcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 						ret = Err(());
 					}
 				},
-				ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast } => {
-					log_trace!(logger, "Updating ChannelMonitor: channel force closed, should broadcast: {}", should_broadcast);
+				ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast, broadcast_externally } => {
+					log_trace!(logger, "Updating ChannelMonitor: channel force closed, should broadcast: {}, externally: {}", should_broadcast, broadcast_externally);
 					self.lockdown_from_offchain = true;
 					if *should_broadcast {
 						// There's no need to broadcast our commitment transaction if we've seen one
@@ -2982,7 +3077,11 @@ impl<Signer: EcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 							log_trace!(logger, "Avoiding commitment broadcast, already detected confirmed spend onchain");
 							continue;
 						}
-						self.queue_latest_holder_commitment_txn_for_broadcast(broadcaster, &bounded_fee_estimator, logger);
+						if *broadcast_externally {
+							self.queue_latest_holder_commitment_txn_for_external_broadcast(&bounded_fee_estimator, logger);
+						} else {
+							self.queue_latest_holder_commitment_txn_for_broadcast(broadcaster, &bounded_fee_estimator, logger);
+						}
 					} else if !self.holder_tx_signed {
 						log_error!(logger, "WARNING: You have a potentially-unsafe holder commitment transaction available to broadcast");
 						log_error!(logger, "    in channel monitor for channel {}!", &self.channel_id());
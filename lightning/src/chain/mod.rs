@@ -16,7 +16,7 @@ use bitcoin::hash_types::{BlockHash, Txid};
 use bitcoin::network::Network;
 use bitcoin::secp256k1::PublicKey;
 
-use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, MonitorEvent};
+use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ClosingTxPackage, MonitorEvent};
 use crate::ln::types::ChannelId;
 use crate::sign::ecdsa::EcdsaChannelSigner;
 use crate::chain::transaction::{OutPoint, TransactionData};
@@ -305,6 +305,21 @@ pub trait Watch<ChannelSigner: EcdsaChannelSigner> {
 	/// For details on asynchronous [`ChannelMonitor`] updating and returning
 	/// [`MonitorEvent::Completed`] here, see [`ChannelMonitorUpdateStatus::InProgress`].
 	fn release_pending_monitor_events(&self) -> Vec<(OutPoint, ChannelId, Vec<MonitorEvent>, Option<PublicKey>)>;
+
+	/// Gets the latest holder commitment transaction of the force-closed channel identified by
+	/// `funding_txo`, for the user to broadcast themselves.
+	///
+	/// Implementations should return the result of
+	/// [`ChannelMonitor::get_latest_holder_commitment_package`] for the channel's monitor, or
+	/// `None` if no such monitor is registered.
+	///
+	/// Used by [`ChannelManager::force_close_without_broadcast`], which has already closed the
+	/// channel by the time this is called. Thus, implementations wrapping a [`ChainMonitor`]
+	/// should forward the call to it rather than returning `None`.
+	///
+	/// [`ChannelManager::force_close_without_broadcast`]: crate::ln::channelmanager::ChannelManager::force_close_without_broadcast
+	/// [`ChainMonitor`]: crate::chain::chainmonitor::ChainMonitor
+	fn get_latest_holder_commitment_package(&self, funding_txo: OutPoint) -> Option<ClosingTxPackage>;
 }
 
 /// The `Filter` trait defines behavior for indicating chain activity of interest pertaining to
@@ -363,7 +378,7 @@ pub trait Filter {
 ///
 /// [`ChannelMonitor`]: channelmonitor::ChannelMonitor
 /// [`ChannelMonitor::block_connected`]: channelmonitor::ChannelMonitor::block_connected
-#[derive(Clone, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct WatchedOutput {
 	/// First block where the transaction output may have been spent.
 	pub block_hash: Option<BlockHash>,
@@ -375,6 +390,12 @@ pub struct WatchedOutput {
 	pub script_pubkey: ScriptBuf,
 }
 
+impl_writeable_tlv_based!(WatchedOutput, {
+	(0, block_hash, option),
+	(2, outpoint, required),
+	(4, script_pubkey, required),
+});
+
 impl<T: Listen> Listen for dyn core::ops::Deref<Target = T> {
 	fn filtered_block_connected(&self, header: &Header, txdata: &TransactionData, height: u32) {
 		(**self).filtered_block_connected(header, txdata, height);
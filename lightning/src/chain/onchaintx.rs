@@ -480,6 +480,12 @@ impl<ChannelSigner: EcdsaChannelSigner> OnchainTxHandler<ChannelSigner> {
 		events
 	}
 
+	/// Drops any pending [`ClaimEvent::BumpCommitment`]. The underlying claim remains tracked, so
+	/// the event will be yielded again on the next call to [`Self::rebroadcast_pending_claims`].
+	pub(crate) fn clear_pending_commitment_claim_events(&mut self) {
+		self.pending_claim_events.retain(|(_, event)| !matches!(event, ClaimEvent::BumpCommitment { .. }));
+	}
+
 	/// Triggers rebroadcasts/fee-bumps of pending claims from a force-closed channel. This is
 	/// crucial in preventing certain classes of pinning attacks, detecting substantial mempool
 	/// feerate changes between blocks, and ensuring reliability if broadcasting fails. We recommend
@@ -25,12 +25,11 @@ use crate::ln::chan_utils::{
 	HTLC_TIMEOUT_INPUT_ANCHOR_WITNESS_WEIGHT, HTLCOutputInCommitment
 };
 use crate::prelude::*;
-use crate::sign::{
-	ChannelDerivationParameters, HTLCDescriptor, SignerProvider, P2WPKH_WITNESS_WEIGHT
-};
+use crate::sign::{ChannelDerivationParameters, HTLCDescriptor, SignerProvider};
 use crate::sign::ecdsa::EcdsaChannelSigner;
 use crate::sync::Mutex;
 use crate::util::logger::Logger;
+use crate::util::transaction_utils::{estimate_inputs_witness_weight, InputType};
 
 use bitcoin::{OutPoint, Psbt, PubkeyHash, Sequence, ScriptBuf, Transaction, TxIn, TxOut, Witness, WPubkeyHash};
 use bitcoin::amount::Amount;
@@ -276,17 +275,14 @@ impl Utxo {
 
 	/// Returns a `Utxo` with the `satisfaction_weight` estimate for a P2WPKH nested in P2SH output.
 	pub fn new_nested_p2wpkh(outpoint: OutPoint, value: Amount, pubkey_hash: &WPubkeyHash) -> Self {
-		let script_sig_size = 1 /* script_sig length */ +
-			1 /* OP_0 */ +
-			1 /* OP_PUSH20 */ +
-			20 /* pubkey_hash */;
 		Self {
 			outpoint,
 			output: TxOut {
 				value,
 				script_pubkey: ScriptBuf::new_p2sh(&ScriptBuf::new_p2wpkh(pubkey_hash).script_hash()),
 			},
-			satisfaction_weight: script_sig_size * WITNESS_SCALE_FACTOR as u64 + P2WPKH_WITNESS_WEIGHT,
+			satisfaction_weight: EMPTY_SCRIPT_SIG_WEIGHT +
+				estimate_inputs_witness_weight(&[(InputType::P2shP2wpkh, 1)]),
 		}
 	}
 
@@ -298,7 +294,8 @@ impl Utxo {
 				value,
 				script_pubkey: ScriptBuf::new_p2wpkh(pubkey_hash),
 			},
-			satisfaction_weight: EMPTY_SCRIPT_SIG_WEIGHT + P2WPKH_WITNESS_WEIGHT,
+			satisfaction_weight: EMPTY_SCRIPT_SIG_WEIGHT +
+				estimate_inputs_witness_weight(&[(InputType::P2wpkh, 1)]),
 		}
 	}
 }
@@ -28,7 +28,7 @@ use crate::ln::types::{ChannelId, PaymentPreimage, PaymentHash, PaymentSecret};
 use crate::offers::invoice::Bolt12Invoice;
 use crate::onion_message::messenger::Responder;
 use crate::routing::gossip::NetworkUpdate;
-use crate::routing::router::{BlindedTail, Path, RouteHop, RouteParameters};
+use crate::routing::router::{BlindedTail, Path, RouteHop, RouteParameters, TrampolineTail};
 use crate::sign::SpendableOutputDescriptor;
 use crate::util::errors::APIError;
 use crate::util::ser::{BigSize, FixedLengthReader, Writeable, Writer, MaybeReadable, Readable, RequiredWrapper, UpgradableRequired, WithoutLength};
@@ -227,6 +227,29 @@ impl_writeable_tlv_based!(ClaimedHTLC, {
 	(6, value_msat, required),
 });
 
+/// The funding output for one of the channels in [`Event::BatchFundingGenerationReady`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchFundingOutput {
+	/// The random channel_id we picked, which you'll need to pass into
+	/// [`ChannelManager::batch_funding_transaction_generated`].
+	///
+	/// [`ChannelManager::batch_funding_transaction_generated`]: crate::ln::channelmanager::ChannelManager::batch_funding_transaction_generated
+	pub temporary_channel_id: ChannelId,
+	/// The counterparty's node_id, which you'll need to pass back into
+	/// [`ChannelManager::batch_funding_transaction_generated`].
+	///
+	/// [`ChannelManager::batch_funding_transaction_generated`]: crate::ln::channelmanager::ChannelManager::batch_funding_transaction_generated
+	pub counterparty_node_id: PublicKey,
+	/// The value, in satoshis, that the output should have.
+	pub channel_value_satoshis: u64,
+	/// The script which should be used in the transaction output.
+	pub output_script: ScriptBuf,
+	/// The `user_channel_id` value passed in the [`OpenChannelRequest`] for this channel.
+	///
+	/// [`OpenChannelRequest`]: crate::ln::channelmanager::OpenChannelRequest
+	pub user_channel_id: u128,
+}
+
 /// When the payment path failure took place and extra details about it. [`PathFailure::OnPath`] may
 /// contain a [`NetworkUpdate`] that needs to be applied to the [`NetworkGraph`].
 ///
@@ -579,6 +602,26 @@ pub enum Event {
 		/// [`UserConfig::manually_accept_inbound_channels`]: crate::util::config::UserConfig::manually_accept_inbound_channels
 		user_channel_id: u128,
 	},
+	/// Used to indicate that the client should generate a single funding transaction paying to all
+	/// of the given outputs and then call [`ChannelManager::batch_funding_transaction_generated`]
+	/// with every channel in the batch.
+	/// Generated in [`ChannelManager`] message handling once every channel opened via
+	/// [`ChannelManager::batch_open_channels`] has been accepted by its counterparty, in place of
+	/// an [`Event::FundingGenerationReady`] for each of them.
+	/// Note that *all inputs* in the funding transaction must spend SegWit outputs or your
+	/// counterparty can steal your funds!
+	///
+	/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+	/// [`ChannelManager::batch_funding_transaction_generated`]: crate::ln::channelmanager::ChannelManager::batch_funding_transaction_generated
+	/// [`ChannelManager::batch_open_channels`]: crate::ln::channelmanager::ChannelManager::batch_open_channels
+	BatchFundingGenerationReady {
+		/// The batch's [`BatchFundingHandle::batch_id`].
+		///
+		/// [`BatchFundingHandle::batch_id`]: crate::ln::channelmanager::BatchFundingHandle::batch_id
+		batch_id: u128,
+		/// The output each channel in the batch needs, in the order the channels were requested.
+		funding_outputs: Vec<BatchFundingOutput>,
+	},
 	/// Indicates that we've been offered a payment and it needs to be claimed via calling
 	/// [`ChannelManager::claim_funds`] with the preimage given in [`PaymentPurpose`].
 	///
@@ -599,6 +642,11 @@ pub enum Event {
 	/// [`ChannelManager::fail_htlc_backwards_with_reason`] within the HTLC's timeout, the HTLC will
 	/// be automatically failed.
 	///
+	/// For payments to a hold invoice, [`Event::PaymentClaimable::requires_manual_claim`] is set
+	/// and the preimage is not included in the [`PaymentPurpose`]. These should instead be settled
+	/// via [`ChannelManager::settle_held_payment`] or cancelled via
+	/// [`ChannelManager::cancel_held_payment`].
+	///
 	/// # Note
 	/// LDK will not stop an inbound payment from being paid multiple times, so multiple
 	/// `PaymentClaimable` events may be generated for the same payment. In such a case it is
@@ -613,6 +661,8 @@ pub enum Event {
 	/// [`FailureCode::InvalidOnionPayload`]: crate::ln::channelmanager::FailureCode::InvalidOnionPayload
 	/// [`ChannelManager::fail_htlc_backwards`]: crate::ln::channelmanager::ChannelManager::fail_htlc_backwards
 	/// [`ChannelManager::fail_htlc_backwards_with_reason`]: crate::ln::channelmanager::ChannelManager::fail_htlc_backwards_with_reason
+	/// [`ChannelManager::settle_held_payment`]: crate::ln::channelmanager::ChannelManager::settle_held_payment
+	/// [`ChannelManager::cancel_held_payment`]: crate::ln::channelmanager::ChannelManager::cancel_held_payment
 	PaymentClaimable {
 		/// The node that will receive the payment after it has been claimed.
 		/// This is useful to identify payments received via [phantom nodes].
@@ -663,6 +713,16 @@ pub enum Event {
 		///
 		/// [`ChannelManager::claim_funds`]: crate::ln::channelmanager::ChannelManager::claim_funds
 		claim_deadline: Option<u32>,
+		/// Whether this payment was made to a hold invoice created via
+		/// [`ChannelManager::create_inbound_payment_for_hold`], in which case it will not be claimed
+		/// until [`ChannelManager::settle_held_payment`] is called, even if the preimage is known.
+		///
+		/// Payments received on LDK versions which did not support hold invoices will have this
+		/// field set to `false`.
+		///
+		/// [`ChannelManager::create_inbound_payment_for_hold`]: crate::ln::channelmanager::ChannelManager::create_inbound_payment_for_hold
+		/// [`ChannelManager::settle_held_payment`]: crate::ln::channelmanager::ChannelManager::settle_held_payment
+		requires_manual_claim: bool,
 	},
 	/// Indicates a payment has been claimed and we've received money!
 	///
@@ -707,6 +767,22 @@ pub enum Event {
 		/// Payments received on LDK versions prior to 0.0.124 will have this field unset.
 		onion_fields: Option<RecipientOnionFields>,
 	},
+	/// Indicates an inbound payment registered via [`ChannelManager::create_inbound_payment`] (or
+	/// one of its variants) expired without ever being paid, and thus any further attempts to pay
+	/// it will fail. Applications may use this to mark the corresponding order as abandoned.
+	///
+	/// This is generated by [`ChannelManager::timer_tick_occurred`] once the block header time has
+	/// passed the invoice's expiry (plus some margin to compensate for the inaccuracy of block
+	/// header timestamps). It will not be generated for payments which were claimed or which were
+	/// removed via [`ChannelManager::remove_inbound_payment`].
+	///
+	/// [`ChannelManager::create_inbound_payment`]: crate::ln::channelmanager::ChannelManager::create_inbound_payment
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	/// [`ChannelManager::remove_inbound_payment`]: crate::ln::channelmanager::ChannelManager::remove_inbound_payment
+	InvoiceExpired {
+		/// The payment hash of the expired inbound payment.
+		payment_hash: PaymentHash,
+	},
 	/// Indicates that a peer connection with a node is needed in order to send an [`OnionMessage`].
 	///
 	/// Typically, this happens when a [`MessageRouter`] is unable to find a complete path to a
@@ -821,6 +897,33 @@ pub enum Event {
 		/// by versions prior to 0.0.115.
 		reason: Option<PaymentFailureReason>,
 	},
+	/// Indicates a circular rebalance started via [`ChannelManager::rebalance_channels`] has
+	/// completed, either successfully or not.
+	///
+	/// No [`Event::PaymentSent`], [`Event::PaymentFailed`], [`Event::PaymentClaimable`] or
+	/// [`Event::PaymentClaimed`] events are generated for rebalances, though per-path events are
+	/// still generated to allow for scoring channels.
+	///
+	/// [`ChannelManager::rebalance_channels`]: crate::ln::channelmanager::ChannelManager::rebalance_channels
+	RebalanceComplete {
+		/// The `payment_id` returned by [`ChannelManager::rebalance_channels`].
+		///
+		/// [`ChannelManager::rebalance_channels`]: crate::ln::channelmanager::ChannelManager::rebalance_channels
+		payment_id: PaymentId,
+		/// The channel over which we sent the rebalance, i.e., whose outbound liquidity was
+		/// decreased.
+		from_channel_id: ChannelId,
+		/// The channel over which the rebalance returned to us, i.e., whose outbound liquidity was
+		/// increased.
+		to_channel_id: ChannelId,
+		/// The amount, in thousandths of a satoshi, moved from `from_channel_id` to
+		/// `to_channel_id`, not including any fees.
+		amount_msat: u64,
+		/// The total fee paid to the nodes along the rebalance's path, if it succeeded.
+		fee_paid_msat: Option<u64>,
+		/// The reason the rebalance failed, or `None` if it succeeded.
+		failure_reason: Option<PaymentFailureReason>,
+	},
 	/// Indicates that a path for an outbound payment was successful.
 	///
 	/// Always generated after [`Event::PaymentSent`] and thus useful for scoring channels. See
@@ -963,6 +1066,16 @@ pub enum Event {
 		/// check that whatever fee you want has been included here or subtract it as required. Further,
 		/// LDK will not stop you from forwarding more than you received.
 		expected_outbound_amount_msat: u64,
+		/// The maximum fee, in msats, which may be skimmed by forwarding less than
+		/// `expected_outbound_amount_msat`, as limited by
+		/// [`UserConfig::max_intercepted_htlc_skimmed_fee_proportional_millionths`] when the HTLC was
+		/// intercepted.
+		///
+		/// For events serialized by versions prior to 0.0.124 this will be
+		/// `expected_outbound_amount_msat`.
+		///
+		/// [`UserConfig::max_intercepted_htlc_skimmed_fee_proportional_millionths`]: crate::util::config::UserConfig::max_intercepted_htlc_skimmed_fee_proportional_millionths
+		max_skimmed_fee_msat: u64,
 	},
 	/// Used to indicate that an output which you should know how to spend was confirmed on chain
 	/// and is now spendable.
@@ -1040,6 +1153,28 @@ pub enum Event {
 		/// The caveat described above the `total_fee_earned_msat` field applies here as well.
 		outbound_amount_forwarded_msat: Option<u64>,
 	},
+	/// Indicates a channel open queued via [`ChannelManager::create_channel_when_connected`] was
+	/// dropped as the counterparty did not connect within
+	/// [`UserConfig::queued_channel_open_timeout_ticks`].
+	///
+	/// No channel was created, and the open may simply be queued again if desired.
+	///
+	/// [`ChannelManager::create_channel_when_connected`]: crate::ln::channelmanager::ChannelManager::create_channel_when_connected
+	/// [`UserConfig::queued_channel_open_timeout_ticks`]: crate::util::config::UserConfig::queued_channel_open_timeout_ticks
+	ChannelOpenTimedOut {
+		/// The `user_channel_id` value passed in to
+		/// [`ChannelManager::create_channel_when_connected`].
+		///
+		/// [`ChannelManager::create_channel_when_connected`]: crate::ln::channelmanager::ChannelManager::create_channel_when_connected
+		user_channel_id: u128,
+		/// The temporary channel id returned by
+		/// [`ChannelManager::create_channel_when_connected`].
+		///
+		/// [`ChannelManager::create_channel_when_connected`]: crate::ln::channelmanager::ChannelManager::create_channel_when_connected
+		temporary_channel_id: ChannelId,
+		/// The `node_id` of the counterparty which never connected.
+		counterparty_node_id: PublicKey,
+	},
 	/// Used to indicate that a channel with the given `channel_id` is being opened and pending
 	/// confirmation on-chain.
 	///
@@ -1130,6 +1265,12 @@ pub enum Event {
 		/// status of the closing tx.
 		/// Note that for instances serialized in v0.0.119 or prior this will be missing (None).
 		channel_funding_txo: Option<transaction::OutPoint>,
+		/// The fee paid by the closing transaction we negotiated with our counterparty, in
+		/// satoshis.
+		///
+		/// This will be `None` if the channel wasn't closed cooperatively, or for objects serialized
+		/// prior to LDK 0.0.124.
+		closing_fee_satoshis: Option<u64>,
 	},
 	/// Used to indicate to the user that they can abandon the funding transaction and recycle the
 	/// inputs for another purpose.
@@ -1255,9 +1396,14 @@ impl Writeable for Event {
 				// We never write out FundingGenerationReady events as, upon disconnection, peers
 				// drop any channels which have not yet exchanged funding_signed.
 			},
+			&Event::BatchFundingGenerationReady { .. } => {
+				0u8.write(writer)?;
+				// Like FundingGenerationReady, we never write out BatchFundingGenerationReady
+				// events, and reuse its type so that they're skipped on read by all versions.
+			},
 			&Event::PaymentClaimable { ref payment_hash, ref amount_msat, counterparty_skimmed_fee_msat,
 				ref purpose, ref receiver_node_id, ref via_channel_id, ref via_user_channel_id,
-				ref claim_deadline, ref onion_fields
+				ref claim_deadline, ref onion_fields, ref requires_manual_claim
 			} => {
 				1u8.write(writer)?;
 				let mut payment_secret = None;
@@ -1303,6 +1449,7 @@ impl Writeable for Event {
 					(9, onion_fields, option),
 					(10, skimmed_fee_opt, option),
 					(11, payment_context, option),
+					(13, requires_manual_claim, required),
 				});
 			},
 			&Event::PaymentSent { ref payment_id, ref payment_preimage, ref payment_hash, ref fee_paid_msat } => {
@@ -1334,6 +1481,7 @@ impl Writeable for Event {
 					(3, false, required), // all_paths_failed in LDK versions prior to 0.0.114
 					(4, path.blinded_tail, option),
 					(5, path.hops, required_vec),
+					(6, path.trampoline_tail, option),
 					(7, short_channel_id, option),
 					(9, None::<RouteParameters>, option), // retry in LDK versions prior to 0.0.115
 					(11, payment_id, option),
@@ -1352,7 +1500,7 @@ impl Writeable for Event {
 					(1, channel_id, option),
 				});
 			},
-			&Event::HTLCIntercepted { requested_next_hop_scid, payment_hash, inbound_amount_msat, expected_outbound_amount_msat, intercept_id } => {
+			&Event::HTLCIntercepted { requested_next_hop_scid, payment_hash, inbound_amount_msat, expected_outbound_amount_msat, intercept_id, max_skimmed_fee_msat } => {
 				6u8.write(writer)?;
 				let intercept_scid = InterceptNextHop::FakeScid { requested_next_hop_scid };
 				write_tlv_fields!(writer, {
@@ -1361,6 +1509,7 @@ impl Writeable for Event {
 					(4, payment_hash, required),
 					(6, inbound_amount_msat, required),
 					(8, expected_outbound_amount_msat, required),
+					(9, max_skimmed_fee_msat, required),
 				});
 			}
 			&Event::PaymentForwarded {
@@ -1381,7 +1530,8 @@ impl Writeable for Event {
 				});
 			},
 			&Event::ChannelClosed { ref channel_id, ref user_channel_id, ref reason,
-				ref counterparty_node_id, ref channel_capacity_sats, ref channel_funding_txo
+				ref counterparty_node_id, ref channel_capacity_sats, ref channel_funding_txo,
+				ref closing_fee_satoshis,
 			} => {
 				9u8.write(writer)?;
 				// `user_channel_id` used to be a single u64 value. In order to remain backwards
@@ -1397,6 +1547,7 @@ impl Writeable for Event {
 					(5, counterparty_node_id, option),
 					(7, channel_capacity_sats, option),
 					(9, channel_funding_txo, option),
+					(11, closing_fee_satoshis, option),
 				});
 			},
 			&Event::DiscardFunding { ref channel_id, ref transaction } => {
@@ -1413,6 +1564,7 @@ impl Writeable for Event {
 					(2, payment_hash, option),
 					(4, path.hops, required_vec),
 					(6, path.blinded_tail, option),
+					(8, path.trampoline_tail, option),
 				})
 			},
 			&Event::PaymentFailed { ref payment_id, ref payment_hash, ref reason } => {
@@ -1447,6 +1599,7 @@ impl Writeable for Event {
 					(2, payment_hash, required),
 					(4, path.hops, required_vec),
 					(6, path.blinded_tail, option),
+					(8, path.trampoline_tail, option),
 				})
 			},
 			&Event::ProbeFailed { ref payment_id, ref payment_hash, ref path, ref short_channel_id } => {
@@ -1457,6 +1610,7 @@ impl Writeable for Event {
 					(4, path.hops, required_vec),
 					(6, short_channel_id, option),
 					(8, path.blinded_tail, option),
+					(10, path.trampoline_tail, option),
 				})
 			},
 			&Event::HTLCHandlingFailed { ref prev_channel_id, ref failed_next_destination } => {
@@ -1530,6 +1684,34 @@ impl Writeable for Event {
 					(4, responder, option),
 				})
 			},
+			&Event::InvoiceExpired { ref payment_hash } => {
+				43u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, payment_hash, required),
+				});
+			},
+			&Event::RebalanceComplete {
+				ref payment_id, ref from_channel_id, ref to_channel_id, ref amount_msat,
+				ref fee_paid_msat, ref failure_reason,
+			} => {
+				45u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, payment_id, required),
+					(2, from_channel_id, required),
+					(4, to_channel_id, required),
+					(6, amount_msat, required),
+					(8, fee_paid_msat, option),
+					(10, failure_reason, option),
+				});
+			},
+			&Event::ChannelOpenTimedOut { ref user_channel_id, ref temporary_channel_id, ref counterparty_node_id } => {
+				47u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, user_channel_id, required),
+					(2, temporary_channel_id, required),
+					(4, counterparty_node_id, required),
+				});
+			},
 			// Note that, going forward, all new events must only write data inside of
 			// `write_tlv_fields`. Versions 0.0.101+ will ignore odd-numbered events that write
 			// data via `write_tlv_fields`.
@@ -1556,6 +1738,7 @@ impl MaybeReadable for Event {
 					let mut via_user_channel_id = None;
 					let mut onion_fields = None;
 					let mut payment_context = None;
+					let mut requires_manual_claim = None;
 					read_tlv_fields!(reader, {
 						(0, payment_hash, required),
 						(1, receiver_node_id, option),
@@ -1569,6 +1752,7 @@ impl MaybeReadable for Event {
 						(9, onion_fields, option),
 						(10, counterparty_skimmed_fee_msat_opt, option),
 						(11, payment_context, option),
+						(13, requires_manual_claim, option),
 					});
 					let purpose = match payment_secret {
 						Some(secret) => PaymentPurpose::from_parts(payment_preimage, secret, payment_context),
@@ -1585,6 +1769,7 @@ impl MaybeReadable for Event {
 						via_user_channel_id,
 						claim_deadline,
 						onion_fields,
+						requires_manual_claim: requires_manual_claim.unwrap_or(false),
 					}))
 				};
 				f()
@@ -1623,6 +1808,7 @@ impl MaybeReadable for Event {
 					let mut payment_failed_permanently = false;
 					let mut network_update = None;
 					let mut blinded_tail: Option<BlindedTail> = None;
+					let mut trampoline_tail: Option<TrampolineTail> = None;
 					let mut path: Option<Vec<RouteHop>> = Some(vec![]);
 					let mut short_channel_id = None;
 					let mut payment_id = None;
@@ -1635,6 +1821,7 @@ impl MaybeReadable for Event {
 						// Added as a part of LDK 0.0.101 and always filled in since.
 						// Defaults to an empty Vec, though likely should have been `Option`al.
 						(5, path, optional_vec),
+						(6, trampoline_tail, option),
 						(7, short_channel_id, option),
 						(11, payment_id, option),
 						(13, failure_opt, upgradable_option),
@@ -1645,7 +1832,7 @@ impl MaybeReadable for Event {
 						payment_hash,
 						payment_failed_permanently,
 						failure,
-						path: Path { hops: path.unwrap(), blinded_tail },
+						path: Path { hops: path.unwrap(), blinded_tail, trampoline_tail },
 						short_channel_id,
 						#[cfg(test)]
 						error_code,
@@ -1674,12 +1861,14 @@ impl MaybeReadable for Event {
 				let mut requested_next_hop_scid = InterceptNextHop::FakeScid { requested_next_hop_scid: 0 };
 				let mut inbound_amount_msat = 0;
 				let mut expected_outbound_amount_msat = 0;
+				let mut max_skimmed_fee_msat = None;
 				read_tlv_fields!(reader, {
 					(0, intercept_id, required),
 					(2, requested_next_hop_scid, required),
 					(4, payment_hash, required),
 					(6, inbound_amount_msat, required),
 					(8, expected_outbound_amount_msat, required),
+					(9, max_skimmed_fee_msat, option),
 				});
 				let next_scid = match requested_next_hop_scid {
 					InterceptNextHop::FakeScid { requested_next_hop_scid: scid } => scid
@@ -1690,6 +1879,7 @@ impl MaybeReadable for Event {
 					inbound_amount_msat,
 					expected_outbound_amount_msat,
 					intercept_id,
+					max_skimmed_fee_msat: max_skimmed_fee_msat.unwrap_or(expected_outbound_amount_msat),
 				}))
 			},
 			7u8 => {
@@ -1729,6 +1919,7 @@ impl MaybeReadable for Event {
 					let mut counterparty_node_id = None;
 					let mut channel_capacity_sats = None;
 					let mut channel_funding_txo = None;
+					let mut closing_fee_satoshis = None;
 					read_tlv_fields!(reader, {
 						(0, channel_id, required),
 						(1, user_channel_id_low_opt, option),
@@ -1737,6 +1928,7 @@ impl MaybeReadable for Event {
 						(5, counterparty_node_id, option),
 						(7, channel_capacity_sats, option),
 						(9, channel_funding_txo, option),
+						(11, closing_fee_satoshis, option),
 					});
 
 					// `user_channel_id` used to be a single u64 value. In order to remain
@@ -1746,7 +1938,7 @@ impl MaybeReadable for Event {
 						((user_channel_id_high_opt.unwrap_or(0) as u128) << 64);
 
 					Ok(Some(Event::ChannelClosed { channel_id, user_channel_id, reason: _init_tlv_based_struct_field!(reason, upgradable_required),
-						counterparty_node_id, channel_capacity_sats, channel_funding_txo }))
+						counterparty_node_id, channel_capacity_sats, channel_funding_txo, closing_fee_satoshis }))
 				};
 				f()
 			},
@@ -1769,11 +1961,12 @@ impl MaybeReadable for Event {
 						(2, payment_hash, option),
 						(4, path, required_vec),
 						(6, blinded_tail, option),
+						(8, trampoline_tail, option),
 					});
 					Ok(Some(Event::PaymentPathSuccessful {
 						payment_id: payment_id.0.unwrap(),
 						payment_hash,
-						path: Path { hops: path, blinded_tail },
+						path: Path { hops: path, blinded_tail, trampoline_tail },
 					}))
 				};
 				f()
@@ -1837,11 +2030,12 @@ impl MaybeReadable for Event {
 						(2, payment_hash, required),
 						(4, path, required_vec),
 						(6, blinded_tail, option),
+						(8, trampoline_tail, option),
 					});
 					Ok(Some(Event::ProbeSuccessful {
 						payment_id: payment_id.0.unwrap(),
 						payment_hash: payment_hash.0.unwrap(),
-						path: Path { hops: path, blinded_tail },
+						path: Path { hops: path, blinded_tail, trampoline_tail },
 					}))
 				};
 				f()
@@ -1854,11 +2048,12 @@ impl MaybeReadable for Event {
 						(4, path, required_vec),
 						(6, short_channel_id, option),
 						(8, blinded_tail, option),
+						(10, trampoline_tail, option),
 					});
 					Ok(Some(Event::ProbeFailed {
 						payment_id: payment_id.0.unwrap(),
 						payment_hash: payment_hash.0.unwrap(),
-						path: Path { hops: path, blinded_tail },
+						path: Path { hops: path, blinded_tail, trampoline_tail },
 						short_channel_id,
 					}))
 				};
@@ -1981,6 +2176,53 @@ impl MaybeReadable for Event {
 				};
 				f()
 			},
+			43u8 => {
+				let mut f = || {
+					_init_and_read_len_prefixed_tlv_fields!(reader, {
+						(0, payment_hash, required),
+					});
+					Ok(Some(Event::InvoiceExpired {
+						payment_hash: payment_hash.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			45u8 => {
+				let mut f = || {
+					_init_and_read_len_prefixed_tlv_fields!(reader, {
+						(0, payment_id, required),
+						(2, from_channel_id, required),
+						(4, to_channel_id, required),
+						(6, amount_msat, required),
+						(8, fee_paid_msat, option),
+						(10, failure_reason, upgradable_option),
+					});
+					Ok(Some(Event::RebalanceComplete {
+						payment_id: payment_id.0.unwrap(),
+						from_channel_id: from_channel_id.0.unwrap(),
+						to_channel_id: to_channel_id.0.unwrap(),
+						amount_msat: amount_msat.0.unwrap(),
+						fee_paid_msat,
+						failure_reason,
+					}))
+				};
+				f()
+			},
+			47u8 => {
+				let mut f = || {
+					_init_and_read_len_prefixed_tlv_fields!(reader, {
+						(0, user_channel_id, required),
+						(2, temporary_channel_id, required),
+						(4, counterparty_node_id, required),
+					});
+					Ok(Some(Event::ChannelOpenTimedOut {
+						user_channel_id: user_channel_id.0.unwrap(),
+						temporary_channel_id: temporary_channel_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+					}))
+				};
+				f()
+			},
 			// Versions prior to 0.0.100 did not ignore odd types, instead returning InvalidValue.
 			// Version 0.0.100 failed to properly ignore odd types, possibly resulting in corrupt
 			// reads.
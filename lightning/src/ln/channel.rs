@@ -82,6 +82,15 @@ pub struct AvailableBalances {
 	pub next_outbound_htlc_limit_msat: u64,
 	/// The minimum value we can assign to the next outbound HTLC
 	pub next_outbound_htlc_minimum_msat: u64,
+	/// The amount set aside to pay the commitment transaction fee (including our fee spike buffer)
+	/// and anchor outputs when adding the next outbound HTLC. Zero if we don't pay the fee.
+	pub commitment_fee_buffer_msat: u64,
+	/// The total value of our pending outbound HTLCs.
+	pub pending_outbound_htlcs_msat: u64,
+	/// How much more dust HTLC exposure we can take on before hitting our limit.
+	pub dust_exposure_headroom_msat: u64,
+	/// An upper bound on the total value we can send across several outbound HTLCs.
+	pub max_outbound_total_msat: u64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -937,6 +946,9 @@ pub(crate) struct ShutdownResult {
 	pub(crate) counterparty_node_id: PublicKey,
 	pub(crate) unbroadcasted_funding_tx: Option<Transaction>,
 	pub(crate) channel_funding_txo: Option<OutPoint>,
+	/// The fee of the closing transaction we agreed on with our counterparty, if the channel was
+	/// closed cooperatively.
+	pub(crate) closing_fee_satoshis: Option<u64>,
 }
 
 /// Tracks the transaction number, along with current and next commitment points.
@@ -1259,6 +1271,9 @@ pub(super) struct ChannelContext<SP: Deref> where SP::Target: SignerProvider {
 
 	last_sent_closing_fee: Option<(u64, Signature)>, // (fee, holder_sig)
 	target_closing_feerate_sats_per_kw: Option<u32>,
+	/// The highest feerate the user allowed for the closing transaction when initiating shutdown,
+	/// if any. This caps our maximum closing fee, overriding our usual fee estimate-based limit.
+	max_closing_feerate_sats_per_kw: Option<u32>,
 
 	/// If our counterparty sent us a closing_signed while we were waiting for a `ChannelMonitor`
 	/// update, we need to delay processing it until later. We do that here by simply storing the
@@ -1697,6 +1712,7 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 			expecting_peer_commitment_signed: false,
 			closing_fee_limits: None,
 			target_closing_feerate_sats_per_kw: None,
+			max_closing_feerate_sats_per_kw: None,
 
 			funding_tx_confirmed_in: None,
 			funding_tx_confirmation_height: 0,
@@ -1923,6 +1939,7 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 			expecting_peer_commitment_signed: false,
 			closing_fee_limits: None,
 			target_closing_feerate_sats_per_kw: None,
+			max_closing_feerate_sats_per_kw: None,
 
 			funding_tx_confirmed_in: None,
 			funding_tx_confirmation_height: 0,
@@ -2366,6 +2383,10 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 		self.channel_value_satoshis
 	}
 
+	pub fn get_value_to_self_msat(&self) -> u64 {
+		self.value_to_self_msat
+	}
+
 	pub fn get_fee_proportional_millionths(&self) -> u32 {
 		self.config.options.forwarding_fee_proportional_millionths
 	}
@@ -3005,6 +3026,8 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 					context.counterparty_selected_channel_reserve_satoshis.unwrap_or(0) * 1000);
 
 		let mut available_capacity_msat = outbound_capacity_msat;
+		let mut commitment_fee_buffer_msat = 0;
+		let mut only_dust_sendable = false;
 
 		let anchor_outputs_value_msat = if context.get_channel_type().supports_anchors_zero_fee_htlc_tx() {
 			ANCHOR_OUTPUT_VALUE_SATOSHI * 2 * 1000
@@ -3033,6 +3056,8 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 				min_reserved_commit_tx_fee_msat *= FEE_SPIKE_BUFFER_FEE_INCREASE_MULTIPLE;
 			}
 
+			commitment_fee_buffer_msat = max_reserved_commit_tx_fee_msat + anchor_outputs_value_msat;
+
 			// We will first subtract the fee as if we were above-dust. Then, if the resulting
 			// value ends up being below dust, we have this fee available again. In that case,
 			// match the value to right-below-dust.
@@ -3066,6 +3091,7 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 				// If another HTLC's fee would reduce the remote's balance below the reserve limit
 				// we've selected for them, we can only send dust HTLCs.
 				available_capacity_msat = cmp::min(available_capacity_msat, real_dust_limit_success_sat * 1000 - 1);
+				only_dust_sendable = true;
 			}
 		}
 
@@ -3097,6 +3123,7 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 				// If adding an extra HTLC would put us over the dust limit in total fees, we cannot
 				// send any non-dust HTLCs.
 				available_capacity_msat = cmp::min(available_capacity_msat, htlc_success_dust_limit * 1000);
+				only_dust_sendable = true;
 			}
 		}
 
@@ -3123,13 +3150,27 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 			}
 		}
 
-		available_capacity_msat = cmp::min(available_capacity_msat,
-			context.counterparty_max_htlc_value_in_flight_msat - htlc_stats.pending_outbound_htlcs_value_msat);
+		let in_flight_headroom_msat =
+			context.counterparty_max_htlc_value_in_flight_msat - htlc_stats.pending_outbound_htlcs_value_msat;
+		available_capacity_msat = cmp::min(available_capacity_msat, in_flight_headroom_msat);
+
+		// Each further HTLC adds to the commitment transaction fee, so this is only an upper bound,
+		// but it's never below what we can send in the next HTLC.
+		let mut max_outbound_total_msat = if only_dust_sendable {
+			available_capacity_msat
+		} else {
+			cmp::max(available_capacity_msat, cmp::min(in_flight_headroom_msat,
+				outbound_capacity_msat.saturating_sub(commitment_fee_buffer_msat)))
+		};
 
 		if htlc_stats.pending_outbound_htlcs + 1 > context.counterparty_max_accepted_htlcs as usize {
 			available_capacity_msat = 0;
+			max_outbound_total_msat = 0;
 		}
 
+		let dust_exposure_headroom_msat = max_dust_htlc_exposure_msat.saturating_sub(cmp::max(
+			htlc_stats.on_counterparty_tx_dust_exposure_msat, htlc_stats.on_holder_tx_dust_exposure_msat));
+
 		AvailableBalances {
 			inbound_capacity_msat: cmp::max(context.channel_value_satoshis as i64 * 1000
 					- context.value_to_self_msat as i64
@@ -3140,6 +3181,10 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 			next_outbound_htlc_limit_msat: available_capacity_msat,
 			next_outbound_htlc_minimum_msat,
 			balance_msat,
+			commitment_fee_buffer_msat,
+			pending_outbound_htlcs_msat: htlc_stats.pending_outbound_htlcs_value_msat,
+			dust_exposure_headroom_msat,
+			max_outbound_total_msat,
 		}
 	}
 
@@ -3388,6 +3433,16 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 	/// Also returns the list of payment_hashes for channels which we can safely fail backwards
 	/// immediately (others we will have to allow to time out).
 	pub fn force_shutdown(&mut self, should_broadcast: bool, closure_reason: ClosureReason) -> ShutdownResult {
+		self.force_shutdown_inner(should_broadcast, false, closure_reason)
+	}
+
+	/// Like [`Self::force_shutdown`], but the monitor is told the user will broadcast the latest
+	/// commitment transaction themselves, so only tracks its claims instead of broadcasting it.
+	pub fn force_shutdown_for_external_broadcast(&mut self, closure_reason: ClosureReason) -> ShutdownResult {
+		self.force_shutdown_inner(true, true, closure_reason)
+	}
+
+	fn force_shutdown_inner(&mut self, should_broadcast: bool, broadcast_externally: bool, closure_reason: ClosureReason) -> ShutdownResult {
 		// Note that we MUST only generate a monitor update that indicates force-closure - we're
 		// called during initialization prior to the chain_monitor in the encompassing ChannelManager
 		// being fully configured in some cases. Thus, its likely any monitor events we generate will
@@ -3419,7 +3474,7 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 				Some((self.get_counterparty_node_id(), funding_txo, self.channel_id(), ChannelMonitorUpdate {
 					update_id: self.latest_monitor_update_id,
 					counterparty_node_id: Some(self.counterparty_node_id),
-					updates: vec![ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast }],
+					updates: vec![ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast, broadcast_externally }],
 					channel_id: Some(self.channel_id()),
 				}))
 			} else { None }
@@ -3440,6 +3495,7 @@ impl<SP: Deref> ChannelContext<SP> where SP::Target: SignerProvider  {
 			counterparty_node_id: self.counterparty_node_id,
 			unbroadcasted_funding_tx,
 			channel_funding_txo: self.get_funding_txo(),
+			closing_fee_satoshis: None,
 		}
 	}
 
@@ -5729,8 +5785,8 @@ impl<SP: Deref> Channel<SP> where
 		// relatively rare case. We can revisit this later, though note that in order to determine
 		// if the funders' output is dust we have to know the absolute fee we're going to use.
 		let tx_weight = self.get_closing_transaction_weight(Some(&self.get_closing_scriptpubkey()), Some(self.context.counterparty_shutdown_scriptpubkey.as_ref().unwrap()));
-		let proposed_total_fee_satoshis = proposed_feerate as u64 * tx_weight / 1000;
-		let proposed_max_total_fee_satoshis = if self.context.is_outbound() {
+		let mut proposed_total_fee_satoshis = proposed_feerate as u64 * tx_weight / 1000;
+		let mut proposed_max_total_fee_satoshis = if self.context.is_outbound() {
 				// We always add force_close_avoidance_max_fee_satoshis to our normal
 				// feerate-calculated fee, but allow the max to be overridden if we're using a
 				// target feerate-calculated fee.
@@ -5740,6 +5796,15 @@ impl<SP: Deref> Channel<SP> where
 				self.context.channel_value_satoshis - (self.context.value_to_self_msat + 999) / 1000
 			};
 
+		// If we're paying and the user capped the closing feerate, never go above the cap, even if
+		// that means proposing less than our fee estimates would have us.
+		if let Some(max_feerate) = self.context.max_closing_feerate_sats_per_kw {
+			if self.context.is_outbound() {
+				proposed_max_total_fee_satoshis = max_feerate as u64 * tx_weight / 1000;
+				proposed_total_fee_satoshis = cmp::min(proposed_total_fee_satoshis, proposed_max_total_fee_satoshis);
+			}
+		}
+
 		self.context.closing_fee_limits = Some((proposed_total_fee_satoshis, proposed_max_total_fee_satoshis));
 		self.context.closing_fee_limits.clone().unwrap()
 	}
@@ -6037,6 +6102,7 @@ impl<SP: Deref> Channel<SP> where
 					counterparty_node_id: self.context.counterparty_node_id,
 					unbroadcasted_funding_tx: self.context.unbroadcasted_funding(),
 					channel_funding_txo: self.context.get_funding_txo(),
+					closing_fee_satoshis: Some(last_fee),
 				};
 				let tx = self.build_signed_closing_transaction(&mut closing_tx, &msg.signature, &sig);
 				self.context.channel_state = ChannelState::ShutdownComplete;
@@ -6072,6 +6138,7 @@ impl<SP: Deref> Channel<SP> where
 								counterparty_node_id: self.context.counterparty_node_id,
 								unbroadcasted_funding_tx: self.context.unbroadcasted_funding(),
 								channel_funding_txo: self.context.get_funding_txo(),
+								closing_fee_satoshis: Some(used_fee),
 							};
 							self.context.channel_state = ChannelState::ShutdownComplete;
 							self.context.update_time_counter += 1;
@@ -6206,6 +6273,24 @@ impl<SP: Deref> Channel<SP> where
 
 		let dust_exposure_limiting_feerate = self.context.get_dust_exposure_limiting_feerate(&fee_estimator);
 		let htlc_stats = self.context.get_pending_htlc_stats(None, dust_exposure_limiting_feerate);
+
+		// The HTLC may or may not have been added to our pending inbound HTLCs already, depending
+		// on whether we're checking it upon receipt or once its onion was decoded.
+		let msg_pending_value_msat =
+			if self.context.pending_inbound_htlcs.iter().any(|htlc| htlc.htlc_id == msg.htlc_id) {
+				0
+			} else {
+				msg.amount_msat
+			};
+		let max_inbound_htlc_value_in_flight_msat = self.context.config().max_inbound_htlc_value_in_flight_msat;
+		let inbound_htlc_value_in_flight_msat =
+			htlc_stats.pending_inbound_htlcs_value_msat.saturating_add(msg_pending_value_msat);
+		if inbound_htlc_value_in_flight_msat > max_inbound_htlc_value_in_flight_msat {
+			log_info!(logger, "Cannot accept value that would put our inbound HTLC value in flight at {} over the limit {}",
+				inbound_htlc_value_in_flight_msat, max_inbound_htlc_value_in_flight_msat);
+			return Err(("Exceeded our max inbound HTLC value in flight", 0x1000|7))
+		}
+
 		let max_dust_htlc_exposure_msat = self.context.get_max_dust_htlc_exposure_msat(dust_exposure_limiting_feerate);
 		let (htlc_timeout_dust_limit, htlc_success_dust_limit) = if self.context.get_channel_type().supports_anchors_zero_fee_htlc_tx() {
 			(0, 0)
@@ -7327,7 +7412,8 @@ impl<SP: Deref> Channel<SP> where
 	/// Begins the shutdown process, getting a message for the remote peer and returning all
 	/// holding cell HTLCs for payment failure.
 	pub fn get_shutdown(&mut self, signer_provider: &SP, their_features: &InitFeatures,
-		target_feerate_sats_per_kw: Option<u32>, override_shutdown_script: Option<ShutdownScript>)
+		target_feerate_sats_per_kw: Option<u32>, max_feerate_sats_per_kw: Option<u32>,
+		override_shutdown_script: Option<ShutdownScript>)
 	-> Result<(msgs::Shutdown, Option<ChannelMonitorUpdate>, Vec<(HTLCSource, PaymentHash)>), APIError>
 	{
 		for htlc in self.context.pending_outbound_htlcs.iter() {
@@ -7373,6 +7459,7 @@ impl<SP: Deref> Channel<SP> where
 
 		// From here on out, we may not fail!
 		self.context.target_closing_feerate_sats_per_kw = target_feerate_sats_per_kw;
+		self.context.max_closing_feerate_sats_per_kw = max_feerate_sats_per_kw;
 		self.context.channel_state.set_local_shutdown_sent();
 		self.context.local_initiated_shutdown = Some(());
 		self.context.update_time_counter += 1;
@@ -8762,6 +8849,7 @@ impl<SP: Deref> Writeable for Channel<SP> where SP::Target: SignerProvider {
 			(45, cur_holder_commitment_point, option),
 			(47, next_holder_commitment_point, option),
 			(49, self.context.local_initiated_shutdown, option), // Added in 0.0.122
+			(51, self.context.max_closing_feerate_sats_per_kw, option), // Added in 0.0.124
 		});
 
 		Ok(())
@@ -9034,6 +9122,7 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 
 		let mut announcement_sigs = None;
 		let mut target_closing_feerate_sats_per_kw = None;
+		let mut max_closing_feerate_sats_per_kw: Option<u32> = None;
 		let mut monitor_pending_finalized_fulfills = Some(Vec::new());
 		let mut holder_selected_channel_reserve_satoshis = Some(get_legacy_default_holder_selected_channel_reserve_satoshis(channel_value_satoshis));
 		let mut holder_max_htlc_value_in_flight_msat = Some(get_holder_max_htlc_value_in_flight_msat(channel_value_satoshis, &UserConfig::default().channel_handshake_config));
@@ -9107,6 +9196,7 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 			(45, cur_holder_commitment_point_opt, option),
 			(47, next_holder_commitment_point_opt, option),
 			(49, local_initiated_shutdown, option),
+			(51, max_closing_feerate_sats_per_kw, option), // Added in 0.0.124
 		});
 
 		let (channel_keys_id, holder_signer) = if let Some(channel_keys_id) = channel_keys_id {
@@ -9299,6 +9389,7 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 				expecting_peer_commitment_signed: false,
 				closing_fee_limits: None,
 				target_closing_feerate_sats_per_kw,
+				max_closing_feerate_sats_per_kw,
 
 				funding_tx_confirmed_in,
 				funding_tx_confirmation_height,
@@ -9588,7 +9679,7 @@ mod tests {
 			cltv_expiry: 200000000,
 			state: OutboundHTLCState::Committed,
 			source: HTLCSource::OutboundRoute {
-				path: Path { hops: Vec::new(), blinded_tail: None },
+				path: Path { hops: Vec::new(), blinded_tail: None, trampoline_tail: None },
 				session_priv: SecretKey::from_slice(&<Vec<u8>>::from_hex("0fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff").unwrap()[..]).unwrap(),
 				first_hop_htlc_msat: 548,
 				payment_id: PaymentId([42; 32]),
@@ -9962,7 +10053,8 @@ mod tests {
 					node_features: NodeFeatures::empty(), short_channel_id: 0, fee_msat: 0,
 					cltv_expiry_delta: 0, maybe_announced_channel: false,
 				}],
-				blinded_tail: None
+				blinded_tail: None,
+				trampoline_tail: None
 			},
 			session_priv: test_utils::privkey(42),
 			first_hop_htlc_msat: 0,
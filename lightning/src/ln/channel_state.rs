@@ -674,6 +674,74 @@ impl Readable for ChannelDetails {
 	}
 }
 
+/// A breakdown of how much we can send over a channel, explaining how
+/// [`ChannelDetails::outbound_capacity_msat`] and [`ChannelDetails::next_outbound_htlc_limit_msat`]
+/// relate to our balance in the channel.
+///
+/// Returned by [`ChannelManager::channel_balance_details`].
+///
+/// [`ChannelManager::channel_balance_details`]: crate::ln::channelmanager::ChannelManager::channel_balance_details
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelBalanceBreakdown {
+	/// Our balance in the channel, including the value of our pending outbound HTLCs and before
+	/// subtracting any reserve or fees.
+	pub local_balance_msat: u64,
+	/// The reserve our counterparty requires us to keep in the channel, which we cannot send. See
+	/// [`ChannelDetails::unspendable_punishment_reserve`].
+	pub counterparty_selected_reserve_msat: u64,
+	/// The total value of our pending outbound HTLCs, which is not available to send again until
+	/// they are resolved.
+	pub pending_outbound_htlcs_msat: u64,
+	/// The amount set aside for the commitment transaction fee at the current feerate when adding
+	/// another HTLC, including our buffer against feerate spikes and, for anchor channels, the
+	/// value of the anchor outputs.
+	///
+	/// This is always zero for inbound channels, as our counterparty pays the commitment
+	/// transaction fee.
+	pub commitment_fee_buffer_msat: u64,
+	/// How much more dust HTLC exposure we can take on before reaching the limit set via
+	/// [`ChannelConfig::max_dust_htlc_exposure`].
+	///
+	/// Once this is exhausted, we can no longer send HTLCs which would be dust.
+	pub dust_exposure_headroom_msat: u64,
+	/// Our balance less our pending outbound HTLCs and the counterparty-selected reserve. This is
+	/// the same as [`ChannelDetails::outbound_capacity_msat`].
+	pub outbound_capacity_msat: u64,
+	/// The maximum value we can send in a single HTLC right now. This is the same as
+	/// [`ChannelDetails::next_outbound_htlc_limit_msat`], and is what the router uses when
+	/// considering this channel as a first hop.
+	pub max_sendable_htlc_msat: u64,
+	/// An upper bound on the total value we can send over several HTLCs right now, accounting for
+	/// the commitment fee buffer and our counterparty's in-flight and HTLC count limits.
+	///
+	/// As each further HTLC increases the commitment transaction fee, the actual total may be
+	/// slightly lower. This is never below [`Self::max_sendable_htlc_msat`].
+	pub max_sendable_total_msat: u64,
+}
+
+impl ChannelBalanceBreakdown {
+	pub(super) fn from_channel_context<SP: Deref, F: Deref>(
+		context: &ChannelContext<SP>, fee_estimator: &LowerBoundedFeeEstimator<F>,
+	) -> Self
+	where
+		SP::Target: SignerProvider,
+		F::Target: FeeEstimator,
+	{
+		let balance = context.get_available_balances(fee_estimator);
+		let (_, to_self_reserve_satoshis) = context.get_holder_counterparty_selected_channel_reserve_satoshis();
+		ChannelBalanceBreakdown {
+			local_balance_msat: context.get_value_to_self_msat(),
+			counterparty_selected_reserve_msat: to_self_reserve_satoshis.unwrap_or(0) * 1000,
+			pending_outbound_htlcs_msat: balance.pending_outbound_htlcs_msat,
+			commitment_fee_buffer_msat: balance.commitment_fee_buffer_msat,
+			dust_exposure_headroom_msat: balance.dust_exposure_headroom_msat,
+			outbound_capacity_msat: balance.outbound_capacity_msat,
+			max_sendable_htlc_msat: balance.next_outbound_htlc_limit_msat,
+			max_sendable_total_msat: balance.max_outbound_total_msat,
+		}
+	}
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 /// Further information on the details of the channel shutdown.
 /// Upon channels being forced closed (i.e. commitment transaction confirmation detected
@@ -37,7 +37,7 @@ use crate::blinded_path::payment::{Bolt12OfferContext, Bolt12RefundContext, Paym
 use crate::chain;
 use crate::chain::{Confirm, ChannelMonitorUpdateStatus, Watch, BestBlock};
 use crate::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator, LowerBoundedFeeEstimator};
-use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, WithChannelMonitor, ChannelMonitorUpdateStep, HTLC_FAIL_BACK_BUFFER, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ANTI_REORG_DELAY, MonitorEvent, CLOSED_CHANNEL_UPDATE_ID};
+use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ClosingTxPackage, WithChannelMonitor, ChannelMonitorUpdateStep, HTLC_FAIL_BACK_BUFFER, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ANTI_REORG_DELAY, MonitorEvent, CLOSED_CHANNEL_UPDATE_ID};
 use crate::chain::transaction::{OutPoint, TransactionData};
 use crate::events;
 use crate::events::{Event, EventHandler, EventsProvider, MessageSendEvent, MessageSendEventsProvider, ClosureReason, HTLCDestination, PaymentFailureReason};
@@ -46,11 +46,10 @@ use crate::events::{Event, EventHandler, EventsProvider, MessageSendEvent, Messa
 use crate::ln::inbound_payment;
 use crate::ln::types::{ChannelId, PaymentHash, PaymentPreimage, PaymentSecret};
 use crate::ln::channel::{self, Channel, ChannelPhase, ChannelContext, ChannelError, ChannelUpdateStatus, ShutdownResult, UnfundedChannelContext, UpdateFulfillCommitFetch, OutboundV1Channel, InboundV1Channel, WithChannelContext};
-use crate::ln::channel_state::ChannelDetails;
-use crate::ln::features::{Bolt12InvoiceFeatures, ChannelFeatures, ChannelTypeFeatures, InitFeatures, NodeFeatures};
-#[cfg(any(feature = "_test_utils", test))]
-use crate::ln::features::Bolt11InvoiceFeatures;
-use crate::routing::router::{BlindedTail, InFlightHtlcs, Path, Payee, PaymentParameters, Route, RouteParameters, Router};
+use crate::ln::channel_state::{ChannelBalanceBreakdown, ChannelDetails};
+use crate::ln::features::{Bolt11InvoiceFeatures, Bolt12InvoiceFeatures, ChannelFeatures, ChannelTypeFeatures, InitFeatures, NodeFeatures};
+use crate::routing::router::{BlindedTail, InFlightHtlcs, Path, Payee, PaymentParameters, Route, RouteHint, RouteHintHop, RouteParameters, Router, TrampolineTail, find_trampoline_route};
+use crate::routing::gossip::RoutingFees;
 use crate::ln::onion_payment::{check_incoming_htlc_cltv, create_recv_pending_htlc_info, create_fwd_pending_htlc_info, decode_incoming_update_add_htlc_onion, InboundHTLCErr, NextPacketDetails};
 use crate::ln::msgs;
 use crate::ln::onion_utils;
@@ -71,7 +70,7 @@ use crate::onion_message::messenger::{new_pending_onion_message, Destination, Me
 use crate::onion_message::offers::{OffersMessage, OffersMessageHandler};
 use crate::sign::{EntropySource, NodeSigner, Recipient, SignerProvider};
 use crate::sign::ecdsa::EcdsaChannelSigner;
-use crate::util::config::{UserConfig, ChannelConfig, ChannelConfigUpdate};
+use crate::util::config::{UserConfig, ChannelAcceptOverrides, ChannelConfig, ChannelConfigUpdate};
 use crate::util::wakers::{Future, Notifier};
 use crate::util::scid_utils::fake_scid;
 use crate::util::string::UntrustedString;
@@ -143,6 +142,11 @@ pub enum PendingHTLCRouting {
 		short_channel_id: u64, // This should be NonZero<u64> eventually when we bump MSRV
 		/// Set if this HTLC is being forwarded within a blinded path.
 		blinded: Option<BlindedForward>,
+		/// The absolute CLTV of the inbound HTLC
+		///
+		/// This field was added in LDK 0.0.124 and will be `None` for objects written by prior
+		/// versions.
+		incoming_cltv_expiry: Option<u32>,
 	},
 	/// The onion indicates that this is a payment for an invoice (supposedly) generated by us.
 	///
@@ -448,6 +452,60 @@ impl Readable for InterceptId {
 	}
 }
 
+/// Details of an HTLC which was intercepted and is pending a call to
+/// [`ChannelManager::forward_intercepted_htlc`] or [`ChannelManager::fail_intercepted_htlc`].
+///
+/// Unlike the [`HTLCIntercepted`] event, these can be retrieved at any time, e.g. to re-inspect
+/// pending intercepts after a restart, via [`ChannelManager::get_intercepted_htlc`] and
+/// [`ChannelManager::list_intercepted_htlcs`].
+///
+/// [`HTLCIntercepted`]: events::Event::HTLCIntercepted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterceptedHtlcDetails {
+	/// An id to help LDK identify which HTLC is being forwarded or failed.
+	pub intercept_id: InterceptId,
+	/// The fake scid that was programmed as the next hop's scid, generated using
+	/// [`ChannelManager::get_intercept_scid`].
+	pub requested_next_hop_scid: u64,
+	/// The payment hash used for this HTLC.
+	pub payment_hash: PaymentHash,
+	/// How many msats were received on the inbound edge of this HTLC.
+	pub inbound_amount_msat: u64,
+	/// How many msats the payer intended to route to the next node, as in
+	/// [`HTLCIntercepted::expected_outbound_amount_msat`].
+	///
+	/// [`HTLCIntercepted::expected_outbound_amount_msat`]: events::Event::HTLCIntercepted::expected_outbound_amount_msat
+	pub expected_outbound_amount_msat: u64,
+	/// The absolute CLTV expiry of the inbound HTLC.
+	///
+	/// Will be `None` for HTLCs intercepted prior to LDK 0.0.124.
+	pub incoming_cltv_expiry: Option<u32>,
+	/// The absolute CLTV expiry the payer intended the forwarded HTLC to have.
+	pub expected_outgoing_cltv_expiry: u32,
+	/// The channel over which the HTLC was received.
+	pub prev_channel_id: ChannelId,
+}
+
+impl InterceptedHtlcDetails {
+	fn from_pending_htlc(intercept_id: InterceptId, htlc: &PendingAddHTLCInfo) -> Self {
+		let (requested_next_hop_scid, incoming_cltv_expiry) = match htlc.forward_info.routing {
+			PendingHTLCRouting::Forward { short_channel_id, incoming_cltv_expiry, .. } =>
+				(short_channel_id, incoming_cltv_expiry),
+			_ => unreachable!(), // Only `PendingHTLCRouting::Forward`s are intercepted
+		};
+		InterceptedHtlcDetails {
+			intercept_id,
+			requested_next_hop_scid,
+			payment_hash: htlc.forward_info.payment_hash,
+			inbound_amount_msat: htlc.forward_info.incoming_amt_msat.unwrap(),
+			expected_outbound_amount_msat: htlc.forward_info.outgoing_amt_msat,
+			incoming_cltv_expiry,
+			expected_outgoing_cltv_expiry: htlc.forward_info.outgoing_cltv_value,
+			prev_channel_id: htlc.prev_channel_id,
+		}
+	}
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 /// Uniquely describes an HTLC by its source. Just the guaranteed-unique subset of [`HTLCSource`].
 pub(crate) enum SentHTLCId {
@@ -514,7 +572,7 @@ impl HTLCSource {
 	#[cfg(test)]
 	pub fn dummy() -> Self {
 		HTLCSource::OutboundRoute {
-			path: Path { hops: Vec::new(), blinded_tail: None },
+			path: Path { hops: Vec::new(), blinded_tail: None, trampoline_tail: None },
 			session_priv: SecretKey::from_slice(&[1; 32]).unwrap(),
 			first_hop_htlc_msat: 0,
 			payment_id: PaymentId([2; 32]),
@@ -570,6 +628,50 @@ impl Into<u16> for FailureCode {
 	}
 }
 
+/// This enum is used to specify which error data to send to the sender when failing back an
+/// intercepted HTLC using [`ChannelManager::fail_intercepted_htlc_with_reason`].
+///
+/// Unlike [`FailureCode`], these describe why we declined to forward the HTLC to the next hop,
+/// allowing the sender's retry logic to behave accordingly.
+///
+/// For more info on failure codes, see <https://github.com/lightning/bolts/blob/master/04-onion-routing.md#failure-messages>.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterceptFailureCode {
+	/// We had a temporary error processing the HTLC which is not specific to the next hop, e.g.
+	/// because we are shutting down. The sender may retry through us later.
+	TemporaryNodeFailure,
+	/// We don't have, and will not open, a channel to the requested next hop. This is the failure
+	/// returned by [`ChannelManager::fail_intercepted_htlc`].
+	UnknownNextPeer,
+	/// The channel the HTLC would be forwarded over is temporarily unable to carry it, e.g. because
+	/// it lacks the outbound liquidity. The channel's current `channel_update` is included.
+	TemporaryChannelFailure {
+		/// The channel over which we would have forwarded the HTLC.
+		next_hop_channel_id: ChannelId,
+		/// The counterparty of the channel over which we would have forwarded the HTLC.
+		next_node_id: PublicKey,
+	},
+	/// The HTLC does not pay enough fees to be forwarded over the given channel. The channel's
+	/// current `channel_update` is included, informing the sender of our actual fees.
+	FeeInsufficient {
+		/// The channel over which we would have forwarded the HTLC.
+		next_hop_channel_id: ChannelId,
+		/// The counterparty of the channel over which we would have forwarded the HTLC.
+		next_node_id: PublicKey,
+	},
+}
+
+impl Into<u16> for InterceptFailureCode {
+	fn into(self) -> u16 {
+		match self {
+			InterceptFailureCode::TemporaryNodeFailure => 0x2000 | 2,
+			InterceptFailureCode::UnknownNextPeer => 0x4000 | 10,
+			InterceptFailureCode::TemporaryChannelFailure { .. } => 0x1000 | 7,
+			InterceptFailureCode::FeeInsufficient { .. } => 0x1000 | 12,
+		}
+	}
+}
+
 /// Error type returned across the peer_state mutex boundary. When an Err is generated for a
 /// Channel, we generally end up with a ChannelError::Close for which we have to close the channel
 /// immediately (ie with no further calls on it made). Thus, this step happens inside a
@@ -989,6 +1091,111 @@ struct PendingInboundPayment {
 	min_value_msat: Option<u64>,
 }
 
+/// Information about an inbound payment registered via
+/// [`ChannelManager::create_inbound_payment_for_hold`], which must be settled or cancelled manually
+/// rather than being claimed as soon as its preimage is known.
+struct HeldInboundPayment {
+	/// The number of blocks before the expiry of any of the payment's HTLCs at which we fail the
+	/// payment back if it hasn't been settled yet.
+	cltv_buffer: u16,
+	/// Time at which the invoice for this payment expires - blocks with a header time above this
+	/// value will result in this payment being removed unless it is currently claimable.
+	expiry_time: u64,
+	/// Whether the payment was cancelled, in which case any further HTLCs paying it are failed.
+	cancelled: bool,
+}
+
+/// Information about an inbound payment registered via [`ChannelManager::create_inbound_payment`]
+/// or one of its variants which has not yet been claimed, tracked so that we can generate an
+/// [`Event::InvoiceExpired`] if it expires unpaid.
+///
+/// [`Event::InvoiceExpired`]: events::Event::InvoiceExpired
+struct UnpaidInboundPayment {
+	/// Time at which the invoice for this payment expires - once the highest seen block header
+	/// time is above this value, the payment can no longer be received.
+	expiry_time: u64,
+	/// Whether the payment was removed via [`ChannelManager::remove_inbound_payment`], in which
+	/// case any further HTLCs paying it are failed until it expires.
+	removed: bool,
+}
+
+/// A circular payment started via [`ChannelManager::rebalance_channels`] which has not yet been
+/// both claimed by us and completed as an outbound payment.
+struct PendingRebalance {
+	payment_hash: PaymentHash,
+	from_channel_id: ChannelId,
+	to_channel_id: ChannelId,
+	amount_msat: u64,
+	/// Whether the outbound side of the rebalance completed, i.e., we generated an
+	/// [`Event::RebalanceComplete`] for it.
+	///
+	/// [`Event::RebalanceComplete`]: events::Event::RebalanceComplete
+	completed: bool,
+	/// Whether the inbound side of the rebalance was claimed.
+	claimed: bool,
+}
+
+/// An outbound channel open queued via [`ChannelManager::create_channel_when_connected`] until its
+/// counterparty connects.
+#[derive(Clone)]
+struct QueuedChannelOpen {
+	counterparty_node_id: PublicKey,
+	temporary_channel_id: ChannelId,
+	channel_value_satoshis: u64,
+	push_msat: u64,
+	user_channel_id: u128,
+	/// The number of timer ticks remaining until the open is dropped.
+	ticks_remaining: u16,
+}
+
+/// The parameters of one of the channels to open via [`ChannelManager::batch_open_channels`], as
+/// would otherwise be passed to [`ChannelManager::create_channel`].
+#[derive(Clone, Debug)]
+pub struct OpenChannelRequest {
+	/// The node to open the channel with, which we must be connected to.
+	pub counterparty_node_id: PublicKey,
+	/// The value of the channel, in satoshis.
+	pub channel_value_satoshis: u64,
+	/// The amount, in millisatoshis, to push to our counterparty on channel open.
+	pub push_msat: u64,
+	/// An identifier for the channel which will be provided back in
+	/// [`BatchFundingOutput::user_channel_id`] and in later events for the channel.
+	///
+	/// [`BatchFundingOutput::user_channel_id`]: events::BatchFundingOutput::user_channel_id
+	pub user_channel_id: u128,
+	/// The configuration to use for the channel, if it should differ from the default.
+	pub override_config: Option<UserConfig>,
+}
+
+/// Identifies a batch of channels being opened via [`ChannelManager::batch_open_channels`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchFundingHandle {
+	/// A random identifier for the batch, provided back in
+	/// [`Event::BatchFundingGenerationReady::batch_id`].
+	///
+	/// [`Event::BatchFundingGenerationReady::batch_id`]: events::Event::BatchFundingGenerationReady::batch_id
+	pub batch_id: u128,
+	/// The temporary channel id and counterparty of each channel in the batch, in the order they
+	/// were requested.
+	pub temporary_channels: Vec<(ChannelId, PublicKey)>,
+}
+
+/// A batch of channels opened via [`ChannelManager::batch_open_channels`] which hasn't been funded
+/// yet.
+struct PendingBatchOpen {
+	/// The temporary channel id and counterparty of each channel in the batch, along with its
+	/// funding output once our counterparty has accepted the channel.
+	channels: Vec<(ChannelId, PublicKey, Option<events::BatchFundingOutput>)>,
+}
+
+impl PendingBatchOpen {
+	fn contains(&self, channel_id: &ChannelId, counterparty_node_id: &PublicKey) -> bool {
+		self.channels.iter().any(|(batch_channel_id, batch_node_id, _)| {
+			batch_channel_id == channel_id && batch_node_id == counterparty_node_id
+		})
+	}
+}
+
 /// [`SimpleArcChannelManager`] is useful when you need a [`ChannelManager`] with a static lifetime, e.g.
 /// when you're using `lightning-net-tokio` (since `tokio::spawn` requires parameters with static
 /// lifetimes). Other times you can afford a reference, which is more efficient, in which case
@@ -1498,7 +1705,9 @@ where
 /// ```
 ///
 /// For paying an invoice, [`lightning-invoice`] provides a `payment` module with convenience
-/// functions for use with [`send_payment`].
+/// functions for use with [`send_payment`], as well as `pay_invoice` and `probe_invoice` functions
+/// doing so in one call. Unlike [`pay_for_offer`], these can't be `ChannelManager` methods, as
+/// [`lightning-invoice`] depends on this crate rather than the other way around.
 ///
 /// ```
 /// # use lightning::events::{Event, EventsProvider};
@@ -1512,7 +1721,7 @@ where
 /// # ) {
 /// # let channel_manager = channel_manager.get_cm();
 /// // let (payment_hash, recipient_onion, route_params) =
-/// //     payment::payment_parameters_from_invoice(&invoice);
+/// //     payment::payment_parameters_from_invoice(&invoice, network, duration_since_epoch);
 /// let payment_id = PaymentId([42; 32]);
 /// match channel_manager.send_payment(
 ///     payment_hash, recipient_onion, payment_id, route_params, retry
@@ -1860,7 +2069,11 @@ where
 //      |
 //      |__`pending_inbound_payments`
 //          |
-//          |__`claimable_payments`
+//          |__`held_inbound_payments`
+//          |   |
+//          |   |__`unpaid_inbound_payments`
+//          |       |
+//          |       |__`claimable_payments`
 //          |
 //          |__`pending_outbound_payments` // This field's struct contains a map of pending outbounds
 //              |
@@ -1874,6 +2087,12 @@ where
 //                  |
 //                  |__`best_block`
 //                  |
+//                  |__`forwarding_history`
+//                  |
+//                  |__`pending_rebalances`
+//                  |
+//                  |__`queued_channel_opens`
+//                  |
 //                  |__`pending_events`
 //                      |
 //                      |__`pending_background_events`
@@ -1912,6 +2131,40 @@ where
 	/// See `ChannelManager` struct-level documentation for lock order requirements.
 	pending_inbound_payments: Mutex<HashMap<PaymentHash, PendingInboundPayment>>,
 
+	/// Inbound payments registered via [`Self::create_inbound_payment_for_hold`] which have not
+	/// yet expired. HTLCs paying these are never exposed with a preimage and, once claimable, are
+	/// failed back `cltv_buffer` blocks before they expire unless settled via
+	/// [`Self::settle_held_payment`].
+	///
+	/// See `ChannelManager` struct-level documentation for lock order requirements.
+	held_inbound_payments: Mutex<HashMap<PaymentHash, HeldInboundPayment>>,
+
+	/// Inbound payments registered via [`Self::create_inbound_payment`] or one of its variants
+	/// which have neither been claimed nor expired. Entries removed via
+	/// [`Self::remove_inbound_payment`] are kept until they expire so that HTLCs paying them are
+	/// failed.
+	///
+	/// See `ChannelManager` struct-level documentation for lock order requirements.
+	unpaid_inbound_payments: Mutex<HashMap<PaymentHash, UnpaidInboundPayment>>,
+
+	/// The most recently completed HTLC forwards, oldest first, bounded by
+	/// [`UserConfig::max_forwarding_history_entries`].
+	///
+	/// See `ChannelManager` struct-level documentation for lock order requirements.
+	forwarding_history: Mutex<VecDeque<ForwardedHTLC>>,
+
+	/// Circular payments started via [`Self::rebalance_channels`], keyed by their [`PaymentId`].
+	/// Events for these payments are handled internally rather than being exposed to the user.
+	///
+	/// See `ChannelManager` struct-level documentation for lock order requirements.
+	pending_rebalances: Mutex<HashMap<PaymentId, PendingRebalance>>,
+
+	/// Outbound channel opens queued via [`Self::create_channel_when_connected`] until their
+	/// counterparty connects, in the order they were queued.
+	///
+	/// See `ChannelManager` struct-level documentation for lock order requirements.
+	queued_channel_opens: Mutex<Vec<QueuedChannelOpen>>,
+
 	/// The session_priv bytes and retry metadata of outbound payments which are pending resolution.
 	/// The authoritative state of these HTLCs resides either within Channels or ChannelMonitors
 	/// (if the channel has been force-closed), however we track them here to prevent duplicative
@@ -2093,6 +2346,12 @@ where
 	/// This information does not need to be persisted as funding nodes can forget
 	/// unfunded channels upon disconnection.
 	funding_batch_states: Mutex<BTreeMap<Txid, Vec<(ChannelId, PublicKey, bool)>>>,
+	/// Batches of channels opened via [`ChannelManager::batch_open_channels`] which haven't been
+	/// funded yet, by their [`BatchFundingHandle::batch_id`].
+	///
+	/// This information does not need to be persisted as, like [`Self::funding_batch_states`], it
+	/// only concerns unfunded channels.
+	pending_batch_opens: Mutex<HashMap<u128, PendingBatchOpen>>,
 
 	background_events_processed_since_startup: AtomicBool,
 
@@ -2285,6 +2544,10 @@ const CHECK_CLTV_EXPIRY_SANITY: u32 = MIN_CLTV_EXPIRY_DELTA as u32 - LATENCY_GRA
 #[allow(dead_code)]
 const CHECK_CLTV_EXPIRY_SANITY_2: u32 = MIN_CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - 2*CLTV_CLAIM_BUFFER;
 
+/// The expiry, in seconds, of the inbound payments we create to receive rebalances started via
+/// [`ChannelManager::rebalance_channels`].
+const REBALANCE_PAYMENT_EXPIRY_SECS: u32 = 60 * 60;
+
 /// The number of ticks of [`ChannelManager::timer_tick_occurred`] until expiry of incomplete MPPs
 pub(crate) const MPP_TIMEOUT_TICKS: u8 = 3;
 
@@ -2309,6 +2572,10 @@ const MAX_UNFUNDED_CHANNEL_PEERS: usize = 50;
 /// many peers we reject new (inbound) connections.
 const MAX_NO_CHANNEL_PEERS: usize = 250;
 
+/// The maximum number of channel opens which may be queued via
+/// [`ChannelManager::create_channel_when_connected`] at once.
+const MAX_QUEUED_CHANNEL_OPENS: usize = 100;
+
 /// The maximum expiration from the current time where an [`Offer`] or [`Refund`] is considered
 /// short-lived, while anything with a greater expiration is considered long-lived.
 ///
@@ -2367,6 +2634,189 @@ pub enum RecentPaymentDetails {
 	},
 }
 
+/// The status of an outbound payment, as reported in [`PaymentDetails`] and used to filter payments
+/// via [`PaymentFilter::status`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentStatus {
+	/// An invoice was requested and thus the payment has not yet been sent.
+	AwaitingInvoice,
+	/// The payment is still being sent and awaiting successful delivery.
+	Pending,
+	/// The payment was fulfilled, but is still tracked until all pending HTLCs have been resolved.
+	/// See [`RecentPaymentDetails::Fulfilled`] for more details.
+	Fulfilled,
+	/// The payment was abandoned, but is still tracked until all pending HTLCs have been resolved.
+	/// See [`RecentPaymentDetails::Abandoned`] for more details.
+	Abandoned,
+}
+
+/// Details of an outbound payment, as returned by [`ChannelManager::list_payments_with_filter`]
+/// and [`ChannelManager::payment_details`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentDetails {
+	/// A user-provided identifier in [`ChannelManager::send_payment`] used to uniquely identify a
+	/// payment and ensure idempotency in LDK.
+	pub payment_id: PaymentId,
+	/// The hash of the payment. `None` until an invoice was received and for payments fulfilled on
+	/// LDK versions prior to 0.0.104.
+	pub payment_hash: Option<PaymentHash>,
+	/// The current status of the payment.
+	pub status: PaymentStatus,
+	/// Total amount (in msat, excluding fees) across all paths of the payment. `None` while
+	/// awaiting an invoice and for payments fulfilled or abandoned on LDK 0.0.123 or earlier.
+	pub amount_msat: Option<u64>,
+	/// The fees paid (in msat) for the payment, as also reported in [`Event::PaymentSent`]. Only
+	/// set once the payment was fulfilled, and `None` if the fees are unknown, e.g., because the
+	/// payment was fulfilled on LDK 0.0.123 or earlier.
+	///
+	/// [`Event::PaymentSent`]: events::Event::PaymentSent
+	pub fee_paid_msat: Option<u64>,
+	/// Our best known block height at the time the payment was initiated. `None` while awaiting
+	/// an invoice and for payments fulfilled or abandoned on LDK 0.0.123 or earlier.
+	pub starting_block_height: Option<u32>,
+}
+
+impl PaymentDetails {
+	fn from_pending_outbound_payment(
+		payment_id: PaymentId, payment: &PendingOutboundPayment
+	) -> Option<Self> {
+		let (status, fee_paid_msat) = match payment {
+			PendingOutboundPayment::Legacy { .. } => return None,
+			// InvoiceReceived is an intermediate state and doesn't need to be exposed
+			PendingOutboundPayment::AwaitingInvoice { .. } |
+				PendingOutboundPayment::InvoiceReceived { .. } => (PaymentStatus::AwaitingInvoice, None),
+			PendingOutboundPayment::Retryable { .. } => (PaymentStatus::Pending, None),
+			PendingOutboundPayment::Fulfilled { fee_paid_msat, .. } => (PaymentStatus::Fulfilled, *fee_paid_msat),
+			PendingOutboundPayment::Abandoned { .. } => (PaymentStatus::Abandoned, None),
+		};
+		Some(PaymentDetails {
+			payment_id,
+			payment_hash: payment.payment_hash(),
+			status,
+			amount_msat: payment.total_msat(),
+			fee_paid_msat,
+			starting_block_height: payment.starting_block_height(),
+		})
+	}
+}
+
+/// A filter selecting which outbound payments to return from
+/// [`ChannelManager::list_payments_with_filter`].
+///
+/// Matching payments are ordered by their [`PaymentId`], allowing to page through them via
+/// [`Self::offset`] and [`Self::limit`]. The [`Default`] filter matches all payments.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaymentFilter {
+	/// If set, only payments with the given status are returned.
+	pub status: Option<PaymentStatus>,
+	/// If set, only payments initiated at or after the given block height are returned, excluding
+	/// any payments for which [`PaymentDetails::starting_block_height`] is unknown.
+	pub min_starting_block_height: Option<u32>,
+	/// If set, only payments initiated at or before the given block height are returned, excluding
+	/// any payments for which [`PaymentDetails::starting_block_height`] is unknown.
+	pub max_starting_block_height: Option<u32>,
+	/// Only payments whose [`PaymentId`] starts with the given bytes are returned.
+	pub payment_id_prefix: Vec<u8>,
+	/// The number of matching payments to skip.
+	pub offset: usize,
+	/// If set, at most the given number of matching payments are returned.
+	pub limit: Option<usize>,
+}
+
+impl PaymentFilter {
+	fn matches(&self, details: &PaymentDetails) -> bool {
+		if let Some(status) = self.status {
+			if status != details.status {
+				return false;
+			}
+		}
+		if self.min_starting_block_height.is_some() || self.max_starting_block_height.is_some() {
+			let height = match details.starting_block_height {
+				Some(height) => height,
+				None => return false,
+			};
+			if height < self.min_starting_block_height.unwrap_or(0)
+				|| height > self.max_starting_block_height.unwrap_or(u32::MAX)
+			{
+				return false;
+			}
+		}
+		details.payment_id.0.starts_with(&self.payment_id_prefix)
+	}
+}
+
+/// A completed HTLC forward, as returned by [`ChannelManager::forwarding_history`].
+///
+/// Each entry corresponds to an [`Event::PaymentForwarded`] generated by the [`ChannelManager`].
+///
+/// [`Event::PaymentForwarded`]: events::Event::PaymentForwarded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardedHTLC {
+	/// The channel the HTLC was received over.
+	pub prev_channel_id: ChannelId,
+	/// The channel the HTLC was forwarded over.
+	pub next_channel_id: ChannelId,
+	/// The short channel id of the channel the HTLC was received over, as used in the inbound
+	/// HTLC's onion. This may be an SCID alias.
+	pub prev_short_channel_id: u64,
+	/// The short channel id of the channel the HTLC was forwarded over, if the channel was still
+	/// open and confirmed when the HTLC was claimed.
+	pub next_short_channel_id: Option<u64>,
+	/// The amount (in msat) we forwarded over the outbound channel. `None` if the forward was
+	/// claimed on-chain and the amount is thus unknown.
+	///
+	/// See [`Event::PaymentForwarded::outbound_amount_forwarded_msat`] for more details.
+	///
+	/// [`Event::PaymentForwarded::outbound_amount_forwarded_msat`]: events::Event::PaymentForwarded::outbound_amount_forwarded_msat
+	pub outbound_amount_forwarded_msat: Option<u64>,
+	/// The fee (in msat) we earned for the forward, including any skimmed fee. `None` if it is
+	/// unknown.
+	///
+	/// See [`Event::PaymentForwarded::total_fee_earned_msat`] for more details.
+	///
+	/// [`Event::PaymentForwarded::total_fee_earned_msat`]: events::Event::PaymentForwarded::total_fee_earned_msat
+	pub total_fee_earned_msat: Option<u64>,
+	/// The share of [`Self::total_fee_earned_msat`] which was skimmed from the HTLC we forwarded.
+	pub skimmed_fee_msat: Option<u64>,
+	/// Whether the forward was claimed on-chain rather than via an `update_fulfill_htlc`.
+	pub claim_from_onchain_tx: bool,
+	/// The time at which the forward completed, in seconds since the UNIX epoch.
+	///
+	/// Without the `std` feature, this is the highest block header time we've seen at the time.
+	pub timestamp: u64,
+}
+
+impl_writeable_tlv_based!(ForwardedHTLC, {
+	(0, prev_channel_id, required),
+	(2, next_channel_id, required),
+	(4, prev_short_channel_id, required),
+	(6, next_short_channel_id, option),
+	(8, outbound_amount_forwarded_msat, option),
+	(10, total_fee_earned_msat, option),
+	(12, skimmed_fee_msat, option),
+	(14, claim_from_onchain_tx, required),
+	(16, timestamp, required),
+});
+
+/// Aggregate statistics of the HTLC forwards over a channel, as returned by
+/// [`ChannelManager::forwarding_stats_per_channel`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ForwardingStats {
+	/// The number of forwarded HTLCs we received over the channel.
+	pub inbound_forwards: u64,
+	/// The number of HTLCs we forwarded over the channel.
+	pub outbound_forwards: u64,
+	/// The total amount (in msat) we forwarded over the channel, excluding forwards for which the
+	/// amount is unknown.
+	pub outbound_amount_forwarded_msat: u64,
+	/// The total fees (in msat) we earned for HTLCs forwarded over the channel, excluding forwards
+	/// for which the fee is unknown.
+	///
+	/// Fees are attributed to the channel the HTLC was forwarded over, i.e., the channel whose
+	/// liquidity they were paid for.
+	pub fee_earned_msat: u64,
+}
+
 /// Route hints used in constructing invoices for [phantom node payents].
 ///
 /// [phantom node payments]: crate::sign::PhantomKeysManager
@@ -2780,8 +3230,10 @@ macro_rules! process_events_body {
 			let mut post_event_actions = Vec::new();
 
 			for (event, action_opt) in pending_events {
-				$event_to_handle = event;
-				$handle_event;
+				if let Some(event) = $self.handle_rebalance_event(event) {
+					$event_to_handle = event;
+					$handle_event;
+				}
 				if let Some(action) = action_opt {
 					post_event_actions.push(action);
 				}
@@ -2864,6 +3316,11 @@ where
 
 			outbound_scid_aliases: Mutex::new(new_hash_set()),
 			pending_inbound_payments: Mutex::new(new_hash_map()),
+			held_inbound_payments: Mutex::new(new_hash_map()),
+			unpaid_inbound_payments: Mutex::new(new_hash_map()),
+			forwarding_history: Mutex::new(VecDeque::new()),
+			pending_rebalances: Mutex::new(new_hash_map()),
+			queued_channel_opens: Mutex::new(Vec::new()),
 			pending_outbound_payments: OutboundPayments::new(),
 			forward_htlcs: Mutex::new(new_hash_map()),
 			decode_update_add_htlcs: Mutex::new(new_hash_map()),
@@ -2892,6 +3349,7 @@ where
 			event_persist_notifier: Notifier::new(),
 			needs_persist_flag: AtomicBool::new(false),
 			funding_batch_states: Mutex::new(BTreeMap::new()),
+			pending_batch_opens: Mutex::new(new_hash_map()),
 
 			pending_offers_messages: Mutex::new(Vec::new()),
 			pending_broadcast_messages: Mutex::new(Vec::new()),
@@ -3020,6 +3478,224 @@ where
 		Ok(temporary_channel_id)
 	}
 
+	/// Creates a new outbound channel to the given remote node as with [`Self::create_channel`],
+	/// but queues the open until the peer connects if we aren't currently connected to it.
+	///
+	/// If we are connected to the peer, this is equivalent to [`Self::create_channel`] using our
+	/// default configuration. Otherwise, the open is persisted with the `ChannelManager` and the
+	/// `open_channel` message is sent as soon as the peer connects, after which channel
+	/// establishment proceeds as usual, e.g., with an [`Event::FundingGenerationReady`]. If the
+	/// channel fails to be created at that point, an [`Event::ChannelClosed`] is generated instead.
+	///
+	/// If the peer does not connect within [`UserConfig::queued_channel_open_timeout_ticks`] calls
+	/// to [`Self::timer_tick_occurred`], the open is dropped and an [`Event::ChannelOpenTimedOut`]
+	/// is generated. At most 100 opens may be queued at once.
+	///
+	/// Returns the temporary `channel_id` the channel will be created with, which is the given
+	/// `temporary_channel_id` if one is specified.
+	///
+	/// [`Event::FundingGenerationReady`]: events::Event::FundingGenerationReady
+	/// [`Event::ChannelClosed`]: events::Event::ChannelClosed
+	/// [`Event::ChannelOpenTimedOut`]: events::Event::ChannelOpenTimedOut
+	pub fn create_channel_when_connected(
+		&self, their_network_key: PublicKey, channel_value_satoshis: u64, push_msat: u64,
+		user_channel_id: u128, temporary_channel_id: Option<ChannelId>,
+	) -> Result<ChannelId, APIError> {
+		if self.is_peer_connected(&their_network_key) {
+			return self.create_channel(their_network_key, channel_value_satoshis, push_msat,
+				user_channel_id, temporary_channel_id, None);
+		}
+
+		if channel_value_satoshis < 1000 {
+			return Err(APIError::APIMisuseError { err: format!("Channel value must be at least 1000 satoshis. It was {}", channel_value_satoshis) });
+		}
+		if push_msat > channel_value_satoshis.saturating_mul(1000) {
+			return Err(APIError::APIMisuseError { err: format!("Push value ({}) was larger than channel_value ({})", push_msat, channel_value_satoshis * 1000) });
+		}
+
+		let temporary_channel_id = {
+			let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+			let mut queued_channel_opens = self.queued_channel_opens.lock().unwrap();
+			if queued_channel_opens.len() >= MAX_QUEUED_CHANNEL_OPENS {
+				return Err(APIError::APIMisuseError {
+					err: format!("Cannot queue more than {} channel opens", MAX_QUEUED_CHANNEL_OPENS)
+				});
+			}
+			let temporary_channel_id = temporary_channel_id
+				.unwrap_or_else(|| ChannelId::temporary_from_entropy_source(&self.entropy_source));
+			if queued_channel_opens.iter().any(|queued_open| queued_open.temporary_channel_id == temporary_channel_id) {
+				return Err(APIError::APIMisuseError {
+					err: format!("Channel with temporary channel ID {} is already queued!", temporary_channel_id)
+				});
+			}
+			queued_channel_opens.push(QueuedChannelOpen {
+				counterparty_node_id: their_network_key,
+				temporary_channel_id,
+				channel_value_satoshis,
+				push_msat,
+				user_channel_id,
+				ticks_remaining: self.default_configuration.queued_channel_open_timeout_ticks,
+			});
+			temporary_channel_id
+		};
+
+		// The peer may have connected while we were queueing the open, in which case it may have
+		// missed it.
+		if self.is_peer_connected(&their_network_key) {
+			self.open_queued_channels(&their_network_key);
+		}
+		Ok(temporary_channel_id)
+	}
+
+	fn is_peer_connected(&self, counterparty_node_id: &PublicKey) -> bool {
+		self.per_peer_state.read().unwrap().get(counterparty_node_id)
+			.map_or(false, |peer_state_mutex| peer_state_mutex.lock().unwrap().is_connected)
+	}
+
+	/// Creates any channels queued via [`Self::create_channel_when_connected`] with the given peer,
+	/// which must be connected.
+	fn open_queued_channels(&self, counterparty_node_id: &PublicKey) {
+		let queued_opens: Vec<QueuedChannelOpen> = {
+			let mut queued_channel_opens = self.queued_channel_opens.lock().unwrap();
+			if !queued_channel_opens.iter().any(|queued_open| queued_open.counterparty_node_id == *counterparty_node_id) {
+				return;
+			}
+			let (queued_opens, remaining_opens) = queued_channel_opens.drain(..)
+				.partition(|queued_open| queued_open.counterparty_node_id == *counterparty_node_id);
+			*queued_channel_opens = remaining_opens;
+			queued_opens
+		};
+
+		for queued_open in queued_opens {
+			let logger = WithContext::from(&self.logger, Some(*counterparty_node_id), Some(queued_open.temporary_channel_id), None);
+			match self.create_channel(*counterparty_node_id, queued_open.channel_value_satoshis,
+				queued_open.push_msat, queued_open.user_channel_id, Some(queued_open.temporary_channel_id), None)
+			{
+				Ok(_) => {
+					log_debug!(logger, "Opening queued channel now that the peer connected");
+				},
+				Err(e) => {
+					log_error!(logger, "Failed to open queued channel: {:?}", e);
+					let err = match e {
+						APIError::APIMisuseError { err } | APIError::ChannelUnavailable { err } => err,
+						e => format!("{:?}", e),
+					};
+					self.pending_events.lock().unwrap().push_back((events::Event::ChannelClosed {
+						channel_id: queued_open.temporary_channel_id,
+						user_channel_id: queued_open.user_channel_id,
+						reason: ClosureReason::ProcessingError { err },
+						counterparty_node_id: Some(*counterparty_node_id),
+						channel_capacity_sats: Some(queued_open.channel_value_satoshis),
+						channel_funding_txo: None,
+						closing_fee_satoshis: None,
+					}, None));
+				},
+			}
+		}
+	}
+
+	/// Creates several new outbound channels which are to be funded by a single transaction.
+	///
+	/// Each channel is created as with [`Self::create_channel`]. Once every counterparty has
+	/// accepted its channel, a single [`Event::BatchFundingGenerationReady`] is generated with the
+	/// outputs the funding transaction must contain, in place of an
+	/// [`Event::FundingGenerationReady`] per channel. The funding transaction should then be
+	/// provided for all of the channels at once via [`Self::batch_funding_transaction_generated`].
+	///
+	/// If any channel in the batch closes before the funding transaction has been provided, e.g.
+	/// because its counterparty rejected it or disconnected, all other channels in the batch are
+	/// closed as well with [`ClosureReason::FundingBatchClosure`]. As with any unfunded channel,
+	/// this includes the funding transaction not being provided within a minute or so (measured in
+	/// calls to [`Self::timer_tick_occurred`]) of the channels being created. Nothing is ever
+	/// broadcast for an abandoned batch.
+	///
+	/// Raises [`APIError::APIMisuseError`] if `requests` is empty or if we aren't currently
+	/// connected to any of the requested counterparties, in which case no channels are created. Otherwise, returns
+	/// the first error [`Self::create_channel`] returns for any channel, after closing any channels
+	/// which were already created for the batch.
+	///
+	/// [`Event::BatchFundingGenerationReady`]: events::Event::BatchFundingGenerationReady
+	/// [`Event::FundingGenerationReady`]: events::Event::FundingGenerationReady
+	pub fn batch_open_channels(&self, requests: Vec<OpenChannelRequest>) -> Result<BatchFundingHandle, APIError> {
+		if requests.is_empty() {
+			return Err(APIError::APIMisuseError { err: "No channels were requested to be opened".to_owned() });
+		}
+		{
+			// Check every counterparty up front, as `create_channel` only fails for a peer we don't
+			// have any state for, not for one which disconnected but still has channels with us.
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			for request in requests.iter() {
+				let is_connected = per_peer_state.get(&request.counterparty_node_id)
+					.map_or(false, |peer_state_mutex| peer_state_mutex.lock().unwrap().is_connected);
+				if !is_connected {
+					return Err(APIError::APIMisuseError { err: format!("Not connected to node: {}", request.counterparty_node_id) });
+				}
+			}
+		}
+
+		let mut random_bytes = [0u8; 16];
+		random_bytes.copy_from_slice(&self.entropy_source.get_secure_random_bytes()[..16]);
+		let batch_id = u128::from_be_bytes(random_bytes);
+		let temporary_channels = requests.iter()
+			.map(|request| (ChannelId::temporary_from_entropy_source(&self.entropy_source), request.counterparty_node_id))
+			.collect::<Vec<_>>();
+		// Track the batch before creating any of its channels so that we can't miss any channel
+		// being accepted or closed.
+		self.pending_batch_opens.lock().unwrap().insert(batch_id, PendingBatchOpen {
+			channels: temporary_channels.iter()
+				.map(|(channel_id, counterparty_node_id)| (*channel_id, *counterparty_node_id, None))
+				.collect(),
+		});
+		for (request, (temporary_channel_id, _)) in requests.iter().zip(temporary_channels.iter()) {
+			let res = self.create_channel(request.counterparty_node_id, request.channel_value_satoshis,
+				request.push_msat, request.user_channel_id, Some(*temporary_channel_id), request.override_config);
+			if let Err(e) = res {
+				let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+				for shutdown_result in self.abandon_batch_open(batch_id) {
+					self.finish_close_channel(shutdown_result);
+				}
+				return Err(e);
+			}
+		}
+		Ok(BatchFundingHandle { batch_id, temporary_channels })
+	}
+
+	/// Stops tracking the given batch open and removes any of its channels which are still
+	/// unfunded, returning the [`ShutdownResult`]s to be finished by the caller.
+	fn abandon_batch_open(&self, batch_id: u128) -> Vec<ShutdownResult> {
+		let batch = match self.pending_batch_opens.lock().unwrap().remove(&batch_id) {
+			Some(batch) => batch,
+			None => return Vec::new(),
+		};
+		let mut shutdown_results = Vec::new();
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		for (channel_id, counterparty_node_id, _) in batch.channels {
+			let peer_state_mutex = match per_peer_state.get(&counterparty_node_id) {
+				Some(peer_state_mutex) => peer_state_mutex,
+				None => continue,
+			};
+			let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+			let peer_state = &mut *peer_state_lock;
+			if let hash_map::Entry::Occupied(chan_phase_entry) = peer_state.channel_by_id.entry(channel_id) {
+				if let ChannelPhase::UnfundedOutboundV1(_) = chan_phase_entry.get() {
+					let mut chan_phase = chan_phase_entry.remove();
+					update_maps_on_chan_removal!(self, &chan_phase.context());
+					shutdown_results.push(chan_phase.context_mut().force_shutdown(false, ClosureReason::FundingBatchClosure));
+					peer_state.pending_msg_events.push(events::MessageSendEvent::HandleError {
+						node_id: counterparty_node_id,
+						action: msgs::ErrorAction::SendErrorMessage {
+							msg: msgs::ErrorMessage {
+								channel_id,
+								data: "Another channel in the funding batch failed".to_owned(),
+							}
+						},
+					});
+				}
+			}
+		}
+		shutdown_results
+	}
+
 	fn list_funded_channels_with_filter<Fn: FnMut(&(&ChannelId, &Channel<SP>)) -> bool + Copy>(&self, f: Fn) -> Vec<ChannelDetails> {
 		// Allocate our best estimate of the number of channels we have in the `res`
 		// Vec. Sadly the `short_to_chan_info` map doesn't cover channels without
@@ -3111,6 +3787,22 @@ where
 		vec![]
 	}
 
+	/// Gets a breakdown of how much we can send over the channel with the given id, explaining
+	/// the reserve, fees, and pending HTLCs which are included in
+	/// [`ChannelDetails::outbound_capacity_msat`] and [`ChannelDetails::next_outbound_htlc_limit_msat`].
+	///
+	/// Returns `None` if no channel with the given id exists.
+	pub fn channel_balance_details(&self, channel_id: &ChannelId) -> Option<ChannelBalanceBreakdown> {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		for (_cp_id, peer_state_mutex) in per_peer_state.iter() {
+			let peer_state = peer_state_mutex.lock().unwrap();
+			if let Some(phase) = peer_state.channel_by_id.get(channel_id) {
+				return Some(ChannelBalanceBreakdown::from_channel_context(phase.context(), &self.fee_estimator));
+			}
+		}
+		None
+	}
+
 	/// Returns in an undefined order recent payments that -- if not fulfilled -- have yet to find a
 	/// successful path, or have unresolved HTLCs.
 	///
@@ -3147,7 +3839,81 @@ where
 			.collect()
 	}
 
-	fn close_channel_internal(&self, channel_id: &ChannelId, counterparty_node_id: &PublicKey, target_feerate_sats_per_1000_weight: Option<u32>, override_shutdown_script: Option<ShutdownScript>) -> Result<(), APIError> {
+	/// Returns the recent payments matching the given [`PaymentFilter`], ordered by their
+	/// [`PaymentId`].
+	///
+	/// Like [`Self::list_recent_payments`], this only includes payments which are still tracked,
+	/// i.e., which have not been removed a short time after having been fulfilled or abandoned.
+	/// Use [`PaymentFilter::offset`] and [`PaymentFilter::limit`] to page through a large number
+	/// of payments.
+	pub fn list_payments_with_filter(&self, filter: PaymentFilter) -> Vec<PaymentDetails> {
+		let mut payments = self.pending_outbound_payments.pending_outbound_payments.lock().unwrap().iter()
+			.filter_map(|(payment_id, payment)| PaymentDetails::from_pending_outbound_payment(*payment_id, payment))
+			.filter(|details| filter.matches(details))
+			.collect::<Vec<_>>();
+		payments.sort_unstable_by_key(|details| details.payment_id.0);
+		payments.into_iter()
+			.skip(filter.offset)
+			.take(filter.limit.unwrap_or(usize::MAX))
+			.collect()
+	}
+
+	/// Returns the [`PaymentDetails`] of the recent payment with the given [`PaymentId`], if any.
+	///
+	/// See [`Self::list_payments_with_filter`] for more information.
+	pub fn payment_details(&self, payment_id: PaymentId) -> Option<PaymentDetails> {
+		self.pending_outbound_payments.pending_outbound_payments.lock().unwrap().get(&payment_id)
+			.and_then(|payment| PaymentDetails::from_pending_outbound_payment(payment_id, payment))
+	}
+
+	/// Returns up to `limit` of the most recently completed HTLC forwards, oldest first. If `since`
+	/// is set, only forwards completed at or after the given time (in seconds since the UNIX epoch)
+	/// are returned.
+	///
+	/// Only the last [`UserConfig::max_forwarding_history_entries`] forwards are kept, including
+	/// across restarts. Thus, this is no replacement for handling [`Event::PaymentForwarded`] if a
+	/// complete history is required.
+	///
+	/// [`Event::PaymentForwarded`]: events::Event::PaymentForwarded
+	pub fn forwarding_history(&self, since: Option<u64>, limit: usize) -> Vec<ForwardedHTLC> {
+		let since = since.unwrap_or(0);
+		let forwarding_history = self.forwarding_history.lock().unwrap();
+		let mut forwards = forwarding_history.iter().rev()
+			.filter(|forward| forward.timestamp >= since)
+			.take(limit)
+			.cloned()
+			.collect::<Vec<_>>();
+		forwards.reverse();
+		forwards
+	}
+
+	/// Returns the aggregate [`ForwardingStats`] of each channel over which we received or
+	/// forwarded an HTLC, as per the forwards in [`Self::forwarding_history`].
+	pub fn forwarding_stats_per_channel(&self) -> HashMap<ChannelId, ForwardingStats> {
+		let mut stats_per_channel: HashMap<ChannelId, ForwardingStats> = new_hash_map();
+		for forward in self.forwarding_history.lock().unwrap().iter() {
+			stats_per_channel.entry(forward.prev_channel_id).or_default().inbound_forwards += 1;
+			let outbound_stats = stats_per_channel.entry(forward.next_channel_id).or_default();
+			outbound_stats.outbound_forwards += 1;
+			outbound_stats.outbound_amount_forwarded_msat += forward.outbound_amount_forwarded_msat.unwrap_or(0);
+			outbound_stats.fee_earned_msat += forward.total_fee_earned_msat.unwrap_or(0);
+		}
+		stats_per_channel
+	}
+
+	fn record_forwarded_htlc(&self, forward: ForwardedHTLC) {
+		let max_entries = self.default_configuration.max_forwarding_history_entries;
+		if max_entries == 0 {
+			return;
+		}
+		let mut forwarding_history = self.forwarding_history.lock().unwrap();
+		if forwarding_history.len() >= max_entries {
+			forwarding_history.pop_front();
+		}
+		forwarding_history.push_back(forward);
+	}
+
+	fn close_channel_internal(&self, channel_id: &ChannelId, counterparty_node_id: &PublicKey, target_feerate_sats_per_1000_weight: Option<u32>, max_feerate_sats_per_1000_weight: Option<u32>, override_shutdown_script: Option<ShutdownScript>) -> Result<(), APIError> {
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
 		let mut failed_htlcs: Vec<(HTLCSource, PaymentHash)> = Vec::new();
@@ -3168,7 +3934,8 @@ where
 						let funding_txo_opt = chan.context.get_funding_txo();
 						let their_features = &peer_state.latest_features;
 						let (shutdown_msg, mut monitor_update_opt, htlcs) =
-							chan.get_shutdown(&self.signer_provider, their_features, target_feerate_sats_per_1000_weight, override_shutdown_script)?;
+							chan.get_shutdown(&self.signer_provider, their_features, target_feerate_sats_per_1000_weight,
+								max_feerate_sats_per_1000_weight, override_shutdown_script)?;
 						failed_htlcs = htlcs;
 
 						// We can send the `shutdown` message before updating the `ChannelMonitor`
@@ -3240,7 +4007,7 @@ where
 	/// [`NonAnchorChannelFee`]: crate::chain::chaininterface::ConfirmationTarget::NonAnchorChannelFee
 	/// [`SendShutdown`]: crate::events::MessageSendEvent::SendShutdown
 	pub fn close_channel(&self, channel_id: &ChannelId, counterparty_node_id: &PublicKey) -> Result<(), APIError> {
-		self.close_channel_internal(channel_id, counterparty_node_id, None, None)
+		self.close_channel_internal(channel_id, counterparty_node_id, None, None, None)
 	}
 
 	/// Begins the process of closing a channel. After this call (plus some timeout), no new HTLCs
@@ -3273,7 +4040,48 @@ where
 	/// [`NonAnchorChannelFee`]: crate::chain::chaininterface::ConfirmationTarget::NonAnchorChannelFee
 	/// [`SendShutdown`]: crate::events::MessageSendEvent::SendShutdown
 	pub fn close_channel_with_feerate_and_script(&self, channel_id: &ChannelId, counterparty_node_id: &PublicKey, target_feerate_sats_per_1000_weight: Option<u32>, shutdown_script: Option<ShutdownScript>) -> Result<(), APIError> {
-		self.close_channel_internal(channel_id, counterparty_node_id, target_feerate_sats_per_1000_weight, shutdown_script)
+		self.close_channel_internal(channel_id, counterparty_node_id, target_feerate_sats_per_1000_weight, None, shutdown_script)
+	}
+
+	/// Begins the process of closing a channel, as with [`Self::close_channel_with_feerate_and_script`],
+	/// but bounding the closing transaction feerate from both sides.
+	///
+	/// If we are the channel initiator, we will only negotiate a closing transaction feerate
+	/// between `min_feerate_sats_per_1000_weight` and `max_feerate_sats_per_1000_weight`, in place
+	/// of the limits described in [`Self::close_channel`]. As with those limits, if our
+	/// counterparty proposes a fee outside of the range, the channel is failed and our commitment
+	/// transaction is broadcast.
+	///
+	/// If our counterparty is the channel initiator, they pay the closing transaction fee, so
+	/// `max_feerate_sats_per_1000_weight` is ignored and `min_feerate_sats_per_1000_weight` is
+	/// treated as the target feerate in [`Self::close_channel_with_feerate_and_script`].
+	///
+	/// The `shutdown_script` is handled as in [`Self::close_channel_with_feerate_and_script`], in
+	/// particular failing if one was already committed to via
+	/// [`ChannelHandshakeConfig::commit_upfront_shutdown_pubkey`].
+	///
+	/// The fee of the resulting closing transaction is provided in
+	/// [`Event::ChannelClosed::closing_fee_satoshis`].
+	///
+	/// Raises [`APIError::APIMisuseError`] if `min_feerate_sats_per_1000_weight` is greater than
+	/// `max_feerate_sats_per_1000_weight`, and otherwise fails as
+	/// [`Self::close_channel_with_feerate_and_script`] does.
+	///
+	/// [`ChannelHandshakeConfig::commit_upfront_shutdown_pubkey`]: crate::util::config::ChannelHandshakeConfig::commit_upfront_shutdown_pubkey
+	/// [`Event::ChannelClosed::closing_fee_satoshis`]: events::Event::ChannelClosed::closing_fee_satoshis
+	pub fn close_channel_with_feerate_range(
+		&self, channel_id: &ChannelId, counterparty_node_id: &PublicKey,
+		min_feerate_sats_per_1000_weight: u32, max_feerate_sats_per_1000_weight: u32,
+		shutdown_script: Option<ShutdownScript>,
+	) -> Result<(), APIError> {
+		if min_feerate_sats_per_1000_weight > max_feerate_sats_per_1000_weight {
+			return Err(APIError::APIMisuseError {
+				err: format!("Minimum closing feerate {} sat/kw was above the maximum of {} sat/kw",
+					min_feerate_sats_per_1000_weight, max_feerate_sats_per_1000_weight),
+			});
+		}
+		self.close_channel_internal(channel_id, counterparty_node_id, Some(min_feerate_sats_per_1000_weight),
+			Some(max_feerate_sats_per_1000_weight), shutdown_script)
 	}
 
 	fn finish_close_channel(&self, mut shutdown_res: ShutdownResult) {
@@ -3323,6 +4131,12 @@ where
 				"Closing a batch where all channels have completed initial monitor update",
 			);
 		}
+		let pending_batch_open = self.pending_batch_opens.lock().unwrap().iter()
+			.find(|(_, batch)| batch.contains(&shutdown_res.channel_id, &shutdown_res.counterparty_node_id))
+			.map(|(batch_id, _)| *batch_id);
+		if let Some(batch_id) = pending_batch_open {
+			shutdown_results.append(&mut self.abandon_batch_open(batch_id));
+		}
 
 		{
 			let mut pending_events = self.pending_events.lock().unwrap();
@@ -3333,6 +4147,7 @@ where
 				counterparty_node_id: Some(shutdown_res.counterparty_node_id),
 				channel_capacity_sats: Some(shutdown_res.channel_capacity_satoshis),
 				channel_funding_txo: shutdown_res.channel_funding_txo,
+				closing_fee_satoshis: shutdown_res.closing_fee_satoshis,
 			}, None));
 
 			if let Some(transaction) = shutdown_res.unbroadcasted_funding_tx {
@@ -3458,6 +4273,80 @@ where
 		self.force_close_sending_error(channel_id, counterparty_node_id, false, error_message)
 	}
 
+	/// Force closes a channel like [`Self::force_close_broadcasting_latest_txn`], but rather than
+	/// broadcasting the latest local commitment transaction, returns it (along with our anchor
+	/// output, if any, which may be spent to bump its fee) for submission via the user's own
+	/// transaction-submission infrastructure, e.g. package relay endpoints.
+	///
+	/// The channel is otherwise closed as usual, with the [`ChannelMonitor`] tracking claims from
+	/// the commitment transaction. Thus, if the user's submission fails, the commitment
+	/// transaction will still be broadcast on the next call to
+	/// [`ChannelMonitor::rebroadcast_pending_claims`] (or, for anchor channels, yielded in a
+	/// [`BumpTransactionEvent::ChannelClose`]), and our funds will be claimed once it confirms.
+	///
+	/// Fails if `channel_id` is unknown to the manager or not yet funded, or if the
+	/// `counterparty_node_id` isn't the counterparty of the corresponding channel, in which case
+	/// the channel is left untouched.
+	///
+	/// Otherwise, the channel is always closed, even if an error is returned. If the commitment
+	/// transaction is not available once the channel has been closed (e.g. because the signer is
+	/// currently unable to sign it, or the [`chain::Watch`] returned `None` from
+	/// [`chain::Watch::get_latest_holder_commitment_package`]), an
+	/// [`APIError::ChannelUnavailable`] is returned and nothing is broadcast until the next call to
+	/// [`ChannelMonitor::rebroadcast_pending_claims`], which should thus be made promptly.
+	///
+	/// [`BumpTransactionEvent::ChannelClose`]: crate::events::bump_transaction::BumpTransactionEvent::ChannelClose
+	pub fn force_close_without_broadcast(&self, channel_id: &ChannelId, counterparty_node_id: &PublicKey)
+	-> Result<ClosingTxPackage, APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+		let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+		let peer_state = &mut *peer_state_lock;
+		let mut chan = match peer_state.channel_by_id.entry(*channel_id) {
+			hash_map::Entry::Occupied(chan_phase_entry) if matches!(chan_phase_entry.get(), ChannelPhase::Funded(_)) => {
+				match remove_channel_phase!(self, chan_phase_entry) {
+					ChannelPhase::Funded(chan) => chan,
+					_ => unreachable!(),
+				}
+			},
+			hash_map::Entry::Occupied(_) => return Err(APIError::APIMisuseError {
+				err: format!("Channel {} has no commitment transaction to broadcast as it is not yet funded", channel_id)
+			}),
+			hash_map::Entry::Vacant(_) => return Err(APIError::ChannelUnavailable {
+				err: format!("Channel with id {} not found for the passed counterparty node_id {}", channel_id, counterparty_node_id)
+			}),
+		};
+		let logger = WithChannelContext::from(&self.logger, &chan.context, None);
+		log_error!(logger, "Force-closing channel {} for external broadcast of our latest commitment transaction", channel_id);
+		let funding_txo = chan.context.get_funding_txo();
+		let shutdown_res = chan.context.force_shutdown_for_external_broadcast(
+			ClosureReason::HolderForceClosed { broadcasted_latest_txn: Some(false) });
+		peer_state.pending_msg_events.push(events::MessageSendEvent::HandleError {
+			node_id: *counterparty_node_id,
+			action: msgs::ErrorAction::SendErrorMessage {
+				msg: msgs::ErrorMessage { channel_id: *channel_id, data: "Channel force-closed".to_owned() }
+			},
+		});
+		mem::drop(peer_state_lock);
+		mem::drop(per_peer_state);
+
+		self.finish_close_channel(shutdown_res);
+		if let Ok(update) = self.get_channel_update_for_broadcast(&chan) {
+			let mut pending_broadcast_messages = self.pending_broadcast_messages.lock().unwrap();
+			pending_broadcast_messages.push(events::MessageSendEvent::BroadcastChannelUpdate {
+				msg: update
+			});
+		}
+
+		funding_txo
+			.and_then(|funding_txo| self.chain_monitor.get_latest_holder_commitment_package(funding_txo))
+			.ok_or_else(|| APIError::ChannelUnavailable {
+				err: format!("Channel {} was closed, but its latest commitment transaction is not available to broadcast", channel_id)
+			})
+	}
+
 	/// Force close all channels, immediately broadcasting the latest local commitment transaction
 	/// for each to the chain and rejecting new HTLCs on each.
 	///
@@ -4038,6 +4927,182 @@ where
 			)
 	}
 
+	/// Moves `amount_msat` of outbound liquidity from the channel with `from_channel_id` to the
+	/// channel with `to_channel_id` by paying ourselves along a circular route which leaves via the
+	/// former and returns via the latter, paying at most `max_fee_msat` in routing fees.
+	///
+	/// Both channels must be usable and with different counterparties, and we must have received
+	/// the counterparty's forwarding parameters for the channel with `to_channel_id`. The returned
+	/// [`PaymentId`] identifies the rebalance, which is retried according to `retry_strategy`.
+	///
+	/// The rebalance is claimed by us automatically, and no [`Event::PaymentSent`],
+	/// [`Event::PaymentFailed`], [`Event::PaymentClaimable`] or [`Event::PaymentClaimed`] events
+	/// are generated for it. Instead, an [`Event::RebalanceComplete`] is generated once it either
+	/// succeeded or failed.
+	///
+	/// [`Event::PaymentSent`]: events::Event::PaymentSent
+	/// [`Event::PaymentFailed`]: events::Event::PaymentFailed
+	/// [`Event::PaymentClaimable`]: events::Event::PaymentClaimable
+	/// [`Event::PaymentClaimed`]: events::Event::PaymentClaimed
+	/// [`Event::RebalanceComplete`]: events::Event::RebalanceComplete
+	pub fn rebalance_channels(
+		&self, from_channel_id: &ChannelId, to_channel_id: &ChannelId, amount_msat: u64,
+		max_fee_msat: u64, retry_strategy: Retry,
+	) -> Result<PaymentId, APIError> {
+		let usable_channels = self.list_usable_channels();
+		let find_channel = |channel_id: &ChannelId| {
+			usable_channels.iter().find(|channel| channel.channel_id == *channel_id)
+				.ok_or_else(|| APIError::ChannelUnavailable {
+					err: format!("Channel with id {} not found or not usable", channel_id)
+				})
+		};
+		let from_channel = find_channel(from_channel_id)?;
+		let to_channel = find_channel(to_channel_id)?;
+		if from_channel.counterparty.node_id == to_channel.counterparty.node_id {
+			return Err(APIError::APIMisuseError {
+				err: format!("Cannot rebalance between channels {} and {} as they have the same counterparty",
+					from_channel_id, to_channel_id)
+			});
+		}
+		if amount_msat > to_channel.inbound_capacity_msat {
+			return Err(APIError::ChannelUnavailable {
+				err: format!("Channel with id {} has only {}msat of inbound capacity, cannot rebalance {}msat",
+					to_channel_id, to_channel.inbound_capacity_msat, amount_msat)
+			});
+		}
+		let forwarding_info = to_channel.counterparty.forwarding_info.as_ref().ok_or_else(|| APIError::ChannelUnavailable {
+			err: format!("Have not yet received the counterparty's forwarding parameters for channel {}", to_channel_id)
+		})?;
+		let (inbound_scid, outbound_scid) = match (to_channel.get_inbound_payment_scid(), from_channel.get_outbound_payment_scid()) {
+			(Some(inbound_scid), Some(outbound_scid)) => (inbound_scid, outbound_scid),
+			_ => return Err(APIError::ChannelUnavailable {
+				err: "Channels must have a short channel id or alias to be rebalanced".to_owned()
+			}),
+		};
+
+		let route_hint = RouteHint(vec![RouteHintHop {
+			src_node_id: to_channel.counterparty.node_id,
+			short_channel_id: inbound_scid,
+			fees: RoutingFees {
+				base_msat: forwarding_info.fee_base_msat,
+				proportional_millionths: forwarding_info.fee_proportional_millionths,
+			},
+			cltv_expiry_delta: forwarding_info.cltv_expiry_delta,
+			htlc_minimum_msat: to_channel.inbound_htlc_minimum_msat,
+			htlc_maximum_msat: to_channel.inbound_htlc_maximum_msat,
+		}]);
+		let payment_params = PaymentParameters::from_node_id(self.get_our_node_id(), MIN_FINAL_CLTV_EXPIRY_DELTA as u32)
+			.with_route_hints(vec![route_hint])
+			.and_then(|params| params.with_bolt11_features(provided_bolt11_invoice_features(&self.default_configuration)))
+			.map_err(|()| APIError::APIMisuseError { err: "Failed to build rebalance payment parameters".to_owned() })?
+			.with_first_hop_channel_ids(vec![outbound_scid])
+			.with_max_path_count(1);
+		let mut route_params = RouteParameters::from_payment_params_and_value(payment_params, amount_msat);
+		route_params.max_total_routing_fee_msat = Some(max_fee_msat);
+
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let highest_seen_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire) as u64;
+		let (payment_hash, payment_secret) = inbound_payment::create(&self.inbound_payment_key,
+			Some(amount_msat), REBALANCE_PAYMENT_EXPIRY_SECS, &self.entropy_source,
+			highest_seen_timestamp, None)
+			.map_err(|()| APIError::APIMisuseError { err: format!("Invalid rebalance amount {}msat", amount_msat) })?;
+		let payment_id = PaymentId(payment_hash.0);
+		self.pending_rebalances.lock().unwrap().insert(payment_id, PendingRebalance {
+			payment_hash, from_channel_id: *from_channel_id, to_channel_id: *to_channel_id,
+			amount_msat, completed: false, claimed: false,
+		});
+
+		let best_block_height = self.best_block.read().unwrap().height;
+		let res = self.pending_outbound_payments
+			.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret), payment_id,
+				retry_strategy, route_params, &self.router, usable_channels, || self.compute_inflight_htlcs(),
+				&self.entropy_source, &self.node_signer, best_block_height, &self.logger,
+				&self.pending_events, |args| self.send_payment_along_path(args));
+		match res {
+			Ok(()) => Ok(payment_id),
+			Err(e) => {
+				self.pending_rebalances.lock().unwrap().remove(&payment_id);
+				Err(match e {
+					RetryableSendFailure::RouteNotFound => APIError::InvalidRoute {
+						err: "Failed to find a circular route for the rebalance".to_owned()
+					},
+					e => APIError::APIMisuseError { err: format!("Failed to send rebalance: {:?}", e) },
+				})
+			},
+		}
+	}
+
+	/// Handles any payment events for rebalances started via [`Self::rebalance_channels`],
+	/// claiming them and mapping their completion to [`Event::RebalanceComplete`]. Returns the
+	/// event to be passed to the user, if any.
+	///
+	/// [`Event::RebalanceComplete`]: events::Event::RebalanceComplete
+	fn handle_rebalance_event(&self, event: events::Event) -> Option<events::Event> {
+		match event {
+			events::Event::PaymentClaimable { ref payment_hash, ref purpose, .. } => {
+				if !self.pending_rebalances.lock().unwrap().contains_key(&PaymentId(payment_hash.0)) {
+					return Some(event);
+				}
+				match purpose.preimage() {
+					Some(payment_preimage) => self.claim_funds(payment_preimage),
+					None => self.fail_htlc_backwards(payment_hash),
+				}
+				None
+			},
+			events::Event::PaymentClaimed { ref payment_hash, .. } => {
+				let payment_id = PaymentId(payment_hash.0);
+				let mut pending_rebalances = self.pending_rebalances.lock().unwrap();
+				match pending_rebalances.get_mut(&payment_id) {
+					Some(rebalance) if rebalance.payment_hash == *payment_hash => {
+						rebalance.claimed = true;
+						if rebalance.completed {
+							pending_rebalances.remove(&payment_id);
+						}
+						None
+					},
+					_ => Some(event),
+				}
+			},
+			events::Event::PaymentSent { payment_id: Some(payment_id), ref payment_hash, fee_paid_msat, .. } => {
+				let mut pending_rebalances = self.pending_rebalances.lock().unwrap();
+				let rebalance = match pending_rebalances.get_mut(&payment_id) {
+					Some(rebalance) if rebalance.payment_hash == *payment_hash => rebalance,
+					_ => return Some(event),
+				};
+				rebalance.completed = true;
+				let event = events::Event::RebalanceComplete {
+					payment_id,
+					from_channel_id: rebalance.from_channel_id,
+					to_channel_id: rebalance.to_channel_id,
+					amount_msat: rebalance.amount_msat,
+					fee_paid_msat,
+					failure_reason: None,
+				};
+				if rebalance.claimed {
+					pending_rebalances.remove(&payment_id);
+				}
+				Some(event)
+			},
+			events::Event::PaymentFailed { payment_id, ref payment_hash, reason } => {
+				let mut pending_rebalances = self.pending_rebalances.lock().unwrap();
+				match pending_rebalances.get(&payment_id) {
+					Some(rebalance) if rebalance.payment_hash == *payment_hash => {},
+					_ => return Some(event),
+				}
+				let rebalance = pending_rebalances.remove(&payment_id).unwrap();
+				Some(events::Event::RebalanceComplete {
+					payment_id,
+					from_channel_id: rebalance.from_channel_id,
+					to_channel_id: rebalance.to_channel_id,
+					amount_msat: rebalance.amount_msat,
+					fee_paid_msat: None,
+					failure_reason: reason,
+				})
+			},
+			event => Some(event),
+		}
+	}
+
 	/// Signals that no further attempts for the given payment should occur. Useful if you have a
 	/// pending outbound payment with retries remaining, but wish to stop retrying the payment before
 	/// retries are exhausted.
@@ -4070,6 +5135,25 @@ where
 		self.pending_outbound_payments.abandon_payment(payment_id, PaymentFailureReason::UserAbandoned, &self.pending_events);
 	}
 
+	/// Abandons all outbound payments which are still pending but have no HTLCs in flight and for
+	/// which no path was sent within the last `older_than`, returning their [`PaymentId`]s.
+	///
+	/// This may be used to clean up payments which stalled, e.g., ones whose paths failed but for
+	/// which a retry was never attempted, instead of calling [`Self::abandon_payment`] for each of
+	/// them. Like the latter, this generates an
+	/// [`Event::PaymentFailed`] for each payment abandoned, after which its [`PaymentId`] may be
+	/// reused. Payments with HTLCs still in flight are never abandoned by this method.
+	///
+	/// Note that the time a path was last sent isn't persisted. Thus, after a restart, payments are
+	/// considered to have last been attempted when the [`ChannelManager`] was loaded.
+	///
+	/// [`Event::PaymentFailed`]: events::Event::PaymentFailed
+	#[cfg(feature = "std")]
+	pub fn abandon_stale_payments(&self, older_than: Duration) -> Vec<PaymentId> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		self.pending_outbound_payments.abandon_stale_payments(older_than, &self.pending_events)
+	}
+
 	/// Send a spontaneous payment, which is a payment that does not require the recipient to have
 	/// generated an invoice. Optionally, you may specify the preimage. If you do choose to specify
 	/// the preimage, it must be a cryptographically secure random value that no intermediate node
@@ -4155,6 +5239,9 @@ where
 	/// payment. To mitigate this issue, channels with available liquidity less than the required
 	/// amount times the given `liquidity_limit_multiplier` won't be used to send pre-flight
 	/// probes. If `None` is given as `liquidity_limit_multiplier`, it defaults to `3`.
+	///
+	/// For a [`Payee::Trampoline`], we only probe the route to the trampoline node, paying it the
+	/// amount plus its maximum trampoline fee, as the trampoline node picks the rest of the route.
 	pub fn send_preflight_probes(
 		&self, route_params: RouteParameters, liquidity_limit_multiplier: Option<u64>,
 	) -> Result<Vec<(PaymentHash, PaymentId)>, ProbeSendFailure> {
@@ -4165,13 +5252,28 @@ where
 		let first_hops = usable_channels.iter().collect::<Vec<_>>();
 		let inflight_htlcs = self.compute_inflight_htlcs();
 
-		let route = self
-			.router
-			.find_route(&payer, &route_params, Some(&first_hops), inflight_htlcs)
-			.map_err(|e| {
-				log_error!(self.logger, "Failed to find path for payment probe: {:?}", e);
-				ProbeSendFailure::RouteNotFound
-			})?;
+		let route = match route_params.payment_params.payee {
+			Payee::Trampoline { .. } => find_trampoline_route(
+				&self.router,
+				&payer,
+				&route_params,
+				Some(&first_hops),
+				inflight_htlcs,
+				None,
+			)
+			.map(|mut route| {
+				// Probes end at the trampoline node, as we can't learn anything past it anyway.
+				for path in route.paths.iter_mut() {
+					path.trampoline_tail = None;
+				}
+				route
+			}),
+			_ => self.router.find_route(&payer, &route_params, Some(&first_hops), inflight_htlcs),
+		}
+		.map_err(|e| {
+			log_error!(self.logger, "Failed to find path for payment probe: {:?}", e);
+			ProbeSendFailure::RouteNotFound
+		})?;
 
 		let mut used_liquidity_map = hash_map_with_capacity(first_hops.len());
 
@@ -4415,6 +5517,24 @@ where
 			}
 		}
 
+		{
+			// Channels opened via `batch_open_channels` must all be funded at once, after which we no
+			// longer need to track them. If only some of them are being funded, the error below
+			// closes them, and with them the rest of the batch.
+			let mut pending_batch_opens = self.pending_batch_opens.lock().unwrap();
+			pending_batch_opens.retain(|_, batch| {
+				let funded_channels = batch.channels.iter()
+					.filter(|(channel_id, counterparty_node_id, _)| temporary_channels.contains(&(channel_id, counterparty_node_id)))
+					.count();
+				if funded_channels != 0 && funded_channels != batch.channels.len() {
+					result = result.clone().and(Err(APIError::APIMisuseError {
+						err: "All channels opened in a batch must be funded by the same transaction".to_owned()
+					}));
+				}
+				funded_channels != batch.channels.len()
+			});
+		}
+
 		let txid = funding_transaction.txid();
 		let is_batch_funding = temporary_channels.len() > 1;
 		let mut funding_batch_states = if is_batch_funding {
@@ -4560,15 +5680,7 @@ where
 					continue;
 				}
 				if let ChannelPhase::Funded(channel) = channel_phase {
-					if let Ok(msg) = self.get_channel_update_for_broadcast(channel) {
-						let mut pending_broadcast_messages = self.pending_broadcast_messages.lock().unwrap();
-						pending_broadcast_messages.push(events::MessageSendEvent::BroadcastChannelUpdate { msg });
-					} else if let Ok(msg) = self.get_channel_update_for_unicast(channel) {
-						peer_state.pending_msg_events.push(events::MessageSendEvent::SendChannelUpdate {
-							node_id: channel.context.get_counterparty_node_id(),
-							msg,
-						});
-					}
+					self.queue_channel_update_for_config_change(channel, &mut peer_state.pending_msg_events);
 				}
 				continue;
 			} else {
@@ -4612,6 +5724,150 @@ where
 		return self.update_partial_channel_config(counterparty_node_id, channel_ids, &(*config).into());
 	}
 
+	/// Applies partial updates to the [`ChannelConfig`] of many channels, possibly across several
+	/// counterparties, in a single pass.
+	///
+	/// Unlike [`update_partial_channel_config`], each entry in `updates` succeeds or fails on its
+	/// own, and the returned `Vec` contains one result per entry, in the same order. An entry which
+	/// fails does not prevent the remaining entries from being applied.
+	///
+	/// If a channel appears more than once, its updates are applied in order and the channel's
+	/// relay policy changes only once. Thus, each eligible channel (see
+	/// [`update_partial_channel_config`]) has at most one [`BroadcastChannelUpdate`] event message
+	/// generated, regardless of how many of its fields or entries changed.
+	///
+	/// Returns [`ChannelUnavailable`] for an entry whose channel is not found or whose
+	/// `counterparty_node_id` is incorrect, and [`APIMisuseError`] for an entry whose
+	/// [`cltv_expiry_delta`] is below [`MIN_CLTV_EXPIRY_DELTA`].
+	///
+	/// [`update_partial_channel_config`]: Self::update_partial_channel_config
+	/// [`cltv_expiry_delta`]: ChannelConfig::cltv_expiry_delta
+	/// [`BroadcastChannelUpdate`]: events::MessageSendEvent::BroadcastChannelUpdate
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn update_channel_config_batch(
+		&self, updates: &[(PublicKey, ChannelId, ChannelConfigUpdate)],
+	) -> Vec<Result<(), APIError>> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let per_peer_state = self.per_peer_state.read().unwrap();
+
+		// First merge all updates for each channel into a single new config, so that each channel
+		// only has its relay policy (and ChannelUpdate timestamp) bumped once.
+		let mut results = Vec::with_capacity(updates.len());
+		let mut new_configs: Vec<(PublicKey, ChannelId, ChannelConfig)> = Vec::new();
+		let mut new_config_idxs: HashMap<(PublicKey, ChannelId), usize> = new_hash_map();
+		for (counterparty_node_id, channel_id, config_update) in updates.iter() {
+			if config_update.cltv_expiry_delta.map(|delta| delta < MIN_CLTV_EXPIRY_DELTA).unwrap_or(false) {
+				results.push(Err(APIError::APIMisuseError {
+					err: format!("The chosen CLTV expiry delta is below the minimum of {}", MIN_CLTV_EXPIRY_DELTA),
+				}));
+				continue;
+			}
+			let peer_state_mutex = match per_peer_state.get(counterparty_node_id) {
+				Some(peer_state_mutex) => peer_state_mutex,
+				None => {
+					results.push(Err(APIError::ChannelUnavailable {
+						err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id),
+					}));
+					continue;
+				},
+			};
+			let peer_state = peer_state_mutex.lock().unwrap();
+			let current_config = match peer_state.channel_by_id.get(channel_id) {
+				Some(channel_phase) => channel_phase.context().config(),
+				None => {
+					results.push(Err(APIError::ChannelUnavailable {
+						err: format!("Channel with id {} not found for the passed counterparty node_id {}", channel_id, counterparty_node_id),
+					}));
+					continue;
+				},
+			};
+			match new_config_idxs.entry((*counterparty_node_id, *channel_id)) {
+				hash_map::Entry::Occupied(entry) => new_configs[*entry.get()].2.apply(config_update),
+				hash_map::Entry::Vacant(entry) => {
+					let mut config = current_config;
+					config.apply(config_update);
+					entry.insert(new_configs.len());
+					new_configs.push((*counterparty_node_id, *channel_id, config));
+				},
+			}
+			results.push(Ok(()));
+		}
+
+		for (counterparty_node_id, channel_id, config) in new_configs {
+			let peer_state_mutex = match per_peer_state.get(&counterparty_node_id) {
+				Some(peer_state_mutex) => peer_state_mutex,
+				None => { debug_assert!(false); continue; },
+			};
+			let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+			let peer_state = &mut *peer_state_lock;
+			// The channel may have been closed since we released the peer lock above, in which case
+			// there is nothing left to update.
+			if let Some(channel_phase) = peer_state.channel_by_id.get_mut(&channel_id) {
+				if !channel_phase.context_mut().update_config(&config) {
+					continue;
+				}
+				if let ChannelPhase::Funded(channel) = channel_phase {
+					self.queue_channel_update_for_config_change(channel, &mut peer_state.pending_msg_events);
+				}
+			}
+		}
+		results
+	}
+
+	/// Applies a partial update to the [`ChannelConfig`] of every channel we have, e.g. to raise
+	/// the forwarding fees of all channels at once.
+	///
+	/// This is a convenience wrapper around [`update_channel_config_batch`]. Channels which close
+	/// while the update is being applied are skipped.
+	///
+	/// Returns [`APIMisuseError`] when a [`cltv_expiry_delta`] update is to be applied with a value
+	/// below [`MIN_CLTV_EXPIRY_DELTA`], in which case no channel is updated.
+	///
+	/// [`update_channel_config_batch`]: Self::update_channel_config_batch
+	/// [`cltv_expiry_delta`]: ChannelConfig::cltv_expiry_delta
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn update_all_channels_config(&self, config_update: &ChannelConfigUpdate) -> Result<(), APIError> {
+		if config_update.cltv_expiry_delta.map(|delta| delta < MIN_CLTV_EXPIRY_DELTA).unwrap_or(false) {
+			return Err(APIError::APIMisuseError {
+				err: format!("The chosen CLTV expiry delta is below the minimum of {}", MIN_CLTV_EXPIRY_DELTA),
+			});
+		}
+
+		let mut updates = Vec::new();
+		{
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			for (counterparty_node_id, peer_state_mutex) in per_peer_state.iter() {
+				let peer_state = peer_state_mutex.lock().unwrap();
+				for channel_id in peer_state.channel_by_id.keys() {
+					updates.push((*counterparty_node_id, *channel_id, *config_update));
+				}
+			}
+		}
+		for result in self.update_channel_config_batch(&updates) {
+			debug_assert!(matches!(result, Ok(()) | Err(APIError::ChannelUnavailable { .. })));
+		}
+		Ok(())
+	}
+
+	/// Queues a [`ChannelUpdate`] for `channel` after its relay policy was changed, broadcasting
+	/// it if the channel is public or otherwise only sending it to our counterparty.
+	///
+	/// [`ChannelUpdate`]: msgs::ChannelUpdate
+	fn queue_channel_update_for_config_change(
+		&self, channel: &Channel<SP>, pending_msg_events: &mut Vec<events::MessageSendEvent>,
+	) {
+		if let Ok(msg) = self.get_channel_update_for_broadcast(channel) {
+			let mut pending_broadcast_messages = self.pending_broadcast_messages.lock().unwrap();
+			pending_broadcast_messages.push(events::MessageSendEvent::BroadcastChannelUpdate { msg });
+		} else if let Ok(msg) = self.get_channel_update_for_unicast(channel) {
+			pending_msg_events.push(events::MessageSendEvent::SendChannelUpdate {
+				node_id: channel.context.get_counterparty_node_id(),
+				msg,
+			});
+		}
+	}
+
 	/// Attempts to forward an intercepted HTLC over the provided channel id and with the provided
 	/// amount to forward. Should only be called in response to an [`HTLCIntercepted`] event.
 	///
@@ -4629,15 +5885,48 @@ where
 	/// [`HTLCIntercepted::expected_outbound_amount_msat`] for more on forwarding a different amount
 	/// than expected.
 	///
+	/// Any amount by which `amt_to_forward_msat` is below the expected outbound amount is skimmed
+	/// as an additional fee and reported in [`PaymentForwarded::skimmed_fee_msat`]. Returns
+	/// [`APIMisuseError`] without forwarding the HTLC, leaving it pending, if this exceeds
+	/// [`HTLCIntercepted::max_skimmed_fee_msat`] as currently configured via
+	/// [`UserConfig::max_intercepted_htlc_skimmed_fee_proportional_millionths`].
+	///
 	/// Errors if the event was not handled in time, in which case the HTLC was automatically failed
 	/// backwards.
 	///
 	/// [`UserConfig::accept_intercept_htlcs`]: crate::util::config::UserConfig::accept_intercept_htlcs
+	/// [`UserConfig::max_intercepted_htlc_skimmed_fee_proportional_millionths`]: crate::util::config::UserConfig::max_intercepted_htlc_skimmed_fee_proportional_millionths
+	/// [`PaymentForwarded::skimmed_fee_msat`]: events::Event::PaymentForwarded::skimmed_fee_msat
+	/// [`HTLCIntercepted::max_skimmed_fee_msat`]: events::Event::HTLCIntercepted::max_skimmed_fee_msat
+	/// [`APIMisuseError`]: APIError::APIMisuseError
 	/// [`HTLCIntercepted`]: events::Event::HTLCIntercepted
 	/// [`HTLCIntercepted::expected_outbound_amount_msat`]: events::Event::HTLCIntercepted::expected_outbound_amount_msat
 	// TODO: when we move to deciding the best outbound channel at forward time, only take
 	// `next_node_id` and not `next_hop_channel_id`
 	pub fn forward_intercepted_htlc(&self, intercept_id: InterceptId, next_hop_channel_id: &ChannelId, next_node_id: PublicKey, amt_to_forward_msat: u64) -> Result<(), APIError> {
+		self.forward_intercepted_htlc_with_overrides(intercept_id, next_hop_channel_id, next_node_id, amt_to_forward_msat, None)
+	}
+
+	/// Attempts to forward an intercepted HTLC as in [`ChannelManager::forward_intercepted_htlc`],
+	/// optionally overriding the CLTV expiry delta we take on the forwarded HTLC.
+	///
+	/// By default, the forwarded HTLC's CLTV expiry is the one the sender set in the onion, which
+	/// was computed using the `cltv_expiry_delta` advertised in the route hint for the intercept
+	/// SCID. If the just-in-time channel's policy differs from what was advertised, setting
+	/// `cltv_expiry_delta_override` forwards the HTLC with a CLTV expiry of the inbound HTLC's
+	/// expiry minus the given delta instead. Note that the recipient will reject the HTLC if this
+	/// results in a lower CLTV expiry than the sender intended.
+	///
+	/// Returns [`APIMisuseError`] without forwarding the HTLC, leaving it pending, if the override
+	/// is below [`MIN_CLTV_EXPIRY_DELTA`] (which would reduce our own safety margin), if the
+	/// resulting CLTV expiry would be too soon, or if the inbound HTLC's CLTV expiry is unknown
+	/// because it was intercepted prior to LDK 0.0.124.
+	///
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn forward_intercepted_htlc_with_overrides(
+		&self, intercept_id: InterceptId, next_hop_channel_id: &ChannelId, next_node_id: PublicKey,
+		amt_to_forward_msat: u64, cltv_expiry_delta_override: Option<u16>,
+	) -> Result<(), APIError> {
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
 		let next_hop_scid = {
@@ -4671,24 +5960,45 @@ where
 			}
 		};
 
-		let payment = self.pending_intercepted_htlcs.lock().unwrap().remove(&intercept_id)
-			.ok_or_else(|| APIError::APIMisuseError {
-				err: format!("Payment with intercept id {} not found", log_bytes!(intercept_id.0))
-			})?;
+		let payment = {
+			let mut pending_intercepts = self.pending_intercepted_htlcs.lock().unwrap();
+			let payment = pending_intercepts.get(&intercept_id)
+				.ok_or_else(|| APIError::APIMisuseError {
+					err: format!("Payment with intercept id {} not found", log_bytes!(intercept_id.0))
+				})?;
+			let max_skimmed_fee_msat =
+				self.max_intercepted_htlc_skimmed_fee_msat(payment.forward_info.outgoing_amt_msat);
+			if payment.forward_info.outgoing_amt_msat.saturating_sub(amt_to_forward_msat) > max_skimmed_fee_msat {
+				return Err(APIError::APIMisuseError {
+					err: format!("Forwarding {} msat would skim more than the maximum of {} msat from the expected {} msat",
+						amt_to_forward_msat, max_skimmed_fee_msat, payment.forward_info.outgoing_amt_msat),
+				});
+			}
+			if let Some(cltv_expiry_delta) = cltv_expiry_delta_override {
+				self.check_intercepted_htlc_cltv_override(intercept_id, payment, cltv_expiry_delta)?;
+			}
+			pending_intercepts.remove(&intercept_id).unwrap()
+		};
 
-		let routing = match payment.forward_info.routing {
-			PendingHTLCRouting::Forward { onion_packet, blinded, .. } => {
-				PendingHTLCRouting::Forward {
-					onion_packet, blinded, short_channel_id: next_hop_scid
-				}
+		let (routing, incoming_cltv_expiry) = match payment.forward_info.routing {
+			PendingHTLCRouting::Forward { onion_packet, blinded, incoming_cltv_expiry, .. } => {
+				(PendingHTLCRouting::Forward {
+					onion_packet, blinded, short_channel_id: next_hop_scid, incoming_cltv_expiry,
+				}, incoming_cltv_expiry)
 			},
 			_ => unreachable!() // Only `PendingHTLCRouting::Forward`s are intercepted
 		};
+		let outgoing_cltv_value = match (incoming_cltv_expiry, cltv_expiry_delta_override) {
+			// The override was checked against the incoming CLTV expiry above.
+			(Some(incoming_cltv_expiry), Some(cltv_expiry_delta)) =>
+				incoming_cltv_expiry - cltv_expiry_delta as u32,
+			_ => payment.forward_info.outgoing_cltv_value,
+		};
 		let skimmed_fee_msat =
 			payment.forward_info.outgoing_amt_msat.saturating_sub(amt_to_forward_msat);
 		let pending_htlc_info = PendingHTLCInfo {
 			skimmed_fee_msat: if skimmed_fee_msat == 0 { None } else { Some(skimmed_fee_msat) },
-			outgoing_amt_msat: amt_to_forward_msat, routing, ..payment.forward_info
+			outgoing_amt_msat: amt_to_forward_msat, outgoing_cltv_value, routing, ..payment.forward_info
 		};
 
 		let mut per_source_pending_forward = [(
@@ -4702,41 +6012,175 @@ where
 		Ok(())
 	}
 
+	/// Gets the maximum fee which may be skimmed from an intercepted HTLC with the given expected
+	/// outbound amount, per [`UserConfig::max_intercepted_htlc_skimmed_fee_proportional_millionths`].
+	///
+	/// This is both reported in [`Event::HTLCIntercepted`] and enforced when forwarding the HTLC.
+	fn max_intercepted_htlc_skimmed_fee_msat(&self, expected_outbound_amount_msat: u64) -> u64 {
+		let proportional_millionths = cmp::min(
+			self.default_configuration.max_intercepted_htlc_skimmed_fee_proportional_millionths, 1_000_000);
+		(expected_outbound_amount_msat as u128 * proportional_millionths as u128 / 1_000_000) as u64
+	}
+
+	/// Checks that forwarding the intercepted HTLC with the given CLTV expiry delta leaves us with
+	/// at least our minimum safety margin and the next hop with a usable HTLC.
+	fn check_intercepted_htlc_cltv_override(
+		&self, intercept_id: InterceptId, payment: &PendingAddHTLCInfo, cltv_expiry_delta: u16,
+	) -> Result<(), APIError> {
+		if cltv_expiry_delta < MIN_CLTV_EXPIRY_DELTA {
+			return Err(APIError::APIMisuseError {
+				err: format!("The chosen CLTV expiry delta is below the minimum of {}", MIN_CLTV_EXPIRY_DELTA),
+			});
+		}
+		let incoming_cltv_expiry = match payment.forward_info.routing {
+			PendingHTLCRouting::Forward { incoming_cltv_expiry: Some(incoming_cltv_expiry), .. } =>
+				incoming_cltv_expiry,
+			_ => return Err(APIError::APIMisuseError {
+				err: format!("The CLTV expiry of the HTLC with intercept id {} is unknown", log_bytes!(intercept_id.0)),
+			}),
+		};
+		let cur_height = self.best_block.read().unwrap().height;
+		let outgoing_cltv_value = incoming_cltv_expiry.saturating_sub(cltv_expiry_delta as u32);
+		if outgoing_cltv_value <= cur_height + LATENCY_GRACE_PERIOD_BLOCKS {
+			return Err(APIError::APIMisuseError {
+				err: format!("The chosen CLTV expiry delta of {} would result in an outgoing CLTV expiry of {}, which is too soon", cltv_expiry_delta, outgoing_cltv_value),
+			});
+		}
+		Ok(())
+	}
+
 	/// Fails the intercepted HTLC indicated by intercept_id. Should only be called in response to
 	/// an [`HTLCIntercepted`] event. See [`ChannelManager::forward_intercepted_htlc`].
 	///
+	/// The HTLC is failed back with [`InterceptFailureCode::UnknownNextPeer`]. Use
+	/// [`ChannelManager::fail_intercepted_htlc_with_reason`] to return a different failure.
+	///
 	/// Errors if the event was not handled in time, in which case the HTLC was automatically failed
 	/// backwards.
 	///
 	/// [`HTLCIntercepted`]: events::Event::HTLCIntercepted
 	pub fn fail_intercepted_htlc(&self, intercept_id: InterceptId) -> Result<(), APIError> {
+		self.fail_intercepted_htlc_with_reason(intercept_id, InterceptFailureCode::UnknownNextPeer)
+	}
+
+	/// Fails the intercepted HTLC indicated by intercept_id with the given [`InterceptFailureCode`].
+	/// Should only be called in response to an [`HTLCIntercepted`] event. See
+	/// [`ChannelManager::forward_intercepted_htlc`].
+	///
+	/// For [`InterceptFailureCode::TemporaryChannelFailure`] and
+	/// [`InterceptFailureCode::FeeInsufficient`], the given channel must be a fully established
+	/// channel with the given counterparty, as its `channel_update` is included in the failure.
+	/// Otherwise, [`ChannelUnavailable`] is returned and the HTLC is left pending.
+	///
+	/// Errors if the event was not handled in time, in which case the HTLC was automatically failed
+	/// backwards.
+	///
+	/// [`HTLCIntercepted`]: events::Event::HTLCIntercepted
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	pub fn fail_intercepted_htlc_with_reason(
+		&self, intercept_id: InterceptId, failure_code: InterceptFailureCode,
+	) -> Result<(), APIError> {
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
-		let payment = self.pending_intercepted_htlcs.lock().unwrap().remove(&intercept_id)
-			.ok_or_else(|| APIError::APIMisuseError {
-				err: format!("Payment with intercept id {} not found", log_bytes!(intercept_id.0))
-			})?;
+		let not_found_err = || APIError::APIMisuseError {
+			err: format!("Payment with intercept id {} not found", log_bytes!(intercept_id.0))
+		};
+		let (requested_forward_scid, incoming_amt_msat) = {
+			let pending_intercepts = self.pending_intercepted_htlcs.lock().unwrap();
+			let payment = pending_intercepts.get(&intercept_id).ok_or_else(not_found_err)?;
+			match payment.forward_info.routing {
+				PendingHTLCRouting::Forward { short_channel_id, .. } =>
+					(short_channel_id, payment.forward_info.incoming_amt_msat.unwrap()),
+				_ => unreachable!() // Only `PendingHTLCRouting::Forward`s are intercepted
+			}
+		};
+		let failure_reason = self.get_intercepted_htlc_fail_reason_from_failure_code(
+			failure_code, requested_forward_scid, incoming_amt_msat
+		)?;
 
-		if let PendingHTLCRouting::Forward { short_channel_id, .. } = payment.forward_info.routing {
-			let htlc_source = HTLCSource::PreviousHopData(HTLCPreviousHopData {
-				short_channel_id: payment.prev_short_channel_id,
-				user_channel_id: Some(payment.prev_user_channel_id),
-				outpoint: payment.prev_funding_outpoint,
-				channel_id: payment.prev_channel_id,
-				htlc_id: payment.prev_htlc_id,
-				incoming_packet_shared_secret: payment.forward_info.incoming_shared_secret,
-				phantom_shared_secret: None,
-				blinded_failure: payment.forward_info.routing.blinded_failure(),
-			});
+		let payment = self.pending_intercepted_htlcs.lock().unwrap().remove(&intercept_id)
+			.ok_or_else(not_found_err)?;
+
+		let htlc_source = HTLCSource::PreviousHopData(HTLCPreviousHopData {
+			short_channel_id: payment.prev_short_channel_id,
+			user_channel_id: Some(payment.prev_user_channel_id),
+			outpoint: payment.prev_funding_outpoint,
+			channel_id: payment.prev_channel_id,
+			htlc_id: payment.prev_htlc_id,
+			incoming_packet_shared_secret: payment.forward_info.incoming_shared_secret,
+			phantom_shared_secret: None,
+			blinded_failure: payment.forward_info.routing.blinded_failure(),
+		});
 
-			let failure_reason = HTLCFailReason::from_failure_code(0x4000 | 10);
-			let destination = HTLCDestination::UnknownNextHop { requested_forward_scid: short_channel_id };
-			self.fail_htlc_backwards_internal(&htlc_source, &payment.forward_info.payment_hash, &failure_reason, destination);
-		} else { unreachable!() } // Only `PendingHTLCRouting::Forward`s are intercepted
+		let destination = match failure_code {
+			InterceptFailureCode::TemporaryChannelFailure { next_hop_channel_id, next_node_id } |
+			InterceptFailureCode::FeeInsufficient { next_hop_channel_id, next_node_id } =>
+				HTLCDestination::NextHopChannel { node_id: Some(next_node_id), channel_id: next_hop_channel_id },
+			InterceptFailureCode::TemporaryNodeFailure | InterceptFailureCode::UnknownNextPeer =>
+				HTLCDestination::UnknownNextHop { requested_forward_scid },
+		};
+		self.fail_htlc_backwards_internal(&htlc_source, &payment.forward_info.payment_hash, &failure_reason, destination);
 
 		Ok(())
 	}
 
+	/// Gets error data to form an [`HTLCFailReason`] given an [`InterceptFailureCode`] and the
+	/// intercepted HTLC's requested forward SCID and inbound amount.
+	fn get_intercepted_htlc_fail_reason_from_failure_code(
+		&self, failure_code: InterceptFailureCode, requested_forward_scid: u64, incoming_amt_msat: u64,
+	) -> Result<HTLCFailReason, APIError> {
+		match failure_code {
+			InterceptFailureCode::TemporaryNodeFailure | InterceptFailureCode::UnknownNextPeer =>
+				Ok(HTLCFailReason::from_failure_code(failure_code.into())),
+			InterceptFailureCode::TemporaryChannelFailure { next_hop_channel_id, next_node_id } |
+			InterceptFailureCode::FeeInsufficient { next_hop_channel_id, next_node_id } => {
+				let per_peer_state = self.per_peer_state.read().unwrap();
+				let peer_state_mutex = per_peer_state.get(&next_node_id)
+					.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", next_node_id) })?;
+				let peer_state = peer_state_mutex.lock().unwrap();
+				match peer_state.channel_by_id.get(&next_hop_channel_id) {
+					Some(ChannelPhase::Funded(chan)) => {
+						// The sender only knows the next hop by the SCID it asked us to forward over,
+						// so we report our channel's policy under that SCID.
+						let (err_code, update_data) =
+							self.get_htlc_temp_fail_err_and_data(failure_code.into(), requested_forward_scid, chan);
+						let mut data = Vec::with_capacity(8 + update_data.len());
+						if err_code == 0x1000 | 12 {
+							data.extend_from_slice(&incoming_amt_msat.to_be_bytes());
+						}
+						data.extend_from_slice(&update_data);
+						Ok(HTLCFailReason::reason(err_code, data))
+					},
+					Some(_) => Err(APIError::ChannelUnavailable {
+						err: format!("Channel with id {} for the passed counterparty node_id {} is still opening.",
+							next_hop_channel_id, next_node_id)
+					}),
+					None => Err(APIError::ChannelUnavailable {
+						err: format!("Channel with id {} not found for the passed counterparty node_id {}",
+							next_hop_channel_id, next_node_id)
+					}),
+				}
+			},
+		}
+	}
+
+	/// Gets the details of the pending intercepted HTLC indicated by `intercept_id`, if any.
+	///
+	/// Returns `None` if the HTLC was already forwarded or failed, including if it was failed
+	/// automatically because it was not handled in time.
+	pub fn get_intercepted_htlc(&self, intercept_id: InterceptId) -> Option<InterceptedHtlcDetails> {
+		self.pending_intercepted_htlcs.lock().unwrap().get(&intercept_id)
+			.map(|htlc| InterceptedHtlcDetails::from_pending_htlc(intercept_id, htlc))
+	}
+
+	/// Gets the details of all intercepted HTLCs which are pending a call to
+	/// [`ChannelManager::forward_intercepted_htlc`] or [`ChannelManager::fail_intercepted_htlc`].
+	pub fn list_intercepted_htlcs(&self) -> Vec<InterceptedHtlcDetails> {
+		self.pending_intercepted_htlcs.lock().unwrap().iter()
+			.map(|(intercept_id, htlc)| InterceptedHtlcDetails::from_pending_htlc(*intercept_id, htlc))
+			.collect()
+	}
+
 	fn process_pending_update_add_htlcs(&self) {
 		let mut decode_update_add_htlcs = new_hash_map();
 		mem::swap(&mut decode_update_add_htlcs, &mut self.decode_update_add_htlcs.lock().unwrap());
@@ -5252,7 +6696,8 @@ where
 								}
 
 								macro_rules! check_total_value {
-									($purpose: expr) => {{
+									($purpose: expr, $held_payment_cltv_buffer: expr) => {{
+										let held_payment_cltv_buffer: Option<u16> = $held_payment_cltv_buffer;
 										let mut payment_claimable_generated = false;
 										let is_keysend = $purpose.is_keysend();
 										let mut claimable_payments = self.claimable_payments.lock().unwrap();
@@ -5324,8 +6769,10 @@ where
 												counterparty_skimmed_fee_msat,
 												via_channel_id: Some(prev_channel_id),
 												via_user_channel_id: Some(prev_user_channel_id),
-												claim_deadline: Some(earliest_expiry - HTLC_FAIL_BACK_BUFFER),
+												claim_deadline: Some(earliest_expiry - held_payment_cltv_buffer
+													.map(|cltv_buffer| cltv_buffer as u32).unwrap_or(HTLC_FAIL_BACK_BUFFER)),
 												onion_fields: claimable_payment.onion_fields.clone(),
+												requires_manual_claim: held_payment_cltv_buffer.is_some(),
 											}, None));
 											payment_claimable_generated = true;
 										} else {
@@ -5368,16 +6815,33 @@ where
 														fail_htlc!(claimable_htlc, payment_hash);
 													}
 												}
+												let removed = matches!(self.unpaid_inbound_payments.lock().unwrap().get(&payment_hash),
+													Some(unpaid_payment) if unpaid_payment.removed);
+												if removed {
+													log_trace!(self.logger, "Failing new HTLC with payment_hash {} as its inbound payment was removed", &payment_hash);
+													fail_htlc!(claimable_htlc, payment_hash);
+												}
+												let held_payment_cltv_buffer = match self.held_inbound_payments.lock().unwrap().get(&payment_hash) {
+													Some(held_payment) if held_payment.cancelled => {
+														log_trace!(self.logger, "Failing new HTLC with payment_hash {} as its hold invoice was cancelled", &payment_hash);
+														fail_htlc!(claimable_htlc, payment_hash);
+													},
+													Some(held_payment) => Some(held_payment.cltv_buffer),
+													None => None,
+												};
+												// Payments to hold invoices must not be claimed until they're
+												// settled, thus we don't hand out their preimage.
+												let payment_preimage = if held_payment_cltv_buffer.is_some() { None } else { payment_preimage };
 												let purpose = events::PaymentPurpose::from_parts(
 													payment_preimage,
 													payment_data.payment_secret,
 													payment_context,
 												);
-												check_total_value!(purpose);
+												check_total_value!(purpose, held_payment_cltv_buffer);
 											},
 											OnionPayload::Spontaneous(preimage) => {
 												let purpose = events::PaymentPurpose::SpontaneousPayment(preimage);
-												check_total_value!(purpose);
+												check_total_value!(purpose, None);
 											}
 										}
 									},
@@ -5400,7 +6864,7 @@ where
 												payment_data.payment_secret,
 												payment_context,
 											);
-											let payment_claimable_generated = check_total_value!(purpose);
+											let payment_claimable_generated = check_total_value!(purpose, None);
 											if payment_claimable_generated {
 												inbound_payment.remove_entry();
 											}
@@ -5582,12 +7046,15 @@ where
 	///    or those awaiting an invoice that hasn't been delivered in the necessary amount of time.
 	///    The latter is determined using the system clock in `std` and the highest seen block time
 	///    minus two hours in `no-std`.
+	///  * Removing inbound payments which expired without being paid, generating an
+	///    [`Event::InvoiceExpired`] for each.
 	///
 	/// Note that this may cause reentrancy through [`chain::Watch::update_channel`] calls or feerate
 	/// estimate fetches.
 	///
 	/// [`ChannelUpdate`]: msgs::ChannelUpdate
 	/// [`ChannelConfig`]: crate::util::config::ChannelConfig
+	/// [`Event::InvoiceExpired`]: events::Event::InvoiceExpired
 	pub fn timer_tick_occurred(&self) {
 		PersistenceNotifierGuard::optionally_notify(self, || {
 			let mut should_persist = NotifyOption::SkipPersistNoEvents;
@@ -5816,6 +7283,68 @@ where
 				self.fail_htlc_backwards_internal(&source, &htlc_source.1, &reason, receiver);
 			}
 
+			// Note that we use the same condition as `inbound_payment::verify` to determine whether
+			// a payment has expired, so that any HTLCs paying an expired payment which we have not
+			// yet processed will be failed.
+			let highest_seen_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire) as u64;
+			let mut expired_payment_hashes = Vec::new();
+			{
+				let mut unpaid_inbound_payments = self.unpaid_inbound_payments.lock().unwrap();
+				let claimable_payments = self.claimable_payments.lock().unwrap();
+				let unpaid_payment_count = unpaid_inbound_payments.len();
+				unpaid_inbound_payments.retain(|payment_hash, unpaid_payment| {
+					// Payments which are currently claimable may still be claimed, so we leave them
+					// until they're either claimed or failed back.
+					if unpaid_payment.expiry_time >= highest_seen_timestamp ||
+						claimable_payments.claimable_payments.contains_key(payment_hash) ||
+						claimable_payments.pending_claiming_payments.contains_key(payment_hash)
+					{
+						return true;
+					}
+					if !unpaid_payment.removed {
+						expired_payment_hashes.push(*payment_hash);
+					}
+					false
+				});
+				if unpaid_inbound_payments.len() != unpaid_payment_count {
+					should_persist = NotifyOption::DoPersist;
+				}
+			}
+			if !expired_payment_hashes.is_empty() {
+				let mut pending_events = self.pending_events.lock().unwrap();
+				for payment_hash in expired_payment_hashes {
+					log_debug!(self.logger, "Inbound payment with payment_hash {} expired unpaid", &payment_hash);
+					pending_events.push_back((events::Event::InvoiceExpired { payment_hash }, None));
+				}
+			}
+
+			let mut timed_out_channel_opens = Vec::new();
+			{
+				let mut queued_channel_opens = self.queued_channel_opens.lock().unwrap();
+				if !queued_channel_opens.is_empty() {
+					should_persist = NotifyOption::DoPersist;
+				}
+				queued_channel_opens.retain_mut(|queued_open| {
+					queued_open.ticks_remaining = queued_open.ticks_remaining.saturating_sub(1);
+					if queued_open.ticks_remaining == 0 {
+						timed_out_channel_opens.push((queued_open.user_channel_id,
+							queued_open.temporary_channel_id, queued_open.counterparty_node_id));
+						return false;
+					}
+					true
+				});
+			}
+			if !timed_out_channel_opens.is_empty() {
+				let mut pending_events = self.pending_events.lock().unwrap();
+				for (user_channel_id, temporary_channel_id, counterparty_node_id) in timed_out_channel_opens {
+					log_debug!(self.logger, "Queued open of channel {} timed out waiting for peer {} to connect",
+						&temporary_channel_id, log_pubkey!(counterparty_node_id));
+					pending_events.push_back((events::Event::ChannelOpenTimedOut {
+						user_channel_id, temporary_channel_id, counterparty_node_id,
+					}, None));
+				}
+			}
+
 			for (err, counterparty_node_id) in handle_errors.drain(..) {
 				let _ = handle_error!(self, err, counterparty_node_id);
 			}
@@ -6211,6 +7740,7 @@ where
 			return;
 		}
 		if valid_mpp {
+			self.unpaid_inbound_payments.lock().unwrap().remove(&payment_hash);
 			for htlc in sources.drain(..) {
 				self.claim_funds_from_hop(
 					htlc.prev_hop, payment_preimage,
@@ -6423,7 +7953,18 @@ where
 			HTLCSource::PreviousHopData(hop_data) => {
 				let prev_channel_id = hop_data.channel_id;
 				let prev_user_channel_id = hop_data.user_channel_id;
+				let prev_short_channel_id = hop_data.short_channel_id;
 				let completed_blocker = RAAMonitorUpdateBlockingAction::from_prev_hop_data(&hop_data);
+				// Look up the outbound channel's SCID for the forwarding history up front, as we
+				// can't take another peer's lock in the completion action below.
+				let next_short_channel_id = if self.default_configuration.max_forwarding_history_entries > 0 {
+					next_channel_counterparty_node_id.and_then(|node_id| {
+						let per_peer_state = self.per_peer_state.read().unwrap();
+						let peer_state = per_peer_state.get(&node_id)?.lock().unwrap();
+						peer_state.channel_by_id.get(&next_channel_id)
+							.and_then(|phase| phase.context().get_short_channel_id())
+					})
+				} else { None };
 				#[cfg(debug_assertions)]
 				let claiming_chan_funding_outpoint = hop_data.outpoint;
 				self.claim_funds_from_hop(hop_data, payment_preimage,
@@ -6505,6 +8046,17 @@ where
 							} else { None };
 							debug_assert!(skimmed_fee_msat <= total_fee_earned_msat,
 								"skimmed_fee_msat must always be included in total_fee_earned_msat");
+							self.record_forwarded_htlc(ForwardedHTLC {
+								prev_channel_id,
+								next_channel_id,
+								prev_short_channel_id,
+								next_short_channel_id,
+								outbound_amount_forwarded_msat: forwarded_htlc_value_msat,
+								total_fee_earned_msat,
+								skimmed_fee_msat,
+								claim_from_onchain_tx: from_onchain,
+								timestamp: self.duration_since_epoch().as_secs(),
+							});
 							Some(MonitorUpdateCompletionAction::EmitEventAndFreeOtherChannel {
 								event: events::Event::PaymentForwarded {
 									prev_channel_id: Some(prev_channel_id),
@@ -6726,7 +8278,25 @@ where
 	/// [`Event::OpenChannelRequest`]: events::Event::OpenChannelRequest
 	/// [`Event::ChannelClosed::user_channel_id`]: events::Event::ChannelClosed::user_channel_id
 	pub fn accept_inbound_channel(&self, temporary_channel_id: &ChannelId, counterparty_node_id: &PublicKey, user_channel_id: u128) -> Result<(), APIError> {
-		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, false, user_channel_id)
+		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, false, user_channel_id, None)
+	}
+
+	/// Accepts a request to open a channel after a [`Event::OpenChannelRequest`], overriding some
+	/// of our [`ChannelHandshakeConfig`] for this channel only.
+	///
+	/// This is otherwise identical to [`ChannelManager::accept_inbound_channel`], and may be used
+	/// to, e.g., require a larger channel reserve or more confirmations from unknown peers. If any
+	/// of the `config_overrides` is outside the allowed limits, an [`APIError::APIMisuseError`] is
+	/// returned describing it and the request is left pending, allowing it to be accepted with
+	/// different overrides or rejected.
+	///
+	/// [`Event::OpenChannelRequest`]: events::Event::OpenChannelRequest
+	/// [`ChannelHandshakeConfig`]: crate::util::config::ChannelHandshakeConfig
+	pub fn accept_inbound_channel_with_config(
+		&self, temporary_channel_id: &ChannelId, counterparty_node_id: &PublicKey, user_channel_id: u128,
+		config_overrides: ChannelAcceptOverrides,
+	) -> Result<(), APIError> {
+		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, false, user_channel_id, Some(config_overrides))
 	}
 
 	/// Accepts a request to open a channel after a [`events::Event::OpenChannelRequest`], treating
@@ -6748,14 +8318,27 @@ where
 	/// [`Event::OpenChannelRequest`]: events::Event::OpenChannelRequest
 	/// [`Event::ChannelClosed::user_channel_id`]: events::Event::ChannelClosed::user_channel_id
 	pub fn accept_inbound_channel_from_trusted_peer_0conf(&self, temporary_channel_id: &ChannelId, counterparty_node_id: &PublicKey, user_channel_id: u128) -> Result<(), APIError> {
-		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, true, user_channel_id)
+		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, true, user_channel_id, None)
 	}
 
-	fn do_accept_inbound_channel(&self, temporary_channel_id: &ChannelId, counterparty_node_id: &PublicKey, accept_0conf: bool, user_channel_id: u128) -> Result<(), APIError> {
+	fn do_accept_inbound_channel(
+		&self, temporary_channel_id: &ChannelId, counterparty_node_id: &PublicKey, accept_0conf: bool,
+		user_channel_id: u128, config_overrides: Option<ChannelAcceptOverrides>,
+	) -> Result<(), APIError> {
 
 		let logger = WithContext::from(&self.logger, Some(*counterparty_node_id), Some(*temporary_channel_id), None);
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
+		// Validate any overrides before looking up the request, so that it is left pending if they
+		// are invalid.
+		let mut config = self.default_configuration;
+		if let Some(config_overrides) = config_overrides {
+			config_overrides.apply(&mut config.channel_handshake_config).map_err(|err| {
+				log_error!(logger, "Invalid channel config overrides: {:?}", err);
+				err
+			})?;
+		}
+
 		let peers_without_funded_channels =
 			self.peers_without_funded_channels(|peer| { peer.total_channel_count() > 0 });
 		let per_peer_state = self.per_peer_state.read().unwrap();
@@ -6779,7 +8362,7 @@ where
 				let best_block_height = self.best_block.read().unwrap().height;
 				InboundV1Channel::new(&self.fee_estimator, &self.entropy_source, &self.signer_provider,
 					counterparty_node_id.clone(), &self.channel_type_features(), &peer_state.latest_features,
-					&unaccepted_channel.open_channel_msg, user_channel_id, &self.default_configuration, best_block_height,
+					&unaccepted_channel.open_channel_msg, user_channel_id, &config, best_block_height,
 					&self.logger, accept_0conf).map_err(|err| MsgHandleErrInternal::from_chan_no_close(err, *temporary_channel_id))
 			},
 			_ => {
@@ -7063,6 +8646,37 @@ where
 				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close(format!("Got a message for a channel from the wrong node! No such channel for the passed counterparty_node_id {}", counterparty_node_id), msg.common_fields.temporary_channel_id))
 			}
 		};
+		let temporary_channel_id = msg.common_fields.temporary_channel_id;
+		let mut pending_batch_opens = self.pending_batch_opens.lock().unwrap();
+		let pending_batch_open = pending_batch_opens.iter_mut()
+			.find(|(_, batch)| batch.contains(&temporary_channel_id, counterparty_node_id));
+		if let Some((batch_id, batch)) = pending_batch_open {
+			// Channels opened in a batch are only funded together, so we wait until all of them have
+			// been accepted to generate a single event for the batch.
+			for (channel_id, _, funding_output) in batch.channels.iter_mut() {
+				if *channel_id == temporary_channel_id {
+					*funding_output = Some(events::BatchFundingOutput {
+						temporary_channel_id,
+						counterparty_node_id: *counterparty_node_id,
+						channel_value_satoshis: value,
+						output_script: output_script.clone(),
+						user_channel_id: user_id,
+					});
+				}
+			}
+			if batch.channels.iter().all(|(_, _, funding_output)| funding_output.is_some()) {
+				let funding_outputs = batch.channels.iter()
+					.filter_map(|(_, _, funding_output)| funding_output.clone())
+					.collect();
+				self.pending_events.lock().unwrap().push_back((events::Event::BatchFundingGenerationReady {
+					batch_id: *batch_id,
+					funding_outputs,
+				}, None));
+			}
+			return Ok(());
+		}
+		mem::drop(pending_batch_opens);
+
 		let mut pending_events = self.pending_events.lock().unwrap();
 		pending_events.push_back((events::Event::FundingGenerationReady {
 			temporary_channel_id: msg.common_fields.temporary_channel_id,
@@ -7689,7 +9303,8 @@ where
 											payment_hash: forward_info.payment_hash,
 											inbound_amount_msat: forward_info.incoming_amt_msat.unwrap(),
 											expected_outbound_amount_msat: forward_info.outgoing_amt_msat,
-											intercept_id
+											intercept_id,
+											max_skimmed_fee_msat: self.max_intercepted_htlc_skimmed_fee_msat(forward_info.outgoing_amt_msat),
 										}, None));
 										entry.insert(PendingAddHTLCInfo {
 											prev_short_channel_id, prev_funding_outpoint, prev_channel_id, prev_htlc_id, prev_user_channel_id, forward_info });
@@ -8329,7 +9944,7 @@ where
 			// timer_tick_occurred, guaranteeing we're running normally.
 			if let Some((counterparty_node_id, funding_txo, channel_id, update)) = failure.monitor_update.take() {
 				assert_eq!(update.updates.len(), 1);
-				if let ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast } = update.updates[0] {
+				if let ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast, .. } = update.updates[0] {
 					assert!(should_broadcast);
 				} else { unreachable!(); }
 				self.pending_background_events.lock().unwrap().push(
@@ -8461,7 +10076,7 @@ macro_rules! create_refund_builder { ($self: ident, $builder: ty) => {
 		let expiration = StaleExpiration::AbsoluteTimeout(absolute_expiry);
 		$self.pending_outbound_payments
 			.add_new_awaiting_invoice(
-				payment_id, expiration, retry_strategy, max_total_routing_fee_msat,
+				payment_id, expiration, retry_strategy, max_total_routing_fee_msat, None, None,
 			)
 			.map_err(|_| Bolt12SemanticError::DuplicatePaymentId)?;
 
@@ -8547,6 +10162,28 @@ where
 		&self, offer: &Offer, quantity: Option<u64>, amount_msats: Option<u64>,
 		payer_note: Option<String>, payment_id: PaymentId, retry_strategy: Retry,
 		max_total_routing_fee_msat: Option<u64>
+	) -> Result<(), Bolt12SemanticError> {
+		self.pay_for_offer_with_shard_limits(
+			offer, quantity, amount_msats, payer_note, payment_id, retry_strategy,
+			max_total_routing_fee_msat, None, None
+		)
+	}
+
+	/// Pays for an [`Offer`] like [`ChannelManager::pay_for_offer`], while bounding how the
+	/// payment for the received [`Bolt12Invoice`] may be split.
+	///
+	/// If set, `max_path_count_override` and `min_shard_amount_msat` are used as
+	/// [`PaymentParameters::max_path_count_override`] and
+	/// [`PaymentParameters::min_shard_amount_msat`], respectively, e.g., to send the payment in at
+	/// most three parts of at least 50k sats each. If the payment can't be split within these
+	/// bounds, it will fail once the invoice is received.
+	///
+	/// [`Bolt12Invoice`]: crate::offers::invoice::Bolt12Invoice
+	pub fn pay_for_offer_with_shard_limits(
+		&self, offer: &Offer, quantity: Option<u64>, amount_msats: Option<u64>,
+		payer_note: Option<String>, payment_id: PaymentId, retry_strategy: Retry,
+		max_total_routing_fee_msat: Option<u64>, max_path_count_override: Option<u8>,
+		min_shard_amount_msat: Option<u64>
 	) -> Result<(), Bolt12SemanticError> {
 		let expanded_key = &self.inbound_payment_key;
 		let entropy = &*self.entropy_source;
@@ -8577,7 +10214,8 @@ where
 		let expiration = StaleExpiration::TimerTicks(1);
 		self.pending_outbound_payments
 			.add_new_awaiting_invoice(
-				payment_id, expiration, retry_strategy, max_total_routing_fee_msat
+				payment_id, expiration, retry_strategy, max_total_routing_fee_msat,
+				max_path_count_override, min_shard_amount_msat,
 			)
 			.map_err(|_| Bolt12SemanticError::DuplicatePaymentId)?;
 
@@ -8648,7 +10286,11 @@ where
 
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
-		match self.create_inbound_payment(Some(amount_msats), relative_expiry, None) {
+		let highest_seen_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire) as u64;
+		match inbound_payment::create(
+			expanded_key, Some(amount_msats), relative_expiry, &self.entropy_source,
+			highest_seen_timestamp, None
+		) {
 			Ok((payment_hash, payment_secret)) => {
 				let payment_context = PaymentContext::Bolt12Refund(Bolt12RefundContext {});
 				let payment_paths = self.create_blinded_payment_paths(
@@ -8673,6 +10315,8 @@ where
 				let reply_path = self.create_blinded_path()
 					.map_err(|_| Bolt12SemanticError::MissingPaths)?;
 
+				self.track_unpaid_inbound_payment(payment_hash, relative_expiry, highest_seen_timestamp);
+
 				let mut pending_offers_messages = self.pending_offers_messages.lock().unwrap();
 				if refund.paths().is_empty() {
 					let message = new_pending_onion_message(
@@ -8730,9 +10374,13 @@ where
 	/// [`create_inbound_payment_for_hash`]: Self::create_inbound_payment_for_hash
 	pub fn create_inbound_payment(&self, min_value_msat: Option<u64>, invoice_expiry_delta_secs: u32,
 		min_final_cltv_expiry_delta: Option<u16>) -> Result<(PaymentHash, PaymentSecret), ()> {
-		inbound_payment::create(&self.inbound_payment_key, min_value_msat, invoice_expiry_delta_secs,
-			&self.entropy_source, self.highest_seen_timestamp.load(Ordering::Acquire) as u64,
-			min_final_cltv_expiry_delta)
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let highest_seen_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire) as u64;
+		let (payment_hash, payment_secret) = inbound_payment::create(&self.inbound_payment_key,
+			min_value_msat, invoice_expiry_delta_secs, &self.entropy_source, highest_seen_timestamp,
+			min_final_cltv_expiry_delta)?;
+		self.track_unpaid_inbound_payment(payment_hash, invoice_expiry_delta_secs, highest_seen_timestamp);
+		Ok((payment_hash, payment_secret))
 	}
 
 	/// Gets a [`PaymentSecret`] for a given [`PaymentHash`], for which the payment preimage is
@@ -8783,9 +10431,169 @@ where
 	/// [`PaymentClaimable`]: events::Event::PaymentClaimable
 	pub fn create_inbound_payment_for_hash(&self, payment_hash: PaymentHash, min_value_msat: Option<u64>,
 		invoice_expiry_delta_secs: u32, min_final_cltv_expiry: Option<u16>) -> Result<PaymentSecret, ()> {
-		inbound_payment::create_from_hash(&self.inbound_payment_key, min_value_msat, payment_hash,
-			invoice_expiry_delta_secs, self.highest_seen_timestamp.load(Ordering::Acquire) as u64,
-			min_final_cltv_expiry)
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let highest_seen_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire) as u64;
+		let payment_secret = inbound_payment::create_from_hash(&self.inbound_payment_key,
+			min_value_msat, payment_hash, invoice_expiry_delta_secs, highest_seen_timestamp,
+			min_final_cltv_expiry)?;
+		self.track_unpaid_inbound_payment(payment_hash, invoice_expiry_delta_secs, highest_seen_timestamp);
+		Ok(payment_secret)
+	}
+
+	/// Removes an inbound payment registered via [`Self::create_inbound_payment`] or one of its
+	/// variants, e.g. because the corresponding order was cancelled. Any HTLCs paying it which we
+	/// process afterwards will be failed back, and no [`Event::InvoiceExpired`] will be generated
+	/// for it.
+	///
+	/// Errors if the payment is unknown (including if it was already claimed, removed, or
+	/// expired) or if it has HTLCs pending claim, i.e., a [`PaymentClaimable`] event was generated
+	/// for it. In the latter case, the payment must be failed back via
+	/// [`Self::fail_htlc_backwards`] before it can be removed.
+	///
+	/// [`Event::InvoiceExpired`]: events::Event::InvoiceExpired
+	/// [`PaymentClaimable`]: events::Event::PaymentClaimable
+	pub fn remove_inbound_payment(&self, payment_hash: PaymentHash) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let mut unpaid_inbound_payments = self.unpaid_inbound_payments.lock().unwrap();
+		let claimable_payments = self.claimable_payments.lock().unwrap();
+		if claimable_payments.claimable_payments.contains_key(&payment_hash) ||
+			claimable_payments.pending_claiming_payments.contains_key(&payment_hash)
+		{
+			return Err(APIError::APIMisuseError {
+				err: format!("Inbound payment with payment hash {} has HTLCs pending claim", payment_hash)
+			});
+		}
+		match unpaid_inbound_payments.get_mut(&payment_hash) {
+			Some(unpaid_payment) if !unpaid_payment.removed => {
+				unpaid_payment.removed = true;
+				Ok(())
+			},
+			_ => Err(APIError::APIMisuseError {
+				err: format!("No unpaid inbound payment with payment hash {}", payment_hash)
+			}),
+		}
+	}
+
+	/// Tracks an inbound payment registered via [`Self::create_inbound_payment`] or one of its
+	/// variants until it is claimed or expires.
+	fn track_unpaid_inbound_payment(
+		&self, payment_hash: PaymentHash, invoice_expiry_delta_secs: u32, highest_seen_timestamp: u64
+	) {
+		// Like `inbound_payment::create`, we add two hours to the expiry to compensate for the
+		// inaccuracy of block header timestamps.
+		let expiry_time = highest_seen_timestamp + invoice_expiry_delta_secs as u64 + 7200;
+		self.unpaid_inbound_payments.lock().unwrap().insert(payment_hash, UnpaidInboundPayment {
+			expiry_time, removed: false,
+		});
+	}
+
+	/// Gets a payment secret and payment hash for use in a hold invoice, i.e., an invoice whose
+	/// payment is held until it is explicitly settled or cancelled rather than being claimed as
+	/// soon as it is received.
+	///
+	/// This differs from [`create_inbound_payment`] in that the resulting [`PaymentClaimable`]
+	/// event has [`PaymentClaimable::requires_manual_claim`] set and doesn't include the
+	/// [`PaymentPreimage`] in its [`PaymentClaimable::purpose`]. Once any out-of-band checks have
+	/// passed, the payment should be claimed via [`settle_held_payment`], using the preimage
+	/// available via [`get_payment_preimage`], or failed back via [`cancel_held_payment`].
+	///
+	/// If the payment is neither settled nor cancelled, it is failed back automatically once the
+	/// chain tip is within `cltv_buffer` blocks of the expiry of any of its HTLCs, giving us enough
+	/// time to do so before our counterparty would need to close the channel to enforce the
+	/// HTLC's timeout. Thus, `cltv_buffer` must be at least [`MIN_CLTV_EXPIRY_DELTA`].
+	///
+	/// To leave room for holding the payment, we require the HTLCs paying it to have a CLTV expiry
+	/// at least `cltv_buffer` + [`MIN_FINAL_CLTV_EXPIRY_DELTA`] blocks in the future. The invoice
+	/// should thus have its `min_final_cltv_expiry_delta` set to at least that value.
+	///
+	/// See [`create_inbound_payment_for_hash`] for documentation on the remaining parameters.
+	///
+	/// Errors if `cltv_buffer` is below [`MIN_CLTV_EXPIRY_DELTA`] or if `min_value_msat` is greater
+	/// than total bitcoin supply.
+	///
+	/// [`create_inbound_payment`]: Self::create_inbound_payment
+	/// [`create_inbound_payment_for_hash`]: Self::create_inbound_payment_for_hash
+	/// [`settle_held_payment`]: Self::settle_held_payment
+	/// [`cancel_held_payment`]: Self::cancel_held_payment
+	/// [`get_payment_preimage`]: Self::get_payment_preimage
+	/// [`PaymentClaimable`]: events::Event::PaymentClaimable
+	/// [`PaymentClaimable::purpose`]: events::Event::PaymentClaimable::purpose
+	/// [`PaymentClaimable::requires_manual_claim`]: events::Event::PaymentClaimable::requires_manual_claim
+	pub fn create_inbound_payment_for_hold(&self, min_value_msat: Option<u64>,
+		invoice_expiry_delta_secs: u32, cltv_buffer: u16) -> Result<(PaymentHash, PaymentSecret), ()> {
+		if cltv_buffer < MIN_CLTV_EXPIRY_DELTA {
+			return Err(());
+		}
+		let min_final_cltv_expiry_delta = cltv_buffer.checked_add(MIN_FINAL_CLTV_EXPIRY_DELTA).ok_or(())?;
+
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let highest_seen_timestamp = self.highest_seen_timestamp.load(Ordering::Acquire) as u64;
+		let (payment_hash, payment_secret) = inbound_payment::create(&self.inbound_payment_key,
+			min_value_msat, invoice_expiry_delta_secs, &self.entropy_source, highest_seen_timestamp,
+			Some(min_final_cltv_expiry_delta))?;
+
+		// Like `inbound_payment::create`, we add two hours to the expiry to compensate for the
+		// inaccuracy of block header timestamps.
+		let expiry_time = highest_seen_timestamp + invoice_expiry_delta_secs as u64 + 7200;
+		self.held_inbound_payments.lock().unwrap().insert(payment_hash, HeldInboundPayment {
+			cltv_buffer, expiry_time, cancelled: false,
+		});
+		self.track_unpaid_inbound_payment(payment_hash, invoice_expiry_delta_secs, highest_seen_timestamp);
+		Ok((payment_hash, payment_secret))
+	}
+
+	/// Claims a payment to a hold invoice created via [`create_inbound_payment_for_hold`] after its
+	/// [`PaymentClaimable`] event was received.
+	///
+	/// Like [`claim_funds`], this will generate a [`PaymentClaimed`] event once the payment has been
+	/// claimed.
+	///
+	/// Errors if the preimage doesn't match `payment_hash`, if no hold invoice is pending for it
+	/// (including if it was cancelled), or if the payment is not currently claimable, e.g., as it
+	/// was already failed back automatically.
+	///
+	/// [`create_inbound_payment_for_hold`]: Self::create_inbound_payment_for_hold
+	/// [`claim_funds`]: Self::claim_funds
+	/// [`PaymentClaimable`]: events::Event::PaymentClaimable
+	/// [`PaymentClaimed`]: events::Event::PaymentClaimed
+	pub fn settle_held_payment(
+		&self, payment_hash: PaymentHash, payment_preimage: PaymentPreimage
+	) -> Result<(), APIError> {
+		if PaymentHash(Sha256::hash(&payment_preimage.0).to_byte_array()) != payment_hash {
+			return Err(APIError::APIMisuseError {
+				err: "Payment preimage doesn't match the payment hash".to_owned()
+			});
+		}
+		match self.held_inbound_payments.lock().unwrap().get(&payment_hash) {
+			Some(held_payment) if !held_payment.cancelled => {},
+			_ => return Err(APIError::APIMisuseError {
+				err: format!("No pending hold invoice with payment hash {}", payment_hash)
+			}),
+		}
+		if !self.claimable_payments.lock().unwrap().claimable_payments.contains_key(&payment_hash) {
+			return Err(APIError::APIMisuseError {
+				err: format!("No claimable payment with payment hash {}", payment_hash)
+			});
+		}
+		self.claim_funds(payment_preimage);
+		Ok(())
+	}
+
+	/// Cancels a hold invoice created via [`create_inbound_payment_for_hold`], failing back any
+	/// HTLCs already received for it, as well as any received in the future.
+	///
+	/// Errors if no hold invoice is pending for the given `payment_hash`.
+	///
+	/// [`create_inbound_payment_for_hold`]: Self::create_inbound_payment_for_hold
+	pub fn cancel_held_payment(&self, payment_hash: PaymentHash) -> Result<(), APIError> {
+		match self.held_inbound_payments.lock().unwrap().get_mut(&payment_hash) {
+			Some(held_payment) => held_payment.cancelled = true,
+			None => return Err(APIError::APIMisuseError {
+				err: format!("No pending hold invoice with payment hash {}", payment_hash)
+			}),
+		}
+		self.fail_htlc_backwards(&payment_hash);
+		Ok(())
 	}
 
 	/// Gets an LDK-generated payment preimage from a payment hash and payment secret that were
@@ -9319,6 +11127,13 @@ where
 		payment_secrets.retain(|_, inbound_payment| {
 			inbound_payment.expiry_time > header.time as u64
 		});
+		core::mem::drop(payment_secrets);
+		let mut held_inbound_payments = self.held_inbound_payments.lock().unwrap();
+		let claimable_payments = self.claimable_payments.lock().unwrap();
+		held_inbound_payments.retain(|payment_hash, held_payment| {
+			held_payment.expiry_time > header.time as u64 ||
+				claimable_payments.claimable_payments.contains_key(payment_hash)
+		});
 	}
 
 	fn get_relevant_txids(&self) -> Vec<(Txid, u32, Option<BlockHash>)> {
@@ -9480,13 +11295,18 @@ where
 		}
 
 		if let Some(height) = height_opt {
+			let held_inbound_payments = self.held_inbound_payments.lock().unwrap();
 			self.claimable_payments.lock().unwrap().claimable_payments.retain(|payment_hash, payment| {
+				// Payments to hold invoices are failed back with the larger margin given on creation.
+				let fail_back_buffer = held_inbound_payments.get(payment_hash)
+					.map(|held_payment| held_payment.cltv_buffer as u32)
+					.unwrap_or(HTLC_FAIL_BACK_BUFFER);
 				payment.htlcs.retain(|htlc| {
 					// If height is approaching the number of blocks we think it takes us to get
 					// our commitment transaction confirmed before the HTLC expires, plus the
 					// number of blocks we generally consider it to take to do a commitment update,
 					// just give up on it and fail the HTLC.
-					if height >= htlc.cltv_expiry - HTLC_FAIL_BACK_BUFFER {
+					if height >= htlc.cltv_expiry.saturating_sub(fail_back_buffer) {
 						let mut htlc_msat_height_data = htlc.value.to_be_bytes().to_vec();
 						htlc_msat_height_data.extend_from_slice(&height.to_be_bytes());
 
@@ -9498,6 +11318,7 @@ where
 				});
 				!payment.htlcs.is_empty() // Only retain this entry if htlcs has at least one entry.
 			});
+			core::mem::drop(held_inbound_payments);
 
 			let mut intercepted_htlcs = self.pending_intercepted_htlcs.lock().unwrap();
 			intercepted_htlcs.retain(|_, htlc| {
@@ -10063,6 +11884,9 @@ where
 			return NotifyOption::SkipPersistHandleEvents;
 			//TODO: Also re-broadcast announcement_signatures
 		});
+		if res.is_ok() {
+			self.open_queued_channels(counterparty_node_id);
+		}
 		res
 	}
 
@@ -10277,8 +12101,11 @@ where
 				};
 
 				let relative_expiry = DEFAULT_RELATIVE_EXPIRY.as_secs() as u32;
-				let (payment_hash, payment_secret) = match self.create_inbound_payment(
-					Some(amount_msats), relative_expiry, None
+				// Unlike with `create_inbound_payment`, we don't track the payment for expiry as
+				// invoice requests may be sent to us by anyone.
+				let (payment_hash, payment_secret) = match inbound_payment::create(
+					expanded_key, Some(amount_msats), relative_expiry, &self.entropy_source,
+					self.highest_seen_timestamp.load(Ordering::Acquire) as u64, None
 				) {
 					Ok((payment_hash, payment_secret)) => (payment_hash, payment_secret),
 					Err(()) => {
@@ -10456,7 +12283,6 @@ pub(crate) fn provided_node_features(config: &UserConfig) -> NodeFeatures {
 ///
 /// Note that the invoice feature flags can vary depending on if the invoice is a "phantom invoice"
 /// or not. Thus, this method is not public.
-#[cfg(any(feature = "_test_utils", test))]
 pub(crate) fn provided_bolt11_invoice_features(config: &UserConfig) -> Bolt11InvoiceFeatures {
 	provided_init_features(config).to_context()
 }
@@ -10523,6 +12349,7 @@ impl_writeable_tlv_based_enum!(PendingHTLCRouting,
 		(0, onion_packet, required),
 		(1, blinded, option),
 		(2, short_channel_id, required),
+		(3, incoming_cltv_expiry, option),
 	},
 	(1, Receive) => {
 		(0, payment_data, required),
@@ -10727,6 +12554,7 @@ impl Readable for HTLCSource {
 				let mut payment_id = None;
 				let mut payment_params: Option<PaymentParameters> = None;
 				let mut blinded_tail: Option<BlindedTail> = None;
+				let mut trampoline_tail: Option<TrampolineTail> = None;
 				read_tlv_fields!(reader, {
 					(0, session_priv, required),
 					(1, payment_id, option),
@@ -10734,13 +12562,14 @@ impl Readable for HTLCSource {
 					(4, path_hops, required_vec),
 					(5, payment_params, (option: ReadableArgs, 0)),
 					(6, blinded_tail, option),
+					(8, trampoline_tail, option),
 				});
 				if payment_id.is_none() {
 					// For backwards compat, if there was no payment_id written, use the session_priv bytes
 					// instead.
 					payment_id = Some(PaymentId(*session_priv.0.unwrap().as_ref()));
 				}
-				let path = Path { hops: path_hops, blinded_tail };
+				let path = Path { hops: path_hops, blinded_tail, trampoline_tail };
 				if path.hops.len() == 0 {
 					return Err(DecodeError::InvalidValue);
 				}
@@ -10778,6 +12607,7 @@ impl Writeable for HTLCSource {
 					(4, path.hops, required_vec),
 					(5, None::<PaymentParameters>, option), // payment_params in LDK versions prior to 0.0.115
 					(6, path.blinded_tail, option),
+					(8, path.trampoline_tail, option),
 				 });
 			}
 			HTLCSource::PreviousHopData(ref field) => {
@@ -10871,6 +12701,35 @@ impl_writeable_tlv_based!(PendingInboundPayment, {
 	(8, min_value_msat, required),
 });
 
+impl_writeable_tlv_based!(HeldInboundPayment, {
+	(0, cltv_buffer, required),
+	(2, expiry_time, required),
+	(4, cancelled, required),
+});
+
+impl_writeable_tlv_based!(UnpaidInboundPayment, {
+	(0, expiry_time, required),
+	(2, removed, required),
+});
+
+impl_writeable_tlv_based!(PendingRebalance, {
+	(0, payment_hash, required),
+	(2, from_channel_id, required),
+	(4, to_channel_id, required),
+	(6, amount_msat, required),
+	(8, completed, required),
+	(10, claimed, required),
+});
+
+impl_writeable_tlv_based!(QueuedChannelOpen, {
+	(0, counterparty_node_id, required),
+	(2, temporary_channel_id, required),
+	(4, channel_value_satoshis, required),
+	(6, push_msat, required),
+	(8, user_channel_id, required),
+	(10, ticks_remaining, required),
+});
+
 impl<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref> Writeable for ChannelManager<M, T, ES, NS, SP, F, R, L>
 where
 	M::Target: chain::Watch<<SP::Target as SignerProvider>::EcdsaSigner>,
@@ -10945,6 +12804,8 @@ where
 		}
 
 		let pending_inbound_payments = self.pending_inbound_payments.lock().unwrap();
+		let held_inbound_payments = self.held_inbound_payments.lock().unwrap();
+		let unpaid_inbound_payments = self.unpaid_inbound_payments.lock().unwrap();
 		let claimable_payments = self.claimable_payments.lock().unwrap();
 		let pending_outbound_payments = self.pending_outbound_payments.pending_outbound_payments.lock().unwrap();
 
@@ -11063,6 +12924,28 @@ where
 			pending_intercepted_htlcs = Some(our_pending_intercepts);
 		}
 
+		let mut held_inbound_payments_opt = None;
+		if !held_inbound_payments.is_empty() {
+			held_inbound_payments_opt = Some(&*held_inbound_payments);
+		}
+
+		let mut unpaid_inbound_payments_opt = None;
+		if !unpaid_inbound_payments.is_empty() {
+			unpaid_inbound_payments_opt = Some(&*unpaid_inbound_payments);
+		}
+
+		let forwarding_history: Vec<ForwardedHTLC> =
+			self.forwarding_history.lock().unwrap().iter().cloned().collect();
+
+		let pending_rebalances = self.pending_rebalances.lock().unwrap();
+		let mut pending_rebalances_opt = None;
+		if !pending_rebalances.is_empty() {
+			pending_rebalances_opt = Some(&*pending_rebalances);
+		}
+
+		let queued_channel_opens: Vec<QueuedChannelOpen> =
+			self.queued_channel_opens.lock().unwrap().iter().cloned().collect();
+
 		let mut pending_claiming_payments = Some(&claimable_payments.pending_claiming_payments);
 		if pending_claiming_payments.as_ref().unwrap().is_empty() {
 			// LDK versions prior to 0.0.113 do not know how to read the pending claimed payments
@@ -11094,6 +12977,11 @@ where
 			(11, self.probing_cookie_secret, required),
 			(13, htlc_onion_fields, optional_vec),
 			(14, decode_update_add_htlcs_opt, option),
+			(15, held_inbound_payments_opt, option),
+			(17, unpaid_inbound_payments_opt, option),
+			(19, forwarding_history, optional_vec),
+			(21, pending_rebalances_opt, option),
+			(23, queued_channel_opens, optional_vec),
 		});
 
 		Ok(())
@@ -11361,6 +13249,7 @@ where
 						counterparty_node_id: Some(channel.context.get_counterparty_node_id()),
 						channel_capacity_sats: Some(channel.context.get_value_satoshis()),
 						channel_funding_txo: channel.context.get_funding_txo(),
+						closing_fee_satoshis: None,
 					}, None));
 					for (channel_htlc_source, payment_hash) in channel.inflight_htlc_sources() {
 						let mut found_htlc = false;
@@ -11417,6 +13306,7 @@ where
 					counterparty_node_id: Some(channel.context.get_counterparty_node_id()),
 					channel_capacity_sats: Some(channel.context.get_value_satoshis()),
 					channel_funding_txo: channel.context.get_funding_txo(),
+					closing_fee_satoshis: None,
 				}, None));
 			} else {
 				log_error!(logger, "Missing ChannelMonitor for channel {} needed by ChannelManager.", &channel.context.channel_id());
@@ -11437,7 +13327,7 @@ where
 				let monitor_update = ChannelMonitorUpdate {
 					update_id: CLOSED_CHANNEL_UPDATE_ID,
 					counterparty_node_id: None,
-					updates: vec![ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast: true }],
+					updates: vec![ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast: true, broadcast_externally: false }],
 					channel_id: Some(monitor.channel_id()),
 				};
 				close_background_events.push(BackgroundEvent::ClosedMonitorUpdateRegeneratedOnStartup((*funding_txo, channel_id, monitor_update)));
@@ -11554,6 +13444,11 @@ where
 		let mut events_override = None;
 		let mut in_flight_monitor_updates: Option<HashMap<(PublicKey, OutPoint), Vec<ChannelMonitorUpdate>>> = None;
 		let mut decode_update_add_htlcs: Option<HashMap<u64, Vec<msgs::UpdateAddHTLC>>> = None;
+		let mut held_inbound_payments: Option<HashMap<PaymentHash, HeldInboundPayment>> = None;
+		let mut unpaid_inbound_payments: Option<HashMap<PaymentHash, UnpaidInboundPayment>> = None;
+		let mut forwarding_history: Option<Vec<ForwardedHTLC>> = None;
+		let mut pending_rebalances: Option<HashMap<PaymentId, PendingRebalance>> = None;
+		let mut queued_channel_opens: Option<Vec<QueuedChannelOpen>> = None;
 		read_tlv_fields!(reader, {
 			(1, pending_outbound_payments_no_retry, option),
 			(2, pending_intercepted_htlcs, option),
@@ -11568,6 +13463,11 @@ where
 			(11, probing_cookie_secret, option),
 			(13, claimable_htlc_onion_fields, optional_vec),
 			(14, decode_update_add_htlcs, option),
+			(15, held_inbound_payments, option),
+			(17, unpaid_inbound_payments, option),
+			(19, forwarding_history, optional_vec),
+			(21, pending_rebalances, option),
+			(23, queued_channel_opens, optional_vec),
 		});
 		let mut decode_update_add_htlcs = decode_update_add_htlcs.unwrap_or_else(|| new_hash_map());
 		if fake_scid_rand_bytes.is_none() {
@@ -11771,6 +13671,7 @@ where
 										total_msat: path_amt,
 										starting_block_height: best_block_height,
 										remaining_max_total_routing_fee_msat: None, // only used for retries, and we'll never retry on startup
+										max_candidate_hop_evaluations: None, // only used for retries, and we'll never retry on startup
 									});
 									log_info!(logger, "Added a pending payment for {} msat with payment hash {} for path with session priv {}",
 										path_amt, &htlc.payment_hash,  log_bytes!(session_priv_bytes));
@@ -12104,6 +14005,14 @@ where
 			}
 		}
 
+		// The configured history size may have been reduced since we were last written, in which
+		// case we only keep the most recent forwards.
+		let mut forwarding_history: VecDeque<ForwardedHTLC> = forwarding_history.unwrap_or_default().into();
+		let max_forwarding_history_entries = args.default_config.max_forwarding_history_entries;
+		if forwarding_history.len() > max_forwarding_history_entries {
+			forwarding_history.drain(..forwarding_history.len() - max_forwarding_history_entries);
+		}
+
 		let channel_manager = ChannelManager {
 			chain_hash,
 			fee_estimator: bounded_fee_estimator,
@@ -12115,6 +14024,11 @@ where
 
 			inbound_payment_key: expanded_inbound_key,
 			pending_inbound_payments: Mutex::new(pending_inbound_payments),
+			held_inbound_payments: Mutex::new(held_inbound_payments.unwrap_or_else(new_hash_map)),
+			unpaid_inbound_payments: Mutex::new(unpaid_inbound_payments.unwrap_or_else(new_hash_map)),
+			forwarding_history: Mutex::new(forwarding_history),
+			pending_rebalances: Mutex::new(pending_rebalances.unwrap_or_else(new_hash_map)),
+			queued_channel_opens: Mutex::new(queued_channel_opens.unwrap_or_default()),
 			pending_outbound_payments: pending_outbounds,
 			pending_intercepted_htlcs: Mutex::new(pending_intercepted_htlcs.unwrap()),
 
@@ -12145,6 +14059,7 @@ where
 			needs_persist_flag: AtomicBool::new(false),
 
 			funding_batch_states: Mutex::new(BTreeMap::new()),
+			pending_batch_opens: Mutex::new(new_hash_map()),
 
 			pending_offers_messages: Mutex::new(Vec::new()),
 
@@ -12192,7 +14107,8 @@ mod tests {
 	use core::sync::atomic::Ordering;
 	use crate::events::{Event, HTLCDestination, MessageSendEvent, MessageSendEventsProvider, ClosureReason};
 	use crate::ln::types::{ChannelId, PaymentPreimage, PaymentHash, PaymentSecret};
-	use crate::ln::channelmanager::{create_recv_pending_htlc_info, HTLCForwardInfo, inbound_payment, PaymentId, PaymentSendFailure, RecipientOnionFields, InterceptId};
+	use crate::ln::channel::{commit_tx_fee_msat, ANCHOR_OUTPUT_VALUE_SATOSHI, FEE_SPIKE_BUFFER_FEE_INCREASE_MULTIPLE};
+	use crate::ln::channelmanager::{create_recv_pending_htlc_info, HTLCForwardInfo, inbound_payment, PaymentId, PaymentSendFailure, RecipientOnionFields, InterceptId, MIN_CLTV_EXPIRY_DELTA};
 	use crate::ln::functional_test_utils::*;
 	use crate::ln::msgs::{self, ErrorAction};
 	use crate::ln::msgs::ChannelMessageHandler;
@@ -13463,6 +15379,201 @@ mod tests {
 		assert_eq!(events.len(), 0);
 	}
 
+	#[test]
+	fn test_update_channel_config_batch() {
+		let chanmon_cfg = create_chanmon_cfgs(3);
+		let node_cfg = create_node_cfgs(3, &chanmon_cfg);
+		let node_chanmgr = create_node_chanmgrs(3, &node_cfg, &[None, None, None]);
+		let nodes = create_network(3, &node_cfg, &node_chanmgr);
+		let (_, _, chan_id_0, _) = create_announced_chan_between_nodes(&nodes, 0, 1);
+		let (_, _, chan_id_2, _) = create_announced_chan_between_nodes(&nodes, 1, 2);
+		let node_0_id = nodes[0].node.get_our_node_id();
+		let node_2_id = nodes[2].node.get_our_node_id();
+		let channel_config = |channel_id: ChannelId| nodes[1].node.list_channels().into_iter()
+			.find(|channel| channel.channel_id == channel_id).unwrap().config.unwrap();
+		let check_broadcasts = |expected_channel_ids: &[ChannelId]| {
+			let events = nodes[1].node.get_and_clear_pending_msg_events();
+			assert_eq!(events.len(), expected_channel_ids.len());
+			for (event, channel_id) in events.iter().zip(expected_channel_ids.iter()) {
+				match event {
+					MessageSendEvent::BroadcastChannelUpdate { msg } => {
+						let scid = nodes[1].node.list_channels().into_iter()
+							.find(|channel| channel.channel_id == *channel_id).unwrap().short_channel_id.unwrap();
+						assert_eq!(msg.contents.short_channel_id, scid);
+					},
+					_ => panic!("expected BroadcastChannelUpdate event"),
+				}
+			}
+		};
+		let initial_config = channel_config(chan_id_0);
+
+		// Several fields across several entries for the same channel only generate one update, and
+		// failing entries don't prevent the rest of the batch from being applied.
+		let bad_channel_id = ChannelId::v1_from_funding_txid(&[10; 32], 10);
+		let results = nodes[1].node.update_channel_config_batch(&[
+			(node_0_id, chan_id_0, ChannelConfigUpdate {
+				forwarding_fee_base_msat: Some(initial_config.forwarding_fee_base_msat + 10),
+				forwarding_fee_proportional_millionths: Some(initial_config.forwarding_fee_proportional_millionths + 100),
+				..Default::default()
+			}),
+			(node_0_id, bad_channel_id, ChannelConfigUpdate {
+				forwarding_fee_base_msat: Some(0),
+				..Default::default()
+			}),
+			(node_2_id, chan_id_2, ChannelConfigUpdate {
+				cltv_expiry_delta: Some(MIN_CLTV_EXPIRY_DELTA - 1),
+				..Default::default()
+			}),
+			(node_2_id, chan_id_0, ChannelConfigUpdate::default()),
+			(node_2_id, chan_id_2, ChannelConfigUpdate {
+				forwarding_fee_proportional_millionths: Some(initial_config.forwarding_fee_proportional_millionths + 200),
+				..Default::default()
+			}),
+			(node_0_id, chan_id_0, ChannelConfigUpdate {
+				cltv_expiry_delta: Some(initial_config.cltv_expiry_delta + 6),
+				..Default::default()
+			}),
+		]);
+		assert_eq!(results.len(), 6);
+		assert!(results[0].is_ok());
+		assert!(matches!(results[1], Err(APIError::ChannelUnavailable { .. })));
+		assert!(matches!(results[2], Err(APIError::APIMisuseError { .. })));
+		assert!(matches!(results[3], Err(APIError::ChannelUnavailable { .. })));
+		assert!(results[4].is_ok());
+		assert!(results[5].is_ok());
+
+		let config_0 = channel_config(chan_id_0);
+		assert_eq!(config_0.forwarding_fee_base_msat, initial_config.forwarding_fee_base_msat + 10);
+		assert_eq!(config_0.forwarding_fee_proportional_millionths, initial_config.forwarding_fee_proportional_millionths + 100);
+		assert_eq!(config_0.cltv_expiry_delta, initial_config.cltv_expiry_delta + 6);
+		let config_2 = channel_config(chan_id_2);
+		assert_eq!(config_2.forwarding_fee_proportional_millionths, initial_config.forwarding_fee_proportional_millionths + 200);
+		assert_eq!(config_2.cltv_expiry_delta, initial_config.cltv_expiry_delta);
+		check_broadcasts(&[chan_id_0, chan_id_2]);
+
+		// A batch which doesn't change any relay policy doesn't generate any updates.
+		let results = nodes[1].node.update_channel_config_batch(&[
+			(node_0_id, chan_id_0, ChannelConfigUpdate::default()),
+			(node_2_id, chan_id_2, ChannelConfigUpdate {
+				force_close_avoidance_max_fee_satoshis: Some(5_000),
+				..Default::default()
+			}),
+		]);
+		assert!(results.iter().all(|result| result.is_ok()));
+		assert_eq!(channel_config(chan_id_2).force_close_avoidance_max_fee_satoshis, 5_000);
+		check_broadcasts(&[]);
+
+		// Raise the fees of all channels at once.
+		assert!(matches!(
+			nodes[1].node.update_all_channels_config(&ChannelConfigUpdate {
+				cltv_expiry_delta: Some(MIN_CLTV_EXPIRY_DELTA - 1),
+				..Default::default()
+			}),
+			Err(APIError::APIMisuseError { .. })
+		));
+		check_broadcasts(&[]);
+		nodes[1].node.update_all_channels_config(&ChannelConfigUpdate {
+			forwarding_fee_proportional_millionths: Some(5_000),
+			..Default::default()
+		}).unwrap();
+		assert_eq!(channel_config(chan_id_0).forwarding_fee_proportional_millionths, 5_000);
+		assert_eq!(channel_config(chan_id_2).forwarding_fee_proportional_millionths, 5_000);
+		let events = nodes[1].node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 2);
+		assert!(events.iter().all(|event| matches!(event, MessageSendEvent::BroadcastChannelUpdate { .. })));
+	}
+
+	#[test]
+	fn test_channel_balance_details() {
+		do_test_channel_balance_details(false);
+		do_test_channel_balance_details(true);
+	}
+
+	fn do_test_channel_balance_details(anchors: bool) {
+		// Checks that the balance breakdown of a channel with several HTLCs in flight explains the
+		// limits we expose in `ChannelDetails` and which the router uses for first hops.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut config = test_default_channel_config();
+		if anchors {
+			config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
+			config.manually_accept_inbound_channels = true;
+		}
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), Some(config)]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let (_, _, chan_id, _) = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100_000, 10_000_000);
+
+		let channel_details = nodes[0].node.list_channels().pop().unwrap();
+		assert_eq!(channel_details.channel_type.as_ref().unwrap().supports_anchors_zero_fee_htlc_tx(), anchors);
+		let initial_breakdown = nodes[0].node.channel_balance_details(&chan_id).unwrap();
+		assert_eq!(initial_breakdown.pending_outbound_htlcs_msat, 0);
+		assert!(initial_breakdown.dust_exposure_headroom_msat > 0);
+
+		// Route one dust HTLC and several non-dust HTLCs, which remain pending.
+		let dust_htlc_msat = 100_000;
+		route_payment(&nodes[0], &[&nodes[1]], dust_htlc_msat);
+		let nondust_htlcs_msat = [2_500_000, 3_000_000, 3_500_000];
+		for amount_msat in nondust_htlcs_msat.iter() {
+			route_payment(&nodes[0], &[&nodes[1]], *amount_msat);
+		}
+		let pending_msat = dust_htlc_msat + nondust_htlcs_msat.iter().sum::<u64>();
+
+		let channel_details = nodes[0].node.list_channels().pop().unwrap();
+		let breakdown = nodes[0].node.channel_balance_details(&chan_id).unwrap();
+		assert_eq!(breakdown.local_balance_msat, 90_000_000);
+		assert_eq!(breakdown.pending_outbound_htlcs_msat, pending_msat);
+		assert_eq!(breakdown.counterparty_selected_reserve_msat,
+			channel_details.unspendable_punishment_reserve.unwrap() * 1000);
+		assert_eq!(breakdown.outbound_capacity_msat,
+			breakdown.local_balance_msat - pending_msat - breakdown.counterparty_selected_reserve_msat);
+		assert_eq!(breakdown.outbound_capacity_msat, channel_details.outbound_capacity_msat);
+		assert_eq!(breakdown.max_sendable_htlc_msat, channel_details.next_outbound_htlc_limit_msat);
+		// Our counterparty's default in-flight limit of 10% of the channel value is what limits us
+		// here, rather than our balance.
+		assert_eq!(breakdown.max_sendable_htlc_msat, 10_000_000 - pending_msat);
+		assert_eq!(breakdown.max_sendable_total_msat, breakdown.max_sendable_htlc_msat);
+		assert!(breakdown.max_sendable_total_msat <= breakdown.outbound_capacity_msat - breakdown.commitment_fee_buffer_msat);
+		assert_eq!(breakdown.dust_exposure_headroom_msat, initial_breakdown.dust_exposure_headroom_msat - dust_htlc_msat);
+
+		// The fee buffer covers the commitment transaction with our non-dust HTLCs, the next HTLC,
+		// and one more for the fee spike buffer.
+		let channel_type = channel_details.channel_type.unwrap();
+		let feerate = channel_details.feerate_sat_per_1000_weight.unwrap();
+		let commit_tx_fee = commit_tx_fee_msat(feerate, nondust_htlcs_msat.len() + 2, &channel_type);
+		if anchors {
+			assert_eq!(breakdown.commitment_fee_buffer_msat, commit_tx_fee + ANCHOR_OUTPUT_VALUE_SATOSHI * 2 * 1000);
+		} else {
+			assert_eq!(breakdown.commitment_fee_buffer_msat, commit_tx_fee * FEE_SPIKE_BUFFER_FEE_INCREASE_MULTIPLE);
+		}
+
+		// The router agrees on how much we can send over the channel as a first hop.
+		let scorer = test_utils::TestScorer::new();
+		let random_seed_bytes = chanmon_cfgs[0].keys_manager.get_secure_random_bytes();
+		let first_hops = nodes[0].node.list_usable_channels();
+		let find_route_for = |amount_msat: u64| {
+			let route_params = RouteParameters::from_payment_params_and_value(
+				PaymentParameters::from_node_id(nodes[1].node.get_our_node_id(), TEST_FINAL_CLTV), amount_msat);
+			find_route(
+				&nodes[0].node.get_our_node_id(), &route_params, nodes[0].network_graph,
+				Some(&first_hops.iter().collect::<Vec<_>>()), nodes[0].logger, &scorer, &Default::default(),
+				&random_seed_bytes
+			)
+		};
+		assert!(find_route_for(breakdown.max_sendable_htlc_msat).is_ok());
+		assert!(find_route_for(breakdown.max_sendable_htlc_msat + 1).is_err());
+
+		// Our counterparty doesn't pay the commitment transaction fee, so it has no fee buffer.
+		let counterparty_details = nodes[1].node.list_channels().pop().unwrap();
+		let counterparty_breakdown = nodes[1].node.channel_balance_details(&chan_id).unwrap();
+		assert_eq!(counterparty_breakdown.local_balance_msat, 10_000_000);
+		assert_eq!(counterparty_breakdown.pending_outbound_htlcs_msat, 0);
+		assert_eq!(counterparty_breakdown.commitment_fee_buffer_msat, 0);
+		assert_eq!(counterparty_breakdown.outbound_capacity_msat, counterparty_details.outbound_capacity_msat);
+		assert_eq!(counterparty_breakdown.max_sendable_htlc_msat, counterparty_details.next_outbound_htlc_limit_msat);
+
+		assert!(nodes[0].node.channel_balance_details(&ChannelId::from_bytes([42; 32])).is_none());
+	}
+
 	#[test]
 	fn test_payment_display() {
 		let payment_id = PaymentId([42; 32]);
@@ -35,7 +35,7 @@ use crate::util::test_utils::{self, WatchtowerPersister};
 use crate::util::errors::APIError;
 use crate::util::ser::{Writeable, ReadableArgs};
 use crate::util::string::UntrustedString;
-use crate::util::config::{UserConfig, MaxDustHTLCExposure};
+use crate::util::config::{ChannelAcceptOverrides, ChannelConfigUpdate, UserConfig, MaxDustHTLCExposure};
 
 use bitcoin::hash_types::BlockHash;
 use bitcoin::blockdata::locktime::absolute::LockTime;
@@ -1092,7 +1092,7 @@ fn fake_network_test() {
 	hops[1].fee_msat = chan_4.1.contents.fee_base_msat as u64 + chan_4.1.contents.fee_proportional_millionths as u64 * hops[2].fee_msat as u64 / 1000000;
 	hops[0].fee_msat = chan_3.0.contents.fee_base_msat as u64 + chan_3.0.contents.fee_proportional_millionths as u64 * hops[1].fee_msat as u64 / 1000000;
 	let payment_preimage_1 = send_along_route(&nodes[1],
-		Route { paths: vec![Path { hops, blinded_tail: None }], route_params: None },
+		Route { paths: vec![Path { hops, blinded_tail: None, trampoline_tail: None }], route_params: None },
 			&vec!(&nodes[2], &nodes[3], &nodes[1])[..], 1000000).0;
 
 	let mut hops = Vec::with_capacity(3);
@@ -1126,7 +1126,7 @@ fn fake_network_test() {
 	hops[1].fee_msat = chan_2.1.contents.fee_base_msat as u64 + chan_2.1.contents.fee_proportional_millionths as u64 * hops[2].fee_msat as u64 / 1000000;
 	hops[0].fee_msat = chan_3.1.contents.fee_base_msat as u64 + chan_3.1.contents.fee_proportional_millionths as u64 * hops[1].fee_msat as u64 / 1000000;
 	let payment_hash_2 = send_along_route(&nodes[1],
-		Route { paths: vec![Path { hops, blinded_tail: None }], route_params: None },
+		Route { paths: vec![Path { hops, blinded_tail: None, trampoline_tail: None }], route_params: None },
 			&vec!(&nodes[3], &nodes[2], &nodes[1])[..], 1000000).1;
 
 	// Claim the rebalances...
@@ -8109,6 +8109,167 @@ fn test_manually_accept_inbound_channel_request() {
 	}
 }
 
+#[test]
+fn test_manually_accept_inbound_channel_with_config() {
+	// Tests that config overrides provided when manually accepting an inbound channel are validated
+	// and then negotiated with the counterparty, here demanding a non-default to_self_delay.
+	let mut manually_accept_conf = test_default_channel_config();
+	manually_accept_conf.manually_accept_inbound_channels = true;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(manually_accept_conf)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100_000, 10_001, 42, None, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), &open_channel);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let temporary_channel_id = match events[0] {
+		Event::OpenChannelRequest { temporary_channel_id, .. } => temporary_channel_id,
+		_ => panic!("Unexpected event"),
+	};
+
+	// Overrides outside of the protocol limits are rejected, leaving the request pending.
+	let invalid_overrides = [
+		ChannelAcceptOverrides { minimum_depth: Some(0), ..Default::default() },
+		ChannelAcceptOverrides { our_to_self_delay: Some(BREAKDOWN_TIMEOUT - 1), ..Default::default() },
+		ChannelAcceptOverrides { our_to_self_delay: Some(2017), ..Default::default() },
+		ChannelAcceptOverrides { our_max_accepted_htlcs: Some(484), ..Default::default() },
+		ChannelAcceptOverrides { their_channel_reserve_proportional_millionths: Some(1_000_000), ..Default::default() },
+	];
+	for config_overrides in invalid_overrides {
+		match nodes[1].node.accept_inbound_channel_with_config(&temporary_channel_id, &nodes[0].node.get_our_node_id(), 23, config_overrides) {
+			Err(APIError::APIMisuseError { .. }) => {},
+			res => panic!("Unexpected result {:?}", res),
+		}
+	}
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	let to_self_delay = BREAKDOWN_TIMEOUT * 2;
+	let config_overrides = ChannelAcceptOverrides {
+		minimum_depth: Some(3),
+		our_to_self_delay: Some(to_self_delay),
+		our_max_accepted_htlcs: Some(30),
+		their_channel_reserve_proportional_millionths: Some(50_000),
+	};
+	nodes[1].node.accept_inbound_channel_with_config(&temporary_channel_id, &nodes[0].node.get_our_node_id(), 23, config_overrides).unwrap();
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	assert_eq!(accept_channel.common_fields.minimum_depth, 3);
+	assert_eq!(accept_channel.common_fields.to_self_delay, to_self_delay);
+	assert_eq!(accept_channel.common_fields.max_accepted_htlcs, 30);
+	assert_eq!(accept_channel.channel_reserve_satoshis, 5_000);
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), &accept_channel);
+
+	let funding_tx = sign_funding_transaction(&nodes[0], &nodes[1], 100_000, temporary_channel_id);
+	let funding_msgs = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &funding_tx);
+	create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_msgs.0);
+
+	// nodes[0] must wait the delay demanded by nodes[1] to claim its funds on a force-close, while
+	// nodes[1] is still subject to the default delay demanded by nodes[0].
+	let node_0_channel = &nodes[0].node.list_channels()[0];
+	assert_eq!(node_0_channel.force_close_spend_delay, Some(to_self_delay));
+	assert_eq!(node_0_channel.unspendable_punishment_reserve, Some(5_000));
+	assert_eq!(nodes[1].node.list_channels()[0].force_close_spend_delay, Some(BREAKDOWN_TIMEOUT));
+
+	send_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+}
+
+#[test]
+fn test_queued_channel_open_after_restart() {
+	// Tests that a channel open queued while the peer is disconnected survives a restart and is
+	// initiated once the peer connects.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let persister;
+	let new_chain_monitor;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes_0_deserialized;
+	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+	nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id());
+
+	match nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100_000, 10_001, 42, None, None) {
+		Err(APIError::APIMisuseError { .. }) => {},
+		res => panic!("Unexpected result {:?}", res),
+	}
+	let temporary_channel_id = nodes[0].node.create_channel_when_connected(
+		nodes[1].node.get_our_node_id(), 100_000, 10_001, 42, None).unwrap();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[0].node.list_channels().is_empty());
+
+	reload_node!(nodes[0], test_default_channel_config(), &nodes[0].node.encode(), &[], persister,
+		new_chain_monitor, nodes_0_deserialized);
+	assert!(nodes[0].node.list_channels().is_empty());
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init {
+		features: nodes[1].node.init_features(), networks: None, remote_network_address: None
+	}, true).unwrap();
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init {
+		features: nodes[0].node.init_features(), networks: None, remote_network_address: None
+	}, false).unwrap();
+
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	assert_eq!(open_channel.common_fields.temporary_channel_id, temporary_channel_id);
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), &open_channel);
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), &accept_channel);
+	let (funding_temporary_channel_id, ..) = create_funding_transaction(&nodes[0], &nodes[1].node.get_our_node_id(), 100_000, 42);
+	assert_eq!(funding_temporary_channel_id, temporary_channel_id);
+
+	// The open is no longer queued, so reconnecting doesn't open another channel.
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+	check_closed_event!(nodes[0], 1, ClosureReason::DisconnectedPeer, [nodes[1].node.get_our_node_id()], 100_000);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init {
+		features: nodes[1].node.init_features(), networks: None, remote_network_address: None
+	}, true).unwrap();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+}
+
+#[test]
+fn test_queued_channel_open_timeout() {
+	// Tests that a channel open queued while the peer is disconnected is dropped with an
+	// `Event::ChannelOpenTimedOut` if the peer doesn't connect in time.
+	let mut config = test_default_channel_config();
+	config.queued_channel_open_timeout_ticks = 2;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+	nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id());
+
+	let temporary_channel_id = nodes[0].node.create_channel_when_connected(
+		nodes[1].node.get_our_node_id(), 100_000, 0, 42, None).unwrap();
+	match nodes[0].node.create_channel_when_connected(nodes[1].node.get_our_node_id(), 100_000, 0, 43, Some(temporary_channel_id)) {
+		Err(APIError::APIMisuseError { .. }) => {},
+		res => panic!("Unexpected result {:?}", res),
+	}
+
+	nodes[0].node.timer_tick_occurred();
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+	nodes[0].node.timer_tick_occurred();
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match &events[0] {
+		Event::ChannelOpenTimedOut { user_channel_id, temporary_channel_id: channel_id, counterparty_node_id } => {
+			assert_eq!(*user_channel_id, 42);
+			assert_eq!(*channel_id, temporary_channel_id);
+			assert_eq!(*counterparty_node_id, nodes[1].node.get_our_node_id());
+		},
+		ev => panic!("Unexpected event {:?}", ev),
+	}
+
+	// Once the peer connects, the timed out open is not initiated.
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init {
+		features: nodes[1].node.init_features(), networks: None, remote_network_address: None
+	}, true).unwrap();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[0].node.list_channels().is_empty());
+}
+
 #[test]
 fn test_manually_reject_inbound_channel_request() {
 	let mut manually_accept_conf = UserConfig::default();
@@ -10266,6 +10427,64 @@ fn test_nondust_htlc_fees_are_dust() {
 	expect_payment_failed_conditions(&nodes[2], payment_hash, false, PaymentFailedConditions::new());
 }
 
+fn do_test_channel_config_update_limits_new_htlcs(dust_exposure: bool) {
+	// Test that lowering a channel's `max_dust_htlc_exposure` or
+	// `max_inbound_htlc_value_in_flight_msat` at runtime fails new HTLCs which would exceed the
+	// updated limit, while HTLCs which are already pending are unaffected.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = test_default_channel_config();
+	config.channel_config.max_dust_htlc_exposure = MaxDustHTLCExposure::FixedLimitMsat(5_000_000);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	// Dust HTLCs are below the 354 sat dust limit.
+	let htlc_value_msat = if dust_exposure { 300_000 } else { 1_000_000 };
+	let payment_preimage_1 = route_payment(&nodes[0], &[&nodes[1]], htlc_value_msat).0;
+	let payment_preimage_2 = route_payment(&nodes[0], &[&nodes[1]], htlc_value_msat).0;
+
+	let node_0_id = nodes[0].node.get_our_node_id();
+	let update = if dust_exposure {
+		ChannelConfigUpdate {
+			max_dust_htlc_exposure_msat: Some(MaxDustHTLCExposure::FixedLimitMsat(htlc_value_msat * 5 / 2)),
+			..Default::default()
+		}
+	} else {
+		ChannelConfigUpdate {
+			max_inbound_htlc_value_in_flight_msat: Some(htlc_value_msat * 5 / 2),
+			..Default::default()
+		}
+	};
+	nodes[1].node.update_partial_channel_config(&node_0_id, &[chan_id], &update).unwrap();
+
+	// A further HTLC would exceed the new limit and is failed back.
+	let (route, payment_hash, _, payment_secret) =
+		get_route_and_payment_hash!(nodes[0], nodes[1], htlc_value_msat);
+	let onion = RecipientOnionFields::secret_only(payment_secret);
+	nodes[0].node.send_payment_with_route(&route, payment_hash, onion, PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors(&nodes[0], 1);
+	let send = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&node_0_id, &send.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], send.commitment_msg, false, true);
+	let fail = get_htlc_update_msgs(&nodes[1], &node_0_id);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &fail.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], fail.commitment_signed, false);
+	expect_payment_failed_conditions(&nodes[0], payment_hash, false, PaymentFailedConditions::new());
+
+	// The HTLCs pending before the update can still be claimed, after which new HTLCs fit within
+	// the new limit again.
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage_1);
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage_2);
+	send_payment(&nodes[0], &[&nodes[1]], htlc_value_msat);
+}
+
+#[test]
+fn test_channel_config_update_limits_new_htlcs() {
+	do_test_channel_config_update_limits_new_htlcs(true);
+	do_test_channel_config_update_limits_new_htlcs(false);
+}
 
 #[test]
 fn test_non_final_funding_tx() {
@@ -11118,6 +11337,262 @@ fn test_batch_funding_close_after_funding_signed() {
 	assert!(nodes[0].node.list_channels().is_empty());
 }
 
+/// Opens a batch of channels from `funding_node` to each of `counterparties` via
+/// `batch_open_channels`, returning each counterparty's `accept_channel` message without
+/// delivering it.
+fn open_channel_batch<'a, 'b, 'c>(
+	funding_node: &Node<'a, 'b, 'c>, counterparties: &[&Node<'a, 'b, 'c>],
+) -> (channelmanager::BatchFundingHandle, Vec<msgs::AcceptChannel>) {
+	let requests = counterparties.iter().enumerate().map(|(idx, node)| channelmanager::OpenChannelRequest {
+		counterparty_node_id: node.node.get_our_node_id(),
+		channel_value_satoshis: 100_000 * (idx as u64 + 1),
+		push_msat: 0,
+		user_channel_id: 42 + idx as u128,
+		override_config: None,
+	}).collect();
+	let handle = funding_node.node.batch_open_channels(requests).unwrap();
+	assert_eq!(handle.temporary_channels.len(), counterparties.len());
+
+	let msg_events = funding_node.node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), counterparties.len());
+	let mut accept_channel_msgs = Vec::new();
+	for (node, (temporary_channel_id, counterparty_node_id)) in counterparties.iter().zip(handle.temporary_channels.iter()) {
+		assert_eq!(*counterparty_node_id, node.node.get_our_node_id());
+		let open_channel_msg = msg_events.iter().find_map(|event| match event {
+			MessageSendEvent::SendOpenChannel { node_id, msg } if node_id == counterparty_node_id => Some(msg),
+			_ => None,
+		}).unwrap();
+		assert_eq!(open_channel_msg.common_fields.temporary_channel_id, *temporary_channel_id);
+		node.node.handle_open_channel(&funding_node.node.get_our_node_id(), open_channel_msg);
+		accept_channel_msgs.push(get_event_msg!(node, MessageSendEvent::SendAcceptChannel, funding_node.node.get_our_node_id()));
+	}
+	(handle, accept_channel_msgs)
+}
+
+#[test]
+fn test_batch_open_channels() {
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+	let counterparties = [&nodes[1], &nodes[2], &nodes[3]];
+
+	let (handle, accept_channel_msgs) = open_channel_batch(&nodes[0], &counterparties);
+
+	// A single event is only generated once every channel in the batch has been accepted.
+	for (node, accept_channel_msg) in counterparties.iter().zip(accept_channel_msgs.iter()) {
+		assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+		nodes[0].node.handle_accept_channel(&node.node.get_our_node_id(), accept_channel_msg);
+	}
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let funding_outputs = match events[0] {
+		Event::BatchFundingGenerationReady { batch_id, ref funding_outputs } => {
+			assert_eq!(batch_id, handle.batch_id);
+			funding_outputs.clone()
+		},
+		_ => panic!("Unexpected event"),
+	};
+	assert_eq!(funding_outputs.len(), counterparties.len());
+	for (idx, funding_output) in funding_outputs.iter().enumerate() {
+		assert_eq!((funding_output.temporary_channel_id, funding_output.counterparty_node_id), handle.temporary_channels[idx]);
+		assert_eq!(funding_output.channel_value_satoshis, 100_000 * (idx as u64 + 1));
+		assert_eq!(funding_output.user_channel_id, 42 + idx as u128);
+	}
+
+	let tx = Transaction {
+		version: Version::TWO,
+		lock_time: LockTime::ZERO,
+		input: Vec::new(),
+		output: funding_outputs.iter().map(|funding_output| TxOut {
+			value: Amount::from_sat(funding_output.channel_value_satoshis),
+			script_pubkey: funding_output.output_script.clone(),
+		}).collect(),
+	};
+	let temporary_channels = handle.temporary_channels.iter()
+		.map(|(channel_id, node_id)| (channel_id, node_id)).collect::<Vec<_>>();
+	nodes[0].node.batch_funding_transaction_generated(&temporary_channels, tx.clone()).unwrap();
+
+	// Go through the funding_created and funding_signed flow with each counterparty. The funding
+	// transaction is only broadcast once all of them are done.
+	let msg_events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), counterparties.len());
+	for node in counterparties.iter() {
+		assert!(nodes[0].tx_broadcaster.txn_broadcast().is_empty());
+		let funding_created_msg = msg_events.iter().find_map(|event| match event {
+			MessageSendEvent::SendFundingCreated { node_id, msg } if *node_id == node.node.get_our_node_id() => Some(msg),
+			_ => None,
+		}).unwrap();
+		node.node.handle_funding_created(&nodes[0].node.get_our_node_id(), funding_created_msg);
+		check_added_monitors(node, 1);
+		expect_channel_pending_event(node, &nodes[0].node.get_our_node_id());
+		let funding_signed_msg = get_event_msg!(node, MessageSendEvent::SendFundingSigned, nodes[0].node.get_our_node_id());
+		nodes[0].node.handle_funding_signed(&node.node.get_our_node_id(), &funding_signed_msg);
+		check_added_monitors(&nodes[0], 1);
+	}
+	assert_eq!(nodes[0].tx_broadcaster.txn_broadcast(), vec![tx]);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), counterparties.len());
+	assert!(events.iter().all(|event| matches!(event, Event::ChannelPending { .. })));
+}
+
+#[test]
+fn test_batch_open_channels_disconnected_peer() {
+	// Tests that a batch isn't opened at all if any of its counterparties isn't connected, even if
+	// we still have a channel with it.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 2);
+
+	nodes[0].node.peer_disconnected(&nodes[2].node.get_our_node_id());
+	nodes[0].node.get_and_clear_pending_msg_events();
+
+	let requests = [&nodes[1], &nodes[2]].iter().map(|node| channelmanager::OpenChannelRequest {
+		counterparty_node_id: node.node.get_our_node_id(),
+		channel_value_satoshis: 100_000,
+		push_msat: 0,
+		user_channel_id: 42,
+		override_config: None,
+	}).collect();
+	assert!(matches!(nodes[0].node.batch_open_channels(requests), Err(APIError::APIMisuseError { .. })));
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+	assert_eq!(nodes[0].node.list_channels().len(), 1);
+}
+
+#[test]
+fn test_batch_open_channels_peer_rejects() {
+	// Tests that if one of the counterparties rejects its channel, all other channels in the batch
+	// are abandoned without ever generating a funding event or broadcasting anything.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	let (handle, accept_channel_msgs) = open_channel_batch(&nodes[0], &[&nodes[1], &nodes[2], &nodes[3]]);
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), &accept_channel_msgs[0]);
+
+	// Rather than accepting, nodes[2] rejects its channel.
+	let rejected_channel_id = handle.temporary_channels[1].0;
+	nodes[2].node.force_close_without_broadcasting_txn(&rejected_channel_id, &nodes[0].node.get_our_node_id(),
+		"Channel rejected".to_owned()).unwrap();
+	check_closed_event!(nodes[2], 1, ClosureReason::HolderForceClosed { broadcasted_latest_txn: Some(false) },
+		[nodes[0].node.get_our_node_id()], 200_000);
+	let error_msg = get_err_msg(&nodes[2], &nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_error(&nodes[2].node.get_our_node_id(), &error_msg);
+
+	check_closed_events(&nodes[0], &[
+		ExpectedCloseEvent {
+			channel_id: Some(rejected_channel_id),
+			counterparty_node_id: Some(nodes[2].node.get_our_node_id()),
+			reason: Some(ClosureReason::CounterpartyForceClosed { peer_msg: UntrustedString(error_msg.data.clone()) }),
+			user_channel_id: Some(43),
+			..Default::default()
+		},
+		ExpectedCloseEvent {
+			channel_id: Some(handle.temporary_channels[0].0),
+			counterparty_node_id: Some(nodes[1].node.get_our_node_id()),
+			reason: Some(ClosureReason::FundingBatchClosure),
+			user_channel_id: Some(42),
+			..Default::default()
+		},
+		ExpectedCloseEvent {
+			channel_id: Some(handle.temporary_channels[2].0),
+			counterparty_node_id: Some(nodes[3].node.get_our_node_id()),
+			reason: Some(ClosureReason::FundingBatchClosure),
+			user_channel_id: Some(44),
+			..Default::default()
+		},
+	]);
+	assert!(nodes[0].node.list_channels().is_empty());
+
+	// The counterparties of the abandoned channels are told to forget about them.
+	let msg_events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 2);
+	for (node, channel_value_satoshis) in [(&nodes[1], 100_000), (&nodes[3], 300_000)] {
+		let error_msg = msg_events.iter().find_map(|event| match event {
+			MessageSendEvent::HandleError { node_id, action: msgs::ErrorAction::SendErrorMessage { msg } }
+				if *node_id == node.node.get_our_node_id() => Some(msg),
+			_ => None,
+		}).unwrap();
+		node.node.handle_error(&nodes[0].node.get_our_node_id(), error_msg);
+		check_closed_event!(node, 1, ClosureReason::CounterpartyForceClosed { peer_msg: UntrustedString(error_msg.data.clone()) },
+			[nodes[0].node.get_our_node_id()], channel_value_satoshis);
+		assert!(node.node.list_channels().is_empty());
+	}
+	assert!(nodes[0].tx_broadcaster.txn_broadcast().is_empty());
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+}
+
+#[test]
+fn test_batch_open_channels_unfunded() {
+	// Tests that all channels in a batch are closed if only some of them are funded, or if the
+	// funding transaction is never provided.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let counterparties = [&nodes[1], &nodes[2]];
+
+	let open_and_accept_batch = || {
+		let (handle, accept_channel_msgs) = open_channel_batch(&nodes[0], &counterparties);
+		for (node, accept_channel_msg) in counterparties.iter().zip(accept_channel_msgs.iter()) {
+			nodes[0].node.handle_accept_channel(&node.node.get_our_node_id(), accept_channel_msg);
+		}
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::BatchFundingGenerationReady { ref funding_outputs, .. } => (handle, funding_outputs.clone()),
+			_ => panic!("Unexpected event"),
+		}
+	};
+
+	let (handle, funding_outputs) = open_and_accept_batch();
+	let tx = Transaction {
+		version: Version::TWO,
+		lock_time: LockTime::ZERO,
+		input: Vec::new(),
+		output: vec![TxOut {
+			value: Amount::from_sat(funding_outputs[0].channel_value_satoshis),
+			script_pubkey: funding_outputs[0].output_script.clone(),
+		}],
+	};
+	let (first_channel_id, first_node_id) = handle.temporary_channels[0];
+	let (second_channel_id, second_node_id) = handle.temporary_channels[1];
+	assert!(matches!(
+		nodes[0].node.funding_transaction_generated(&first_channel_id, &first_node_id, tx),
+		Err(APIError::APIMisuseError { .. })
+	));
+	let err = "Error in transaction funding: Misuse error: All channels opened in a batch must be funded by the same transaction".to_owned();
+	check_closed_events(&nodes[0], &[
+		ExpectedCloseEvent::from_id_reason(first_channel_id, false, ClosureReason::ProcessingError { err }),
+		ExpectedCloseEvent::from_id_reason(second_channel_id, false, ClosureReason::FundingBatchClosure),
+	]);
+	let msg_events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 2);
+	assert!(msg_events.iter().any(|event| matches!(event,
+		MessageSendEvent::HandleError { node_id, .. } if *node_id == first_node_id)));
+	assert!(msg_events.iter().any(|event| matches!(event,
+		MessageSendEvent::HandleError { node_id, .. } if *node_id == second_node_id)));
+	assert!(nodes[0].node.list_channels().is_empty());
+
+	// Open another batch, but never provide its funding transaction.
+	let (handle, _) = open_and_accept_batch();
+	for _ in 0..UNFUNDED_CHANNEL_AGE_LIMIT_TICKS {
+		nodes[0].node.timer_tick_occurred();
+	}
+	let reason = ClosureReason::HolderForceClosed { broadcasted_latest_txn: Some(false) };
+	check_closed_events(&nodes[0], &[
+		ExpectedCloseEvent::from_id_reason(handle.temporary_channels[0].0, false, reason.clone()),
+		ExpectedCloseEvent::from_id_reason(handle.temporary_channels[1].0, false, reason),
+	]);
+	assert_eq!(nodes[0].node.get_and_clear_pending_msg_events().len(), 2);
+	assert!(nodes[0].node.list_channels().is_empty());
+	assert!(nodes[0].tx_broadcaster.txn_broadcast().is_empty());
+}
+
 fn do_test_funding_and_commitment_tx_confirm_same_block(confirm_remote_commitment: bool) {
 	// Tests that a node will forget the channel (when it only requires 1 confirmation) if the
 	// funding and commitment transaction confirm in the same block.
@@ -11,6 +11,7 @@
 
 use crate::sign::{ecdsa::EcdsaChannelSigner, OutputSpender, SpendableOutputDescriptor};
 use crate::chain::channelmonitor::{ANTI_REORG_DELAY, LATENCY_GRACE_PERIOD_BLOCKS, Balance};
+use crate::chain::chainmonitor::NodeBalances;
 use crate::chain::transaction::OutPoint;
 use crate::chain::chaininterface::{LowerBoundedFeeEstimator, compute_feerate_sat_per_1000_weight};
 use crate::events::bump_transaction::{BumpTransactionEvent, WalletSource};
@@ -19,6 +20,7 @@ use crate::ln::channel;
 use crate::ln::types::ChannelId;
 use crate::ln::channelmanager::{BREAKDOWN_TIMEOUT, PaymentId, RecipientOnionFields};
 use crate::ln::msgs::ChannelMessageHandler;
+use crate::util::errors::APIError;
 use crate::crypto::utils::sign;
 use crate::util::ser::Writeable;
 use crate::util::scid_utils::block_from_scid;
@@ -926,6 +928,88 @@ fn test_balances_on_local_commitment_htlcs() {
 	do_test_balances_on_local_commitment_htlcs(true);
 }
 
+#[test]
+fn test_node_balances() {
+	// Check that `ChainMonitor::get_node_balances` counts open channels via their
+	// `ChannelDetails` and closed channels via their monitor's balances, without double-counting a
+	// channel which is mid-force-close.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1_000_000, 0);
+	let (_, _, chan_id, funding_tx) = create_announced_chan_between_nodes_with_value(&nodes, 0, 2, 1_000_000, 0);
+
+	// While both channels are open, everything is accounted for via the `ChannelDetails`.
+	let channels = nodes[0].node.list_channels();
+	assert_eq!(channels.len(), 2);
+	let balances = nodes[0].chain_monitor.chain_monitor.get_node_balances(&channels);
+	assert_eq!(balances.outbound_capacity_msat, channels.iter().map(|c| c.outbound_capacity_msat).sum::<u64>());
+	assert_eq!(balances.inbound_capacity_msat, channels.iter().map(|c| c.inbound_capacity_msat).sum::<u64>());
+	assert_eq!(balances.pending_on_chain_claimable_satoshis(), 0);
+	assert_eq!(balances.timelocked_satoshis, 0);
+
+	// Force-close the second channel with an outbound HTLC pending, leaving us with our
+	// `to_self` output and the HTLC both timelocked.
+	let (_, payment_hash, ..) = route_payment(&nodes[0], &[&nodes[2]], 10_000_000);
+	nodes[0].node.force_close_broadcasting_latest_txn(&chan_id, &nodes[2].node.get_our_node_id(), "Channel force-closed".to_string()).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	check_closed_broadcast!(nodes[0], true);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed { broadcasted_latest_txn: Some(true) }, [nodes[2].node.get_our_node_id()], 1000000);
+	let commitment_tx = {
+		let mut txn = nodes[0].tx_broadcaster.unique_txn_broadcast();
+		assert_eq!(txn.len(), 1);
+		let commitment_tx = txn.pop().unwrap();
+		check_spends!(commitment_tx, funding_tx);
+		commitment_tx
+	};
+	mine_transaction(&nodes[0], &commitment_tx);
+	if nodes[0].connect_style.borrow().updates_best_block_first() {
+		let mut txn = nodes[0].tx_broadcaster.txn_broadcast();
+		assert_eq!(txn.len(), 1);
+		assert_eq!(txn[0].txid(), commitment_tx.txid());
+	}
+
+	let channels = nodes[0].node.list_channels();
+	assert_eq!(channels.len(), 1);
+	let balances = nodes[0].chain_monitor.chain_monitor.get_node_balances(&channels);
+	assert_eq!(balances.outbound_capacity_msat, channels[0].outbound_capacity_msat);
+	assert_eq!(balances.inbound_capacity_msat, channels[0].inbound_capacity_msat);
+	let claimable_balances = nodes[0].chain_monitor.chain_monitor.get_claimable_balances(&[&channels[0]]);
+	let to_self_satoshis = claimable_balances.iter().map(|b| b.claimable_amount_satoshis()).sum::<u64>();
+	assert!(to_self_satoshis > 0);
+	assert_eq!(balances, NodeBalances {
+		outbound_capacity_msat: channels[0].outbound_capacity_msat,
+		inbound_capacity_msat: channels[0].inbound_capacity_msat,
+		claimable_awaiting_confirmations_satoshis: to_self_satoshis,
+		maybe_timeout_claimable_htlc_satoshis: 10_000,
+		timelocked_satoshis: to_self_satoshis + 10_000,
+		..Default::default()
+	});
+	assert_eq!(balances.pending_on_chain_claimable_satoshis(), to_self_satoshis);
+
+	// Time out the HTLC on-chain and wait for all outputs to mature, after which the closed
+	// channel no longer contributes anything.
+	connect_blocks(&nodes[0], TEST_FINAL_CLTV);
+	let timeout_htlc_txn = nodes[0].tx_broadcaster.unique_txn_broadcast();
+	assert_eq!(timeout_htlc_txn.len(), 1);
+	check_spends!(timeout_htlc_txn[0], commitment_tx);
+	mine_transaction(&nodes[0], &timeout_htlc_txn[0]);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 1);
+	expect_payment_failed!(nodes[0], payment_hash, false);
+	connect_blocks(&nodes[0], BREAKDOWN_TIMEOUT as u32);
+
+	let channels = nodes[0].node.list_channels();
+	assert!(nodes[0].chain_monitor.chain_monitor.get_claimable_balances(&[&channels[0]]).is_empty());
+	let balances = nodes[0].chain_monitor.chain_monitor.get_node_balances(&channels);
+	assert_eq!(balances, NodeBalances {
+		outbound_capacity_msat: channels[0].outbound_capacity_msat,
+		inbound_capacity_msat: channels[0].inbound_capacity_msat,
+		..Default::default()
+	});
+}
+
 #[test]
 fn test_no_preimage_inbound_htlc_balances() {
 	// Tests that MaybePreimageClaimableHTLC are generated for inbound HTLCs for which we do not
@@ -2241,6 +2325,100 @@ fn test_monitor_timer_based_claim() {
 	do_test_monitor_rebroadcast_pending_claims(true);
 }
 
+fn do_test_force_close_without_broadcast(anchors: bool) {
+	// Tests that `ChannelManager::force_close_without_broadcast` closes the channel as usual, but
+	// hands the latest commitment transaction to the user rather than broadcasting it, while still
+	// allowing the monitor to rebroadcast it and claim our funds once it confirms.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = test_default_channel_config();
+	if anchors {
+		config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
+		config.manually_accept_inbound_channels = true;
+	}
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let (_, _, chan_id, funding_tx) = create_announced_chan_between_nodes_with_value(
+		&nodes, 0, 1, 1_000_000, 500_000_000
+	);
+
+	if anchors {
+		// Require the commitment transaction's fee to be bumped, such that the monitor yields it
+		// in an event rather than broadcasting it directly.
+		*nodes[0].fee_estimator.sat_per_kw.lock().unwrap() *= 2;
+	}
+
+	let node_a_commitment_claimable = nodes[0].best_block_info().1 + BREAKDOWN_TIMEOUT as u32;
+	let package = nodes[0].node.force_close_without_broadcast(&chan_id, &nodes[1].node.get_our_node_id()).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	check_closed_broadcast!(nodes[0], true);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed { broadcasted_latest_txn: Some(false) }, [nodes[1].node.get_our_node_id()], 1_000_000);
+
+	// Neither the commitment transaction nor an event to bump it should be handed out by LDK.
+	assert!(nodes[0].tx_broadcaster.txn_broadcast().is_empty());
+	assert!(nodes[0].chain_monitor.chain_monitor.get_and_clear_pending_events().is_empty());
+
+	let commitment_tx = package.commitment_tx.clone();
+	check_spends!(commitment_tx, funding_tx);
+	assert_eq!(package.serialized_commitment_tx(), bitcoin::consensus::encode::serialize(&commitment_tx));
+	let output_value: u64 = commitment_tx.output.iter().map(|output| output.value.to_sat()).sum();
+	assert_eq!(package.commitment_tx_fee_satoshis, 1_000_000 - output_value);
+	assert_eq!(package.anchor_descriptor.is_some(), anchors);
+	if let Some(anchor_descriptor) = &package.anchor_descriptor {
+		assert_eq!(anchor_descriptor.outpoint.txid, commitment_tx.txid());
+		assert_eq!(commitment_tx.output[anchor_descriptor.outpoint.vout as usize].value.to_sat(),
+			channel::ANCHOR_OUTPUT_VALUE_SATOSHI);
+	}
+
+	// The package can't be fetched for a channel which no longer exists.
+	assert!(matches!(
+		nodes[0].node.force_close_without_broadcast(&chan_id, &nodes[1].node.get_our_node_id()),
+		Err(APIError::ChannelUnavailable { .. })
+	));
+
+	// If the user's submission fails, the monitor will still broadcast the commitment transaction
+	// through its usual rebroadcast path.
+	nodes[0].chain_monitor.chain_monitor.rebroadcast_pending_claims();
+	if anchors {
+		let mut events = nodes[0].chain_monitor.chain_monitor.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events.pop().unwrap() {
+			Event::BumpTransaction(BumpTransactionEvent::ChannelClose { commitment_tx: tx, anchor_descriptor, .. }) => {
+				assert_eq!(tx.txid(), commitment_tx.txid());
+				assert_eq!(Some(anchor_descriptor), package.anchor_descriptor);
+			},
+			_ => panic!("Unexpected event"),
+		}
+	} else {
+		let txn = nodes[0].tx_broadcaster.txn_broadcast();
+		assert_eq!(txn.len(), 1);
+		assert_eq!(txn[0].txid(), commitment_tx.txid());
+	}
+
+	// Once the externally-broadcast commitment transaction confirms, both sides claim their funds.
+	mine_transaction(&nodes[0], &commitment_tx);
+	nodes[0].tx_broadcaster.txn_broadcast();
+	nodes[0].chain_monitor.chain_monitor.get_and_clear_pending_events();
+	mine_transaction(&nodes[1], &commitment_tx);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_broadcast!(nodes[1], true);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed, [nodes[0].node.get_our_node_id()], 1_000_000);
+
+	connect_blocks(&nodes[1], ANTI_REORG_DELAY - 1);
+	test_spendable_output(&nodes[1], &commitment_tx, false);
+
+	connect_blocks(&nodes[0], node_a_commitment_claimable - nodes[0].best_block_info().1);
+	test_spendable_output(&nodes[0], &commitment_tx, false);
+	assert!(get_monitor!(nodes[0], chan_id).get_claimable_balances().is_empty());
+}
+
+#[test]
+fn test_force_close_without_broadcast() {
+	do_test_force_close_without_broadcast(false);
+	do_test_force_close_without_broadcast(true);
+}
+
 #[test]
 fn test_yield_anchors_events() {
 	// Tests that two parties supporting anchor outputs can open a channel, route payments over
@@ -1747,7 +1747,6 @@ mod fuzzy_internal_msgs {
 			amt_to_forward: u64,
 			outgoing_cltv_value: u32,
 		},
-		#[allow(unused)]
 		TrampolineEntrypoint {
 			amt_to_forward: u64,
 			outgoing_cltv_value: u32,
@@ -1777,15 +1776,22 @@ mod fuzzy_internal_msgs {
 		}
 	}
 
-	pub(crate) enum OutboundTrampolinePayload {
-		#[allow(unused)]
+	pub(crate) enum OutboundTrampolinePayload<'a> {
 		Forward {
 			/// The value, in msat, of the payment after this hop's fee is deducted.
 			amt_to_forward: u64,
 			outgoing_cltv_value: u32,
 			/// The node id to which the trampoline node must find a route
 			outgoing_node_id: PublicKey,
-		}
+		},
+		Receive {
+			payment_data: Option<FinalOnionHopData>,
+			payment_metadata: Option<&'a Vec<u8>>,
+			keysend_preimage: Option<PaymentPreimage>,
+			custom_tlvs: &'a Vec<(u64, Vec<u8>)>,
+			sender_intended_htlc_amt_msat: u64,
+			cltv_expiry_height: u32,
+		},
 	}
 
 	pub struct DecodedOnionErrorPacket {
@@ -2686,7 +2692,7 @@ impl<'a> Writeable for OutboundOnionPayload<'a> {
 	}
 }
 
-impl Writeable for OutboundTrampolinePayload {
+impl<'a> Writeable for OutboundTrampolinePayload<'a> {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
 		match self {
 			Self::Forward { amt_to_forward, outgoing_cltv_value, outgoing_node_id } => {
@@ -2695,7 +2701,23 @@ impl Writeable for OutboundTrampolinePayload {
 					(4, HighZeroBytesDroppedBigSize(*outgoing_cltv_value), required),
 					(14, outgoing_node_id, required)
 				});
-			}
+			},
+			Self::Receive {
+				ref payment_data, ref payment_metadata, ref keysend_preimage, sender_intended_htlc_amt_msat,
+				cltv_expiry_height, ref custom_tlvs,
+			} => {
+				// The recipient's payload within a trampoline onion is encoded identically to the
+				// final payload of a regular onion.
+				let keysend_tlv = keysend_preimage.map(|preimage| (5482373484, preimage.encode()));
+				let mut custom_tlvs: Vec<&(u64, Vec<u8>)> = custom_tlvs.iter().chain(keysend_tlv.iter()).collect();
+				custom_tlvs.sort_unstable_by_key(|(typ, _)| *typ);
+				_encode_varint_length_prefixed_tlv!(w, {
+					(2, HighZeroBytesDroppedBigSize(*sender_intended_htlc_amt_msat), required),
+					(4, HighZeroBytesDroppedBigSize(*cltv_expiry_height), required),
+					(8, payment_data, option),
+					(16, payment_metadata.map(|m| WithoutLength(m)), option)
+				}, custom_tlvs.iter());
+			},
 		}
 		Ok(())
 	}
@@ -114,6 +114,7 @@ pub(super) fn create_fwd_pending_htlc_info(
 						.map(|_| BlindedFailure::FromIntroductionNode)
 						.unwrap_or(BlindedFailure::FromBlindedNode),
 				}),
+			incoming_cltv_expiry: Some(msg.cltv_expiry),
 		},
 		payment_hash: msg.payment_hash,
 		incoming_shared_secret: shared_secret,
@@ -533,7 +534,7 @@ mod tests {
 		// Ensure the onion will not fit all the payloads by adding a large custom TLV.
 		recipient_onion.custom_tlvs.push((13377331, vec![0; 1156]));
 
-		let path = Path { hops, blinded_tail: None, };
+		let path = Path { hops, blinded_tail: None, trampoline_tail: None, };
 		let onion_keys = super::onion_utils::construct_onion_keys(&secp_ctx, &path, &session_priv).unwrap();
 		let (onion_payloads, ..) = super::onion_utils::build_onion_payloads(
 			&path, total_amt_msat, &recipient_onion, cur_height + 1, &Some(keysend_preimage)
@@ -560,6 +561,7 @@ mod tests {
 		let path = Path {
 			hops: hops,
 			blinded_tail: None,
+			trampoline_tail: None,
 		};
 
 		let (onion, amount_msat, cltv_expiry) = create_payment_onion(
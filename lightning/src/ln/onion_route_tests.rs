@@ -14,9 +14,9 @@
 use crate::chain::channelmonitor::{CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS};
 use crate::sign::{EntropySource, NodeSigner, Recipient};
 use crate::events::{Event, HTLCDestination, MessageSendEvent, MessageSendEventsProvider, PathFailure, PaymentFailureReason};
-use crate::ln::types::{PaymentHash, PaymentSecret};
+use crate::ln::types::{ChannelId, PaymentHash, PaymentSecret};
 use crate::ln::channel::EXPIRE_PREV_CONFIG_TICKS;
-use crate::ln::channelmanager::{HTLCForwardInfo, FailureCode, CLTV_FAR_FAR_AWAY, DISABLE_GOSSIP_TICKS, MIN_CLTV_EXPIRY_DELTA, PendingAddHTLCInfo, PendingHTLCInfo, PendingHTLCRouting, PaymentId, RecipientOnionFields};
+use crate::ln::channelmanager::{HTLCForwardInfo, FailureCode, InterceptFailureCode, CLTV_FAR_FAR_AWAY, DISABLE_GOSSIP_TICKS, MIN_CLTV_EXPIRY_DELTA, PendingAddHTLCInfo, PendingHTLCInfo, PendingHTLCRouting, PaymentId, RecipientOnionFields};
 use crate::ln::onion_utils;
 use crate::routing::gossip::{NetworkUpdate, RoutingFees};
 use crate::routing::router::{get_route, PaymentParameters, Route, RouteParameters, RouteHint, RouteHintHop};
@@ -25,7 +25,7 @@ use crate::ln::functional_test_utils::test_default_channel_config;
 use crate::ln::msgs;
 use crate::ln::msgs::{ChannelMessageHandler, ChannelUpdate, OutboundTrampolinePayload};
 use crate::ln::wire::Encode;
-use crate::util::ser::{Writeable, Writer, BigSize};
+use crate::util::ser::{Readable, Writeable, Writer, BigSize};
 use crate::util::test_utils;
 use crate::util::config::{UserConfig, ChannelConfig, MaxDustHTLCExposure};
 use crate::util::errors::APIError;
@@ -1076,6 +1076,122 @@ fn test_fail_htlc_backwards_with_reason() {
 	do_test_fail_htlc_backwards_with_reason(FailureCode::InvalidOnionPayload(None));
 }
 
+fn do_test_fail_intercepted_htlc_with_reason<F>(get_failure_code: F)
+where F: FnOnce(ChannelId, PublicKey) -> InterceptFailureCode {
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let mut intercept_forwards_config = test_default_channel_config();
+	intercept_forwards_config.accept_intercept_htlcs = true;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, Some(intercept_forwards_config), None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	let next_hop_channel_id = create_announced_chan_between_nodes(&nodes, 1, 2).2;
+	let next_node_id = nodes[2].node.get_our_node_id();
+	let failure_code = get_failure_code(next_hop_channel_id, next_node_id);
+
+	let intercept_scid = nodes[1].node.get_intercept_scid();
+	let (mut route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[2], 100_000);
+	route.paths[0].hops[1].short_channel_id = intercept_scid;
+	let htlc_msat = route.paths[0].hops[0].fee_msat + route.paths[0].hops[1].fee_msat;
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let intercept_id = match events[0] {
+		Event::HTLCIntercepted { intercept_id, .. } => intercept_id,
+		_ => panic!("Unexpected event"),
+	};
+
+	let expected_destination = match failure_code {
+		InterceptFailureCode::TemporaryChannelFailure { .. } | InterceptFailureCode::FeeInsufficient { .. } => {
+			// We need a channel to include a `channel_update` for, so failing with an unknown one
+			// errors and leaves the HTLC pending.
+			let unknown_channel_id = ChannelId::from_bytes([42; 32]);
+			let bad_failure_code = match failure_code {
+				InterceptFailureCode::TemporaryChannelFailure { next_node_id, .. } =>
+					InterceptFailureCode::TemporaryChannelFailure { next_hop_channel_id: unknown_channel_id, next_node_id },
+				_ => InterceptFailureCode::FeeInsufficient { next_hop_channel_id: unknown_channel_id, next_node_id },
+			};
+			let err = nodes[1].node.fail_intercepted_htlc_with_reason(intercept_id, bad_failure_code).unwrap_err();
+			assert_eq!(err, APIError::ChannelUnavailable {
+				err: format!("Channel with id {} not found for the passed counterparty node_id {}", unknown_channel_id, next_node_id)
+			});
+			assert!(nodes[1].node.get_intercepted_htlc(intercept_id).is_some());
+			HTLCDestination::NextHopChannel { node_id: Some(next_node_id), channel_id: next_hop_channel_id }
+		},
+		_ => HTLCDestination::UnknownNextHop { requested_forward_scid: intercept_scid },
+	};
+
+	nodes[1].node.fail_intercepted_htlc_with_reason(intercept_id, failure_code).unwrap();
+	assert!(nodes[1].node.get_intercepted_htlc(intercept_id).is_none());
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed_ignore!(nodes[1], vec![expected_destination]);
+	nodes[1].node.process_pending_htlc_forwards();
+	check_added_monitors!(nodes[1], 1);
+	let update_fail = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(update_fail.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &update_fail.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], update_fail.commitment_signed, false);
+
+	let events = nodes[0].node.get_and_clear_pending_events();
+	let error_data = match &events[0] {
+		Event::PaymentPathFailed { error_data, .. } => error_data.clone().unwrap(),
+		_ => panic!("Unexpected event"),
+	};
+	let expected_failure_code: u16 = failure_code.into();
+	let mut conditions = PaymentFailedConditions::new();
+	let mut expected_error_data = Vec::new();
+	match failure_code {
+		InterceptFailureCode::TemporaryNodeFailure => {},
+		InterceptFailureCode::UnknownNextPeer => {
+			conditions = conditions.blamed_scid(intercept_scid).blamed_chan_closed(true);
+		},
+		InterceptFailureCode::TemporaryChannelFailure { .. } | InterceptFailureCode::FeeInsufficient { .. } => {
+			// The failure includes the forwarding channel's policy under the SCID the sender used.
+			let mut update_data = &error_data[..];
+			if expected_failure_code == 0x1000 | 12 {
+				let amount_msat: u64 = Readable::read(&mut update_data).unwrap();
+				assert_eq!(amount_msat, htlc_msat);
+				amount_msat.write(&mut expected_error_data).unwrap();
+			}
+			let update_len: u16 = Readable::read(&mut update_data).unwrap();
+			let update_type: u16 = Readable::read(&mut update_data).unwrap();
+			assert_eq!(update_type, ChannelUpdate::TYPE);
+			let update: ChannelUpdate = Readable::read(&mut update_data).unwrap();
+			assert!(update_data.is_empty());
+			let config = nodes[1].node.list_channels().into_iter()
+				.find(|channel| channel.channel_id == next_hop_channel_id).unwrap().config.unwrap();
+			assert_eq!(update.contents.short_channel_id, intercept_scid);
+			assert_eq!(update.contents.fee_base_msat, config.forwarding_fee_base_msat);
+			assert_eq!(update.contents.fee_proportional_millionths, config.forwarding_fee_proportional_millionths);
+			assert_eq!(update.contents.cltv_expiry_delta, config.cltv_expiry_delta);
+
+			update_len.write(&mut expected_error_data).unwrap();
+			update_type.write(&mut expected_error_data).unwrap();
+			update.write(&mut expected_error_data).unwrap();
+			conditions = conditions.blamed_scid(intercept_scid).blamed_chan_closed(false);
+		},
+	}
+	conditions = conditions.expected_htlc_error_data(expected_failure_code, &expected_error_data);
+	expect_payment_failed_conditions_event(events, payment_hash, false, conditions);
+}
+
+#[test]
+fn test_fail_intercepted_htlc_with_reason() {
+	do_test_fail_intercepted_htlc_with_reason(|_, _| InterceptFailureCode::TemporaryNodeFailure);
+	do_test_fail_intercepted_htlc_with_reason(|_, _| InterceptFailureCode::UnknownNextPeer);
+	do_test_fail_intercepted_htlc_with_reason(|next_hop_channel_id, next_node_id|
+		InterceptFailureCode::TemporaryChannelFailure { next_hop_channel_id, next_node_id });
+	do_test_fail_intercepted_htlc_with_reason(|next_hop_channel_id, next_node_id|
+		InterceptFailureCode::FeeInsufficient { next_hop_channel_id, next_node_id });
+}
+
 macro_rules! get_phantom_route {
 	($nodes: expr, $amt: expr, $channel: expr) => {{
 		let phantom_pubkey = $nodes[1].keys_manager.get_node_id(Recipient::PhantomNode).unwrap();
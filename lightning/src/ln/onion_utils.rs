@@ -14,9 +14,9 @@ use crate::ln::channel::TOTAL_BITCOIN_SUPPLY_SATOSHIS;
 use crate::ln::channelmanager::{HTLCSource, RecipientOnionFields};
 use crate::ln::features::{ChannelFeatures, NodeFeatures};
 use crate::ln::msgs;
-use crate::ln::types::{PaymentHash, PaymentPreimage};
+use crate::ln::types::{PaymentHash, PaymentPreimage, PaymentSecret};
 use crate::routing::gossip::NetworkUpdate;
-use crate::routing::router::{Path, RouteHop, RouteParameters};
+use crate::routing::router::{Path, Payee, RouteHop, RouteParameters, TrampolineTail};
 use crate::sign::NodeSigner;
 use crate::util::errors::{self, APIError};
 use crate::util::logger::Logger;
@@ -111,15 +111,12 @@ pub(crate) fn next_hop_pubkey<T: secp256k1::Verification>(
 // can only fail if an intermediary hop has an invalid public key or session_priv is invalid
 #[inline]
 pub(super) fn construct_onion_keys_callback<T, FType>(
-	secp_ctx: &Secp256k1<T>, path: &Path, session_priv: &SecretKey, mut callback: FType,
+	secp_ctx: &Secp256k1<T>, path: &Path, session_priv: &SecretKey, callback: FType,
 ) -> Result<(), secp256k1::Error>
 where
 	T: secp256k1::Signing,
 	FType: FnMut(SharedSecret, [u8; 32], PublicKey, Option<&RouteHop>, usize),
 {
-	let mut blinded_priv = session_priv.clone();
-	let mut blinded_pub = PublicKey::from_secret_key(secp_ctx, &blinded_priv);
-
 	let unblinded_hops_iter = path.hops.iter().map(|h| (&h.pubkey, Some(h)));
 	let blinded_pks_iter = path
 		.blinded_tail
@@ -128,7 +125,54 @@ where
 		.unwrap_or([].iter())
 		.skip(1) // Skip the intro node because it's included in the unblinded hops
 		.map(|h| (&h.blinded_node_id, None));
-	for (idx, (pubkey, route_hop_opt)) in unblinded_hops_iter.chain(blinded_pks_iter).enumerate() {
+	construct_onion_keys_for_pubkeys_callback(
+		secp_ctx,
+		unblinded_hops_iter.chain(blinded_pks_iter),
+		session_priv,
+		callback,
+	)
+}
+
+// can only fail if the trampoline node or recipient has an invalid public key or session_priv is
+// invalid
+#[inline]
+fn construct_trampoline_onion_keys_callback<T, FType>(
+	secp_ctx: &Secp256k1<T>, path: &Path, trampoline_tail: &TrampolineTail,
+	trampoline_session_priv: &SecretKey, callback: FType,
+) -> Result<(), secp256k1::Error>
+where
+	T: secp256k1::Signing,
+	FType: FnMut(SharedSecret, [u8; 32], PublicKey, Option<&RouteHop>, usize),
+{
+	// The trampoline onion is peeled by the trampoline node, i.e. the last hop of the outer path,
+	// followed by the recipient.
+	let trampoline_hops_iter = path
+		.hops
+		.last()
+		.map(|h| (&h.pubkey, Some(h)))
+		.into_iter()
+		.chain(core::iter::once((&trampoline_tail.final_node_id, None)));
+	construct_onion_keys_for_pubkeys_callback(
+		secp_ctx,
+		trampoline_hops_iter,
+		trampoline_session_priv,
+		callback,
+	)
+}
+
+#[inline]
+fn construct_onion_keys_for_pubkeys_callback<'a, T, I, FType>(
+	secp_ctx: &Secp256k1<T>, hops: I, session_priv: &SecretKey, mut callback: FType,
+) -> Result<(), secp256k1::Error>
+where
+	T: secp256k1::Signing,
+	I: Iterator<Item = (&'a PublicKey, Option<&'a RouteHop>)>,
+	FType: FnMut(SharedSecret, [u8; 32], PublicKey, Option<&RouteHop>, usize),
+{
+	let mut blinded_priv = session_priv.clone();
+	let mut blinded_pub = PublicKey::from_secret_key(secp_ctx, &blinded_priv);
+
+	for (idx, (pubkey, route_hop_opt)) in hops.enumerate() {
 		let shared_secret = SharedSecret::new(pubkey, &blinded_priv);
 
 		let mut sha = Sha256::engine();
@@ -158,27 +202,77 @@ pub(super) fn construct_onion_keys<T: secp256k1::Signing>(
 		&path,
 		session_priv,
 		|shared_secret, _blinding_factor, ephemeral_pubkey, _, _| {
-			let (rho, mu) = gen_rho_mu_from_shared_secret(shared_secret.as_ref());
+			res.push(onion_keys_from_shared_secret(shared_secret, _blinding_factor, ephemeral_pubkey));
+		},
+	)?;
 
-			res.push(OnionKeys {
-				#[cfg(test)]
-				shared_secret,
-				#[cfg(test)]
-				blinding_factor: _blinding_factor,
-				ephemeral_pubkey,
-				rho,
-				mu,
-			});
+	Ok(res)
+}
+
+// can only fail if the trampoline node or recipient has an invalid public key or session_priv is
+// invalid
+pub(super) fn construct_trampoline_onion_keys<T: secp256k1::Signing>(
+	secp_ctx: &Secp256k1<T>, path: &Path, trampoline_tail: &TrampolineTail,
+	trampoline_session_priv: &SecretKey,
+) -> Result<Vec<OnionKeys>, secp256k1::Error> {
+	let mut res = Vec::with_capacity(2);
+
+	construct_trampoline_onion_keys_callback(
+		secp_ctx,
+		path,
+		trampoline_tail,
+		trampoline_session_priv,
+		|shared_secret, _blinding_factor, ephemeral_pubkey, _, _| {
+			res.push(onion_keys_from_shared_secret(shared_secret, _blinding_factor, ephemeral_pubkey));
 		},
 	)?;
 
 	Ok(res)
 }
 
+#[inline]
+fn onion_keys_from_shared_secret(
+	shared_secret: SharedSecret, _blinding_factor: [u8; 32], ephemeral_pubkey: PublicKey,
+) -> OnionKeys {
+	let (rho, mu) = gen_rho_mu_from_shared_secret(shared_secret.as_ref());
+
+	OnionKeys {
+		#[cfg(test)]
+		shared_secret,
+		#[cfg(test)]
+		blinding_factor: _blinding_factor,
+		ephemeral_pubkey,
+		rho,
+		mu,
+	}
+}
+
+/// The session key of a trampoline onion is derived from the session key of the outer onion, so
+/// that we can decrypt failures from within the trampoline onion without storing it separately.
+pub(super) fn compute_trampoline_session_priv(outer_onion_session_priv: &SecretKey) -> SecretKey {
+	let session_priv_hash = Sha256::hash(&outer_onion_session_priv.secret_bytes()).to_byte_array();
+	SecretKey::from_slice(&session_priv_hash[..]).expect("You broke SHA-256!")
+}
+
 /// returns the hop data, as well as the first-hop value_msat and CLTV value we should send.
 pub(super) fn build_onion_payloads<'a>(
 	path: &'a Path, total_msat: u64, recipient_onion: &'a RecipientOnionFields,
 	starting_htlc_offset: u32, keysend_preimage: &Option<PaymentPreimage>,
+) -> Result<(Vec<msgs::OutboundOnionPayload<'a>>, u64, u32), APIError> {
+	build_onion_payloads_with_trampoline_entrypoint(
+		path,
+		total_msat,
+		recipient_onion,
+		starting_htlc_offset,
+		keysend_preimage,
+		None,
+	)
+}
+
+fn build_onion_payloads_with_trampoline_entrypoint<'a>(
+	path: &'a Path, total_msat: u64, recipient_onion: &'a RecipientOnionFields,
+	starting_htlc_offset: u32, keysend_preimage: &Option<PaymentPreimage>,
+	trampoline_entrypoint: Option<TrampolineEntrypoint>,
 ) -> Result<(Vec<msgs::OutboundOnionPayload<'a>>, u64, u32), APIError> {
 	let mut res: Vec<msgs::OutboundOnionPayload> = Vec::with_capacity(
 		path.hops.len() + path.blinded_tail.as_ref().map_or(0, |t| t.hops.len()),
@@ -193,6 +287,7 @@ pub(super) fn build_onion_payloads<'a>(
 	let (value_msat, cltv) = build_onion_payloads_callback(
 		path.hops.iter(),
 		blinded_tail_with_hop_iter,
+		trampoline_entrypoint,
 		total_msat,
 		recipient_onion,
 		starting_htlc_offset,
@@ -211,12 +306,18 @@ struct BlindedTailHopIter<'a, I: Iterator<Item = &'a BlindedHop>> {
 	final_value_msat: u64,
 	excess_final_cltv_expiry_delta: u32,
 }
+/// The trampoline onion and outer payment secret for the trampoline node at the end of a path.
+struct TrampolineEntrypoint {
+	trampoline_packet: msgs::TrampolineOnionPacket,
+	trampoline_secret: PaymentSecret,
+}
 enum PayloadCallbackAction {
 	PushBack,
 	PushFront,
 }
 fn build_onion_payloads_callback<'a, H, B, F>(
-	hops: H, mut blinded_tail: Option<BlindedTailHopIter<'a, B>>, total_msat: u64,
+	hops: H, mut blinded_tail: Option<BlindedTailHopIter<'a, B>>,
+	mut trampoline_entrypoint: Option<TrampolineEntrypoint>, total_msat: u64,
 	recipient_onion: &'a RecipientOnionFields, starting_htlc_offset: u32,
 	keysend_preimage: &Option<PaymentPreimage>, mut callback: F,
 ) -> Result<(u64, u32), APIError>
@@ -275,6 +376,23 @@ where
 						);
 					}
 				}
+			} else if let Some(TrampolineEntrypoint { trampoline_packet, trampoline_secret }) =
+				trampoline_entrypoint.take()
+			{
+				// The trampoline node receives the full value of the path, which it then forwards
+				// (minus its fee) to the recipient as instructed by the trampoline onion.
+				callback(
+					PayloadCallbackAction::PushBack,
+					msgs::OutboundOnionPayload::TrampolineEntrypoint {
+						amt_to_forward: value_msat,
+						outgoing_cltv_value: cltv,
+						multipath_trampoline_data: Some(msgs::FinalOnionHopData {
+							payment_secret: trampoline_secret,
+							total_msat: value_msat,
+						}),
+						trampoline_packet,
+					},
+				);
 			} else {
 				callback(
 					PayloadCallbackAction::PushBack,
@@ -346,6 +464,21 @@ pub(crate) fn set_max_path_length(
 			excess_final_cltv_expiry_delta: 0,
 		});
 
+	// Payments via a trampoline node carry the recipient's payload in a fixed-size trampoline onion
+	// within the trampoline node's payload.
+	let trampoline_entrypoint_opt = match route_params.payment_params.payee {
+		Payee::Trampoline { .. } => Some(TrampolineEntrypoint {
+			trampoline_packet: msgs::TrampolineOnionPacket {
+				version: 0,
+				public_key: PublicKey::from_slice(&[2; 33]).unwrap(),
+				hop_data: vec![0; TRAMPOLINE_ONION_DATA_LEN],
+				hmac: [0; 32],
+			},
+			trampoline_secret: PaymentSecret([0; 32]),
+		}),
+		_ => None,
+	};
+
 	let unblinded_route_hop = RouteHop {
 		pubkey: PublicKey::from_slice(&[2; 33]).unwrap(),
 		node_features: NodeFeatures::empty(),
@@ -359,6 +492,7 @@ pub(crate) fn set_max_path_length(
 	let build_payloads_res = build_onion_payloads_callback(
 		core::iter::once(&unblinded_route_hop),
 		blinded_tail_opt,
+		trampoline_entrypoint_opt,
 		final_value_msat_with_overpay_buffer,
 		&recipient_onion,
 		best_block_height,
@@ -386,6 +520,9 @@ pub(crate) fn set_max_path_length(
 /// the hops can be of variable length.
 pub(crate) const ONION_DATA_LEN: usize = 20 * 65;
 
+/// Length of the trampoline onion data packet we construct when paying via a trampoline node.
+pub(crate) const TRAMPOLINE_ONION_DATA_LEN: usize = 650;
+
 pub(super) const INVALID_ONION_BLINDING: u16 = 0x8000 | 0x4000 | 24;
 
 #[inline]
@@ -416,7 +553,6 @@ pub(super) fn construct_onion_packet(
 	)
 }
 
-#[allow(unused)]
 pub(super) fn construct_trampoline_onion_packet(
 	payloads: Vec<msgs::OutboundTrampolinePayload>, onion_keys: Vec<OnionKeys>,
 	prng_seed: [u8; 32], associated_data: &PaymentHash, length: u16,
@@ -662,6 +798,14 @@ where
 	const NODE: u16 = 0x2000;
 	const UPDATE: u16 = 0x1000;
 
+	// Failures from a trampoline node which couldn't reach the recipient within the fee or CLTV
+	// expiry budget we gave it. As the budgets are fixed for a payment, retrying won't help.
+	const TRAMPOLINE_FEE_INSUFFICIENT: u16 = NODE | 51;
+	const TRAMPOLINE_EXPIRY_TOO_SOON: u16 = NODE | 52;
+	let is_trampoline_budget_failure = |error_code: u16| {
+		error_code == TRAMPOLINE_FEE_INSUFFICIENT || error_code == TRAMPOLINE_EXPIRY_TOO_SOON
+	};
+
 	// Handle packed channel/node updates for passing back for the route handler
 	let callback = |shared_secret, _, _, route_hop_opt: Option<&RouteHop>, route_hop_idx| {
 		if res.is_some() {
@@ -843,10 +987,20 @@ where
 			short_channel_id = Some(route_hop.short_channel_id);
 		}
 
+		let mut payment_failed_permanently = error_code & PERM == PERM && is_from_final_node;
+		if is_from_final_node
+			&& path.trampoline_tail.is_some()
+			&& is_trampoline_budget_failure(error_code)
+		{
+			network_update = None;
+			short_channel_id = None;
+			payment_failed_permanently = true;
+		}
+
 		res = Some(FailureLearnings {
 			network_update,
 			short_channel_id,
-			payment_failed_permanently: error_code & PERM == PERM && is_from_final_node,
+			payment_failed_permanently,
 			failed_within_blinded_path: false,
 		});
 
@@ -877,6 +1031,84 @@ where
 	construct_onion_keys_callback(secp_ctx, &path, session_priv, callback)
 		.expect("Route that we sent via spontaneously grew invalid keys in the middle of it?");
 
+	let trampoline_tail_opt = if res.is_none() { path.trampoline_tail.as_ref() } else { None };
+	if let Some(trampoline_tail) = trampoline_tail_opt {
+		// Failures from within the trampoline onion are additionally wrapped by the trampoline
+		// node and the recipient using the trampoline onion's shared secrets.
+		let trampoline_callback = |shared_secret, _, _, _: Option<&RouteHop>, trampoline_hop_idx| {
+			if res.is_some() {
+				return;
+			}
+			let is_from_recipient = trampoline_hop_idx == 1;
+
+			let err_packet = match decrypt_onion_error_packet(&mut encrypted_packet, shared_secret) {
+				Ok(p) => p,
+				Err(_) => return,
+			};
+			let um = gen_um_from_shared_secret(shared_secret.as_ref());
+			let mut hmac = HmacEngine::<Sha256>::new(&um);
+			hmac.input(&err_packet.encode()[32..]);
+
+			if !fixed_time_eq(&Hmac::from_engine(hmac).to_byte_array(), &err_packet.hmac) {
+				return;
+			}
+			let error_code = match err_packet.failuremsg.get(0..2) {
+				Some(s) => u16::from_be_bytes(s.try_into().expect("len is 2")),
+				None => {
+					// A useless packet which passed HMAC, we can't learn anything from it.
+					res = Some(FailureLearnings {
+						network_update: None,
+						short_channel_id: None,
+						payment_failed_permanently: true,
+						failed_within_blinded_path: false,
+					});
+					return;
+				},
+			};
+			error_code_ret = Some(error_code);
+			error_packet_ret = Some(err_packet.failuremsg[2..].to_vec());
+
+			// We only know the route up to the trampoline node, so there's nothing to learn for
+			// routing or scoring here.
+			let payment_failed_permanently = if is_from_recipient {
+				error_code & PERM == PERM
+			} else {
+				error_code & PERM == PERM || is_trampoline_budget_failure(error_code)
+			};
+			res = Some(FailureLearnings {
+				network_update: None,
+				short_channel_id: None,
+				payment_failed_permanently,
+				failed_within_blinded_path: false,
+			});
+
+			let (description, title) = errors::get_onion_error_description(error_code);
+			let failing_node_id = if is_from_recipient {
+				trampoline_tail.final_node_id
+			} else {
+				path.hops.last().expect("Outbound payments must have had a valid path").pubkey
+			};
+			log_info!(
+				logger,
+				"Onion Error[from {} via trampoline: {}({:#x})] {}",
+				failing_node_id,
+				title,
+				error_code,
+				description
+			);
+		};
+
+		let trampoline_session_priv = compute_trampoline_session_priv(session_priv);
+		construct_trampoline_onion_keys_callback(
+			secp_ctx,
+			&path,
+			trampoline_tail,
+			&trampoline_session_priv,
+			trampoline_callback,
+		)
+		.expect("Route that we sent via spontaneously grew invalid keys in the middle of it?");
+	}
+
 	if let Some(FailureLearnings {
 		network_update,
 		short_channel_id,
@@ -1163,12 +1395,28 @@ pub fn create_payment_onion<T: secp256k1::Signing>(
 	let onion_keys = construct_onion_keys(&secp_ctx, &path, &session_priv).map_err(|_| {
 		APIError::InvalidRoute { err: "Pubkey along hop was maliciously selected".to_owned() }
 	})?;
-	let (onion_payloads, htlc_msat, htlc_cltv) = build_onion_payloads(
+	let trampoline_entrypoint = match &path.trampoline_tail {
+		Some(trampoline_tail) => Some(build_trampoline_entrypoint(
+			secp_ctx,
+			path,
+			trampoline_tail,
+			session_priv,
+			total_msat,
+			recipient_onion,
+			cur_block_height,
+			payment_hash,
+			keysend_preimage,
+			prng_seed,
+		)?),
+		None => None,
+	};
+	let (onion_payloads, htlc_msat, htlc_cltv) = build_onion_payloads_with_trampoline_entrypoint(
 		&path,
 		total_msat,
 		recipient_onion,
 		cur_block_height,
 		keysend_preimage,
+		trampoline_entrypoint,
 	)?;
 	let onion_packet = construct_onion_packet(onion_payloads, onion_keys, prng_seed, payment_hash)
 		.map_err(|_| APIError::InvalidRoute {
@@ -1177,6 +1425,73 @@ pub fn create_payment_onion<T: secp256k1::Signing>(
 	Ok((onion_packet, htlc_msat, htlc_cltv))
 }
 
+/// Returns the trampoline hop data for the trampoline node and the recipient of the given
+/// [`TrampolineTail`].
+pub(super) fn build_trampoline_onion_payloads<'a>(
+	trampoline_tail: &TrampolineTail, total_msat: u64, recipient_onion: &'a RecipientOnionFields,
+	starting_htlc_offset: u32, keysend_preimage: &Option<PaymentPreimage>,
+) -> Result<Vec<msgs::OutboundTrampolinePayload<'a>>, APIError> {
+	let cltv_expiry_height = starting_htlc_offset
+		.checked_add(trampoline_tail.final_cltv_expiry_delta)
+		.filter(|cltv| *cltv < 500000000)
+		.ok_or_else(|| APIError::InvalidRoute { err: "Channel CLTV overflowed?".to_owned() })?;
+	Ok(vec![
+		msgs::OutboundTrampolinePayload::Forward {
+			amt_to_forward: trampoline_tail.final_value_msat,
+			outgoing_cltv_value: cltv_expiry_height,
+			outgoing_node_id: trampoline_tail.final_node_id,
+		},
+		msgs::OutboundTrampolinePayload::Receive {
+			payment_data: recipient_onion
+				.payment_secret
+				.map(|payment_secret| msgs::FinalOnionHopData { payment_secret, total_msat }),
+			payment_metadata: recipient_onion.payment_metadata.as_ref(),
+			keysend_preimage: *keysend_preimage,
+			custom_tlvs: &recipient_onion.custom_tlvs,
+			sender_intended_htlc_amt_msat: trampoline_tail.final_value_msat,
+			cltv_expiry_height,
+		},
+	])
+}
+
+fn build_trampoline_entrypoint<T: secp256k1::Signing>(
+	secp_ctx: &Secp256k1<T>, path: &Path, trampoline_tail: &TrampolineTail,
+	session_priv: &SecretKey, total_msat: u64, recipient_onion: &RecipientOnionFields,
+	cur_block_height: u32, payment_hash: &PaymentHash, keysend_preimage: &Option<PaymentPreimage>,
+	prng_seed: [u8; 32],
+) -> Result<TrampolineEntrypoint, APIError> {
+	let trampoline_session_priv = compute_trampoline_session_priv(session_priv);
+	let onion_keys =
+		construct_trampoline_onion_keys(secp_ctx, path, trampoline_tail, &trampoline_session_priv)
+			.map_err(|_| APIError::InvalidRoute {
+				err: "Pubkey along hop was maliciously selected".to_owned(),
+			})?;
+	let trampoline_payloads = build_trampoline_onion_payloads(
+		trampoline_tail,
+		total_msat,
+		recipient_onion,
+		cur_block_height,
+		keysend_preimage,
+	)?;
+	// Don't reuse the outer onion's filler noise for the trampoline onion.
+	let trampoline_prng_seed = Sha256::hash(&prng_seed).to_byte_array();
+	let trampoline_packet = construct_trampoline_onion_packet(
+		trampoline_payloads,
+		onion_keys,
+		trampoline_prng_seed,
+		payment_hash,
+		TRAMPOLINE_ONION_DATA_LEN as u16,
+	)
+	.map_err(|_| APIError::InvalidRoute {
+		err: "Recipient data too large to fit in the trampoline onion".to_owned(),
+	})?;
+	// The trampoline node requires a payment secret for the outer onion, which must not reveal the
+	// recipient's payment secret.
+	let trampoline_secret =
+		PaymentSecret(Sha256::hash(&trampoline_session_priv.secret_bytes()).to_byte_array());
+	Ok(TrampolineEntrypoint { trampoline_packet, trampoline_secret })
+}
+
 pub(crate) fn decode_next_untagged_hop<T, R: ReadableArgs<T>, N: NextPacketBytes>(
 	shared_secret: [u8; 32], hop_data: &[u8], hmac_bytes: [u8; 32], read_args: T,
 ) -> Result<(R, Option<([u8; 32], N)>), OnionDecodeErr> {
@@ -1273,14 +1588,18 @@ mod tests {
 	use crate::io;
 	use crate::ln::features::{ChannelFeatures, NodeFeatures};
 	use crate::ln::msgs;
-	use crate::ln::types::PaymentHash;
-	use crate::routing::router::{Path, Route, RouteHop};
-	use crate::util::ser::{VecWriter, Writeable, Writer};
+	use crate::ln::msgs::DecodeError;
+	use crate::ln::outbound_payment::RecipientOnionFields;
+	use crate::ln::types::{PaymentHash, PaymentSecret};
+	use crate::routing::router::{Path, Route, RouteHop, TrampolineTail};
+	use crate::util::ser::{BigSize, FixedLengthReader, HighZeroBytesDroppedBigSize, Readable, ReadableArgs, VecWriter, Writeable, Writer};
+	use crate::util::test_utils::TestNodeSigner;
 
 	#[allow(unused_imports)]
 	use crate::prelude::*;
 
 	use bitcoin::hashes::hex::FromHex;
+	use bitcoin::secp256k1::ecdh::SharedSecret;
 	use bitcoin::secp256k1::Secp256k1;
 	use bitcoin::secp256k1::{PublicKey, SecretKey};
 
@@ -1322,7 +1641,7 @@ mod tests {
 						channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
 						short_channel_id: 0, fee_msat: 0, cltv_expiry_delta: 0, maybe_announced_channel: true, // We fill in the payloads manually instead of generating them from RouteHops.
 					},
-			], blinded_tail: None }],
+			], blinded_tail: None, trampoline_tail: None }],
 			route_params: None,
 		};
 
@@ -1586,6 +1905,218 @@ mod tests {
 		assert_eq!(onion_packet_5.data, <Vec<u8>>::from_hex(hex).unwrap());
 	}
 
+	#[test]
+	fn trampoline_onion_keys_vectors() {
+		// The trampoline onion is keyed by the same ECDH chain as a regular onion, so with the
+		// BOLT 4 session key, trampoline node and recipient we expect the BOLT 4 keys for the
+		// first two hops.
+		let secp_ctx = Secp256k1::new();
+		let path = Path {
+			hops: vec![RouteHop {
+				pubkey: PublicKey::from_slice(&<Vec<u8>>::from_hex("02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619").unwrap()[..]).unwrap(),
+				channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
+				short_channel_id: 0, fee_msat: 0, cltv_expiry_delta: 0, maybe_announced_channel: true,
+			}],
+			blinded_tail: None,
+			trampoline_tail: Some(TrampolineTail {
+				final_node_id: PublicKey::from_slice(&<Vec<u8>>::from_hex("0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c").unwrap()[..]).unwrap(),
+				final_cltv_expiry_delta: 0,
+				final_value_msat: 0,
+			}),
+		};
+		let trampoline_keys = super::construct_trampoline_onion_keys(
+			&secp_ctx, &path, path.trampoline_tail.as_ref().unwrap(), &get_test_session_key(),
+		).unwrap();
+		assert_eq!(trampoline_keys.len(), 2);
+
+		let onion_keys = build_test_onion_keys();
+		for (trampoline_key, onion_key) in trampoline_keys.iter().zip(onion_keys.iter()) {
+			assert_eq!(trampoline_key.shared_secret, onion_key.shared_secret);
+			assert_eq!(trampoline_key.blinding_factor, onion_key.blinding_factor);
+			assert_eq!(trampoline_key.ephemeral_pubkey, onion_key.ephemeral_pubkey);
+			assert_eq!(trampoline_key.rho, onion_key.rho);
+			assert_eq!(trampoline_key.mu, onion_key.mu);
+		}
+		let hex = "a6519e98832a0b179f62123b3567c106db99ee37bef036e783263602f3488fae";
+		assert_eq!(
+			trampoline_keys[1].shared_secret.secret_bytes(),
+			<Vec<u8>>::from_hex(hex).unwrap()[..]
+		);
+	}
+
+	#[test]
+	fn trampoline_onion_packet_vectors() {
+		// Builds the trampoline onion for a payment to the second BOLT 4 node via the first one,
+		// checking the packet byte-for-byte against an independent implementation of the
+		// construction.
+		let secp_ctx = Secp256k1::new();
+		let path = Path {
+			hops: vec![RouteHop {
+				pubkey: PublicKey::from_slice(&<Vec<u8>>::from_hex("02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619").unwrap()[..]).unwrap(),
+				channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
+				short_channel_id: 1, fee_msat: 105_000, cltv_expiry_delta: 186, maybe_announced_channel: true,
+			}],
+			blinded_tail: None,
+			trampoline_tail: Some(TrampolineTail {
+				final_node_id: PublicKey::from_slice(&<Vec<u8>>::from_hex("0324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c").unwrap()[..]).unwrap(),
+				final_cltv_expiry_delta: 42,
+				final_value_msat: 100_000,
+			}),
+		};
+		let trampoline_tail = path.trampoline_tail.as_ref().unwrap();
+		let recipient_onion = RecipientOnionFields::secret_only(PaymentSecret([0x45; 32]));
+
+		let payloads = super::build_trampoline_onion_payloads(
+			trampoline_tail, 100_000, &recipient_onion, 800_000, &None,
+		).unwrap();
+		let hex = "2d02030186a004030c352a0e210324653eac434488002cc06bbfb7f10fe18991e35f9fe4302dbea6d2353dc0ab1c";
+		assert_eq!(payloads[0].encode(), <Vec<u8>>::from_hex(hex).unwrap());
+		let hex = "2f02030186a004030c352a082345454545454545454545454545454545454545454545454545454545454545450186a0";
+		assert_eq!(payloads[1].encode(), <Vec<u8>>::from_hex(hex).unwrap());
+
+		let pad_keytype_seed =
+			super::gen_pad_from_shared_secret(&get_test_session_key().secret_bytes());
+		let entrypoint = super::build_trampoline_entrypoint(
+			&secp_ctx, &path, trampoline_tail, &get_test_session_key(), 100_000, &recipient_onion,
+			800_000, &PaymentHash([0x42; 32]), &None, pad_keytype_seed,
+		).unwrap();
+
+		let hex = "b7961fea29d65acee63dbe39b7194db0b2657270826c41311c4507d72a91f1c4";
+		assert_eq!(entrypoint.trampoline_secret.0[..], <Vec<u8>>::from_hex(hex).unwrap()[..]);
+
+		let hex = "00032e32b162724bd5d7b79d2757c82ea1e1a052196697b356bca2c2b8330d71fa415bb749753e93e8c458042517f56a1d347f77c2f4928760124bb6952eee142be09252f6621180997ed3254b75fdb9a0a7869c1668ea560f219f3064e676788f8117f40bd4c5404ef9ce13e058cfac4ad5c5b0a13d9d4b430b75193655b823d1980664c27024a7ac1f904e8d03992273b46151bd5b0819076d14f41c17ea222d390b5051ff3e6e3760bee8de93345e04b844b58598e05389b68a28a4a6c619bd61190dc5ea5604925d480ec999c3fc5a33809950f9d20b219e788ea662bab95e3d294b7203f740d3ba8c96e81edc327a7f466f3ec5ee16a875709b87b83ad87b38e8167a94873fb2c433eae28a867d429ac4ff011c2809603804049fee3e539b848ce17bda0c029390db106258eed31b8ddc325101a890d75b20991f86a6c9e2d35c9931ef4d9e4f1a02ab06d83476cfb5285004e284495cc850c7ceb7728c1445d0696aa2299aaf64928bbb3eba8ee8f02988087e980fd5506a1ab3d8e64aba584a9f07d204cf8a1b2a973b2c9408f5e43a8d8d3a2280054c8cfe7a401a9e56df18b6bf6effba26a525a8a3e08f41efa3cb642bcb9382d8163218dc86b1da3c0be84af8d1453ff84eab3d9df58e5886034d98a5270bca9d6a97fa7f1272b1c3b91c683a4ba64a9f285f7087d57b8a302f9765e0fbecdb9acbbd9124a64ea03c0c42e490fa118a7ce99bf4cda4e1b9e1713d73eee86c35ca5d462e7ea123dfccb32f2148a4e807a4cc868e982ffd77222b53fe0fb260ab5ac4320137a7c0cdd5ecdae756cd3f83b73382725187a85700dc0951cc1e298a804e9848e6dbd1cf418e8d83f6cec806c442eeb6c7375dfa2710b7b013b94a4f1abc600648ad2eda22cb1a6417761798030e1843a25e80497aa8e6d254d620c413a1b746500f34b4029fe58462a18db278a5a19996c4c31718c07e1cf642a23b11cd82781aa31ba639d0f00bc39fd47e2b03c119";
+		assert_eq!(entrypoint.trampoline_packet.encode(), <Vec<u8>>::from_hex(hex).unwrap());
+	}
+
+	struct TrampolineForwardHopData {
+		amt_to_forward: u64,
+		outgoing_cltv_value: u32,
+		outgoing_node_id: PublicKey,
+	}
+	impl ReadableArgs<()> for TrampolineForwardHopData {
+		fn read<R: io::Read>(r: &mut R, _args: ()) -> Result<Self, DecodeError> {
+			let mut amt_to_forward = HighZeroBytesDroppedBigSize(0u64);
+			let mut outgoing_cltv_value = HighZeroBytesDroppedBigSize(0u32);
+			let mut outgoing_node_id = None;
+			let tlv_len = BigSize::read(r)?;
+			let mut rd = FixedLengthReader::new(r, tlv_len.0);
+			decode_tlv_stream!(&mut rd, {
+				(2, amt_to_forward, required),
+				(4, outgoing_cltv_value, required),
+				(14, outgoing_node_id, option)
+			});
+			Ok(Self {
+				amt_to_forward: amt_to_forward.0,
+				outgoing_cltv_value: outgoing_cltv_value.0,
+				outgoing_node_id: outgoing_node_id.ok_or(DecodeError::InvalidValue)?,
+			})
+		}
+	}
+
+	#[test]
+	fn trampoline_onion_round_trip() {
+		let secp_ctx = Secp256k1::new();
+		let session_priv = get_test_session_key();
+		let trampoline_secret_key = SecretKey::from_slice(&[0x43; 32]).unwrap();
+		let recipient_secret_key = SecretKey::from_slice(&[0x44; 32]).unwrap();
+		let recipient_node_id = PublicKey::from_secret_key(&secp_ctx, &recipient_secret_key);
+		let payment_hash = PaymentHash([0x42; 32]);
+		let payment_secret = PaymentSecret([0x45; 32]);
+		let cur_height = 800_000;
+
+		let path = Path {
+			hops: vec![
+				RouteHop {
+					pubkey: PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[0x41; 32]).unwrap()),
+					node_features: NodeFeatures::empty(), channel_features: ChannelFeatures::empty(),
+					short_channel_id: 1, fee_msat: 1_000, cltv_expiry_delta: 40, maybe_announced_channel: true,
+				},
+				RouteHop {
+					pubkey: PublicKey::from_secret_key(&secp_ctx, &trampoline_secret_key),
+					node_features: NodeFeatures::empty(), channel_features: ChannelFeatures::empty(),
+					short_channel_id: 2, fee_msat: 105_000, cltv_expiry_delta: 186, maybe_announced_channel: true,
+				},
+			],
+			blinded_tail: None,
+			trampoline_tail: Some(TrampolineTail {
+				final_node_id: recipient_node_id,
+				final_cltv_expiry_delta: 42,
+				final_value_msat: 100_000,
+			}),
+		};
+		let trampoline_tail = path.trampoline_tail.as_ref().unwrap();
+		assert_eq!(path.fee_msat(), 6_000);
+		assert_eq!(path.final_value_msat(), 100_000);
+
+		let recipient_onion = RecipientOnionFields::secret_only(payment_secret);
+		let entrypoint = super::build_trampoline_entrypoint(
+			&secp_ctx, &path, trampoline_tail, &session_priv, 100_000, &recipient_onion, cur_height,
+			&payment_hash, &None, [0x46; 32],
+		).unwrap();
+		assert_ne!(entrypoint.trampoline_secret, payment_secret);
+		let trampoline_packet = entrypoint.trampoline_packet.clone();
+		assert_eq!(trampoline_packet.hop_data.len(), super::TRAMPOLINE_ONION_DATA_LEN);
+
+		// The outer onion hands the trampoline packet to the trampoline node, which receives the
+		// recipient's amount plus its fee.
+		let (payloads, htlc_msat, htlc_cltv) = super::build_onion_payloads_with_trampoline_entrypoint(
+			&path, 100_000, &recipient_onion, cur_height, &None, Some(entrypoint),
+		).unwrap();
+		assert_eq!(htlc_msat, 106_000);
+		assert_eq!(htlc_cltv, cur_height + 186 + 40);
+		match payloads.last().unwrap() {
+			msgs::OutboundOnionPayload::TrampolineEntrypoint {
+				amt_to_forward, outgoing_cltv_value, multipath_trampoline_data, ..
+			} => {
+				assert_eq!(*amt_to_forward, 105_000);
+				assert_eq!(*outgoing_cltv_value, cur_height + 186);
+				assert_eq!(multipath_trampoline_data.as_ref().unwrap().total_msat, 105_000);
+			},
+			_ => panic!("Unexpected final outer payload"),
+		}
+
+		// The trampoline node peels its layer and learns where to forward the payment.
+		let trampoline_shared_secret =
+			SharedSecret::new(&trampoline_packet.public_key, &trampoline_secret_key);
+		let (forward, next_hop): (TrampolineForwardHopData, Option<([u8; 32], Vec<u8>)>) =
+			super::decode_next_hop(
+				trampoline_shared_secret.secret_bytes(), &trampoline_packet.hop_data,
+				trampoline_packet.hmac, Some(payment_hash), (),
+			).unwrap();
+		assert_eq!(forward.amt_to_forward, 100_000);
+		assert_eq!(forward.outgoing_cltv_value, cur_height + 42);
+		assert_eq!(forward.outgoing_node_id, recipient_node_id);
+
+		// The recipient then sees a regular final payload carrying its payment secret.
+		let (next_hop_hmac, next_hop_data) = next_hop.unwrap();
+		let trampoline_session_priv = super::compute_trampoline_session_priv(&session_priv);
+		let trampoline_keys = super::construct_trampoline_onion_keys(
+			&secp_ctx, &path, trampoline_tail, &trampoline_session_priv,
+		).unwrap();
+		assert_eq!(trampoline_keys[0].shared_secret, trampoline_shared_secret);
+		let recipient_shared_secret =
+			SharedSecret::new(&trampoline_keys[1].ephemeral_pubkey, &recipient_secret_key);
+		let node_signer = TestNodeSigner::new(recipient_secret_key);
+		let (receive, next_hop): (msgs::InboundOnionPayload, Option<([u8; 32], Vec<u8>)>) =
+			super::decode_next_hop(
+				recipient_shared_secret.secret_bytes(), &next_hop_data, next_hop_hmac,
+				Some(payment_hash), (None, &&node_signer),
+			).unwrap();
+		assert!(next_hop.is_none());
+		match receive {
+			msgs::InboundOnionPayload::Receive {
+				payment_data, sender_intended_htlc_amt_msat, cltv_expiry_height, ..
+			} => {
+				let payment_data = payment_data.unwrap();
+				assert_eq!(payment_data.payment_secret, payment_secret);
+				assert_eq!(payment_data.total_msat, 100_000);
+				assert_eq!(sender_intended_htlc_amt_msat, 100_000);
+				assert_eq!(cltv_expiry_height, cur_height + 42);
+			},
+			_ => panic!("Unexpected recipient payload"),
+		}
+	}
+
 	struct RawOnionHopData {
 		data: Vec<u8>,
 	}
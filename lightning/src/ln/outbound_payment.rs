@@ -11,7 +11,7 @@
 
 use bitcoin::hashes::Hash;
 use bitcoin::hashes::sha256::Hash as Sha256;
-use bitcoin::secp256k1::{self, Secp256k1, SecretKey};
+use bitcoin::secp256k1::{self, PublicKey, Secp256k1, SecretKey};
 
 use crate::blinded_path::{IntroductionNode, NodeIdLookUp};
 use crate::blinded_path::payment::advance_path_by_one;
@@ -19,10 +19,11 @@ use crate::events::{self, PaymentFailureReason};
 use crate::ln::types::{PaymentHash, PaymentPreimage, PaymentSecret};
 use crate::ln::channel_state::ChannelDetails;
 use crate::ln::channelmanager::{EventCompletionAction, HTLCSource, PaymentId};
+use crate::ln::msgs::LightningError;
 use crate::ln::onion_utils;
 use crate::ln::onion_utils::{DecodedOnionFailure, HTLCFailReason};
 use crate::offers::invoice::Bolt12Invoice;
-use crate::routing::router::{BlindedTail, InFlightHtlcs, Path, PaymentParameters, Route, RouteParameters, Router};
+use crate::routing::router::{BlindedTail, InFlightHtlcs, Path, Payee, PaymentParameters, Route, RouteParameters, Router, find_circular_route, find_trampoline_route};
 use crate::sign::{EntropySource, NodeSigner, Recipient};
 use crate::util::errors::APIError;
 use crate::util::logger::Logger;
@@ -54,6 +55,10 @@ pub(crate) enum PendingOutboundPayment {
 		expiration: StaleExpiration,
 		retry_strategy: Retry,
 		max_total_routing_fee_msat: Option<u64>,
+		/// The [`PaymentParameters::max_path_count_override`] to use once the invoice is received.
+		max_path_count_override: Option<u8>,
+		/// The [`PaymentParameters::min_shard_amount_msat`] to use once the invoice is received.
+		min_shard_amount_msat: Option<u64>,
 	},
 	InvoiceReceived {
 		payment_hash: PaymentHash,
@@ -80,6 +85,8 @@ pub(crate) enum PendingOutboundPayment {
 		/// Our best known block height at the time this payment was initiated.
 		starting_block_height: u32,
 		remaining_max_total_routing_fee_msat: Option<u64>,
+		/// The [`RouteParameters::max_candidate_hop_evaluations`] to use when retrying.
+		max_candidate_hop_evaluations: Option<u32>,
 	},
 	/// When a pending payment is fulfilled, we continue tracking it until all pending HTLCs have
 	/// been resolved. This ensures we don't look up pending payments in ChannelMonitors on restart
@@ -89,6 +96,11 @@ pub(crate) enum PendingOutboundPayment {
 		/// Filled in for any payment which moved to `Fulfilled` on LDK 0.0.104 or later.
 		payment_hash: Option<PaymentHash>,
 		timer_ticks_without_htlcs: u8,
+		/// The following are carried over from `Retryable` and will be `None` if the payment moved
+		/// to `Fulfilled` on LDK 0.0.123 or earlier.
+		total_msat: Option<u64>,
+		fee_paid_msat: Option<u64>,
+		starting_block_height: Option<u32>,
 	},
 	/// When we've decided to give up retrying a payment, we mark it as abandoned so we can eventually
 	/// generate a `PaymentFailed` event when all HTLCs have irrevocably failed.
@@ -97,6 +109,10 @@ pub(crate) enum PendingOutboundPayment {
 		payment_hash: PaymentHash,
 		/// Will be `None` if the payment was serialized before 0.0.115.
 		reason: Option<PaymentFailureReason>,
+		/// The following are carried over from `Retryable` and will be `None` if the payment was
+		/// abandoned on LDK 0.0.123 or earlier, or before an invoice was received.
+		total_msat: Option<u64>,
+		starting_block_height: Option<u32>,
 	},
 }
 
@@ -163,7 +179,7 @@ impl PendingOutboundPayment {
 		}
 	}
 
-	fn payment_hash(&self) -> Option<PaymentHash> {
+	pub(super) fn payment_hash(&self) -> Option<PaymentHash> {
 		match self {
 			PendingOutboundPayment::Legacy { .. } => None,
 			PendingOutboundPayment::AwaitingInvoice { .. } => None,
@@ -174,6 +190,24 @@ impl PendingOutboundPayment {
 		}
 	}
 
+	pub(super) fn total_msat(&self) -> Option<u64> {
+		match self {
+			PendingOutboundPayment::Retryable { total_msat, .. } => Some(*total_msat),
+			PendingOutboundPayment::Fulfilled { total_msat, .. } => *total_msat,
+			PendingOutboundPayment::Abandoned { total_msat, .. } => *total_msat,
+			_ => None,
+		}
+	}
+
+	pub(super) fn starting_block_height(&self) -> Option<u32> {
+		match self {
+			PendingOutboundPayment::Retryable { starting_block_height, .. } => Some(*starting_block_height),
+			PendingOutboundPayment::Fulfilled { starting_block_height, .. } => *starting_block_height,
+			PendingOutboundPayment::Abandoned { starting_block_height, .. } => *starting_block_height,
+			_ => None,
+		}
+	}
+
 	fn mark_fulfilled(&mut self) {
 		let mut session_privs = new_hash_set();
 		core::mem::swap(&mut session_privs, match self {
@@ -185,23 +219,39 @@ impl PendingOutboundPayment {
 				PendingOutboundPayment::InvoiceReceived { .. } => { debug_assert!(false); return; },
 		});
 		let payment_hash = self.payment_hash();
-		*self = PendingOutboundPayment::Fulfilled { session_privs, payment_hash, timer_ticks_without_htlcs: 0 };
+		let total_msat = self.total_msat();
+		let fee_paid_msat = match self {
+			PendingOutboundPayment::Retryable { pending_fee_msat, .. } => *pending_fee_msat,
+			PendingOutboundPayment::Fulfilled { fee_paid_msat, .. } => *fee_paid_msat,
+			_ => None,
+		};
+		let starting_block_height = self.starting_block_height();
+		*self = PendingOutboundPayment::Fulfilled {
+			session_privs, payment_hash, timer_ticks_without_htlcs: 0, total_msat, fee_paid_msat,
+			starting_block_height,
+		};
 	}
 
 	fn mark_abandoned(&mut self, reason: PaymentFailureReason) {
-		if let PendingOutboundPayment::Retryable { session_privs, payment_hash, .. } = self {
+		if let PendingOutboundPayment::Retryable {
+			session_privs, payment_hash, total_msat, starting_block_height, ..
+		} = self {
 			let mut our_session_privs = new_hash_set();
 			core::mem::swap(&mut our_session_privs, session_privs);
 			*self = PendingOutboundPayment::Abandoned {
 				session_privs: our_session_privs,
 				payment_hash: *payment_hash,
-				reason: Some(reason)
+				reason: Some(reason),
+				total_msat: Some(*total_msat),
+				starting_block_height: Some(*starting_block_height),
 			};
 		} else if let PendingOutboundPayment::InvoiceReceived { payment_hash, .. } = self {
 			*self = PendingOutboundPayment::Abandoned {
 				session_privs: new_hash_set(),
 				payment_hash: *payment_hash,
-				reason: Some(reason)
+				reason: Some(reason),
+				total_msat: None,
+				starting_block_height: None,
 			};
 		}
 	}
@@ -252,8 +302,9 @@ impl PendingOutboundPayment {
 		if insert_res {
 			if let PendingOutboundPayment::Retryable {
 				ref mut pending_amt_msat, ref mut pending_fee_msat,
-				ref mut remaining_max_total_routing_fee_msat, ..
+				ref mut remaining_max_total_routing_fee_msat, ref mut attempts, ..
 			} = self {
+					attempts.record_attempt();
 					*pending_amt_msat += path.final_value_msat();
 					let path_fee_msat = path.fee_msat();
 					if let Some(fee_msat) = pending_fee_msat.as_mut() {
@@ -349,6 +400,10 @@ pub(crate) struct PaymentAttemptsUsingTime<T: Time> {
 	/// This field is only used when retry is `Retry::Timeout` which is only build with feature std
 	#[cfg(feature = "std")]
 	first_attempted_at: T,
+	/// The time a path was last sent for this payment, used to determine stale payments in
+	/// [`OutboundPayments::abandon_stale_payments`] which is only build with feature std
+	#[cfg(feature = "std")]
+	last_attempted_at: T,
 	#[cfg(not(feature = "std"))]
 	phantom: core::marker::PhantomData<T>,
 
@@ -367,10 +422,23 @@ impl<T: Time> PaymentAttemptsUsingTime<T> {
 			count: 0,
 			#[cfg(feature = "std")]
 			first_attempted_at: T::now(),
+			#[cfg(feature = "std")]
+			last_attempted_at: T::now(),
 			#[cfg(not(feature = "std"))]
 			phantom: core::marker::PhantomData,
 		}
 	}
+
+	fn record_attempt(&mut self) {
+		#[cfg(feature = "std")] {
+			self.last_attempted_at = T::now();
+		}
+	}
+
+	#[cfg(feature = "std")]
+	fn time_since_last_attempt(&self) -> Duration {
+		T::now().duration_since(self.last_attempted_at)
+	}
 }
 
 impl<T: Time> Display for PaymentAttemptsUsingTime<T> {
@@ -798,10 +866,17 @@ impl OutboundPayments {
 	{
 		let payment_hash = invoice.payment_hash();
 		let max_total_routing_fee_msat;
+		let max_path_count_override;
+		let min_shard_amount_msat;
 		match self.pending_outbound_payments.lock().unwrap().entry(payment_id) {
 			hash_map::Entry::Occupied(entry) => match entry.get() {
-				PendingOutboundPayment::AwaitingInvoice { retry_strategy, max_total_routing_fee_msat: max_total_fee, .. } => {
+				PendingOutboundPayment::AwaitingInvoice {
+					retry_strategy, max_total_routing_fee_msat: max_total_fee,
+					max_path_count_override: max_path_count, min_shard_amount_msat: min_shard_amount, ..
+				} => {
 					max_total_routing_fee_msat = *max_total_fee;
+					max_path_count_override = *max_path_count;
+					min_shard_amount_msat = *min_shard_amount;
 					*entry.into_mut() = PendingOutboundPayment::InvoiceReceived {
 						payment_hash,
 						retry_strategy: *retry_strategy,
@@ -814,6 +889,8 @@ impl OutboundPayments {
 		};
 
 		let mut payment_params = PaymentParameters::from_bolt12_invoice(&invoice);
+		payment_params.max_path_count_override = max_path_count_override;
+		payment_params.min_shard_amount_msat = min_shard_amount_msat;
 
 		// Advance any blinded path where the introduction node is our node.
 		if let Ok(our_node_id) = node_signer.get_node_id(Recipient::Node) {
@@ -871,12 +948,14 @@ impl OutboundPayments {
 			let mut retry_id_route_params = None;
 			for (pmt_id, pmt) in outbounds.iter_mut() {
 				if pmt.is_auto_retryable_now() {
-					if let PendingOutboundPayment::Retryable { pending_amt_msat, total_msat, payment_params: Some(params), payment_hash, remaining_max_total_routing_fee_msat, .. } = pmt {
+					if let PendingOutboundPayment::Retryable { pending_amt_msat, total_msat, payment_params: Some(params), payment_hash, remaining_max_total_routing_fee_msat, max_candidate_hop_evaluations, .. } = pmt {
 						if pending_amt_msat < total_msat {
 							retry_id_route_params = Some((*payment_hash, *pmt_id, RouteParameters {
 								final_value_msat: *total_msat - *pending_amt_msat,
 								payment_params: params.clone(),
 								max_total_routing_fee_msat: *remaining_max_total_routing_fee_msat,
+								max_candidate_hop_evaluations: *max_candidate_hop_evaluations,
+								best_block_height: None,
 							}));
 							break
 						}
@@ -942,6 +1021,10 @@ impl OutboundPayments {
 			}
 		}
 
+		if route_params.payment_params.max_absolute_cltv_expiry_height.is_some() {
+			route_params.best_block_height = Some(best_block_height);
+		}
+
 		onion_utils::set_max_path_length(
 			&mut route_params, &recipient_onion, keysend_preimage, best_block_height
 		)
@@ -951,8 +1034,8 @@ impl OutboundPayments {
 				RetryableSendFailure::OnionPacketSizeExceeded
 			})?;
 
-		let mut route = router.find_route_with_id(
-			&node_signer.get_node_id(Recipient::Node).unwrap(), &route_params,
+		let mut route = find_route_for_payment(
+			router, &node_signer.get_node_id(Recipient::Node).unwrap(), &route_params,
 			Some(&first_hops.iter().collect::<Vec<_>>()), inflight_htlcs(),
 			payment_hash, payment_id,
 		).map_err(|_| {
@@ -988,7 +1071,7 @@ impl OutboundPayments {
 	}
 
 	fn find_route_and_send_payment<R: Deref, NS: Deref, ES: Deref, IH, SP, L: Deref>(
-		&self, payment_hash: PaymentHash, payment_id: PaymentId, route_params: RouteParameters,
+		&self, payment_hash: PaymentHash, payment_id: PaymentId, mut route_params: RouteParameters,
 		router: &R, first_hops: Vec<ChannelDetails>, inflight_htlcs: &IH, entropy_source: &ES,
 		node_signer: &NS, best_block_height: u32, logger: &L,
 		pending_events: &Mutex<VecDeque<(events::Event, Option<EventCompletionAction>)>>, send_payment_along_path: &SP,
@@ -1009,8 +1092,14 @@ impl OutboundPayments {
 			}
 		}
 
-		let mut route = match router.find_route_with_id(
-			&node_signer.get_node_id(Recipient::Node).unwrap(), &route_params,
+		// Check any absolute CLTV expiry limit against the current best block height, which may
+		// have moved on since we first tried to send the payment.
+		if route_params.payment_params.max_absolute_cltv_expiry_height.is_some() {
+			route_params.best_block_height = Some(best_block_height);
+		}
+
+		let mut route = match find_route_for_payment(
+			router, &node_signer.get_node_id(Recipient::Node).unwrap(), &route_params,
 			Some(&first_hops.iter().collect::<Vec<_>>()), inflight_htlcs(),
 			payment_hash, payment_id,
 		) {
@@ -1326,6 +1415,8 @@ impl OutboundPayments {
 			total_msat: route.get_total_amount(),
 			remaining_max_total_routing_fee_msat:
 				route.route_params.as_ref().and_then(|p| p.max_total_routing_fee_msat),
+			max_candidate_hop_evaluations:
+				route.route_params.as_ref().and_then(|p| p.max_candidate_hop_evaluations),
 		};
 
 		for (path, session_priv_bytes) in route.paths.iter().zip(onion_session_privs.iter()) {
@@ -1337,7 +1428,8 @@ impl OutboundPayments {
 
 	pub(super) fn add_new_awaiting_invoice(
 		&self, payment_id: PaymentId, expiration: StaleExpiration, retry_strategy: Retry,
-		max_total_routing_fee_msat: Option<u64>
+		max_total_routing_fee_msat: Option<u64>, max_path_count_override: Option<u8>,
+		min_shard_amount_msat: Option<u64>
 	) -> Result<(), ()> {
 		let mut pending_outbounds = self.pending_outbound_payments.lock().unwrap();
 		match pending_outbounds.entry(payment_id) {
@@ -1347,6 +1439,8 @@ impl OutboundPayments {
 					expiration,
 					retry_strategy,
 					max_total_routing_fee_msat,
+					max_path_count_override,
+					min_shard_amount_msat,
 				});
 
 				Ok(())
@@ -1804,6 +1898,39 @@ impl OutboundPayments {
 		}
 	}
 
+	#[cfg(feature = "std")]
+	pub(super) fn abandon_stale_payments(
+		&self, older_than: Duration,
+		pending_events: &Mutex<VecDeque<(events::Event, Option<EventCompletionAction>)>>
+	) -> Vec<PaymentId> {
+		let mut outbounds = self.pending_outbound_payments.lock().unwrap();
+		let mut pending_events = pending_events.lock().unwrap();
+		let mut abandoned_payment_ids = Vec::new();
+		outbounds.retain(|payment_id, payment| {
+			let is_stale = match payment {
+				PendingOutboundPayment::Retryable { attempts, .. } => {
+					attempts.time_since_last_attempt() >= older_than
+				},
+				_ => false,
+			};
+			// Payments with HTLCs in flight may still succeed, so we leave them alone.
+			if !is_stale || payment.remaining_parts() != 0 {
+				return true;
+			}
+			payment.mark_abandoned(PaymentFailureReason::UserAbandoned);
+			if let PendingOutboundPayment::Abandoned { payment_hash, reason, .. } = payment {
+				pending_events.push_back((events::Event::PaymentFailed {
+					payment_id: *payment_id,
+					payment_hash: *payment_hash,
+					reason: *reason,
+				}, None));
+			}
+			abandoned_payment_ids.push(*payment_id);
+			false
+		});
+		abandoned_payment_ids
+	}
+
 	#[cfg(test)]
 	pub fn has_pending_payments(&self) -> bool {
 		!self.pending_outbound_payments.lock().unwrap().is_empty()
@@ -1824,6 +1951,28 @@ pub(super) fn payment_is_probe(payment_hash: &PaymentHash, payment_id: &PaymentI
 	target_payment_hash == *payment_hash
 }
 
+/// Finds a route for the given payment via the [`Router`], handling circular payments back to
+/// ourselves (which routers refuse to find routes for) and payments via a trampoline node (for
+/// which we only route to the trampoline node) separately.
+fn find_route_for_payment<R: Deref>(
+	router: &R, payer: &PublicKey, route_params: &RouteParameters,
+	first_hops: Option<&[&ChannelDetails]>, inflight_htlcs: InFlightHtlcs,
+	payment_hash: PaymentHash, payment_id: PaymentId,
+) -> Result<Route, LightningError>
+where R::Target: Router {
+	match route_params.payment_params.payee {
+		Payee::Clear { node_id, .. } if node_id == *payer => find_circular_route(
+			router, payer, route_params, first_hops, inflight_htlcs, payment_hash, payment_id
+		),
+		Payee::Trampoline { .. } => find_trampoline_route(
+			router, payer, route_params, first_hops, inflight_htlcs, Some((payment_hash, payment_id))
+		),
+		_ => router.find_route_with_id(
+			payer, route_params, first_hops, inflight_htlcs, payment_hash, payment_id
+		),
+	}
+}
+
 /// Returns the 'probing cookie' for the given [`PaymentId`].
 fn probing_cookie_from_id(payment_id: &PaymentId, probing_cookie_secret: [u8; 32]) -> PaymentHash {
 	let mut preimage = [0u8; 64];
@@ -1840,6 +1989,9 @@ impl_writeable_tlv_based_enum_upgradable!(PendingOutboundPayment,
 		(0, session_privs, required),
 		(1, payment_hash, option),
 		(3, timer_ticks_without_htlcs, (default_value, 0)),
+		(5, total_msat, option),
+		(7, fee_paid_msat, option),
+		(9, starting_block_height, option),
 	},
 	(2, Retryable) => {
 		(0, session_privs, required),
@@ -1856,6 +2008,7 @@ impl_writeable_tlv_based_enum_upgradable!(PendingOutboundPayment,
 		(9, custom_tlvs, optional_vec),
 		(10, starting_block_height, required),
 		(11, remaining_max_total_routing_fee_msat, option),
+		(13, max_candidate_hop_evaluations, option),
 		(not_written, retry_strategy, (static_value, None)),
 		(not_written, attempts, (static_value, PaymentAttempts::new())),
 	},
@@ -1863,11 +2016,15 @@ impl_writeable_tlv_based_enum_upgradable!(PendingOutboundPayment,
 		(0, session_privs, required),
 		(1, reason, option),
 		(2, payment_hash, required),
+		(3, total_msat, option),
+		(5, starting_block_height, option),
 	},
 	(5, AwaitingInvoice) => {
 		(0, expiration, required),
 		(2, retry_strategy, required),
 		(4, max_total_routing_fee_msat, option),
+		(5, max_path_count_override, option),
+		(7, min_shard_amount_msat, option),
 	},
 	(7, InvoiceReceived) => {
 		(0, payment_hash, required),
@@ -2013,6 +2170,51 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn sets_best_block_height_for_absolute_cltv_limit() {
+		do_sets_best_block_height_for_absolute_cltv_limit(false);
+		do_sets_best_block_height_for_absolute_cltv_limit(true);
+	}
+	fn do_sets_best_block_height_for_absolute_cltv_limit(on_retry: bool) {
+		// When the payment has an absolute CLTV expiry limit, the router needs to be given our
+		// current best block height, both on the initial send and on any retries.
+		let outbound_payments = OutboundPayments::new();
+		let logger = test_utils::TestLogger::new();
+		let network_graph = Arc::new(NetworkGraph::new(Network::Testnet, &logger));
+		let scorer = RwLock::new(test_utils::TestScorer::new());
+		let router = test_utils::TestRouter::new(network_graph, &logger, &scorer);
+		let secp_ctx = Secp256k1::new();
+		let keys_manager = test_utils::TestKeysInterface::new(&[0; 32], Network::Testnet);
+		let best_block_height = 1000;
+
+		let payment_params = PaymentParameters::from_node_id(
+			PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap()), 0)
+			.with_max_absolute_cltv_expiry_height(best_block_height + 500);
+		let route_params = RouteParameters::from_payment_params_and_value(payment_params, 0);
+		let mut expected_route_params = route_params.clone();
+		expected_route_params.best_block_height = Some(best_block_height);
+		router.expect_find_route(expected_route_params,
+			Err(LightningError { err: String::new(), action: ErrorAction::IgnoreError }));
+
+		let pending_events = Mutex::new(VecDeque::new());
+		if on_retry {
+			outbound_payments.add_new_pending_payment(PaymentHash([0; 32]), RecipientOnionFields::spontaneous_empty(),
+				PaymentId([0; 32]), None, &Route { paths: vec![], route_params: None },
+				Some(Retry::Attempts(1)), Some(route_params.payment_params.clone()),
+				&&keys_manager, 0).unwrap();
+			outbound_payments.find_route_and_send_payment(
+				PaymentHash([0; 32]), PaymentId([0; 32]), route_params, &&router, vec![],
+				&|| InFlightHtlcs::new(), &&keys_manager, &&keys_manager, best_block_height, &&logger,
+				&pending_events, &|_| Ok(()));
+		} else {
+			outbound_payments.send_payment(
+				PaymentHash([0; 32]), RecipientOnionFields::spontaneous_empty(), PaymentId([0; 32]),
+				Retry::Attempts(0), route_params, &&router, vec![], || InFlightHtlcs::new(),
+				&&keys_manager, &&keys_manager, best_block_height, &&logger, &pending_events,
+				|_| Ok(())).unwrap_err();
+		}
+	}
+
 	#[test]
 	fn initial_send_payment_path_failed_evs() {
 		let outbound_payments = OutboundPayments::new();
@@ -2037,7 +2239,7 @@ mod tests {
 				fee_msat: 0,
 				cltv_expiry_delta: 0,
 				maybe_announced_channel: true,
-			}], blinded_tail: None }],
+			}], blinded_tail: None, trampoline_tail: None }],
 			route_params: Some(route_params.clone()),
 		};
 		router.expect_find_route(route_params.clone(), Ok(route.clone()));
@@ -2106,7 +2308,7 @@ mod tests {
 		assert!(!outbound_payments.has_pending_payments());
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
@@ -2132,14 +2334,14 @@ mod tests {
 
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
 
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_err()
 		);
 	}
@@ -2155,7 +2357,7 @@ mod tests {
 		assert!(!outbound_payments.has_pending_payments());
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
@@ -2181,14 +2383,14 @@ mod tests {
 
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
 
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_err()
 		);
 	}
@@ -2203,7 +2405,7 @@ mod tests {
 		assert!(!outbound_payments.has_pending_payments());
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
@@ -2237,7 +2439,7 @@ mod tests {
 
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), None
+				payment_id, expiration, Retry::Attempts(0), None, None, None
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
@@ -2301,7 +2503,7 @@ mod tests {
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
 				payment_id, expiration, Retry::Attempts(0),
-				Some(invoice.amount_msats() / 100 + 50_000)
+				Some(invoice.amount_msats() / 100 + 50_000), None, None
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
@@ -2360,9 +2562,13 @@ mod tests {
 			.sign(recipient_sign).unwrap();
 
 		let route_params = RouteParameters {
-			payment_params: PaymentParameters::from_bolt12_invoice(&invoice),
+			payment_params: PaymentParameters::from_bolt12_invoice(&invoice)
+				.with_max_path_count_override(3)
+				.with_min_shard_amount_msat(50_000),
 			final_value_msat: invoice.amount_msats(),
 			max_total_routing_fee_msat: Some(1234),
+			max_candidate_hop_evaluations: None,
+			best_block_height: None,
 		};
 		router.expect_find_route(
 			route_params.clone(),
@@ -2381,6 +2587,7 @@ mod tests {
 							}
 						],
 						blinded_tail: None,
+						trampoline_tail: None,
 					}
 				],
 				route_params: Some(route_params),
@@ -2401,7 +2608,7 @@ mod tests {
 
 		assert!(
 			outbound_payments.add_new_awaiting_invoice(
-				payment_id, expiration, Retry::Attempts(0), Some(1234)
+				payment_id, expiration, Retry::Attempts(0), Some(1234), Some(3), Some(50_000)
 			).is_ok()
 		);
 		assert!(outbound_payments.has_pending_payments());
@@ -16,14 +16,14 @@ use crate::chain::channelmonitor::{ANTI_REORG_DELAY, HTLC_FAIL_BACK_BUFFER, LATE
 use crate::sign::EntropySource;
 use crate::events::{ClosureReason, Event, HTLCDestination, MessageSendEvent, MessageSendEventsProvider, PathFailure, PaymentFailureReason, PaymentPurpose};
 use crate::ln::channel::{EXPIRE_PREV_CONFIG_TICKS, commit_tx_fee_msat, get_holder_selected_channel_reserve_satoshis, ANCHOR_OUTPUT_VALUE_SATOSHI};
-use crate::ln::channelmanager::{BREAKDOWN_TIMEOUT, MPP_TIMEOUT_TICKS, MIN_CLTV_EXPIRY_DELTA, PaymentId, PaymentSendFailure, RecentPaymentDetails, RecipientOnionFields, HTLCForwardInfo, PendingHTLCRouting, PendingAddHTLCInfo};
-use crate::ln::features::{Bolt11InvoiceFeatures, ChannelTypeFeatures};
+use crate::ln::channelmanager::{BREAKDOWN_TIMEOUT, MPP_TIMEOUT_TICKS, MIN_CLTV_EXPIRY_DELTA, MIN_FINAL_CLTV_EXPIRY_DELTA, ForwardingStats, PaymentDetails, PaymentFilter, PaymentId, PaymentSendFailure, PaymentStatus, RecentPaymentDetails, RecipientOnionFields, HTLCForwardInfo, PendingHTLCRouting, PendingAddHTLCInfo};
+use crate::ln::features::{Bolt11InvoiceFeatures, ChannelTypeFeatures, NodeFeatures};
 use crate::ln::msgs;
 use crate::ln::types::{ChannelId, PaymentHash, PaymentSecret, PaymentPreimage};
 use crate::ln::msgs::ChannelMessageHandler;
 use crate::ln::onion_utils;
-use crate::ln::outbound_payment::{IDEMPOTENCY_TIMEOUT_TICKS, Retry};
-use crate::routing::gossip::{EffectiveCapacity, RoutingFees};
+use crate::ln::outbound_payment::{IDEMPOTENCY_TIMEOUT_TICKS, ProbeSendFailure, Retry, RetryableSendFailure};
+use crate::routing::gossip::{EffectiveCapacity, NodeAlias, RoutingFees};
 use crate::routing::router::{get_route, Path, PaymentParameters, Route, Router, RouteHint, RouteHintHop, RouteHop, RouteParameters, find_route};
 use crate::routing::scoring::ChannelUsage;
 use crate::util::config::UserConfig;
@@ -1685,6 +1685,765 @@ fn abandoned_send_payment_idempotent() {
 	claim_payment(&nodes[0], &[&nodes[1]], second_payment_preimage);
 }
 
+#[test]
+#[cfg(feature = "std")]
+fn abandon_stale_payments() {
+	// Tests that `abandon_stale_payments` abandons payments which stalled without any HTLCs in
+	// flight in bulk, freeing their `PaymentId`s, while leaving payments with HTLCs in flight alone.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	create_announced_chan_between_nodes(&nodes, 1, 2);
+
+	// Once nodes[2] is disconnected, any HTLCs nodes[1] should forward to it fail back. The
+	// payments then stall waiting on a retry, which we never let `ChannelManager` attempt.
+	nodes[1].node.peer_disconnected(&nodes[2].node.get_our_node_id());
+	nodes[2].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+
+	let mut stalled_payments = Vec::new();
+	for _ in 0..3 {
+		let (route, payment_hash, payment_preimage, payment_secret) =
+			get_route_and_payment_hash!(nodes[0], nodes[2], 100_000);
+		let payment_id = PaymentId(payment_hash.0);
+		nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+			payment_id, route.route_params.clone().unwrap(), Retry::Attempts(1)).unwrap();
+		check_added_monitors!(nodes[0], 1);
+
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		// As the outbound channel isn't live, nodes[1] fails the HTLC back right away.
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+
+		let htlc_updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+		nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &htlc_updates.update_fail_htlcs[0]);
+		commitment_signed_dance!(nodes[0], nodes[1], htlc_updates.commitment_signed, false);
+
+		// Only the first failure queues a retry event, which we never handle.
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), if stalled_payments.is_empty() { 2 } else { 1 });
+		match events[0] {
+			Event::PaymentPathFailed { payment_hash: ev_payment_hash, payment_failed_permanently, .. } => {
+				assert_eq!(ev_payment_hash, payment_hash);
+				assert!(!payment_failed_permanently);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		if events.len() == 2 {
+			match events[1] {
+				Event::PendingHTLCsForwardable { .. } => {},
+				_ => panic!("Unexpected event"),
+			}
+		}
+
+		stalled_payments.push((route, payment_hash, payment_preimage, payment_secret, payment_id));
+	}
+
+	// A payment with an HTLC in flight is never considered stale.
+	let (in_flight_preimage, in_flight_payment_hash, _, in_flight_payment_id) =
+		route_payment(&nodes[0], &[&nodes[1]], 100_000);
+
+	assert!(nodes[0].node.abandon_stale_payments(Duration::from_secs(60)).is_empty());
+	SinceEpoch::advance(Duration::from_secs(61));
+
+	let mut abandoned_payment_ids = nodes[0].node.abandon_stale_payments(Duration::from_secs(60));
+	abandoned_payment_ids.sort_unstable_by_key(|payment_id| payment_id.0);
+	let mut stalled_payment_ids =
+		stalled_payments.iter().map(|(.., payment_id)| *payment_id).collect::<Vec<_>>();
+	stalled_payment_ids.sort_unstable_by_key(|payment_id| payment_id.0);
+	assert_eq!(abandoned_payment_ids, stalled_payment_ids);
+
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 3);
+	for event in events {
+		match event {
+			Event::PaymentFailed { payment_id, payment_hash, reason } => {
+				let (_, expected_payment_hash, ..) = stalled_payments.iter()
+					.find(|(.., stalled_payment_id)| *stalled_payment_id == payment_id).unwrap();
+				assert_eq!(payment_hash, *expected_payment_hash);
+				assert_eq!(reason, Some(PaymentFailureReason::UserAbandoned));
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
+	assert!(nodes[0].node.abandon_stale_payments(Duration::from_secs(60)).is_empty());
+
+	// Abandoned payments are no longer retried.
+	nodes[0].node.process_pending_htlc_forwards();
+	check_added_monitors!(nodes[0], 0);
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	assert_eq!(nodes[0].node.list_recent_payments(), vec![RecentPaymentDetails::Pending {
+		payment_id: in_flight_payment_id, payment_hash: in_flight_payment_hash, total_msat: 100_000,
+	}]);
+
+	// The PaymentIds of the abandoned payments may be reused.
+	let mut reconnect_args = ReconnectArgs::new(&nodes[1], &nodes[2]);
+	reconnect_args.send_channel_ready = (true, true);
+	reconnect_nodes(reconnect_args);
+	let (route, payment_hash, payment_preimage, payment_secret, payment_id) = &stalled_payments[0];
+	nodes[0].node.send_payment_with_route(route, *payment_hash,
+		RecipientOnionFields::secret_only(*payment_secret), *payment_id).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]], 100_000, *payment_hash, *payment_secret);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], *payment_preimage);
+
+	claim_payment(&nodes[0], &[&nodes[1]], in_flight_preimage);
+}
+
+#[test]
+fn pinned_first_hop_channels() {
+	// Tests that payments and pre-flight probes are only sent over the first hop channels given in
+	// `PaymentParameters::first_hop_channel_ids`, even if the router would otherwise prefer another.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+
+	// We alleviate the HTLC max-in-flight limit, as otherwise we'd always be limited through that.
+	let mut no_htlc_limit_config = test_default_channel_config();
+	no_htlc_limit_config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel = 100;
+
+	let user_configs = std::iter::repeat(no_htlc_limit_config).take(3).map(|c| Some(c)).collect::<Vec<Option<UserConfig>>>();
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &user_configs);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	// Setup channel topology:
+	//      -(1M:0)-
+	//    N0        N1 -(1M:0)- N2
+	//      -(100k:0)-
+	let chan_a_id = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1_000_000, 0).2;
+	let (chan_b_update, _, chan_b_id, _) =
+		create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100_000, 0);
+	create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 1_000_000, 0);
+
+	let pending_htlcs = |channel_id| nodes[0].node.list_channels().iter()
+		.find(|chan| chan.channel_id == channel_id).unwrap().pending_outbound_htlcs.len();
+
+	let amt_msat = 50_000_000;
+	let payment_params = PaymentParameters::from_node_id(nodes[2].node.get_our_node_id(), TEST_FINAL_CLTV)
+		.with_bolt11_features(nodes[2].node.bolt11_invoice_features()).unwrap();
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params.clone(), amt_msat);
+
+	// Unpinned, channel A is preferred as it has plenty of liquidity to spare.
+	let res = nodes[0].node.send_preflight_probes(route_params, Some(1)).unwrap();
+	assert_eq!(res.len(), 1);
+	assert_eq!((pending_htlcs(chan_a_id), pending_htlcs(chan_b_id)), (1, 0));
+	send_probe_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]]);
+	expect_probe_successful_events(&nodes[0], res);
+
+	// Once pinned to channel B, both probes and payments are sent over it instead.
+	let payment_params = payment_params
+		.with_first_hop_channel_ids(vec![chan_b_update.contents.short_channel_id]);
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params, amt_msat);
+
+	let res = nodes[0].node.send_preflight_probes(route_params.clone(), Some(1)).unwrap();
+	assert_eq!(res.len(), 1);
+	assert_eq!((pending_htlcs(chan_a_id), pending_htlcs(chan_b_id)), (0, 1));
+	send_probe_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]]);
+	expect_probe_successful_events(&nodes[0], res);
+
+	let (payment_preimage, payment_hash, payment_secret) = get_payment_preimage_hash!(nodes[2]);
+	nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+		PaymentId(payment_hash.0), route_params.clone(), Retry::Attempts(0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	assert_eq!((pending_htlcs(chan_a_id), pending_htlcs(chan_b_id)), (0, 1));
+	pass_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]], amt_msat, payment_hash, payment_secret);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage);
+
+	// If the pinned channel lacks the liquidity for the payment, we fail rather than using another.
+	let route_params = RouteParameters::from_payment_params_and_value(
+		route_params.payment_params, 200_000_000);
+	let (_, payment_hash, payment_secret) = get_payment_preimage_hash!(nodes[2]);
+	assert_eq!(nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+		PaymentId(payment_hash.0), route_params, Retry::Attempts(0)), Err(RetryableSendFailure::RouteNotFound));
+}
+
+fn send_hold_invoice_payment<'a, 'b, 'c>(
+	nodes: &[Node<'a, 'b, 'c>], amt_msat: u64, cltv_buffer: u16, payment_id: PaymentId,
+	payment_hash: PaymentHash, payment_secret: PaymentSecret,
+) {
+	let payee = nodes.last().unwrap();
+	let min_final_cltv_expiry_delta = (cltv_buffer + MIN_FINAL_CLTV_EXPIRY_DELTA) as u32;
+	let payment_params = PaymentParameters::from_node_id(payee.node.get_our_node_id(), min_final_cltv_expiry_delta)
+		.with_bolt11_features(payee.node.bolt11_invoice_features()).unwrap();
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params, amt_msat);
+	nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+		payment_id, route_params, Retry::Attempts(0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+}
+
+fn do_test_hold_invoice(settle: bool) {
+	// Tests that payments to hold invoices are only claimed once settled, failing them back if
+	// cancelled instead, including any further HTLCs paying a cancelled invoice.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	// The buffer must leave sufficient time to fail the payment back before it expires.
+	assert!(nodes[1].node.create_inbound_payment_for_hold(None, 3600, MIN_CLTV_EXPIRY_DELTA - 1).is_err());
+
+	let amt_msat = 100_000;
+	let (payment_hash, payment_secret) =
+		nodes[1].node.create_inbound_payment_for_hold(Some(amt_msat), 3600, MIN_CLTV_EXPIRY_DELTA).unwrap();
+	let payment_preimage = nodes[1].node.get_payment_preimage(payment_hash, payment_secret).unwrap();
+	send_hold_invoice_payment(&nodes, amt_msat, MIN_CLTV_EXPIRY_DELTA, PaymentId(payment_hash.0),
+		payment_hash, payment_secret);
+
+	// Even though we know the preimage, it isn't handed out as the payment requires a manual claim.
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let ev = events.pop().unwrap();
+	let claimable_event = pass_along_path(&nodes[0], &[&nodes[1]], amt_msat, payment_hash,
+		Some(payment_secret), ev, true, None).unwrap();
+	match claimable_event {
+		Event::PaymentClaimable { requires_manual_claim, purpose, .. } => {
+			assert!(requires_manual_claim);
+			assert_eq!(purpose.preimage(), None);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	assert!(matches!(nodes[1].node.settle_held_payment(payment_hash, PaymentPreimage([42; 32])),
+		Err(APIError::APIMisuseError { .. })));
+	if settle {
+		nodes[1].node.settle_held_payment(payment_hash, payment_preimage).unwrap();
+		let expected_route: &[&[&Node]] = &[&[&nodes[1]]];
+		pass_claimed_payment_along_route(ClaimAlongRouteArgs::new(&nodes[0], expected_route, payment_preimage));
+		expect_payment_sent!(nodes[0], payment_preimage);
+
+		// Once settled, the payment is no longer claimable.
+		assert!(nodes[1].node.settle_held_payment(payment_hash, payment_preimage).is_err());
+	} else {
+		nodes[1].node.cancel_held_payment(payment_hash).unwrap();
+		expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1],
+			[HTLCDestination::FailedPayment { payment_hash }]);
+		pass_failed_payment_back(&nodes[0], &[&[&nodes[1]]], false, payment_hash,
+			PaymentFailureReason::RecipientRejected);
+		assert!(nodes[1].node.settle_held_payment(payment_hash, payment_preimage).is_err());
+
+		// Paying the cancelled invoice again fails immediately.
+		send_hold_invoice_payment(&nodes, amt_msat, MIN_CLTV_EXPIRY_DELTA, PaymentId([42; 32]),
+			payment_hash, payment_secret);
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+		expect_pending_htlcs_forwardable!(nodes[1]);
+		expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1],
+			[HTLCDestination::FailedPayment { payment_hash }]);
+		pass_failed_payment_back(&nodes[0], &[&[&nodes[1]]], false, payment_hash,
+			PaymentFailureReason::RecipientRejected);
+	}
+}
+
+#[test]
+fn test_hold_invoice() {
+	do_test_hold_invoice(true);
+	do_test_hold_invoice(false);
+}
+
+#[test]
+fn hold_invoice_fails_back_before_expiry() {
+	// Tests that payments to hold invoices which are neither settled nor cancelled are failed back
+	// automatically once the chain tip is within the invoice's CLTV buffer of the HTLCs' expiry.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	create_announced_chan_between_nodes(&nodes, 1, 2);
+
+	let amt_msat = 100_000;
+	let cltv_buffer = MIN_CLTV_EXPIRY_DELTA + 10;
+	let (payment_hash, payment_secret) =
+		nodes[2].node.create_inbound_payment_for_hold(Some(amt_msat), 3600, cltv_buffer).unwrap();
+	let payment_preimage = nodes[2].node.get_payment_preimage(payment_hash, payment_secret).unwrap();
+	send_hold_invoice_payment(&nodes, amt_msat, cltv_buffer, PaymentId(payment_hash.0),
+		payment_hash, payment_secret);
+
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let ev = events.pop().unwrap();
+	let claimable_event = pass_along_path(&nodes[0], &[&nodes[1], &nodes[2]], amt_msat, payment_hash,
+		Some(payment_secret), ev, true, None).unwrap();
+	let cltv_expiry = nodes[2].node.list_channels().iter()
+		.flat_map(|chan| chan.pending_inbound_htlcs.iter())
+		.map(|htlc| htlc.cltv_expiry).next().unwrap();
+	let claim_deadline = match claimable_event {
+		Event::PaymentClaimable { claim_deadline, requires_manual_claim, .. } => {
+			assert!(requires_manual_claim);
+			claim_deadline.unwrap()
+		},
+		_ => panic!("Unexpected event"),
+	};
+	assert_eq!(claim_deadline, cltv_expiry - cltv_buffer as u32);
+
+	// Right up until the claim deadline, which is well before the HTLC would be failed back for a
+	// regular payment, the payment is held.
+	assert!(claim_deadline < cltv_expiry - HTLC_FAIL_BACK_BUFFER);
+	connect_blocks(&nodes[2], claim_deadline - nodes[2].best_block_info().1 - 1);
+	assert!(nodes[2].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[2].node.get_and_clear_pending_msg_events().is_empty());
+
+	connect_blocks(&nodes[2], 1);
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[2],
+		[HTLCDestination::FailedPayment { payment_hash }]);
+	pass_failed_payment_back(&nodes[0], &[&[&nodes[1], &nodes[2]]], false, payment_hash,
+		PaymentFailureReason::RecipientRejected);
+
+	// Having been failed back, the payment can no longer be settled.
+	assert!(nodes[2].node.settle_held_payment(payment_hash, payment_preimage).is_err());
+}
+
+fn advance_past_inbound_payment_expiry(node: &Node, invoice_expiry_delta_secs: u32) {
+	// Inbound payments are given an extra two hours on top of their expiry to account for block
+	// header timestamp inaccuracy.
+	let highest_seen_timestamp = node.blocks.lock().unwrap().iter()
+		.map(|(block, _)| block.header.time).max().unwrap();
+	let time = highest_seen_timestamp + invoice_expiry_delta_secs + 7200 + 1;
+	connect_block(node, &create_dummy_block(node.best_block_hash(), time, Vec::new()));
+}
+
+#[test]
+fn test_inbound_payment_expiry() {
+	// Tests that unpaid inbound payments are purged once expired, generating an
+	// `Event::InvoiceExpired` unless they were explicitly removed beforehand.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let (payment_hash, _) = nodes[1].node.create_inbound_payment(Some(100_000), 60, None).unwrap();
+	let (removed_payment_hash, _) =
+		nodes[1].node.create_inbound_payment(Some(100_000), 60, None).unwrap();
+
+	nodes[1].node.remove_inbound_payment(removed_payment_hash).unwrap();
+	assert!(matches!(nodes[1].node.remove_inbound_payment(removed_payment_hash),
+		Err(APIError::APIMisuseError { .. })));
+	assert!(matches!(nodes[1].node.remove_inbound_payment(PaymentHash([42; 32])),
+		Err(APIError::APIMisuseError { .. })));
+
+	// Nothing expires until the invoice's expiry has passed.
+	nodes[1].node.timer_tick_occurred();
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+	advance_past_inbound_payment_expiry(&nodes[1], 60);
+	nodes[1].node.timer_tick_occurred();
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::InvoiceExpired { payment_hash: expired_payment_hash } => {
+			assert_eq!(expired_payment_hash, payment_hash);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	// Once purged, the payments are no longer tracked.
+	nodes[1].node.timer_tick_occurred();
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[1].node.remove_inbound_payment(payment_hash).is_err());
+	assert!(nodes[1].node.remove_inbound_payment(removed_payment_hash).is_err());
+}
+
+fn do_test_inbound_payment_expiry_race(process_htlc_first: bool) {
+	// Tests the race between an HTLC paying an invoice arriving and the invoice being purged as
+	// expired in the same timer tick. If the HTLC is handled first, the payment is claimable and
+	// thus never expires, otherwise the HTLC is failed back once the invoice has expired.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let amt_msat = 100_000;
+	let (payment_hash, payment_secret) =
+		nodes[1].node.create_inbound_payment(Some(amt_msat), 60, None).unwrap();
+	let payment_preimage = nodes[1].node.get_payment_preimage(payment_hash, payment_secret).unwrap();
+	let payment_params = PaymentParameters::from_node_id(nodes[1].node.get_our_node_id(), TEST_FINAL_CLTV)
+		.with_bolt11_features(nodes[1].node.bolt11_invoice_features()).unwrap();
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params, amt_msat);
+	nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+		PaymentId(payment_hash.0), route_params, Retry::Attempts(0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+
+	if process_htlc_first {
+		expect_pending_htlcs_forwardable!(nodes[1]);
+		expect_payment_claimable!(nodes[1], payment_hash, payment_secret, amt_msat,
+			Some(payment_preimage), nodes[1].node.get_our_node_id());
+	}
+
+	advance_past_inbound_payment_expiry(&nodes[1], 60);
+	nodes[1].node.timer_tick_occurred();
+
+	if process_htlc_first {
+		// The payment is pending claim, so it neither expires nor can be removed.
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+		assert!(matches!(nodes[1].node.remove_inbound_payment(payment_hash),
+			Err(APIError::APIMisuseError { .. })));
+		claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+	} else {
+		let mut events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 2);
+		let expired_idx = events.iter()
+			.position(|ev| matches!(ev, Event::InvoiceExpired { .. })).unwrap();
+		match events.remove(expired_idx) {
+			Event::InvoiceExpired { payment_hash: expired_payment_hash } => {
+				assert_eq!(expired_payment_hash, payment_hash);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		match events[0] {
+			Event::PendingHTLCsForwardable { .. } => {},
+			_ => panic!("Unexpected event"),
+		}
+		nodes[1].node.process_pending_htlc_forwards();
+		expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1],
+			[HTLCDestination::FailedPayment { payment_hash }]);
+		pass_failed_payment_back(&nodes[0], &[&[&nodes[1]]], false, payment_hash,
+			PaymentFailureReason::RecipientRejected);
+	}
+
+	// Either way, the payment is no longer tracked afterwards.
+	nodes[1].node.timer_tick_occurred();
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[1].node.remove_inbound_payment(payment_hash).is_err());
+}
+
+#[test]
+fn test_inbound_payment_expiry_race() {
+	do_test_inbound_payment_expiry_race(true);
+	do_test_inbound_payment_expiry_race(false);
+}
+
+#[test]
+fn test_remove_inbound_payment() {
+	// Tests that inbound payments can't be removed while HTLCs paying them are pending claim, and
+	// that once removed, any further HTLCs paying them are failed back.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let amt_msat = 100_000;
+	let (payment_hash, payment_secret) =
+		nodes[1].node.create_inbound_payment(Some(amt_msat), 3600, None).unwrap();
+	let payment_preimage = nodes[1].node.get_payment_preimage(payment_hash, payment_secret).unwrap();
+	let send_htlc = |payment_id| {
+		let payment_params = PaymentParameters::from_node_id(nodes[1].node.get_our_node_id(), TEST_FINAL_CLTV)
+			.with_bolt11_features(nodes[1].node.bolt11_invoice_features()).unwrap();
+		let route_params = RouteParameters::from_payment_params_and_value(payment_params, amt_msat);
+		nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+			payment_id, route_params, Retry::Attempts(0)).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+		expect_pending_htlcs_forwardable!(nodes[1]);
+	};
+
+	send_htlc(PaymentId(payment_hash.0));
+	expect_payment_claimable!(nodes[1], payment_hash, payment_secret, amt_msat,
+		Some(payment_preimage), nodes[1].node.get_our_node_id());
+	assert!(matches!(nodes[1].node.remove_inbound_payment(payment_hash),
+		Err(APIError::APIMisuseError { .. })));
+
+	nodes[1].node.fail_htlc_backwards(&payment_hash);
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1],
+		[HTLCDestination::FailedPayment { payment_hash }]);
+	pass_failed_payment_back(&nodes[0], &[&[&nodes[1]]], false, payment_hash,
+		PaymentFailureReason::RecipientRejected);
+
+	// With no HTLCs pending claim, the payment can now be removed, failing any further HTLCs.
+	nodes[1].node.remove_inbound_payment(payment_hash).unwrap();
+	send_htlc(PaymentId([42; 32]));
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1],
+		[HTLCDestination::FailedPayment { payment_hash }]);
+	pass_failed_payment_back(&nodes[0], &[&[&nodes[1]]], false, payment_hash,
+		PaymentFailureReason::RecipientRejected);
+
+	// A removed payment is never reported as expired.
+	advance_past_inbound_payment_expiry(&nodes[1], 3600);
+	nodes[1].node.timer_tick_occurred();
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+}
+
+#[test]
+fn test_forwarding_history() {
+	// Tests that completed forwards are kept in a bounded history, which is persisted with the
+	// `ChannelManager`, and aggregated per channel.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let persister;
+	let new_chain_monitor;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes_1_deserialized;
+	let mut nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1);
+	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2);
+
+	send_payment(&nodes[0], &[&nodes[1], &nodes[2]], 1_000_000);
+	send_payment(&nodes[0], &[&nodes[1], &nodes[2]], 2_000_000);
+	send_payment(&nodes[2], &[&nodes[1], &nodes[0]], 500_000);
+
+	let history = nodes[1].node.forwarding_history(None, usize::MAX);
+	assert_eq!(history.len(), 3);
+	for (forward, amt_msat) in history.iter().zip([1_000_000, 2_000_000, 500_000]) {
+		assert_eq!(forward.outbound_amount_forwarded_msat, Some(amt_msat));
+		assert_eq!(forward.total_fee_earned_msat, Some(1000));
+		assert_eq!(forward.skimmed_fee_msat, None);
+		assert!(!forward.claim_from_onchain_tx);
+	}
+	assert_eq!(history[0].prev_channel_id, chan_1.2);
+	assert_eq!(history[0].next_channel_id, chan_2.2);
+	assert_eq!(history[0].prev_short_channel_id, chan_1.0.contents.short_channel_id);
+	assert_eq!(history[0].next_short_channel_id, Some(chan_2.0.contents.short_channel_id));
+	assert_eq!(history[2].prev_channel_id, chan_2.2);
+	assert_eq!(history[2].next_channel_id, chan_1.2);
+
+	// The most recent forwards are returned, oldest first.
+	assert_eq!(nodes[1].node.forwarding_history(None, 2), history[1..].to_vec());
+	assert_eq!(nodes[1].node.forwarding_history(Some(history[2].timestamp), usize::MAX).last(), history.last());
+	assert!(nodes[1].node.forwarding_history(Some(history[2].timestamp + 1), usize::MAX).is_empty());
+
+	let stats = nodes[1].node.forwarding_stats_per_channel();
+	assert_eq!(stats.len(), 2);
+	assert_eq!(stats[&chan_1.2], ForwardingStats {
+		inbound_forwards: 2, outbound_forwards: 1, outbound_amount_forwarded_msat: 500_000,
+		fee_earned_msat: 1000,
+	});
+	assert_eq!(stats[&chan_2.2], ForwardingStats {
+		inbound_forwards: 1, outbound_forwards: 2, outbound_amount_forwarded_msat: 3_000_000,
+		fee_earned_msat: 2000,
+	});
+
+	// The history survives a restart, though only the most recent forwards are kept if the
+	// configured history size was reduced.
+	let mut config = test_default_channel_config();
+	config.max_forwarding_history_entries = 2;
+	let chan_1_monitor_serialized = get_monitor!(nodes[1], chan_1.2).encode();
+	let chan_2_monitor_serialized = get_monitor!(nodes[1], chan_2.2).encode();
+	reload_node!(nodes[1], config, &nodes[1].node.encode(),
+		&[&chan_1_monitor_serialized, &chan_2_monitor_serialized], persister, new_chain_monitor,
+		nodes_1_deserialized);
+	assert_eq!(nodes[1].node.forwarding_history(None, usize::MAX), history[1..].to_vec());
+}
+
+#[test]
+fn test_rebalance_channels() {
+	// Tests rebalancing between two of our channels by paying ourselves around a square of nodes,
+	// with the inbound and outbound sides of the rebalance being handled internally.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1);
+	create_announced_chan_between_nodes(&nodes, 1, 2);
+	create_announced_chan_between_nodes(&nodes, 2, 3);
+	let chan_4 = create_announced_chan_between_nodes(&nodes, 3, 0);
+	let chan_5 = create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let amt_msat = 1_000_000;
+	match nodes[0].node.rebalance_channels(&chan_1.2, &chan_5.2, amt_msat, 10_000, Retry::Attempts(0)) {
+		Err(APIError::APIMisuseError { err }) => assert!(err.contains("same counterparty")),
+		res => panic!("Unexpected result {:?}", res),
+	}
+	match nodes[0].node.rebalance_channels(&chan_1.2, &ChannelId([42; 32]), amt_msat, 10_000, Retry::Attempts(0)) {
+		Err(APIError::ChannelUnavailable { .. }) => {},
+		res => panic!("Unexpected result {:?}", res),
+	}
+	// Each of the three intermediate nodes charges a 1000 msat fee.
+	match nodes[0].node.rebalance_channels(&chan_1.2, &chan_4.2, amt_msat, 2_999, Retry::Attempts(0)) {
+		Err(APIError::InvalidRoute { .. }) => {},
+		res => panic!("Unexpected result {:?}", res),
+	}
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	let channel_capacities = |node: &Node| {
+		let channels = node.node.list_channels();
+		let from = channels.iter().find(|c| c.channel_id == chan_1.2).unwrap().outbound_capacity_msat;
+		let to = channels.iter().find(|c| c.channel_id == chan_4.2).unwrap().inbound_capacity_msat;
+		(from, to)
+	};
+	let (from_capacity_before, to_capacity_before) = channel_capacities(&nodes[0]);
+
+	let payment_id = nodes[0].node.rebalance_channels(&chan_1.2, &chan_4.2, amt_msat, 3_000, Retry::Attempts(0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_hash = PaymentHash(payment_id.0);
+	// The rebalance is claimed as soon as it is received, without a `PaymentClaimable` event.
+	pass_along_path(&nodes[0], &[&nodes[1], &nodes[2], &nodes[3], &nodes[0]], amt_msat, payment_hash,
+		None, events.pop().unwrap(), false, None);
+	check_added_monitors!(nodes[0], 1);
+
+	let path = [&nodes[0], &nodes[1], &nodes[2], &nodes[3], &nodes[0]];
+	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[3].node.get_our_node_id());
+	for idx in (1..4).rev() {
+		let (prev_node, node, next_node) = (path[idx - 1], path[idx], path[idx + 1]);
+		node.node.handle_update_fulfill_htlc(&next_node.node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+		expect_payment_forwarded!(node, prev_node, next_node, Some(1000), false, false);
+		check_added_monitors!(node, 1);
+		let next_updates = get_htlc_update_msgs!(node, prev_node.node.get_our_node_id());
+		commitment_signed_dance!(node, next_node, updates.commitment_signed, false);
+		updates = next_updates;
+	}
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	check_added_monitors!(nodes[0], 1);
+	match &events[0] {
+		Event::RebalanceComplete {
+			payment_id: id, from_channel_id, to_channel_id, amount_msat, fee_paid_msat, failure_reason,
+		} => {
+			assert_eq!(*id, payment_id);
+			assert_eq!(*from_channel_id, chan_1.2);
+			assert_eq!(*to_channel_id, chan_4.2);
+			assert_eq!(*amount_msat, amt_msat);
+			assert_eq!(*fee_paid_msat, Some(3000));
+			assert!(failure_reason.is_none());
+		},
+		ev => panic!("Unexpected event {:?}", ev),
+	}
+	match &events[1] {
+		Event::PaymentPathSuccessful { payment_id: id, .. } => assert_eq!(*id, payment_id),
+		ev => panic!("Unexpected event {:?}", ev),
+	}
+
+	let (from_capacity_after, to_capacity_after) = channel_capacities(&nodes[0]);
+	assert_eq!(from_capacity_before - from_capacity_after, amt_msat + 3000);
+	assert_eq!(to_capacity_before - to_capacity_after, amt_msat);
+}
+
+#[test]
+fn test_trampoline_payment_send() {
+	// Tests that trampoline payments are only routed via trampoline nodes which signal support for
+	// trampoline routing, and that we hand the trampoline node the payment amount plus its fee.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	create_announced_chan_between_nodes(&nodes, 1, 2);
+	create_announced_chan_between_nodes(&nodes, 2, 3);
+
+	let amt_msat = 100_000;
+	let max_trampoline_fee_msat = 5_000;
+	let payment_params = PaymentParameters::trampoline(nodes[2].node.get_our_node_id(),
+		nodes[3].node.get_our_node_id(), max_trampoline_fee_msat, 144);
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params, amt_msat);
+	let (_, payment_hash, payment_secret) = get_payment_preimage_hash!(nodes[3]);
+
+	// nodes[2] doesn't signal trampoline support, so we refuse to route through it.
+	match nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+		PaymentId(payment_hash.0), route_params.clone(), Retry::Attempts(0))
+	{
+		Err(RetryableSendFailure::RouteNotFound) => {},
+		res => panic!("Unexpected result {:?}", res),
+	}
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	let mut features = NodeFeatures::empty();
+	features.set_trampoline_routing_optional();
+	nodes[0].network_graph.update_node_from_unsigned_announcement(&msgs::UnsignedNodeAnnouncement {
+		features,
+		timestamp: 1,
+		node_id: NodeId::from_pubkey(&nodes[2].node.get_our_node_id()),
+		rgb: [0; 3],
+		alias: NodeAlias([0; 32]),
+		addresses: Vec::new(),
+		excess_address_data: Vec::new(),
+		excess_data: Vec::new(),
+	}).unwrap();
+
+	nodes[0].node.send_payment(payment_hash, RecipientOnionFields::secret_only(payment_secret),
+		PaymentId([42; 32]), route_params, Retry::Attempts(0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let ev = SendEvent::from_event(events.pop().unwrap());
+	// nodes[1] charges a 1000 msat fee to forward to nodes[2].
+	assert_eq!(ev.msgs[0].amount_msat, amt_msat + max_trampoline_fee_msat + 1000);
+
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &ev.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], &ev.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let ev = SendEvent::from_node(&nodes[1]);
+	assert_eq!(ev.msgs[0].amount_msat, amt_msat + max_trampoline_fee_msat);
+
+	// nodes[2] can't actually forward trampoline payments, so it fails the HTLC back, which we
+	// treat as a permanent failure of the payment.
+	nodes[2].node.handle_update_add_htlc(&nodes[1].node.get_our_node_id(), &ev.msgs[0]);
+	commitment_signed_dance!(nodes[2], nodes[1], &ev.commitment_msg, false, true);
+	let updates = get_htlc_update_msgs!(nodes[2], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_fail_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, true);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	expect_payment_failed_conditions(&nodes[0], payment_hash, true,
+		PaymentFailedConditions::new().expected_htlc_error_data(0x4000 | 22, &[0; 0]));
+}
+
+#[test]
+fn preflight_probes_trampoline_payee() {
+	// Tests that pre-flight probes for a trampoline payee stop at the trampoline node, which also
+	// has to signal support for trampoline routing.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	create_announced_chan_between_nodes(&nodes, 1, 2);
+	create_announced_chan_between_nodes(&nodes, 2, 3);
+
+	let payment_params = PaymentParameters::trampoline(nodes[2].node.get_our_node_id(),
+		nodes[3].node.get_our_node_id(), 5_000, 144);
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params, 100_000);
+
+	match nodes[0].node.send_preflight_probes(route_params.clone(), None) {
+		Err(ProbeSendFailure::RouteNotFound) => {},
+		res => panic!("Unexpected result {:?}", res),
+	}
+
+	let mut features = NodeFeatures::empty();
+	features.set_trampoline_routing_optional();
+	nodes[0].network_graph.update_node_from_unsigned_announcement(&msgs::UnsignedNodeAnnouncement {
+		features,
+		timestamp: 1,
+		node_id: NodeId::from_pubkey(&nodes[2].node.get_our_node_id()),
+		rgb: [0; 3],
+		alias: NodeAlias([0; 32]),
+		addresses: Vec::new(),
+		excess_address_data: Vec::new(),
+		excess_data: Vec::new(),
+	}).unwrap();
+
+	let res = nodes[0].node.send_preflight_probes(route_params, None).unwrap();
+	assert_eq!(res.len(), 1);
+
+	send_probe_along_route(&nodes[0], &[&[&nodes[1], &nodes[2]]]);
+	expect_probe_successful_events(&nodes[0], res);
+
+	assert!(!nodes[0].node.has_pending_payments());
+}
+
 #[derive(PartialEq)]
 enum InterceptTest {
 	Forward,
@@ -1814,6 +2573,88 @@ fn test_trivial_inflight_htlc_tracking(){
 	assert_eq!(pending_payments.len(), 0);
 }
 
+#[test]
+fn test_list_payments_with_filter() {
+	// Tests filtering and paging through recent payments via `list_payments_with_filter`.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	create_announced_chan_between_nodes(&nodes, 1, 2);
+
+	let start_height = nodes[0].best_block_info().1;
+	let (preimage_a, hash_a, _, id_a) = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 100_000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], preimage_a);
+
+	for node in nodes.iter() {
+		connect_blocks(node, 2);
+	}
+	let (preimage_b, hash_b, _, id_b) = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 200_000);
+	let (preimage_c, hash_c, _, id_c) = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 300_000);
+	nodes[0].node.abandon_payment(id_c);
+
+	// Forwarding via nodes[1] costs its default base fee of 1 sat.
+	let details_a = PaymentDetails {
+		payment_id: id_a, payment_hash: Some(hash_a), status: PaymentStatus::Fulfilled,
+		amount_msat: Some(100_000), fee_paid_msat: Some(1_000), starting_block_height: Some(start_height),
+	};
+	let details_b = PaymentDetails {
+		payment_id: id_b, payment_hash: Some(hash_b), status: PaymentStatus::Pending,
+		amount_msat: Some(200_000), fee_paid_msat: None, starting_block_height: Some(start_height + 2),
+	};
+	let details_c = PaymentDetails {
+		payment_id: id_c, payment_hash: Some(hash_c), status: PaymentStatus::Abandoned,
+		amount_msat: Some(300_000), fee_paid_msat: None, starting_block_height: Some(start_height + 2),
+	};
+	assert_eq!(nodes[0].node.payment_details(id_a), Some(details_a.clone()));
+	assert_eq!(nodes[0].node.payment_details(id_b), Some(details_b.clone()));
+	assert_eq!(nodes[0].node.payment_details(id_c), Some(details_c.clone()));
+	assert_eq!(nodes[0].node.payment_details(PaymentId([42; 32])), None);
+
+	// All payments are listed by default, ordered by their id.
+	let mut all_payments = vec![details_a.clone(), details_b.clone(), details_c.clone()];
+	all_payments.sort_unstable_by_key(|details| details.payment_id.0);
+	assert_eq!(nodes[0].node.list_payments_with_filter(PaymentFilter::default()), all_payments);
+
+	let filter = PaymentFilter { status: Some(PaymentStatus::Pending), ..Default::default() };
+	assert_eq!(nodes[0].node.list_payments_with_filter(filter), vec![details_b.clone()]);
+	let filter = PaymentFilter { status: Some(PaymentStatus::AwaitingInvoice), ..Default::default() };
+	assert!(nodes[0].node.list_payments_with_filter(filter).is_empty());
+
+	let filter = PaymentFilter { max_starting_block_height: Some(start_height), ..Default::default() };
+	assert_eq!(nodes[0].node.list_payments_with_filter(filter), vec![details_a.clone()]);
+	let filter = PaymentFilter {
+		min_starting_block_height: Some(start_height + 1),
+		max_starting_block_height: Some(start_height + 2),
+		..Default::default()
+	};
+	let mut later_payments = vec![details_b.clone(), details_c.clone()];
+	later_payments.sort_unstable_by_key(|details| details.payment_id.0);
+	assert_eq!(nodes[0].node.list_payments_with_filter(filter), later_payments);
+
+	let filter = PaymentFilter { payment_id_prefix: id_b.0[..4].to_vec(), ..Default::default() };
+	assert_eq!(nodes[0].node.list_payments_with_filter(filter), vec![details_b.clone()]);
+
+	let filter = PaymentFilter { offset: 1, limit: Some(1), ..Default::default() };
+	assert_eq!(nodes[0].node.list_payments_with_filter(filter), vec![all_payments[1].clone()]);
+	let filter = PaymentFilter { offset: 1, ..Default::default() };
+	assert_eq!(nodes[0].node.list_payments_with_filter(filter), all_payments[1..].to_vec());
+	let filter = PaymentFilter { offset: 3, ..Default::default() };
+	assert!(nodes[0].node.list_payments_with_filter(filter).is_empty());
+
+	// Once fulfilled, the payments retain their amount and starting height.
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], preimage_b);
+	let details_b = PaymentDetails { status: PaymentStatus::Fulfilled, fee_paid_msat: Some(1_000), ..details_b };
+	assert_eq!(nodes[0].node.payment_details(id_b), Some(details_b));
+
+	// As we abandoned the payment, we don't know the fee paid when it's claimed anyway.
+	do_claim_payment_along_route(ClaimAlongRouteArgs::new(&nodes[0], &[&[&nodes[1], &nodes[2]]], preimage_c));
+	expect_payment_sent(&nodes[0], preimage_c, Some(None), true, true);
+	let details_c = PaymentDetails { status: PaymentStatus::Fulfilled, ..details_c };
+	assert_eq!(nodes[0].node.payment_details(id_c), Some(details_c));
+}
+
 #[test]
 fn test_holding_cell_inflight_htlcs() {
 	let chanmon_cfgs = create_chanmon_cfgs(2);
@@ -1927,9 +2768,11 @@ fn do_test_intercepted_payment(test: InterceptTest) {
 	assert_eq!(events.len(), 1);
 	let (intercept_id, expected_outbound_amount_msat) = match events[0] {
 		crate::events::Event::HTLCIntercepted {
-			intercept_id, expected_outbound_amount_msat, payment_hash: pmt_hash, inbound_amount_msat, requested_next_hop_scid: short_channel_id
+			intercept_id, expected_outbound_amount_msat, payment_hash: pmt_hash, inbound_amount_msat, requested_next_hop_scid: short_channel_id,
+			max_skimmed_fee_msat,
 		} => {
 			assert_eq!(pmt_hash, payment_hash);
+			assert_eq!(max_skimmed_fee_msat, expected_outbound_amount_msat);
 			assert_eq!(inbound_amount_msat, route.get_total_amount() + route.get_total_fees());
 			assert_eq!(short_channel_id, intercept_scid);
 			(intercept_id, expected_outbound_amount_msat)
@@ -2043,6 +2886,132 @@ fn do_test_intercepted_payment(test: InterceptTest) {
 	}
 }
 
+#[test]
+fn intercepted_payment_cltv_expiry_delta_override() {
+	// Test that an LSP can re-inspect an intercepted HTLC after a restart and forward it over a
+	// 0-conf channel opened after interception, overriding the CLTV expiry delta it advertised in
+	// the intercept route hint.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let persister;
+	let new_chain_monitor;
+
+	let mut zero_conf_chan_config = test_default_channel_config();
+	zero_conf_chan_config.manually_accept_inbound_channels = true;
+	let mut intercept_forwards_config = test_default_channel_config();
+	intercept_forwards_config.accept_intercept_htlcs = true;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, Some(intercept_forwards_config), Some(zero_conf_chan_config)]);
+	let nodes_1_deserialized;
+
+	let mut nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let scorer = test_utils::TestScorer::new();
+	let random_seed_bytes = chanmon_cfgs[0].keys_manager.get_secure_random_bytes();
+
+	let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	let amt_msat = 100_000;
+	let advertised_cltv_expiry_delta = MIN_CLTV_EXPIRY_DELTA + 24;
+	let intercept_scid = nodes[1].node.get_intercept_scid();
+	let payment_params = PaymentParameters::from_node_id(nodes[2].node.get_our_node_id(), TEST_FINAL_CLTV)
+		.with_route_hints(vec![
+			RouteHint(vec![RouteHintHop {
+				src_node_id: nodes[1].node.get_our_node_id(),
+				short_channel_id: intercept_scid,
+				fees: RoutingFees {
+					base_msat: 1000,
+					proportional_millionths: 0,
+				},
+				cltv_expiry_delta: advertised_cltv_expiry_delta,
+				htlc_minimum_msat: None,
+				htlc_maximum_msat: None,
+			}])
+		]).unwrap()
+		.with_bolt11_features(nodes[2].node.bolt11_invoice_features()).unwrap();
+	let route_params = RouteParameters::from_payment_params_and_value(payment_params, amt_msat);
+	let route = get_route(
+		&nodes[0].node.get_our_node_id(), &route_params, &nodes[0].network_graph.read_only(), None,
+		nodes[0].logger, &scorer, &Default::default(), &random_seed_bytes
+	).unwrap();
+
+	let (payment_hash, payment_secret) = nodes[2].node.create_inbound_payment(Some(amt_msat), 60 * 60, None).unwrap();
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	let incoming_cltv_expiry = payment_event.msgs[0].cltv_expiry;
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], &payment_event.commitment_msg, false, true);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let (intercept_id, expected_outbound_amount_msat) = match events[0] {
+		Event::HTLCIntercepted { intercept_id, expected_outbound_amount_msat, .. } =>
+			(intercept_id, expected_outbound_amount_msat),
+		_ => panic!()
+	};
+
+	let details = nodes[1].node.get_intercepted_htlc(intercept_id).unwrap();
+	assert_eq!(details.intercept_id, intercept_id);
+	assert_eq!(details.requested_next_hop_scid, intercept_scid);
+	assert_eq!(details.payment_hash, payment_hash);
+	assert_eq!(details.inbound_amount_msat, amt_msat + 1000);
+	assert_eq!(details.expected_outbound_amount_msat, expected_outbound_amount_msat);
+	assert_eq!(details.incoming_cltv_expiry, Some(incoming_cltv_expiry));
+	assert_eq!(details.expected_outgoing_cltv_expiry, incoming_cltv_expiry - advertised_cltv_expiry_delta as u32);
+	assert_eq!(details.prev_channel_id, chan_id);
+
+	// Restart the LSP before it gets around to opening the JIT channel, and check the intercepted
+	// HTLC can still be inspected.
+	let nodes_1_serialized = nodes[1].node.encode();
+	let chan_0_monitor_serialized = get_monitor!(nodes[1], chan_id).encode();
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+	nodes[2].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+	reload_node!(nodes[1], intercept_forwards_config, &nodes_1_serialized, &[&chan_0_monitor_serialized], persister, new_chain_monitor, nodes_1_deserialized);
+	reconnect_nodes(ReconnectArgs::new(&nodes[0], &nodes[1]));
+	nodes[1].node.peer_connected(&nodes[2].node.get_our_node_id(), &msgs::Init {
+		features: nodes[2].node.init_features(), networks: None, remote_network_address: None
+	}, true).unwrap();
+	nodes[2].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init {
+		features: nodes[1].node.init_features(), networks: None, remote_network_address: None
+	}, false).unwrap();
+	assert_eq!(nodes[1].node.list_intercepted_htlcs(), vec![details]);
+
+	let (_, channel_id) = open_zero_conf_channel(&nodes[1], &nodes[2], None);
+
+	// An override which would leave us with less than our minimum CLTV safety margin is rejected,
+	// leaving the HTLC pending.
+	let err = nodes[1].node.forward_intercepted_htlc_with_overrides(intercept_id, &channel_id,
+		nodes[2].node.get_our_node_id(), expected_outbound_amount_msat, Some(MIN_CLTV_EXPIRY_DELTA - 1)
+	).unwrap_err();
+	assert_eq!(err, APIError::APIMisuseError {
+		err: format!("The chosen CLTV expiry delta is below the minimum of {}", MIN_CLTV_EXPIRY_DELTA)
+	});
+	assert!(nodes[1].node.get_intercepted_htlc(intercept_id).is_some());
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	// Forward with the JIT channel's smaller CLTV expiry delta, giving the recipient more time.
+	nodes[1].node.forward_intercepted_htlc_with_overrides(intercept_id, &channel_id,
+		nodes[2].node.get_our_node_id(), expected_outbound_amount_msat, Some(MIN_CLTV_EXPIRY_DELTA)
+	).unwrap();
+	assert!(nodes[1].node.get_intercepted_htlc(intercept_id).is_none());
+	assert!(nodes[1].node.list_intercepted_htlcs().is_empty());
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+
+	let payment_event = SendEvent::from_node(&nodes[1]);
+	assert_eq!(payment_event.msgs[0].cltv_expiry, incoming_cltv_expiry - MIN_CLTV_EXPIRY_DELTA as u32);
+	nodes[2].node.handle_update_add_htlc(&nodes[1].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[2], nodes[1], &payment_event.commitment_msg, false, true);
+	expect_pending_htlcs_forwardable!(nodes[2]);
+
+	let payment_preimage = nodes[2].node.get_payment_preimage(payment_hash, payment_secret).unwrap();
+	expect_payment_claimable!(&nodes[2], payment_hash, payment_secret, amt_msat, Some(payment_preimage), nodes[2].node.get_our_node_id());
+	do_claim_payment_along_route(
+		ClaimAlongRouteArgs::new(&nodes[0], &[&[&nodes[1], &nodes[2]]], payment_preimage)
+	);
+	expect_payment_sent(&nodes[0], payment_preimage, Some(Some(1000)), true, true);
+}
+
 #[test]
 fn accept_underpaying_htlcs_config() {
 	do_accept_underpaying_htlcs_config(1);
@@ -2057,6 +3026,8 @@ fn do_accept_underpaying_htlcs_config(num_mpp_parts: usize) {
 	let mut intercept_forwards_config = test_default_channel_config();
 	intercept_forwards_config.accept_intercept_htlcs = true;
 	intercept_forwards_config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel = max_in_flight_percent;
+	let max_skim_proportional_millionths = 100;
+	intercept_forwards_config.max_intercepted_htlc_skimmed_fee_proportional_millionths = max_skim_proportional_millionths;
 	let mut underpay_config = test_default_channel_config();
 	underpay_config.channel_config.accept_underpaying_htlcs = true;
 	underpay_config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel = max_in_flight_percent;
@@ -2108,15 +3079,25 @@ fn do_accept_underpaying_htlcs_config(num_mpp_parts: usize) {
 
 		let events = nodes[1].node.get_and_clear_pending_events();
 		assert_eq!(events.len(), 1);
-		let (intercept_id, expected_outbound_amt_msat) = match events[0] {
+		let (intercept_id, expected_outbound_amt_msat, max_skimmed_fee_msat) = match events[0] {
 			crate::events::Event::HTLCIntercepted {
-				intercept_id, expected_outbound_amount_msat, payment_hash: pmt_hash, ..
+				intercept_id, expected_outbound_amount_msat, payment_hash: pmt_hash, max_skimmed_fee_msat, ..
 			} => {
 				assert_eq!(pmt_hash, payment_hash);
-				(intercept_id, expected_outbound_amount_msat)
+				(intercept_id, expected_outbound_amount_msat, max_skimmed_fee_msat)
 			},
 			_ => panic!()
 		};
+		assert_eq!(max_skimmed_fee_msat,
+			expected_outbound_amt_msat * max_skim_proportional_millionths as u64 / 1_000_000);
+		assert!(skimmed_fee_msat <= max_skimmed_fee_msat);
+
+		// Skimming more than the configured maximum is rejected, leaving the HTLC pending.
+		let err = nodes[1].node.forward_intercepted_htlc(intercept_id, &chan_ids[idx],
+			nodes[2].node.get_our_node_id(), expected_outbound_amt_msat - max_skimmed_fee_msat - 1).unwrap_err();
+		assert!(matches!(err, APIError::APIMisuseError { .. }));
+		assert!(nodes[1].node.get_intercepted_htlc(intercept_id).is_some());
+
 		nodes[1].node.forward_intercepted_htlc(intercept_id, &chan_ids[idx],
 			nodes[2].node.get_our_node_id(), expected_outbound_amt_msat - skimmed_fee_msat).unwrap();
 		expect_pending_htlcs_forwardable!(nodes[1]);
@@ -2460,7 +3441,7 @@ fn auto_retry_partial_failure() {
 				fee_msat: amt_msat / 2,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 			Path { hops: vec![RouteHop {
 				pubkey: nodes[1].node.get_our_node_id(),
 				node_features: nodes[1].node.node_features(),
@@ -2469,7 +3450,7 @@ fn auto_retry_partial_failure() {
 				fee_msat: amt_msat / 2,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 		],
 		route_params: Some(route_params.clone()),
 	};
@@ -2491,7 +3472,7 @@ fn auto_retry_partial_failure() {
 				fee_msat: amt_msat / 4,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 			Path { hops: vec![RouteHop {
 				pubkey: nodes[1].node.get_our_node_id(),
 				node_features: nodes[1].node.node_features(),
@@ -2500,7 +3481,7 @@ fn auto_retry_partial_failure() {
 				fee_msat: amt_msat / 4,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 		],
 		route_params: Some(retry_1_params.clone()),
 	};
@@ -2522,7 +3503,7 @@ fn auto_retry_partial_failure() {
 				fee_msat: amt_msat / 4,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 		],
 		route_params: Some(retry_2_params.clone()),
 	};
@@ -2667,7 +3648,7 @@ fn auto_retry_zero_attempts_send_error() {
 				fee_msat: amt_msat,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 		],
 		route_params: Some(route_params.clone()),
 	};
@@ -2765,7 +3746,7 @@ fn retry_multi_path_single_failed_payment() {
 				fee_msat: 10_000,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 			Path { hops: vec![RouteHop {
 				pubkey: nodes[1].node.get_our_node_id(),
 				node_features: nodes[1].node.node_features(),
@@ -2774,7 +3755,7 @@ fn retry_multi_path_single_failed_payment() {
 				fee_msat: 100_000_001, // Our default max-HTLC-value is 10% of the channel value, which this is one more than
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 		],
 		route_params: Some(route_params.clone()),
 	};
@@ -2856,7 +3837,7 @@ fn immediate_retry_on_failure() {
 				fee_msat: 100_000_001, // Our default max-HTLC-value is 10% of the channel value, which this is one more than
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 		],
 		route_params: Some(route_params.clone()),
 	};
@@ -2950,7 +3931,7 @@ fn no_extra_retries_on_back_to_back_fail() {
 				fee_msat: 100_000_000,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 			Path { hops: vec![RouteHop {
 				pubkey: nodes[1].node.get_our_node_id(),
 				node_features: nodes[1].node.node_features(),
@@ -2967,7 +3948,7 @@ fn no_extra_retries_on_back_to_back_fail() {
 				fee_msat: 100_000_000,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None }
+			}], blinded_tail: None, trampoline_tail: None }
 		],
 		route_params: Some(route_params.clone()),
 	};
@@ -3155,7 +4136,7 @@ fn test_simple_partial_retry() {
 				fee_msat: 100_000_000,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 			Path { hops: vec![RouteHop {
 				pubkey: nodes[1].node.get_our_node_id(),
 				node_features: nodes[1].node.node_features(),
@@ -3172,7 +4153,7 @@ fn test_simple_partial_retry() {
 				fee_msat: 100_000_000,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None }
+			}], blinded_tail: None, trampoline_tail: None }
 		],
 		route_params: Some(route_params.clone()),
 	};
@@ -3306,6 +4287,7 @@ fn test_threaded_payment_retries() {
 		.with_bolt11_features(invoice_features).unwrap();
 	let mut route_params = RouteParameters {
 		payment_params, final_value_msat: amt_msat, max_total_routing_fee_msat: Some(500_000),
+		max_candidate_hop_evaluations: None, best_block_height: None,
 	};
 
 	let mut route = Route {
@@ -3326,7 +4308,7 @@ fn test_threaded_payment_retries() {
 				fee_msat: amt_msat / 1000,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None },
+			}], blinded_tail: None, trampoline_tail: None },
 			Path { hops: vec![RouteHop {
 				pubkey: nodes[2].node.get_our_node_id(),
 				node_features: nodes[2].node.node_features(),
@@ -3343,7 +4325,7 @@ fn test_threaded_payment_retries() {
 				fee_msat: amt_msat - amt_msat / 1000,
 				cltv_expiry_delta: 100,
 				maybe_announced_channel: true,
-			}], blinded_tail: None }
+			}], blinded_tail: None, trampoline_tail: None }
 		],
 		route_params: Some(route_params.clone()),
 	};
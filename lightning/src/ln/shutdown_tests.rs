@@ -24,6 +24,7 @@ use crate::ln::onion_utils::INVALID_ONION_BLINDING;
 use crate::ln::script::ShutdownScript;
 use crate::util::test_utils;
 use crate::util::test_utils::OnGetShutdownScriptpubkey;
+use crate::util::transaction_utils::{predicted_cooperative_close_weight, predicted_funding_tx_weight};
 use crate::util::errors::APIError;
 use crate::util::config::UserConfig;
 use crate::util::string::UntrustedString;
@@ -1298,6 +1299,89 @@ fn simple_target_feerate_shutdown() {
 	check_closed_event!(nodes[1], 1, ClosureReason::LocallyInitiatedCooperativeClosure, [nodes[0].node.get_our_node_id()], 100000);
 }
 
+fn do_feerate_range_shutdown(hide_max_fee: bool) {
+	// Tests that a closing feerate range passed to `close_channel_with_feerate_range` bounds the
+	// fee we negotiate. If our counterparty ignores our ceiling, we fail the channel as we would
+	// for any other closing_signed outside of the range we told them about.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	assert!(matches!(
+		nodes[0].node.close_channel_with_feerate_range(&chan_id, &nodes[1].node.get_our_node_id(), 253 * 4, 253 * 2, None),
+		Err(APIError::APIMisuseError { .. })
+	));
+	nodes[0].node.close_channel_with_feerate_range(&chan_id, &nodes[1].node.get_our_node_id(), 253 * 2, 253 * 4, None).unwrap();
+	let node_0_shutdown = get_event_msg!(nodes[0], MessageSendEvent::SendShutdown, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
+	let node_1_shutdown = get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_shutdown(&nodes[1].node.get_our_node_id(), &node_1_shutdown);
+
+	// nodes[0] proposes a fee range matching the requested feerates, well below the default upper
+	// bound of our fee estimate plus `force_close_avoidance_max_fee_satoshis`.
+	let mut node_0_closing_signed = get_event_msg!(nodes[0], MessageSendEvent::SendClosingSigned, nodes[1].node.get_our_node_id());
+	let fee_range = node_0_closing_signed.fee_range.clone().unwrap();
+	assert_eq!(node_0_closing_signed.fee_satoshis, fee_range.min_fee_satoshis);
+	assert!(fee_range.max_fee_satoshis >= fee_range.min_fee_satoshis * 2 - 1);
+	assert!(fee_range.max_fee_satoshis <= fee_range.min_fee_satoshis * 2 + 1);
+	assert!(fee_range.max_fee_satoshis < 1000);
+
+	if hide_max_fee {
+		// Hide our ceiling from nodes[1], which as the fundee will then propose a fee above it.
+		node_0_closing_signed.fee_range.as_mut().unwrap().max_fee_satoshis *= 10;
+		nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_closing_signed);
+		let node_1_closing_signed = get_event_msg!(nodes[1], MessageSendEvent::SendClosingSigned, nodes[0].node.get_our_node_id());
+		assert!(node_1_closing_signed.fee_satoshis > fee_range.max_fee_satoshis);
+
+		nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed);
+		check_closed_broadcast!(nodes[0], true);
+		check_added_monitors!(nodes[0], 1);
+		let err = format!("Peer sent a bogus closing_signed - suggested fee of {} sat was not in our desired range of {} sat - {} sat after we informed them of our range.",
+			node_1_closing_signed.fee_satoshis, fee_range.min_fee_satoshis, fee_range.max_fee_satoshis);
+		check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err }, [nodes[1].node.get_our_node_id()], 100000);
+		return;
+	}
+
+	// As the fundee, nodes[1] picks the highest fee in our ranges' overlap, i.e. our ceiling.
+	nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_closing_signed);
+	let node_1_closing_signed = get_event_msg!(nodes[1], MessageSendEvent::SendClosingSigned, nodes[0].node.get_our_node_id());
+	assert_eq!(node_1_closing_signed.fee_satoshis, fee_range.max_fee_satoshis);
+
+	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed);
+	let (_, node_0_2nd_closing_signed) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_2nd_closing_signed.unwrap());
+	let (_, node_1_none) = get_closing_signed_broadcast!(nodes[1].node, nodes[0].node.get_our_node_id());
+	assert!(node_1_none.is_none());
+
+	let closing_tx = nodes[0].tx_broadcaster.txn_broadcast();
+	assert_eq!(closing_tx.len(), 1);
+	assert_eq!(closing_tx, nodes[1].tx_broadcaster.txn_broadcast());
+	// nodes[1]'s 10_001 msat balance is dust, leaving only nodes[0]'s output, which pays the fee.
+	assert_eq!(closing_tx[0].output.len(), 1);
+	assert_eq!(closing_tx[0].output[0].value.to_sat(), 99_989 - fee_range.max_fee_satoshis);
+
+	// The negotiated fee is provided in the `ChannelClosed` event on both sides.
+	for node in nodes.iter() {
+		let events = node.node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::ChannelClosed { closing_fee_satoshis, .. } => {
+				assert_eq!(closing_fee_satoshis, Some(fee_range.max_fee_satoshis));
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
+}
+
+#[test]
+fn feerate_range_shutdown() {
+	do_feerate_range_shutdown(false);
+	do_feerate_range_shutdown(true);
+}
+
 fn do_outbound_update_no_early_closing_signed(use_htlc: bool) {
 	// Previously, if we have a pending inbound HTLC (or fee update) on a channel which has
 	// initiated shutdown, we'd send our initial closing_signed immediately after receiving the
@@ -1520,3 +1604,29 @@ fn test_force_closure_on_low_stale_fee() {
 	let reason = ClosureReason::PeerFeerateTooLow { peer_feerate_sat_per_kw: 253, required_feerate_sat_per_kw: 253 * 2 };
 	check_closed_events(&nodes[1], &[ExpectedCloseEvent::from_id_reason(chan_id, false, reason)]);
 }
+
+#[test]
+fn test_predicted_funding_and_close_weight() {
+	// Check the fee UI weight predictions are upper bounds of the actual funding and cooperative
+	// close transactions.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let (_, _, chan_id, funding_tx) = create_announced_chan_between_nodes(&nodes, 0, 1);
+	// Our test funding transactions spend no inputs, leaving no room for error.
+	assert!(funding_tx.input.is_empty());
+	assert_eq!(predicted_funding_tx_weight(&[], 0, true), funding_tx.weight().to_wu());
+
+	// Make sure both parties end up with an output on the closing transaction.
+	send_payment(&nodes[0], &[&nodes[1]], 10_000_000);
+	let (_, _, closing_tx) = close_channel(&nodes[0], &nodes[1], &chan_id, funding_tx, true);
+	check_closed_event!(nodes[0], 1, ClosureReason::CounterpartyInitiatedCooperativeClosure, [nodes[1].node.get_our_node_id()], 100000);
+	check_closed_event!(nodes[1], 1, ClosureReason::LocallyInitiatedCooperativeClosure, [nodes[0].node.get_our_node_id()], 100000);
+	assert_eq!(closing_tx.output.len(), 2);
+	let predicted_weight = predicted_cooperative_close_weight(&closing_tx.output[0].script_pubkey, &closing_tx.output[1].script_pubkey);
+	// ECDSA signatures may be a byte or two shorter than the maximum we account for.
+	let actual_weight = closing_tx.weight().to_wu();
+	assert!(predicted_weight >= actual_weight);
+	assert!(predicted_weight - actual_weight <= 4);
+}
@@ -25,7 +25,7 @@ use crate::onion_message::messenger::{DefaultMessageRouter, Destination, Message
 use crate::routing::gossip::{DirectedChannelInfo, EffectiveCapacity, ReadOnlyNetworkGraph, NetworkGraph, NodeId, RoutingFees};
 use crate::routing::scoring::{ChannelUsage, LockableScore, ScoreLookUp};
 use crate::sign::EntropySource;
-use crate::util::ser::{Writeable, Readable, ReadableArgs, Writer};
+use crate::util::ser::{Writeable, Readable, ReadableArgs, Writer, WithoutLength};
 use crate::util::logger::{Level, Logger};
 use crate::crypto::chacha20::ChaCha20;
 
@@ -441,6 +441,27 @@ impl_writeable_tlv_based!(BlindedTail, {
 	(6, final_value_msat, required),
 });
 
+/// The trampoline portion of a [`Path`], if we're delegating finding the remainder of the route to
+/// the recipient to the last node in [`Path::hops`].
+///
+/// The last [`RouteHop::fee_msat`] and [`RouteHop::cltv_expiry_delta`] of such a [`Path`] are the
+/// amount and CLTV delta handed to the trampoline node, including its fee and CLTV budget.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct TrampolineTail {
+	/// The node id of the recipient, to which the trampoline node will find a route.
+	pub final_node_id: PublicKey,
+	/// The minimum CLTV delta the recipient requires at the end of the route.
+	pub final_cltv_expiry_delta: u32,
+	/// The total amount paid on this [`Path`], excluding the fees.
+	pub final_value_msat: u64,
+}
+
+impl_writeable_tlv_based!(TrampolineTail, {
+	(0, final_node_id, required),
+	(2, final_cltv_expiry_delta, required),
+	(4, final_value_msat, required),
+});
+
 /// A path in a [`Route`] to the payment recipient. Must always be at least length one.
 /// If no [`Path::blinded_tail`] is present, then [`Path::hops`] length may be up to 19.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -449,14 +470,22 @@ pub struct Path {
 	pub hops: Vec<RouteHop>,
 	/// The blinded path at which this path terminates, if we're sending to one, and its metadata.
 	pub blinded_tail: Option<BlindedTail>,
+	/// The recipient and payment metadata for the trampoline node at which this path terminates,
+	/// if we're sending via one.
+	pub trampoline_tail: Option<TrampolineTail>,
 }
 
 impl Path {
 	/// Gets the fees for a given path, excluding any excess paid to the recipient.
 	pub fn fee_msat(&self) -> u64 {
-		match &self.blinded_tail {
-			Some(_) => self.hops.iter().map(|hop| hop.fee_msat).sum::<u64>(),
-			None => {
+		match (&self.blinded_tail, &self.trampoline_tail) {
+			(Some(_), _) => self.hops.iter().map(|hop| hop.fee_msat).sum::<u64>(),
+			(None, Some(trampoline_tail)) => {
+				// The last hop's value includes the trampoline node's fee budget
+				self.hops.iter().map(|hop| hop.fee_msat).sum::<u64>()
+					.saturating_sub(trampoline_tail.final_value_msat)
+			},
+			(None, None) => {
 				// Do not count last hop of each path since that's the full value of the payment
 				self.hops.split_last().map_or(0,
 					|(_, path_prefix)| path_prefix.iter().map(|hop| hop.fee_msat).sum())
@@ -466,17 +495,19 @@ impl Path {
 
 	/// Gets the total amount paid on this [`Path`], excluding the fees.
 	pub fn final_value_msat(&self) -> u64 {
-		match &self.blinded_tail {
-			Some(blinded_tail) => blinded_tail.final_value_msat,
-			None => self.hops.last().map_or(0, |hop| hop.fee_msat)
+		match (&self.blinded_tail, &self.trampoline_tail) {
+			(Some(blinded_tail), _) => blinded_tail.final_value_msat,
+			(None, Some(trampoline_tail)) => trampoline_tail.final_value_msat,
+			(None, None) => self.hops.last().map_or(0, |hop| hop.fee_msat)
 		}
 	}
 
 	/// Gets the final hop's CLTV expiry delta.
 	pub fn final_cltv_expiry_delta(&self) -> Option<u32> {
-		match &self.blinded_tail {
-			Some(_) => None,
-			None => self.hops.last().map(|hop| hop.cltv_expiry_delta)
+		match (&self.blinded_tail, &self.trampoline_tail) {
+			(Some(_), _) => None,
+			(None, Some(trampoline_tail)) => Some(trampoline_tail.final_cltv_expiry_delta),
+			(None, None) => self.hops.last().map(|hop| hop.cltv_expiry_delta)
 		}
 	}
 }
@@ -536,6 +567,7 @@ impl Writeable for Route {
 		write_ver_prefix!(writer, SERIALIZATION_VERSION, MIN_SERIALIZATION_VERSION);
 		(self.paths.len() as u64).write(writer)?;
 		let mut blinded_tails = Vec::new();
+		let mut trampoline_tails = Vec::new();
 		for (idx, path) in self.paths.iter().enumerate() {
 			(path.hops.len() as u8).write(writer)?;
 			for hop in path.hops.iter() {
@@ -550,6 +582,15 @@ impl Writeable for Route {
 				}
 				blinded_tails.push(Some(blinded_tail));
 			} else if !blinded_tails.is_empty() { blinded_tails.push(None); }
+			if let Some(trampoline_tail) = &path.trampoline_tail {
+				if trampoline_tails.is_empty() {
+					trampoline_tails = Vec::with_capacity(self.paths.len());
+					for _ in 0..idx {
+						trampoline_tails.push(None);
+					}
+				}
+				trampoline_tails.push(Some(trampoline_tail));
+			} else if !trampoline_tails.is_empty() { trampoline_tails.push(None); }
 		}
 		write_tlv_fields!(writer, {
 			// For compatibility with LDK versions prior to 0.0.117, we take the individual
@@ -557,6 +598,7 @@ impl Writeable for Route {
 			(1, self.route_params.as_ref().map(|p| &p.payment_params), option),
 			(2, blinded_tails, optional_vec),
 			(3, self.route_params.as_ref().map(|p| p.final_value_msat), option),
+			(4, trampoline_tails, optional_vec),
 			(5, self.route_params.as_ref().and_then(|p| p.max_total_routing_fee_msat), option),
 		});
 		Ok(())
@@ -579,13 +621,14 @@ impl Readable for Route {
 			if hops.is_empty() { return Err(DecodeError::InvalidValue); }
 			min_final_cltv_expiry_delta =
 				cmp::min(min_final_cltv_expiry_delta, hops.last().unwrap().cltv_expiry_delta);
-			paths.push(Path { hops, blinded_tail: None });
+			paths.push(Path { hops, blinded_tail: None, trampoline_tail: None });
 		}
 		_init_and_read_len_prefixed_tlv_fields!(reader, {
 			(1, payment_params, (option: ReadableArgs, min_final_cltv_expiry_delta)),
 			(2, blinded_tails, optional_vec),
 			(3, final_value_msat, option),
-			(5, max_total_routing_fee_msat, option)
+			(4, trampoline_tails, optional_vec),
+			(5, max_total_routing_fee_msat, option),
 		});
 		let blinded_tails = blinded_tails.unwrap_or(Vec::new());
 		if blinded_tails.len() != 0 {
@@ -594,11 +637,21 @@ impl Readable for Route {
 				path.blinded_tail = blinded_tail_opt;
 			}
 		}
+		let trampoline_tails = trampoline_tails.unwrap_or(Vec::new());
+		if trampoline_tails.len() != 0 {
+			if trampoline_tails.len() != paths.len() { return Err(DecodeError::InvalidValue) }
+			for (path, trampoline_tail_opt) in paths.iter_mut().zip(trampoline_tails.into_iter()) {
+				path.trampoline_tail = trampoline_tail_opt;
+			}
+		}
 
 		// If we previously wrote the corresponding fields, reconstruct RouteParameters.
 		let route_params = match (payment_params, final_value_msat) {
 			(Some(payment_params), Some(final_value_msat)) => {
-				Some(RouteParameters { payment_params, final_value_msat, max_total_routing_fee_msat })
+				Some(RouteParameters {
+					payment_params, final_value_msat, max_total_routing_fee_msat,
+					max_candidate_hop_evaluations: None, best_block_height: None,
+				})
 			}
 			_ => None,
 		};
@@ -625,6 +678,29 @@ pub struct RouteParameters {
 	///
 	/// Note that values below a few sats may result in some paths being spuriously ignored.
 	pub max_total_routing_fee_msat: Option<u64>,
+
+	/// The maximum number of candidate hops the router may evaluate while searching for a route,
+	/// bounding the worst-case time spent in pathfinding, e.g., on low-end devices with a full
+	/// network graph.
+	///
+	/// Once the budget is exhausted, the router stops searching and returns the best route found
+	/// among the paths collected so far, or fails if they don't suffice to pay the full amount.
+	/// Thus, small values may result in more expensive routes or in routing failures.
+	///
+	/// This budget also applies separately to each route finding attempt when retrying failed
+	/// payment paths.
+	///
+	/// Default value: `None`, i.e., the search is unbounded.
+	pub max_candidate_hop_evaluations: Option<u32>,
+
+	/// The current best block height, used to enforce
+	/// [`PaymentParameters::max_absolute_cltv_expiry_height`].
+	///
+	/// When sending a payment with such a limit, [`ChannelManager`] sets this to its own view of
+	/// the best block height, including on retries.
+	///
+	/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+	pub best_block_height: Option<u32>,
 }
 
 impl RouteParameters {
@@ -632,7 +708,41 @@ impl RouteParameters {
 	///
 	/// [`Self::max_total_routing_fee_msat`] defaults to 1% of the payment amount + 50 sats
 	pub fn from_payment_params_and_value(payment_params: PaymentParameters, final_value_msat: u64) -> Self {
-		Self { payment_params, final_value_msat, max_total_routing_fee_msat: Some(final_value_msat / 100 + 50_000) }
+		Self {
+			payment_params, final_value_msat,
+			max_total_routing_fee_msat: Some(final_value_msat / 100 + 50_000),
+			max_candidate_hop_evaluations: None,
+			best_block_height: None,
+		}
+	}
+
+	/// Includes a limit on the number of candidate hops the router may evaluate. See
+	/// [`RouteParameters::max_candidate_hop_evaluations`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_max_candidate_hop_evaluations(self, max_candidate_hop_evaluations: u32) -> Self {
+		Self { max_candidate_hop_evaluations: Some(max_candidate_hop_evaluations), ..self }
+	}
+
+	/// Returns the maximum total CLTV expiry delta any path of the route may have, accounting for
+	/// both [`PaymentParameters::max_total_cltv_expiry_delta`] and
+	/// [`PaymentParameters::max_absolute_cltv_expiry_height`].
+	fn max_total_cltv_expiry_delta(&self) -> Result<u32, LightningError> {
+		let max_absolute_height = match self.payment_params.max_absolute_cltv_expiry_height {
+			Some(height) => height,
+			None => return Ok(self.payment_params.max_total_cltv_expiry_delta),
+		};
+		let best_block_height = self.best_block_height.ok_or_else(|| LightningError {
+			err: "Can't enforce the maximum absolute CLTV expiry height without the current best block height".to_owned(),
+			action: ErrorAction::IgnoreError,
+		})?;
+		// HTLCs are sent with an expiry relative to the next block, see `onion_utils`.
+		let max_delta_to_absolute_height = max_absolute_height.checked_sub(best_block_height.saturating_add(1))
+			.ok_or_else(|| LightningError {
+				err: "The maximum absolute CLTV expiry height has already passed".to_owned(),
+				action: ErrorAction::IgnoreError,
+			})?;
+		Ok(cmp::min(self.payment_params.max_total_cltv_expiry_delta, max_delta_to_absolute_height))
 	}
 
 	/// Sets the maximum number of hops that can be included in a payment path, based on the provided
@@ -653,9 +763,11 @@ impl Writeable for RouteParameters {
 			(0, self.payment_params, required),
 			(1, self.max_total_routing_fee_msat, option),
 			(2, self.final_value_msat, required),
+			(3, self.max_candidate_hop_evaluations, option),
 			// LDK versions prior to 0.0.114 had the `final_cltv_expiry_delta` parameter in
 			// `RouteParameters` directly. For compatibility, we write it here.
 			(4, self.payment_params.payee.final_cltv_expiry_delta(), option),
+			(5, self.best_block_height, option),
 		});
 		Ok(())
 	}
@@ -667,7 +779,9 @@ impl Readable for RouteParameters {
 			(0, payment_params, (required: ReadableArgs, 0)),
 			(1, max_total_routing_fee_msat, option),
 			(2, final_value_msat, required),
+			(3, max_candidate_hop_evaluations, option),
 			(4, final_cltv_delta, option),
+			(5, best_block_height, option),
 		});
 		let mut payment_params: PaymentParameters = payment_params.0.unwrap();
 		if let Payee::Clear { ref mut final_cltv_expiry_delta, .. } = payment_params.payee {
@@ -679,6 +793,8 @@ impl Readable for RouteParameters {
 			payment_params,
 			final_value_msat: final_value_msat.0.unwrap(),
 			max_total_routing_fee_msat,
+			max_candidate_hop_evaluations,
+			best_block_height,
 		})
 	}
 }
@@ -720,7 +836,8 @@ pub struct PaymentParameters {
 	/// Expiration of a payment to the payee, in seconds relative to the UNIX epoch.
 	pub expiry_time: Option<u64>,
 
-	/// The maximum total CLTV delta we accept for the route.
+	/// The maximum total CLTV delta we accept for the route, including any route hints and the
+	/// final CLTV expiry delta.
 	/// Defaults to [`DEFAULT_MAX_TOTAL_CLTV_EXPIRY_DELTA`].
 	pub max_total_cltv_expiry_delta: u32,
 
@@ -728,6 +845,13 @@ pub struct PaymentParameters {
 	/// Defaults to [`DEFAULT_MAX_PATH_COUNT`].
 	pub max_path_count: u8,
 
+	/// If set, further limits the number of paths that may be used by (MPP) payments to below
+	/// [`PaymentParameters::max_path_count`], e.g., for a payee which only accepts few HTLCs.
+	///
+	/// Unlike [`PaymentParameters::max_path_count`], which we may lower ourselves where a payment
+	/// must be sent over a single path, this is only ever set by the user.
+	pub max_path_count_override: Option<u8>,
+
 	/// The maximum number of [`Path::hops`] in any returned path.
 	/// Defaults to [`MAX_PATH_LENGTH_ESTIMATE`].
 	pub max_path_length: u8,
@@ -756,15 +880,64 @@ pub struct PaymentParameters {
 	/// payment was previously attempted over and which caused the payment to fail. Future attempts
 	/// for the same payment shouldn't be relayed through any of these blinded paths.
 	pub previously_failed_blinded_path_idxs: Vec<u64>,
+
+	/// If set, restricts the first hop of each path to our channels with one of the given SCIDs,
+	/// matching either a channel's [`ChannelDetails::short_channel_id`] or its
+	/// [`ChannelDetails::outbound_scid_alias`]. MPP payments may still be split across multiple of
+	/// the given channels.
+	///
+	/// Routing fails if none of the given channels are usable or have sufficient capacity.
+	pub first_hop_channel_ids: Option<Vec<u64>>,
+
+	/// If set, the minimum amount, in millisatoshis, which each path of an MPP payment must
+	/// deliver to the payee. Payments of less than this amount are always sent over a single path.
+	///
+	/// Together with [`PaymentParameters::max_path_count_override`], this bounds how finely a
+	/// payment may be split, e.g., to at most three parts of at least 50k sats each. Routing fails
+	/// if the payment can't be split within these bounds.
+	pub min_shard_amount_msat: Option<u64>,
+
+	/// A list of nodes which paths for this payment must not be routed through, including via
+	/// route hints or as the introduction node of a blinded path.
+	///
+	/// Unlike [`PaymentParameters::previously_failed_channels`], this is set by the user and is
+	/// never modified when retrying the payment.
+	pub excluded_nodes: Vec<NodeId>,
+
+	/// A list of SCIDs which paths for this payment must not be routed over, including our own
+	/// first-hop channels and channels given in route hints.
+	///
+	/// Unlike [`PaymentParameters::previously_failed_channels`], this is set by the user and is
+	/// never modified when retrying the payment.
+	pub excluded_channels: Vec<u64>,
+
+	/// If set, the maximum absolute block height at which any HTLC of this payment may expire,
+	/// including any route hints and the final CLTV expiry delta.
+	///
+	/// Unlike [`PaymentParameters::max_total_cltv_expiry_delta`], this limit doesn't move as new
+	/// blocks are connected, so retries of the payment are held to the same ceiling. Enforcing it
+	/// requires [`RouteParameters::best_block_height`] to be set, and routing fails otherwise.
+	pub max_absolute_cltv_expiry_height: Option<u32>,
 }
 
 impl Writeable for PaymentParameters {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
 		let mut clear_hints = &vec![];
 		let mut blinded_hints = &vec![];
+		let mut trampoline_node_id = None;
+		let mut max_trampoline_fee_msat = None;
+		let mut max_trampoline_cltv_expiry_delta = None;
 		match &self.payee {
 			Payee::Clear { route_hints, .. } => clear_hints = route_hints,
 			Payee::Blinded { route_hints, .. } => blinded_hints = route_hints,
+			Payee::Trampoline {
+				trampoline_node_id: node_id, max_trampoline_fee_msat: max_fee_msat,
+				max_trampoline_cltv_expiry_delta: max_cltv_expiry_delta, ..
+			} => {
+				trampoline_node_id = Some(node_id);
+				max_trampoline_fee_msat = Some(*max_fee_msat);
+				max_trampoline_cltv_expiry_delta = Some(*max_cltv_expiry_delta);
+			},
 		}
 		write_tlv_fields!(writer, {
 			(0, self.payee.node_id(), option),
@@ -777,8 +950,17 @@ impl Writeable for PaymentParameters {
 			(7, self.previously_failed_channels, required_vec),
 			(8, *blinded_hints, optional_vec),
 			(9, self.payee.final_cltv_expiry_delta(), option),
+			(10, trampoline_node_id, option),
 			(11, self.previously_failed_blinded_path_idxs, required_vec),
+			(12, max_trampoline_fee_msat, option),
 			(13, self.max_path_length, required),
+			(14, max_trampoline_cltv_expiry_delta, option),
+			(15, self.first_hop_channel_ids.as_ref().map(WithoutLength), option),
+			(17, self.min_shard_amount_msat, option),
+			(19, self.excluded_nodes, optional_vec),
+			(21, self.excluded_channels, optional_vec),
+			(23, self.max_absolute_cltv_expiry_height, option),
+			(25, self.max_path_count_override, option),
 		});
 		Ok(())
 	}
@@ -797,8 +979,17 @@ impl ReadableArgs<u32> for PaymentParameters {
 			(7, previously_failed_channels, optional_vec),
 			(8, blinded_route_hints, optional_vec),
 			(9, final_cltv_expiry_delta, (default_value, default_final_cltv_expiry_delta)),
+			(10, trampoline_node_id, option),
 			(11, previously_failed_blinded_path_idxs, optional_vec),
+			(12, max_trampoline_fee_msat, option),
 			(13, max_path_length, (default_value, MAX_PATH_LENGTH_ESTIMATE)),
+			(14, max_trampoline_cltv_expiry_delta, option),
+			(15, first_hop_channel_ids, (option, encoding: (Vec<u64>, WithoutLength))),
+			(17, min_shard_amount_msat, option),
+			(19, excluded_nodes, optional_vec),
+			(21, excluded_channels, optional_vec),
+			(23, max_absolute_cltv_expiry_height, option),
+			(25, max_path_count_override, option),
 		});
 		let blinded_route_hints = blinded_route_hints.unwrap_or(vec![]);
 		let payee = if blinded_route_hints.len() != 0 {
@@ -807,6 +998,16 @@ impl ReadableArgs<u32> for PaymentParameters {
 				route_hints: blinded_route_hints,
 				features: features.and_then(|f: Features| f.bolt12()),
 			}
+		} else if let Some(trampoline_node_id) = trampoline_node_id {
+			if clear_route_hints.len() != 0 { return Err(DecodeError::InvalidValue) }
+			Payee::Trampoline {
+				trampoline_node_id,
+				final_node_id: payee_pubkey.ok_or(DecodeError::InvalidValue)?,
+				max_trampoline_fee_msat: max_trampoline_fee_msat.ok_or(DecodeError::InvalidValue)?,
+				max_trampoline_cltv_expiry_delta:
+					max_trampoline_cltv_expiry_delta.ok_or(DecodeError::InvalidValue)?,
+				final_cltv_expiry_delta: final_cltv_expiry_delta.0.unwrap(),
+			}
 		} else {
 			Payee::Clear {
 				route_hints: clear_route_hints,
@@ -818,12 +1019,18 @@ impl ReadableArgs<u32> for PaymentParameters {
 		Ok(Self {
 			max_total_cltv_expiry_delta: _init_tlv_based_struct_field!(max_total_cltv_expiry_delta, (default_value, unused)),
 			max_path_count: _init_tlv_based_struct_field!(max_path_count, (default_value, unused)),
+			max_path_count_override,
 			payee,
 			max_channel_saturation_power_of_half: _init_tlv_based_struct_field!(max_channel_saturation_power_of_half, (default_value, unused)),
 			expiry_time,
 			previously_failed_channels: previously_failed_channels.unwrap_or(Vec::new()),
 			previously_failed_blinded_path_idxs: previously_failed_blinded_path_idxs.unwrap_or(Vec::new()),
 			max_path_length: _init_tlv_based_struct_field!(max_path_length, (default_value, unused)),
+			first_hop_channel_ids,
+			min_shard_amount_msat,
+			excluded_nodes: excluded_nodes.unwrap_or(Vec::new()),
+			excluded_channels: excluded_channels.unwrap_or(Vec::new()),
+			max_absolute_cltv_expiry_height,
 		})
 	}
 }
@@ -840,10 +1047,16 @@ impl PaymentParameters {
 			expiry_time: None,
 			max_total_cltv_expiry_delta: DEFAULT_MAX_TOTAL_CLTV_EXPIRY_DELTA,
 			max_path_count: DEFAULT_MAX_PATH_COUNT,
+			max_path_count_override: None,
 			max_path_length: MAX_PATH_LENGTH_ESTIMATE,
 			max_channel_saturation_power_of_half: DEFAULT_MAX_CHANNEL_SATURATION_POW_HALF,
 			previously_failed_channels: Vec::new(),
 			previously_failed_blinded_path_idxs: Vec::new(),
+			first_hop_channel_ids: None,
+			min_shard_amount_msat: None,
+			excluded_nodes: Vec::new(),
+			excluded_channels: Vec::new(),
+			max_absolute_cltv_expiry_height: None,
 		}
 	}
 
@@ -880,10 +1093,70 @@ impl PaymentParameters {
 			expiry_time: None,
 			max_total_cltv_expiry_delta: DEFAULT_MAX_TOTAL_CLTV_EXPIRY_DELTA,
 			max_path_count: DEFAULT_MAX_PATH_COUNT,
+			max_path_count_override: None,
 			max_path_length: MAX_PATH_LENGTH_ESTIMATE,
 			max_channel_saturation_power_of_half: DEFAULT_MAX_CHANNEL_SATURATION_POW_HALF,
 			previously_failed_channels: Vec::new(),
 			previously_failed_blinded_path_idxs: Vec::new(),
+			first_hop_channel_ids: None,
+			min_shard_amount_msat: None,
+			excluded_nodes: Vec::new(),
+			excluded_channels: Vec::new(),
+			max_absolute_cltv_expiry_height: None,
+		}
+	}
+
+	/// Creates parameters for paying to `final_node_id` via the trampoline node
+	/// `trampoline_node_id`, which must support trampoline routing.
+	///
+	/// We only find a route to the trampoline node, which is then responsible for finding the
+	/// remainder of the route to the payee. It may use up to `max_trampoline_fee_msat` in fees and
+	/// `max_trampoline_cltv_expiry_delta` in CLTV expiry delta to do so. The payee's final CLTV
+	/// delta defaults to [`MIN_FINAL_CLTV_EXPIRY_DELTA`] and may be set via
+	/// [`PaymentParameters::with_trampoline_final_cltv_expiry_delta`].
+	pub fn trampoline(
+		trampoline_node_id: PublicKey, final_node_id: PublicKey, max_trampoline_fee_msat: u64,
+		max_trampoline_cltv_expiry_delta: u32,
+	) -> Self {
+		Self {
+			payee: Payee::Trampoline {
+				trampoline_node_id, final_node_id, max_trampoline_fee_msat,
+				max_trampoline_cltv_expiry_delta,
+				final_cltv_expiry_delta: MIN_FINAL_CLTV_EXPIRY_DELTA as u32,
+			},
+			expiry_time: None,
+			max_total_cltv_expiry_delta: DEFAULT_MAX_TOTAL_CLTV_EXPIRY_DELTA,
+			max_path_count: DEFAULT_MAX_PATH_COUNT,
+			max_path_count_override: None,
+			max_path_length: MAX_PATH_LENGTH_ESTIMATE,
+			max_channel_saturation_power_of_half: DEFAULT_MAX_CHANNEL_SATURATION_POW_HALF,
+			previously_failed_channels: Vec::new(),
+			previously_failed_blinded_path_idxs: Vec::new(),
+			first_hop_channel_ids: None,
+			min_shard_amount_msat: None,
+			excluded_nodes: Vec::new(),
+			excluded_channels: Vec::new(),
+			max_absolute_cltv_expiry_height: None,
+		}
+	}
+
+	/// Includes the payee's final CLTV expiry delta. Errors if the parameters were not initialized
+	/// with [`PaymentParameters::trampoline`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_trampoline_final_cltv_expiry_delta(self, final_cltv_expiry_delta: u32) -> Result<Self, ()> {
+		match self.payee {
+			Payee::Trampoline {
+				trampoline_node_id, final_node_id, max_trampoline_fee_msat,
+				max_trampoline_cltv_expiry_delta, ..
+			} =>
+				Ok(Self {
+					payee: Payee::Trampoline {
+						trampoline_node_id, final_node_id, max_trampoline_fee_msat,
+						max_trampoline_cltv_expiry_delta, final_cltv_expiry_delta,
+					}, ..self
+				}),
+			_ => Err(()),
 		}
 	}
 
@@ -893,7 +1166,7 @@ impl PaymentParameters {
 	/// This is not exported to bindings users since bindings don't support move semantics
 	pub fn with_bolt12_features(self, features: Bolt12InvoiceFeatures) -> Result<Self, ()> {
 		match self.payee {
-			Payee::Clear { .. } => Err(()),
+			Payee::Clear { .. } | Payee::Trampoline { .. } => Err(()),
 			Payee::Blinded { route_hints, .. } =>
 				Ok(Self { payee: Payee::Blinded { route_hints, features: Some(features) }, ..self })
 		}
@@ -905,7 +1178,7 @@ impl PaymentParameters {
 	/// This is not exported to bindings users since bindings don't support move semantics
 	pub fn with_bolt11_features(self, features: Bolt11InvoiceFeatures) -> Result<Self, ()> {
 		match self.payee {
-			Payee::Blinded { .. } => Err(()),
+			Payee::Blinded { .. } | Payee::Trampoline { .. } => Err(()),
 			Payee::Clear { route_hints, node_id, final_cltv_expiry_delta, .. } =>
 				Ok(Self {
 					payee: Payee::Clear {
@@ -921,7 +1194,7 @@ impl PaymentParameters {
 	/// This is not exported to bindings users since bindings don't support move semantics
 	pub fn with_route_hints(self, route_hints: Vec<RouteHint>) -> Result<Self, ()> {
 		match self.payee {
-			Payee::Blinded { .. } => Err(()),
+			Payee::Blinded { .. } | Payee::Trampoline { .. } => Err(()),
 			Payee::Clear { node_id, features, final_cltv_expiry_delta, .. } =>
 				Ok(Self {
 					payee: Payee::Clear {
@@ -952,6 +1225,14 @@ impl PaymentParameters {
 		Self { max_path_count, ..self }
 	}
 
+	/// Includes a further limit for the maximum number of payment paths that may be used. See
+	/// [`PaymentParameters::max_path_count_override`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_max_path_count_override(self, max_path_count_override: u8) -> Self {
+		Self { max_path_count_override: Some(max_path_count_override), ..self }
+	}
+
 	/// Includes a limit for the maximum share of a channel's total capacity that can be sent over, as
 	/// a power of 1/2. See [`PaymentParameters::max_channel_saturation_power_of_half`].
 	///
@@ -960,6 +1241,55 @@ impl PaymentParameters {
 		Self { max_channel_saturation_power_of_half, ..self }
 	}
 
+	/// Restricts the first hop of each path to our channels with one of the given SCIDs. See
+	/// [`PaymentParameters::first_hop_channel_ids`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_first_hop_channel_ids(self, first_hop_channel_ids: Vec<u64>) -> Self {
+		Self { first_hop_channel_ids: Some(first_hop_channel_ids), ..self }
+	}
+
+	/// Includes a minimum amount each path of an MPP payment must deliver. See
+	/// [`PaymentParameters::min_shard_amount_msat`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_min_shard_amount_msat(self, min_shard_amount_msat: u64) -> Self {
+		Self { min_shard_amount_msat: Some(min_shard_amount_msat), ..self }
+	}
+
+	/// Excludes the given nodes and channels from all paths of this payment. See
+	/// [`PaymentParameters::excluded_nodes`] and [`PaymentParameters::excluded_channels`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_excluded_hops(self, excluded_nodes: Vec<NodeId>, excluded_channels: Vec<u64>) -> Self {
+		Self { excluded_nodes, excluded_channels, ..self }
+	}
+
+	/// Includes a limit for the absolute block height at which any HTLC of this payment may expire.
+	/// See [`PaymentParameters::max_absolute_cltv_expiry_height`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_max_absolute_cltv_expiry_height(self, max_absolute_cltv_expiry_height: u32) -> Self {
+		Self { max_absolute_cltv_expiry_height: Some(max_absolute_cltv_expiry_height), ..self }
+	}
+
+	/// Returns whether the given channel of ours may be used as the first hop of a path.
+	fn allows_first_hop(&self, short_channel_id: Option<u64>, outbound_scid_alias: Option<u64>) -> bool {
+		match &self.first_hop_channel_ids {
+			Some(scids) => [short_channel_id, outbound_scid_alias].iter()
+				.any(|scid| scid.map(|scid| scids.contains(&scid)).unwrap_or(false)),
+			None => true,
+		}
+	}
+
+	/// Returns whether a hop over the given channel from `source` to `target` is excluded from this
+	/// payment.
+	fn excludes_hop(&self, short_channel_id: Option<u64>, source: &NodeId, target: Option<&NodeId>) -> bool {
+		short_channel_id.map(|scid| self.excluded_channels.contains(&scid)).unwrap_or(false) ||
+			self.excluded_nodes.contains(source) ||
+			target.map(|target| self.excluded_nodes.contains(target)).unwrap_or(false)
+	}
+
 	pub(crate) fn insert_previously_failed_blinded_path(&mut self, failed_blinded_tail: &BlindedTail) {
 		let mut found_blinded_tail = false;
 		for (idx, (_, path)) in self.payee.blinded_route_hints().iter().enumerate() {
@@ -975,7 +1305,7 @@ impl PaymentParameters {
 }
 
 /// The recipient of a payment, differing based on whether they've hidden their identity with route
-/// blinding.
+/// blinding or are reached via a trampoline node.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Payee {
 	/// The recipient provided blinded paths and payinfo to reach them. The blinded paths themselves
@@ -1006,12 +1336,27 @@ pub enum Payee {
 		/// The minimum CLTV delta at the end of the route. This value must not be zero.
 		final_cltv_expiry_delta: u32,
 	},
+	/// The recipient is reached via a trampoline node, which finds the route from itself to the
+	/// recipient. Only the route to the trampoline node is found locally.
+	Trampoline {
+		/// The node id of the trampoline node, which must support trampoline routing.
+		trampoline_node_id: PublicKey,
+		/// The node id of the payee.
+		final_node_id: PublicKey,
+		/// The maximum fee, in millisatoshi, the trampoline node may take for routing to the payee.
+		max_trampoline_fee_msat: u64,
+		/// The maximum CLTV expiry delta the trampoline node may use for routing to the payee.
+		max_trampoline_cltv_expiry_delta: u32,
+		/// The minimum CLTV delta at the end of the route. This value must not be zero.
+		final_cltv_expiry_delta: u32,
+	},
 }
 
 impl Payee {
 	fn node_id(&self) -> Option<PublicKey> {
 		match self {
 			Self::Clear { node_id, .. } => Some(*node_id),
+			Self::Trampoline { final_node_id, .. } => Some(*final_node_id),
 			_ => None,
 		}
 	}
@@ -1019,43 +1364,47 @@ impl Payee {
 		match self {
 			Self::Clear { features, .. } => features.as_ref().map(|f| f.to_context()),
 			Self::Blinded { features, .. } => features.as_ref().map(|f| f.to_context()),
+			Self::Trampoline { .. } => None,
 		}
 	}
 	fn supports_basic_mpp(&self) -> bool {
 		match self {
 			Self::Clear { features, .. } => features.as_ref().map_or(false, |f| f.supports_basic_mpp()),
 			Self::Blinded { features, .. } => features.as_ref().map_or(false, |f| f.supports_basic_mpp()),
+			Self::Trampoline { .. } => false,
 		}
 	}
 	fn features(&self) -> Option<FeaturesRef> {
 		match self {
 			Self::Clear { features, .. } => features.as_ref().map(|f| FeaturesRef::Bolt11(f)),
 			Self::Blinded { features, .. } => features.as_ref().map(|f| FeaturesRef::Bolt12(f)),
+			Self::Trampoline { .. } => None,
 		}
 	}
 	fn final_cltv_expiry_delta(&self) -> Option<u32> {
 		match self {
 			Self::Clear { final_cltv_expiry_delta, .. } => Some(*final_cltv_expiry_delta),
+			Self::Trampoline { final_cltv_expiry_delta, .. } => Some(*final_cltv_expiry_delta),
 			_ => None,
 		}
 	}
 	pub(crate) fn blinded_route_hints(&self) -> &[(BlindedPayInfo, BlindedPath)] {
 		match self {
 			Self::Blinded { route_hints, .. } => &route_hints[..],
-			Self::Clear { .. } => &[]
+			Self::Clear { .. } | Self::Trampoline { .. } => &[]
 		}
 	}
 
 	pub(crate) fn blinded_route_hints_mut(&mut self) -> &mut [(BlindedPayInfo, BlindedPath)] {
 		match self {
 			Self::Blinded { route_hints, .. } => &mut route_hints[..],
-			Self::Clear { .. } => &mut []
+			Self::Clear { .. } | Self::Trampoline { .. } => &mut []
 		}
 	}
 
 	fn unblinded_route_hints(&self) -> &[RouteHint] {
 		match self {
-			Self::Blinded { .. } => &[],
+			Self::Blinded { .. } | Self::Trampoline { .. } => &[],
 			Self::Clear { route_hints, .. } => &route_hints[..]
 		}
 	}
@@ -1897,7 +2246,198 @@ where L::Target: Logger, GL::Target: Logger {
 	let graph_lock = network_graph.read_only();
 	let mut route = get_route(our_node_pubkey, &route_params, &graph_lock, first_hops, logger,
 		scorer, score_params, random_seed_bytes)?;
-	add_random_cltv_offset(&mut route, &route_params.payment_params, &graph_lock, random_seed_bytes);
+	add_random_cltv_offset(&mut route, route_params.max_total_cltv_expiry_delta()?, &graph_lock,
+		random_seed_bytes);
+	Ok(route)
+}
+
+/// Finds a [`Route`] for a circular payment from us back to ourselves, as used to rebalance our
+/// channels.
+///
+/// As routers can't find routes to ourselves, the payee must be given with a single route hint
+/// consisting of a single hop, i.e., the channel over which the payment returns to us. We then
+/// find a single-path route to that hop's source via the given `router` and append the hop.
+pub(crate) fn find_circular_route<R: Deref>(
+	router: &R, payer: &PublicKey, route_params: &RouteParameters,
+	first_hops: Option<&[&ChannelDetails]>, inflight_htlcs: InFlightHtlcs,
+	payment_hash: PaymentHash, payment_id: PaymentId,
+) -> Result<Route, LightningError>
+where R::Target: Router {
+	let (last_hop, final_cltv_expiry_delta) = match &route_params.payment_params.payee {
+		Payee::Clear { route_hints, final_cltv_expiry_delta, .. }
+			if route_hints.len() == 1 && route_hints[0].0.len() == 1 =>
+				(&route_hints[0].0[0], *final_cltv_expiry_delta),
+		_ => return Err(LightningError {
+			err: "Circular payments must return to us via a single route hint hop".to_owned(),
+			action: ErrorAction::IgnoreError,
+		}),
+	};
+	if last_hop.src_node_id == *payer {
+		return Err(LightningError {
+			err: "Circular payments must return to us via a channel with a peer".to_owned(),
+			action: ErrorAction::IgnoreError,
+		});
+	}
+
+	let amount_msat = route_params.final_value_msat;
+	let last_hop_fee_msat = compute_fees(amount_msat, last_hop.fees).ok_or_else(|| LightningError {
+		err: "Fees for the last hop of the circular payment overflowed".to_owned(),
+		action: ErrorAction::IgnoreError,
+	})?;
+	let max_total_routing_fee_msat = match route_params.max_total_routing_fee_msat {
+		Some(max_fee_msat) => Some(max_fee_msat.checked_sub(last_hop_fee_msat).ok_or_else(|| LightningError {
+			err: format!("The last hop's fee of {}msat exceeds the maximum total fee of {}msat",
+				last_hop_fee_msat, max_fee_msat),
+			action: ErrorAction::IgnoreError,
+		})?),
+		None => None,
+	};
+
+	// Route to the last hop's source, leaving room for the last hop itself.
+	let mut payment_params = route_params.payment_params.clone();
+	payment_params.payee = Payee::Clear {
+		node_id: last_hop.src_node_id,
+		route_hints: Vec::new(),
+		features: None,
+		final_cltv_expiry_delta: final_cltv_expiry_delta + last_hop.cltv_expiry_delta as u32,
+	};
+	payment_params.max_total_cltv_expiry_delta = payment_params.max_total_cltv_expiry_delta
+		.saturating_sub(last_hop.cltv_expiry_delta as u32);
+	payment_params.max_path_count = 1;
+	payment_params.max_path_length = payment_params.max_path_length.saturating_sub(1);
+	let intermediate_route_params = RouteParameters {
+		payment_params,
+		final_value_msat: amount_msat + last_hop_fee_msat,
+		max_total_routing_fee_msat,
+		max_candidate_hop_evaluations: route_params.max_candidate_hop_evaluations,
+		best_block_height: route_params.best_block_height,
+	};
+	let mut route = router.find_route_with_id(
+		payer, &intermediate_route_params, first_hops, inflight_htlcs, payment_hash, payment_id,
+	)?;
+
+	for path in route.paths.iter_mut() {
+		let hop_to_source = match path.hops.last_mut() {
+			Some(hop) => hop,
+			None => continue,
+		};
+		// Any shadow CLTV offset added to the route is moved to the final hop.
+		let final_hop_cltv_expiry_delta =
+			hop_to_source.cltv_expiry_delta.saturating_sub(last_hop.cltv_expiry_delta as u32);
+		hop_to_source.fee_msat = last_hop_fee_msat;
+		hop_to_source.cltv_expiry_delta = last_hop.cltv_expiry_delta as u32;
+		let node_features = route_params.payment_params.payee.node_features()
+			.unwrap_or_else(default_node_features);
+		path.hops.push(RouteHop {
+			pubkey: *payer,
+			node_features,
+			short_channel_id: last_hop.short_channel_id,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: amount_msat,
+			cltv_expiry_delta: final_hop_cltv_expiry_delta,
+			maybe_announced_channel: false,
+		});
+	}
+	route.route_params = Some(route_params.clone());
+	Ok(route)
+}
+
+/// Finds a [`Route`] for a payment to a [`Payee::Trampoline`].
+///
+/// We only find a single-path route to the trampoline node via the given `router`, paying it the
+/// payment amount plus its maximum trampoline fee, and attach a [`TrampolineTail`] describing the
+/// payee to each path. Fails if the trampoline node does not signal support for trampoline
+/// routing.
+///
+/// If the route isn't for a specific payment, e.g. when probing, `payment_hash_and_id` is `None`
+/// and we use [`Router::find_route`] rather than [`Router::find_route_with_id`].
+pub(crate) fn find_trampoline_route<R: Deref>(
+	router: &R, payer: &PublicKey, route_params: &RouteParameters,
+	first_hops: Option<&[&ChannelDetails]>, inflight_htlcs: InFlightHtlcs,
+	payment_hash_and_id: Option<(PaymentHash, PaymentId)>,
+) -> Result<Route, LightningError>
+where R::Target: Router {
+	let (trampoline_node_id, final_node_id, max_trampoline_fee_msat, max_trampoline_cltv_expiry_delta,
+		final_cltv_expiry_delta) = match &route_params.payment_params.payee
+	{
+		Payee::Trampoline {
+			trampoline_node_id, final_node_id, max_trampoline_fee_msat,
+			max_trampoline_cltv_expiry_delta, final_cltv_expiry_delta,
+		} => (*trampoline_node_id, *final_node_id, *max_trampoline_fee_msat,
+			*max_trampoline_cltv_expiry_delta, *final_cltv_expiry_delta),
+		_ => return Err(LightningError {
+			err: "Trampoline routes can only be found for trampoline payees".to_owned(),
+			action: ErrorAction::IgnoreError,
+		}),
+	};
+	if trampoline_node_id == *payer || final_node_id == *payer {
+		return Err(LightningError {
+			err: "Cannot generate a trampoline route through or to ourselves".to_owned(),
+			action: ErrorAction::IgnoreError,
+		});
+	}
+
+	let amount_msat = route_params.final_value_msat;
+	let trampoline_amount_msat = amount_msat.checked_add(max_trampoline_fee_msat)
+		.filter(|amt| *amt <= MAX_VALUE_MSAT)
+		.ok_or_else(|| LightningError {
+			err: "Cannot generate a route of more value than all existing satoshis".to_owned(),
+			action: ErrorAction::IgnoreError,
+		})?;
+	let max_total_routing_fee_msat = match route_params.max_total_routing_fee_msat {
+		Some(max_fee_msat) => Some(max_fee_msat.checked_sub(max_trampoline_fee_msat).ok_or_else(|| LightningError {
+			err: format!("The maximum trampoline fee of {}msat exceeds the maximum total fee of {}msat",
+				max_trampoline_fee_msat, max_fee_msat),
+			action: ErrorAction::IgnoreError,
+		})?),
+		None => None,
+	};
+
+	// Route to the trampoline node, leaving room for its fee and CLTV expiry delta budgets.
+	let mut payment_params = route_params.payment_params.clone();
+	payment_params.payee = Payee::Clear {
+		node_id: trampoline_node_id,
+		route_hints: Vec::new(),
+		features: None,
+		final_cltv_expiry_delta: final_cltv_expiry_delta.saturating_add(max_trampoline_cltv_expiry_delta),
+	};
+	payment_params.max_path_count = 1;
+	let intermediate_route_params = RouteParameters {
+		payment_params,
+		final_value_msat: trampoline_amount_msat,
+		max_total_routing_fee_msat,
+		max_candidate_hop_evaluations: route_params.max_candidate_hop_evaluations,
+		best_block_height: route_params.best_block_height,
+	};
+	let mut route = match payment_hash_and_id {
+		Some((payment_hash, payment_id)) => router.find_route_with_id(
+			payer, &intermediate_route_params, first_hops, inflight_htlcs, payment_hash, payment_id,
+		)?,
+		None => router.find_route(payer, &intermediate_route_params, first_hops, inflight_htlcs)?,
+	};
+
+	for path in route.paths.iter_mut() {
+		match path.hops.last() {
+			Some(hop) if hop.pubkey == trampoline_node_id => {
+				if !hop.node_features.supports_trampoline_routing() {
+					return Err(LightningError {
+						err: format!("Trampoline node {} does not support trampoline routing", trampoline_node_id),
+						action: ErrorAction::IgnoreError,
+					});
+				}
+			},
+			_ => return Err(LightningError {
+				err: "Router returned a path which does not end at the trampoline node".to_owned(),
+				action: ErrorAction::IgnoreError,
+			}),
+		}
+		path.trampoline_tail = Some(TrampolineTail {
+			final_node_id,
+			final_cltv_expiry_delta,
+			final_value_msat: amount_msat,
+		});
+	}
+	route.route_params = Some(route_params.clone());
 	Ok(route)
 }
 
@@ -1966,10 +2506,14 @@ where L::Target: Logger {
 					return Err(LightningError{err: format!("1-hop blinded paths must all have matching introduction node ids"), action: ErrorAction::IgnoreError});
 				}
 			}
-		}
+		},
+		Payee::Trampoline { .. } => {
+			return Err(LightningError{err: "Routes to trampoline payees must be found to the trampoline node".to_owned(), action: ErrorAction::IgnoreError});
+		},
 	}
 	let final_cltv_expiry_delta = payment_params.payee.final_cltv_expiry_delta().unwrap_or(0);
-	if payment_params.max_total_cltv_expiry_delta <= final_cltv_expiry_delta {
+	let max_total_cltv_expiry_delta = route_params.max_total_cltv_expiry_delta()?;
+	if max_total_cltv_expiry_delta <= final_cltv_expiry_delta {
 		return Err(LightningError{err: "Can't find a route where the maximum total CLTV expiry delta is below the final CLTV expiry.".to_owned(), action: ErrorAction::IgnoreError});
 	}
 
@@ -2032,14 +2576,17 @@ where L::Target: Logger {
 	let network_channels = network_graph.channels();
 	let network_nodes = network_graph.nodes();
 
-	if payment_params.max_path_count == 0 {
+	// The user's override may only lower the number of paths we'd otherwise allow.
+	let max_path_count = cmp::min(payment_params.max_path_count,
+		payment_params.max_path_count_override.unwrap_or(u8::max_value()));
+	if max_path_count == 0 {
 		return Err(LightningError{err: "Can't find a route with no paths allowed.".to_owned(), action: ErrorAction::IgnoreError});
 	}
 
 	// Allow MPP only if we have a features set from somewhere that indicates the payee supports
 	// it. If the payee supports it they're supposed to include it in the invoice, so that should
 	// work reliably.
-	let allow_mpp = if payment_params.max_path_count == 1 {
+	let allow_mpp = if max_path_count == 1 {
 		false
 	} else if payment_params.payee.supports_basic_mpp() {
 		true
@@ -2087,12 +2634,18 @@ where L::Target: Logger {
 			if chan.counterparty.node_id == *our_node_pubkey {
 				return Err(LightningError{err: "First hop cannot have our_node_pubkey as a destination.".to_owned(), action: ErrorAction::IgnoreError});
 			}
+			if !payment_params.allows_first_hop(chan.short_channel_id, chan.outbound_scid_alias) {
+				continue;
+			}
 			first_hop_targets
 				.entry(NodeId::from_pubkey(&chan.counterparty.node_id))
 				.or_insert(Vec::new())
 				.push(chan);
 		}
 		if first_hop_targets.is_empty() {
+			if payment_params.first_hop_channel_ids.is_some() {
+				return Err(LightningError{err: "Cannot route when none of the pinned first hop channels are usable".to_owned(), action: ErrorAction::IgnoreError});
+			}
 			return Err(LightningError{err: "Cannot route when there are no outbound routes away from us".to_owned(), action: ErrorAction::IgnoreError});
 		}
 	}
@@ -2141,9 +2694,13 @@ where L::Target: Logger {
 	// Thus to avoid this effect, we require from our collected links to provide
 	// at least a minimal contribution to the recommended value yet-to-be-fulfilled.
 	// This requirement is currently set to be 1/max_path_count of the payment
-	// value to ensure we only ever return routes that do not violate this limit.
+	// value to ensure we only ever return routes that do not violate this limit, or the minimum
+	// shard amount, if set and higher.
+	let min_shard_amount_msat = cmp::min(payment_params.min_shard_amount_msat.unwrap_or(0), final_value_msat);
 	let minimal_value_contribution_msat: u64 = if allow_mpp {
-		(final_value_msat + (payment_params.max_path_count as u64 - 1)) / payment_params.max_path_count as u64
+		let max_path_count_contribution_msat =
+			(final_value_msat + (max_path_count as u64 - 1)) / max_path_count as u64;
+		cmp::max(max_path_count_contribution_msat, min_shard_amount_msat)
 	} else {
 		final_value_msat
 	};
@@ -2178,10 +2735,15 @@ where L::Target: Logger {
 	let mut num_ignored_path_length_limit: u32 = 0;
 	let mut num_ignored_cltv_delta_limit: u32 = 0;
 	let mut num_ignored_previously_failed: u32 = 0;
+	let mut num_ignored_excluded: u32 = 0;
 	let mut num_ignored_total_fee_limit: u32 = 0;
 	let mut num_ignored_avoid_overpayment: u32 = 0;
 	let mut num_ignored_htlc_minimum_msat_limit: u32 = 0;
 
+	// Count how many candidate hops we evaluated so that we can stop searching once we exceed
+	// `max_candidate_hop_evaluations`, if set.
+	let mut num_candidate_hops_evaluated: u32 = 0;
+
 	macro_rules! add_entry {
 		// Adds entry which goes from $candidate.source() to $candidate.target() over the $candidate hop.
 		// $next_hops_fee_msat represents the fees paid for using all the channels *after* this one,
@@ -2193,6 +2755,7 @@ where L::Target: Logger {
 			// We "return" whether we updated the path at the end, and how much we can route via
 			// this channel, via this:
 			let mut hop_contribution_amt_msat = None;
+			num_candidate_hops_evaluated = num_candidate_hops_evaluated.saturating_add(1);
 			// Channels to self should not be used. This is more of belt-and-suspenders, because in
 			// practice these cases should be caught earlier:
 			// - for regular channels at channel announcement (TODO)
@@ -2230,9 +2793,9 @@ where L::Target: Logger {
 					// In order to already account for some of the privacy enhancing random CLTV
 					// expiry delta offset we add on top later, we subtract a rough estimate
 					// (2*MEDIAN_HOP_CLTV_EXPIRY_DELTA) here.
-					let max_total_cltv_expiry_delta = (payment_params.max_total_cltv_expiry_delta - final_cltv_expiry_delta)
+					let max_total_cltv_expiry_delta = (max_total_cltv_expiry_delta - final_cltv_expiry_delta)
 						.checked_sub(2*MEDIAN_HOP_CLTV_EXPIRY_DELTA)
-						.unwrap_or(payment_params.max_total_cltv_expiry_delta - final_cltv_expiry_delta);
+						.unwrap_or(max_total_cltv_expiry_delta - final_cltv_expiry_delta);
 					let hop_total_cltv_delta = ($next_hops_cltv_delta as u32)
 						.saturating_add($candidate.cltv_expiry_delta());
 					let exceeds_cltv_delta_limit = hop_total_cltv_delta > max_total_cltv_expiry_delta;
@@ -2264,6 +2827,7 @@ where L::Target: Logger {
 							None => false,
 						},
 					};
+					let excluded_from_payment = payment_params.excludes_hop(scid_opt, &src_node_id, $candidate.target().as_ref());
 
 					let (should_log_candidate, first_hop_details) = match $candidate {
 						CandidateRouteHop::FirstHop(hop) => (true, Some(hop.details)),
@@ -2307,6 +2871,11 @@ where L::Target: Logger {
 							log_trace!(logger, "Ignoring {} due to a failed previous payment attempt.", LoggedCandidateHop(&$candidate));
 						}
 						num_ignored_previously_failed += 1;
+					} else if excluded_from_payment {
+						if should_log_candidate {
+							log_trace!(logger, "Ignoring {} due to it being excluded from the payment.", LoggedCandidateHop(&$candidate));
+						}
+						num_ignored_excluded += 1;
 					} else if may_overpay_to_meet_path_minimum_msat {
 						if should_log_candidate {
 							log_trace!(logger,
@@ -2545,7 +3114,9 @@ where L::Target: Logger {
 						let chan = network_channels.get(chan_id).unwrap();
 						if !chan.features.requires_unknown_bits() {
 							if let Some((directed_channel, source)) = chan.as_directed_to(&$node_id) {
-								if first_hops.is_none() || *source != our_node_id {
+								if *source != our_node_id || (first_hops.is_none() &&
+									payment_params.allows_first_hop(Some(*chan_id), None))
+								{
 									if directed_channel.direction().enabled {
 										let candidate = CandidateRouteHop::PublicHop(PublicHopCandidate {
 											info: directed_channel,
@@ -2743,8 +3314,10 @@ where L::Target: Logger {
 					aggregate_next_hops_path_penalty_msat = aggregate_next_hops_path_penalty_msat
 						.saturating_add(channel_penalty_msat);
 
+					// Use the candidate's CLTV expiry delta rather than the hint's, as they may differ
+					// for public channels and the former is what we'll use when building the route.
 					aggregate_next_hops_cltv_delta = aggregate_next_hops_cltv_delta
-						.saturating_add(hop.cltv_expiry_delta as u32);
+						.saturating_add(candidate.cltv_expiry_delta());
 
 					aggregate_next_hops_path_length = aggregate_next_hops_path_length
 						.saturating_add(1);
@@ -2966,6 +3539,16 @@ where L::Target: Logger {
 			// add_entries_to_cheapest_to_target_node!() (see comment there for more info).
 			if node_id == maybe_dummy_payee_node_id { continue 'path_construction; }
 
+			// If we've exhausted our budget of candidate hop evaluations, stop searching for any
+			// further paths and select the best route among the ones we've collected so far.
+			if let Some(max_evaluations) = route_params.max_candidate_hop_evaluations {
+				if num_candidate_hops_evaluated >= max_evaluations {
+					log_trace!(logger, "Stopping route finding after evaluating {} candidate hops, having collected {} msat in paths.",
+						num_candidate_hops_evaluated, already_collected_value_msat);
+					break 'paths_collection;
+				}
+			}
+
 			// Otherwise, since the current target node is not us,
 			// keep "unrolling" the payment graph from payee to payer by
 			// finding a way to reach the current target from the payer side.
@@ -3018,14 +3601,14 @@ where L::Target: Logger {
 	}
 
 	let num_ignored_total = num_ignored_value_contribution + num_ignored_path_length_limit +
-		num_ignored_cltv_delta_limit + num_ignored_previously_failed +
+		num_ignored_cltv_delta_limit + num_ignored_previously_failed + num_ignored_excluded +
 		num_ignored_avoid_overpayment + num_ignored_htlc_minimum_msat_limit +
 		num_ignored_total_fee_limit;
 	if num_ignored_total > 0 {
 		log_trace!(logger,
-			"Ignored {} candidate hops due to insufficient value contribution, {} due to path length limit, {} due to CLTV delta limit, {} due to previous payment failure, {} due to htlc_minimum_msat limit, {} to avoid overpaying, {} due to maximum total fee limit. Total: {} ignored candidates.",
+			"Ignored {} candidate hops due to insufficient value contribution, {} due to path length limit, {} due to CLTV delta limit, {} due to previous payment failure, {} due to being excluded, {} due to htlc_minimum_msat limit, {} to avoid overpaying, {} due to maximum total fee limit. Total: {} ignored candidates.",
 			num_ignored_value_contribution, num_ignored_path_length_limit,
-			num_ignored_cltv_delta_limit, num_ignored_previously_failed,
+			num_ignored_cltv_delta_limit, num_ignored_previously_failed, num_ignored_excluded,
 			num_ignored_htlc_minimum_msat_limit, num_ignored_avoid_overpayment,
 			num_ignored_total_fee_limit, num_ignored_total);
 	}
@@ -3071,7 +3654,8 @@ where L::Target: Logger {
 
 	if overpaid_value_msat != 0 {
 		// Step (7).
-		// Now, subtract the remaining overpaid value from the most-expensive path.
+		// Now, subtract the remaining overpaid value from the most-expensive path, moving on to the
+		// next most-expensive paths if that would take it below the minimum shard amount.
 		// TODO: this could also be optimized by also sorting by feerate_per_sat_routed,
 		// so that the sender pays less fees overall. And also htlc_minimum_msat.
 		selected_route.sort_unstable_by(|a, b| {
@@ -3079,12 +3663,21 @@ where L::Target: Logger {
 			let b_f = b.hops.iter().map(|hop| hop.0.candidate.fees().proportional_millionths as u64).sum::<u64>();
 			a_f.cmp(&b_f).then_with(|| b.get_cost_msat().cmp(&a.get_cost_msat()))
 		});
-		let expensive_payment_path = selected_route.first_mut().unwrap();
-
-		// We already dropped all the paths with value below `overpaid_value_msat` above, thus this
-		// can't go negative.
-		let expensive_path_new_value_msat = expensive_payment_path.get_value_msat() - overpaid_value_msat;
-		expensive_payment_path.update_value_and_recompute_fees(expensive_path_new_value_msat);
+		for expensive_payment_path in selected_route.iter_mut() {
+			if overpaid_value_msat == 0 { break; }
+			// We already dropped all the paths with value below `overpaid_value_msat` above, thus
+			// without a minimum shard amount the first path covers all of it.
+			let reducible_value_msat = expensive_payment_path.get_value_msat()
+				.saturating_sub(min_shard_amount_msat);
+			let reduce_by_msat = cmp::min(reducible_value_msat, overpaid_value_msat);
+			if reduce_by_msat == 0 { continue; }
+			let expensive_path_new_value_msat = expensive_payment_path.get_value_msat() - reduce_by_msat;
+			expensive_payment_path.update_value_and_recompute_fees(expensive_path_new_value_msat);
+			overpaid_value_msat -= reduce_by_msat;
+		}
+		if overpaid_value_msat != 0 {
+			return Err(LightningError{err: "Failed to find a route with paths of at least the minimum shard amount".to_owned(), action: ErrorAction::IgnoreError});
+		}
 	}
 
 	// Step (8).
@@ -3162,10 +3755,10 @@ where L::Target: Logger {
 			core::mem::replace(&mut hop.cltv_expiry_delta, prev_cltv_expiry_delta)
 		});
 
-		paths.push(Path { hops, blinded_tail });
+		paths.push(Path { hops, blinded_tail, trampoline_tail: None });
 	}
 	// Make sure we would never create a route with more paths than we allow.
-	debug_assert!(paths.len() <= payment_params.max_path_count.into());
+	debug_assert!(paths.len() <= max_path_count.into());
 
 	if let Some(node_features) = payment_params.payee.node_features() {
 		for path in paths.iter_mut() {
@@ -3183,6 +3776,17 @@ where L::Target: Logger {
 		}
 	}
 
+	// Make sure we would never create a route with a path exceeding the maximum total CLTV expiry
+	// delta, including any route hints and the final CLTV expiry delta.
+	for path in route.paths.iter() {
+		let path_cltv_expiry_delta = path.hops.iter()
+			.fold(0u32, |total, hop| total.saturating_add(hop.cltv_expiry_delta));
+		if path_cltv_expiry_delta > max_total_cltv_expiry_delta {
+			return Err(LightningError{err: format!("Failed to find route that adheres to the maximum total CLTV expiry delta of {}",
+				max_total_cltv_expiry_delta), action: ErrorAction::IgnoreError});
+		}
+	}
+
 	log_info!(logger, "Got route: {}", log_route!(route));
 	Ok(route)
 }
@@ -3191,7 +3795,7 @@ where L::Target: Logger {
 // destination, if the remaining CLTV expiry delta exactly matches a feasible path in the network
 // graph. In order to improve privacy, this method obfuscates the CLTV expiry deltas along the
 // payment path by adding a randomized 'shadow route' offset to the final hop.
-fn add_random_cltv_offset(route: &mut Route, payment_params: &PaymentParameters,
+fn add_random_cltv_offset(route: &mut Route, max_total_cltv_expiry_delta: u32,
 	network_graph: &ReadOnlyNetworkGraph, random_seed_bytes: &[u8; 32]
 ) {
 	let network_channels = network_graph.channels();
@@ -3259,7 +3863,7 @@ fn add_random_cltv_offset(route: &mut Route, payment_params: &PaymentParameters,
 		// Limit the offset so we never exceed the max_total_cltv_expiry_delta. To improve plausibility,
 		// we choose the limit to be the largest possible multiple of MEDIAN_HOP_CLTV_EXPIRY_DELTA.
 		let path_total_cltv_expiry_delta: u32 = path.hops.iter().map(|h| h.cltv_expiry_delta).sum();
-		let mut max_path_offset = payment_params.max_total_cltv_expiry_delta - path_total_cltv_expiry_delta;
+		let mut max_path_offset = max_total_cltv_expiry_delta.saturating_sub(path_total_cltv_expiry_delta);
 		max_path_offset = cmp::max(
 			max_path_offset - (max_path_offset % MEDIAN_HOP_CLTV_EXPIRY_DELTA),
 			max_path_offset % MEDIAN_HOP_CLTV_EXPIRY_DELTA);
@@ -3289,7 +3893,8 @@ where L::Target: Logger, GL::Target: Logger {
 	let graph_lock = network_graph.read_only();
 	let mut route = build_route_from_hops_internal(our_node_pubkey, hops, &route_params,
 		&graph_lock, logger, random_seed_bytes)?;
-	add_random_cltv_offset(&mut route, &route_params.payment_params, &graph_lock, random_seed_bytes);
+	add_random_cltv_offset(&mut route, route_params.max_total_cltv_expiry_delta()?, &graph_lock,
+		random_seed_bytes);
 	Ok(route)
 }
 
@@ -5987,7 +6592,7 @@ mod tests {
 		{
 			// Attempt to route while setting max_total_routing_fee_msat to 149_999 results in a failure.
 			let route_params = RouteParameters { payment_params: payment_params.clone(), final_value_msat: 200_000,
-				max_total_routing_fee_msat: Some(149_999) };
+				max_total_routing_fee_msat: Some(149_999), max_candidate_hop_evaluations: None, best_block_height: None };
 			if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(
 				&our_id, &route_params, &network_graph.read_only(), None, Arc::clone(&logger),
 				&scorer, &Default::default(), &random_seed_bytes) {
@@ -5998,7 +6603,7 @@ mod tests {
 		{
 			// Now, attempt to route 200 sats (exact amount we can route).
 			let route_params = RouteParameters { payment_params: payment_params.clone(), final_value_msat: 200_000,
-				max_total_routing_fee_msat: Some(150_000) };
+				max_total_routing_fee_msat: Some(150_000), max_candidate_hop_evaluations: None, best_block_height: None };
 			let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
 				Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
 			assert_eq!(route.paths.len(), 2);
@@ -6654,6 +7259,220 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn pinned_first_hops() {
+		// Tests that setting `PaymentParameters::first_hop_channel_ids` restricts the first hop of
+		// each path to the given channels, both when routing over `first_hops` and over our channels
+		// in the network graph, while still allowing MPP across the pinned channels.
+		let secp_ctx = Secp256k1::new();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let logger = Arc::new(ln_test_utils::TestLogger::new());
+		let network_graph = NetworkGraph::new(Network::Testnet, Arc::clone(&logger));
+		let scorer = ln_test_utils::TestScorer::new();
+		let config = UserConfig::default();
+		let payment_params = PaymentParameters::from_node_id(nodes[0], 42)
+			.with_bolt11_features(channelmanager::provided_bolt11_invoice_features(&config))
+			.unwrap();
+		let random_seed_bytes = [42; 32];
+		let first_hops = [
+			get_channel_details(Some(2), nodes[0], channelmanager::provided_init_features(&config), 200_000),
+			get_channel_details(Some(3), nodes[0], channelmanager::provided_init_features(&config), 60_000),
+			get_channel_details(Some(5), nodes[0], channelmanager::provided_init_features(&config), 60_000),
+		];
+		let first_hops = first_hops.iter().collect::<Vec<_>>();
+
+		{
+			// Unpinned, the single channel which can carry the full payment is used.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone(), 100_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 1);
+			assert_eq!(route.paths[0].hops[0].short_channel_id, 2);
+		}
+		{
+			// Once pinned to channel 3, it is used even though channel 2 would be preferred.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_first_hop_channel_ids(vec![3]), 50_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 1);
+			assert_eq!(route.paths[0].hops[0].short_channel_id, 3);
+			assert_eq!(route.paths[0].hops[0].fee_msat, 50_000);
+		}
+		{
+			// MPP may still split the payment across multiple pinned channels.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_first_hop_channel_ids(vec![3, 5]), 100_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 2);
+			let mut scids = route.paths.iter()
+				.map(|path| path.hops[0].short_channel_id).collect::<Vec<_>>();
+			scids.sort_unstable();
+			assert_eq!(scids, vec![3, 5]);
+			assert_eq!(route.get_total_amount(), 100_000);
+		}
+		{
+			// If the pinned channels lack the capacity for the payment, routing fails.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_first_hop_channel_ids(vec![3]), 100_000);
+			assert!(get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).is_err());
+		}
+		{
+			// As does pinning only channels we don't have.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.with_first_hop_channel_ids(vec![42]), 50_000);
+			if let Err(LightningError { err, .. }) = get_route(&our_id, &route_params,
+				&network_graph.read_only(), Some(&first_hops), Arc::clone(&logger), &scorer,
+				&Default::default(), &random_seed_bytes)
+			{
+				assert_eq!(err, "Cannot route when none of the pinned first hop channels are usable");
+			} else { panic!(); }
+		}
+
+		// Without `first_hops`, our channels in the network graph are restricted instead.
+		let (secp_ctx, network_graph, _, _, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let payment_params = PaymentParameters::from_node_id(nodes[2], 42);
+		let route_params = RouteParameters::from_payment_params_and_value(
+			payment_params.clone(), 100);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert_ne!(route.paths[0].hops[0].short_channel_id, 12);
+
+		let route_params = RouteParameters::from_payment_params_and_value(
+			payment_params.with_first_hop_channel_ids(vec![12]), 100);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		let path = route.paths[0].hops.iter().map(|hop| hop.short_channel_id).collect::<Vec<_>>();
+		assert_eq!(path, vec![12, 13]);
+	}
+
+	#[test]
+	fn mpp_shard_limits() {
+		// Tests that `PaymentParameters::max_path_count`, `max_path_count_override` and
+		// `min_shard_amount_msat` bound how a payment is split, failing to route rather than
+		// exceeding any limit.
+		let secp_ctx = Secp256k1::new();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let logger = Arc::new(ln_test_utils::TestLogger::new());
+		let network_graph = NetworkGraph::new(Network::Testnet, Arc::clone(&logger));
+		let scorer = ln_test_utils::TestScorer::new();
+		let config = UserConfig::default();
+		let payment_params = PaymentParameters::from_node_id(nodes[0], 42)
+			.with_bolt11_features(channelmanager::provided_bolt11_invoice_features(&config))
+			.unwrap();
+		let random_seed_bytes = [42; 32];
+		let first_hops = [
+			get_channel_details(Some(2), nodes[0], channelmanager::provided_init_features(&config), 50_000),
+			get_channel_details(Some(3), nodes[0], channelmanager::provided_init_features(&config), 50_000),
+			get_channel_details(Some(4), nodes[0], channelmanager::provided_init_features(&config), 50_000),
+			get_channel_details(Some(5), nodes[0], channelmanager::provided_init_features(&config), 50_000),
+			get_channel_details(Some(6), nodes[0], channelmanager::provided_init_features(&config), 30_000),
+		];
+		let first_hops = first_hops.iter().collect::<Vec<_>>();
+
+		{
+			// Paying 180k sats requires at least four shards.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone(), 180_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert!(route.paths.len() >= 4);
+			assert_eq!(route.get_total_amount(), 180_000);
+
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_max_path_count(4), 180_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 4);
+			assert_eq!(route.get_total_amount(), 180_000);
+
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_max_path_count_override(4), 180_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 4);
+			assert_eq!(route.get_total_amount(), 180_000);
+		}
+		{
+			// Capped at two shards, it can't be routed.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_max_path_count(2), 180_000);
+			if let Err(LightningError { err, .. }) = get_route(&our_id, &route_params,
+				&network_graph.read_only(), Some(&first_hops), Arc::clone(&logger), &scorer,
+				&Default::default(), &random_seed_bytes)
+			{
+				assert_eq!(err, "Failed to find a path to the given destination");
+			} else { panic!(); }
+
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_max_path_count_override(2), 180_000);
+			if let Err(LightningError { err, .. }) = get_route(&our_id, &route_params,
+				&network_graph.read_only(), Some(&first_hops), Arc::clone(&logger), &scorer,
+				&Default::default(), &random_seed_bytes)
+			{
+				assert_eq!(err, "Failed to find a path to the given destination");
+			} else { panic!(); }
+
+			// The override can't raise the limit.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_max_path_count(2).with_max_path_count_override(4), 180_000);
+			assert!(get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).is_err());
+		}
+		{
+			// Paying 210k sats requires using the 30k sat channel, which a minimum shard amount of
+			// 35k sats rules out.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone(), 210_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 5);
+
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_min_shard_amount_msat(35_000), 210_000);
+			assert!(get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).is_err());
+		}
+		{
+			// Paying 120k sats over three 50k sat shards overpays by 30k sats, which must be taken
+			// from more than one shard to keep them all at or above the minimum shard amount.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.clone().with_min_shard_amount_msat(40_000), 120_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 3);
+			assert_eq!(route.get_total_amount(), 120_000);
+			for path in route.paths.iter() {
+				assert_eq!(path.final_value_msat(), 40_000);
+				assert_ne!(path.hops[0].short_channel_id, 6);
+			}
+
+			// Smaller payments are sent over a single path, regardless of the minimum shard amount.
+			let route_params = RouteParameters::from_payment_params_and_value(
+				payment_params.with_min_shard_amount_msat(40_000), 30_000);
+			let route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				Some(&first_hops), Arc::clone(&logger), &scorer, &Default::default(),
+				&random_seed_bytes).unwrap();
+			assert_eq!(route.paths.len(), 1);
+			assert_eq!(route.get_total_amount(), 30_000);
+		}
+	}
+
 	#[test]
 	fn prefers_shorter_route_with_higher_fees() {
 		let (secp_ctx, network_graph, _, _, logger) = build_graph();
@@ -6778,7 +7597,7 @@ mod tests {
 					channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
 					short_channel_id: 0, fee_msat: 225, cltv_expiry_delta: 0, maybe_announced_channel: true,
 				},
-			], blinded_tail: None }],
+			], blinded_tail: None, trampoline_tail: None }],
 			route_params: None,
 		};
 
@@ -6800,7 +7619,7 @@ mod tests {
 					channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
 					short_channel_id: 0, fee_msat: 150, cltv_expiry_delta: 0, maybe_announced_channel: true,
 				},
-			], blinded_tail: None }, Path { hops: vec![
+			], blinded_tail: None, trampoline_tail: None }, Path { hops: vec![
 				RouteHop {
 					pubkey: PublicKey::from_slice(&<Vec<u8>>::from_hex("02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619").unwrap()[..]).unwrap(),
 					channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
@@ -6811,7 +7630,7 @@ mod tests {
 					channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
 					short_channel_id: 0, fee_msat: 150, cltv_expiry_delta: 0, maybe_announced_channel: true,
 				},
-			], blinded_tail: None }],
+			], blinded_tail: None, trampoline_tail: None }],
 			route_params: None,
 		};
 
@@ -6901,6 +7720,203 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn avoids_excluded_hops() {
+		// Ensure that the router never routes through nodes or channels excluded via
+		// `PaymentParameters::with_excluded_hops`, even if they'd offer the cheapest path.
+		let (secp_ctx, network_graph, _, _, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let scorer = ln_test_utils::TestScorer::new();
+		let random_seed_bytes = [42; 32];
+
+		// By default, the cheapest path to nodes[2] goes through nodes[1].
+		let payment_params = PaymentParameters::from_node_id(nodes[2], 42);
+		let route_params = RouteParameters::from_payment_params_and_value(payment_params.clone(), 100);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert_eq!(route.paths.len(), 1);
+		assert_eq!(route.paths[0].hops[0].pubkey, nodes[1]);
+		let cheapest_fees_msat = route.get_total_fees();
+
+		// Once nodes[1] is excluded we have to fall back to a more expensive path.
+		let excluded_node_params = payment_params.clone()
+			.with_excluded_hops(vec![NodeId::from_pubkey(&nodes[1])], Vec::new());
+		let route_params = RouteParameters::from_payment_params_and_value(excluded_node_params, 100);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert_eq!(route.paths.len(), 1);
+		assert!(route.paths[0].hops.iter().all(|hop| hop.pubkey != nodes[1]));
+		assert!(route.get_total_fees() > cheapest_fees_msat);
+
+		// Excluding the channel from nodes[1] to nodes[2] likewise avoids it.
+		let excluded_chan_params = payment_params.with_excluded_hops(Vec::new(), vec![4]);
+		let route_params = RouteParameters::from_payment_params_and_value(excluded_chan_params, 100);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert!(route.paths[0].hops.iter().all(|hop| hop.short_channel_id != 4));
+		assert!(route.get_total_fees() > cheapest_fees_msat);
+
+		// Route hints over an excluded channel are dropped entirely.
+		let payment_params = PaymentParameters::from_node_id(nodes[6], 42)
+			.with_route_hints(last_hops(&nodes)).unwrap();
+		let route_params = RouteParameters::from_payment_params_and_value(payment_params.clone(), 100);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert_eq!(route.paths[0].hops.last().unwrap().short_channel_id, 8);
+
+		let excluded_hint_params = payment_params.with_excluded_hops(Vec::new(), vec![8]);
+		let route_params = RouteParameters::from_payment_params_and_value(excluded_hint_params, 100);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert!(route.paths[0].hops.iter().all(|hop| hop.short_channel_id != 8));
+		assert_eq!(route.paths[0].hops.last().unwrap().short_channel_id, 10);
+	}
+
+	#[test]
+	fn limits_candidate_hop_evaluations() {
+		// Ensure that a generous `max_candidate_hop_evaluations` budget doesn't change the route we
+		// find, while an exhausted budget stops the search.
+		let (secp_ctx, network_graph, _, _, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let scorer = ln_test_utils::TestScorer::new();
+		let random_seed_bytes = [42; 32];
+		let config = UserConfig::default();
+
+		let mpp_payment_params = PaymentParameters::from_node_id(nodes[2], 42)
+			.with_bolt11_features(channelmanager::provided_bolt11_invoice_features(&config)).unwrap();
+		let hints_payment_params = PaymentParameters::from_node_id(nodes[6], 42)
+			.with_route_hints(last_hops(&nodes)).unwrap();
+		// Limit our channels to a few small ones to nodes[0] to force splitting MPP payments.
+		let mpp_first_hops = [
+			get_channel_details(Some(200), nodes[0], channelmanager::provided_init_features(&config), 50_000),
+			get_channel_details(Some(201), nodes[0], channelmanager::provided_init_features(&config), 50_000),
+			get_channel_details(Some(202), nodes[0], channelmanager::provided_init_features(&config), 50_000),
+		];
+		let mpp_first_hops = mpp_first_hops.iter().collect::<Vec<_>>();
+		let test_cases = [
+			(PaymentParameters::from_node_id(nodes[2], 42), 100, None),
+			(mpp_payment_params.clone(), 100, None),
+			(mpp_payment_params, 120_000, Some(&mpp_first_hops[..])),
+			(hints_payment_params, 100, None),
+		];
+		for (payment_params, amount_msat, first_hops) in test_cases {
+			let route_params = RouteParameters::from_payment_params_and_value(payment_params, amount_msat);
+			let unbounded_route = get_route(&our_id, &route_params, &network_graph.read_only(),
+				first_hops, Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+
+			let budgeted_route_params = route_params.with_max_candidate_hop_evaluations(100_000);
+			let budgeted_route = get_route(&our_id, &budgeted_route_params, &network_graph.read_only(),
+				first_hops, Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+			assert_eq!(unbounded_route.paths, budgeted_route.paths);
+
+			// If we can't evaluate any candidate hops beyond the initial ones we fail to find a path.
+			let exhausted_route_params = budgeted_route_params.with_max_candidate_hop_evaluations(1);
+			match get_route(&our_id, &exhausted_route_params, &network_graph.read_only(), first_hops,
+				Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes)
+			{
+				Err(LightningError { err, .. }) => {
+					assert_eq!(err, "Failed to find a path to the given destination");
+				},
+				Ok(_) => panic!("Expected error"),
+			}
+		}
+	}
+
+	#[test]
+	fn limits_total_cltv_delta_of_route_hints() {
+		// Ensure that route hints understating the CLTV expiry delta of public channels can't be
+		// used to slip past `max_total_cltv_expiry_delta`, as we'll use the delta from the network
+		// graph when building the route.
+		let (secp_ctx, network, _, _, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let network_graph = network.read_only();
+		let scorer = ln_test_utils::TestScorer::new();
+		let random_seed_bytes = [42; 32];
+
+		let understated_hop = |src_node_id, short_channel_id| RouteHintHop {
+			src_node_id, short_channel_id,
+			fees: RoutingFees { base_msat: 0, proportional_millionths: 0 },
+			cltv_expiry_delta: 0,
+			htlc_minimum_msat: None,
+			htlc_maximum_msat: None,
+		};
+		// A long hint chain over public channels, ending in the private channel 8 to nodes[6].
+		let route_hint = RouteHint(vec![
+			understated_hop(nodes[1], 4), understated_hop(nodes[2], 6), understated_hop(nodes[4], 11),
+			understated_hop(nodes[3], 8),
+		]);
+		let payment_params = PaymentParameters::from_node_id(nodes[6], 42)
+			.with_route_hints(vec![route_hint]).unwrap();
+
+		// Without a restrictive limit we route along the hint, using the real deltas.
+		let route_params = RouteParameters::from_payment_params_and_value(payment_params.clone(), 100);
+		let route = get_route(&our_id, &route_params, &network_graph, None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert_eq!(route.paths.len(), 1);
+		let scids = route.paths[0].hops.iter().map(|hop| hop.short_channel_id).collect::<Vec<_>>();
+		assert_eq!(scids, vec![2, 4, 6, 11, 8]);
+		let path_total_cltv_expiry_delta: u32 = route.paths[0].hops.iter()
+			.map(|hop| hop.cltv_expiry_delta).sum();
+		assert_eq!(path_total_cltv_expiry_delta, ((4 << 4) | 1) + ((6 << 4) | 1) + ((11 << 4) | 1) + 42);
+
+		// Previously, the hint's deltas were used when checking against the limit, letting this
+		// route through even though its total delta exceeds the limit.
+		let limited_payment_params = payment_params.with_max_total_cltv_expiry_delta(300);
+		let route_params = RouteParameters::from_payment_params_and_value(limited_payment_params, 100);
+		match get_route(&our_id, &route_params, &network_graph, None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes)
+		{
+			Err(LightningError { err, .. }) => {
+				assert_eq!(err, "Failed to find a path to the given destination");
+			},
+			Ok(route) => panic!("Expected error, got route {:?}", route),
+		}
+	}
+
+	#[test]
+	fn limits_absolute_cltv_expiry_height() {
+		let (secp_ctx, network_graph, _, _, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let scorer = ln_test_utils::TestScorer::new();
+		let random_seed_bytes = [42; 32];
+		let best_block_height = 1000;
+
+		let expect_route_err = |route_params: &RouteParameters, expected_err: &str| {
+			match get_route(&our_id, route_params, &network_graph.read_only(), None,
+				Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes)
+			{
+				Err(LightningError { err, .. }) => assert_eq!(err, expected_err),
+				Ok(_) => panic!("Expected error"),
+			}
+		};
+
+		// An absolute limit is equivalent to a relative one from the next block.
+		let payment_params = PaymentParameters::from_node_id(nodes[2], 42)
+			.with_max_absolute_cltv_expiry_height(best_block_height + 1 + 300);
+		let mut route_params = RouteParameters::from_payment_params_and_value(payment_params.clone(), 100);
+		route_params.best_block_height = Some(best_block_height);
+		let route = get_route(&our_id, &route_params, &network_graph.read_only(), None,
+			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		let relative_route_params = RouteParameters::from_payment_params_and_value(
+			PaymentParameters::from_node_id(nodes[2], 42).with_max_total_cltv_expiry_delta(300), 100);
+		let relative_route = get_route(&our_id, &relative_route_params, &network_graph.read_only(),
+			None, Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
+		assert_eq!(route.paths, relative_route.paths);
+
+		// The absolute limit applies even if the relative one is looser, including the final CLTV
+		// expiry delta.
+		route_params.best_block_height = Some(best_block_height + 300 - 42);
+		expect_route_err(&route_params,
+			"Can't find a route where the maximum total CLTV expiry delta is below the final CLTV expiry.");
+
+		// Without the current best block height or past the limit, we can't route at all.
+		route_params.best_block_height = Some(best_block_height + 301);
+		expect_route_err(&route_params, "The maximum absolute CLTV expiry height has already passed");
+		route_params.best_block_height = None;
+		expect_route_err(&route_params,
+			"Can't enforce the maximum absolute CLTV expiry height without the current best block height");
+	}
+
 	#[test]
 	fn limits_path_length() {
 		let (secp_ctx, network, _, _, logger) = build_line_graph();
@@ -6952,7 +7968,7 @@ mod tests {
 
 		// Check whether the offset added to the last hop by default is in [1 .. DEFAULT_MAX_TOTAL_CLTV_EXPIRY_DELTA]
 		let mut route_default = route.clone();
-		add_random_cltv_offset(&mut route_default, &payment_params, &network_graph.read_only(), &random_seed_bytes);
+		add_random_cltv_offset(&mut route_default, payment_params.max_total_cltv_expiry_delta, &network_graph.read_only(), &random_seed_bytes);
 		let cltv_expiry_deltas_default = route_default.paths[0].hops.iter().map(|h| h.cltv_expiry_delta).collect::<Vec<u32>>();
 		assert_eq!(cltv_expiry_deltas_before.split_last().unwrap().1, cltv_expiry_deltas_default.split_last().unwrap().1);
 		assert!(cltv_expiry_deltas_default.last() > cltv_expiry_deltas_before.last());
@@ -6962,7 +7978,7 @@ mod tests {
 		let mut route_limited = route.clone();
 		let limited_max_total_cltv_expiry_delta = cltv_expiry_deltas_before.iter().sum();
 		let limited_payment_params = payment_params.with_max_total_cltv_expiry_delta(limited_max_total_cltv_expiry_delta);
-		add_random_cltv_offset(&mut route_limited, &limited_payment_params, &network_graph.read_only(), &random_seed_bytes);
+		add_random_cltv_offset(&mut route_limited, limited_payment_params.max_total_cltv_expiry_delta, &network_graph.read_only(), &random_seed_bytes);
 		let cltv_expiry_deltas_limited = route_limited.paths[0].hops.iter().map(|h| h.cltv_expiry_delta).collect::<Vec<u32>>();
 		assert_eq!(cltv_expiry_deltas_before, cltv_expiry_deltas_limited);
 	}
@@ -6982,7 +7998,7 @@ mod tests {
 			payment_params.clone(), 100);
 		let mut route = get_route(&our_id, &route_params, &network_graph, None,
 			Arc::clone(&logger), &scorer, &Default::default(), &random_seed_bytes).unwrap();
-		add_random_cltv_offset(&mut route, &payment_params, &network_graph, &random_seed_bytes);
+		add_random_cltv_offset(&mut route, payment_params.max_total_cltv_expiry_delta, &network_graph, &random_seed_bytes);
 
 		let mut path_plausibility = vec![];
 
@@ -7414,7 +8430,8 @@ mod tests {
 				blinding_point: blinded_path_1.blinding_point,
 				excess_final_cltv_expiry_delta: 40,
 				final_value_msat: 100,
-			})}, Path {
+			}),
+			trampoline_tail: None}, Path {
 			hops: vec![RouteHop {
 				pubkey: ln_test_utils::pubkey(51),
 				node_features: NodeFeatures::empty(),
@@ -7423,7 +8440,7 @@ mod tests {
 				fee_msat: 100,
 				cltv_expiry_delta: 0,
 				maybe_announced_channel: true,
-			}], blinded_tail: None }],
+			}], blinded_tail: None, trampoline_tail: None }],
 			route_params: None,
 		};
 		let encoded_route = route.encode();
@@ -7479,6 +8496,7 @@ mod tests {
 				excess_final_cltv_expiry_delta: 0,
 				final_value_msat: 200,
 			}),
+			trampoline_tail: None,
 		};
 		inflight_htlcs.process_path(&path, ln_test_utils::pubkey(44));
 		assert_eq!(*inflight_htlcs.0.get(&(42, true)).unwrap(), 301);
@@ -7522,11 +8540,12 @@ mod tests {
 				excess_final_cltv_expiry_delta: 0,
 				final_value_msat: 200,
 			}),
+			trampoline_tail: None,
 		}], route_params: None};
 
 		let payment_params = PaymentParameters::from_node_id(ln_test_utils::pubkey(47), 18);
 		let (_, network_graph, _, _, _) = build_line_graph();
-		add_random_cltv_offset(&mut route, &payment_params, &network_graph.read_only(), &[0; 32]);
+		add_random_cltv_offset(&mut route, payment_params.max_total_cltv_expiry_delta, &network_graph.read_only(), &[0; 32]);
 		assert_eq!(route.paths[0].blinded_tail.as_ref().unwrap().excess_final_cltv_expiry_delta, 40);
 		assert_eq!(route.paths[0].hops.last().unwrap().cltv_expiry_delta, 40);
 	}
@@ -8654,7 +9673,7 @@ pub mod benches {
 		let (network_graph, _) = bench_utils::read_graph_scorer(&logger).unwrap();
 		let scorer = FixedPenaltyScorer::with_penalty(0);
 		generate_routes(bench, &network_graph, scorer, &Default::default(),
-			Bolt11InvoiceFeatures::empty(), 0, "generate_routes_with_zero_penalty_scorer");
+			Bolt11InvoiceFeatures::empty(), 0, "generate_routes_with_zero_penalty_scorer", None);
 	}
 
 	pub fn generate_mpp_routes_with_zero_penalty_scorer(bench: &mut Criterion) {
@@ -8663,7 +9682,7 @@ pub mod benches {
 		let scorer = FixedPenaltyScorer::with_penalty(0);
 		generate_routes(bench, &network_graph, scorer, &Default::default(),
 			channelmanager::provided_bolt11_invoice_features(&UserConfig::default()), 0,
-			"generate_mpp_routes_with_zero_penalty_scorer");
+			"generate_mpp_routes_with_zero_penalty_scorer", None);
 	}
 
 	pub fn generate_routes_with_probabilistic_scorer(bench: &mut Criterion) {
@@ -8671,7 +9690,7 @@ pub mod benches {
 		let (network_graph, scorer) = bench_utils::read_graph_scorer(&logger).unwrap();
 		let params = ProbabilisticScoringFeeParameters::default();
 		generate_routes(bench, &network_graph, scorer, &params, Bolt11InvoiceFeatures::empty(), 0,
-			"generate_routes_with_probabilistic_scorer");
+			"generate_routes_with_probabilistic_scorer", None);
 	}
 
 	pub fn generate_mpp_routes_with_probabilistic_scorer(bench: &mut Criterion) {
@@ -8680,7 +9699,7 @@ pub mod benches {
 		let params = ProbabilisticScoringFeeParameters::default();
 		generate_routes(bench, &network_graph, scorer, &params,
 			channelmanager::provided_bolt11_invoice_features(&UserConfig::default()), 0,
-			"generate_mpp_routes_with_probabilistic_scorer");
+			"generate_mpp_routes_with_probabilistic_scorer", None);
 	}
 
 	pub fn generate_large_mpp_routes_with_probabilistic_scorer(bench: &mut Criterion) {
@@ -8689,7 +9708,16 @@ pub mod benches {
 		let params = ProbabilisticScoringFeeParameters::default();
 		generate_routes(bench, &network_graph, scorer, &params,
 			channelmanager::provided_bolt11_invoice_features(&UserConfig::default()), 100_000_000,
-			"generate_large_mpp_routes_with_probabilistic_scorer");
+			"generate_large_mpp_routes_with_probabilistic_scorer", None);
+	}
+
+	pub fn generate_budgeted_large_mpp_routes_with_probabilistic_scorer(bench: &mut Criterion) {
+		let logger = TestLogger::new();
+		let (network_graph, scorer) = bench_utils::read_graph_scorer(&logger).unwrap();
+		let params = ProbabilisticScoringFeeParameters::default();
+		generate_routes(bench, &network_graph, scorer, &params,
+			channelmanager::provided_bolt11_invoice_features(&UserConfig::default()), 100_000_000,
+			"generate_budgeted_large_mpp_routes_with_probabilistic_scorer", Some(10_000));
 	}
 
 	pub fn generate_routes_with_nonlinear_probabilistic_scorer(bench: &mut Criterion) {
@@ -8699,7 +9727,7 @@ pub mod benches {
 		params.linear_success_probability = false;
 		generate_routes(bench, &network_graph, scorer, &params,
 			channelmanager::provided_bolt11_invoice_features(&UserConfig::default()), 0,
-			"generate_routes_with_nonlinear_probabilistic_scorer");
+			"generate_routes_with_nonlinear_probabilistic_scorer", None);
 	}
 
 	pub fn generate_mpp_routes_with_nonlinear_probabilistic_scorer(bench: &mut Criterion) {
@@ -8709,7 +9737,7 @@ pub mod benches {
 		params.linear_success_probability = false;
 		generate_routes(bench, &network_graph, scorer, &params,
 			channelmanager::provided_bolt11_invoice_features(&UserConfig::default()), 0,
-			"generate_mpp_routes_with_nonlinear_probabilistic_scorer");
+			"generate_mpp_routes_with_nonlinear_probabilistic_scorer", None);
 	}
 
 	pub fn generate_large_mpp_routes_with_nonlinear_probabilistic_scorer(bench: &mut Criterion) {
@@ -8719,19 +9747,20 @@ pub mod benches {
 		params.linear_success_probability = false;
 		generate_routes(bench, &network_graph, scorer, &params,
 			channelmanager::provided_bolt11_invoice_features(&UserConfig::default()), 100_000_000,
-			"generate_large_mpp_routes_with_nonlinear_probabilistic_scorer");
+			"generate_large_mpp_routes_with_nonlinear_probabilistic_scorer", None);
 	}
 
 	fn generate_routes<S: ScoreLookUp + ScoreUpdate>(
 		bench: &mut Criterion, graph: &NetworkGraph<&TestLogger>, mut scorer: S,
 		score_params: &S::ScoreParams, features: Bolt11InvoiceFeatures, starting_amount: u64,
-		bench_name: &'static str,
+		bench_name: &'static str, max_candidate_hop_evaluations: Option<u32>,
 	) {
 		// First, get 100 (source, destination) pairs for which route-getting actually succeeds...
 		let route_endpoints = bench_utils::generate_test_routes(graph, &mut scorer, score_params, features, 0xdeadbeef, starting_amount, 50);
 
 		// ...then benchmark finding paths between the nodes we learned.
-		do_route_bench(bench, graph, scorer, score_params, bench_name, route_endpoints);
+		do_route_bench(bench, graph, scorer, score_params, bench_name, route_endpoints,
+			max_candidate_hop_evaluations);
 	}
 
 	#[inline(never)]
@@ -8739,6 +9768,7 @@ pub mod benches {
 		bench: &mut Criterion, graph: &NetworkGraph<&TestLogger>, scorer: S,
 		score_params: &S::ScoreParams, bench_name: &'static str,
 		route_endpoints: Vec<(ChannelDetails, PaymentParameters, u64)>,
+		max_candidate_hop_evaluations: Option<u32>,
 	) {
 		let payer = bench_utils::payer_pubkey();
 		let random_seed_bytes = [42; 32];
@@ -8746,9 +9776,12 @@ pub mod benches {
 		let mut idx = 0;
 		bench.bench_function(bench_name, |b| b.iter(|| {
 			let (first_hop, params, amt) = &route_endpoints[idx % route_endpoints.len()];
-			let route_params = RouteParameters::from_payment_params_and_value(params.clone(), *amt);
-			assert!(get_route(&payer, &route_params, &graph.read_only(), Some(&[first_hop]),
-				&DummyLogger{}, &scorer, score_params, &random_seed_bytes).is_ok());
+			let mut route_params = RouteParameters::from_payment_params_and_value(params.clone(), *amt);
+			route_params.max_candidate_hop_evaluations = max_candidate_hop_evaluations;
+			let res = get_route(&payer, &route_params, &graph.read_only(), Some(&[first_hop]),
+				&DummyLogger{}, &scorer, score_params, &random_seed_bytes);
+			// With a limited budget we may fail to find a route.
+			assert!(res.is_ok() || max_candidate_hop_evaluations.is_some());
 			idx += 1;
 		}));
 	}
@@ -530,7 +530,9 @@ impl SpendableOutputDescriptor {
 			witness_weight,
 			feerate_sat_per_1000_weight,
 			change_destination_script,
-		)?;
+		)
+		.map_err(|_| ())?
+		.predicted_weight;
 
 		let psbt_inputs =
 			descriptors.iter().map(|d| d.to_psbt_input(&secp_ctx)).collect::<Vec<_>>();
@@ -10,8 +10,10 @@
 //! Various user-configurable channel limits and settings which ChannelManager
 //! applies for you.
 
+use crate::ln::chan_utils::MAX_HTLCS;
 use crate::ln::channel::MAX_FUNDING_SATOSHIS_NO_WUMBO;
 use crate::ln::channelmanager::{BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT};
+use crate::util::errors::APIError;
 
 #[cfg(fuzzing)]
 use crate::util::ser::Readable;
@@ -520,6 +522,17 @@ pub struct ChannelConfig {
 	///
 	/// [`ConfirmationTarget::OnChainSweep`]: crate::chain::chaininterface::ConfirmationTarget::OnChainSweep
 	pub max_dust_htlc_exposure: MaxDustHTLCExposure,
+	/// The maximum total value (in msat) of inbound HTLCs we'll accept being in-flight on the
+	/// channel at once.
+	///
+	/// Unlike [`ChannelHandshakeConfig::max_inbound_htlc_value_in_flight_percent_of_channel`],
+	/// which is communicated to our counterparty at channel open and can't be changed
+	/// afterwards, this limit is enforced locally and may be changed at any time. Any newly
+	/// received HTLC which would put the total value of inbound HTLCs over this limit is failed
+	/// back, while HTLCs already pending are unaffected.
+	///
+	/// Default value: `u64::MAX`, i.e., only the limit negotiated at channel open applies.
+	pub max_inbound_htlc_value_in_flight_msat: u64,
 	/// The additional fee we're willing to pay to avoid waiting for the counterparty's
 	/// `to_self_delay` to reclaim funds.
 	///
@@ -593,6 +606,9 @@ impl ChannelConfig {
 		if let Some(max_dust_htlc_exposure_msat) = update.max_dust_htlc_exposure_msat {
 			self.max_dust_htlc_exposure = max_dust_htlc_exposure_msat;
 		}
+		if let Some(max_inbound_htlc_value_in_flight_msat) = update.max_inbound_htlc_value_in_flight_msat {
+			self.max_inbound_htlc_value_in_flight_msat = max_inbound_htlc_value_in_flight_msat;
+		}
 		if let Some(force_close_avoidance_max_fee_satoshis) = update.force_close_avoidance_max_fee_satoshis {
 			self.force_close_avoidance_max_fee_satoshis = force_close_avoidance_max_fee_satoshis;
 		}
@@ -607,6 +623,7 @@ impl Default for ChannelConfig {
 			forwarding_fee_base_msat: 1000,
 			cltv_expiry_delta: 6 * 12, // 6 blocks/hour * 12 hours
 			max_dust_htlc_exposure: MaxDustHTLCExposure::FeeRateMultiplier(10000),
+			max_inbound_htlc_value_in_flight_msat: u64::MAX,
 			force_close_avoidance_max_fee_satoshis: 1000,
 			accept_underpaying_htlcs: false,
 		}
@@ -625,6 +642,7 @@ impl crate::util::ser::Writeable for ChannelConfig {
 			(2, self.forwarding_fee_base_msat, required),
 			(3, self.max_dust_htlc_exposure, required),
 			(4, self.cltv_expiry_delta, required),
+			(5, self.max_inbound_htlc_value_in_flight_msat, (default_value, u64::MAX)),
 			(6, max_dust_htlc_exposure_msat_fixed_limit, required),
 			// ChannelConfig serialized this field with a required type of 8 prior to the introduction of
 			// LegacyChannelConfig. To make sure that serialization is not compatible with this one, we use
@@ -643,6 +661,7 @@ impl crate::util::ser::Readable for ChannelConfig {
 		let mut cltv_expiry_delta = 6 * 12;
 		let mut max_dust_htlc_exposure_msat = None;
 		let mut max_dust_htlc_exposure_enum = None;
+		let mut max_inbound_htlc_value_in_flight_msat = u64::MAX;
 		let mut force_close_avoidance_max_fee_satoshis = 1000;
 		read_tlv_fields!(reader, {
 			(0, forwarding_fee_proportional_millionths, required),
@@ -650,6 +669,7 @@ impl crate::util::ser::Readable for ChannelConfig {
 			(2, forwarding_fee_base_msat, required),
 			(3, max_dust_htlc_exposure_enum, option),
 			(4, cltv_expiry_delta, required),
+			(5, max_inbound_htlc_value_in_flight_msat, (default_value, u64::MAX)),
 			// Has always been written, but became optionally read in 0.0.116
 			(6, max_dust_htlc_exposure_msat, option),
 			(10, force_close_avoidance_max_fee_satoshis, required),
@@ -663,6 +683,7 @@ impl crate::util::ser::Readable for ChannelConfig {
 			forwarding_fee_base_msat,
 			cltv_expiry_delta,
 			max_dust_htlc_exposure: max_dust_htlc_exposure_msat,
+			max_inbound_htlc_value_in_flight_msat,
 			force_close_avoidance_max_fee_satoshis,
 		})
 	}
@@ -670,11 +691,13 @@ impl crate::util::ser::Readable for ChannelConfig {
 
 /// A parallel struct to [`ChannelConfig`] to define partial updates.
 #[allow(missing_docs)]
+#[derive(Copy, Clone, Debug)]
 pub struct ChannelConfigUpdate {
 	pub forwarding_fee_proportional_millionths: Option<u32>,
 	pub forwarding_fee_base_msat: Option<u32>,
 	pub cltv_expiry_delta: Option<u16>,
 	pub max_dust_htlc_exposure_msat: Option<MaxDustHTLCExposure>,
+	pub max_inbound_htlc_value_in_flight_msat: Option<u64>,
 	pub force_close_avoidance_max_fee_satoshis: Option<u64>,
 }
 
@@ -685,6 +708,7 @@ impl Default for ChannelConfigUpdate {
 			forwarding_fee_base_msat: None,
 			cltv_expiry_delta: None,
 			max_dust_htlc_exposure_msat: None,
+			max_inbound_htlc_value_in_flight_msat: None,
 			force_close_avoidance_max_fee_satoshis: None,
 		}
 	}
@@ -697,11 +721,87 @@ impl From<ChannelConfig> for ChannelConfigUpdate {
 			forwarding_fee_base_msat: Some(config.forwarding_fee_base_msat),
 			cltv_expiry_delta: Some(config.cltv_expiry_delta),
 			max_dust_htlc_exposure_msat: Some(config.max_dust_htlc_exposure),
+			max_inbound_htlc_value_in_flight_msat: Some(config.max_inbound_htlc_value_in_flight_msat),
 			force_close_avoidance_max_fee_satoshis: Some(config.force_close_avoidance_max_fee_satoshis),
 		}
 	}
 }
 
+/// Per-channel overrides of [`ChannelHandshakeConfig`] fields, provided when manually accepting an
+/// inbound channel via [`ChannelManager::accept_inbound_channel_with_config`].
+///
+/// Any field left as `None` uses the value from [`UserConfig::channel_handshake_config`]. Unlike
+/// the configured values, overrides are not clamped to protocol limits but rejected if they
+/// exceed them.
+///
+/// [`ChannelManager::accept_inbound_channel_with_config`]: crate::ln::channelmanager::ChannelManager::accept_inbound_channel_with_config
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelAcceptOverrides {
+	/// Overrides [`ChannelHandshakeConfig::minimum_depth`].
+	///
+	/// Must be at least `1`. Channels with zero confirmations must instead be accepted via
+	/// [`ChannelManager::accept_inbound_channel_from_trusted_peer_0conf`].
+	///
+	/// [`ChannelManager::accept_inbound_channel_from_trusted_peer_0conf`]: crate::ln::channelmanager::ChannelManager::accept_inbound_channel_from_trusted_peer_0conf
+	pub minimum_depth: Option<u32>,
+	/// Overrides [`ChannelHandshakeConfig::our_to_self_delay`].
+	///
+	/// Must be at least [`BREAKDOWN_TIMEOUT`] and at most `2016` (i.e., two weeks), the default
+	/// maximum counterparties accept per [`ChannelHandshakeLimits::their_to_self_delay`].
+	pub our_to_self_delay: Option<u16>,
+	/// Overrides [`ChannelHandshakeConfig::our_max_accepted_htlcs`].
+	///
+	/// Must be at least `1` and at most `483`, the BOLT #2 spec limit on `max_accepted_htlcs`.
+	pub our_max_accepted_htlcs: Option<u16>,
+	/// Overrides [`ChannelHandshakeConfig::their_channel_reserve_proportional_millionths`].
+	///
+	/// Must be less than `1_000_000` (i.e., 100% of the channel value).
+	pub their_channel_reserve_proportional_millionths: Option<u32>,
+}
+
+impl ChannelAcceptOverrides {
+	/// Applies the overrides to the given `config`, failing if any of them is outside the limits
+	/// documented on the corresponding field.
+	pub(crate) fn apply(&self, config: &mut ChannelHandshakeConfig) -> Result<(), APIError> {
+		if let Some(minimum_depth) = self.minimum_depth {
+			if minimum_depth == 0 {
+				return Err(APIError::APIMisuseError {
+					err: "A minimum_depth override of 0 is not allowed, use accept_inbound_channel_from_trusted_peer_0conf to accept channels with zero confirmations".to_owned()
+				});
+			}
+			config.minimum_depth = minimum_depth;
+		}
+		if let Some(our_to_self_delay) = self.our_to_self_delay {
+			if !(BREAKDOWN_TIMEOUT..=MAX_LOCAL_BREAKDOWN_TIMEOUT).contains(&our_to_self_delay) {
+				return Err(APIError::APIMisuseError {
+					err: format!("An our_to_self_delay override of {} is outside the allowed range of {} to {}",
+						our_to_self_delay, BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT)
+				});
+			}
+			config.our_to_self_delay = our_to_self_delay;
+		}
+		if let Some(our_max_accepted_htlcs) = self.our_max_accepted_htlcs {
+			if our_max_accepted_htlcs == 0 || our_max_accepted_htlcs > MAX_HTLCS {
+				return Err(APIError::APIMisuseError {
+					err: format!("An our_max_accepted_htlcs override of {} is outside the allowed range of 1 to {}",
+						our_max_accepted_htlcs, MAX_HTLCS)
+				});
+			}
+			config.our_max_accepted_htlcs = our_max_accepted_htlcs;
+		}
+		if let Some(reserve_proportional_millionths) = self.their_channel_reserve_proportional_millionths {
+			if reserve_proportional_millionths >= 1_000_000 {
+				return Err(APIError::APIMisuseError {
+					err: format!("A their_channel_reserve_proportional_millionths override of {} must be less than 1000000",
+						reserve_proportional_millionths)
+				});
+			}
+			config.their_channel_reserve_proportional_millionths = reserve_proportional_millionths;
+		}
+		Ok(())
+	}
+}
+
 /// Legacy version of [`ChannelConfig`] that stored the static
 /// [`ChannelHandshakeConfig::announced_channel`] and
 /// [`ChannelHandshakeConfig::commit_upfront_shutdown_pubkey`] fields.
@@ -741,6 +841,7 @@ impl crate::util::ser::Writeable for LegacyChannelConfig {
 			(4, self.announced_channel, required),
 			(5, self.options.max_dust_htlc_exposure, required),
 			(6, self.commit_upfront_shutdown_pubkey, required),
+			(7, self.options.max_inbound_htlc_value_in_flight_msat, (default_value, u64::MAX)),
 			(8, self.options.forwarding_fee_base_msat, required),
 		});
 		Ok(())
@@ -757,6 +858,7 @@ impl crate::util::ser::Readable for LegacyChannelConfig {
 		let mut commit_upfront_shutdown_pubkey = false;
 		let mut forwarding_fee_base_msat = 0;
 		let mut max_dust_htlc_exposure_enum = None;
+		let mut max_inbound_htlc_value_in_flight_msat = u64::MAX;
 		read_tlv_fields!(reader, {
 			(0, forwarding_fee_proportional_millionths, required),
 			// Has always been written, but became optionally read in 0.0.116
@@ -766,6 +868,7 @@ impl crate::util::ser::Readable for LegacyChannelConfig {
 			(4, announced_channel, required),
 			(5, max_dust_htlc_exposure_enum, option),
 			(6, commit_upfront_shutdown_pubkey, required),
+			(7, max_inbound_htlc_value_in_flight_msat, (default_value, u64::MAX)),
 			(8, forwarding_fee_base_msat, required),
 		});
 		let max_dust_htlc_exposure_msat_fixed_limit =
@@ -776,6 +879,7 @@ impl crate::util::ser::Readable for LegacyChannelConfig {
 			options: ChannelConfig {
 				forwarding_fee_proportional_millionths,
 				max_dust_htlc_exposure: max_dust_htlc_exposure_msat,
+				max_inbound_htlc_value_in_flight_msat,
 				cltv_expiry_delta,
 				force_close_avoidance_max_fee_satoshis,
 				forwarding_fee_base_msat,
@@ -870,6 +974,40 @@ pub struct UserConfig {
 	/// [`ChannelManager::send_payment_for_bolt12_invoice`]: crate::ln::channelmanager::ChannelManager::send_payment_for_bolt12_invoice
 	/// [`ChannelManager::abandon_payment`]: crate::ln::channelmanager::ChannelManager::abandon_payment
 	pub manually_handle_bolt12_invoices: bool,
+	/// The maximum number of completed HTLC forwards kept in the history exposed via
+	/// [`ChannelManager::forwarding_history`] and [`ChannelManager::forwarding_stats_per_channel`].
+	///
+	/// Once the limit is reached, the oldest forwards are dropped as new ones complete. Setting
+	/// this to `0` disables keeping a forwarding history.
+	///
+	/// Default value: `1000`
+	///
+	/// [`ChannelManager::forwarding_history`]: crate::ln::channelmanager::ChannelManager::forwarding_history
+	/// [`ChannelManager::forwarding_stats_per_channel`]: crate::ln::channelmanager::ChannelManager::forwarding_stats_per_channel
+	pub max_forwarding_history_entries: usize,
+	/// The number of calls to [`ChannelManager::timer_tick_occurred`] after which a channel open
+	/// queued via [`ChannelManager::create_channel_when_connected`] is dropped if the counterparty
+	/// hasn't connected by then, generating an [`Event::ChannelOpenTimedOut`].
+	///
+	/// Default value: `60` (i.e., roughly one hour if timer ticks occur once per minute)
+	///
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	/// [`ChannelManager::create_channel_when_connected`]: crate::ln::channelmanager::ChannelManager::create_channel_when_connected
+	/// [`Event::ChannelOpenTimedOut`]: crate::events::Event::ChannelOpenTimedOut
+	pub queued_channel_open_timeout_ticks: u16,
+	/// The maximum fee, in millionths of [`Event::HTLCIntercepted::expected_outbound_amount_msat`],
+	/// which may be skimmed from an intercepted HTLC by forwarding less than the expected amount via
+	/// [`ChannelManager::forward_intercepted_htlc`].
+	///
+	/// Attempts to skim more than this are rejected, leaving the HTLC pending. The resulting limit
+	/// for each HTLC is provided in [`Event::HTLCIntercepted::max_skimmed_fee_msat`].
+	///
+	/// Default value: `1_000_000` (i.e., up to the full expected outbound amount may be skimmed)
+	///
+	/// [`Event::HTLCIntercepted::expected_outbound_amount_msat`]: crate::events::Event::HTLCIntercepted::expected_outbound_amount_msat
+	/// [`ChannelManager::forward_intercepted_htlc`]: crate::ln::channelmanager::ChannelManager::forward_intercepted_htlc
+	/// [`Event::HTLCIntercepted::max_skimmed_fee_msat`]: crate::events::Event::HTLCIntercepted::max_skimmed_fee_msat
+	pub max_intercepted_htlc_skimmed_fee_proportional_millionths: u32,
 }
 
 impl Default for UserConfig {
@@ -884,6 +1022,9 @@ impl Default for UserConfig {
 			accept_intercept_htlcs: false,
 			accept_mpp_keysend: false,
 			manually_handle_bolt12_invoices: false,
+			max_forwarding_history_entries: 1000,
+			queued_channel_open_timeout_ticks: 60,
+			max_intercepted_htlc_skimmed_fee_proportional_millionths: 1_000_000,
 		}
 	}
 }
@@ -904,6 +1045,9 @@ impl Readable for UserConfig {
 			accept_intercept_htlcs: Readable::read(reader)?,
 			accept_mpp_keysend: Readable::read(reader)?,
 			manually_handle_bolt12_invoices: Readable::read(reader)?,
+			max_forwarding_history_entries: <u16 as Readable>::read(reader)? as usize,
+			queued_channel_open_timeout_ticks: Readable::read(reader)?,
+			max_intercepted_htlc_skimmed_fee_proportional_millionths: Readable::read(reader)?,
 		})
 	}
 }
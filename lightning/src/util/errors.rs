@@ -141,6 +141,8 @@ pub(crate) fn get_onion_error_description(error_code: u16) -> (&'static str, &'s
 		_c if _c == 21 => ("Node indicated the CLTV expiry in the HTLC is too far in the future", "expiry_too_far"),
 		_c if _c == PERM|22 => ("Node indicated that the decrypted onion per-hop payload was not understood by it or is incomplete", "invalid_onion_payload"),
 		_c if _c == 23 => ("The final node indicated the complete amount of the multi-part payment was not received within a reasonable time", "mpp_timeout"),
+		_c if _c == NODE|51 => ("The trampoline node indicated the fee budget is insufficient to reach the recipient", "trampoline_fee_insufficient"),
+		_c if _c == NODE|52 => ("The trampoline node indicated the CLTV expiry budget is insufficient to reach the recipient", "trampoline_expiry_too_soon"),
 		_ => ("Unknown", ""),
 	}
 }
@@ -60,6 +60,7 @@ impl<'a> core::fmt::Display for DebugRoute<'a> {
 				writeln!(f, " node_id: {}, short_channel_id: {}, fee_msat: {}, cltv_expiry_delta: {}", log_pubkey!(h.pubkey), h.short_channel_id, h.fee_msat, h.cltv_expiry_delta)?;
 			}
 			writeln!(f, " blinded_tail: {:?}", p.blinded_tail)?;
+			writeln!(f, " trampoline_tail: {:?}", p.trampoline_tail)?;
 		}
 		Ok(())
 	}
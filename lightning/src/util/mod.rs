@@ -23,6 +23,7 @@ pub mod persist;
 pub mod scid_utils;
 pub mod string;
 pub mod sweep;
+pub mod transaction_utils;
 pub mod wakers;
 #[cfg(fuzzing)]
 pub mod base32;
@@ -32,7 +33,6 @@ pub(crate) mod base32;
 pub(crate) mod atomic_counter;
 pub(crate) mod async_poll;
 pub(crate) mod byte_utils;
-pub(crate) mod transaction_utils;
 pub(crate) mod time;
 pub mod hash_tables;
 
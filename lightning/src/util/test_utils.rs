@@ -396,7 +396,7 @@ impl<'a> chain::Watch<TestChannelSigner> for TestChainMonitor<'a> {
 		if let Some(exp) = self.expect_channel_force_closed.lock().unwrap().take() {
 			assert_eq!(channel_id, exp.0);
 			assert_eq!(update.updates.len(), 1);
-			if let channelmonitor::ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast } = update.updates[0] {
+			if let channelmonitor::ChannelMonitorUpdateStep::ChannelForceClosed { should_broadcast, .. } = update.updates[0] {
 				assert_eq!(should_broadcast, exp.1);
 			} else { panic!(); }
 		}
@@ -424,6 +424,10 @@ impl<'a> chain::Watch<TestChannelSigner> for TestChainMonitor<'a> {
 	fn release_pending_monitor_events(&self) -> Vec<(OutPoint, ChannelId, Vec<MonitorEvent>, Option<PublicKey>)> {
 		return self.chain_monitor.release_pending_monitor_events();
 	}
+
+	fn get_latest_holder_commitment_package(&self, funding_txo: OutPoint) -> Option<channelmonitor::ClosingTxPackage> {
+		self.chain_monitor.get_latest_holder_commitment_package(funding_txo)
+	}
 }
 
 #[cfg(test)]
@@ -7,65 +7,758 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
+//! Utilities for constructing transactions and computing their fees.
+
 use bitcoin::amount::Amount;
-use bitcoin::blockdata::transaction::{Transaction, TxOut};
-use bitcoin::blockdata::script::ScriptBuf;
+use bitcoin::blockdata::locktime::absolute::LockTime;
+use bitcoin::blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut, Version};
+use bitcoin::blockdata::script::{Script, ScriptBuf};
+use bitcoin::blockdata::witness::Witness;
 use bitcoin::consensus::Encodable;
 use bitcoin::consensus::encode::VarInt;
+use bitcoin::hashes::Hash;
+use bitcoin::policy::MAX_STANDARD_TX_WEIGHT;
+use bitcoin::blockdata::constants::WITNESS_SCALE_FACTOR;
 
 #[allow(unused_imports)]
 use crate::prelude::*;
 
+use crate::chain::chaininterface::fee_for_weight;
+use crate::events::bump_transaction::{BASE_INPUT_WEIGHT, CoinSelection, EMPTY_SCRIPT_SIG_WEIGHT, Utxo};
 use crate::io_extras::sink;
+use crate::sign::{P2TR_KEY_PATH_WITNESS_WEIGHT, P2WPKH_WITNESS_WEIGHT};
 use core::cmp::Ordering;
 
+/// Sorts the given outputs in the order mandated by [BIP 69], i.e., first by value and then by
+/// `script_pubkey`, using `tie_breaker` to order any outputs that are otherwise identical.
+///
+/// [BIP 69]: https://github.com/bitcoin/bips/blob/master/bip-0069.mediawiki
 pub fn sort_outputs<T, C : Fn(&T, &T) -> Ordering>(outputs: &mut Vec<(TxOut, T)>, tie_breaker: C) {
 	outputs.sort_unstable_by(|a, b| {
-		a.0.value.cmp(&b.0.value).then_with(|| {
-			a.0.script_pubkey[..].cmp(&b.0.script_pubkey[..]).then_with(|| {
-				tie_breaker(&a.1, &b.1)
-			})
-		})
+		bip69_output_cmp(&a.0, &b.0).then_with(|| tie_breaker(&a.1, &b.1))
+	});
+}
+
+/// Sorts the given inputs in the order mandated by [BIP 69], i.e., first by the txid of the output
+/// they spend and then by its index, using `tie_breaker` to order any inputs that are otherwise
+/// identical.
+///
+/// Note that, as per BIP 69, txids are compared in the byte order they're usually displayed in.
+///
+/// [BIP 69]: https://github.com/bitcoin/bips/blob/master/bip-0069.mediawiki
+pub fn sort_inputs<T, C : Fn(&T, &T) -> Ordering>(inputs: &mut Vec<(TxIn, T)>, tie_breaker: C) {
+	inputs.sort_unstable_by(|a, b| {
+		bip69_input_cmp(&a.0, &b.0).then_with(|| tie_breaker(&a.1, &b.1))
 	});
 }
 
+fn bip69_input_cmp(a: &TxIn, b: &TxIn) -> Ordering {
+	let (a, b) = (&a.previous_output, &b.previous_output);
+	a.txid.as_byte_array().iter().rev().cmp(b.txid.as_byte_array().iter().rev())
+		.then_with(|| a.vout.cmp(&b.vout))
+}
+
+/// Checks that the outputs of the given transaction are sorted in the order mandated by [BIP 69],
+/// e.g., before signing or broadcasting a transaction our counterparty expects to be sorted.
+///
+/// Returns an `Err` with the index of the first output that is ordered before its predecessor.
+///
+/// [BIP 69]: https://github.com/bitcoin/bips/blob/master/bip-0069.mediawiki
+pub fn assert_bip69_sorted(tx: &Transaction) -> Result<(), usize> {
+	match tx.output.windows(2).position(|outputs| bip69_output_cmp(&outputs[0], &outputs[1]) == Ordering::Greater) {
+		Some(idx) => Err(idx + 1),
+		None => Ok(()),
+	}
+}
+
+fn bip69_output_cmp(a: &TxOut, b: &TxOut) -> Ordering {
+	a.value.cmp(&b.value).then_with(|| a.script_pubkey[..].cmp(&b.script_pubkey[..]))
+}
+
+/// The type of an input, determining the weight its `script_sig` and witness add once signed, see
+/// [`estimate_inputs_witness_weight`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputType {
+	/// A spend of a SegWit v0 P2WPKH output.
+	P2wpkh,
+	/// A spend of a P2WPKH output nested in a P2SH output.
+	P2shP2wpkh,
+	/// A key-path spend of a SegWit v1 P2TR output, using the default sighash type.
+	P2trKeySpend,
+	/// A spend of a SegWit v0 P2WSH output whose witness script is satisfied by ECDSA signatures,
+	/// e.g., a k-of-n `OP_CHECKMULTISIG` with `num_signatures` being k.
+	P2wsh {
+		/// The length of the witness script in bytes.
+		witness_script_len: u64,
+		/// The number of signatures required to satisfy the witness script.
+		num_signatures: u64,
+	},
+}
+
+impl InputType {
+	fn witness_weight(&self) -> u64 {
+		match self {
+			InputType::P2wpkh => P2WPKH_WITNESS_WEIGHT,
+			InputType::P2shP2wpkh => {
+				let script_sig_len = 1 /* OP_PUSH22 */ + 1 /* OP_0 */ + 1 /* OP_PUSH20 */ + 20 /* pubkey_hash */;
+				script_sig_len * WITNESS_SCALE_FACTOR as u64 + P2WPKH_WITNESS_WEIGHT
+			},
+			InputType::P2trKeySpend => P2TR_KEY_PATH_WITNESS_WEIGHT,
+			InputType::P2wsh { witness_script_len, num_signatures } => {
+				VarInt(num_signatures.saturating_add(2)).size() as u64 /* num stack items */ +
+					1 /* empty element consumed by OP_CHECKMULTISIG */ +
+					num_signatures.saturating_mul(1 /* sig length */ + 73 /* sig including sighash flag */) +
+					VarInt(*witness_script_len).size() as u64 /* witness script length */ +
+					witness_script_len
+			},
+		}
+	}
+}
+
+/// Estimates the maximum weight an input spending an output with the given `script_pubkey` adds
+/// once signed, i.e., the weight of its witness and of any non-empty `script_sig`, as expected by
+/// the `witness_max_weight` parameters of the utilities in this module.
+///
+/// Any P2SH output is assumed to be a nested P2WPKH output, while P2TR outputs are assumed to be
+/// spent via the key path. Returns `None` for any other output types, including P2WSH outputs,
+/// whose weight depends on their witness script, see [`estimate_inputs_witness_weight`] for those.
+pub fn estimate_input_witness_weight(script_pubkey: &Script) -> Option<u64> {
+	let input_type = if script_pubkey.is_p2wpkh() {
+		InputType::P2wpkh
+	} else if script_pubkey.is_p2sh() {
+		InputType::P2shP2wpkh
+	} else if script_pubkey.is_p2tr() {
+		InputType::P2trKeySpend
+	} else {
+		return None;
+	};
+	Some(input_type.witness_weight())
+}
+
+/// Estimates the maximum weight the given number of inputs of each [`InputType`] add once signed,
+/// i.e., the weight of their witnesses and of any non-empty `script_sig`s, as expected by the
+/// `witness_max_weight` parameters of the utilities in this module.
+pub fn estimate_inputs_witness_weight(inputs: &[(InputType, usize)]) -> u64 {
+	inputs.iter().fold(0u64, |acc, (input_type, count)| {
+		acc.saturating_add(input_type.witness_weight().saturating_mul(*count as u64))
+	})
+}
+
+/// The number of inputs of each [`InputType`] a transaction spends, e.g., as given to
+/// [`predicted_funding_tx_weight`].
+pub type InputTypeCounts = [(InputType, usize)];
+
+/// The size of a P2WSH or P2TR output, the largest standard segwit outputs, such as a channel's
+/// funding output.
+const MAX_SEGWIT_OUTPUT_SIZE: u64 = 8 /* value */ + 1 /* script length */ + 34 /* script_pubkey */;
+
+/// The length of a channel's 2-of-2 multisig funding witness script.
+const FUNDING_WITNESS_SCRIPT_LEN: u64 = 71;
+
+fn output_size(script_pubkey: &Script) -> u64 {
+	8 /* value */ + VarInt(script_pubkey.len() as u64).size() as u64 + script_pubkey.len() as u64
+}
+
+/// Predicts the maximum weight of a channel funding transaction once signed, e.g., to show the fee
+/// opening a channel will cost at the current feerate before the transaction is built.
+///
+/// The transaction is assumed to spend the given number of inputs of each [`InputType`] and to
+/// create `num_change_outputs` change outputs plus, if `channel_value_output` is set, the channel's
+/// P2WSH funding output. As their script type isn't known yet, change outputs are accounted for as
+/// P2WSH or P2TR outputs, the largest standard segwit outputs, making the prediction an upper bound
+/// for any of them. Like the funding utilities in this module, we include the witness flag bytes.
+pub fn predicted_funding_tx_weight(num_inputs_by_type: &InputTypeCounts, num_change_outputs: usize, channel_value_output: bool) -> u64 {
+	const WITNESS_FLAG_BYTES: u64 = 2;
+
+	let num_inputs = num_inputs_by_type.iter().fold(0u64, |acc, (_, count)| acc.saturating_add(*count as u64));
+	let num_outputs = (num_change_outputs as u64).saturating_add(channel_value_output as u64);
+	let non_input_size = 4 /* version */ + VarInt(num_inputs).size() as u64 + VarInt(num_outputs).size() as u64 +
+		num_outputs.saturating_mul(MAX_SEGWIT_OUTPUT_SIZE) + 4 /* lock time */;
+	let inputs_weight = num_inputs.saturating_mul(BASE_INPUT_WEIGHT + EMPTY_SCRIPT_SIG_WEIGHT)
+		.saturating_add(estimate_inputs_witness_weight(num_inputs_by_type));
+	non_input_size.saturating_mul(WITNESS_SCALE_FACTOR as u64)
+		.saturating_add(WITNESS_FLAG_BYTES)
+		.saturating_add(inputs_weight)
+}
+
+/// Predicts the maximum weight of a channel's cooperative close transaction once signed, paying
+/// out to the given holder and counterparty scripts, e.g., to show the fee closing a channel will
+/// cost at the current feerate.
+///
+/// Both outputs are accounted for, though either is omitted if its value would be dust, making the
+/// prediction an upper bound in that case, too.
+pub fn predicted_cooperative_close_weight(holder_script: &Script, counterparty_script: &Script) -> u64 {
+	const WITNESS_FLAG_BYTES: u64 = 2;
+
+	let funding_input = InputType::P2wsh { witness_script_len: FUNDING_WITNESS_SCRIPT_LEN, num_signatures: 2 };
+	let non_input_size = 4 /* version */ + 1 /* input count */ + 1 /* output count */ +
+		output_size(holder_script) + output_size(counterparty_script) + 4 /* lock time */;
+	non_input_size * WITNESS_SCALE_FACTOR as u64 + WITNESS_FLAG_BYTES + BASE_INPUT_WEIGHT + EMPTY_SCRIPT_SIG_WEIGHT +
+		funding_input.witness_weight()
+}
+
+/// Computes the amount by which `input_value` exceeds the fee required to have the given
+/// transaction pay the requested feerate, after subtracting the value of its outputs.
+///
+/// `witness_max_weight` is the maximum weight of the witnesses yet to be added to the
+/// transaction. Assumes at least one input will have a witness (ie spends a segwit output).
+///
+/// Returns a negative value if the transaction is underfunded, i.e., if the inputs don't suffice to
+/// pay for the outputs and fee.
+pub fn excess_over_feerate(tx: &Transaction, input_value: Amount, witness_max_weight: u64, feerate_sat_per_1000_weight: u32) -> i64 {
+	const WITNESS_FLAG_BYTES: u64 = 2;
+
+	// Values and weights are provided as u64s, so saturate them rather than wrapping them around
+	// when converting them to i64s, as we otherwise may flip the sign of the result.
+	let to_i64 = |value: u64| i64::try_from(value).unwrap_or(i64::MAX);
+	let output_value = tx.output.iter().fold(0i64, |acc, output| acc.saturating_add(to_i64(output.value.to_sat())));
+	let weight = tx.weight().to_wu().saturating_add(WITNESS_FLAG_BYTES).saturating_add(witness_max_weight);
+	let fee = weight.saturating_mul(feerate_sat_per_1000_weight as u64) / 1000;
+	to_i64(input_value.to_sat()).saturating_sub(output_value).saturating_sub(to_i64(fee))
+}
+
+/// The reason we failed to fund a transaction at the requested feerate, e.g., when bumping its fee
+/// via [`bump_transaction_fee`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeError {
+	/// The given input value exceeds the maximum money supply.
+	InputValueTooLarge,
+	/// The value of the transaction's outputs exceeds the given input value.
+	OutputsExceedInputs,
+	/// The inputs suffice to pay for the outputs, but not for the fee at the requested feerate.
+	InsufficientFundsForFee {
+		/// The additional input value required to meet the requested feerate.
+		missing_sats: u64,
+	},
+	/// An output to spend is of a type whose witness weight we can't estimate, see
+	/// [`estimate_input_witness_weight`].
+	UnsupportedInputType,
+	/// The fee at the requested feerate, or the weight it is computed for, overflows a `u64`, e.g.,
+	/// due to a bogus feerate or witness weight.
+	FeeOverflow,
+	/// An input to add is already spent by the transaction, see
+	/// [`build_rbf_replacement_with_inputs`].
+	DuplicateInput,
+}
+
+/// Computes the fee for the given weight at the requested feerate, rounding down.
+fn checked_fee_for_weight(weight: u64, feerate_sat_per_1000_weight: u32) -> Result<u64, ChangeError> {
+	weight.checked_mul(feerate_sat_per_1000_weight as u64).map(|fee| fee / 1000).ok_or(ChangeError::FeeOverflow)
+}
+
+/// Details on a transaction funded via [`maybe_add_change_output_with_dust_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChangeOutputResult {
+	/// The expected maximum weight of the fully signed transaction.
+	pub predicted_weight: u64,
+	/// The absolute fee paid by the transaction, i.e., the input value not paid out to any output.
+	pub fee_paid: Amount,
+	/// The index of the change output added to the transaction, if any.
+	pub change_output_index: Option<usize>,
+}
+
 /// Possibly adds a change output to the given transaction, always doing so if there are excess
 /// funds available beyond the requested feerate.
 /// Assumes at least one input will have a witness (ie spends a segwit output).
-/// Returns an Err(()) if the requested feerate cannot be met.
-/// Returns the expected maximum weight of the fully signed transaction on success.
-pub(crate) fn maybe_add_change_output(tx: &mut Transaction, input_value: Amount, witness_max_weight: u64, feerate_sat_per_1000_weight: u32, change_destination_script: ScriptBuf) -> Result<u64, ()> {
-	if input_value > Amount::MAX_MONEY { return Err(()); }
+/// Returns a [`ChangeError`] if the requested feerate cannot be met.
+/// Returns the [`ChangeOutputResult`] of the funded transaction on success.
+pub(crate) fn maybe_add_change_output(tx: &mut Transaction, input_value: Amount, witness_max_weight: u64, feerate_sat_per_1000_weight: u32, change_destination_script: ScriptBuf) -> Result<ChangeOutputResult, ChangeError> {
+	maybe_add_change_output_with_dust_limit(tx, input_value, witness_max_weight, feerate_sat_per_1000_weight, change_destination_script, Amount::ZERO)
+}
 
-	const WITNESS_FLAG_BYTES: u64 = 2;
+/// Possibly adds a change output to the given transaction, doing so if the excess funds available
+/// beyond the requested feerate suffice for a change output of at least `min_change_value`, e.g.,
+/// to avoid creating change below a floor higher than the change script's dust limit.
+///
+/// The change output is only added if its value reaches both `min_change_value` and its script's
+/// dust limit, with any excess funds being left to the fee otherwise.
+///
+/// `witness_max_weight` is the maximum weight of the witnesses yet to be added to the
+/// transaction. Assumes at least one input will have a witness (ie spends a segwit output).
+///
+/// Returns the expected maximum weight of the fully signed transaction, the fee it pays, and the
+/// index of the change output, if added, on success. Returns a [`ChangeError`], leaving the
+/// transaction unchanged, if the requested feerate cannot be met.
+pub fn maybe_add_change_output_with_dust_limit(tx: &mut Transaction, input_value: Amount, witness_max_weight: u64, feerate_sat_per_1000_weight: u32, change_destination_script: ScriptBuf, min_change_value: Amount) -> Result<ChangeOutputResult, ChangeError> {
+	if input_value > Amount::MAX_MONEY { return Err(ChangeError::InputValueTooLarge); }
 
 	let mut output_value = Amount::ZERO;
 	for output in tx.output.iter() {
-		output_value += output.value;
-		if output_value >= input_value { return Err(()); }
+		output_value = output_value.checked_add(output.value)
+			.filter(|output_value| *output_value <= input_value)
+			.ok_or(ChangeError::OutputsExceedInputs)?;
 	}
+	debug_assert!(output_value <= input_value && input_value <= Amount::MAX_MONEY);
+	let available_value = (input_value - output_value).to_sat();
 
-	let dust_value = change_destination_script.dust_value();
+	let dust_value = change_destination_script.dust_value().max(min_change_value);
 	let mut change_output = TxOut {
 		script_pubkey: change_destination_script,
 		value: Amount::ZERO,
 	};
+	let (starting_weight, weight_with_change) = signed_weight_with_change(tx, witness_max_weight, &change_output)
+		.ok_or(ChangeError::FeeOverflow)?;
+	debug_assert!(weight_with_change > starting_weight);
+	// The fee may well exceed the available value for bogus feerates or weights, so only subtract it
+	// once we know it's covered rather than mixing signed and unsigned arithmetic.
+	let fee = checked_fee_for_weight(starting_weight, feerate_sat_per_1000_weight)?;
+	let fee_with_change = checked_fee_for_weight(weight_with_change, feerate_sat_per_1000_weight)?;
+	debug_assert!(fee_with_change >= fee);
+	if let Some(change_value) = available_value.checked_sub(fee_with_change) {
+		if change_value >= dust_value.to_sat() {
+			change_output.value = Amount::from_sat(change_value);
+			tx.output.push(change_output);
+			return Ok(ChangeOutputResult {
+				predicted_weight: weight_with_change,
+				fee_paid: Amount::from_sat(available_value - change_value),
+				change_output_index: Some(tx.output.len() - 1),
+			});
+		}
+	}
+	if available_value < fee {
+		Err(ChangeError::InsufficientFundsForFee { missing_sats: fee - available_value })
+	} else {
+		// Any excess over the fee is too little for a change output and left to the fee, too.
+		Ok(ChangeOutputResult {
+			predicted_weight: starting_weight,
+			fee_paid: Amount::from_sat(available_value),
+			change_output_index: None,
+		})
+	}
+}
+
+/// Bumps the fee of the given transaction to pay the requested feerate by reducing its change, e.g.,
+/// to replace a transaction built via
+/// [`SpendableOutputDescriptor::create_spendable_outputs_psbt`] that failed to confirm timely.
+///
+/// The change output at `change_output_index`, if any, is reduced accordingly, and dropped
+/// altogether, leaving its value to the fee, if it would fall below its dust limit. As required by
+/// [BIP 125] rule 4, the bumped transaction pays a higher absolute fee than the original by at
+/// least the incremental relay fee of 1 sat/vB for its own size.
+///
+/// The transaction is expected to be unsigned, `input_value` being the total value of its inputs
+/// and `witness_max_weight` the maximum weight of the witnesses yet to be added. Assumes at least
+/// one input will have a witness (ie spends a segwit output).
+///
+/// Returns the expected maximum weight of the fully signed transaction on success. Returns a
+/// [`ChangeError`], leaving the transaction unchanged, if the requested feerate cannot be met even
+/// when dropping the change output.
+///
+/// Panics if `change_output_index` is out of bounds.
+///
+/// [`SpendableOutputDescriptor::create_spendable_outputs_psbt`]: crate::sign::SpendableOutputDescriptor::create_spendable_outputs_psbt
+/// [BIP 125]: https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+pub fn bump_transaction_fee(tx: &mut Transaction, input_value: Amount, witness_max_weight: u64, new_feerate_sat_per_1000_weight: u32, change_output_index: Option<usize>) -> Result<u64, ChangeError> {
+	if input_value > Amount::MAX_MONEY { return Err(ChangeError::InputValueTooLarge); }
+	let output_value = tx.output.iter().try_fold(Amount::ZERO, |acc, output| acc.checked_add(output.value))
+		.filter(|output_value| *output_value <= input_value)
+		.ok_or(ChangeError::OutputsExceedInputs)?;
+	let original_fee = input_value - output_value;
+
+	// The minimum fee the bumped transaction needs to pay given its expected weight once signed.
+	let required_fee = |weight: u64| -> Result<u64, ChangeError> {
+		let fee_at_feerate = checked_fee_for_weight(weight, new_feerate_sat_per_1000_weight)?;
+		let min_fee_increase = weight.checked_mul(INCREMENTAL_RELAY_FEE_SAT_PER_1000_WEIGHT).map(|fee| fee / 1000)
+			.ok_or(ChangeError::FeeOverflow)?;
+		Ok(fee_at_feerate.max(original_fee.to_sat().saturating_add(min_fee_increase)))
+	};
+
+	let mut bumped = tx.clone();
+	let mut fee = original_fee.to_sat();
+	if let Some(idx) = change_output_index {
+		let change_output = &mut bumped.output[idx];
+		fee += change_output.value.to_sat();
+		// Reducing the change output's value doesn't affect the transaction's weight.
+		let weight = signed_weight(tx, witness_max_weight).ok_or(ChangeError::FeeOverflow)?;
+		let change_value = fee.saturating_sub(required_fee(weight)?);
+		if change_value >= change_output.script_pubkey.dust_value().to_sat() {
+			change_output.value = Amount::from_sat(change_value);
+			*tx = bumped;
+			return Ok(weight);
+		}
+		bumped.output.remove(idx);
+	}
+
+	let weight = signed_weight(&bumped, witness_max_weight).ok_or(ChangeError::FeeOverflow)?;
+	let required_fee = required_fee(weight)?;
+	if fee < required_fee {
+		return Err(ChangeError::InsufficientFundsForFee { missing_sats: required_fee - fee });
+	}
+	*tx = bumped;
+	Ok(weight)
+}
+
+/// Builds a child transaction spending the given outputs of an unconfirmed parent transaction,
+/// e.g., a commitment transaction's `to_remote` output, to bump the feerate of the package formed
+/// by both transactions to the target feerate via Child-Pays-For-Parent (CPFP).
+///
+/// The child pays for its own weight at the target feerate as well as for any fee the parent,
+/// paying `parent_fee` for an expected weight of `parent_weight`, falls short of the target. If the
+/// parent outputs don't suffice to do so, we additionally spend the largest of the given
+/// `additional_utxos`, skipping any not worth spending at the target feerate. All remaining funds
+/// are paid to `destination_script`.
+///
+/// The parent outputs need to be of a type supported by [`estimate_input_witness_weight`], while
+/// the weight of the additional UTXOs is estimated based on their [`Utxo::satisfaction_weight`].
+///
+/// Returns the unsigned child transaction. Returns a [`ChangeError`] if the target feerate cannot
+/// be met while paying at least the dust limit to `destination_script`, even when spending all
+/// additional UTXOs.
+pub fn build_cpfp_transaction(parent_outpoints: &[(OutPoint, TxOut)], additional_utxos: &[Utxo], target_package_feerate_sat_per_1000_weight: u32, parent_fee: Amount, parent_weight: u64, destination_script: ScriptBuf) -> Result<Transaction, ChangeError> {
+	let feerate = target_package_feerate_sat_per_1000_weight;
+	let mut tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
+	let mut input_value = Amount::ZERO;
+	let mut witness_max_weight = 0;
+	let push_input = |tx: &mut Transaction, outpoint: OutPoint| tx.input.push(TxIn {
+		previous_output: outpoint,
+		script_sig: ScriptBuf::new(),
+		sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+		witness: Witness::new(),
+	});
+
+	for (outpoint, output) in parent_outpoints {
+		witness_max_weight += estimate_input_witness_weight(&output.script_pubkey)
+			.ok_or(ChangeError::UnsupportedInputType)?;
+		input_value = input_value.checked_add(output.value).ok_or(ChangeError::InputValueTooLarge)?;
+		push_input(&mut tx, *outpoint);
+	}
+
+	// The child needs to make up for any fee the parent falls short of at the target feerate.
+	let parent_deficit = (parent_weight.saturating_mul(feerate as u64) / 1000).saturating_sub(parent_fee.to_sat());
+
+	let mut candidates = additional_utxos.iter()
+		.filter(|utxo| utxo.output.value.to_sat() > fee_for_weight(feerate, BASE_INPUT_WEIGHT + utxo.satisfaction_weight))
+		.collect::<Vec<_>>();
+	candidates.sort_unstable_by(|a, b| b.output.value.cmp(&a.output.value));
+	let mut candidates = candidates.into_iter();
+
+	let dust_value = destination_script.dust_value().to_sat() as i64;
+	let destination_output = TxOut { script_pubkey: destination_script.clone(), value: Amount::ZERO };
+	loop {
+		let mut tx_with_output = tx.clone();
+		tx_with_output.output.push(destination_output.clone());
+		let excess = excess_over_feerate(&tx_with_output, input_value, witness_max_weight, feerate)
+			.saturating_sub(parent_deficit as i64);
+		if excess >= dust_value {
+			break;
+		}
+		match candidates.next() {
+			Some(utxo) => {
+				witness_max_weight += utxo.satisfaction_weight.saturating_sub(EMPTY_SCRIPT_SIG_WEIGHT);
+				input_value = input_value.checked_add(utxo.output.value).ok_or(ChangeError::InputValueTooLarge)?;
+				push_input(&mut tx, utxo.outpoint);
+			},
+			None => return Err(ChangeError::InsufficientFundsForFee { missing_sats: (dust_value - excess) as u64 }),
+		}
+	}
+
+	// As the child pays the parent's deficit on top of the fee for its own weight, all funds beyond
+	// that go to the destination output.
+	let child_input_value = input_value - Amount::from_sat(parent_deficit);
+	let result = maybe_add_change_output(&mut tx, child_input_value, witness_max_weight, feerate, destination_script)?;
+	debug_assert_eq!(result.change_output_index, Some(0));
+	Ok(tx)
+}
+
+/// Computes the input value required to fund a transaction with the given outputs and an
+/// additional change output paying to `change_destination_script` at the requested feerate, i.e.,
+/// the value of the outputs plus the fee for such a transaction.
+///
+/// This is the inverse of the change computation done when funding a transaction, and may be
+/// used as a target for coin selection. Note that the change output is accounted for at zero
+/// value, so any value it is meant to carry, e.g., at least its dust limit in order for it to be
+/// added, needs to be selected on top.
+///
+/// As the inputs are yet to be selected, `witness_max_weight` should include the weight of the
+/// inputs expected to be spent in addition to their witnesses. Assumes at least one input will
+/// have a witness (ie spends a segwit output).
+pub fn required_input_value(outputs: &[TxOut], witness_max_weight: u64, feerate_sat_per_1000_weight: u32, change_destination_script: &ScriptBuf) -> Amount {
+	let tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: outputs.to_vec() };
+	let change_output = TxOut { script_pubkey: change_destination_script.clone(), value: Amount::ZERO };
+	// Saturate the weight like the value below, which is then bound to exceed any input value.
+	let weight_with_change = signed_weight_with_change(&tx, witness_max_weight, &change_output)
+		.map_or(u64::MAX, |(_, weight_with_change)| weight_with_change);
+
+	let output_value = outputs.iter().fold(0u64, |acc, output| acc.saturating_add(output.value.to_sat()));
+	let fee = weight_with_change.saturating_mul(feerate_sat_per_1000_weight as u64) / 1000;
+	Amount::from_sat(output_value.saturating_add(fee))
+}
+
+/// Returns whether adding a change output paying to `change_destination_script` to a transaction
+/// with the given outputs can ever be economical at the requested feerate, i.e., whether the fee
+/// required for the change output itself is below its dust limit.
+///
+/// If not, any change output we could add would cost more in fees than the minimum value it would
+/// need to carry, so coin selection may just as well target a transaction without change, leaving
+/// any excess to the fee.
+///
+/// `witness_max_weight` is the maximum weight of the witnesses yet to be added to the
+/// transaction. Assumes at least one input will have a witness (ie spends a segwit output).
+pub fn change_feasible(outputs: &[TxOut], witness_max_weight: u64, feerate_sat_per_1000_weight: u32, change_destination_script: &ScriptBuf) -> bool {
+	let tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: outputs.to_vec() };
+	let change_output = TxOut { script_pubkey: change_destination_script.clone(), value: Amount::ZERO };
+	let (starting_weight, weight_with_change) = match signed_weight_with_change(&tx, witness_max_weight, &change_output) {
+		Some(weights) => weights,
+		// We can't fund a transaction of such weight at all, let alone with change.
+		None => return false,
+	};
+
+	let change_fee = (weight_with_change - starting_weight).saturating_mul(feerate_sat_per_1000_weight as u64) / 1000;
+	change_fee < change_destination_script.dust_value().to_sat()
+}
+
+/// Selects coins from `utxos` to fund a transaction at the requested feerate, picking the largest
+/// ones first, e.g., for use in custom [`CoinSelectionSource`] implementations.
+///
+/// `target_value` is the value the selected coins need to cover besides the fees for spending them,
+/// i.e., the total value of the transaction's outputs plus the fee for their weight. Any coins
+/// given in `must_spend` are always spent and count towards the target, but aren't included in the
+/// result, while any coins whose value doesn't exceed the fee for spending them are skipped. The
+/// fee for each coin is computed based on its [`Utxo::satisfaction_weight`].
+///
+/// A change output paying to `change_destination_script` is included in the result if the excess
+/// funds suffice to pay for it while leaving it above its dust limit, matching the behavior of the
+/// other funding utilities in this module. Otherwise, any excess is left to the fee.
+///
+/// Returns [`ChangeError::InsufficientFundsForFee`] if the coins don't suffice to fund the
+/// transaction, or another [`ChangeError`] if the value of the coins or the fee overflows.
+///
+/// [`CoinSelectionSource`]: crate::events::bump_transaction::CoinSelectionSource
+pub fn select_coins(utxos: &[Utxo], target_value: Amount, feerate_sat_per_1000_weight: u32, must_spend: &[Utxo], change_destination_script: &ScriptBuf) -> Result<CoinSelection, ChangeError> {
+	const BASE_TX_WEIGHT: u64 = (4 /* version */ + 1 /* input count */ + 1 /* output count */ +
+		4 /* locktime */) * WITNESS_SCALE_FACTOR as u64 + 2 /* segwit marker & flag */;
+	let input_weight = |utxo: &Utxo| BASE_INPUT_WEIGHT.checked_add(utxo.satisfaction_weight).ok_or(ChangeError::FeeOverflow);
+	// Round fees up as `fee_for_weight` does, but fail rather than overflow for bogus weights.
+	let fee_for_weight = |weight: u64| weight.checked_mul(feerate_sat_per_1000_weight as u64)
+		.and_then(|fee| fee.checked_add(1000 - 1)).map(|fee| fee / 1000).ok_or(ChangeError::FeeOverflow);
+	let add_weight = |weight: u64, utxo: &Utxo| -> Result<u64, ChangeError> {
+		weight.checked_add(input_weight(utxo)?).ok_or(ChangeError::FeeOverflow)
+	};
+
+	let mut selected_value = must_spend.iter().try_fold(Amount::ZERO, |acc, utxo| acc.checked_add(utxo.output.value))
+		.ok_or(ChangeError::InputValueTooLarge)?;
+	let mut weight = must_spend.iter().try_fold(BASE_TX_WEIGHT, &add_weight)?;
+
+	let mut candidates = utxos.iter()
+		.filter(|utxo| must_spend.iter().all(|spent| spent.outpoint != utxo.outpoint))
+		.filter(|utxo| input_weight(utxo).and_then(fee_for_weight).map_or(false, |fee| utxo.output.value.to_sat() > fee))
+		.collect::<Vec<_>>();
+	candidates.sort_unstable_by(|a, b| b.output.value.cmp(&a.output.value));
+
+	let required_value = |weight: u64| -> Result<Amount, ChangeError> {
+		target_value.checked_add(Amount::from_sat(fee_for_weight(weight)?)).ok_or(ChangeError::FeeOverflow)
+	};
+	let mut selected_utxos = Vec::new();
+	for utxo in candidates {
+		if selected_value >= required_value(weight)? { break; }
+		selected_value = selected_value.checked_add(utxo.output.value).ok_or(ChangeError::InputValueTooLarge)?;
+		weight = add_weight(weight, utxo)?;
+		selected_utxos.push(utxo.clone());
+	}
+	let required_value_without_change = required_value(weight)?;
+	if selected_value < required_value_without_change {
+		return Err(ChangeError::InsufficientFundsForFee { missing_sats: (required_value_without_change - selected_value).to_sat() });
+	}
+
+	let mut change_output = TxOut { script_pubkey: change_destination_script.clone(), value: Amount::ZERO };
+	let change_weight = change_output.consensus_encode(&mut sink()).unwrap() as u64 * WITNESS_SCALE_FACTOR as u64;
+	let weight_with_change = weight.checked_add(change_weight).ok_or(ChangeError::FeeOverflow)?;
+	let change_value = selected_value.to_sat().saturating_sub(required_value(weight_with_change)?.to_sat());
+	let change_output = if change_value >= change_destination_script.dust_value().to_sat() {
+		change_output.value = Amount::from_sat(change_value);
+		Some(change_output)
+	} else {
+		None
+	};
+
+	Ok(CoinSelection { confirmed_utxos: selected_utxos, change_output })
+}
+
+/// Returns the expected maximum weight of the given transaction once signed, or `None` if the
+/// weight overflows a `u64`.
+fn signed_weight(tx: &Transaction, witness_max_weight: u64) -> Option<u64> {
+	const WITNESS_FLAG_BYTES: u64 = 2;
+
+	// When calculating weight, add two for the flag bytes
+	tx.weight().to_wu().checked_add(WITNESS_FLAG_BYTES)?.checked_add(witness_max_weight)
+}
+
+/// Returns the expected maximum weight of the given transaction once signed, both as-is and with
+/// the given change output added, or `None` if the weight overflows a `u64`.
+fn signed_weight_with_change(tx: &Transaction, witness_max_weight: u64, change_output: &TxOut) -> Option<(u64, u64)> {
+	let starting_weight = signed_weight(tx, witness_max_weight)?;
 	let change_len = change_output.consensus_encode(&mut sink()).unwrap();
-	let starting_weight = tx.weight().to_wu() + WITNESS_FLAG_BYTES + witness_max_weight as u64;
-	let mut weight_with_change: i64 = starting_weight as i64 + change_len as i64 * 4;
 	// Include any extra bytes required to push an extra output.
-	weight_with_change += (VarInt(tx.output.len() as u64 + 1).size() - VarInt(tx.output.len() as u64).size()) as i64 * 4;
-	// When calculating weight, add two for the flag bytes
-	let change_value: i64 = (input_value - output_value).to_sat() as i64 - weight_with_change * feerate_sat_per_1000_weight as i64 / 1000;
-	if change_value >= dust_value.to_sat() as i64 {
-		change_output.value = Amount::from_sat(change_value as u64);
-		tx.output.push(change_output);
-		Ok(weight_with_change as u64)
-	} else if (input_value - output_value).to_sat() as i64 - (starting_weight as i64) * feerate_sat_per_1000_weight as i64 / 1000 < 0 {
-		Err(())
-	} else {
-		Ok(starting_weight)
+	let output_count_len = VarInt(tx.output.len() as u64 + 1).size() - VarInt(tx.output.len() as u64).size();
+	let weight_with_change = starting_weight.checked_add((change_len + output_count_len) as u64 * 4)?;
+	Some((starting_weight, weight_with_change))
+}
+
+/// The feerate by which a replacement transaction needs to at least increase the fee paid, see
+/// [BIP 125] rule 4. Matches Bitcoin Core's default `-incrementalrelayfee` of 1 sat/vB.
+///
+/// [BIP 125]: https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+const INCREMENTAL_RELAY_FEE_SAT_PER_1000_WEIGHT: u64 = 250;
+
+/// Builds a [BIP 125] replacement of the given transaction paying the requested feerate, funding
+/// the fee bump by adding `extra_inputs` rather than (only) by reducing its change.
+///
+/// This allows to bump the fee of transactions that have no change to reduce, or whose change
+/// doesn't suffice to pay the requested feerate. Any outputs of `original` paying to
+/// `change_destination_script` are treated as change and are replaced by a single change output
+/// paying out any excess funds, while all other outputs are kept as-is.
+///
+/// `original_input_value` is the total value of the inputs spent by `original`, while
+/// `original_witness_max_weight` is the maximum weight of their witnesses. Each extra input is
+/// given as its outpoint, value, and the maximum weight of its witness. Extra inputs signal
+/// replaceability and, per BIP 125 rule 2, must be confirmed, which is up to the caller to ensure.
+/// Assumes at least one input will have a witness (ie spends a segwit output).
+///
+/// Returns the unsigned replacement alongside its expected maximum weight once signed. Returns
+/// [`ChangeError::DuplicateInput`] if an extra input is already spent by `original`, and
+/// [`ChangeError::InsufficientFundsForFee`] if the requested feerate cannot be met or if the
+/// replacement wouldn't pay a higher absolute fee than `original` by at least the incremental relay
+/// fee of 1 sat/vB as required by BIP 125. Other [`ChangeError`]s are returned for bogus values or
+/// weights as by [`maybe_add_change_output_with_dust_limit`].
+///
+/// [BIP 125]: https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+pub fn build_rbf_replacement_with_inputs(original: &Transaction, original_input_value: Amount, original_witness_max_weight: u64, extra_inputs: &[(OutPoint, Amount, u64)], feerate_sat_per_1000_weight: u32, change_destination_script: ScriptBuf) -> Result<(Transaction, u64), ChangeError> {
+	let original_fee = original.output.iter().try_fold(Amount::ZERO, |acc, output| acc.checked_add(output.value))
+		.and_then(|original_output_value| original_input_value.checked_sub(original_output_value))
+		.ok_or(ChangeError::OutputsExceedInputs)?;
+
+	let mut replacement = original.clone();
+	for input in replacement.input.iter_mut() {
+		input.script_sig = ScriptBuf::new();
+		input.witness = Witness::new();
+	}
+	replacement.output.retain(|output| output.script_pubkey != change_destination_script);
+
+	let mut input_value = original_input_value;
+	let mut witness_max_weight = original_witness_max_weight;
+	for (outpoint, value, witness_weight) in extra_inputs {
+		if replacement.input.iter().any(|input| input.previous_output == *outpoint) { return Err(ChangeError::DuplicateInput); }
+		replacement.input.push(TxIn {
+			previous_output: *outpoint,
+			script_sig: ScriptBuf::new(),
+			sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+			witness: Witness::new(),
+		});
+		input_value = input_value.checked_add(*value).ok_or(ChangeError::InputValueTooLarge)?;
+		witness_max_weight = witness_max_weight.saturating_add(*witness_weight);
+	}
+
+	let ChangeOutputResult { predicted_weight: weight, fee_paid: fee, .. } =
+		maybe_add_change_output(&mut replacement, input_value, witness_max_weight, feerate_sat_per_1000_weight, change_destination_script)?;
+
+	let min_fee_increase = weight.checked_mul(INCREMENTAL_RELAY_FEE_SAT_PER_1000_WEIGHT).map(|fee| fee / 1000)
+		.ok_or(ChangeError::FeeOverflow)?;
+	let required_fee = original_fee.checked_add(Amount::from_sat(min_fee_increase)).ok_or(ChangeError::FeeOverflow)?;
+	if fee < required_fee {
+		return Err(ChangeError::InsufficientFundsForFee { missing_sats: (required_fee - fee).to_sat() });
 	}
+
+	Ok((replacement, weight))
+}
+
+/// The minimum size of a transaction's non-witness data for it to be relayed, see
+/// [`check_standardness`].
+///
+/// Note that this is smaller than `bitcoin::policy::MIN_STANDARD_TX_NONWITNESS_SIZE`, which still
+/// reflects the limit before Bitcoin Core 25.0.
+const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+
+/// The reason a transaction failed [`check_standardness`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StandardnessError {
+	/// The transaction's version is neither 1 nor 2.
+	NonStandardVersion,
+	/// The transaction's weight exceeds the maximum standard weight of 400,000 weight units.
+	TooLarge {
+		/// The weight of the transaction.
+		weight: u64,
+	},
+	/// The size of the transaction's non-witness data is below the minimum of 65 bytes.
+	TooSmall {
+		/// The size of the transaction's non-witness data.
+		non_witness_size: usize,
+	},
+	/// The transaction has no outputs.
+	NoOutputs,
+	/// An output's value is below the dust limit of its script.
+	DustOutput {
+		/// The index of the first output below the dust limit.
+		index: usize,
+	},
+}
+
+/// Checks the given transaction against common standardness rules, i.e., the policy rules
+/// Bitcoin Core nodes apply before relaying it, e.g., after a change output was added.
+///
+/// This allows to catch transactions that would fail to relay before broadcasting them. Note that
+/// this doesn't cover every policy rule, e.g., it doesn't check the inputs' scripts or witnesses,
+/// so passing the check doesn't guarantee the transaction will be relayed.
+///
+/// As the weight of the signed transaction is checked, this should be called on the fully signed
+/// transaction.
+pub fn check_standardness(tx: &Transaction) -> Result<(), StandardnessError> {
+	if !tx.version.is_standard() {
+		return Err(StandardnessError::NonStandardVersion);
+	}
+
+	let weight = tx.weight().to_wu();
+	if weight > MAX_STANDARD_TX_WEIGHT as u64 {
+		return Err(StandardnessError::TooLarge { weight });
+	}
+
+	let non_witness_size = tx.base_size();
+	if non_witness_size < MIN_STANDARD_TX_NONWITNESS_SIZE {
+		return Err(StandardnessError::TooSmall { non_witness_size });
+	}
+
+	if tx.output.is_empty() {
+		return Err(StandardnessError::NoOutputs);
+	}
+
+	if let Some(index) = tx.output.iter().position(|output| output.value < output.script_pubkey.dust_value()) {
+		return Err(StandardnessError::DustOutput { index });
+	}
+
+	Ok(())
+}
+
+/// Distributes `total` across the given recipients proportionally to their respective weights.
+///
+/// Any rounding remainder is added to the output of the last recipient with a non-zero weight.
+/// Outputs that would end up below their script's dust limit are dropped, leaving their value to
+/// be claimed by a change output once the transaction is funded.
+///
+/// Returns an `Err(())` if `total` exceeds the maximum money supply, if no weights are non-zero,
+/// or if all outputs would be dust.
+pub fn distribute_outputs(total: Amount, weights: &[(ScriptBuf, u32)]) -> Result<Vec<TxOut>, ()> {
+	if total > Amount::MAX_MONEY { return Err(()); }
+
+	let total_weight = weights.iter().map(|(_, weight)| *weight as u64).sum::<u64>();
+	if total_weight == 0 { return Err(()); }
+
+	let mut values = weights.iter()
+		.map(|(_, weight)| (total.to_sat() as u128 * *weight as u128 / total_weight as u128) as u64)
+		.collect::<Vec<u64>>();
+
+	let remainder = total.to_sat() - values.iter().sum::<u64>();
+	// unwrap() safety: we checked above that at least one weight is non-zero.
+	let last_idx = weights.iter().rposition(|(_, weight)| *weight > 0).unwrap();
+	values[last_idx] += remainder;
+
+	let outputs = weights.iter().zip(values)
+		.filter(|((script_pubkey, _), value)| *value >= script_pubkey.dust_value().to_sat())
+		.map(|((script_pubkey, _), value)| TxOut {
+			script_pubkey: script_pubkey.clone(), value: Amount::from_sat(value),
+		})
+		.collect::<Vec<TxOut>>();
+
+	if outputs.is_empty() { return Err(()); }
+	Ok(outputs)
 }
 
 #[cfg(test)]
@@ -73,15 +766,19 @@ mod tests {
 	use super::*;
 
 	use bitcoin::amount::Amount;
-	use bitcoin::blockdata::locktime::absolute::LockTime;
-	use bitcoin::blockdata::transaction::{TxIn, OutPoint, Version};
 	use bitcoin::blockdata::script::Builder;
 	use bitcoin::hash_types::Txid;
 	use bitcoin::hashes::Hash;
 	use bitcoin::hashes::hex::FromHex;
-	use bitcoin::{PubkeyHash, Sequence, Witness};
+	use bitcoin::{PubkeyHash, WPubkeyHash, WScriptHash};
+	use bitcoin::blockdata::opcodes::all::OP_CHECKMULTISIG;
+	use bitcoin::blockdata::script::PushBytesBuf;
+	use bitcoin::key::TapTweak;
+	use bitcoin::secp256k1::{Keypair, Message, Secp256k1, SecretKey};
+	use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
 
 	use alloc::vec;
+	use core::str::FromStr;
 
 	#[test]
 	fn sort_output_by_value() {
@@ -212,14 +909,128 @@ mod tests {
 		bip69_txout_test_2: TXOUT2.to_vec(),
 	}
 
+	#[test]
+	fn sort_input_tie_breaker_test() {
+		let txin1 = TxIn {
+			previous_output: OutPoint::new(Txid::all_zeros(), 1), script_sig: ScriptBuf::new(), witness: Witness::new(), sequence: Sequence::ZERO,
+		};
+		let txin1_ = txin1.clone();
+
+		let txin2 = txin1.clone();
+		let txin2_ = txin1.clone();
+
+		let mut inputs = vec![(txin1, 420), (txin2, 69)];
+		sort_inputs(&mut inputs, |a, b| { a.cmp(b) });
+
+		assert_eq!(
+			&inputs,
+			&vec![(txin2_, 69), (txin1_, 420)]
+		);
+	}
+
+	macro_rules! bip_txin_tests {
+		($($name:ident: $value:expr,)*) => {
+			$(
+				#[test]
+				fn $name() {
+					let expected_raw: Vec<(&str, u32)> = $value;
+					let expected: Vec<(TxIn, &str)> = expected_raw.iter()
+						.map(|txin_raw| TxIn {
+							previous_output: OutPoint::new(Txid::from_str(txin_raw.0).unwrap(), txin_raw.1),
+							script_sig: ScriptBuf::new(),
+							witness: Witness::new(),
+							sequence: Sequence::MAX,
+						}).map(|txin| (txin, "ignore"))
+					.collect();
+
+					let mut inputs = expected.clone();
+					inputs.reverse(); // prep it
+
+					// actually do the work!
+					sort_inputs(&mut inputs, |_, _| { unreachable!(); });
+
+					assert_eq!(inputs, expected);
+				}
+			)*
+		}
+	}
+
+	const TXIN1: [(&str, u32); 17] = [
+		("0e53ec5dfb2cb8a71fec32dc9a634a35b7e24799295ddd5278217822e0b31f57", 0),
+		("26aa6e6d8b9e49bb0630aac301db6757c02e3619feb4ee0eea81eb1672947024", 1),
+		("28e0fdd185542f2c6ea19030b0796051e7772b6026dd5ddccd7a2f93b73e6fc2", 0),
+		("381de9b9ae1a94d9c17f6a08ef9d341a5ce29e2e60c36a52d333ff6203e58d5d", 1),
+		("3b8b2f8efceb60ba78ca8bba206a137f14cb5ea4035e761ee204302d46b98de2", 0),
+		("402b2c02411720bf409eff60d05adad684f135838962823f3614cc657dd7bc0a", 1),
+		("54ffff182965ed0957dba1239c27164ace5a73c9b62a660c74b7b7f15ff61e7a", 1),
+		("643e5f4e66373a57251fb173151e838ccd27d279aca882997e005016bb53d5aa", 0),
+		("6c1d56f31b2de4bfc6aaea28396b333102b1f600da9c6d6149e96ca43f1102b1", 1),
+		("7a1de137cbafb5c70405455c49c5104ca3057a1f1243e6563bb9245c9c88c191", 0),
+		("7d037ceb2ee0dc03e82f17be7935d238b35d1deabf953a892a4507bfbeeb3ba4", 1),
+		("a5e899dddb28776ea9ddac0a502316d53a4a3fca607c72f66c470e0412e34086", 0),
+		("b4112b8f900a7ca0c8b0e7c4dfad35c6be5f6be46b3458974988e1cdb2fa61b8", 0),
+		("bafd65e3c7f3f9fdfdc1ddb026131b278c3be1af90a4a6ffa78c4658f9ec0c85", 0),
+		("de0411a1e97484a2804ff1dbde260ac19de841bebad1880c782941aca883b4e9", 1),
+		("f0a130a84912d03c1d284974f563c5949ac13f8342b8112edff52971599e6a45", 0),
+		("f320832a9d2e2452af63154bc687493484a0e7745ebd3aaf9ca19eb80834ad60", 0),
+	];
+	const TXIN2: [(&str, u32); 2] = [
+		("35288d269cee1941eaebb2ea85e32b42cdb2b04284a56d8b14dcc3f5c65d6055", 0),
+		("35288d269cee1941eaebb2ea85e32b42cdb2b04284a56d8b14dcc3f5c65d6055", 1),
+	];
+	bip_txin_tests! {
+		bip69_txin_test_1: TXIN1.to_vec(),
+		bip69_txin_test_2: TXIN2.to_vec(),
+	}
+
+	fn tx_with_outputs(outputs: Vec<TxOut>) -> Transaction {
+		Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: outputs }
+	}
+
+	#[test]
+	fn assert_bip69_sorted_accepts_sorted_outputs() {
+		assert_eq!(assert_bip69_sorted(&tx_with_outputs(Vec::new())), Ok(()));
+
+		for txouts_raw in [TXOUT1, TXOUT2] {
+			let outputs = txouts_raw.iter().map(|txout_raw| TxOut {
+				value: Amount::from_sat(txout_raw.0), script_pubkey: script_from_hex(txout_raw.1)
+			}).collect::<Vec<_>>();
+			assert_eq!(assert_bip69_sorted(&tx_with_outputs(outputs)), Ok(()));
+		}
+
+		// Identical outputs are fine in any order.
+		let txout = TxOut { value: Amount::from_sat(100), script_pubkey: Builder::new().push_int(1).into_script() };
+		assert_eq!(assert_bip69_sorted(&tx_with_outputs(vec![txout.clone(), txout])), Ok(()));
+	}
+
+	#[test]
+	fn assert_bip69_sorted_rejects_unsorted_outputs() {
+		let txout1 = TxOut { value: Amount::from_sat(99), script_pubkey: Builder::new().push_int(0).into_script() };
+		let txout2 = TxOut { value: Amount::from_sat(100), script_pubkey: Builder::new().push_int(1).push_int(2).into_script() };
+		let txout3 = TxOut { value: Amount::from_sat(100), script_pubkey: Builder::new().push_int(3).into_script() };
+		assert_eq!(assert_bip69_sorted(&tx_with_outputs(vec![txout1.clone(), txout2.clone(), txout3.clone()])), Ok(()));
+
+		// Mis-ordered by value.
+		assert_eq!(assert_bip69_sorted(&tx_with_outputs(vec![txout2.clone(), txout1.clone(), txout3.clone()])), Err(1));
+		// Mis-ordered by script_pubkey.
+		assert_eq!(assert_bip69_sorted(&tx_with_outputs(vec![txout1.clone(), txout3.clone(), txout2.clone()])), Err(2));
+		// We report the first out-of-order output.
+		assert_eq!(assert_bip69_sorted(&tx_with_outputs(vec![txout3, txout2, txout1])), Err(1));
+	}
+
 	#[test]
 	fn test_tx_value_overrun() {
 		// If we have a bogus input amount or outputs valued more than inputs, we should fail
 		let mut tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: vec![TxOut {
 			script_pubkey: ScriptBuf::new(), value: Amount::from_sat(1000)
 		}] };
-		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(21_000_000_0000_0001), 0, 253, ScriptBuf::new()).is_err());
-		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(400), 0, 253, ScriptBuf::new()).is_err());
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(21_000_000_0000_0001), 0, 253, ScriptBuf::new()),
+			Err(ChangeError::InputValueTooLarge));
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(400), 0, 253, ScriptBuf::new()),
+			Err(ChangeError::OutputsExceedInputs));
+		// Outputs matching the inputs leave nothing to pay the fee with.
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(1000), 0, 253, ScriptBuf::new()),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 20 }));
 		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(4000), 0, 253, ScriptBuf::new()).is_ok());
 	}
 
@@ -235,7 +1046,8 @@ mod tests {
 		// weight = 3 * base size + total size = 3 * (4 + 1 + 0 + 1 + 0 + 4) + (4 + 1 + 1 + 1 + 0 + 1 + 0 + 4) = 3 * 10 + 12 = 42
 		assert_eq!(tx.weight().to_wu(), 42);
 		// 10 sats isn't enough to pay fee on a dummy transaction...
-		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(10), 0, 250, output_spk.clone()).is_err());
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(10), 0, 250, output_spk.clone()),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 1 }));
 		assert_eq!(tx.wtxid(), orig_wtxid); // Failure doesn't change the transaction
 		// but 11 (= ceil(42 * 250 / 1000)) is, just not enough to add a change output...
 		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(11), 0, 250, output_spk.clone()).is_ok());
@@ -259,6 +1071,82 @@ mod tests {
 		assert_eq!(tx.wtxid(), orig_wtxid); // The only change is the addition of one output.
 	}
 
+	#[test]
+	fn test_tx_change_dust_limit_override() {
+		// Check that we leave change below the configured floor to the fee, just like dust
+		let mut tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
+		let orig_wtxid = tx.wtxid();
+		let output_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[0; 0]));
+		assert_eq!(output_spk.dust_value().to_sat(), 546);
+		let min_change_value = Amount::from_sat(1000);
+		// As in test_tx_change_edge, the transaction with a change output costs 591 - 546 = 45 sats...
+		assert!(maybe_add_change_output_with_dust_limit(&mut tx, Amount::from_sat(1044), 0, 250, output_spk.clone(), min_change_value).is_ok());
+		assert_eq!(tx.output.len(), 0);
+		assert_eq!(tx.wtxid(), orig_wtxid); // If we don't add an output, we don't change the transaction
+		// ...so at 1045 we can afford the change output at the configured floor.
+		assert!(maybe_add_change_output_with_dust_limit(&mut tx, Amount::from_sat(1045), 0, 250, output_spk.clone(), min_change_value).is_ok());
+		assert_eq!(tx.output.len(), 1);
+		assert_eq!(tx.output[0].value.to_sat(), 1000);
+		tx.output.pop();
+		assert_eq!(tx.wtxid(), orig_wtxid); // The only change is the addition of one output.
+
+		// A floor below the dust limit doesn't allow dust change.
+		assert!(maybe_add_change_output_with_dust_limit(&mut tx, Amount::from_sat(590), 0, 250, output_spk.clone(), Amount::from_sat(100)).is_ok());
+		assert_eq!(tx.output.len(), 0);
+		assert!(maybe_add_change_output_with_dust_limit(&mut tx, Amount::from_sat(591), 0, 250, output_spk.clone(), Amount::from_sat(100)).is_ok());
+		assert_eq!(tx.output[0].value.to_sat(), 546);
+		tx.output.pop();
+
+		// The floor doesn't affect whether the feerate can be met.
+		assert_eq!(maybe_add_change_output_with_dust_limit(&mut tx, Amount::from_sat(10), 0, 250, output_spk, min_change_value),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 1 }));
+	}
+
+	#[test]
+	fn test_tx_change_fee_overflow() {
+		// Check that we never wrap around when computing the fee for bogus feerates or weights
+		let mut tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
+		let orig_wtxid = tx.wtxid();
+		let output_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[0; 0]));
+		let max_money = Amount::MAX_MONEY;
+
+		// At the maximum feerate, a megabyte-scale transaction weighing 42 + 2 + 4_000_000 WU costs
+		// 4_000_044 * u32::MAX / 1000 = 17_180_058_158_560 sats, and 136 WU more with change.
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(17_180_058_158_560 - 1), 4_000_000, u32::MAX, output_spk.clone()),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 1 }));
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(17_180_058_158_560), 4_000_000, u32::MAX, output_spk.clone())
+			.map(|result| result.predicted_weight), Ok(4_000_044));
+		assert_eq!(tx.wtxid(), orig_wtxid);
+		assert_eq!(maybe_add_change_output(&mut tx, max_money, 4_000_000, u32::MAX, output_spk.clone())
+			.map(|result| result.predicted_weight), Ok(4_000_180));
+		assert_eq!(tx.output[0].value.to_sat(), max_money.to_sat() - 17_180_642_274_113);
+		tx.output.pop();
+
+		// With a witness weight of 3_000_000_000 WU, the fee of 12_884_902_658_094_113 sats with change
+		// no longer fits in an i64 prior to dividing by 1000, which previously wrapped around to a
+		// negative fee, adding change worth more than the input value.
+		assert_eq!(maybe_add_change_output(&mut tx, max_money, 3_000_000_000, u32::MAX, output_spk.clone()),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 12_884_902_073_978_560 - max_money.to_sat() }));
+		assert_eq!(tx.wtxid(), orig_wtxid); // Failure doesn't change the transaction
+		assert!(excess_over_feerate(&tx, max_money, 3_000_000_000, u32::MAX) < 0);
+
+		// Beyond that, the fee overflows a u64...
+		assert_eq!(maybe_add_change_output(&mut tx, max_money, 5_000_000_000, u32::MAX, output_spk.clone()),
+			Err(ChangeError::FeeOverflow));
+		// ...as does the weight itself eventually.
+		assert_eq!(maybe_add_change_output(&mut tx, max_money, u64::MAX, 0, output_spk.clone()),
+			Err(ChangeError::FeeOverflow));
+		assert_eq!(tx.wtxid(), orig_wtxid);
+		assert_eq!(excess_over_feerate(&tx, max_money, u64::MAX, u32::MAX), max_money.to_sat() as i64 - (u64::MAX / 1000) as i64);
+
+		// Outputs summing up beyond u64::MAX exceed any input value.
+		tx.output = vec![TxOut { script_pubkey: output_spk.clone(), value: Amount::from_sat(1000) },
+			TxOut { script_pubkey: output_spk.clone(), value: Amount::from_sat(u64::MAX) }];
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(2000), 0, 250, output_spk.clone()),
+			Err(ChangeError::OutputsExceedInputs));
+		assert!(excess_over_feerate(&tx, Amount::from_sat(2000), 0, 250) < 0);
+	}
+
 	#[test]
 	fn test_tx_extra_outputs() {
 		// Check that we correctly handle existing outputs
@@ -274,16 +1162,30 @@ mod tests {
 		assert_eq!(Builder::new().push_int(2).into_script().dust_value().to_sat(), 474);
 
 		// Input value of the output value + fee - 1 should fail:
-		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(1000 + 61 + 100 - 1), 400, 250, Builder::new().push_int(2).into_script()).is_err());
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(1000 + 61 + 100 - 1), 400, 250, Builder::new().push_int(2).into_script()),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 1 }));
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(1000 + 61 + 100 - 42), 400, 250, Builder::new().push_int(2).into_script()),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 42 }));
 		assert_eq!(tx.wtxid(), orig_wtxid); // Failure doesn't change the transaction
+		// The reported fee always matches the difference between the input and output values.
+		let output_value = |tx: &Transaction| tx.output.iter().fold(Amount::ZERO, |acc, output| acc + output.value);
 		// but one more input sat should succeed, without changing the transaction
-		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(1000 + 61 + 100), 400, 250, Builder::new().push_int(2).into_script()).is_ok());
+		let input_value = Amount::from_sat(1000 + 61 + 100);
+		let result = maybe_add_change_output(&mut tx, input_value, 400, 250, Builder::new().push_int(2).into_script()).unwrap();
+		assert_eq!(result, ChangeOutputResult { predicted_weight: orig_weight + 2 + 400, fee_paid: Amount::from_sat(161), change_output_index: None });
+		assert_eq!(result.fee_paid, input_value - output_value(&tx));
 		assert_eq!(tx.wtxid(), orig_wtxid); // If we don't add an output, we don't change the transaction
 		// In order to get a change output, we need to add 474 plus the output's weight / 4 (10)...
-		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(1000 + 61 + 100 + 474 + 9), 400, 250, Builder::new().push_int(2).into_script()).is_ok());
+		let input_value = Amount::from_sat(1000 + 61 + 100 + 474 + 9);
+		let result = maybe_add_change_output(&mut tx, input_value, 400, 250, Builder::new().push_int(2).into_script()).unwrap();
+		assert_eq!(result.change_output_index, None);
+		assert_eq!(result.fee_paid, input_value - output_value(&tx));
 		assert_eq!(tx.wtxid(), orig_wtxid); // If we don't add an output, we don't change the transaction
 
-		assert!(maybe_add_change_output(&mut tx, Amount::from_sat(1000 + 61 + 100 + 474 + 10), 400, 250, Builder::new().push_int(2).into_script()).is_ok());
+		let input_value = Amount::from_sat(1000 + 61 + 100 + 474 + 10);
+		let result = maybe_add_change_output(&mut tx, input_value, 400, 250, Builder::new().push_int(2).into_script()).unwrap();
+		assert_eq!(result, ChangeOutputResult { predicted_weight: orig_weight + 2 + 400 + 40, fee_paid: Amount::from_sat(171), change_output_index: Some(1) });
+		assert_eq!(result.fee_paid, input_value - output_value(&tx));
 		assert_eq!(tx.output.len(), 2);
 		assert_eq!(tx.output[1].value.to_sat(), 474);
 		assert_eq!(tx.output[1].script_pubkey, Builder::new().push_int(2).into_script());
@@ -291,4 +1193,502 @@ mod tests {
 		tx.output.pop();
 		assert_eq!(tx.wtxid(), orig_wtxid); // The only change is the addition of one output.
 	}
+
+	#[test]
+	fn test_bump_transaction_fee_change_edge() {
+		// Check that we reduce the change as far as its dust limit, but drop it beyond
+		let mut tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
+		let orig_wtxid = tx.wtxid();
+		let output_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[0; 0]));
+		assert_eq!(output_spk.dust_value().to_sat(), 546);
+		// The change output adds 34 * 4 weight units to the 42 + 2 of the dummy transaction, so
+		// we pay a fee of 180 * 250 / 1000 = 45 sats...
+		assert_eq!(maybe_add_change_output(&mut tx, Amount::from_sat(1000), 0, 250, output_spk.clone()),
+			Ok(ChangeOutputResult { predicted_weight: 180, fee_paid: Amount::from_sat(45), change_output_index: Some(0) }));
+		assert_eq!(tx.output[0].value.to_sat(), 1000 - 45);
+		let funded_tx = tx.clone();
+
+		// ...so that re-paying the same feerate needs to add the incremental relay fee on top.
+		assert_eq!(bump_transaction_fee(&mut tx, Amount::from_sat(1000), 0, 250, Some(0)), Ok(180));
+		assert_eq!(tx.output[0].value.to_sat(), 1000 - 45 - 45);
+
+		// At 2525 sat/KW, the fee of 454 sats leaves the change exactly at the dust limit...
+		let mut tx = funded_tx.clone();
+		assert_eq!(bump_transaction_fee(&mut tx, Amount::from_sat(1000), 0, 2525, Some(0)), Ok(180));
+		assert_eq!(tx.output.len(), 1);
+		assert_eq!(tx.output[0].value.to_sat(), 546);
+
+		// ...while at 2530 sat/KW, we drop it and leave its value to the fee.
+		let mut tx = funded_tx.clone();
+		assert_eq!(bump_transaction_fee(&mut tx, Amount::from_sat(1000), 0, 2530, Some(0)), Ok(44));
+		assert_eq!(tx.output.len(), 0);
+		assert_eq!(tx.wtxid(), orig_wtxid);
+
+		// Without change, 1000 sats pay for a feerate of at most 22749 sat/KW, as 44 * 22749 / 1000
+		// rounds down to 1000.
+		let mut tx = funded_tx.clone();
+		assert_eq!(bump_transaction_fee(&mut tx, Amount::from_sat(1000), 0, 22_749, Some(0)), Ok(44));
+		assert_eq!(tx.output.len(), 0);
+		let mut tx = funded_tx.clone();
+		assert_eq!(bump_transaction_fee(&mut tx, Amount::from_sat(1000), 0, 22_750, Some(0)),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 1 }));
+		assert_eq!(tx, funded_tx); // Failure doesn't change the transaction
+
+		// Once there's no change left, the fee can't be bumped any further.
+		let mut tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
+		assert_eq!(bump_transaction_fee(&mut tx, Amount::from_sat(1000), 0, 250, None),
+			Err(ChangeError::InsufficientFundsForFee { missing_sats: 11 }));
+		assert_eq!(bump_transaction_fee(&mut tx, Amount::from_sat(21_000_000_0000_0001), 0, 250, None),
+			Err(ChangeError::InputValueTooLarge));
+		assert_eq!(bump_transaction_fee(&mut funded_tx.clone(), Amount::from_sat(900), 0, 250, Some(0)),
+			Err(ChangeError::OutputsExceedInputs));
+
+		// Bogus witness weights or feerates fail rather than overflow.
+		assert_eq!(bump_transaction_fee(&mut funded_tx.clone(), Amount::from_sat(1000), u64::MAX, 250, Some(0)),
+			Err(ChangeError::FeeOverflow));
+		assert_eq!(bump_transaction_fee(&mut funded_tx.clone(), Amount::from_sat(1000), u64::MAX / 1000, u32::MAX, None),
+			Err(ChangeError::FeeOverflow));
+	}
+
+	#[test]
+	fn test_estimate_input_witness_weight() {
+		// Check the estimates against the actual weight added by signing an input of each type
+		let secp_ctx = Secp256k1::new();
+		let secret_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp_ctx));
+		let keypair = Keypair::from_secret_key(&secp_ctx, &secret_key);
+		let wpubkey_hash = public_key.wpubkey_hash().unwrap();
+		let p2wpkh_script = ScriptBuf::new_p2wpkh(&wpubkey_hash);
+		let multisig_script = Builder::new().push_int(2).push_key(&public_key).push_key(&public_key)
+			.push_int(2).push_opcode(OP_CHECKMULTISIG).into_script();
+		let value = Amount::from_sat(10_000);
+
+		let sign_ecdsa = |digest: [u8; 32]| bitcoin::ecdsa::Signature {
+			sig: secp_ctx.sign_ecdsa(&Message::from_digest(digest), &secret_key),
+			hash_ty: EcdsaSighashType::All,
+		};
+		let unsigned_tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: vec![TxIn {
+			previous_output: OutPoint::new(Txid::all_zeros(), 0), script_sig: ScriptBuf::new(), witness: Witness::new(), sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+		}], output: vec![TxOut { script_pubkey: p2wpkh_script.clone(), value: Amount::from_sat(9_000) }] };
+		let mut sighash_cache = SighashCache::new(&unsigned_tx);
+
+		// P2WPKH
+		let script_pubkey = p2wpkh_script.clone();
+		let sighash = sighash_cache.p2wpkh_signature_hash(0, &script_pubkey, value, EcdsaSighashType::All).unwrap();
+		let mut p2wpkh_tx = unsigned_tx.clone();
+		p2wpkh_tx.input[0].witness = Witness::p2wpkh(&sign_ecdsa(sighash.to_byte_array()), &public_key.inner);
+		let estimate = estimate_input_witness_weight(&script_pubkey).unwrap();
+		assert_eq!(estimate, estimate_inputs_witness_weight(&[(InputType::P2wpkh, 1)]));
+
+		// Nested P2SH-P2WPKH
+		let script_pubkey = ScriptBuf::new_p2sh(&p2wpkh_script.script_hash());
+		let sighash = sighash_cache.p2wpkh_signature_hash(0, &p2wpkh_script, value, EcdsaSighashType::All).unwrap();
+		let mut p2sh_p2wpkh_tx = unsigned_tx.clone();
+		p2sh_p2wpkh_tx.input[0].witness = Witness::p2wpkh(&sign_ecdsa(sighash.to_byte_array()), &public_key.inner);
+		p2sh_p2wpkh_tx.input[0].script_sig = Builder::new()
+			.push_slice(PushBytesBuf::try_from(p2wpkh_script.to_bytes()).unwrap()).into_script();
+		let p2sh_p2wpkh_estimate = estimate_input_witness_weight(&script_pubkey).unwrap();
+		assert_eq!(p2sh_p2wpkh_estimate, estimate_inputs_witness_weight(&[(InputType::P2shP2wpkh, 1)]));
+
+		// P2TR key spend
+		let (internal_key, _) = keypair.x_only_public_key();
+		let script_pubkey = ScriptBuf::new_p2tr(&secp_ctx, internal_key, None);
+		let prevouts = [TxOut { script_pubkey: script_pubkey.clone(), value }];
+		let sighash = sighash_cache.taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), TapSighashType::Default).unwrap();
+		let tweaked_keypair = keypair.tap_tweak(&secp_ctx, None).to_inner();
+		let sig = secp_ctx.sign_schnorr_no_aux_rand(&Message::from_digest(sighash.to_byte_array()), &tweaked_keypair);
+		let mut p2tr_tx = unsigned_tx.clone();
+		p2tr_tx.input[0].witness = Witness::from_slice(&[sig.as_ref()]);
+		let p2tr_estimate = estimate_input_witness_weight(&script_pubkey).unwrap();
+		assert_eq!(p2tr_estimate, estimate_inputs_witness_weight(&[(InputType::P2trKeySpend, 1)]));
+
+		// P2WSH 2-of-2 multisig, whose weight we can't tell from the script_pubkey alone
+		let script_pubkey = ScriptBuf::new_p2wsh(&multisig_script.wscript_hash());
+		let sighash = sighash_cache.p2wsh_signature_hash(0, &multisig_script, value, EcdsaSighashType::All).unwrap();
+		let sig = sign_ecdsa(sighash.to_byte_array()).to_vec();
+		let mut p2wsh_tx = unsigned_tx.clone();
+		p2wsh_tx.input[0].witness = Witness::from_slice(&[Vec::new(), sig.clone(), sig, multisig_script.to_bytes()]);
+		assert!(estimate_input_witness_weight(&script_pubkey).is_none());
+		let p2wsh_input_type = InputType::P2wsh { witness_script_len: multisig_script.len() as u64, num_signatures: 2 };
+		let p2wsh_estimate = estimate_inputs_witness_weight(&[(p2wsh_input_type, 1)]);
+
+		// Signing adds the witness flag bytes on top of the input's weight. ECDSA signatures may be a
+		// byte or two shorter than the maximum we account for, while Schnorr signatures are fixed-size.
+		let added_weight = |signed_tx: &Transaction| signed_tx.weight().to_wu() - unsigned_tx.weight().to_wu() - 2;
+		for (signed_tx, estimate, num_ecdsa_sigs) in [(&p2wpkh_tx, estimate, 1), (&p2sh_p2wpkh_tx, p2sh_p2wpkh_estimate, 1), (&p2wsh_tx, p2wsh_estimate, 2)] {
+			assert!(added_weight(signed_tx) <= estimate);
+			assert!(estimate - added_weight(signed_tx) <= 2 * num_ecdsa_sigs);
+		}
+		assert_eq!(added_weight(&p2tr_tx), p2tr_estimate);
+
+		assert_eq!(estimate_inputs_witness_weight(&[(InputType::P2wpkh, 2), (p2wsh_input_type, 3)]), 2 * estimate + 3 * p2wsh_estimate);
+		assert!(estimate_input_witness_weight(&ScriptBuf::new_p2pkh(&public_key.pubkey_hash())).is_none());
+	}
+
+	#[test]
+	fn test_select_coins() {
+		let change_spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&[0; 0]));
+		assert_eq!(change_spk.dust_value().to_sat(), 294);
+		let utxo = |vout: u32, value: u64| Utxo::new_v0_p2wpkh(
+			OutPoint::new(Txid::all_zeros(), vout), Amount::from_sat(value), &WPubkeyHash::hash(&[0; 0]));
+		let utxos = vec![utxo(0, 10_000), utxo(1, 50_000), utxo(2, 2_000), utxo(3, 300)];
+
+		// Spending a P2WPKH input weighs 160 + 4 + 109 = 273 WU on top of the 42 WU of the transaction
+		// itself, while a P2WPKH change output adds another 31 * 4 = 124 WU.
+		assert_eq!(utxos[0].satisfaction_weight, 4 + 109);
+
+		// We select the largest coins first, returning the excess as change...
+		let selection = select_coins(&utxos, Amount::from_sat(40_000), 253, &[], &change_spk).unwrap();
+		assert_eq!(selection.confirmed_utxos, vec![utxos[1].clone()]);
+		assert_eq!(selection.change_output.unwrap().value.to_sat(), 50_000 - 40_000 - 112);
+
+		let selection = select_coins(&utxos, Amount::from_sat(55_000), 253, &[], &change_spk).unwrap();
+		assert_eq!(selection.confirmed_utxos, vec![utxos[1].clone(), utxos[0].clone()]);
+		assert_eq!(selection.change_output.unwrap().value.to_sat(), 60_000 - 55_000 - 181);
+
+		// ...unless it would end up below the dust limit after paying for the change output.
+		let selection = select_coins(&utxos, Amount::from_sat(49_594), 253, &[], &change_spk).unwrap();
+		assert_eq!(selection.change_output.unwrap().value.to_sat(), 294);
+		let selection = select_coins(&utxos, Amount::from_sat(49_595), 253, &[], &change_spk).unwrap();
+		assert_eq!(selection.confirmed_utxos, vec![utxos[1].clone()]);
+		assert!(selection.change_output.is_none());
+		// Without change, the selected coins need to cover a fee of 80 sats.
+		assert!(select_coins(&utxos, Amount::from_sat(49_920), 253, &[], &change_spk).unwrap().change_output.is_none());
+		assert_eq!(select_coins(&utxos, Amount::from_sat(49_921), 253, &[], &change_spk).unwrap().confirmed_utxos.len(), 2);
+
+		// Coins we must spend count towards the target, but aren't selected again.
+		let selection = select_coins(&utxos, Amount::from_sat(5_000), 253, &[utxos[0].clone()], &change_spk).unwrap();
+		assert!(selection.confirmed_utxos.is_empty());
+		assert_eq!(selection.change_output.unwrap().value.to_sat(), 10_000 - 5_000 - 112);
+
+		// Coins not worth spending at the requested feerate are skipped.
+		let must_spend = [utxos[2].clone()];
+		assert_eq!(select_coins(&utxos[3..], Amount::from_sat(1_700), 1_000, &must_spend, &change_spk).unwrap().confirmed_utxos.len(), 1);
+		assert_eq!(select_coins(&utxos[3..], Amount::from_sat(1_700), 1_100, &must_spend, &change_spk).unwrap_err(),
+			ChangeError::InsufficientFundsForFee { missing_sats: 1_700 + 347 - 2_000 });
+
+		// Spending all coins weighs 42 + 4 * 273 = 1134 WU, costing 287 sats in fees.
+		assert!(select_coins(&utxos, Amount::from_sat(62_000), 253, &[], &change_spk).is_ok());
+		assert_eq!(select_coins(&utxos, Amount::from_sat(63_000), 253, &[], &change_spk).unwrap_err(),
+			ChangeError::InsufficientFundsForFee { missing_sats: 63_000 + 287 - 62_300 });
+
+		// Bogus weights fail rather than overflow.
+		let mut heavy_utxo = utxos[0].clone();
+		heavy_utxo.satisfaction_weight = u64::MAX - BASE_INPUT_WEIGHT;
+		assert_eq!(select_coins(&utxos, Amount::from_sat(5_000), 253, &[heavy_utxo], &change_spk).unwrap_err(),
+			ChangeError::FeeOverflow);
+	}
+
+	#[test]
+	fn test_predicted_funding_tx_weight() {
+		// Check the prediction against a signed funding transaction spending a P2WPKH input
+		let secp_ctx = Secp256k1::new();
+		let secret_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let public_key = bitcoin::PublicKey::new(secret_key.public_key(&secp_ctx));
+		let p2wpkh_script = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash().unwrap());
+		let funding_script = ScriptBuf::new_p2wsh(&WScriptHash::hash(&[0; 71]));
+		let mut tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: vec![TxIn {
+			previous_output: OutPoint::new(Txid::all_zeros(), 0), script_sig: ScriptBuf::new(), witness: Witness::new(), sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+		}], output: vec![
+			TxOut { script_pubkey: funding_script, value: Amount::from_sat(100_000) },
+			TxOut { script_pubkey: p2wpkh_script.clone(), value: Amount::from_sat(10_000) },
+		] };
+		let sighash = SighashCache::new(&tx).p2wpkh_signature_hash(0, &p2wpkh_script, Amount::from_sat(111_000), EcdsaSighashType::All).unwrap();
+		let sig = bitcoin::ecdsa::Signature {
+			sig: secp_ctx.sign_ecdsa(&Message::from_digest(sighash.to_byte_array()), &secret_key),
+			hash_ty: EcdsaSighashType::All,
+		};
+		tx.input[0].witness = Witness::p2wpkh(&sig, &public_key.inner);
+
+		// The P2WPKH change output is 12 bytes smaller than the P2WSH or P2TR change we account for,
+		// and the signature may be a byte or two shorter than the maximum.
+		let predicted_weight = predicted_funding_tx_weight(&[(InputType::P2wpkh, 1)], 1, true);
+		assert!(predicted_weight >= tx.weight().to_wu());
+		assert!(predicted_weight - tx.weight().to_wu() <= 12 * 4 + 2);
+
+		// Going from 252 to 253 outputs requires three more bytes to encode the output count.
+		assert_eq!(predicted_funding_tx_weight(&[], 252, true) - predicted_funding_tx_weight(&[], 252, false), 43 * 4 + 2 * 4);
+		assert_eq!(predicted_funding_tx_weight(&[(InputType::P2wpkh, 1)], 1, true) - predicted_funding_tx_weight(&[], 1, true),
+			BASE_INPUT_WEIGHT + EMPTY_SCRIPT_SIG_WEIGHT + P2WPKH_WITNESS_WEIGHT);
+	}
+
+	#[test]
+	fn test_build_cpfp_transaction() {
+		let p2wpkh_spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&[0; 0]));
+		let destination_spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&[1; 1]));
+		let parent_outpoints = [(OutPoint::new(Txid::all_zeros(), 0), TxOut { value: Amount::from_sat(20_000), script_pubkey: p2wpkh_spk.clone() })];
+		let utxo = |vout: u32, value: u64| Utxo::new_v0_p2wpkh(
+			OutPoint::new(Txid::from_byte_array([1; 32]), vout), Amount::from_sat(value), &WPubkeyHash::hash(&[0; 0]));
+		let utxos = vec![utxo(0, 10_000), utxo(1, 100_000), utxo(2, 100)];
+		let target_feerate = 5_000;
+
+		// Check the package meets the target feerate across parent sizes, spending additional UTXOs
+		// only once the parent output doesn't suffice.
+		for (parent_weight, num_inputs) in [(500, 1), (1_000, 1), (2_000, 1), (4_000, 2), (10_000, 2), (20_000, 2)] {
+			let parent_fee = Amount::from_sat(parent_weight * 253 / 1000);
+			let child = build_cpfp_transaction(&parent_outpoints, &utxos, target_feerate, parent_fee, parent_weight, destination_spk.clone()).unwrap();
+			assert_eq!(child.input.len(), num_inputs);
+			assert_eq!(child.output.len(), 1);
+			assert_eq!(child.output[0].script_pubkey, destination_spk);
+
+			let input_value = if num_inputs == 1 { 20_000 } else { 120_000 };
+			let child_fee = input_value - child.output[0].value.to_sat();
+			let child_weight = child.weight().to_wu() + 2 + num_inputs as u64 * (1 + 1 + 73 + 1 + 33);
+			let package_feerate = (parent_fee.to_sat() + child_fee) * 1000 / (parent_weight + child_weight);
+			assert!(package_feerate + 1 >= target_feerate as u64 && package_feerate <= target_feerate as u64 + 1);
+		}
+
+		// Check we fail if even spending all UTXOs doesn't reach the target feerate, skipping those
+		// not worth spending.
+		let parent_weight = 1_000_000;
+		let parent_fee = Amount::from_sat(parent_weight * 253 / 1000);
+		match build_cpfp_transaction(&parent_outpoints, &utxos, target_feerate, parent_fee, parent_weight, destination_spk.clone()) {
+			Err(ChangeError::InsufficientFundsForFee { missing_sats }) => {
+				// We spend the parent output and all UTXOs but the one worth 100 sats.
+				let child_weight = 40 + 3 * 164 + 31 * 4 + 2 + 3 * 109;
+				let required = (parent_weight + child_weight) * target_feerate as u64 / 1000 - parent_fee.to_sat();
+				assert_eq!(missing_sats, required + destination_spk.dust_value().to_sat() - 130_000);
+			},
+			res => panic!("Unexpected result: {:?}", res),
+		}
+
+		let parent_outpoints = [(OutPoint::new(Txid::all_zeros(), 0), TxOut { value: Amount::from_sat(330), script_pubkey: ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()) })];
+		assert_eq!(build_cpfp_transaction(&parent_outpoints, &utxos, target_feerate, parent_fee, 1_000, destination_spk),
+			Err(ChangeError::UnsupportedInputType));
+	}
+
+	#[test]
+	fn test_excess_over_feerate() {
+		// Check the sign of the excess flips exactly at the boundaries covered by the change tests
+		let tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: Vec::new(), output: Vec::new() };
+		assert_eq!(excess_over_feerate(&tx, Amount::from_sat(10), 0, 250), -1);
+		assert_eq!(excess_over_feerate(&tx, Amount::from_sat(11), 0, 250), 0);
+		assert_eq!(excess_over_feerate(&tx, Amount::from_sat(591), 0, 250), 580);
+
+		let tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: vec![TxIn {
+			previous_output: OutPoint::new(Txid::all_zeros(), 0), script_sig: ScriptBuf::new(), witness: Witness::new(), sequence: Sequence::ZERO,
+		}], output: vec![TxOut {
+			script_pubkey: Builder::new().push_int(1).into_script(), value: Amount::from_sat(1000)
+		}] };
+		assert_eq!(excess_over_feerate(&tx, Amount::from_sat(1000 + 61 + 100 - 1), 400, 250), -1);
+		assert_eq!(excess_over_feerate(&tx, Amount::from_sat(1000 + 61 + 100), 400, 250), 0);
+
+		// Outputs exceeding the inputs are reported as a negative excess, too
+		assert!(excess_over_feerate(&tx, Amount::from_sat(400), 0, 253) < 0);
+	}
+
+	#[test]
+	fn test_required_input_value() {
+		// Matches the boundary at which we can afford a change output at the dust limit (546) in
+		// `test_tx_change_edge`.
+		let change_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[0; 0]));
+		assert_eq!(required_input_value(&[], 0, 250, &change_spk), Amount::from_sat(591 - 546));
+
+		let outputs = vec![
+			TxOut { script_pubkey: Builder::new().push_int(1).into_script(), value: Amount::from_sat(1000) },
+			TxOut { script_pubkey: ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[1])), value: Amount::from_sat(20_000) },
+		];
+		for (witness_max_weight, feerate) in [(0, 253), (400, 250), (1_000, 2_500), (10_000, 50_000)] {
+			let required = required_input_value(&outputs, witness_max_weight, feerate, &change_spk);
+			let dust_value = change_spk.dust_value();
+
+			// Cross-check against the forward computation: the required value on top of the change
+			// output's dust limit gets us a change output at exactly the dust limit...
+			let mut tx = tx_with_outputs(outputs.clone());
+			let weight = maybe_add_change_output(&mut tx, required + dust_value, witness_max_weight, feerate, change_spk.clone()).unwrap().predicted_weight;
+			assert_eq!(tx.output.len(), 3);
+			assert_eq!(tx.output[2].value, dust_value);
+			assert_eq!(Amount::from_sat(weight * feerate as u64 / 1000), required - Amount::from_sat(21_000));
+
+			// ...while one sat less doesn't get us a change output at all.
+			let mut tx = tx_with_outputs(outputs.clone());
+			maybe_add_change_output(&mut tx, required + dust_value - Amount::from_sat(1), witness_max_weight, feerate, change_spk.clone()).unwrap();
+			assert_eq!(tx.output.len(), 2);
+
+			// The required value exactly pays the feerate for the transaction including change.
+			let mut tx = tx_with_outputs(outputs.clone());
+			tx.output.push(TxOut { script_pubkey: change_spk.clone(), value: Amount::ZERO });
+			assert_eq!(excess_over_feerate(&tx, required, witness_max_weight, feerate), 0);
+		}
+	}
+
+	#[test]
+	fn test_change_feasible() {
+		// A P2WPKH change output adds 124 WU and has a dust limit of 294 sats, so it stops being
+		// economical once it costs 294 sats or more, i.e., at 2371 sat/kW.
+		let p2wpkh_change_spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&[2]));
+		let outputs = vec![TxOut { script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&[1])), value: Amount::from_sat(20_000) }];
+		assert!(change_feasible(&outputs, 108, 253, &p2wpkh_change_spk));
+		assert!(change_feasible(&outputs, 108, 2_370, &p2wpkh_change_spk));
+		assert!(!change_feasible(&outputs, 108, 2_371, &p2wpkh_change_spk));
+		assert!(!change_feasible(&outputs, 108, 50_000, &p2wpkh_change_spk));
+
+		// A P2PKH change output adds 136 WU with a dust limit of 546 sats, so remains economical
+		// up to 4014 sat/kW.
+		let p2pkh_change_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[2]));
+		assert!(change_feasible(&outputs, 108, 4_014, &p2pkh_change_spk));
+		assert!(!change_feasible(&outputs, 108, 4_015, &p2pkh_change_spk));
+
+		// Once the output count no longer fits a single byte, the change output also needs to pay
+		// for the larger count, lowering the threshold accordingly.
+		let outputs = vec![outputs[0].clone(); 252];
+		assert!(!change_feasible(&outputs, 108, 2_300, &p2wpkh_change_spk));
+		assert!(change_feasible(&outputs, 108, 2_200, &p2wpkh_change_spk));
+	}
+
+	fn p2wpkh_spending_tx(outputs: Vec<TxOut>) -> Transaction {
+		let mut witness = Witness::new();
+		witness.push([0; 72]);
+		witness.push([0; 33]);
+		Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: vec![TxIn {
+			previous_output: OutPoint::new(Txid::all_zeros(), 0), script_sig: ScriptBuf::new(), witness, sequence: Sequence::ZERO,
+		}], output: outputs }
+	}
+
+	#[test]
+	fn test_check_standardness_accepts_compliant_tx() {
+		let output_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[0; 0]));
+		let mut tx = p2wpkh_spending_tx(vec![TxOut { script_pubkey: output_spk.clone(), value: Amount::from_sat(10_000) }]);
+		assert_eq!(check_standardness(&tx), Ok(()));
+
+		// Outputs right at the dust limit are fine.
+		tx.output.push(TxOut { script_pubkey: output_spk.clone(), value: output_spk.dust_value() });
+		assert_eq!(check_standardness(&tx), Ok(()));
+
+		// As are zero-value OP_RETURN outputs.
+		tx.output.push(TxOut { script_pubkey: ScriptBuf::new_op_return(&[42; 32]), value: Amount::ZERO });
+		assert_eq!(check_standardness(&tx), Ok(()));
+
+		// Including once a change output was added.
+		let mut tx = p2wpkh_spending_tx(vec![TxOut { script_pubkey: output_spk.clone(), value: Amount::from_sat(10_000) }]);
+		maybe_add_change_output(&mut tx, Amount::from_sat(20_000), 0, 253, output_spk).unwrap();
+		assert_eq!(tx.output.len(), 2);
+		assert_eq!(check_standardness(&tx), Ok(()));
+	}
+
+	#[test]
+	fn test_check_standardness_rejects_non_standard_tx() {
+		let output_spk = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[0; 0]));
+		let output = TxOut { script_pubkey: output_spk.clone(), value: Amount::from_sat(10_000) };
+
+		// A transaction with too many outputs exceeds the maximum standard weight.
+		let tx = p2wpkh_spending_tx(vec![output.clone(); 3_000]);
+		assert!(tx.weight().to_wu() > 400_000);
+		assert_eq!(check_standardness(&tx), Err(StandardnessError::TooLarge { weight: tx.weight().to_wu() }));
+		assert_eq!(check_standardness(&p2wpkh_spending_tx(vec![output.clone(); 2_000])), Ok(()));
+
+		let mut tx = p2wpkh_spending_tx(vec![output.clone()]);
+		tx.version = Version::non_standard(3);
+		assert_eq!(check_standardness(&tx), Err(StandardnessError::NonStandardVersion));
+
+		let tx = p2wpkh_spending_tx(Vec::new());
+		assert_eq!(check_standardness(&tx), Err(StandardnessError::TooSmall { non_witness_size: 51 }));
+
+		let mut tx = p2wpkh_spending_tx(Vec::new());
+		tx.input.push(tx.input[0].clone());
+		assert_eq!(check_standardness(&tx), Err(StandardnessError::NoOutputs));
+
+		let dust_output = TxOut { script_pubkey: output_spk.clone(), value: output_spk.dust_value() - Amount::from_sat(1) };
+		let tx = p2wpkh_spending_tx(vec![output, dust_output]);
+		assert_eq!(check_standardness(&tx), Err(StandardnessError::DustOutput { index: 1 }));
+	}
+
+	#[test]
+	fn test_distribute_outputs_evenly() {
+		let scripts = (1..=3u8).map(|i| ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[i]))).collect::<Vec<_>>();
+		let weights = scripts.iter().map(|script| (script.clone(), 1)).collect::<Vec<_>>();
+
+		let outputs = distribute_outputs(Amount::from_sat(30_000), &weights).unwrap();
+		assert_eq!(outputs.len(), 3);
+		for (output, script) in outputs.iter().zip(scripts.iter()) {
+			assert_eq!(output.value.to_sat(), 10_000);
+			assert_eq!(&output.script_pubkey, script);
+		}
+
+		// The last output absorbs any rounding remainder.
+		let outputs = distribute_outputs(Amount::from_sat(30_002), &weights).unwrap();
+		let values = outputs.iter().map(|output| output.value.to_sat()).collect::<Vec<_>>();
+		assert_eq!(values, vec![10_000, 10_000, 10_002]);
+	}
+
+	#[test]
+	fn test_distribute_outputs_unevenly() {
+		let script_a = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[1]));
+		let script_b = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[2]));
+		let script_c = ScriptBuf::new_p2pkh(&PubkeyHash::hash(&[3]));
+
+		let weights = vec![(script_a.clone(), 1), (script_b.clone(), 2)];
+		let outputs = distribute_outputs(Amount::from_sat(10_001), &weights).unwrap();
+		assert_eq!(outputs, vec![
+			TxOut { script_pubkey: script_a.clone(), value: Amount::from_sat(3_333) },
+			TxOut { script_pubkey: script_b.clone(), value: Amount::from_sat(6_668) },
+		]);
+
+		// The remainder goes to the last recipient with a non-zero weight and zero-weight
+		// recipients are dropped as dust.
+		let weights = vec![(script_a.clone(), 1), (script_b.clone(), 2), (script_c.clone(), 0)];
+		let outputs = distribute_outputs(Amount::from_sat(10_001), &weights).unwrap();
+		assert_eq!(outputs.len(), 2);
+		assert_eq!(outputs[1], TxOut { script_pubkey: script_b.clone(), value: Amount::from_sat(6_668) });
+
+		// Outputs below the dust limit (546 for P2PKH) are dropped, leaving their value for change.
+		let weights = vec![(script_a.clone(), 1), (script_b.clone(), 99)];
+		let outputs = distribute_outputs(Amount::from_sat(50_000), &weights).unwrap();
+		assert_eq!(outputs, vec![TxOut { script_pubkey: script_b.clone(), value: Amount::from_sat(49_500) }]);
+
+		// We fail if there's nothing to distribute to.
+		assert!(distribute_outputs(Amount::from_sat(50_000), &[]).is_err());
+		assert!(distribute_outputs(Amount::from_sat(50_000), &[(script_a.clone(), 0)]).is_err());
+		assert!(distribute_outputs(Amount::from_sat(500), &[(script_a.clone(), 1)]).is_err());
+		assert!(distribute_outputs(Amount::from_sat(21_000_000_0000_0001), &[(script_a, 1)]).is_err());
+	}
+
+	#[test]
+	fn test_build_rbf_replacement_with_inputs() {
+		// The original pays 1000 sats in fees and has no change output we could reduce.
+		let destination_spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&[1]));
+		let change_spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&[2]));
+		let original = p2wpkh_spending_tx(vec![TxOut { script_pubkey: destination_spk.clone(), value: Amount::from_sat(99_000) }]);
+		let original_input_value = Amount::from_sat(100_000);
+		let extra_outpoint = OutPoint::new(Txid::from_byte_array([1; 32]), 1);
+		let extra_inputs = [(extra_outpoint, Amount::from_sat(50_000), 108)];
+
+		let (replacement, weight) = build_rbf_replacement_with_inputs(&original, original_input_value, 108, &extra_inputs, 2_500, change_spk.clone()).unwrap();
+		assert_eq!(replacement.input.len(), 2);
+		assert_eq!(replacement.input[0].previous_output, original.input[0].previous_output);
+		assert!(replacement.input[0].witness.is_empty());
+		assert_eq!(replacement.input[1].previous_output, extra_outpoint);
+		assert_eq!(replacement.input[1].sequence, Sequence::ENABLE_RBF_NO_LOCKTIME);
+		assert_eq!(replacement.output.len(), 2);
+		assert_eq!(replacement.output[0], original.output[0]);
+		assert_eq!(replacement.output[1].script_pubkey, change_spk);
+
+		// The replacement exactly pays the requested feerate, which exceeds the original fee.
+		let input_value = Amount::from_sat(150_000);
+		assert_eq!(excess_over_feerate(&replacement, input_value, 216, 2_500), 0);
+		let fee = input_value - replacement.output.iter().map(|output| output.value).sum::<Amount>();
+		assert_eq!(fee, Amount::from_sat(weight * 2_500 / 1000));
+		assert!(fee > Amount::from_sat(1_000));
+
+		// Any existing change output is replaced rather than kept alongside the new one.
+		let original_with_change = p2wpkh_spending_tx(vec![
+			TxOut { script_pubkey: destination_spk.clone(), value: Amount::from_sat(60_000) },
+			TxOut { script_pubkey: change_spk.clone(), value: Amount::from_sat(39_000) },
+		]);
+		let (replacement, _) = build_rbf_replacement_with_inputs(&original_with_change, original_input_value, 108, &extra_inputs, 2_500, change_spk.clone()).unwrap();
+		assert_eq!(replacement.output.len(), 2);
+		assert_eq!(replacement.output[0], original_with_change.output[0]);
+		assert!(replacement.output[1].value > Amount::from_sat(39_000));
+
+		// We fail if the replacement wouldn't pay a higher absolute fee...
+		assert!(matches!(build_rbf_replacement_with_inputs(&original, original_input_value, 108, &extra_inputs, 253, change_spk.clone()),
+			Err(ChangeError::InsufficientFundsForFee { .. })));
+		// ...or if an extra input is already spent by the original.
+		let duplicate_inputs = [(original.input[0].previous_output, Amount::from_sat(50_000), 108)];
+		assert_eq!(build_rbf_replacement_with_inputs(&original, original_input_value, 108, &duplicate_inputs, 2_500, change_spk.clone()),
+			Err(ChangeError::DuplicateInput));
+		// ...or if the original's outputs exceed the given input value.
+		assert_eq!(build_rbf_replacement_with_inputs(&original, Amount::from_sat(98_000), 108, &extra_inputs, 2_500, change_spk),
+			Err(ChangeError::OutputsExceedInputs));
+	}
 }